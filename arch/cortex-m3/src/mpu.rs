@@ -676,6 +676,68 @@ impl kernel::mpu::MPU for MPU {
         Ok(())
     }
 
+    fn resize_app_memory_region(
+        &self,
+        memory_start: *const u8,
+        available_memory_size: usize,
+        min_memory_size: usize,
+        app_memory_break: *const u8,
+        kernel_memory_size: usize,
+        permissions: mpu::Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Option<usize> {
+        // The process memory block must already have been allocated.
+        if config.regions[APP_MEMORY_REGION_NUM].location().is_none() {
+            return None;
+        }
+
+        let region_start = memory_start as usize;
+
+        // Size must be a power of two, and at least 256 bytes to support
+        // subregions.
+        let mut region_size = math::closest_power_of_two(min_memory_size as u32) as usize;
+        if region_size < 256 {
+            region_size = 256;
+        }
+
+        let app_memory_size = app_memory_break as usize - region_start;
+
+        // If the last subregion covering app-owned memory overlaps the start of
+        // kernel-owned memory, try a region twice as big.
+        let num_subregions_used = loop {
+            // The region cannot move, so its start must already be aligned to
+            // the new size.
+            if region_start % region_size != 0 || region_size > available_memory_size {
+                return None;
+            }
+
+            let kernel_memory_break = region_start + region_size - kernel_memory_size;
+            let num_subregions_used = cmp::min(app_memory_size * 8 / region_size + 1, 8);
+            let subregion_size = region_size / 8;
+            let subregions_end = region_start + subregion_size * num_subregions_used;
+
+            if subregions_end <= kernel_memory_break {
+                break num_subregions_used;
+            }
+            region_size *= 2;
+        };
+
+        let region = CortexMRegion::new(
+            region_start as *const u8,
+            region_size,
+            region_start as *const u8,
+            region_size,
+            APP_MEMORY_REGION_NUM,
+            Some((0, num_subregions_used - 1)),
+            permissions,
+        );
+
+        config.regions[APP_MEMORY_REGION_NUM] = region;
+        config.is_dirty.set(true);
+
+        Some(region_size)
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, app_id: &AppId) {
         // If the hardware is already configured for this app and the app's MPU
         // configuration has not changed, then skip the hardware update.
@@ -53,32 +53,30 @@ impl MachineTimer<'_> {
     }
 }
 
-impl hil::time::Time for MachineTimer<'_> {
+impl hil::time::Time<u64> for MachineTimer<'_> {
     type Frequency = hil::time::Freq32KHz;
 
-    fn now(&self) -> u32 {
-        self.registers.mtime.get() as u32
+    fn now(&self) -> u64 {
+        self.registers.mtime.get()
     }
 
-    fn max_tics(&self) -> u32 {
-        core::u32::MAX
+    fn max_tics(&self) -> u64 {
+        core::u64::MAX
     }
 }
 
-impl<'a> hil::time::Alarm<'a> for MachineTimer<'a> {
+impl<'a> hil::time::Alarm<'a, u64> for MachineTimer<'a> {
     fn set_client(&self, client: &'a dyn hil::time::AlarmClient) {
         self.client.set(client);
     }
 
-    fn set_alarm(&self, tics: u32) {
-        self.registers
-            .mtimecmp
-            .write(MTimeCmp::MTIMECMP.val(tics as u64));
+    fn set_alarm(&self, tics: u64) {
+        self.registers.mtimecmp.write(MTimeCmp::MTIMECMP.val(tics));
         csr::CSR.mie.modify(csr::mie::mie::mtimer::SET);
     }
 
-    fn get_alarm(&self) -> u32 {
-        self.registers.mtimecmp.get() as u32
+    fn get_alarm(&self) -> u64 {
+        self.registers.mtimecmp.get()
     }
 
     fn disable(&self) {
@@ -114,6 +114,16 @@ pub struct PMPConfig {
 
 const APP_MEMORY_REGION_NUM: usize = 0;
 
+/// Round `size` up to a size an app memory region can have.
+fn align_region_size(size: usize) -> usize {
+    // Region size always has to align to 4 bytes
+    if size % 4 != 0 {
+        size + 4 - (size % 4)
+    } else {
+        size
+    }
+}
+
 impl Default for PMPConfig {
     /// number of regions on the arty chip
     fn default() -> PMPConfig {
@@ -442,13 +452,8 @@ impl kernel::mpu::MPU for PMPConfig {
             initial_app_memory_size + initial_kernel_memory_size,
         );
 
-        // RISC-V PMP is not inclusive of the final address, while Tock is, increase the memory_size by 1
-        let mut region_size = memory_size as usize + 1;
-
-        // Region size always has to align to 4 bytes
-        if region_size % 4 != 0 {
-            region_size += 4 - (region_size % 4);
-        }
+        // RISC-V PMP is not inclusive of the final address, while Tock is, increase the size by 1
+        let region_size = align_region_size(memory_size + 1);
 
         // The region should start as close as possible to the start of the unallocated memory.
         let region_start = unallocated_memory_start as usize;
@@ -499,6 +504,39 @@ impl kernel::mpu::MPU for PMPConfig {
         Ok(())
     }
 
+    fn resize_app_memory_region(
+        &self,
+        memory_start: *const u8,
+        available_memory_size: usize,
+        min_memory_size: usize,
+        app_memory_break: *const u8,
+        kernel_memory_size: usize,
+        permissions: mpu::Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Option<usize> {
+        // The process memory block must already have been allocated.
+        if config.regions[APP_MEMORY_REGION_NUM].location().is_none() {
+            return None;
+        }
+
+        // The extra byte was already added when the block was allocated, so
+        // a block resized to its original size gets exactly that size back.
+        let region_size = align_region_size(min_memory_size);
+
+        if region_size > available_memory_size
+            || app_memory_break as usize > memory_start as usize + region_size - kernel_memory_size
+        {
+            return None;
+        }
+
+        let region = PMPRegion::new(memory_start, region_size, permissions);
+
+        config.regions[APP_MEMORY_REGION_NUM] = region;
+        config.is_dirty.set(true);
+
+        Some(region_size)
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, app_id: &AppId) {
         // Is the PMP already configured for this app?
         let last_configured_for_this_app = self
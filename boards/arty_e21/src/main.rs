@@ -49,7 +49,8 @@ struct ArtyE21 {
     gpio: &'static capsules::gpio::GPIO<'static, arty_e21_chip::gpio::GpioPin>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
-        VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer<'static>>,
+        VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer<'static>, u64>,
+        u64,
     >,
     led: &'static capsules::led::LED<'static, arty_e21_chip::gpio::GpioPin>,
     button: &'static capsules::button::Button<'static, arty_e21_chip::gpio::GpioPin>,
@@ -125,20 +126,20 @@ pub unsafe fn reset_handler() {
     // Create a shared virtualization mux layer on top of a single hardware
     // alarm.
     let mux_alarm = static_init!(
-        MuxAlarm<'static, rv32i::machine_timer::MachineTimer>,
+        MuxAlarm<'static, rv32i::machine_timer::MachineTimer, u64>,
         MuxAlarm::new(&arty_e21_chip::timer::MACHINETIMER)
     );
     hil::time::Alarm::set_client(&arty_e21_chip::timer::MACHINETIMER, mux_alarm);
 
     // Alarm
     let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm).finalize(
-        components::alarm_component_helper!(rv32i::machine_timer::MachineTimer),
+        components::alarm_component_helper!(rv32i::machine_timer::MachineTimer, u64),
     );
 
     // TEST for timer
     //
     // let virtual_alarm_test = static_init!(
-    //     VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer>,
+    //     VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer, u64>,
     //     VirtualMuxAlarm::new(mux_alarm)
     // );
     // let timertest = static_init!(
@@ -14,10 +14,15 @@
 //! let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
 //!     .finalize(components::alarm_component_helper!(sam4l::ast::Ast));
 //! ```
+//!
+//! Alarms with clock values wider than `u32` pass the type of their clock
+//! values to the helpers, e.g.
+//! `components::alarm_mux_component_helper!(rv32i::machine_timer::MachineTimer, u64)`.
 
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Last modified: 12/21/2019
 
+use core::marker::PhantomData;
 use core::mem::MaybeUninit;
 
 use capsules::alarm::AlarmDriver;
@@ -25,16 +30,19 @@ use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
-use kernel::hil::time;
+use kernel::hil::time::{self, Ticks};
 use kernel::static_init_half;
 
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! alarm_mux_component_helper {
     ($A:ty) => {{
+        $crate::alarm_mux_component_helper!($A, u32)
+    };};
+    ($A:ty, $W:ty) => {{
         use capsules::virtual_alarm::MuxAlarm;
         use core::mem::MaybeUninit;
-        static mut BUF: MaybeUninit<MuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF: MaybeUninit<MuxAlarm<'static, $A, $W>> = MaybeUninit::uninit();
         &mut BUF
     };};
 }
@@ -43,34 +51,43 @@ macro_rules! alarm_mux_component_helper {
 #[macro_export]
 macro_rules! alarm_component_helper {
     ($A:ty) => {{
+        $crate::alarm_component_helper!($A, u32)
+    };};
+    ($A:ty, $W:ty) => {{
         use capsules::alarm::AlarmDriver;
         use capsules::virtual_alarm::VirtualMuxAlarm;
         use core::mem::MaybeUninit;
-        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
-        static mut BUF2: MaybeUninit<AlarmDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A, $W>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<AlarmDriver<'static, VirtualMuxAlarm<'static, $A, $W>, $W>> =
             MaybeUninit::uninit();
         (&mut BUF1, &mut BUF2)
     };};
 }
 
-pub struct AlarmMuxComponent<A: 'static + time::Alarm<'static>> {
+pub struct AlarmMuxComponent<A: 'static + time::Alarm<'static, W>, W: 'static + Ticks = u32> {
     alarm: &'static A,
+    _ticks: PhantomData<W>,
 }
 
-impl<A: 'static + time::Alarm<'static>> AlarmMuxComponent<A> {
-    pub fn new(alarm: &'static A) -> AlarmMuxComponent<A> {
-        AlarmMuxComponent { alarm }
+impl<A: 'static + time::Alarm<'static, W>, W: 'static + Ticks> AlarmMuxComponent<A, W> {
+    pub fn new(alarm: &'static A) -> AlarmMuxComponent<A, W> {
+        AlarmMuxComponent {
+            alarm,
+            _ticks: PhantomData,
+        }
     }
 }
 
-impl<A: 'static + time::Alarm<'static>> Component for AlarmMuxComponent<A> {
-    type StaticInput = &'static mut MaybeUninit<MuxAlarm<'static, A>>;
-    type Output = &'static MuxAlarm<'static, A>;
+impl<A: 'static + time::Alarm<'static, W>, W: 'static + Ticks> Component
+    for AlarmMuxComponent<A, W>
+{
+    type StaticInput = &'static mut MaybeUninit<MuxAlarm<'static, A, W>>;
+    type Output = &'static MuxAlarm<'static, A, W>;
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
         let mux_alarm = static_init_half!(
             static_buffer,
-            MuxAlarm<'static, A>,
+            MuxAlarm<'static, A, W>,
             MuxAlarm::new(self.alarm)
         );
 
@@ -79,16 +96,16 @@ impl<A: 'static + time::Alarm<'static>> Component for AlarmMuxComponent<A> {
     }
 }
 
-pub struct AlarmDriverComponent<A: 'static + time::Alarm<'static>> {
+pub struct AlarmDriverComponent<A: 'static + time::Alarm<'static, W>, W: 'static + Ticks = u32> {
     board_kernel: &'static kernel::Kernel,
-    alarm_mux: &'static MuxAlarm<'static, A>,
+    alarm_mux: &'static MuxAlarm<'static, A, W>,
 }
 
-impl<A: 'static + time::Alarm<'static>> AlarmDriverComponent<A> {
+impl<A: 'static + time::Alarm<'static, W>, W: 'static + Ticks> AlarmDriverComponent<A, W> {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
-        mux: &'static MuxAlarm<'static, A>,
-    ) -> AlarmDriverComponent<A> {
+        mux: &'static MuxAlarm<'static, A, W>,
+    ) -> AlarmDriverComponent<A, W> {
         AlarmDriverComponent {
             board_kernel: board_kernel,
             alarm_mux: mux,
@@ -96,24 +113,26 @@ impl<A: 'static + time::Alarm<'static>> AlarmDriverComponent<A> {
     }
 }
 
-impl<A: 'static + time::Alarm<'static>> Component for AlarmDriverComponent<A> {
+impl<A: 'static + time::Alarm<'static, W>, W: 'static + Ticks> Component
+    for AlarmDriverComponent<A, W>
+{
     type StaticInput = (
-        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
-        &'static mut MaybeUninit<AlarmDriver<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A, W>>,
+        &'static mut MaybeUninit<AlarmDriver<'static, VirtualMuxAlarm<'static, A, W>, W>>,
     );
-    type Output = &'static AlarmDriver<'static, VirtualMuxAlarm<'static, A>>;
+    type Output = &'static AlarmDriver<'static, VirtualMuxAlarm<'static, A, W>, W>;
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
 
         let virtual_alarm1 = static_init_half!(
             static_buffer.0,
-            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm<'static, A, W>,
             VirtualMuxAlarm::new(self.alarm_mux)
         );
         let alarm = static_init_half!(
             static_buffer.1,
-            AlarmDriver<'static, VirtualMuxAlarm<'static, A>>,
+            AlarmDriver<'static, VirtualMuxAlarm<'static, A, W>, W>,
             AlarmDriver::new(virtual_alarm1, self.board_kernel.create_grant(&grant_cap))
         );
 
@@ -3,11 +3,13 @@
 //! This provides one Component, `Ieee802154Component`, which implements a
 //! userspace syscall interface to a full 802.15.4 stack with a
 //! always-on MAC implementation, as well as multiplexed access to that MAC implementation.
+//! It also returns the key table holding the keys and neighbors used to secure
+//! frames, which the board can keep in nonvolatile storage.
 //!
 //! Usage
 //! -----
 //! ```rust
-//! let (radio, mux_mac) = components::ieee802154::Ieee802154Component::new(
+//! let (radio, mux_mac, key_table) = components::ieee802154::Ieee802154Component::new(
 //!     board_kernel,
 //!     &nrf52::ieee802154_radio::RADIO,
 //!     &nrf52::aes::AESECB,
@@ -114,6 +116,7 @@ impl<
     type Output = (
         &'static capsules::ieee802154::RadioDriver<'static>,
         &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        &'static capsules::ieee802154::key_table::KeyTable<'static>,
     );
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -164,22 +167,28 @@ impl<
         );
         mux_mac.add_user(userspace_mac);
 
+        let key_table = static_init!(
+            capsules::ieee802154::key_table::KeyTable<'static>,
+            capsules::ieee802154::key_table::KeyTable::new()
+        );
+        mac_device.set_key_procedure(key_table);
+        mac_device.set_device_procedure(key_table);
+
         let radio_driver = static_init!(
             capsules::ieee802154::RadioDriver<'static>,
             capsules::ieee802154::RadioDriver::new(
                 userspace_mac,
+                key_table,
                 self.board_kernel.create_grant(&grant_cap),
                 &mut RADIO_BUF
             )
         );
 
-        mac_device.set_key_procedure(radio_driver);
-        mac_device.set_device_procedure(radio_driver);
         userspace_mac.set_transmit_client(radio_driver);
         userspace_mac.set_receive_client(radio_driver);
         userspace_mac.set_pan(self.pan_id);
         userspace_mac.set_address(self.short_addr);
 
-        (radio_driver, mux_mac)
+        (radio_driver, mux_mac, key_table)
     }
 }
@@ -51,7 +51,8 @@ struct HiFive1 {
     >,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
-        VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer<'static>>,
+        VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer<'static>, u64>,
+        u64,
     >,
 }
 
@@ -156,20 +157,21 @@ pub unsafe fn reset_handler() {
     // Create a shared virtualization mux layer on top of a single hardware
     // alarm.
     let mux_alarm = static_init!(
-        MuxAlarm<'static, rv32i::machine_timer::MachineTimer>,
+        MuxAlarm<'static, rv32i::machine_timer::MachineTimer, u64>,
         MuxAlarm::new(&e310x::timer::MACHINETIMER)
     );
     hil::time::Alarm::set_client(&e310x::timer::MACHINETIMER, mux_alarm);
 
     // Alarm
     let virtual_alarm_user = static_init!(
-        VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer>,
+        VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer, u64>,
         VirtualMuxAlarm::new(mux_alarm)
     );
     let alarm = static_init!(
         capsules::alarm::AlarmDriver<
             'static,
-            VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer>,
+            VirtualMuxAlarm<'static, rv32i::machine_timer::MachineTimer, u64>,
+            u64,
         >,
         capsules::alarm::AlarmDriver::new(
             virtual_alarm_user,
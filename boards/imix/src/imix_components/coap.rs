@@ -0,0 +1,128 @@
+//! Component to initialize the userspace CoAP driver.
+//!
+//! This provides one Component, CoapComponent. It sends and receives CoAP
+//! messages through the UDP stack, from the CoAP port, and lets apps send
+//! requests and serve resources. It must be finalized after the UDP driver,
+//! which lets the port table check ports bound by apps.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let coap_driver = CoapComponent::new(
+//!        board_kernel,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        mux_alarm,
+//!    )
+//!    .finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules;
+use capsules::net::coap::coap::COAP_PORT;
+use capsules::net::coap::CoapDriver;
+use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel;
+use kernel::capabilities;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init};
+
+use sam4l;
+
+const UDP_HDR_SIZE: usize = 8;
+const PAYLOAD_LEN: usize = super::udp_mux::PAYLOAD_LEN;
+
+static mut COAP_BUF: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_HDR_SIZE];
+
+pub struct CoapComponent {
+    board_kernel: &'static kernel::Kernel,
+    udp_send_mux: &'static MuxUdpSender<
+        'static,
+        IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    >,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+}
+
+impl CoapComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    ) -> CoapComponent {
+        CoapComponent {
+            board_kernel: board_kernel,
+            udp_send_mux: udp_send_mux,
+            udp_recv_mux: udp_recv_mux,
+            port_table: port_table,
+            alarm_mux: alarm,
+        }
+    }
+}
+
+impl Component for CoapComponent {
+    type StaticInput = ();
+    type Output = &'static CoapDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let udp_send = static_init!(
+            UDPSendStruct<
+                'static,
+                IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+            >,
+            UDPSendStruct::new(self.udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let coap_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let coap_driver = static_init!(
+            CoapDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            CoapDriver::new(
+                udp_send,
+                udp_recv,
+                self.port_table,
+                coap_alarm,
+                self.board_kernel.create_grant(&grant_cap),
+                LeasableBuffer::new(&mut COAP_BUF),
+                net_cap,
+            )
+        );
+        udp_send.set_client(coap_driver);
+        udp_recv.set_client(coap_driver);
+        coap_alarm.set_client(coap_driver);
+        coap_driver.bind(COAP_PORT);
+        coap_driver
+    }
+}
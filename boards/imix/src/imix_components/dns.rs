@@ -0,0 +1,135 @@
+//! Component to initialize the userspace DNS resolver.
+//!
+//! This provides one Component, DnsComponent. It sends AAAA queries through
+//! the UDP stack to a recursive DNS server, usually reachable through the
+//! border router, and lets apps resolve host names to IPv6 addresses. It must
+//! be finalized after the UDP driver, which lets the port table check ports
+//! bound by apps.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let dns_driver = DnsComponent::new(
+//!        board_kernel,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        DNS_SERVER,
+//!        mux_alarm,
+//!    )
+//!    .finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules;
+use capsules::net::dns::driver::TX_BUF_LEN;
+use capsules::net::dns::DnsDriver;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel;
+use kernel::capabilities;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init};
+
+use sam4l;
+
+/// The port queries are sent from.
+const DNS_CLIENT_PORT: u16 = 49153;
+
+static mut DNS_BUF: [u8; TX_BUF_LEN] = [0; TX_BUF_LEN];
+
+pub struct DnsComponent {
+    board_kernel: &'static kernel::Kernel,
+    udp_send_mux: &'static MuxUdpSender<
+        'static,
+        IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    >,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    server: IPAddr,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+}
+
+impl DnsComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        server: IPAddr,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    ) -> DnsComponent {
+        DnsComponent {
+            board_kernel: board_kernel,
+            udp_send_mux: udp_send_mux,
+            udp_recv_mux: udp_recv_mux,
+            port_table: port_table,
+            server: server,
+            alarm_mux: alarm,
+        }
+    }
+}
+
+impl Component for DnsComponent {
+    type StaticInput = ();
+    type Output = &'static DnsDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let udp_send = static_init!(
+            UDPSendStruct<
+                'static,
+                IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+            >,
+            UDPSendStruct::new(self.udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let dns_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let dns_driver = static_init!(
+            DnsDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            DnsDriver::new(
+                udp_send,
+                udp_recv,
+                self.port_table,
+                dns_alarm,
+                self.board_kernel.create_grant(&grant_cap),
+                LeasableBuffer::new(&mut DNS_BUF),
+                self.server,
+                net_cap,
+            )
+        );
+        udp_send.set_client(dns_driver);
+        udp_recv.set_client(dns_driver);
+        dns_alarm.set_client(dns_driver);
+        dns_driver.bind(DNS_CLIENT_PORT);
+        dns_driver
+    }
+}
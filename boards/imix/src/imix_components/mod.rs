@@ -1,14 +1,30 @@
 pub mod adc;
+pub mod coap;
+pub mod dns;
 pub mod fxos8700;
+#[cfg(not(feature = "rpl"))]
+pub mod nd;
+pub mod ping;
 pub mod rf233;
+#[cfg(feature = "rpl")]
+pub mod rpl;
+pub mod tcp;
 pub mod test;
 pub mod udp_driver;
 pub mod udp_mux;
 pub mod usb;
 
 pub use self::adc::AdcComponent;
+pub use self::coap::CoapComponent;
+pub use self::dns::DnsComponent;
 pub use self::fxos8700::NineDofComponent;
+#[cfg(not(feature = "rpl"))]
+pub use self::nd::NeighborDiscoveryComponent;
+pub use self::ping::PingComponent;
 pub use self::rf233::RF233Component;
+#[cfg(feature = "rpl")]
+pub use self::rpl::RplComponent;
+pub use self::tcp::TcpComponent;
 pub use self::udp_driver::UDPDriverComponent;
 pub use self::udp_mux::UDPMuxComponent;
 pub use self::usb::UsbComponent;
@@ -0,0 +1,193 @@
+//! Component to initialize 6LoWPAN neighbor discovery.
+//!
+//! This provides one Component, NeighborDiscoveryComponent. It discovers a
+//! router, fills the neighbor cache shared with the other IPv6 senders and
+//! registers an address with the router. Messages are received through the
+//! IPv6 receiver of the UDP/6LoWPAN stack and sent through a separate IPv6
+//! sender on its own MAC user. Global addresses are formed from the prefixes
+//! the router advertises and added to the interface addresses. Discovery
+//! starts once the board calls `start` on the returned engine.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let nd = NeighborDiscoveryComponent::new(
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        eui64,
+//!        ip_receive,
+//!        neighbor_cache,
+//!        interface_addrs,
+//!        mux_alarm,
+//!    )
+//!    .finalize(());
+//!    nd.start(IPAddr::generate_from_mac(MacAddress::Long(eui64)));
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::interface_addrs::InterfaceAddresses;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_nd::{NeighborDiscovery, TX_BUF_LEN};
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::neighbor_cache::NeighborCache;
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+
+use sam4l;
+
+static mut RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut ICMP_DGRAM: [u8; TX_BUF_LEN] = [0; TX_BUF_LEN];
+static mut ND_BUF: [u8; TX_BUF_LEN] = [0; TX_BUF_LEN];
+
+pub struct NeighborDiscoveryComponent {
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    eui64: [u8; 8],
+    ip_receive: &'static IP6RecvStruct<'static>,
+    neighbor_cache: &'static NeighborCache,
+    interface_addrs: &'static InterfaceAddresses,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+}
+
+impl NeighborDiscoveryComponent {
+    pub fn new(
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        eui64: [u8; 8],
+        ip_receive: &'static IP6RecvStruct<'static>,
+        neighbor_cache: &'static NeighborCache,
+        interface_addrs: &'static InterfaceAddresses,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    ) -> NeighborDiscoveryComponent {
+        NeighborDiscoveryComponent {
+            mux_mac: mux_mac,
+            ctx_pfix_len: ctx_pfix_len,
+            ctx_pfix: ctx_pfix,
+            dst_mac_addr: dst_mac_addr,
+            src_mac_addr: src_mac_addr,
+            eui64: eui64,
+            ip_receive: ip_receive,
+            neighbor_cache: neighbor_cache,
+            interface_addrs: interface_addrs,
+            alarm_mux: alarm,
+        }
+    }
+}
+
+impl Component for NeighborDiscoveryComponent {
+    type StaticInput = ();
+    type Output =
+        &'static NeighborDiscovery<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let nd_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(nd_mac);
+
+        // Only used to send, packets are received through the UDP stack's
+        // 6LoWPAN instance.
+        let sixlowpan = static_init!(
+            sixlowpan_state::Sixlowpan<
+                'static,
+                sam4l::ast::Ast<'static>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                &sam4l::ast::AST
+            )
+        );
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type133)),
+            payload: &mut ICMP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ipsender_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let ip_send = static_init!(
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RF233_BUF,
+                sixlowpan_tx,
+                nd_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_client(ip_send);
+        ip_send.set_neighbor_cache(self.neighbor_cache);
+        nd_mac.set_transmit_client(ip_send);
+
+        let nd_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let nd = static_init!(
+            NeighborDiscovery<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            NeighborDiscovery::new(
+                ip_send,
+                nd_alarm,
+                self.neighbor_cache,
+                &mut ND_BUF,
+                self.src_mac_addr,
+                self.eui64,
+                net_cap,
+            )
+        );
+        ip_send.set_client(nd);
+        nd_alarm.set_client(nd);
+        self.ip_receive.add_client(nd);
+        nd.set_interface_addresses(self.interface_addrs);
+        nd
+    }
+}
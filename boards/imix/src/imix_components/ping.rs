@@ -0,0 +1,198 @@
+//! Component to initialize ICMPv6 echo over 6LoWPAN.
+//!
+//! This provides one Component, PingComponent. It answers echo requests
+//! received through the IPv6 receiver of the UDP/6LoWPAN stack and provides
+//! the userspace ping driver. Echo replies and requests are sent through a
+//! separate IPv6 sender on its own MAC user.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let ping_driver = PingComponent::new(
+//!        board_kernel,
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        interface_addrs,
+//!        ip_receive,
+//!        neighbor_cache,
+//!        mux_alarm,
+//!    )
+//!    .finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::icmpv6::icmpv6_echo::ICMP6Echo;
+use capsules::net::icmpv6::PingDriver;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::interface_addrs::InterfaceAddresses;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::neighbor_cache::NeighborCache;
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+
+use sam4l;
+
+/// The largest echo request data that is sent or answered.
+const ECHO_DATA_LEN: usize = 128;
+
+static mut RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut ICMP_DGRAM: [u8; ECHO_DATA_LEN] = [0; ECHO_DATA_LEN];
+static mut ECHO_BUF: [u8; ECHO_DATA_LEN] = [0; ECHO_DATA_LEN];
+
+pub struct PingComponent {
+    board_kernel: &'static kernel::Kernel,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface_addrs: &'static InterfaceAddresses,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    neighbor_cache: &'static NeighborCache,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+}
+
+impl PingComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface_addrs: &'static InterfaceAddresses,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        neighbor_cache: &'static NeighborCache,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    ) -> PingComponent {
+        PingComponent {
+            board_kernel: board_kernel,
+            mux_mac: mux_mac,
+            ctx_pfix_len: ctx_pfix_len,
+            ctx_pfix: ctx_pfix,
+            dst_mac_addr: dst_mac_addr,
+            src_mac_addr: src_mac_addr,
+            interface_addrs: interface_addrs,
+            ip_receive: ip_receive,
+            neighbor_cache: neighbor_cache,
+            alarm_mux: alarm,
+        }
+    }
+}
+
+impl Component for PingComponent {
+    type StaticInput = ();
+    type Output = &'static PingDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let icmp_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(icmp_mac);
+
+        // Only used to send, packets are received through the UDP stack's
+        // 6LoWPAN instance.
+        let sixlowpan = static_init!(
+            sixlowpan_state::Sixlowpan<
+                'static,
+                sam4l::ast::Ast<'static>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                &sam4l::ast::AST
+            )
+        );
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type128)),
+            payload: &mut ICMP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ipsender_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let ip_send = static_init!(
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RF233_BUF,
+                sixlowpan_tx,
+                icmp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_client(ip_send);
+        ip_send.set_neighbor_cache(self.neighbor_cache);
+        ip_send.set_interface_addresses(self.interface_addrs);
+        icmp_mac.set_transmit_client(ip_send);
+
+        let echo = static_init!(
+            ICMP6Echo<'static>,
+            ICMP6Echo::new(ip_send, &mut ECHO_BUF, net_cap)
+        );
+        ip_send.set_client(echo);
+        self.ip_receive.add_client(echo);
+        echo.set_responder_enabled(true);
+
+        let ping_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let ping_driver = static_init!(
+            PingDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            PingDriver::new(
+                echo,
+                ping_alarm,
+                self.board_kernel.create_grant(&grant_cap),
+                net_cap,
+            )
+        );
+        echo.set_client(ping_driver);
+        ping_alarm.set_client(ping_driver);
+        ping_driver
+    }
+}
@@ -0,0 +1,250 @@
+//! Component to initialize the userland TCP driver.
+//!
+//! This provides one Component, TcpComponent. It runs TCP over the IPv6
+//! receiver of the UDP/6LoWPAN stack and a separate IPv6 sender on its own
+//! MAC user, and provides the userspace TCP driver with `NUM_CONNECTIONS`
+//! connections. The RNG it is given keys the initial sequence numbers.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let tcp_driver = TcpComponent::new(
+//!        board_kernel,
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        interface_addrs,
+//!        ip_receive,
+//!        neighbor_cache,
+//!        mux_alarm,
+//!        tcp_rng,
+//!    )
+//!    .finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::interface_addrs::InterfaceAddresses;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::neighbor_cache::NeighborCache;
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, TcpVisibilityCapability,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::tcp::driver::DriverConnection;
+use capsules::net::tcp::tcp::TCPHeader;
+use capsules::net::tcp::tcp_connection::{MuxTcp, TcpConnection};
+use capsules::net::tcp::tcp_port_table::{SocketBindingEntry, TcpPortManager, MAX_NUM_BOUND_PORTS};
+use capsules::net::tcp::TcpDriver;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::debug;
+use kernel::hil::radio;
+use kernel::hil::rng::Rng;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+use kernel::ReturnCode;
+
+use sam4l;
+
+/// The number of connections apps can have open at once.
+pub const NUM_CONNECTIONS: usize = 2;
+/// The most data bytes sent in one segment.
+const SEGMENT_LEN: usize = 128;
+/// The most data bytes an app can send at once.
+const SEND_BUF_LEN: usize = 256;
+
+static mut RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut TCP_DGRAM: [u8; SEGMENT_LEN] = [0; SEGMENT_LEN];
+static mut TCP_SEGMENT_BUF: [u8; SEGMENT_LEN] = [0; SEGMENT_LEN];
+static mut SEND_BUF0: [u8; SEND_BUF_LEN] = [0; SEND_BUF_LEN];
+static mut SEND_BUF1: [u8; SEND_BUF_LEN] = [0; SEND_BUF_LEN];
+static mut USED_TCP_PORTS: [Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS] =
+    [None; MAX_NUM_BOUND_PORTS];
+
+type TcpAlarm = VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>;
+
+pub struct TcpComponent {
+    board_kernel: &'static kernel::Kernel,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface_addrs: &'static InterfaceAddresses,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    neighbor_cache: &'static NeighborCache,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    rng: &'static dyn Rng<'static>,
+}
+
+impl TcpComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface_addrs: &'static InterfaceAddresses,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        neighbor_cache: &'static NeighborCache,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        rng: &'static dyn Rng<'static>,
+    ) -> TcpComponent {
+        TcpComponent {
+            board_kernel: board_kernel,
+            mux_mac: mux_mac,
+            ctx_pfix_len: ctx_pfix_len,
+            ctx_pfix: ctx_pfix,
+            dst_mac_addr: dst_mac_addr,
+            src_mac_addr: src_mac_addr,
+            interface_addrs: interface_addrs,
+            ip_receive: ip_receive,
+            neighbor_cache: neighbor_cache,
+            alarm_mux: alarm,
+            rng: rng,
+        }
+    }
+}
+
+impl Component for TcpComponent {
+    type StaticInput = ();
+    type Output = &'static TcpDriver<'static, TcpAlarm>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+        let tcp_vis = static_init!(
+            TcpVisibilityCapability,
+            TcpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let tcp_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(tcp_mac);
+
+        // Only used to send, segments are received through the UDP stack's
+        // 6LoWPAN instance.
+        let sixlowpan = static_init!(
+            sixlowpan_state::Sixlowpan<
+                'static,
+                sam4l::ast::Ast<'static>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                &sam4l::ast::AST
+            )
+        );
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::TCP(TCPHeader::new()),
+            payload: &mut TCP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ipsender_virtual_alarm = static_init!(TcpAlarm, VirtualMuxAlarm::new(self.alarm_mux));
+        let ip_send = static_init!(
+            IP6SendStruct<'static, TcpAlarm>,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RF233_BUF,
+                sixlowpan_tx,
+                tcp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_client(ip_send);
+        ip_send.set_neighbor_cache(self.neighbor_cache);
+        ip_send.set_interface_addresses(self.interface_addrs);
+        tcp_mac.set_transmit_client(ip_send);
+
+        let tcp_alarm = static_init!(TcpAlarm, VirtualMuxAlarm::new(self.alarm_mux));
+        let tcp_mux = static_init!(
+            MuxTcp<'static, TcpAlarm>,
+            MuxTcp::new(
+                ip_send,
+                tcp_alarm,
+                self.rng,
+                &mut TCP_SEGMENT_BUF,
+                net_cap,
+                tcp_vis
+            )
+        );
+        ip_send.set_client(tcp_mux);
+        tcp_alarm.set_client(tcp_mux);
+        self.rng.set_client(tcp_mux);
+        self.ip_receive.add_client(tcp_mux);
+        tcp_mux.set_interface_addresses(self.interface_addrs);
+        if tcp_mux.initialize() != ReturnCode::SUCCESS {
+            debug!("TCP: no key for initial sequence numbers, connections cannot be opened");
+        }
+
+        let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
+        let port_table = static_init!(
+            TcpPortManager,
+            TcpPortManager::new(&create_table_cap, &mut USED_TCP_PORTS, tcp_vis)
+        );
+
+        let connection0 = static_init!(
+            TcpConnection<'static, TcpAlarm>,
+            TcpConnection::new(tcp_mux)
+        );
+        tcp_mux.add_connection(connection0);
+        let connection1 = static_init!(
+            TcpConnection<'static, TcpAlarm>,
+            TcpConnection::new(tcp_mux)
+        );
+        tcp_mux.add_connection(connection1);
+        let connections = static_init!(
+            [DriverConnection<'static, TcpAlarm>; NUM_CONNECTIONS],
+            [
+                DriverConnection::new(connection0, &mut SEND_BUF0),
+                DriverConnection::new(connection1, &mut SEND_BUF1),
+            ]
+        );
+
+        let tcp_driver = static_init!(
+            TcpDriver<'static, TcpAlarm>,
+            TcpDriver::new(
+                connections,
+                port_table,
+                self.board_kernel.create_grant(&grant_cap),
+                net_cap,
+            )
+        );
+        tcp_driver.initialize();
+        tcp_driver
+    }
+}
@@ -11,7 +11,7 @@
 //!        udp_send_mux,
 //!        udp_recv_mux,
 //!        udp_port_table,
-//!        local_ip_ifaces,
+//!        interface_addrs,
 //!        PAYLOAD_LEN,
 //!     )
 //!     .finalize();
@@ -24,7 +24,7 @@
 #![allow(dead_code)] // Components are intended to be conditionally included
 
 use capsules;
-use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::interface_addrs::InterfaceAddresses;
 use capsules::net::ipv6::ipv6_send::IP6SendStruct;
 use capsules::net::network_capabilities::{
     AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
@@ -56,7 +56,7 @@ pub struct UDPDriverComponent {
     >,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     port_table: &'static UdpPortManager,
-    interface_list: &'static [IPAddr],
+    interface_addrs: &'static InterfaceAddresses,
 }
 
 impl UDPDriverComponent {
@@ -68,14 +68,14 @@ impl UDPDriverComponent {
         >,
         udp_recv_mux: &'static MuxUdpReceiver<'static>,
         port_table: &'static UdpPortManager,
-        interface_list: &'static [IPAddr],
+        interface_addrs: &'static InterfaceAddresses,
     ) -> UDPDriverComponent {
         UDPDriverComponent {
             board_kernel: board_kernel,
             udp_send_mux: udp_send_mux,
             udp_recv_mux: udp_recv_mux,
             port_table: port_table,
-            interface_list: interface_list,
+            interface_addrs: interface_addrs,
         }
     }
 }
@@ -119,7 +119,7 @@ impl Component for UDPDriverComponent {
             capsules::net::udp::UDPDriver::new(
                 udp_send,
                 self.board_kernel.create_grant(&grant_cap),
-                self.interface_list,
+                self.interface_addrs,
                 PAYLOAD_LEN,
                 self.port_table,
                 kernel::common::leasable_buffer::LeasableBuffer::new(&mut DRIVER_BUF),
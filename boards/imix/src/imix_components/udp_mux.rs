@@ -2,12 +2,17 @@
 //!
 //! This provides one Component, UDPMuxComponent. This component
 //! exposes a MuxUdpSender that other components can implement
-//! UDPSenders on top of to use the UDP/6Lowpan stack.
+//! UDPSenders on top of to use the UDP/6Lowpan stack. It also exposes the
+//! IPv6 receiver, so that other protocols can receive packets, the
+//! neighbor cache the IPv6 sender resolves next hops with, and the table of
+//! interface addresses, which starts out holding the addresses in
+//! `interface_list`.
 //!
 //! Usage
 //! -----
 //! ```rust
-//!    let (udp_mux, udp_recv) = UDPMuxComponent::new(
+//!    let (udp_mux, udp_recv, udp_port_table, ip_receive, neighbor_cache, interface_addrs) =
+//!        UDPMuxComponent::new(
 //!        mux_mac,
 //!        DEFAULT_CTX_PREFIX_LEN,
 //!        DEFAULT_CTX_PREFIX,
@@ -28,11 +33,13 @@
 use capsules;
 use capsules::ieee802154::device::MacDevice;
 use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::interface_addrs::InterfaceAddresses;
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
-use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
 use capsules::net::ipv6::ipv6_send::IP6SendStruct;
 use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::neighbor_cache::NeighborCache;
 use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
 use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
 use capsules::net::udp::udp::UDPHeader;
@@ -119,6 +126,9 @@ impl Component for UDPMuxComponent {
         >,
         &'static MuxUdpReceiver<'static>,
         &'static UdpPortManager,
+        &'static IP6RecvStruct<'static>,
+        &'static NeighborCache,
+        &'static InterfaceAddresses,
     );
 
     unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
@@ -175,12 +185,10 @@ impl Component for UDPMuxComponent {
         };
         let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
 
-        // In current design, all udp senders share same IP sender, and the IP sender
-        // holds the destination mac address. This means all UDP senders must send to
-        // the same mac address...this works fine under the assumption
-        // of all packets being routed via a single gateway router, but doesn't work
-        // if multiple senders want to send to different addresses on a local network.
-        // This will be fixed once we have an ipv6_nd cache mapping IP addresses to dst macs
+        // All udp senders share the same IP sender, which picks the destination mac
+        // address of each packet from the neighbor cache. Packets the cache cannot
+        // resolve, e.g. before a router is discovered, are sent to the gateway mac
+        // address.
         let ip_send = static_init!(
             capsules::net::ipv6::ipv6_send::IP6SendStruct<
                 'static,
@@ -198,21 +206,26 @@ impl Component for UDPMuxComponent {
             )
         );
         ipsender_virtual_alarm.set_client(ip_send);
+        let neighbor_cache = static_init!(NeighborCache, NeighborCache::new());
+        ip_send.set_neighbor_cache(neighbor_cache);
 
-        // Initially, set src IP of the sender to be the first IP in the Interface
-        // list. Userland apps can change this if they so choose.
-        // Notably, the src addr is the same regardless of if messages are sent from
-        // userland or capsules.
-        ip_send.set_addr(self.interface_list[0]);
+        // The sender chooses the src IP of each packet from the interface
+        // addresses, regardless of if messages are sent from userland or capsules.
+        let interface_addrs = static_init!(InterfaceAddresses, InterfaceAddresses::new());
+        for addr in self.interface_list {
+            interface_addrs.add(*addr, None);
+        }
+        ip_send.set_interface_addresses(interface_addrs);
         udp_mac.set_transmit_client(ip_send);
 
         let ip_receive = static_init!(
             capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
             capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
         );
+        ip_receive.set_interface_addresses(interface_addrs);
         sixlowpan_state.set_rx_client(ip_receive);
         let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
-        ip_receive.set_client(udp_recv_mux);
+        ip_receive.add_client(udp_recv_mux);
 
         let udp_send_mux = static_init!(
             MuxUdpSender<
@@ -232,6 +245,13 @@ impl Component for UDPMuxComponent {
             UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
         );
 
-        (udp_send_mux, udp_recv_mux, udp_port_table)
+        (
+            udp_send_mux,
+            udp_recv_mux,
+            udp_port_table,
+            ip_receive,
+            neighbor_cache,
+            interface_addrs,
+        )
     }
 }
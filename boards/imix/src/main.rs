@@ -23,9 +23,10 @@ use kernel::hil::i2c::I2CMaster;
 use kernel::hil::radio;
 #[allow(unused_imports)]
 use kernel::hil::radio::{RadioConfig, RadioData};
+use kernel::hil::rng::Rng;
 use kernel::hil::Controller;
 #[allow(unused_imports)]
-use kernel::{create_capability, debug, debug_gpio, static_init};
+use kernel::{create_capability, debug, debug_gpio, static_init, ReturnCode};
 
 use components;
 use components::alarm::{AlarmDriverComponent, AlarmMuxComponent};
@@ -37,12 +38,19 @@ use components::isl29035::AmbientLightComponent;
 use components::led::LedsComponent;
 use components::nrf51822::Nrf51822Component;
 use components::process_console::ProcessConsoleComponent;
-use components::rng::RngComponent;
 use components::si7021::{HumidityComponent, SI7021Component};
 use components::spi::{SpiComponent, SpiSyscallComponent};
 use imix_components::adc::AdcComponent;
+use imix_components::coap::CoapComponent;
+use imix_components::dns::DnsComponent;
 use imix_components::fxos8700::NineDofComponent;
+#[cfg(not(feature = "rpl"))]
+use imix_components::nd::NeighborDiscoveryComponent;
+use imix_components::ping::PingComponent;
 use imix_components::rf233::RF233Component;
+#[cfg(feature = "rpl")]
+use imix_components::rpl::RplComponent;
+use imix_components::tcp::TcpComponent;
 use imix_components::udp_driver::UDPDriverComponent;
 use imix_components::udp_mux::UDPMuxComponent;
 use imix_components::usb::UsbComponent;
@@ -76,7 +84,11 @@ const DST_MAC_ADDR: MacAddress = MacAddress::Short(49138);
 const DEFAULT_CTX_PREFIX_LEN: u8 = 8; //Length of context for 6LoWPAN compression
 const DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16]; //Context for 6LoWPAN Compression
 const PAN_ID: u16 = 0xABCD;
-
+// Recursive DNS server the resolver queries, reached through the border
+// router. This is Google Public DNS (2001:4860:4860::8888).
+const DNS_SERVER: IPAddr = IPAddr([
+    0x20, 0x01, 0x48, 0x60, 0x48, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x88, 0x88,
+]);
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
 
@@ -92,6 +104,15 @@ static mut CHIP: Option<&'static sam4l::chip::Sam4l> = None;
 #[link_section = ".stack_buffer"]
 pub static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
 
+// Keys, neighbors and frame counters of the 802.15.4 key table, which are kept
+// across reboots.
+mod key_table_storage {
+    use kernel::storage_volume;
+    storage_volume!(IEEE802154_KEY_TABLE, 1);
+}
+static mut KEY_TABLE_BUF: [u8; capsules::ieee802154::key_table::STORAGE_LEN] =
+    [0; capsules::ieee802154::key_table::STORAGE_LEN];
+
 struct Imix {
     pconsole: &'static capsules::process_console::ProcessConsole<
         'static,
@@ -116,6 +137,22 @@ struct Imix {
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    ping_driver: &'static capsules::net::icmpv6::PingDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
+    coap_driver: &'static capsules::net::coap::CoapDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
+    tcp_driver: &'static capsules::net::tcp::TcpDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
+    dns_driver: &'static capsules::net::dns::DnsDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules::usb::usb_user::UsbSyscallDriver<
         'static,
@@ -160,6 +197,10 @@ impl kernel::Platform for Imix {
             capsules::usb::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::icmpv6::DRIVER_NUM => f(Some(self.ping_driver)),
+            capsules::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
+            capsules::net::tcp::DRIVER_NUM => f(Some(self.tcp_driver)),
+            capsules::net::dns::DRIVER_NUM => f(Some(self.dns_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
@@ -240,6 +281,13 @@ unsafe fn set_pin_primary_functions() {
     PC[31].configure(None); //... D2          -- GPIO Pin
 }
 
+/// Resets the chip, for the process console's `reboot` command.
+fn reset() {
+    unsafe {
+        cortexm4::scb::reset();
+    }
+}
+
 /// Reset Handler.
 ///
 /// This symbol is loaded into vector table by the SAM4L chip crate.
@@ -274,6 +322,8 @@ pub unsafe fn reset_handler() {
     });
 
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    // Let the process console reboot the board.
+    board_kernel.set_reset_function(reset, &process_mgmt_cap);
 
     let dynamic_deferred_call_clients =
         static_init!([DynamicDeferredCallClientState; 2], Default::default());
@@ -390,7 +440,33 @@ pub unsafe fn reset_handler() {
         ),
     )
     .finalize(components::acomp_component_buf!(sam4l::acifc::Acifc));
-    let rng = RngComponent::new(board_kernel, &sam4l::trng::TRNG).finalize(());
+
+    // The TRNG is shared by the userspace RNG driver and TCP, which keys its
+    // initial sequence numbers with it.
+    let entropy_to_random = static_init!(
+        capsules::rng::Entropy32ToRandom<'static>,
+        capsules::rng::Entropy32ToRandom::new(&sam4l::trng::TRNG)
+    );
+    let mux_rng = static_init!(
+        capsules::virtual_rng::MuxRng<'static>,
+        capsules::virtual_rng::MuxRng::new(entropy_to_random)
+    );
+    entropy_to_random.set_client(mux_rng);
+    let driver_rng = static_init!(
+        capsules::virtual_rng::VirtualRngDevice<'static>,
+        capsules::virtual_rng::VirtualRngDevice::new(mux_rng)
+    );
+    driver_rng.add_to_mux();
+    let rng = static_init!(
+        capsules::rng::RngDriver<'static>,
+        capsules::rng::RngDriver::new(driver_rng, board_kernel.create_grant(&grant_cap))
+    );
+    driver_rng.set_client(rng);
+    let tcp_rng = static_init!(
+        capsules::virtual_rng::VirtualRngDevice<'static>,
+        capsules::virtual_rng::VirtualRngDevice::new(mux_rng)
+    );
+    tcp_rng.add_to_mux();
 
     // For now, assign the 802.15.4 MAC address on the device as
     // simply a 16-bit short address which represents the last 16 bits
@@ -400,10 +476,14 @@ pub unsafe fn reset_handler() {
     let serial_num: sam4l::serial_num::SerialNum = sam4l::serial_num::SerialNum::new();
     let serial_num_bottom_16 = (serial_num.get_lower_64() & 0x0000_0000_0000_ffff) as u16;
     let src_mac_from_serial_num: MacAddress = MacAddress::Short(serial_num_bottom_16);
+    // The long address is the lower 64 bits of the serial number, from which
+    // IPv6 addresses are autoconfigured.
+    let eui64 = serial_num.get_lower_64().to_be_bytes();
+    rf233.set_address_long(eui64);
 
     // Can this initialize be pushed earlier, or into component? -pal
     rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
-    let (radio_driver, mux_mac) = components::ieee802154::Ieee802154Component::new(
+    let (radio_driver, mux_mac, key_table) = components::ieee802154::Ieee802154Component::new(
         board_kernel,
         rf233,
         &sam4l::aes::AES,
@@ -437,8 +517,22 @@ pub unsafe fn reset_handler() {
         sam4l::flashcalw::FLASHCALW
     ));
 
+    // Restore the 802.15.4 key table, which is then saved whenever it changes.
+    kernel::hil::nonvolatile_storage::NonvolatileStorage::set_client(
+        nonvolatile_storage,
+        key_table,
+    );
+    let result = key_table.set_storage(
+        nonvolatile_storage,
+        &key_table_storage::IEEE802154_KEY_TABLE as *const [u8] as *const u8 as usize,
+        &mut KEY_TABLE_BUF,
+    );
+    if result != ReturnCode::SUCCESS {
+        debug!("802.15.4 key table not kept in flash: {:?}", result);
+    }
+
     let local_ip_ifaces = static_init!(
-        [IPAddr; 3],
+        [IPAddr; 4],
         [
             IPAddr([
                 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
@@ -449,31 +543,122 @@ pub unsafe fn reset_handler() {
                 0x1e, 0x1f,
             ]),
             IPAddr::generate_from_mac(src_mac_from_serial_num),
+            IPAddr::generate_from_mac(MacAddress::Long(eui64)),
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = UDPMuxComponent::new(
+    let (udp_send_mux, udp_recv_mux, udp_port_table, ip_receive, neighbor_cache, interface_addrs) =
+        UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num, //comment out for dual rx test only
+            //MacAddress::Short(49138), //comment in for dual rx test only
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(());
+
+    // UDP driver initialization happens here
+    let udp_driver = UDPDriverComponent::new(
+        board_kernel,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        interface_addrs,
+    )
+    .finalize(());
+
+    // Resolves host names for apps, must come after the UDP driver
+    let dns_driver = DnsComponent::new(
+        board_kernel,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        DNS_SERVER,
+        mux_alarm,
+    )
+    .finalize(());
+
+    // Answers echo requests and lets apps send them
+    let ping_driver = PingComponent::new(
+        board_kernel,
         mux_mac,
         DEFAULT_CTX_PREFIX_LEN,
         DEFAULT_CTX_PREFIX,
         DST_MAC_ADDR,
-        src_mac_from_serial_num, //comment out for dual rx test only
-        //MacAddress::Short(49138), //comment in for dual rx test only
-        local_ip_ifaces,
+        src_mac_from_serial_num,
+        interface_addrs,
+        ip_receive,
+        neighbor_cache,
         mux_alarm,
     )
     .finalize(());
 
-    // UDP driver initialization happens here
-    let udp_driver = UDPDriverComponent::new(
+    // Sends CoAP requests and serves resources for apps, on the CoAP port
+    let coap_driver = CoapComponent::new(
         board_kernel,
         udp_send_mux,
         udp_recv_mux,
         udp_port_table,
-        local_ip_ifaces,
+        mux_alarm,
+    )
+    .finalize(());
+
+    // Lets apps open TCP connections, to talk to TCP-only services through
+    // the border router
+    let tcp_driver = TcpComponent::new(
+        board_kernel,
+        mux_mac,
+        DEFAULT_CTX_PREFIX_LEN,
+        DEFAULT_CTX_PREFIX,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        interface_addrs,
+        ip_receive,
+        neighbor_cache,
+        mux_alarm,
+        tcp_rng,
     )
     .finalize(());
 
+    // Neighbor discovery finds a router, forms global addresses from the
+    // prefixes it advertises and registers them with it, so that packets to
+    // other networks are sent to the router. RPL instead joins a mesh and
+    // forwards packets for the other nodes in it. Both pick the default
+    // router, so the `rpl` feature builds RPL in place of neighbor discovery.
+    #[cfg(not(feature = "rpl"))]
+    NeighborDiscoveryComponent::new(
+        mux_mac,
+        DEFAULT_CTX_PREFIX_LEN,
+        DEFAULT_CTX_PREFIX,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        eui64,
+        ip_receive,
+        neighbor_cache,
+        interface_addrs,
+        mux_alarm,
+    )
+    .finalize(())
+    .start(local_ip_ifaces[3]);
+    #[cfg(feature = "rpl")]
+    RplComponent::new(
+        mux_mac,
+        DEFAULT_CTX_PREFIX_LEN,
+        DEFAULT_CTX_PREFIX,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        eui64,
+        ip_receive,
+        neighbor_cache,
+        interface_addrs,
+        mux_alarm,
+    )
+    .finalize(())
+    .start();
+
     let imix = Imix {
         pconsole,
         console,
@@ -493,6 +678,10 @@ pub unsafe fn reset_handler() {
         ninedof,
         radio_driver,
         udp_driver,
+        ping_driver,
+        coap_driver,
+        tcp_driver,
+        dns_driver,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage: nonvolatile_storage,
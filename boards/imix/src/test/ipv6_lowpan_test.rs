@@ -30,6 +30,7 @@
 
 use capsules::ieee802154::device::{MacDevice, TxClient};
 use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ext_headers::ExtHeaders;
 use capsules::net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use capsules::net::ipv6::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
 use capsules::net::sixlowpan::sixlowpan_compression;
@@ -193,6 +194,7 @@ pub unsafe fn initialize_all(
 
     let mut ip6_dg: IP6Packet = IP6Packet {
         header: ip6_hdr,
+        ext_headers: ExtHeaders::new(),
         payload: ip_pyld,
     };
 
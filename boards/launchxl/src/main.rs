@@ -63,7 +63,10 @@ pub struct Platform {
         capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc<'static>>,
     >,
     rng: &'static capsules::rng::RngDriver<'static>,
-    i2c_master: &'static capsules::i2c_master::I2CMasterDriver<cc26x2::i2c::I2CMaster<'static>>,
+    i2c_master: &'static capsules::i2c_master::I2CMasterDriver<
+        cc26x2::i2c::I2CMaster<'static>,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc<'static>>,
+    >,
     ipc: kernel::ipc::IPC,
 }
 
@@ -238,20 +241,6 @@ pub unsafe fn reset_handler() {
     // Create the debugger object that handles calls to `debug!()`.
     components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
 
-    cc26x2::i2c::I2C0.initialize();
-
-    let i2c_master = static_init!(
-        capsules::i2c_master::I2CMasterDriver<cc26x2::i2c::I2CMaster<'static>>,
-        capsules::i2c_master::I2CMasterDriver::new(
-            &cc26x2::i2c::I2C0,
-            &mut capsules::i2c_master::BUF,
-            board_kernel.create_grant(&memory_allocation_capability)
-        )
-    );
-
-    cc26x2::i2c::I2C0.set_client(i2c_master);
-    cc26x2::i2c::I2C0.enable();
-
     // Setup for remaining GPIO pins
     let gpio = components::gpio::GpioComponent::new(
         board_kernel,
@@ -289,6 +278,29 @@ pub unsafe fn reset_handler() {
     );
     hil::time::Alarm::set_client(virtual_alarm1, alarm);
 
+    cc26x2::i2c::I2C0.initialize();
+
+    let i2c_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let i2c_master = static_init!(
+        capsules::i2c_master::I2CMasterDriver<
+            cc26x2::i2c::I2CMaster<'static>,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26x2::rtc::Rtc>,
+        >,
+        capsules::i2c_master::I2CMasterDriver::new(
+            &cc26x2::i2c::I2C0,
+            &mut capsules::i2c_master::BUF,
+            i2c_alarm,
+            board_kernel.create_grant(&memory_allocation_capability)
+        )
+    );
+    hil::time::Alarm::set_client(i2c_alarm, i2c_master);
+
+    cc26x2::i2c::I2C0.set_client(i2c_master);
+    cc26x2::i2c::I2C0.enable();
+
     let entropy_to_random = static_init!(
         capsules::rng::Entropy32ToRandom<'static>,
         capsules::rng::Entropy32ToRandom::new(&cc26x2::trng::TRNG)
@@ -222,7 +222,7 @@ pub unsafe fn setup_board<I: nrf52::interrupt_service::InterruptService>(
         BLEComponent::new(board_kernel, &nrf52::ble_radio::RADIO, mux_alarm).finalize(());
 
     let ieee802154_radio = if ieee802154 {
-        let (radio, _mux_mac) = components::ieee802154::Ieee802154Component::new(
+        let (radio, _mux_mac, _key_table) = components::ieee802154::Ieee802154Component::new(
             board_kernel,
             &nrf52::ieee802154_radio::RADIO,
             &nrf52::aes::AESECB,
@@ -72,7 +72,10 @@ struct OpenTitan {
         'static,
         capsules::usb::usbc_client::Client<'static, lowrisc::usbdev::Usb<'static>>,
     >,
-    i2c_master: &'static capsules::i2c_master::I2CMasterDriver<lowrisc::i2c::I2c<'static>>,
+    i2c_master: &'static capsules::i2c_master::I2CMasterDriver<
+        lowrisc::i2c::I2c<'static>,
+        VirtualMuxAlarm<'static, ibex::timer::RvTimer<'static>>,
+    >,
 }
 
 /// Mapping of integer syscalls to objects that implement syscalls.
@@ -255,14 +258,24 @@ pub unsafe fn reset_handler() {
 
     let usb = usb::UsbComponent::new(board_kernel).finalize(());
 
+    let i2c_alarm = static_init!(
+        VirtualMuxAlarm<'static, ibex::timer::RvTimer>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
     let i2c_master = static_init!(
-        capsules::i2c_master::I2CMasterDriver<lowrisc::i2c::I2c<'static>>,
+        capsules::i2c_master::I2CMasterDriver<
+            lowrisc::i2c::I2c<'static>,
+            VirtualMuxAlarm<'static, ibex::timer::RvTimer>,
+        >,
         capsules::i2c_master::I2CMasterDriver::new(
             &ibex::i2c::I2C,
             &mut capsules::i2c_master::BUF,
+            i2c_alarm,
             board_kernel.create_grant(&memory_allocation_cap)
         )
     );
+    hil::time::Alarm::set_client(i2c_alarm, i2c_master);
+    i2c_master.set_smbus(&ibex::i2c::I2C);
 
     ibex::i2c::I2C.set_master_client(i2c_master);
 
@@ -1,7 +1,18 @@
 //! Provides userspace applications with a alarm API.
+//!
+//! Each process can have several alarms outstanding at once, identified by
+//! their index. The number of alarms per process is set by the `S` storage
+//! type parameter of `AlarmDriver` and defaults to `DEFAULT_NUM_ALARMS`.
+//! Alarms fire either once or periodically. Periodic alarms are re-armed by
+//! the kernel relative to their previous expiration, so they do not drift.
+//!
+//! The underlying alarm may be wider or narrower than 32 bits, as set by the
+//! `W` type parameter. Processes see clock values truncated to `usize`, and
+//! absolute expirations from processes are taken to be the next time the
+//! clock's low bits match.
 
 use core::cell::Cell;
-use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
 
 /// Syscall driver number.
@@ -13,70 +24,150 @@ pub const DRIVER_NUM: usize = driver::NUM::Alarm as usize;
 /// so the alarm gets fired
 const MIN_TICS_AT_16KHZ: usize = 5;
 
+/// Number of alarms each process can have outstanding when the board does not
+/// choose otherwise.
+pub const DEFAULT_NUM_ALARMS: usize = 4;
+
 #[derive(Copy, Clone, Debug)]
-enum Expiration {
+enum Expiration<W> {
     Disabled,
-    Abs(u32),
+    Abs(W),
+    Periodic { expiration: W, period: W },
+}
+
+/// A single alarm of a process.
+#[derive(Copy, Clone, Debug)]
+pub struct AppAlarm<W = u32> {
+    expiration: Expiration<W>,
+}
+
+impl<W: Ticks> AppAlarm<W> {
+    fn expiration(&self) -> Option<W> {
+        match self.expiration {
+            Expiration::Disabled => None,
+            Expiration::Abs(exp)
+            | Expiration::Periodic {
+                expiration: exp, ..
+            } => Some(exp),
+        }
+    }
 }
 
-#[derive(Copy, Clone)]
-pub struct AlarmData {
-    expiration: Expiration,
+impl<W> Default for AppAlarm<W> {
+    fn default() -> AppAlarm<W> {
+        AppAlarm {
+            expiration: Expiration::Disabled,
+        }
+    }
+}
+
+pub struct AlarmData<S = [AppAlarm; DEFAULT_NUM_ALARMS]> {
+    alarms: S,
     callback: Option<Callback>,
 }
 
-impl Default for AlarmData {
-    fn default() -> AlarmData {
+impl<S: Default> Default for AlarmData<S> {
+    fn default() -> AlarmData<S> {
         AlarmData {
-            expiration: Expiration::Disabled,
+            alarms: S::default(),
             callback: None,
         }
     }
 }
 
-pub struct AlarmDriver<'a, A: Alarm<'a>> {
+pub struct AlarmDriver<
+    'a,
+    A: Alarm<'a, W>,
+    W: Ticks = u32,
+    S: AsMut<[AppAlarm<W>]> + Default = [AppAlarm<W>; DEFAULT_NUM_ALARMS],
+> {
     alarm: &'a A,
     num_armed: Cell<usize>,
-    app_alarm: Grant<AlarmData>,
-    prev: Cell<u32>,
+    app_alarm: Grant<AlarmData<S>>,
+    prev: Cell<W>,
 }
 
-impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
-    pub const fn new(alarm: &'a A, grant: Grant<AlarmData>) -> AlarmDriver<'a, A> {
+impl<'a, A: Alarm<'a, W>, W: Ticks, S: AsMut<[AppAlarm<W>]> + Default> AlarmDriver<'a, A, W, S> {
+    pub fn new(alarm: &'a A, grant: Grant<AlarmData<S>>) -> AlarmDriver<'a, A, W, S> {
         AlarmDriver {
             alarm: alarm,
             num_armed: Cell::new(0),
             app_alarm: grant,
-            prev: Cell::new(0),
+            prev: Cell::new(W::default()),
         }
     }
 
-    fn reset_active_alarm(&self, now: u32) -> Option<u32> {
+    fn reset_active_alarm(&self, now: W) -> Option<W> {
         self.prev.set(now);
-        let mut next_alarm = u32::max_value();
-        let mut next_dist = u32::max_value();
-        for alarm in self.app_alarm.iter() {
-            alarm.enter(|alarm, _| match alarm.expiration {
-                Expiration::Abs(exp) => {
-                    let t_dist = exp.wrapping_sub(now);
-                    if next_dist > t_dist {
-                        next_alarm = exp;
-                        next_dist = t_dist;
+        let mut next: Option<(W, W)> = None;
+        for app in self.app_alarm.iter() {
+            app.enter(|app, _| {
+                for alarm in app.alarms.as_mut().iter() {
+                    if let Some(exp) = alarm.expiration() {
+                        let t_dist = self.alarm.tics_between(now, exp);
+                        if next.map_or(true, |(next_dist, _)| next_dist > t_dist) {
+                            next = Some((t_dist, exp));
+                        }
                     }
                 }
-                Expiration::Disabled => {}
             });
         }
-        if next_alarm != u32::max_value() {
+        next.map(|(_, next_alarm)| {
             self.alarm.set_alarm(next_alarm);
-            Some(next_alarm)
+            next_alarm
+        })
+    }
+
+    /// Smallest number of tics in the future an alarm can be set to.
+    fn min_tics(&self) -> W {
+        W::from_usize((MIN_TICS_AT_16KHZ * (<A::Frequency>::frequency() as usize)) / 16000)
+    }
+
+    /// Converts a clock value from a process, which may only hold the low bits
+    /// of the clock, to the next clock value with those low bits.
+    fn expiration_from_user(&self, now: W, time: usize) -> W {
+        let user_max_tics = self.alarm.max_tics().into_usize();
+        let delta = time.wrapping_sub(now.into_usize()) & user_max_tics;
+        self.alarm.add_tics(now, W::from_usize(delta))
+    }
+
+    /// Returns the expiration `delta` tics after `now`, pushed back if it is
+    /// too close to be reliably caught.
+    fn relative_expiration(&self, now: W, delta: usize) -> W {
+        let time = self.alarm.add_tics(now, W::from_usize(delta));
+        if self.alarm.tics_between(now, time) <= self.min_tics() {
+            self.alarm.add_tics(time, self.min_tics())
         } else {
-            None
+            time
+        }
+    }
+
+    /// Arms `alarm`, returning the expiration and that the active alarm needs
+    /// to be reset.
+    fn arm(&self, alarm: &mut AppAlarm<W>, expiration: Expiration<W>) -> (ReturnCode, bool) {
+        if let Expiration::Disabled = alarm.expiration {
+            self.num_armed.set(self.num_armed.get() + 1);
+        }
+        alarm.expiration = expiration;
+        let value = alarm.expiration().map_or(0, |exp| exp.into_usize());
+        (ReturnCode::SuccessWithValue { value: value }, true)
+    }
+
+    /// Disarms `alarm`, returning whether it was armed.
+    fn disarm(&self, alarm: &mut AppAlarm<W>) -> bool {
+        if let Expiration::Disabled = alarm.expiration {
+            false
+        } else {
+            alarm.expiration = Expiration::Disabled;
+            self.num_armed.set(self.num_armed.get() - 1);
+            true
         }
     }
 }
 
-impl<'a, A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
+impl<'a, A: Alarm<'a, W>, W: Ticks, S: AsMut<[AppAlarm<W>]> + Default> Driver
+    for AlarmDriver<'a, A, W, S>
+{
     /// Subscribe to alarm expiration
     ///
     /// ### `_subscribe_num`
@@ -98,15 +189,24 @@ impl<'a, A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
 
     /// Setup and read the alarm.
     ///
+    /// Commands `3` to `5` act on alarm `0`. Commands `6` to `10` take the
+    /// alarm index as their first argument.
+    ///
     /// ### `command_num`
     ///
-    /// - `0`: Driver check.
+    /// - `0`: Driver check, returns the number of alarms per process.
     /// - `1`: Return the clock frequency in Hz.
     /// - `2`: Read the the current clock value
     /// - `3`: Stop the alarm if it is outstanding
     /// - `4`: Set an alarm to fire at a given clock value `time`.
     /// - `5`: Set an alarm to fire at a given clock value `time` relative to `now` (EXPERIMENTAL).
-    fn command(&self, cmd_type: usize, data: usize, _: usize, caller_id: AppId) -> ReturnCode {
+    /// - `6`: Set alarm `id` to fire at a given clock value.
+    /// - `7`: Set alarm `id` to fire a given number of tics from now.
+    /// - `8`: Set alarm `id` to fire every `period` tics, starting `period`
+    ///        tics from now.
+    /// - `9`: Stop alarm `id` if it is outstanding.
+    /// - `10`: Return the clock value at which alarm `id` fires next.
+    fn command(&self, cmd_type: usize, data: usize, data2: usize, caller_id: AppId) -> ReturnCode {
         // Returns the error code to return to the user and whether we need to
         // reset which is the next active alarm. We only _don't_ reset if we're
         // disabling the underlying alarm anyway, if the underlying alarm is
@@ -114,57 +214,72 @@ impl<'a, A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
         // (i.e. no change to the alarms).
         self.app_alarm
             .enter(caller_id, |td, _alloc| {
-                // helper function to rearm alarm
-                let mut rearm = |time: usize| {
-                    if let Expiration::Disabled = td.expiration {
-                        self.num_armed.set(self.num_armed.get() + 1);
-                    }
-                    td.expiration = Expiration::Abs(time as u32);
-                    (ReturnCode::SuccessWithValue { value: time }, true)
-                };
                 let now = self.alarm.now();
+                let alarms = td.alarms.as_mut();
                 let (return_code, reset) = match cmd_type {
-                    0 /* check if present */ => (ReturnCode::SuccessWithValue { value: 1 }, false),
+                    0 /* check if present */ => {
+                        (ReturnCode::SuccessWithValue { value: alarms.len() }, false)
+                    },
                     1 /* Get clock frequency */ => {
                         let freq = <A::Frequency>::frequency() as usize;
                         (ReturnCode::SuccessWithValue { value: freq }, false)
                     },
                     2 /* capture time */ => {
-                        (ReturnCode::SuccessWithValue { value: now as usize },
+                        (ReturnCode::SuccessWithValue { value: now.into_usize() },
                          false)
                     },
-                    3 /* Stop */ => {
-                        let alarm_id = data as u32;
-                        match td.expiration {
-                            Expiration::Disabled => {
-                                // Request to stop when already stopped
-                                (ReturnCode::EALREADY, false)
-                            },
-                            Expiration::Abs(exp) if exp != alarm_id => {
-                                // Request to stop invalid alarm id
-                                (ReturnCode::EINVAL, false)
+                    3..=10 => {
+                        // Commands 3 to 5 predate multiple alarms per process.
+                        let (id, arg) = if cmd_type <= 5 { (0, data) } else { (data, data2) };
+                        match alarms.get_mut(id) {
+                            None => (ReturnCode::EINVAL, false),
+                            Some(alarm) => match cmd_type {
+                                3 /* Stop */ => match alarm.expiration() {
+                                    // Request to stop when already stopped
+                                    None => (ReturnCode::EALREADY, false),
+                                    // Request to stop invalid alarm id
+                                    Some(exp) if exp.into_usize() != arg => (ReturnCode::EINVAL, false),
+                                    Some(_) => {
+                                        self.disarm(alarm);
+                                        (ReturnCode::SUCCESS, true)
+                                    }
+                                },
+                                4 | 6 /* Set absolute expiration */ => {
+                                    let time = self.expiration_from_user(now, arg);
+                                    self.arm(alarm, Expiration::Abs(time))
+                                },
+                                5 | 7 /* Set relative expiration */ => {
+                                    let time = self.relative_expiration(now, arg);
+                                    self.arm(alarm, Expiration::Abs(time))
+                                },
+                                8 /* Set periodic expiration */ => {
+                                    let period = W::from_usize(arg);
+                                    if arg > self.alarm.max_tics().into_usize() || period <= self.min_tics() {
+                                        (ReturnCode::EINVAL, false)
+                                    } else {
+                                        self.arm(alarm, Expiration::Periodic {
+                                            expiration: self.alarm.add_tics(now, period),
+                                            period: period,
+                                        })
+                                    }
+                                },
+                                9 /* Stop by index */ => {
+                                    if self.disarm(alarm) {
+                                        (ReturnCode::SUCCESS, true)
+                                    } else {
+                                        (ReturnCode::EALREADY, false)
+                                    }
+                                },
+                                _ /* Query */ => {
+                                    let return_code = alarm.expiration().map_or(
+                                        ReturnCode::EOFF,
+                                        |exp| ReturnCode::SuccessWithValue { value: exp.into_usize() },
+                                    );
+                                    (return_code, false)
+                                },
                             },
-                            _ => {
-                                td.expiration = Expiration::Disabled;
-                                let new_num_armed = self.num_armed.get() - 1;
-                                self.num_armed.set(new_num_armed);
-                                (ReturnCode::SUCCESS, true)
-                            }
                         }
                     },
-                    4 /* Set absolute expiration */ => {
-                        // if previously unarmed, but now will become armed
-                        rearm(data)
-                    },
-                    5 /* Set relative expiration */ => {
-                        let mut time = now.wrapping_add (data as u32) as usize;
-                        let min_tics = (MIN_TICS_AT_16KHZ * (<A::Frequency>::frequency() as usize)) / 16000;
-                        if time.wrapping_sub (now as usize) <= min_tics {
-                            time = time.wrapping_add (min_tics);
-                        }
-                        // if previously unarmed, but now will become armed
-                        rearm(time)
-                    },
                     _ => (ReturnCode::ENOSUPPORT, false)
                 };
                 if reset {
@@ -176,23 +291,40 @@ impl<'a, A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
     }
 }
 
-fn has_expired(alarm: u32, now: u32, prev: u32) -> bool {
-    now.wrapping_sub(prev) >= alarm.wrapping_sub(prev)
+fn has_expired<W: Ticks>(alarm: W, now: W, prev: W, max_tics: W) -> bool {
+    now.wrapping_sub(prev) & max_tics >= alarm.wrapping_sub(prev) & max_tics
 }
 
-impl<'a, A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
+impl<'a, A: Alarm<'a, W>, W: Ticks, S: AsMut<[AppAlarm<W>]> + Default> time::AlarmClient
+    for AlarmDriver<'a, A, W, S>
+{
     fn fired(&self) {
         let now = self.alarm.now();
-        self.app_alarm.each(|alarm| {
-            if let Expiration::Abs(exp) = alarm.expiration {
-                let expired = has_expired(exp, now, self.prev.get());
-                if expired {
-                    alarm.expiration = Expiration::Disabled;
-                    self.num_armed.set(self.num_armed.get() - 1);
-                    alarm
-                        .callback
-                        .map(|mut cb| cb.schedule(now as usize, exp as usize, 0));
+        let prev = self.prev.get();
+        let max_tics = self.alarm.max_tics();
+        self.app_alarm.each(|app| {
+            let callback = app.callback;
+            for (id, alarm) in app.alarms.as_mut().iter_mut().enumerate() {
+                let exp = match alarm.expiration() {
+                    Some(exp) if has_expired(exp, now, prev, max_tics) => exp,
+                    _ => continue,
+                };
+                if let Expiration::Periodic { period, .. } = alarm.expiration {
+                    // Re-arm relative to the expiration rather than to now so
+                    // the alarm does not drift. Periods that were missed
+                    // entirely are skipped.
+                    let mut next = self.alarm.add_tics(exp, period);
+                    if has_expired(next, now, exp, max_tics) {
+                        next = self.alarm.add_tics(now, period);
+                    }
+                    alarm.expiration = Expiration::Periodic {
+                        expiration: next,
+                        period: period,
+                    };
+                } else {
+                    self.disarm(alarm);
                 }
+                callback.map(|mut cb| cb.schedule(now.into_usize(), exp.into_usize(), id));
             }
         });
 
@@ -202,7 +334,7 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
             self.alarm.disable();
         } else if let Some(next_alarm) = self.reset_active_alarm(now) {
             let new_now = self.alarm.now();
-            if has_expired(next_alarm, new_now, now) {
+            if has_expired(next_alarm, new_now, now, max_tics) {
                 self.fired();
             }
         } else {
@@ -213,28 +345,156 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
 
 #[cfg(test)]
 mod test {
+    extern crate std;
+
+    use super::{AlarmDriver, DRIVER_NUM};
+    use host::chip::HostChip;
+    use host::harness::{
+        create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp, TestPlatform,
+    };
+    use host::mock::alarm::MockAlarm;
+    use host::syscall::{HostApp, Resumption};
+    use kernel::hil::time::Alarm;
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::ReturnCode;
+    use std::vec;
+    use std::vec::Vec;
     #[test]
     pub fn alarm_before_systick_wrap_expired() {
-        assert_eq!(super::has_expired(2u32, 3u32, 1u32), true);
+        assert_eq!(super::has_expired(2u32, 3u32, 1u32, u32::max_value()), true);
     }
 
     #[test]
     pub fn alarm_before_systick_wrap_not_expired() {
-        assert_eq!(super::has_expired(3u32, 2u32, 1u32), false);
+        assert_eq!(
+            super::has_expired(3u32, 2u32, 1u32, u32::max_value()),
+            false
+        );
     }
 
     #[test]
     pub fn alarm_after_systick_wrap_expired() {
-        assert_eq!(super::has_expired(1u32, 2u32, 3u32), true);
+        assert_eq!(super::has_expired(1u32, 2u32, 3u32, u32::max_value()), true);
     }
 
     #[test]
     pub fn alarm_after_systick_wrap_time_before_systick_wrap_not_expired() {
-        assert_eq!(super::has_expired(1u32, 4u32, 3u32), false);
+        assert_eq!(
+            super::has_expired(1u32, 4u32, 3u32, u32::max_value()),
+            false
+        );
     }
 
     #[test]
     pub fn alarm_after_systick_wrap_time_after_systick_wrap_not_expired() {
-        assert_eq!(super::has_expired(1u32, 0u32, 3u32), false);
+        assert_eq!(
+            super::has_expired(1u32, 0u32, 3u32, u32::max_value()),
+            false
+        );
+    }
+
+    #[test]
+    pub fn alarm_after_24bit_wrap_expired() {
+        let max_tics = (1u32 << 24) - 1;
+        assert_eq!(super::has_expired(2u32, 3u32, max_tics - 1, max_tics), true);
+        assert_eq!(
+            super::has_expired(4u32, 3u32, max_tics - 1, max_tics),
+            false
+        );
+    }
+
+    #[test]
+    pub fn alarm_after_64bit_wrap_expired() {
+        assert_eq!(
+            super::has_expired(1u64, 2u64, u64::max_value(), u64::max_value()),
+            true
+        );
+        assert_eq!(
+            super::has_expired(1u64, 1u64 << 40, 2u64, u64::max_value()),
+            false
+        );
+    }
+
+    #[test]
+    pub fn alarm_driver_runs_concurrent_and_periodic_alarms() {
+        let (kernel, processes) = create_kernel();
+        let mock_alarm: &'static MockAlarm = leak(MockAlarm::new());
+        let alarm: &AlarmDriver<MockAlarm> =
+            leak(AlarmDriver::new(mock_alarm, create_grant(kernel)));
+        mock_alarm.set_client(alarm);
+
+        let alarm_command = |command_num, id, time| Syscall::COMMAND {
+            driver_number: DRIVER_NUM,
+            subdriver_number: command_num,
+            arg0: id,
+            arg1: time,
+        };
+        let app = leak(ScriptedApp::new(vec![
+            Syscall::SUBSCRIBE {
+                driver_number: DRIVER_NUM,
+                subdriver_number: 0,
+                callback_ptr: 0x1000 as *mut (),
+                appdata: 0,
+            },
+            alarm_command(6, 1, 100),
+            alarm_command(8, 2, 60),
+            alarm_command(6, 4, 100),
+            alarm_command(6, 3, 500),
+            alarm_command(9, 3, 0),
+            alarm_command(10, 3, 0),
+        ]));
+        let apps: &'static [&'static dyn HostApp] = leak([app as &dyn HostApp]);
+        let chip = leak(HostChip::new(apps, None));
+        load_apps(
+            kernel,
+            chip,
+            processes,
+            &[("alarm", 0)],
+            FaultResponse::Panic,
+        );
+
+        let platform = TestPlatform::new().driver(DRIVER_NUM, alarm);
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            *app.returns.borrow(),
+            [
+                0,
+                100,
+                60,
+                isize::from(ReturnCode::EINVAL),
+                500,
+                0,
+                isize::from(ReturnCode::EOFF)
+            ]
+        );
+        assert_eq!(mock_alarm.get_alarm(), 60);
+
+        // The periodic alarm fires at 60 and then at 120 even though it is
+        // handled late, and alarm 1 fires in between.
+        assert!(mock_alarm.advance_to_alarm());
+        assert_eq!(mock_alarm.get_alarm(), 100);
+        run_until_idle(kernel, &platform, chip);
+        assert!(mock_alarm.advance(40));
+        assert_eq!(mock_alarm.get_alarm(), 120);
+        assert!(mock_alarm.advance(25));
+        run_until_idle(kernel, &platform, chip);
+
+        let fired: Vec<(usize, usize, usize)> = app
+            .callbacks
+            .borrow()
+            .iter()
+            .map(|callback| match *callback {
+                Resumption::Callback {
+                    argument0,
+                    argument1,
+                    argument2,
+                    ..
+                } => (argument0, argument1, argument2),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(fired, [(60, 60, 2), (100, 100, 1), (125, 120, 2)]);
+        assert_eq!(mock_alarm.get_alarm(), 180);
     }
 }
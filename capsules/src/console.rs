@@ -33,7 +33,29 @@
 //! When the buffer has been written successfully, the buffer is released from
 //! the driver. Successive writes must call `allow` each time a buffer is to be
 //! written.
+//!
+//! Multiplexing
+//! ------------
+//!
+//! By default, output from all processes is interleaved as it is written and
+//! input goes to whichever process has a read outstanding. Two optional modes
+//! make the console usable with several interactive processes:
+//!
+//! - With `set_line_prefixes(true)`, every line a process writes starts with
+//!   the process name in brackets. If a process writes while another process
+//!   left a line unfinished, the console starts a new line first.
+//! - With `enable_input_routing()`, input only goes to the foreground process
+//!   selected with `set_foreground()`. Reads from other processes stay queued
+//!   until they are brought to the foreground. The process console enables
+//!   this mode when it is given the console, and its `fg` command selects the
+//!   foreground process.
+//!
+//! ```rust
+//! console.set_line_prefixes(true);
+//! process_console.set_console(console);
+//! ```
 
+use core::cell::Cell;
 use core::cmp;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::uart;
@@ -43,6 +65,14 @@ use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Console as usize;
 
+/// Input byte (Ctrl-G) that returns input from the foreground process to the
+/// process console. It is never delivered to processes while input routing
+/// is enabled.
+pub const FOREGROUND_ESCAPE: u8 = 0x07;
+
+/// Longest process name, in bytes, used in a line prefix.
+const MAX_PREFIX_NAME_LEN: usize = 16;
+
 #[derive(Default)]
 pub struct App {
     write_callback: Option<Callback>,
@@ -54,6 +84,9 @@ pub struct App {
     read_callback: Option<Callback>,
     read_buffer: Option<AppSlice<Shared, u8>>,
     read_len: usize,
+    // Whether a read is waiting for this process to become the foreground
+    // process.
+    pending_read: bool,
 }
 
 pub static mut WRITE_BUF: [u8; 64] = [0; 64];
@@ -66,6 +99,12 @@ pub struct Console<'a> {
     tx_buffer: TakeCell<'static, [u8]>,
     rx_in_progress: OptionalCell<AppId>,
     rx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer_len: usize,
+    line_prefixes: Cell<bool>,
+    // The process whose last line of output is unfinished, if any.
+    open_line: OptionalCell<AppId>,
+    input_routing: Cell<bool>,
+    foreground: OptionalCell<AppId>,
 }
 
 impl<'a> Console<'a> {
@@ -81,10 +120,114 @@ impl<'a> Console<'a> {
             tx_in_progress: OptionalCell::empty(),
             tx_buffer: TakeCell::new(tx_buffer),
             rx_in_progress: OptionalCell::empty(),
+            rx_buffer_len: rx_buffer.len(),
             rx_buffer: TakeCell::new(rx_buffer),
+            line_prefixes: Cell::new(false),
+            open_line: OptionalCell::empty(),
+            input_routing: Cell::new(false),
+            foreground: OptionalCell::empty(),
+        }
+    }
+
+    /// Enables or disables prefixing each line of process output with the
+    /// name of the process that wrote it.
+    pub fn set_line_prefixes(&self, enabled: bool) {
+        self.line_prefixes.set(enabled);
+    }
+
+    /// Routes input only to the foreground process. Until a foreground
+    /// process is selected, no process receives input.
+    pub fn enable_input_routing(&self) {
+        self.input_routing.set(true);
+    }
+
+    /// Returns the process that currently receives input, if any.
+    pub fn foreground(&self) -> Option<AppId> {
+        self.foreground.map(|appid| *appid)
+    }
+
+    /// Selects the process that receives input when input routing is
+    /// enabled, or no process if `appid` is `None`. A read in progress for
+    /// another process is suspended and resumes once that process is brought
+    /// back to the foreground.
+    pub fn set_foreground(&self, appid: Option<AppId>) {
+        match appid {
+            Some(appid) => self.foreground.set(appid),
+            None => self.foreground.clear(),
+        }
+        if self.rx_in_progress.is_some() {
+            if self.rx_in_progress.map(|reader| *reader) != appid {
+                // The read completes through `received_buffer`, which then
+                // starts the foreground process's read.
+                self.uart.receive_abort();
+            }
+        } else {
+            self.start_pending_read();
         }
     }
 
+    /// Starts the foreground process's queued read, if it has one.
+    fn start_pending_read(&self) {
+        self.foreground.map(|appid| {
+            self.apps.enter(*appid, |app, _| {
+                if app.pending_read {
+                    app.pending_read = false;
+                    self.start_receive(*appid, app.read_len);
+                }
+            })
+        });
+    }
+
+    fn start_receive(&self, app_id: AppId, len: usize) {
+        self.rx_buffer.take().map(|buffer| {
+            self.rx_in_progress.set(app_id);
+            let (_err, _opt) = self.uart.receive_buffer(buffer, len);
+        });
+    }
+
+    /// Copies `data` into `buffer`, starting each line with the name of the
+    /// process. Returns how many bytes of `data` were consumed and how many
+    /// bytes of `buffer` were filled.
+    fn copy_with_prefixes(&self, app_id: AppId, data: &[u8], buffer: &mut [u8]) -> (usize, usize) {
+        let name = app_id.get_process_name().as_bytes();
+        let name = &name[..cmp::min(
+            cmp::min(name.len(), MAX_PREFIX_NAME_LEN),
+            buffer.len().saturating_sub(5),
+        )];
+        let mut mid_line = self.open_line.contains(&app_id);
+        let mut consumed = 0;
+        let mut written = 0;
+        if !mid_line && self.open_line.is_some() && !data.is_empty() && buffer.len() > 0 {
+            // Another process left a line unfinished.
+            buffer[0] = b'\n';
+            written = 1;
+            self.open_line.clear();
+        }
+        for &c in data {
+            let needed = if mid_line { 1 } else { name.len() + 4 };
+            if written + needed > buffer.len() {
+                break;
+            }
+            if !mid_line {
+                buffer[written] = b'[';
+                buffer[written + 1..written + 1 + name.len()].copy_from_slice(name);
+                buffer[written + 1 + name.len()] = b']';
+                buffer[written + 2 + name.len()] = b' ';
+                written += name.len() + 3;
+            }
+            buffer[written] = c;
+            written += 1;
+            consumed += 1;
+            mid_line = c != b'\n';
+        }
+        if mid_line {
+            self.open_line.set(app_id);
+        } else if self.open_line.contains(&app_id) {
+            self.open_line.clear();
+        }
+        (consumed, written)
+    }
+
     /// Internal helper function for setting up a new send transaction
     fn send_new(&self, app_id: AppId, app: &mut App, len: usize) -> ReturnCode {
         match app.write_buffer.take() {
@@ -119,25 +262,20 @@ impl<'a> Console<'a> {
         if self.tx_in_progress.is_none() {
             self.tx_in_progress.set(app_id);
             self.tx_buffer.take().map(|buffer| {
-                let mut transaction_len = app.write_remaining;
-                for (i, c) in slice.as_ref()[slice.len() - app.write_remaining..slice.len()]
-                    .iter()
-                    .enumerate()
-                {
-                    if buffer.len() <= i {
-                        break;
-                    }
-                    buffer[i] = *c;
-                }
+                let data = &slice.as_ref()[slice.len() - app.write_remaining..slice.len()];
+                let (consumed, transaction_len) = if self.line_prefixes.get() {
+                    self.copy_with_prefixes(app_id, data, buffer)
+                } else {
+                    let len = cmp::min(data.len(), buffer.len());
+                    buffer[..len].copy_from_slice(&data[..len]);
+                    (len, len)
+                };
 
                 // Check if everything we wanted to print
                 // fit in the buffer.
-                if app.write_remaining > buffer.len() {
-                    transaction_len = buffer.len();
-                    app.write_remaining -= buffer.len();
+                app.write_remaining -= consumed;
+                if app.write_remaining > 0 {
                     app.write_buffer = Some(slice);
-                } else {
-                    app.write_remaining = 0;
                 }
 
                 let (_err, _opt) = self.uart.transmit_buffer(buffer, transaction_len);
@@ -150,7 +288,8 @@ impl<'a> Console<'a> {
 
     /// Internal helper function for starting a receive operation
     fn receive_new(&self, app_id: AppId, app: &mut App, len: usize) -> ReturnCode {
-        if self.rx_buffer.is_none() {
+        let routed = self.input_routing.get();
+        if (!routed && self.rx_buffer.is_none()) || self.rx_in_progress.contains(&app_id) {
             // For now, we tolerate only one concurrent receive operation on this console.
             // Competing apps will have to retry until success.
             return ReturnCode::EBUSY;
@@ -159,17 +298,19 @@ impl<'a> Console<'a> {
         match app.read_buffer {
             Some(ref slice) => {
                 let read_len = cmp::min(len, slice.len());
-                if read_len > self.rx_buffer.map_or(0, |buf| buf.len()) {
+                if read_len > self.rx_buffer_len {
                     // For simplicity, impose a small maximum receive length
                     // instead of doing incremental reads
                     ReturnCode::EINVAL
                 } else {
-                    // Note: We have ensured above that rx_buffer is present
                     app.read_len = read_len;
-                    self.rx_buffer.take().map(|buffer| {
-                        self.rx_in_progress.set(app_id);
-                        let (_err, _opt) = self.uart.receive_buffer(buffer, app.read_len);
-                    });
+                    if routed && (!self.foreground.contains(&app_id) || self.rx_buffer.is_none()) {
+                        // Wait until this process is in the foreground and
+                        // any suspended read has finished.
+                        app.pending_read = true;
+                    } else {
+                        self.start_receive(app_id, read_len);
+                    }
                     ReturnCode::SUCCESS
                 }
             }
@@ -268,7 +409,19 @@ impl Driver for Console<'_> {
                 }).unwrap_or_else(|err| err.into())
             },
             3 /* abort rx */ => {
-                self.uart.receive_abort();
+                let queued = self.apps.enter(appid, |app, _| {
+                    let queued = app.pending_read;
+                    if queued {
+                        app.pending_read = false;
+                        app.read_callback.map(|mut cb| {
+                            cb.schedule(From::from(ReturnCode::ECANCEL), 0, 0);
+                        });
+                    }
+                    queued
+                }).unwrap_or(false);
+                if !queued {
+                    self.uart.receive_abort();
+                }
                 ReturnCode::SUCCESS
             }
             _ => ReturnCode::ENOSUPPORT
@@ -349,23 +502,39 @@ impl uart::ReceiveClient for Console<'_> {
         rcode: ReturnCode,
         error: uart::Error,
     ) {
+        let routed = self.input_routing.get();
         self.rx_in_progress
             .take()
             .map(|appid| {
                 self.apps
                     .enter(appid, |app, _| {
+                        // An iterator over the returned buffer yielding only the first `rx_len`
+                        // bytes, without the escape byte if input is routed
+                        let rx_buffer = buffer
+                            .iter()
+                            .take(rx_len)
+                            .filter(|&&b| !routed || b != FOREGROUND_ESCAPE);
+                        if routed
+                            && error == uart::Error::Aborted
+                            && rx_buffer.clone().next().is_none()
+                            && !self.foreground.contains(&appid)
+                        {
+                            // The read was suspended because the process left
+                            // the foreground before anything was typed.
+                            app.pending_read = true;
+                            return;
+                        }
                         app.read_callback.map(|mut cb| {
-                            // An iterator over the returned buffer yielding only the first `rx_len`
-                            // bytes
-                            let rx_buffer = buffer.iter().take(rx_len);
                             match error {
                                 uart::Error::None | uart::Error::Aborted => {
                                     // Receive some bytes, signal error type and return bytes to process buffer
                                     if let Some(mut app_buffer) = app.read_buffer.take() {
+                                        let mut len = 0;
                                         for (a, b) in app_buffer.iter_mut().zip(rx_buffer) {
                                             *a = *b;
+                                            len += 1;
                                         }
-                                        cb.schedule(From::from(rcode), rx_len, 0);
+                                        cb.schedule(From::from(rcode), len, 0);
                                     } else {
                                         // Oops, no app buffer
                                         cb.schedule(From::from(ReturnCode::EINVAL), 0, 0);
@@ -384,5 +553,146 @@ impl uart::ReceiveClient for Console<'_> {
 
         // Whatever happens, we want to make sure to replace the rx_buffer for future transactions
         self.rx_buffer.replace(buffer);
+
+        if routed {
+            self.start_pending_read();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{Console, DRIVER_NUM};
+    use host::chip::HostChip;
+    use host::harness::{
+        command, create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp,
+        TestPlatform,
+    };
+    use host::mock::uart::MockUart;
+    use host::syscall::{HostApp, Resumption};
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::ReturnCode;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    pub fn console_prefixes_lines_and_routes_input() {
+        let (kernel, processes) = create_kernel();
+        let mock_uart: &'static MockUart = leak(MockUart::new());
+        let console = leak(Console::new(
+            mock_uart,
+            Box::leak(Box::new([0; 64])),
+            Box::leak(Box::new([0; 4])),
+            create_grant(kernel),
+        ));
+        kernel::hil::uart::Transmit::set_transmit_client(mock_uart, console);
+        kernel::hil::uart::Receive::set_receive_client(mock_uart, console);
+        console.set_line_prefixes(true);
+        console.enable_input_routing();
+
+        let driver_num = DRIVER_NUM;
+        let script = || {
+            vec![
+                Syscall::SUBSCRIBE {
+                    driver_number: driver_num,
+                    subdriver_number: 1,
+                    callback_ptr: 0x1000 as *mut (),
+                    appdata: 0,
+                },
+                Syscall::SUBSCRIBE {
+                    driver_number: driver_num,
+                    subdriver_number: 2,
+                    callback_ptr: 0x2000 as *mut (),
+                    appdata: 0,
+                },
+                Syscall::ALLOW {
+                    driver_number: driver_num,
+                    subdriver_number: 1,
+                    allow_address: 0 as *mut u8,
+                    allow_size: 8,
+                },
+                Syscall::ALLOW {
+                    driver_number: driver_num,
+                    subdriver_number: 2,
+                    allow_address: 16 as *mut u8,
+                    allow_size: 4,
+                },
+                command(driver_num, 1, 8),
+                command(driver_num, 2, 4),
+            ]
+        };
+        let alpha = leak(ScriptedApp::new_with_memory(script(), b"hi\nthere"));
+        let beta = leak(ScriptedApp::new_with_memory(script(), b"hi\nthere"));
+        let apps: &'static [&'static dyn HostApp] = leak([alpha as &dyn HostApp, beta]);
+        let chip = leak(HostChip::new(apps, None));
+        let processes = load_apps(
+            kernel,
+            chip,
+            processes,
+            &[("alpha", 0), ("beta", 1)],
+            FaultResponse::Panic,
+        );
+
+        let platform = TestPlatform::new().driver(DRIVER_NUM, console);
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(*alpha.returns.borrow(), [0; 6]);
+        assert_eq!(*beta.returns.borrow(), [0; 6]);
+
+        // Each line starts with the name of its process, and a line left open by
+        // one process is ended before another process writes.
+        let mut written = Vec::new();
+        while mock_uart.is_transmitting() {
+            mock_uart.with_transmitted_data(|data| written.extend_from_slice(data));
+            mock_uart.complete_transmit(ReturnCode::SUCCESS);
+        }
+        assert_eq!(
+            written,
+            &b"[alpha] hi\n[alpha] there\n[beta] hi\n[beta] there"[..]
+        );
+
+        // Neither process receives input until it is in the foreground.
+        assert!(!mock_uart.is_receiving());
+        let (alpha_process, beta_process) = (processes[0].unwrap(), processes[1].unwrap());
+        console.set_foreground(Some(beta_process.appid()));
+        assert_eq!(mock_uart.receive_len(), Some(4));
+        assert!(mock_uart.complete_receive(
+            b"ab\x07c",
+            ReturnCode::SUCCESS,
+            kernel::hil::uart::Error::None
+        ));
+        assert!(!mock_uart.is_receiving());
+
+        // Switching away before anything was typed suspends the read.
+        console.set_foreground(Some(alpha_process.appid()));
+        assert_eq!(mock_uart.receive_len(), Some(4));
+        console.set_foreground(Some(beta_process.appid()));
+        assert!(mock_uart.complete_receive(
+            b"",
+            ReturnCode::ECANCEL,
+            kernel::hil::uart::Error::Aborted
+        ));
+        assert!(!mock_uart.is_receiving());
+        console.set_foreground(Some(alpha_process.appid()));
+        assert_eq!(mock_uart.receive_len(), Some(4));
+        run_until_idle(kernel, &platform, chip);
+
+        let callback_args = |app: &ScriptedApp| -> Vec<(usize, usize)> {
+            app.callbacks
+                .borrow()
+                .iter()
+                .map(|callback| match *callback {
+                    Resumption::Callback { pc, argument1, .. } => (pc, argument1),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        assert_eq!(callback_args(alpha), [(0x1000, 0)]);
+        assert_eq!(callback_args(beta), [(0x1000, 0), (0x2000, 3)]);
+        let received = beta.memory(16, 3);
+        assert_eq!(received, b"abc");
     }
 }
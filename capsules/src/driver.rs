@@ -16,12 +16,15 @@ pub enum NUM {
     Adc                   = 0x00005,
     Dac                   = 0x00006,
     AnalogComparator      = 0x00007,
+    WallClock             = 0x00009,
 
     // Kernel
     Ipc                   = 0x10000,
 
     // HW Buses
+    Uart                  = 0x20000,
     Spi                   = 0x20001,
+    SpiSlave              = 0x20002,
     I2cMaster             = 0x20003,
     UsbUser               = 0x20005,
     I2cMasterSlave        = 0x20006,
@@ -30,6 +33,10 @@ pub enum NUM {
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Ping                  = 0x30003,
+    Coap                  = 0x30004,
+    Dns                   = 0x30005,
+    Tcp                   = 0x30006,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -40,6 +40,11 @@
 //! Commands control and query GPIO information, namely how many GPIOs are
 //! present, the GPIO direction and state, and whether they should interrupt.
 //!
+//! A process can claim a pin for its exclusive use. Other processes then get
+//! `EBUSY` for any command on that pin, and only the owner receives its
+//! interrupts. Claims are kept in the owner's grant, so they are released when
+//! the owner faults or restarts. Unclaimed pins can be used by any process.
+//!
 //! ### Subscribes
 //!
 //! The GPIO interface provides only one callback, which is used for pins that
@@ -53,15 +58,25 @@ use kernel::hil::gpio;
 use kernel::hil::gpio::{Configure, Input, InterruptWithValue, Output};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
 
+/// Only pins with a lower index than this can be claimed.
+pub const MAX_CLAIMABLE_PINS: usize = 64;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    /// Bitmask of the pins this process has claimed.
+    claimed: u64,
+}
+
 pub struct GPIO<'a, IP: gpio::InterruptPin> {
     pins: &'a [Option<&'a gpio::InterruptValueWrapper<'a, IP>>],
-    apps: Grant<Option<Callback>>,
+    apps: Grant<App>,
 }
 
 impl<'a, IP: gpio::InterruptPin> GPIO<'a, IP> {
     pub fn new(
         pins: &'a [Option<&'a gpio::InterruptValueWrapper<'a, IP>>],
-        grant: Grant<Option<Callback>>,
+        grant: Grant<App>,
     ) -> Self {
         for (i, maybe_pin) in pins.iter().enumerate() {
             if let Some(pin) = maybe_pin {
@@ -74,6 +89,23 @@ impl<'a, IP: gpio::InterruptPin> GPIO<'a, IP> {
         }
     }
 
+    /// Returns the process that has claimed `pin_num`, if any. Processes that
+    /// have faulted no longer have an accessible grant, so their claims lapse.
+    fn owner(&self, pin_num: usize) -> Option<AppId> {
+        if pin_num >= MAX_CLAIMABLE_PINS {
+            return None;
+        }
+        self.apps.iter().find_map(|app| {
+            app.enter(|app, _| {
+                if app.claimed & (1 << pin_num) != 0 {
+                    Some(app.appid())
+                } else {
+                    None
+                }
+            })
+        })
+    }
+
     fn configure_input_pin(&self, pin_num: u32, config: usize) -> ReturnCode {
         let maybe_pin = self.pins[pin_num as usize];
         if let Some(pin) = maybe_pin {
@@ -133,9 +165,14 @@ impl<IP: gpio::InterruptPin> gpio::ClientWithValue for GPIO<'_, IP> {
         if let Some(pin) = pins[pin_num as usize] {
             let pin_state = pin.read();
 
-            // schedule callback with the pin number and value
-            self.apps.each(|callback| {
-                callback.map(|mut cb| cb.schedule(pin_num as usize, pin_state as usize, 0));
+            // schedule callback with the pin number and value, only to the
+            // owner if the pin is claimed
+            let owner = self.owner(pin_num as usize);
+            self.apps.each(|app| {
+                if owner.map_or(true, |owner| owner == app.appid()) {
+                    app.callback
+                        .map(|mut cb| cb.schedule(pin_num as usize, pin_state as usize, 0));
+                }
             });
         }
     }
@@ -160,7 +197,7 @@ impl<IP: gpio::InterruptPin> Driver for GPIO<'_, IP> {
             0 => self
                 .apps
                 .enter(app_id, |app, _| {
-                    **app = callback;
+                    app.callback = callback;
                     ReturnCode::SUCCESS
                 })
                 .unwrap_or_else(|err| err.into()),
@@ -200,9 +237,20 @@ impl<IP: gpio::InterruptPin> Driver for GPIO<'_, IP> {
     /// - `7`: Configure interrupt on `pin` with `irq_config` in 0x00XX00000
     /// - `8`: Disable interrupt on `pin`.
     /// - `9`: Disable `pin`.
-    fn command(&self, command_num: usize, data1: usize, data2: usize, _: AppId) -> ReturnCode {
+    /// - `10`: Claim `pin` for exclusive use by this process.
+    /// - `11`: Release a claim on `pin`.
+    ///
+    /// Commands on a pin claimed by another process return `EBUSY`.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
         let pins = self.pins.as_ref();
         let pin_index = data1;
+        if command_num != 0 && pin_index < pins.len() {
+            if let Some(owner) = self.owner(pin_index) {
+                if owner != appid {
+                    return ReturnCode::EBUSY;
+                }
+            }
+        }
         match command_num {
             // number of pins
             0 => ReturnCode::SuccessWithValue {
@@ -332,8 +380,142 @@ impl<IP: gpio::InterruptPin> Driver for GPIO<'_, IP> {
                 }
             }
 
+            // claim pin
+            10 => {
+                if pin_index >= pins.len() {
+                    ReturnCode::EINVAL /* impossible pin */
+                } else if pins[pin_index].is_none() {
+                    ReturnCode::ENODEVICE
+                } else if pin_index >= MAX_CLAIMABLE_PINS {
+                    ReturnCode::ENOSUPPORT
+                } else {
+                    self.apps
+                        .enter(appid, |app, _| {
+                            app.claimed |= 1 << pin_index;
+                            ReturnCode::SUCCESS
+                        })
+                        .unwrap_or_else(|err| err.into())
+                }
+            }
+
+            // release pin
+            11 => {
+                if pin_index >= pins.len() {
+                    ReturnCode::EINVAL /* impossible pin */
+                } else if self.owner(pin_index) != Some(appid) {
+                    ReturnCode::EALREADY
+                } else {
+                    self.apps
+                        .enter(appid, |app, _| {
+                            app.claimed &= !(1 << pin_index);
+                            ReturnCode::SUCCESS
+                        })
+                        .unwrap_or_else(|err| err.into())
+                }
+            }
+
             // default
             _ => ReturnCode::ENOSUPPORT,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{DRIVER_NUM, GPIO};
+    use host::chip::HostChip;
+    use host::harness::{
+        create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp, TestPlatform,
+    };
+    use host::mock::gpio::MockPin;
+    use host::syscall::HostApp;
+    use kernel::hil::gpio::InterruptValueWrapper;
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::{Driver, ReturnCode};
+    use std::vec;
+
+    #[test]
+    pub fn gpio_pins_are_owned_by_one_process() {
+        let (kernel, processes) = create_kernel();
+        let mock_pins: &'static [MockPin; 3] =
+            leak([MockPin::new(), MockPin::new(), MockPin::new()]);
+        let pins = leak([
+            Some(leak(InterruptValueWrapper::new(&mock_pins[0])).finalize()),
+            Some(leak(InterruptValueWrapper::new(&mock_pins[1])).finalize()),
+            Some(leak(InterruptValueWrapper::new(&mock_pins[2])).finalize()),
+        ]);
+        let gpio = leak(GPIO::new(pins, create_grant(kernel)));
+        for pin in pins.iter() {
+            pin.map(|pin| kernel::hil::gpio::InterruptWithValue::set_client(pin, gpio));
+        }
+
+        let gpio_command = |command_num, pin, config| Syscall::COMMAND {
+            driver_number: DRIVER_NUM,
+            subdriver_number: command_num,
+            arg0: pin,
+            arg1: config,
+        };
+        let subscribe = Syscall::SUBSCRIBE {
+            driver_number: DRIVER_NUM,
+            subdriver_number: 0,
+            callback_ptr: 0x1000 as *mut (),
+            appdata: 0,
+        };
+        let owner = leak(ScriptedApp::new(vec![
+            subscribe,
+            gpio_command(10, 0, 0),
+            gpio_command(1, 0, 0),
+            gpio_command(2, 0, 0),
+            gpio_command(10, 1, 0),
+            gpio_command(5, 1, 0),
+            gpio_command(7, 1, 0),
+        ]));
+        let other = leak(ScriptedApp::new(vec![
+            subscribe,
+            gpio_command(3, 0, 0),
+            gpio_command(10, 0, 0),
+            gpio_command(11, 0, 0),
+            gpio_command(10, 2, 0),
+            gpio_command(11, 2, 0),
+            gpio_command(11, 2, 0),
+        ]));
+        let apps: &'static [&'static dyn HostApp] = leak([owner as &dyn HostApp, other]);
+        let chip = leak(HostChip::new(apps, None));
+        let processes = load_apps(
+            kernel,
+            chip,
+            processes,
+            &[("owner", 0), ("other", 1)],
+            FaultResponse::Stop,
+        );
+
+        let platform = TestPlatform::new().driver(DRIVER_NUM, gpio);
+        run_until_idle(kernel, &platform, chip);
+
+        assert_eq!(*owner.returns.borrow(), [0; 7]);
+        let busy = isize::from(ReturnCode::EBUSY);
+        let already = isize::from(ReturnCode::EALREADY);
+        assert_eq!(
+            *other.returns.borrow(),
+            [0, busy, busy, busy, 0, 0, already]
+        );
+        assert!(mock_pins[0].output_level());
+
+        // Only the owner is told about interrupts on its pins.
+        assert!(mock_pins[1].set_input(true));
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(owner.callbacks.borrow().len(), 1);
+        assert!(other.callbacks.borrow().is_empty());
+
+        // Claims lapse when the owner faults.
+        let (owner_process, other_process) = (processes[0].unwrap(), processes[1].unwrap());
+        owner_process.set_fault_state();
+        assert_eq!(
+            gpio.command(10, 0, 0, other_process.appid()),
+            ReturnCode::SUCCESS
+        );
+    }
+}
@@ -1,92 +1,355 @@
 //! Driver for an I2C Master interface.
+//!
+//! Besides raw reads and writes, processes can scan the bus for devices and,
+//! if the controller supports it, talk to SMBus devices with optional packet
+//! error checking (PEC). PEC bytes are computed and checked by this driver,
+//! so they work with any controller that implements `SMBusMaster`.
+//!
+//! Every transfer is guarded by a timeout. If the controller does not
+//! complete a transfer in time, for example because a device holds the clock
+//! low, the driver aborts the transfer, which resets the controller and
+//! returns the kernel buffer, and reports `ECANCEL` to the process instead of
+//! waiting forever.
+//!
+//! A scan probes every address with a one-byte read, since not every
+//! controller supports zero-length writes. Devices that only acknowledge
+//! writes are not found.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let i2c_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let i2c_master = static_init!(
+//!     capsules::i2c_master::I2CMasterDriver<sam4l::i2c::I2CHw, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::i2c_master::I2CMasterDriver::new(
+//!         &sam4l::i2c::I2C2,
+//!         &mut capsules::i2c_master::BUF,
+//!         i2c_alarm,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! sam4l::i2c::I2C2.set_master_client(i2c_master);
+//! i2c_alarm.set_client(i2c_master);
+//! // Only if the controller implements `SMBusMaster`, as the OpenTitan one does:
+//! i2c_master.set_smbus(&sam4l::i2c::I2C2);
+//! ```
 
+use core::cell::Cell;
 use enum_primitive::enum_from_primitive;
-use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::i2c;
+use kernel::hil::time::{self, Alarm, Frequency};
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 
 /// Syscall driver number.
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::I2cMaster as usize;
 
+/// Transfers that take longer than this are aborted, unless the process
+/// chose another timeout.
+pub const DEFAULT_TIMEOUT_MS: usize = 100;
+
+/// Size of the scan result: one bit per 7-bit address.
+pub const SCAN_RESULT_LEN: usize = 16;
+
+/// Addresses probed by a scan. The others are reserved by the I2C
+/// specification.
+const SCAN_FIRST: u8 = 0x08;
+const SCAN_LAST: u8 = 0x77;
+
 #[derive(Default)]
 pub struct App {
     callback: Option<Callback>,
     slice: Option<AppSlice<Shared, u8>>,
+    /// Whether SMBus transfers carry a PEC byte.
+    pec: bool,
+    /// Transfer timeout in alarm tics, or 0 for `DEFAULT_TIMEOUT_MS`.
+    timeout: u32,
 }
 
 pub static mut BUF: [u8; 64] = [0; 64];
 
+#[derive(Clone, Copy)]
 struct Transaction {
-    /// The buffer containing the bytes to transmit as it should be returned to
-    /// the client
     app_id: AppId,
-    /// The total amount to transmit
-    read_len: OptionalCell<usize>,
+    cmd: Cmd,
+    addr: u8,
+    write_len: usize,
+    /// Number of bytes read, including the PEC byte if there is one.
+    read_len: usize,
+    pec: bool,
+    /// For a scan, the addresses that responded so far.
+    found: u128,
 }
 
-pub struct I2CMasterDriver<I: 'static + i2c::I2CMaster> {
+pub struct I2CMasterDriver<I: 'static + i2c::I2CMaster, A: 'static + Alarm<'static>> {
     i2c: &'static I,
+    smbus: OptionalCell<&'static dyn i2c::SMBusMaster>,
+    alarm: &'static A,
     buf: TakeCell<'static, [u8]>,
-    tx: MapCell<Transaction>,
+    tx: OptionalCell<Transaction>,
+    /// Timeout of the transaction in progress, in alarm tics.
+    timeout: Cell<u32>,
     apps: Grant<App>,
 }
 
-impl<I: 'static + i2c::I2CMaster> I2CMasterDriver<I> {
-    pub fn new(i2c: &'static I, buf: &'static mut [u8], apps: Grant<App>) -> I2CMasterDriver<I> {
+impl<I: 'static + i2c::I2CMaster, A: 'static + Alarm<'static>> I2CMasterDriver<I, A> {
+    pub fn new(
+        i2c: &'static I,
+        buf: &'static mut [u8],
+        alarm: &'static A,
+        apps: Grant<App>,
+    ) -> I2CMasterDriver<I, A> {
         I2CMasterDriver {
             i2c,
+            smbus: OptionalCell::empty(),
+            alarm,
             buf: TakeCell::new(buf),
-            tx: MapCell::empty(),
+            tx: OptionalCell::empty(),
+            timeout: Cell::new(0),
             apps,
         }
     }
 
+    /// Enables the SMBus commands. `smbus` must be the same controller as
+    /// the one the driver was created with.
+    pub fn set_smbus(&self, smbus: &'static dyn i2c::SMBusMaster) {
+        self.smbus.set(smbus);
+    }
+
+    /// Starts a transaction, copying the bytes to write from the process's
+    /// buffer.
     fn operation(
         &self,
         app_id: AppId,
-        app: &mut App,
-        command: Cmd,
+        cmd: Cmd,
         addr: u8,
-        wlen: u8,
-        rlen: u8,
+        write_len: usize,
+        read_len: usize,
     ) -> ReturnCode {
+        if self.tx.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let smbus = match cmd {
+            Cmd::SmbusWrite | Cmd::SmbusRead | Cmd::SmbusWriteRead => {
+                match self.smbus.map(|smbus| *smbus) {
+                    Some(smbus) => Some(smbus),
+                    None => return ReturnCode::ENOSUPPORT,
+                }
+            }
+            _ => None,
+        };
         self.apps
-            .enter(app_id, |_, _| {
-                if let Some(app_buffer) = app.slice.take() {
-                    self.buf.take().map(|buffer| {
-                        for n in 0..wlen as usize {
-                            buffer[n] = app_buffer.as_ref()[n];
-                        }
+            .enter(app_id, |app, _| {
+                let app_buffer = match app.slice {
+                    Some(ref slice) => slice,
+                    None => return ReturnCode::EINVAL,
+                };
+                let pec = smbus.is_some() && app.pec;
+                let pec_len = if pec { 1 } else { 0 };
+                let (wire_write_len, wire_read_len) = match cmd {
+                    Cmd::Scan => (0, 1),
+                    _ => (
+                        write_len + if read_len == 0 { pec_len } else { 0 },
+                        read_len + if read_len > 0 { pec_len } else { 0 },
+                    ),
+                };
+                let data_len = match cmd {
+                    Cmd::Scan => SCAN_RESULT_LEN,
+                    _ => core::cmp::max(write_len, read_len),
+                };
+                if app_buffer.len() < data_len {
+                    return ReturnCode::EINVAL;
+                }
+                let buffer = match self.buf.take() {
+                    Some(buffer) => buffer,
+                    // The controller still holds the buffer of an aborted
+                    // transfer.
+                    None => return ReturnCode::EBUSY,
+                };
+                if wire_write_len > buffer.len()
+                    || wire_read_len > buffer.len()
+                    || wire_write_len > u8::max_value() as usize
+                    || wire_read_len > u8::max_value() as usize
+                {
+                    self.buf.replace(buffer);
+                    return ReturnCode::ESIZE;
+                }
+                match app_buffer.as_ref().get(..write_len) {
+                    Some(data) => buffer[..write_len].copy_from_slice(data),
+                    None => {
+                        self.buf.replace(buffer);
+                        return ReturnCode::EINVAL;
+                    }
+                }
+                if pec && read_len == 0 {
+                    buffer[write_len] = pec_write(addr, &buffer[..write_len]);
+                }
 
-                        let read_len: OptionalCell<usize>;
-                        if rlen == 0 {
-                            read_len = OptionalCell::empty();
-                        } else {
-                            read_len = OptionalCell::new(rlen as usize);
-                        }
-                        self.tx.put(Transaction { app_id, read_len });
-                        app.slice = Some(app_buffer);
-
-                        match command {
-                            Cmd::Ping => return ReturnCode::EINVAL,
-                            Cmd::Write => self.i2c.write(addr, buffer, wlen),
-                            Cmd::Read => self.i2c.read(addr, buffer, rlen),
-                            Cmd::WriteRead => self.i2c.write_read(addr, buffer, wlen, rlen),
-                        }
+                self.timeout.set(match app.timeout {
+                    0 => Self::timeout_tics(DEFAULT_TIMEOUT_MS).unwrap_or(u32::max_value() / 2),
+                    timeout => timeout,
+                });
+                self.tx.set(Transaction {
+                    app_id,
+                    cmd,
+                    addr,
+                    write_len,
+                    read_len: wire_read_len,
+                    pec,
+                    found: 0,
+                });
+                self.start(
+                    smbus,
+                    addr,
+                    buffer,
+                    wire_write_len as u8,
+                    wire_read_len as u8,
+                )
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Converts a timeout in milliseconds to alarm tics. Timeouts are kept
+    /// within half the range of the alarm, so that they can be told apart
+    /// from deadlines that already passed.
+    fn timeout_tics(timeout_ms: usize) -> Option<u32> {
+        (timeout_ms as u64)
+            .checked_mul(<A::Frequency>::frequency() as u64)
+            .map(|tics| tics / 1000)
+            .filter(|&tics| tics <= (u32::max_value() / 2) as u64)
+            .map(|tics| tics as u32)
+    }
+
+    /// Hands `buffer` to the controller and arms the timeout. On failure, the
+    /// transaction is dropped.
+    fn start(
+        &self,
+        smbus: Option<&'static dyn i2c::SMBusMaster>,
+        addr: u8,
+        buffer: &'static mut [u8],
+        write_len: u8,
+        read_len: u8,
+    ) -> ReturnCode {
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(self.timeout.get()));
+        let result = match smbus {
+            Some(smbus) => match (write_len, read_len) {
+                (_, 0) => smbus.smbus_write(addr, buffer, write_len),
+                (0, _) => smbus.smbus_read(addr, buffer, read_len),
+                _ => smbus.smbus_write_read(addr, buffer, write_len, read_len),
+            },
+            None => {
+                match (write_len, read_len) {
+                    (_, 0) => self.i2c.write(addr, buffer, write_len),
+                    (0, _) => self.i2c.read(addr, buffer, read_len),
+                    _ => self.i2c.write_read(addr, buffer, write_len, read_len),
+                }
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err((error, buffer)) => {
+                self.alarm.disable();
+                self.tx.clear();
+                self.buf.replace(buffer);
+                error_to_return_code(error)
+            }
+        }
+    }
+
+    /// Ends the transaction and notifies the process with `result` and
+    /// `value`.
+    fn finish(&self, tx: Transaction, result: ReturnCode, value: usize) {
+        self.apps
+            .enter(tx.app_id, |app, _| {
+                app.callback.map(|mut cb| {
+                    cb.schedule(tx.cmd as usize, usize::from(result), value);
+                });
+            })
+            .ok();
+    }
+
+    /// Handles the end of one probe of a scan, starting the next one if
+    /// there are addresses left.
+    fn scan_step(&self, mut tx: Transaction, buffer: &'static mut [u8], error: i2c::Error) {
+        // Only a device that acknowledged its address is there. Other
+        // errors, such as a lost arbitration, say nothing about the address.
+        if error == i2c::Error::CommandComplete {
+            tx.found |= 1 << tx.addr;
+        }
+        if tx.addr < SCAN_LAST {
+            tx.addr += 1;
+            self.tx.set(tx);
+            if self.start(None, tx.addr, buffer, 0, 1) != ReturnCode::SUCCESS {
+                self.finish(tx, ReturnCode::FAIL, 0);
+            }
+            return;
+        }
+        self.buf.replace(buffer);
+        self.apps
+            .enter(tx.app_id, |app, _| {
+                // The process may have shared a shorter buffer since the
+                // scan started.
+                match app
+                    .slice
+                    .as_mut()
+                    .and_then(|slice| slice.as_mut().get_mut(..SCAN_RESULT_LEN))
+                {
+                    Some(result) => {
+                        result.copy_from_slice(&tx.found.to_le_bytes());
                         ReturnCode::SUCCESS
-                    });
-                    // buffer has not been returned by I2C
-                    // i2c_master.rs should not allow us to get here
-                    ReturnCode::ENOMEM
-                } else {
-                    // AppDriver is attempting operation
-                    // but has not granted memory
-                    ReturnCode::EINVAL
+                    }
+                    None => ReturnCode::EINVAL,
                 }
             })
-            .expect("Appid does not map to app");
-        ReturnCode::ENOSUPPORT
+            .map(|result| self.finish(tx, result, tx.found.count_ones() as usize))
+            .ok();
+    }
+}
+
+/// The SMBus PEC: a CRC-8 with polynomial x^8 + x^2 + x + 1 over every byte
+/// on the wire, address bytes included.
+fn crc8(crc: u8, data: &[u8]) -> u8 {
+    data.iter().fold(crc, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// PEC of a write of `data` to `addr`.
+fn pec_write(addr: u8, data: &[u8]) -> u8 {
+    crc8(crc8(0, &[addr << 1]), data)
+}
+
+/// PEC of a transfer that writes `written` to `addr`, if anything, then
+/// reads `read` from it.
+fn pec_read(addr: u8, written: &[u8], read: &[u8]) -> u8 {
+    let crc = if written.is_empty() {
+        0
+    } else {
+        pec_write(addr, written)
+    };
+    crc8(crc8(crc, &[addr << 1 | 1]), read)
+}
+
+fn error_to_return_code(error: i2c::Error) -> ReturnCode {
+    match error {
+        i2c::Error::CommandComplete => ReturnCode::SUCCESS,
+        i2c::Error::AddressNak | i2c::Error::DataNak => ReturnCode::ENOACK,
+        i2c::Error::ArbitrationLost => ReturnCode::EBUSY,
+        i2c::Error::NotSupported => ReturnCode::ENOSUPPORT,
+        i2c::Error::Overrun => ReturnCode::FAIL,
     }
 }
 
@@ -99,10 +362,16 @@ pub enum Cmd {
     Write = 1,
     Read = 2,
     WriteRead = 3,
+    Scan = 4,
+    SmbusWrite = 5,
+    SmbusRead = 6,
+    SmbusWriteRead = 7,
+    SetPec = 8,
+    SetTimeout = 9,
 }
 }
 
-impl<I: i2c::I2CMaster> Driver for I2CMasterDriver<I> {
+impl<I: i2c::I2CMaster, A: Alarm<'static>> Driver for I2CMasterDriver<I, A> {
     /// Setup shared buffers.
     ///
     /// ### `allow_num`
@@ -130,7 +399,9 @@ impl<I: i2c::I2CMaster> Driver for I2CMasterDriver<I> {
     ///
     /// ### `subscribe_num`
     ///
-    /// - `1`: Write buffer completed callback
+    /// - `1`: Transfer completed callback. It receives the command number, the
+    ///        result as a return code, and for a scan the number of devices
+    ///        found.
     fn subscribe(
         &self,
         subscribe_num: usize,
@@ -149,42 +420,57 @@ impl<I: i2c::I2CMaster> Driver for I2CMasterDriver<I> {
     }
 
     /// Initiate transfers
+    ///
+    /// ### `cmd_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Write `arg2` bytes to address `arg1`.
+    /// - `2`: Read `arg2` bytes from address `arg1`.
+    /// - `3`: Write `arg1 >> 8` bytes to address `arg1 & 0xff`, then read
+    ///        `arg2` bytes.
+    /// - `4`: Scan the bus and write a bitmap of the addresses that
+    ///        responded to the buffer.
+    /// - `5`-`7`: Like `1`-`3`, as SMBus transfers.
+    /// - `8`: Enable SMBus packet error checking if `arg1` is non-zero.
+    /// - `9`: Set the transfer timeout to `arg1` milliseconds, or to the
+    ///        default if `arg1` is 0. Returns EINVAL if the timeout is too
+    ///        long for the alarm.
     fn command(&self, cmd_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        let addr = arg1 as u8;
         if let Some(cmd) = Cmd::from_usize(cmd_num) {
             match cmd {
                 Cmd::Ping => ReturnCode::SUCCESS,
-                Cmd::Write => self
-                    .apps
-                    .enter(appid, |app, _| {
-                        let addr = arg1 as u8;
-                        let write_len = arg2;
-                        self.operation(appid, app, Cmd::Write, addr, write_len as u8, 0);
-                        ReturnCode::SUCCESS
-                    })
-                    .unwrap_or_else(|err| err.into()),
-                Cmd::Read => self
+                Cmd::Write | Cmd::SmbusWrite => self.operation(appid, cmd, addr, arg2, 0),
+                Cmd::Read | Cmd::SmbusRead => self.operation(appid, cmd, addr, 0, arg2),
+                Cmd::WriteRead | Cmd::SmbusWriteRead => {
+                    let write_len = arg1 >> 8; // can extend to 24 bit write length
+                    let read_len = arg2; // can extend to 32 bit read length
+                    if write_len == 0 || read_len == 0 {
+                        return ReturnCode::EINVAL;
+                    }
+                    self.operation(appid, cmd, addr, write_len, read_len)
+                }
+                Cmd::Scan => self.operation(appid, cmd, SCAN_FIRST, 0, 0),
+                Cmd::SetPec => self
                     .apps
                     .enter(appid, |app, _| {
-                        let addr = arg1 as u8;
-                        let read_len = arg2;
-                        self.operation(appid, app, Cmd::Read, addr, 0, read_len as u8);
+                        app.pec = arg1 != 0;
                         ReturnCode::SUCCESS
                     })
                     .unwrap_or_else(|err| err.into()),
-                Cmd::WriteRead => {
-                    let addr = arg1 as u8;
-                    let write_len = arg1 >> 8; // can extend to 24 bit write length
-                    let read_len = arg2; // can extend to 32 bit read length
+                Cmd::SetTimeout => {
+                    let timeout = match arg1 {
+                        0 => 0,
+                        timeout_ms => match Self::timeout_tics(timeout_ms) {
+                            // Round up, so that short timeouts on slow alarms
+                            // are not taken as the default.
+                            Some(tics) => core::cmp::max(tics, 1),
+                            None => return ReturnCode::EINVAL,
+                        },
+                    };
                     self.apps
                         .enter(appid, |app, _| {
-                            self.operation(
-                                appid,
-                                app,
-                                Cmd::WriteRead,
-                                addr,
-                                write_len as u8,
-                                read_len as u8,
-                            );
+                            app.timeout = timeout;
                             ReturnCode::SUCCESS
                         })
                         .unwrap_or_else(|err| err.into())
@@ -196,29 +482,244 @@ impl<I: i2c::I2CMaster> Driver for I2CMasterDriver<I> {
     }
 }
 
-impl<I: i2c::I2CMaster> i2c::I2CHwMasterClient for I2CMasterDriver<I> {
-    fn command_complete(&self, buffer: &'static mut [u8], _error: i2c::Error) {
-        self.tx.take().map(|tx| {
-            self.apps.enter(tx.app_id, |app, _| {
-                if let Some(read_len) = tx.read_len.take() {
-                    if let Some(mut app_buffer) = app.slice.take() {
-                        for n in 0..read_len {
-                            app_buffer.as_mut()[n] = buffer[n];
+impl<I: i2c::I2CMaster, A: Alarm<'static>> i2c::I2CHwMasterClient for I2CMasterDriver<I, A> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        // Without a transaction, the transfer was already abandoned, and
+        // only the buffer matters.
+        let tx = match self.tx.take() {
+            Some(tx) => tx,
+            None => {
+                self.buf.replace(buffer);
+                return;
+            }
+        };
+        self.alarm.disable();
+        if tx.cmd == Cmd::Scan {
+            self.scan_step(tx, buffer, error);
+            return;
+        }
+
+        let mut result = error_to_return_code(error);
+        let data_len = tx.read_len - if tx.pec { 1 } else { 0 };
+        if result == ReturnCode::SUCCESS && tx.read_len > 0 {
+            self.apps
+                .enter(tx.app_id, |app, _| {
+                    // The process may have shared a shorter buffer since the
+                    // transfer started.
+                    let slice = match app.slice.as_mut() {
+                        Some(slice) if slice.len() >= core::cmp::max(tx.write_len, data_len) => {
+                            slice
+                        }
+                        _ => {
+                            result = ReturnCode::EINVAL;
+                            return;
                         }
+                    };
+                    let written = &slice.as_ref()[..tx.write_len];
+                    if tx.pec && buffer[data_len] != pec_read(tx.addr, written, &buffer[..data_len])
+                    {
+                        result = ReturnCode::FAIL;
                     } else {
-                        // app has requested read but we have no buffer
-                        // should not arrive here
+                        slice.as_mut()[..data_len].copy_from_slice(&buffer[..data_len]);
                     }
-                }
+                })
+                .ok();
+        }
+        self.buf.replace(buffer);
+        self.finish(tx, result, 0);
+    }
+}
 
-                // signal to driver that tx complete
-                app.callback.map(|mut cb| {
-                    cb.schedule(0, 0, 0);
-                });
-            })
+impl<I: i2c::I2CMaster, A: Alarm<'static>> time::AlarmClient for I2CMasterDriver<I, A> {
+    /// The transfer in progress timed out: abort it, take back the buffer,
+    /// and report the failure.
+    fn fired(&self) {
+        self.tx.take().map(|tx| {
+            self.i2c.abort().map(|buffer| self.buf.replace(buffer));
+            self.finish(tx, ReturnCode::ECANCEL, 0);
         });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{crc8, pec_read, pec_write};
+    use super::{I2CMasterDriver, DRIVER_NUM};
+    use host::chip::HostChip;
+    use host::harness::{
+        command, create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp,
+        TestPlatform,
+    };
+    use host::mock::alarm::MockAlarm;
+    use host::mock::i2c::MockI2CMaster;
+    use host::syscall::{HostApp, Resumption};
+    use kernel::hil::time::Alarm;
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::{Driver, ReturnCode};
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    pub fn pec_matches_smbus_examples() {
+        // CRC-8/SMBUS check value.
+        assert_eq!(crc8(0, b"123456789"), 0xf4);
+        // Write byte 0x55 to command 0x01 of the device at 0x5a.
+        assert_eq!(pec_write(0x5a, &[0x01, 0x55]), crc8(0, &[0xb4, 0x01, 0x55]));
+        // Read a byte from command 0x01: the address is sent again, with the
+        // read bit set.
+        assert_eq!(
+            pec_read(0x5a, &[0x01], &[0x55]),
+            crc8(0, &[0xb4, 0x01, 0xb5, 0x55])
+        );
+        assert_eq!(pec_read(0x5a, &[], &[0x55]), crc8(0, &[0xb5, 0x55]));
+    }
+
+    #[test]
+    pub fn i2c_master_scans_checks_pec_and_times_out() {
+        let (kernel, processes) = create_kernel();
+        let mock_i2c: &'static MockI2CMaster = leak(MockI2CMaster::new());
+        let mock_alarm: &'static MockAlarm = leak(MockAlarm::new());
+        let i2c = leak(I2CMasterDriver::new(
+            mock_i2c,
+            Box::leak(Box::new([0; 8])),
+            mock_alarm,
+            create_grant(kernel),
+        ));
+        kernel::hil::i2c::I2CMaster::set_master_client(mock_i2c, i2c);
+        mock_alarm.set_client(i2c);
+
+        let i2c_command = |command_num, arg| command(DRIVER_NUM, command_num, arg);
+        let app = leak(ScriptedApp::new_with_memory(
+            vec![
+                Syscall::SUBSCRIBE {
+                    driver_number: DRIVER_NUM,
+                    subdriver_number: 1,
+                    callback_ptr: 0x1000 as *mut (),
+                    appdata: 0,
+                },
+                Syscall::ALLOW {
+                    driver_number: DRIVER_NUM,
+                    subdriver_number: 1,
+                    allow_address: 0 as *mut u8,
+                    allow_size: 16,
+                },
+                i2c_command(6, 0x5a),
+                i2c_command(4, 0),
+            ],
+            &[0; 16],
+        ));
+        let apps: &'static [&'static dyn HostApp] = leak([app as &dyn HostApp]);
+        let chip = leak(HostChip::new(apps, None));
+        let processes = load_apps(kernel, chip, processes, &[("i2c", 0)], FaultResponse::Panic);
+
+        let platform = TestPlatform::new().driver(DRIVER_NUM, i2c);
+        run_until_idle(kernel, &platform, chip);
+        // SMBus is unavailable until the board enables it.
+        assert_eq!(
+            *app.returns.borrow(),
+            [0, 0, isize::from(ReturnCode::ENOSUPPORT), 0]
+        );
+
+        // The scan probes every non-reserved address with a one-byte read.
+        // Only addresses that acknowledge count as found.
+        let mut probes = 0;
+        while let Some(transfer) = mock_i2c.transfer() {
+            assert_eq!((transfer.write_len, transfer.read_len), (0, 1));
+            let error = match transfer.addr {
+                0x1d | 0x50 => kernel::hil::i2c::Error::CommandComplete,
+                0x30 => kernel::hil::i2c::Error::ArbitrationLost,
+                _ => kernel::hil::i2c::Error::AddressNak,
+            };
+            assert!(mock_i2c.complete(&[0], error));
+            probes += 1;
+        }
+        assert_eq!(probes, 0x70);
+        run_until_idle(kernel, &platform, chip);
+        let memory = || app.memory(0, 16);
+        let mut expected = [0; 16];
+        expected[0x1d / 8] |= 1 << (0x1d % 8);
+        expected[0x50 / 8] |= 1 << (0x50 % 8);
+        assert_eq!(memory(), expected);
+
+        let callbacks = || -> Vec<(usize, usize, usize)> {
+            app.callbacks
+                .borrow()
+                .iter()
+                .map(|callback| match *callback {
+                    Resumption::Callback {
+                        argument0,
+                        argument1,
+                        argument2,
+                        ..
+                    } => (argument0, argument1, argument2),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        assert_eq!(callbacks(), [(4, 0, 2)]);
+
+        // SMBus reads with PEC are checked against the CRC-8 of every byte on
+        // the wire.
+        let crc8 = |data: &[u8]| {
+            data.iter().fold(0u8, |crc, &byte| {
+                (0..8).fold(crc ^ byte, |crc, _| {
+                    if crc & 0x80 != 0 {
+                        (crc << 1) ^ 0x07
+                    } else {
+                        crc << 1
+                    }
+                })
+            })
+        };
+        i2c.set_smbus(mock_i2c);
+        let appid = processes[0].unwrap().appid();
+        assert_eq!(i2c.command(8, 1, 0, appid), ReturnCode::SUCCESS);
+        let register = memory()[0];
+        let pec = crc8(&[0x5a << 1, register, 0x5a << 1 | 1, 0x42]);
+        for &(received_pec, result) in [(pec, ReturnCode::SUCCESS), (!pec, ReturnCode::FAIL)].iter()
+        {
+            assert_eq!(i2c.command(7, 0x5a | 1 << 8, 1, appid), ReturnCode::SUCCESS);
+            let transfer = mock_i2c.transfer().unwrap();
+            assert!(transfer.smbus);
+            assert_eq!((transfer.write_len, transfer.read_len), (1, 2));
+            assert!(mock_i2c.complete(
+                &[0x42, received_pec],
+                kernel::hil::i2c::Error::CommandComplete
+            ));
+            run_until_idle(kernel, &platform, chip);
+            assert_eq!(callbacks().last(), Some(&(7, usize::from(result), 0)));
+        }
+        assert_eq!(memory()[0], 0x42);
+
+        // A transfer that never completes is aborted when the timeout expires,
+        // and the controller gives the buffer back for the next transfer.
+        assert_eq!(i2c.command(2, 0x20, 1, appid), ReturnCode::SUCCESS);
+        assert!(mock_alarm.advance_to_alarm());
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            callbacks().last(),
+            Some(&(2, usize::from(ReturnCode::ECANCEL), 0))
+        );
+        assert!(mock_i2c.transfer().is_none());
+        assert_eq!(i2c.command(2, 0x20, 1, appid), ReturnCode::SUCCESS);
+        assert!(mock_i2c.complete(&[0x24], kernel::hil::i2c::Error::CommandComplete));
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            callbacks().last(),
+            Some(&(2, usize::from(ReturnCode::SUCCESS), 0))
+        );
+        assert_eq!(memory()[0], 0x24);
 
-        //recover buffer
-        self.buf.put(Some(buffer));
+        // Timeouts the alarm cannot represent are refused.
+        assert_eq!(
+            i2c.command(9, usize::max_value(), 0, appid),
+            ReturnCode::EINVAL
+        );
+        assert_eq!(i2c.command(9, 1 << 31, 0, appid), ReturnCode::EINVAL);
+        assert_eq!(i2c.command(9, 1000, 0, appid), ReturnCode::SUCCESS);
     }
 }
@@ -1,149 +1,21 @@
 //! IEEE 802.15.4 userspace interface for configuration and transmit/receive.
 //!
 //! Implements a userspace interface for sending and receiving IEEE 802.15.4
-//! frames. Also provides a minimal list-based interface for managing keys and
-//! known link neighbors, which is needed for 802.15.4 security.
+//! frames. Also provides an interface for managing the keys and known link
+//! neighbors of the key table, which is needed for 802.15.4 security.
 
-use crate::ieee802154::{device, framer};
+use crate::ieee802154::device;
+use crate::ieee802154::key_table::{self, DeviceDescriptor, KeyDescriptor, KeyTable};
+use crate::ieee802154::key_table::{MAX_DEVICES, MAX_KEYS};
 use crate::net::ieee802154::{AddressMode, Header, KeyId, MacAddress, PanID, SecurityLevel};
-use crate::net::stream::{decode_bytes, decode_u8, encode_bytes, encode_u8, SResult};
-use core::cell::Cell;
+use crate::net::stream::encode_u32;
 use core::cmp::min;
-use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 
-const MAX_NEIGHBORS: usize = 4;
-const MAX_KEYS: usize = 4;
-
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Ieee802154 as usize;
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-struct DeviceDescriptor {
-    short_addr: u16,
-    long_addr: [u8; 8],
-}
-
-impl Default for DeviceDescriptor {
-    fn default() -> Self {
-        DeviceDescriptor {
-            short_addr: 0,
-            long_addr: [0; 8],
-        }
-    }
-}
-
-/// The Key ID mode mapping expected by the userland driver
-#[repr(u8)]
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-enum KeyIdModeUserland {
-    Implicit = 0,
-    Index = 1,
-    Source4Index = 2,
-    Source8Index = 3,
-}
-
-impl KeyIdModeUserland {
-    pub fn from_u8(byte: u8) -> Option<KeyIdModeUserland> {
-        match byte {
-            0 => Some(KeyIdModeUserland::Implicit),
-            1 => Some(KeyIdModeUserland::Index),
-            2 => Some(KeyIdModeUserland::Source4Index),
-            3 => Some(KeyIdModeUserland::Source8Index),
-            _ => None,
-        }
-    }
-}
-
-/// Encodes a key ID into a buffer in the format expected by the userland driver.
-fn encode_key_id(key_id: &KeyId, buf: &mut [u8]) -> SResult {
-    let off = enc_consume!(buf; encode_u8, KeyIdModeUserland::from(key_id) as u8);
-    let off = match *key_id {
-        KeyId::Implicit => 0,
-        KeyId::Index(index) => enc_consume!(buf, off; encode_u8, index),
-        KeyId::Source4Index(ref src, index) => {
-            let off = enc_consume!(buf, off; encode_bytes, src);
-            enc_consume!(buf, off; encode_u8, index)
-        }
-        KeyId::Source8Index(ref src, index) => {
-            let off = enc_consume!(buf, off; encode_bytes, src);
-            enc_consume!(buf, off; encode_u8, index)
-        }
-    };
-    stream_done!(off);
-}
-
-/// Decodes a key ID that is in the format produced by the userland driver.
-fn decode_key_id(buf: &[u8]) -> SResult<KeyId> {
-    stream_len_cond!(buf, 1);
-    let mode = stream_from_option!(KeyIdModeUserland::from_u8(buf[0]));
-    match mode {
-        KeyIdModeUserland::Implicit => stream_done!(0, KeyId::Implicit),
-        KeyIdModeUserland::Index => {
-            let (off, index) = dec_try!(buf; decode_u8);
-            stream_done!(off, KeyId::Index(index));
-        }
-        KeyIdModeUserland::Source4Index => {
-            let mut src = [0u8; 4];
-            let off = dec_consume!(buf; decode_bytes, &mut src);
-            let (off, index) = dec_try!(buf, off; decode_u8);
-            stream_done!(off, KeyId::Source4Index(src, index));
-        }
-        KeyIdModeUserland::Source8Index => {
-            let mut src = [0u8; 8];
-            let off = dec_consume!(buf; decode_bytes, &mut src);
-            let (off, index) = dec_try!(buf, off; decode_u8);
-            stream_done!(off, KeyId::Source8Index(src, index));
-        }
-    }
-}
-
-impl From<&KeyId> for KeyIdModeUserland {
-    fn from(key_id: &KeyId) -> Self {
-        match *key_id {
-            KeyId::Implicit => KeyIdModeUserland::Implicit,
-            KeyId::Index(_) => KeyIdModeUserland::Index,
-            KeyId::Source4Index(_, _) => KeyIdModeUserland::Source4Index,
-            KeyId::Source8Index(_, _) => KeyIdModeUserland::Source8Index,
-        }
-    }
-}
-
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-struct KeyDescriptor {
-    level: SecurityLevel,
-    key_id: KeyId,
-    key: [u8; 16],
-}
-
-impl Default for KeyDescriptor {
-    fn default() -> Self {
-        KeyDescriptor {
-            level: SecurityLevel::None,
-            key_id: KeyId::Implicit,
-            key: [0; 16],
-        }
-    }
-}
-
-impl KeyDescriptor {
-    fn decode(buf: &[u8]) -> SResult<KeyDescriptor> {
-        stream_len_cond!(buf, 27);
-        let level = stream_from_option!(SecurityLevel::from_scf(buf[0]));
-        let (_, key_id) = dec_try!(buf, 1; decode_key_id);
-        let mut key = [0u8; 16];
-        let off = dec_consume!(buf, 11; decode_bytes, &mut key);
-        stream_done!(
-            off,
-            KeyDescriptor {
-                level: level,
-                key_id: key_id,
-                key: key,
-            }
-        );
-    }
-}
-
 pub struct App {
     rx_callback: Option<Callback>,
     tx_callback: Option<Callback>,
@@ -170,17 +42,8 @@ pub struct RadioDriver<'a> {
     /// Underlying MAC device, possibly multiplexed
     mac: &'a dyn device::MacDevice<'a>,
 
-    /// List of (short address, long address) pairs representing IEEE 802.15.4
-    /// neighbors.
-    neighbors: MapCell<[DeviceDescriptor; MAX_NEIGHBORS]>,
-    /// Actual number of neighbors in the fixed size array of neighbors.
-    num_neighbors: Cell<usize>,
-
-    /// List of (security level, key_id, key) tuples representing IEEE 802.15.4
-    /// key descriptors.
-    keys: MapCell<[KeyDescriptor; MAX_KEYS]>,
-    /// Actual number of keys in the fixed size array of keys.
-    num_keys: Cell<usize>,
+    /// Keys and neighbors used to secure frames.
+    key_table: &'a KeyTable<'a>,
 
     /// Grant of apps that use this radio driver.
     apps: Grant<App>,
@@ -194,131 +57,19 @@ pub struct RadioDriver<'a> {
 impl<'a> RadioDriver<'a> {
     pub fn new(
         mac: &'a dyn device::MacDevice<'a>,
+        key_table: &'a KeyTable<'a>,
         grant: Grant<App>,
         kernel_tx: &'static mut [u8],
     ) -> RadioDriver<'a> {
         RadioDriver {
             mac: mac,
-            neighbors: MapCell::new(Default::default()),
-            num_neighbors: Cell::new(0),
-            keys: MapCell::new(Default::default()),
-            num_keys: Cell::new(0),
+            key_table: key_table,
             apps: grant,
             current_app: OptionalCell::empty(),
             kernel_tx: TakeCell::new(kernel_tx),
         }
     }
 
-    // Neighbor management functions
-
-    /// Add a new neighbor to the end of the list if there is still space
-    /// for one, returning its new index. If the neighbor already exists,
-    /// returns the index of the existing neighbor. Returns `None` if there is
-    /// no remaining space.
-    fn add_neighbor(&self, new_neighbor: DeviceDescriptor) -> Option<usize> {
-        self.neighbors.and_then(|neighbors| {
-            let num_neighbors = self.num_neighbors.get();
-            let position = neighbors[..num_neighbors]
-                .iter()
-                .position(|neighbor| *neighbor == new_neighbor);
-            match position {
-                Some(index) => Some(index),
-                None => {
-                    if num_neighbors == MAX_NEIGHBORS {
-                        None
-                    } else {
-                        neighbors[num_neighbors] = new_neighbor;
-                        self.num_neighbors.set(num_neighbors + 1);
-                        Some(num_neighbors)
-                    }
-                }
-            }
-        })
-    }
-
-    /// Deletes the neighbor at `index` if `index` is valid, returning
-    /// `ReturnCode::SUCCESS`. Otherwise, returns `ReturnCode::EINVAL`.  Ensures
-    /// that the `neighbors` list is compact by shifting forward any elements
-    /// after the index.
-    fn remove_neighbor(&self, index: usize) -> ReturnCode {
-        let num_neighbors = self.num_neighbors.get();
-        if index < num_neighbors {
-            self.neighbors.map(|neighbors| {
-                for i in index..(num_neighbors - 1) {
-                    neighbors[i] = neighbors[i + 1];
-                }
-            });
-            self.num_neighbors.set(num_neighbors - 1);
-            ReturnCode::SUCCESS
-        } else {
-            ReturnCode::EINVAL
-        }
-    }
-
-    /// Gets the `DeviceDescriptor` corresponding to the neighbor at a
-    /// particular `index`, if the `index` is valid. Otherwise, returns `None`
-    fn get_neighbor(&self, index: usize) -> Option<DeviceDescriptor> {
-        if index < self.num_neighbors.get() {
-            self.neighbors.map(|neighbors| neighbors[index])
-        } else {
-            None
-        }
-    }
-
-    // Key management functions
-
-    /// Add a new key to the end of the list if there is still space
-    /// for one, returning its new index. If the key already exists,
-    /// returns the index of the existing key. Returns `None` if there
-    /// is no remaining space.
-    fn add_key(&self, new_key: KeyDescriptor) -> Option<usize> {
-        self.keys.and_then(|keys| {
-            let num_keys = self.num_keys.get();
-            let position = keys[..num_keys].iter().position(|key| *key == new_key);
-            match position {
-                Some(index) => Some(index),
-                None => {
-                    if num_keys == MAX_KEYS {
-                        None
-                    } else {
-                        keys[num_keys] = new_key;
-                        self.num_keys.set(num_keys + 1);
-                        Some(num_keys)
-                    }
-                }
-            }
-        })
-    }
-
-    /// Deletes the key at `index` if `index` is valid, returning
-    /// `ReturnCode::SUCCESS`. Otherwise, returns `ReturnCode::EINVAL`.  Ensures
-    /// that the `keys` list is compact by shifting forward any elements
-    /// after the index.
-    fn remove_key(&self, index: usize) -> ReturnCode {
-        let num_keys = self.num_keys.get();
-        if index < num_keys {
-            self.keys.map(|keys| {
-                for i in index..(num_keys - 1) {
-                    keys[i] = keys[i + 1];
-                }
-            });
-            self.num_keys.set(num_keys - 1);
-            ReturnCode::SUCCESS
-        } else {
-            ReturnCode::EINVAL
-        }
-    }
-
-    /// Gets the `DeviceDescriptor` corresponding to the key at a
-    /// particular `index`, if the `index` is valid. Otherwise, returns `None`
-    fn get_key(&self, index: usize) -> Option<KeyDescriptor> {
-        if index < self.num_keys.get() {
-            self.keys.map(|keys| keys[index])
-        } else {
-            None
-        }
-    }
-
     /// Utility function to perform an action on an app in a system call.
     #[inline]
     fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
@@ -493,36 +244,6 @@ impl<'a> RadioDriver<'a> {
     }
 }
 
-impl framer::DeviceProcedure for RadioDriver<'_> {
-    /// Gets the long address corresponding to the neighbor that matches the given
-    /// MAC address. If no such neighbor exists, returns `None`.
-    fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
-        self.neighbors.and_then(|neighbors| {
-            neighbors[..self.num_neighbors.get()]
-                .iter()
-                .find(|neighbor| match addr {
-                    MacAddress::Short(addr) => addr == neighbor.short_addr,
-                    MacAddress::Long(addr) => addr == neighbor.long_addr,
-                })
-                .map(|neighbor| neighbor.long_addr)
-        })
-    }
-}
-
-impl framer::KeyProcedure for RadioDriver<'_> {
-    /// Gets the key corresponding to the key that matches the given security
-    /// level `level` and key ID `key_id`. If no such key matches, returns
-    /// `None`.
-    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
-        self.keys.and_then(|keys| {
-            keys[..self.num_keys.get()]
-                .iter()
-                .find(|key| key.level == level && key.key_id == key_id)
-                .map(|key| key.key)
-        })
-    }
-}
-
 impl Driver for RadioDriver<'_> {
     /// Setup buffers to read/write from.
     ///
@@ -627,6 +348,20 @@ impl Driver for RadioDriver<'_> {
     ///                      9 bytes: the key ID (might not use all bytes) +
     ///                      16 bytes: the key.
     /// - `25`: Remove the key at an index.
+    /// - `26`: Transmit a frame to the given short address.
+    ///        app_cfg (in): 1 byte: the security level +
+    ///                      1 byte: the key ID mode +
+    ///                      9 bytes: the key ID (might not use all bytes).
+    /// - `27`: Get the frame counter of the next secured frame sent.
+    ///        app_cfg (out): 4 bytes: the frame counter, big-endian.
+    /// - `28`: Get the lowest frame counter accepted from the neighbor at an
+    ///        index.
+    ///        app_cfg (out): 4 bytes: the frame counter, big-endian.
+    ///
+    /// Keys and neighbors are kept in the key table, which saves them to
+    /// nonvolatile storage if the board provides it. Adding a key with the same
+    /// security level and key ID as an existing one replaces it, so keys can be
+    /// rotated without interrupting communication with the neighbors.
     fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
         match command_num {
             0 => ReturnCode::SUCCESS,
@@ -684,24 +419,26 @@ impl Driver for RadioDriver<'_> {
             13 => {
                 // Guarantee that it is positive by adding 1
                 ReturnCode::SuccessWithValue {
-                    value: MAX_NEIGHBORS + 1,
+                    value: MAX_DEVICES + 1,
                 }
             }
             14 => {
                 // Guarantee that it is positive by adding 1
                 ReturnCode::SuccessWithValue {
-                    value: self.num_neighbors.get() + 1,
+                    value: self.key_table.num_devices() + 1,
                 }
             }
             15 => self
-                .get_neighbor(arg1)
+                .key_table
+                .get_device(arg1)
                 .map_or(ReturnCode::EINVAL, |neighbor| {
                     ReturnCode::SuccessWithValue {
                         value: (neighbor.short_addr as usize) + 1,
                     }
                 }),
             16 => self.do_with_cfg_mut(appid, 8, |cfg| {
-                self.get_neighbor(arg1)
+                self.key_table
+                    .get_device(arg1)
                     .map_or(ReturnCode::EINVAL, |neighbor| {
                         cfg.copy_from_slice(&neighbor.long_addr);
                         ReturnCode::SUCCESS
@@ -711,12 +448,13 @@ impl Driver for RadioDriver<'_> {
                 let mut new_neighbor: DeviceDescriptor = DeviceDescriptor::default();
                 new_neighbor.short_addr = arg1 as u16;
                 new_neighbor.long_addr.copy_from_slice(cfg);
-                self.add_neighbor(new_neighbor)
+                self.key_table
+                    .add_device(new_neighbor)
                     .map_or(ReturnCode::EINVAL, |index| ReturnCode::SuccessWithValue {
                         value: index + 1,
                     })
             }),
-            18 => self.remove_neighbor(arg1),
+            18 => self.key_table.remove_device(arg1),
             19 => {
                 // Guarantee that it is positive by adding 1
                 ReturnCode::SuccessWithValue {
@@ -726,35 +464,38 @@ impl Driver for RadioDriver<'_> {
             20 => {
                 // Guarantee that it is positive by adding 1
                 ReturnCode::SuccessWithValue {
-                    value: self.num_keys.get() + 1,
+                    value: self.key_table.num_keys() + 1,
                 }
             }
-            21 => {
-                self.get_key(arg1)
-                    .map_or(ReturnCode::EINVAL, |key| ReturnCode::SuccessWithValue {
-                        value: (key.level as usize) + 1,
-                    })
-            }
+            21 => self
+                .key_table
+                .get_key(arg1)
+                .map_or(ReturnCode::EINVAL, |key| ReturnCode::SuccessWithValue {
+                    value: (key.level as usize) + 1,
+                }),
             22 => self.do_with_cfg_mut(appid, 10, |cfg| {
-                self.get_key(arg1)
-                    .and_then(|key| encode_key_id(&key.key_id, cfg).done())
+                self.key_table
+                    .get_key(arg1)
+                    .and_then(|key| key_table::encode_key_id(cfg, &key.key_id).done())
                     .map_or(ReturnCode::EINVAL, |_| ReturnCode::SUCCESS)
             }),
             23 => self.do_with_cfg_mut(appid, 16, |cfg| {
-                self.get_key(arg1).map_or(ReturnCode::EINVAL, |key| {
-                    cfg.copy_from_slice(&key.key);
-                    ReturnCode::SUCCESS
-                })
+                self.key_table
+                    .get_key(arg1)
+                    .map_or(ReturnCode::EINVAL, |key| {
+                        cfg.copy_from_slice(&key.key);
+                        ReturnCode::SUCCESS
+                    })
             }),
             24 => self.do_with_cfg(appid, 27, |cfg| {
                 KeyDescriptor::decode(cfg)
                     .done()
-                    .and_then(|(_, new_key)| self.add_key(new_key))
+                    .and_then(|(_, new_key)| self.key_table.add_key(new_key))
                     .map_or(ReturnCode::EINVAL, |index| ReturnCode::SuccessWithValue {
                         value: index + 1,
                     })
             }),
-            25 => self.remove_key(arg1),
+            25 => self.key_table.remove_key(arg1),
             26 => {
                 self.do_with_app(appid, |app| {
                     if app.pending_tx.is_some() {
@@ -775,7 +516,7 @@ impl Driver for RadioDriver<'_> {
                         if level == SecurityLevel::None {
                             Some((dst_addr, None))
                         } else {
-                            let key_id = match decode_key_id(&cfg.as_ref()[1..]).done() {
+                            let key_id = match key_table::decode_key_id(&cfg.as_ref()[1..]).done() {
                                 Some((_, key_id)) => key_id,
                                 None => {
                                     return None;
@@ -792,6 +533,17 @@ impl Driver for RadioDriver<'_> {
                     self.do_next_tx_sync(appid)
                 })
             }
+            27 => self.do_with_cfg_mut(appid, 4, |cfg| {
+                encode_u32(cfg, self.key_table.frame_counter())
+                    .done()
+                    .map_or(ReturnCode::EINVAL, |_| ReturnCode::SUCCESS)
+            }),
+            28 => self.do_with_cfg_mut(appid, 4, |cfg| {
+                self.key_table
+                    .get_device(arg1)
+                    .and_then(|neighbor| encode_u32(cfg, neighbor.frame_counter).done())
+                    .map_or(ReturnCode::EINVAL, |_| ReturnCode::SUCCESS)
+            }),
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -51,14 +51,18 @@
 //! ```
 //!
 //! You should also be able to set up the userspace driver for receiving/sending
-//! 802.15.4 frames:
+//! 802.15.4 frames, which manages the keys and neighbors of a
+//! `capsules::ieee802154::key_table::KeyTable`:
 //!
 //! ```rust
+//! let key_table = static_init!(
+//!     capsules::ieee802154::key_table::KeyTable<'static>,
+//!     capsules::ieee802154::key_table::KeyTable::new());
 //! let radio_capsule = static_init!(
 //!     capsules::ieee802154::RadioDriver<'static>,
-//!     capsules::ieee802154::RadioDriver::new(mac_device, kernel::Grant::create(), &mut RADIO_BUF));
-//! mac_device.set_key_procedure(radio_capsule);
-//! mac_device.set_device_procedure(radio_capsule);
+//!     capsules::ieee802154::RadioDriver::new(mac_device, key_table, kernel::Grant::create(), &mut RADIO_BUF));
+//! mac_device.set_key_procedure(key_table);
+//! mac_device.set_device_procedure(key_table);
 //! mac_device.set_transmit_client(radio_capsule);
 //! mac_device.set_receive_client(radio_capsule);
 //! ```
@@ -229,6 +233,11 @@ pub trait KeyProcedure {
     /// Lookup the KeyDescriptor matching the provided security level and key ID
     /// mode and return the key associatied with it.
     fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]>;
+
+    /// IEEE 802.15.4-2015, 9.2.1, step f. Return the frame counter to use in
+    /// the next secured frame and increment it, or `None` if no more frames
+    /// can be secured.
+    fn next_frame_counter(&self) -> Option<u32>;
 }
 
 /// IEEE 802.15.4-2015, 9.2.5, DeviceDescriptor lookup procedure.
@@ -242,6 +251,16 @@ pub trait DeviceProcedure {
     /// address is already long, a long address should be returned only if the
     /// given address matches a known DeviceDescriptor.
     fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]>;
+
+    /// IEEE 802.15.4-2015, 9.2.3, step h. Check that a frame with this frame
+    /// counter from the device with the given address is not a replay, that
+    /// is, that its frame counter is at least that of the DeviceDescriptor.
+    fn frame_counter_valid(&self, addr: MacAddress, frame_counter: u32) -> bool;
+
+    /// IEEE 802.15.4-2015, 9.2.3, step n. Record that a frame with this frame
+    /// counter from the device with the given address has been unsecured, so
+    /// that only higher frame counters are valid from then on.
+    fn update_frame_counter(&self, addr: MacAddress, frame_counter: u32);
 }
 
 /// This state enum describes the state of the transmission pipeline.
@@ -355,6 +374,23 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
         })
     }
 
+    /// Check the frame counter of a frame from a device against the one in its
+    /// DeviceDescriptor, managed elsewhere.
+    fn frame_counter_valid(&self, src_addr: Option<MacAddress>, frame_counter: u32) -> bool {
+        src_addr.map_or(false, |addr| {
+            self.device_procedure.map_or(false, |device_procedure| {
+                device_procedure.frame_counter_valid(addr, frame_counter)
+            })
+        })
+    }
+
+    /// Get the frame counter for an outgoing frame from the key management
+    /// procedure implemented elsewhere.
+    fn next_frame_counter(&self) -> Option<u32> {
+        self.key_procedure
+            .and_then(|key_procedure| key_procedure.next_frame_counter())
+    }
+
     /// IEEE 802.15.4-2015, 9.2.1, outgoing frame security procedure
     /// Performs the first checks in the security procedure. The rest of the
     /// steps are performed as part of the transmission pipeline.
@@ -428,11 +464,12 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
                         // Step g, h: Check frame counter
                         let frame_counter = match security.frame_counter {
                             Some(frame_counter) => {
-                                if frame_counter == 0xffffffff {
+                                if frame_counter == 0xffffffff
+                                    || !self.frame_counter_valid(header.src_addr, frame_counter)
+                                {
                                     // Counter error
                                     return None;
                                 }
-                                // TODO: Check frame counter against source device
                                 frame_counter
                             }
                             // TSCH mode, where ASN is used instead, not supported
@@ -622,6 +659,18 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
                         // This is so that it is possible to tell if the
                         // frame was secured or unsecured, while still
                         // always receiving the frame payload in plaintext.
+                        //
+                        // IEEE 802.15.4-2015: 9.2.3, step n: Now that the frame
+                        // is authenticated, frames with the same counter are
+                        // replays.
+                        if let (Some(security), Some(src_addr)) = (header.security, header.src_addr)
+                        {
+                            if let Some(frame_counter) = security.frame_counter {
+                                self.device_procedure.map(|device_procedure| {
+                                    device_procedure.update_frame_counter(src_addr, frame_counter)
+                                });
+                            }
+                        }
                         self.rx_client.map(|client| {
                             client.receive(
                                 &buf,
@@ -703,9 +752,8 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         // specification.
         let src_addr_long = self.get_address_long();
         let security_desc = security_needed.and_then(|(level, key_id)| {
-            self.lookup_key(level, key_id).map(|key| {
-                // TODO: lookup frame counter for device
-                let frame_counter = 0;
+            let key = self.lookup_key(level, key_id)?;
+            self.next_frame_counter().map(|frame_counter| {
                 let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
                 (
                     Security {
@@ -916,3 +964,145 @@ impl<'a, M: Mac, A: AES128CCM<'a>> CCMClient for Framer<'a, M, A> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::ieee802154::mac::AwakeMac;
+    use host::harness::leak;
+    use host::mock::radio::MockRadio;
+    use kernel::hil::radio::{RadioConfig, RadioData};
+    use std::boxed::Box;
+    use std::cell::RefCell;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// CCM* that cannot secure anything, for tests of unsecured frames.
+    struct NoCcm;
+
+    impl AES128CCM<'static> for NoCcm {
+        fn set_client(&self, _client: &'static dyn CCMClient) {}
+
+        fn set_key(&self, _key: &[u8]) -> ReturnCode {
+            ReturnCode::ENOSUPPORT
+        }
+
+        fn set_nonce(&self, _nonce: &[u8]) -> ReturnCode {
+            ReturnCode::ENOSUPPORT
+        }
+
+        fn crypt(
+            &self,
+            buf: &'static mut [u8],
+            _a_off: usize,
+            _m_off: usize,
+            _m_len: usize,
+            _mic_len: usize,
+            _confidential: bool,
+            _encrypting: bool,
+        ) -> (ReturnCode, Option<&'static mut [u8]>) {
+            (ReturnCode::ENOSUPPORT, Some(buf))
+        }
+    }
+
+    /// Records completed transmissions and the source and payload of received
+    /// frames.
+    #[derive(Default)]
+    struct Frames {
+        sent: RefCell<Vec<(bool, ReturnCode)>>,
+        received: RefCell<Vec<(Option<MacAddress>, Vec<u8>)>>,
+    }
+
+    impl TxClient for Frames {
+        fn send_done(&self, _spi_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+            self.sent.borrow_mut().push((acked, result));
+        }
+    }
+
+    impl RxClient for Frames {
+        fn receive<'a>(
+            &self,
+            buf: &'a [u8],
+            header: Header<'a>,
+            data_offset: usize,
+            data_len: usize,
+        ) {
+            self.received.borrow_mut().push((
+                header.src_addr,
+                buf[data_offset..data_offset + data_len].to_vec(),
+            ));
+        }
+    }
+
+    #[test]
+    pub fn frames_go_through_the_radio() {
+        let radio: &MockRadio = leak(MockRadio::new());
+        let mac = leak(AwakeMac::new(radio));
+        let framer = leak(Framer::new(mac, leak(NoCcm)));
+        let frames = leak(Frames::default());
+        radio.set_transmit_client(mac);
+        radio.set_receive_client(mac, Box::leak(Box::new([0; radio::MAX_BUF_SIZE])));
+        mac.set_transmit_client(framer);
+        mac.set_receive_client(framer);
+        framer.set_transmit_client(frames);
+        framer.set_receive_client(frames);
+        radio.set_address(0x0001);
+        radio.start();
+
+        // A secured frame cannot be prepared without keys.
+        let buf = Box::leak(Box::new([0; radio::MAX_BUF_SIZE]));
+        let buf = framer
+            .prepare_data_frame(
+                buf,
+                0xabcd,
+                MacAddress::Short(0x0002),
+                0xabcd,
+                MacAddress::Short(0x0001),
+                Some((SecurityLevel::EncMic32, KeyId::Index(1))),
+            )
+            .unwrap_err();
+
+        let mut frame = framer
+            .prepare_data_frame(
+                buf,
+                0xabcd,
+                MacAddress::Short(0x0002),
+                0xabcd,
+                MacAddress::Short(0x0001),
+                None,
+            )
+            .unwrap();
+        assert_eq!(frame.append_payload(&[1, 2, 3]), ReturnCode::SUCCESS);
+        assert_eq!(framer.transmit(frame), (ReturnCode::SUCCESS, None));
+        assert_eq!(radio.transmit_count(), 1);
+        let sent = radio
+            .with_transmitted_frame(|frame| frame.to_vec())
+            .unwrap();
+        let (data_offset, (header, _)) = Header::decode(&sent, false).done().unwrap();
+        assert_eq!(header.frame_type, FrameType::Data);
+        assert_eq!(header.seq, Some(0));
+        assert_eq!(header.dst_addr, Some(MacAddress::Short(0x0002)));
+        assert_eq!(header.src_addr, Some(MacAddress::Short(0x0001)));
+        assert_eq!(&sent[data_offset..], &[1, 2, 3]);
+
+        assert!(radio.complete_transmit(true, ReturnCode::SUCCESS));
+        assert_eq!(*frames.sent.borrow(), [(true, ReturnCode::SUCCESS)]);
+
+        // Receive the frame as its destination. Frames with a bad CRC are
+        // dropped, and each frame gives the receive buffer back to the radio.
+        radio.set_address(0x0002);
+        assert!(radio.receive_frame(&sent, false));
+        assert!(frames.received.borrow().is_empty());
+        assert!(radio.receive_frame(&sent, true));
+        assert!(radio.receive_frame(&sent, true));
+        assert_eq!(
+            *frames.received.borrow(),
+            [
+                (Some(MacAddress::Short(0x0001)), vec![1, 2, 3]),
+                (Some(MacAddress::Short(0x0001)), vec![1, 2, 3]),
+            ]
+        );
+    }
+}
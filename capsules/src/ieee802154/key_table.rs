@@ -0,0 +1,870 @@
+//! Table of the IEEE 802.15.4 keys and neighbors used by the MAC security
+//! procedures, along with their frame counters.
+//!
+//! The framer looks up keys and the extended addresses of neighbors through
+//! the `KeyProcedure` and `DeviceProcedure` traits, which this table
+//! implements. Keys are looked up by security level and key ID, so several
+//! keys with different key ID modes or indices can be in use at once. The
+//! table also holds the frame counter of outgoing frames and, for each
+//! neighbor, the lowest frame counter that is still accepted from it, so that
+//! replayed frames are dropped (IEEE 802.15.4-2015, 9.2.3).
+//!
+//! The table can be kept in nonvolatile storage, so that keys survive a reboot
+//! and frame counters are never reused. It is written whenever a key or a
+//! neighbor changes. Rather than writing the outgoing frame counter for every
+//! frame, a limit `FRAME_COUNTER_RESERVE` ahead of it is written, and the
+//! counter resumes from that limit after a reboot. The frame counter of each
+//! neighbor is written the same way, so that after a reboot frames from it
+//! are only accepted from its limit on and none can be replayed. At most
+//! `FRAME_COUNTER_RESERVE` of the frames it sends afterwards are rejected.
+//! If a write fails, the table is no longer kept in storage.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let key_table = static_init!(
+//!     capsules::ieee802154::key_table::KeyTable<'static>,
+//!     capsules::ieee802154::key_table::KeyTable::new());
+//! mac_device.set_key_procedure(key_table);
+//! mac_device.set_device_procedure(key_table);
+//!
+//! // Optionally, restore the table from and save it to nonvolatile storage.
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_storage, key_table);
+//! key_table.set_storage(nv_storage, KEY_TABLE_ADDRESS, &mut KEY_TABLE_BUF);
+//! ```
+
+use crate::ieee802154::framer::{DeviceProcedure, KeyProcedure};
+use crate::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8, SResult};
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::debug;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::ReturnCode;
+
+pub const MAX_KEYS: usize = 4;
+pub const MAX_DEVICES: usize = 4;
+
+pub const KEY_DESCRIPTOR_LEN: usize = 27;
+const DEVICE_DESCRIPTOR_LEN: usize = 14;
+
+/// Marks a stored table, and changes along with the layout of the table.
+const STORAGE_MAGIC: u32 = 0x4b54_0001;
+/// The number of bytes the table takes up in nonvolatile storage.
+pub const STORAGE_LEN: usize =
+    4 + 4 + 1 + MAX_KEYS * KEY_DESCRIPTOR_LEN + 1 + MAX_DEVICES * DEVICE_DESCRIPTOR_LEN;
+
+/// How far ahead of a frame counter the stored limit is. The limit is raised
+/// once the counter is within half of this of it.
+pub const FRAME_COUNTER_RESERVE: u32 = 1024;
+
+/// Whether the stored `limit` of a frame counter must be raised, now that
+/// the counter is at `frame_counter`.
+fn near_limit(limit: u32, frame_counter: u32) -> bool {
+    limit != u32::max_value() && limit.saturating_sub(frame_counter) <= FRAME_COUNTER_RESERVE / 2
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DeviceDescriptor {
+    pub short_addr: u16,
+    pub long_addr: [u8; 8],
+    /// The lowest frame counter accepted in the next secured frame from this
+    /// device.
+    pub frame_counter: u32,
+}
+
+impl Default for DeviceDescriptor {
+    fn default() -> Self {
+        DeviceDescriptor {
+            short_addr: 0,
+            long_addr: [0; 8],
+            frame_counter: 0,
+        }
+    }
+}
+
+impl DeviceDescriptor {
+    fn same_device(&self, other: &DeviceDescriptor) -> bool {
+        self.short_addr == other.short_addr && self.long_addr == other.long_addr
+    }
+
+    fn matches(&self, addr: MacAddress) -> bool {
+        match addr {
+            MacAddress::Short(addr) => addr == self.short_addr,
+            MacAddress::Long(addr) => addr == self.long_addr,
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> SResult {
+        let off = enc_consume!(buf; encode_u16, self.short_addr);
+        let off = enc_consume!(buf, off; encode_bytes, &self.long_addr);
+        let off = enc_consume!(buf, off; encode_u32, self.frame_counter);
+        stream_done!(off);
+    }
+
+    fn decode(buf: &[u8]) -> SResult<DeviceDescriptor> {
+        let (off, short_addr) = dec_try!(buf; decode_u16);
+        let mut long_addr = [0u8; 8];
+        let off = dec_consume!(buf, off; decode_bytes, &mut long_addr);
+        let (off, frame_counter) = dec_try!(buf, off; decode_u32);
+        stream_done!(
+            off,
+            DeviceDescriptor {
+                short_addr: short_addr,
+                long_addr: long_addr,
+                frame_counter: frame_counter,
+            }
+        );
+    }
+}
+
+/// The Key ID mode mapping expected by the userland driver, which is also used
+/// for the stored table.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum KeyIdModeUserland {
+    Implicit = 0,
+    Index = 1,
+    Source4Index = 2,
+    Source8Index = 3,
+}
+
+impl KeyIdModeUserland {
+    pub fn from_u8(byte: u8) -> Option<KeyIdModeUserland> {
+        match byte {
+            0 => Some(KeyIdModeUserland::Implicit),
+            1 => Some(KeyIdModeUserland::Index),
+            2 => Some(KeyIdModeUserland::Source4Index),
+            3 => Some(KeyIdModeUserland::Source8Index),
+            _ => None,
+        }
+    }
+}
+
+impl From<&KeyId> for KeyIdModeUserland {
+    fn from(key_id: &KeyId) -> Self {
+        match *key_id {
+            KeyId::Implicit => KeyIdModeUserland::Implicit,
+            KeyId::Index(_) => KeyIdModeUserland::Index,
+            KeyId::Source4Index(_, _) => KeyIdModeUserland::Source4Index,
+            KeyId::Source8Index(_, _) => KeyIdModeUserland::Source8Index,
+        }
+    }
+}
+
+/// Encodes a key ID into a buffer in the format expected by the userland driver.
+pub fn encode_key_id(buf: &mut [u8], key_id: &KeyId) -> SResult {
+    let off = enc_consume!(buf; encode_u8, KeyIdModeUserland::from(key_id) as u8);
+    let off = match *key_id {
+        KeyId::Implicit => off,
+        KeyId::Index(index) => enc_consume!(buf, off; encode_u8, index),
+        KeyId::Source4Index(ref src, index) => {
+            let off = enc_consume!(buf, off; encode_bytes, src);
+            enc_consume!(buf, off; encode_u8, index)
+        }
+        KeyId::Source8Index(ref src, index) => {
+            let off = enc_consume!(buf, off; encode_bytes, src);
+            enc_consume!(buf, off; encode_u8, index)
+        }
+    };
+    stream_done!(off);
+}
+
+/// Decodes a key ID that is in the format produced by the userland driver.
+pub fn decode_key_id(buf: &[u8]) -> SResult<KeyId> {
+    let (off, mode) = dec_try!(buf; decode_u8);
+    let mode = stream_from_option!(KeyIdModeUserland::from_u8(mode));
+    match mode {
+        KeyIdModeUserland::Implicit => stream_done!(off, KeyId::Implicit),
+        KeyIdModeUserland::Index => {
+            let (off, index) = dec_try!(buf, off; decode_u8);
+            stream_done!(off, KeyId::Index(index));
+        }
+        KeyIdModeUserland::Source4Index => {
+            let mut src = [0u8; 4];
+            let off = dec_consume!(buf, off; decode_bytes, &mut src);
+            let (off, index) = dec_try!(buf, off; decode_u8);
+            stream_done!(off, KeyId::Source4Index(src, index));
+        }
+        KeyIdModeUserland::Source8Index => {
+            let mut src = [0u8; 8];
+            let off = dec_consume!(buf, off; decode_bytes, &mut src);
+            let (off, index) = dec_try!(buf, off; decode_u8);
+            stream_done!(off, KeyId::Source8Index(src, index));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct KeyDescriptor {
+    pub level: SecurityLevel,
+    pub key_id: KeyId,
+    pub key: [u8; 16],
+}
+
+impl Default for KeyDescriptor {
+    fn default() -> Self {
+        KeyDescriptor {
+            level: SecurityLevel::None,
+            key_id: KeyId::Implicit,
+            key: [0; 16],
+        }
+    }
+}
+
+impl KeyDescriptor {
+    /// Encodes the descriptor as 1 byte of security level, 10 bytes of key ID
+    /// (padded with zeroes) and 16 bytes of key.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        stream_len_cond!(buf, KEY_DESCRIPTOR_LEN);
+        for byte in buf[1..11].iter_mut() {
+            *byte = 0;
+        }
+        enc_consume!(buf; encode_u8, self.level as u8);
+        enc_consume!(buf, 1; encode_key_id, &self.key_id);
+        let off = enc_consume!(buf, 11; encode_bytes, &self.key);
+        stream_done!(off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<KeyDescriptor> {
+        stream_len_cond!(buf, KEY_DESCRIPTOR_LEN);
+        let level = stream_from_option!(SecurityLevel::from_scf(buf[0]));
+        let (_, key_id) = dec_try!(buf, 1; decode_key_id);
+        let mut key = [0u8; 16];
+        let off = dec_consume!(buf, 11; decode_bytes, &mut key);
+        stream_done!(
+            off,
+            KeyDescriptor {
+                level: level,
+                key_id: key_id,
+                key: key,
+            }
+        );
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum StorageState {
+    /// The table is not kept in nonvolatile storage.
+    Unused,
+    Loading,
+    Idle,
+    Storing,
+}
+
+pub struct KeyTable<'a> {
+    /// List of (security level, key_id, key) tuples representing IEEE 802.15.4
+    /// key descriptors.
+    keys: MapCell<[KeyDescriptor; MAX_KEYS]>,
+    /// Actual number of keys in the fixed size array of keys.
+    num_keys: Cell<usize>,
+
+    /// List of neighbors, with the frame counters expected from them.
+    devices: MapCell<[DeviceDescriptor; MAX_DEVICES]>,
+    /// Actual number of neighbors in the fixed size array of neighbors.
+    num_devices: Cell<usize>,
+
+    /// Frame counter of the next secured frame sent.
+    frame_counter: Cell<u32>,
+    /// Frame counter limit that has been stored, which the outgoing frame
+    /// counter cannot reach until a higher one has been stored.
+    frame_counter_limit: Cell<u32>,
+    /// Frame counter limit that is being stored.
+    pending_limit: Cell<u32>,
+    /// Frame counter limits of the neighbors that have been stored, which
+    /// are where their frame counters resume from after a reboot.
+    device_limits: Cell<[u32; MAX_DEVICES]>,
+    /// Frame counter limits of the neighbors that are being stored.
+    pending_device_limits: Cell<[u32; MAX_DEVICES]>,
+
+    storage: OptionalCell<&'a dyn NonvolatileStorage<'static>>,
+    storage_address: Cell<usize>,
+    storage_buf: TakeCell<'static, [u8]>,
+    storage_state: Cell<StorageState>,
+    /// Whether the table changed while it was being loaded or stored, and
+    /// must be stored again.
+    dirty: Cell<bool>,
+}
+
+impl<'a> KeyTable<'a> {
+    pub fn new() -> KeyTable<'a> {
+        KeyTable {
+            keys: MapCell::new(Default::default()),
+            num_keys: Cell::new(0),
+            devices: MapCell::new(Default::default()),
+            num_devices: Cell::new(0),
+            frame_counter: Cell::new(0),
+            frame_counter_limit: Cell::new(0),
+            pending_limit: Cell::new(0),
+            device_limits: Cell::new([0; MAX_DEVICES]),
+            pending_device_limits: Cell::new([0; MAX_DEVICES]),
+            storage: OptionalCell::empty(),
+            storage_address: Cell::new(0),
+            storage_buf: TakeCell::empty(),
+            storage_state: Cell::new(StorageState::Unused),
+            dirty: Cell::new(false),
+        }
+    }
+
+    /// Keeps the table at `address` in `storage`, using `buf`, which must be
+    /// at least `STORAGE_LEN` bytes long. The stored table is loaded first,
+    /// replacing any keys and neighbors added before it is, and no secured
+    /// frames are sent until it has been loaded. If the read cannot be
+    /// started, the table is not kept in storage and `buf`, which the storage
+    /// does not give back, must be replaced to try again.
+    pub fn set_storage(
+        &self,
+        storage: &'a dyn NonvolatileStorage<'static>,
+        address: usize,
+        buf: &'static mut [u8],
+    ) -> ReturnCode {
+        if buf.len() < STORAGE_LEN {
+            return ReturnCode::ESIZE;
+        }
+        if self.storage.is_some() {
+            return ReturnCode::EALREADY;
+        }
+        self.storage.set(storage);
+        self.storage_address.set(address);
+        self.storage_state.set(StorageState::Loading);
+        let result = storage.read(buf, address, STORAGE_LEN);
+        if result != ReturnCode::SUCCESS {
+            self.storage.clear();
+            self.storage_state.set(StorageState::Unused);
+        }
+        result
+    }
+
+    /// Writes the table to storage if it is kept there. If the table is
+    /// already being loaded or written, it is written again afterwards. If
+    /// the write cannot be started, the table is no longer kept in storage,
+    /// as the storage does not give the buffer back.
+    fn store(&self) {
+        match self.storage_state.get() {
+            StorageState::Unused => {}
+            StorageState::Loading | StorageState::Storing => self.dirty.set(true),
+            StorageState::Idle => {
+                self.storage_buf.take().map(|buf| {
+                    let limit = self
+                        .frame_counter
+                        .get()
+                        .saturating_add(FRAME_COUNTER_RESERVE);
+                    let device_limits = match self.encode(buf, limit).done() {
+                        Some((_, device_limits)) => device_limits,
+                        None => {
+                            self.storage_buf.replace(buf);
+                            return;
+                        }
+                    };
+                    self.dirty.set(false);
+                    self.pending_limit.set(limit);
+                    self.pending_device_limits.set(device_limits);
+                    self.storage_state.set(StorageState::Storing);
+                    let result = self.storage.map_or(ReturnCode::FAIL, move |storage| {
+                        storage.write(buf, self.storage_address.get(), STORAGE_LEN)
+                    });
+                    if result != ReturnCode::SUCCESS {
+                        self.storage.clear();
+                        self.storage_state.set(StorageState::Unused);
+                        debug!("KeyTable: storing failed ({:?}), table not kept", result);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Encodes the table with the limit of the outgoing frame counter, and
+    /// the frame counter of each neighbor replaced by a limit ahead of it.
+    /// Returns the limits of the neighbors.
+    fn encode(&self, buf: &mut [u8], frame_counter_limit: u32) -> SResult<[u32; MAX_DEVICES]> {
+        let off = enc_consume!(buf; encode_u32, STORAGE_MAGIC);
+        let off = enc_consume!(buf, off; encode_u32, frame_counter_limit);
+        let off = enc_consume!(buf, off; encode_u8, self.num_keys.get() as u8);
+        let keys = self.keys.map_or(Default::default(), |keys| *keys);
+        let mut off = off;
+        for key in keys.iter() {
+            off = enc_consume!(buf, off; key; encode);
+        }
+        off = enc_consume!(buf, off; encode_u8, self.num_devices.get() as u8);
+        let devices = self.devices.map_or(Default::default(), |devices| *devices);
+        let mut device_limits = [0; MAX_DEVICES];
+        for (device, limit) in devices.iter().zip(device_limits.iter_mut()) {
+            *limit = device.frame_counter.saturating_add(FRAME_COUNTER_RESERVE);
+            let device = DeviceDescriptor {
+                frame_counter: *limit,
+                ..*device
+            };
+            off = enc_consume!(buf, off; device; encode);
+        }
+        stream_done!(off, device_limits);
+    }
+
+    /// Restores the table from `buf`, if it holds one. Returns the stored
+    /// frame counter limit.
+    fn decode(&self, buf: &[u8]) -> SResult<u32> {
+        let (off, magic) = dec_try!(buf; decode_u32);
+        stream_cond!(magic == STORAGE_MAGIC);
+        let (off, frame_counter_limit) = dec_try!(buf, off; decode_u32);
+        let (mut off, num_keys) = dec_try!(buf, off; decode_u8);
+        let mut keys: [KeyDescriptor; MAX_KEYS] = Default::default();
+        for key in keys.iter_mut() {
+            let (next, decoded) = dec_try!(buf, off; KeyDescriptor::decode);
+            *key = decoded;
+            off = next;
+        }
+        let (mut off, num_devices) = dec_try!(buf, off; decode_u8);
+        let mut devices: [DeviceDescriptor; MAX_DEVICES] = Default::default();
+        let mut device_limits = [0; MAX_DEVICES];
+        for (device, limit) in devices.iter_mut().zip(device_limits.iter_mut()) {
+            let (next, decoded) = dec_try!(buf, off; DeviceDescriptor::decode);
+            *device = decoded;
+            *limit = decoded.frame_counter;
+            off = next;
+        }
+        stream_cond!(num_keys as usize <= MAX_KEYS && num_devices as usize <= MAX_DEVICES);
+
+        self.keys.replace(keys);
+        self.num_keys.set(num_keys as usize);
+        self.devices.replace(devices);
+        self.num_devices.set(num_devices as usize);
+        self.device_limits.set(device_limits);
+        stream_done!(off, frame_counter_limit);
+    }
+
+    /// Returns the frame counter of the next secured frame sent.
+    pub fn frame_counter(&self) -> u32 {
+        self.frame_counter.get()
+    }
+
+    // Neighbor management functions
+
+    pub fn num_devices(&self) -> usize {
+        self.num_devices.get()
+    }
+
+    /// Add a new neighbor to the end of the list if there is still space
+    /// for one, returning its new index. If the neighbor already exists,
+    /// returns the index of the existing neighbor and keeps its frame counter.
+    /// Returns `None` if there is no remaining space.
+    pub fn add_device(&self, new_device: DeviceDescriptor) -> Option<usize> {
+        let result = self.devices.and_then(|devices| {
+            let num_devices = self.num_devices.get();
+            let position = devices[..num_devices]
+                .iter()
+                .position(|device| device.same_device(&new_device));
+            match position {
+                Some(index) => Some(index),
+                None => {
+                    if num_devices == MAX_DEVICES {
+                        None
+                    } else {
+                        devices[num_devices] = new_device;
+                        self.num_devices.set(num_devices + 1);
+                        for limits in [&self.device_limits, &self.pending_device_limits].iter() {
+                            let mut updated = limits.get();
+                            updated[num_devices] = 0;
+                            limits.set(updated);
+                        }
+                        Some(num_devices)
+                    }
+                }
+            }
+        });
+        if result.is_some() {
+            self.store();
+        }
+        result
+    }
+
+    /// Deletes the neighbor at `index` if `index` is valid, returning
+    /// `ReturnCode::SUCCESS`. Otherwise, returns `ReturnCode::EINVAL`.  Ensures
+    /// that the `devices` list is compact by shifting forward any elements
+    /// after the index.
+    pub fn remove_device(&self, index: usize) -> ReturnCode {
+        let num_devices = self.num_devices.get();
+        if index < num_devices {
+            self.devices.map(|devices| {
+                for i in index..(num_devices - 1) {
+                    devices[i] = devices[i + 1];
+                }
+            });
+            for limits in [&self.device_limits, &self.pending_device_limits].iter() {
+                let mut updated = limits.get();
+                for i in index..(num_devices - 1) {
+                    updated[i] = updated[i + 1];
+                }
+                limits.set(updated);
+            }
+            self.num_devices.set(num_devices - 1);
+            self.store();
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::EINVAL
+        }
+    }
+
+    /// Gets the `DeviceDescriptor` corresponding to the neighbor at a
+    /// particular `index`, if the `index` is valid. Otherwise, returns `None`
+    pub fn get_device(&self, index: usize) -> Option<DeviceDescriptor> {
+        if index < self.num_devices.get() {
+            self.devices.map(|devices| devices[index])
+        } else {
+            None
+        }
+    }
+
+    // Key management functions
+
+    pub fn num_keys(&self) -> usize {
+        self.num_keys.get()
+    }
+
+    /// Add a new key to the end of the list if there is still space for one,
+    /// returning its new index. If there already is a key with the same
+    /// security level and key ID, it is replaced, which is how keys are
+    /// rotated, and its index is returned. Returns `None` if there is no
+    /// remaining space.
+    pub fn add_key(&self, new_key: KeyDescriptor) -> Option<usize> {
+        let result = self.keys.and_then(|keys| {
+            let num_keys = self.num_keys.get();
+            let position = keys[..num_keys]
+                .iter()
+                .position(|key| key.level == new_key.level && key.key_id == new_key.key_id);
+            match position {
+                Some(index) => {
+                    keys[index] = new_key;
+                    Some(index)
+                }
+                None => {
+                    if num_keys == MAX_KEYS {
+                        None
+                    } else {
+                        keys[num_keys] = new_key;
+                        self.num_keys.set(num_keys + 1);
+                        Some(num_keys)
+                    }
+                }
+            }
+        });
+        if result.is_some() {
+            self.store();
+        }
+        result
+    }
+
+    /// Deletes the key at `index` if `index` is valid, returning
+    /// `ReturnCode::SUCCESS`. Otherwise, returns `ReturnCode::EINVAL`.  Ensures
+    /// that the `keys` list is compact by shifting forward any elements
+    /// after the index.
+    pub fn remove_key(&self, index: usize) -> ReturnCode {
+        let num_keys = self.num_keys.get();
+        if index < num_keys {
+            self.keys.map(|keys| {
+                for i in index..(num_keys - 1) {
+                    keys[i] = keys[i + 1];
+                }
+            });
+            self.num_keys.set(num_keys - 1);
+            self.store();
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::EINVAL
+        }
+    }
+
+    /// Gets the `KeyDescriptor` corresponding to the key at a
+    /// particular `index`, if the `index` is valid. Otherwise, returns `None`
+    pub fn get_key(&self, index: usize) -> Option<KeyDescriptor> {
+        if index < self.num_keys.get() {
+            self.keys.map(|keys| keys[index])
+        } else {
+            None
+        }
+    }
+}
+
+impl DeviceProcedure for KeyTable<'_> {
+    /// Gets the long address corresponding to the neighbor that matches the given
+    /// MAC address. If no such neighbor exists, returns `None`.
+    fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
+        self.devices.and_then(|devices| {
+            devices[..self.num_devices.get()]
+                .iter()
+                .find(|device| device.matches(addr))
+                .map(|device| device.long_addr)
+        })
+    }
+
+    fn frame_counter_valid(&self, addr: MacAddress, frame_counter: u32) -> bool {
+        self.devices.map_or(false, |devices| {
+            devices[..self.num_devices.get()]
+                .iter()
+                .find(|device| device.matches(addr))
+                .map_or(false, |device| frame_counter >= device.frame_counter)
+        })
+    }
+
+    /// Also raises the stored limit of the neighbor once its frame counter
+    /// gets close to it.
+    fn update_frame_counter(&self, addr: MacAddress, frame_counter: u32) {
+        let next = frame_counter.saturating_add(1);
+        let index = self.devices.and_then(|devices| {
+            let index = devices[..self.num_devices.get()]
+                .iter()
+                .position(|device| device.matches(addr))?;
+            devices[index].frame_counter = next;
+            Some(index)
+        });
+        let limits = match self.storage_state.get() {
+            StorageState::Idle => self.device_limits.get(),
+            StorageState::Storing => self.pending_device_limits.get(),
+            // Either the table is not kept in storage, or it is stored with
+            // new limits once it is loaded.
+            StorageState::Unused | StorageState::Loading => return,
+        };
+        if index.map_or(false, |index| near_limit(limits[index], next)) {
+            self.store();
+        }
+    }
+}
+
+impl KeyProcedure for KeyTable<'_> {
+    /// Gets the key corresponding to the key that matches the given security
+    /// level `level` and key ID `key_id`. If no such key matches, returns
+    /// `None`.
+    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
+        self.keys.and_then(|keys| {
+            keys[..self.num_keys.get()]
+                .iter()
+                .find(|key| key.level == level && key.key_id == key_id)
+                .map(|key| key.key)
+        })
+    }
+
+    fn next_frame_counter(&self) -> Option<u32> {
+        let frame_counter = self.frame_counter.get();
+        // A frame counter of 0xffffffff is rejected by receivers.
+        if frame_counter == 0xffffffff {
+            return None;
+        }
+        match self.storage_state.get() {
+            StorageState::Unused => {}
+            StorageState::Loading => {
+                return None;
+            }
+            state => {
+                let limit = self.frame_counter_limit.get();
+                if frame_counter >= limit {
+                    self.store();
+                    return None;
+                }
+                if state == StorageState::Idle && limit - frame_counter <= FRAME_COUNTER_RESERVE / 2
+                {
+                    self.store();
+                }
+            }
+        }
+        self.frame_counter.set(frame_counter + 1);
+        Some(frame_counter)
+    }
+}
+
+impl NonvolatileStorageClient<'static> for KeyTable<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        if let Some((_, limit)) = self.decode(buffer).done() {
+            // Frame counters below the stored limit may have been used.
+            if limit > self.frame_counter.get() {
+                self.frame_counter.set(limit);
+            }
+        }
+        self.storage_buf.replace(buffer);
+        self.storage_state.set(StorageState::Idle);
+        // Reserve frame counters, and store the table if there was none.
+        self.store();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.frame_counter_limit.set(self.pending_limit.get());
+        self.device_limits.set(self.pending_device_limits.get());
+        self.storage_buf.replace(buffer);
+        self.storage_state.set(StorageState::Idle);
+        if self.dirty.get() {
+            self.store();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+
+    /// Storage that refuses reads if `fail` is set and otherwise holds on to
+    /// the buffer.
+    struct MockStorage {
+        fail: bool,
+        buf: TakeCell<'static, [u8]>,
+    }
+
+    impl NonvolatileStorage<'static> for MockStorage {
+        fn set_client(&self, _client: &'static dyn NonvolatileStorageClient<'static>) {}
+
+        fn read(&self, buffer: &'static mut [u8], _address: usize, _length: usize) -> ReturnCode {
+            if self.fail {
+                return ReturnCode::FAIL;
+            }
+            self.buf.replace(buffer);
+            ReturnCode::SUCCESS
+        }
+
+        fn write(&self, buffer: &'static mut [u8], _address: usize, _length: usize) -> ReturnCode {
+            self.buf.replace(buffer);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    #[test]
+    pub fn frame_counters_are_checked() {
+        let table = KeyTable::new();
+        let neighbor = DeviceDescriptor {
+            short_addr: 0x1009,
+            long_addr: [1, 2, 3, 4, 5, 6, 7, 8],
+            frame_counter: 0,
+        };
+        assert_eq!(table.add_device(neighbor), Some(0));
+        let short = MacAddress::Short(0x1009);
+        let long = MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(table.frame_counter_valid(short, 0));
+        assert!(!table.frame_counter_valid(MacAddress::Short(0x1008), 0));
+
+        // Frames with the same or a lower counter are replays.
+        table.update_frame_counter(long, 41);
+        assert!(!table.frame_counter_valid(short, 40));
+        assert!(!table.frame_counter_valid(long, 41));
+        assert!(table.frame_counter_valid(short, 42));
+
+        // Adding the neighbor again keeps its frame counter.
+        assert_eq!(table.add_device(neighbor), Some(0));
+        assert!(!table.frame_counter_valid(short, 41));
+
+        assert_eq!(table.next_frame_counter(), Some(0));
+        assert_eq!(table.next_frame_counter(), Some(1));
+        assert_eq!(table.frame_counter(), 2);
+    }
+
+    #[test]
+    pub fn keys_are_rotated_and_stored() {
+        let table = KeyTable::new();
+        let mut key = KeyDescriptor {
+            level: SecurityLevel::EncMic32,
+            key_id: KeyId::Source4Index([0, 0, 0, 1], 2),
+            key: [0xaa; 16],
+        };
+        assert_eq!(table.add_key(key), Some(0));
+        let other = KeyDescriptor {
+            key_id: KeyId::Index(3),
+            ..key
+        };
+        assert_eq!(table.add_key(other), Some(1));
+        key.key = [0xbb; 16];
+        assert_eq!(table.add_key(key), Some(0));
+        assert_eq!(table.num_keys(), 2);
+        assert_eq!(
+            table.lookup_key(SecurityLevel::EncMic32, key.key_id),
+            Some([0xbb; 16])
+        );
+        table.add_device(DeviceDescriptor {
+            short_addr: 0x1009,
+            long_addr: [8; 8],
+            frame_counter: 7,
+        });
+
+        // Neighbor frame counters are stored ahead, like the outgoing one.
+        let mut buf = [0u8; STORAGE_LEN];
+        assert_eq!(
+            table.encode(&mut buf, 1024).done(),
+            Some((STORAGE_LEN, [1031, 1024, 1024, 1024]))
+        );
+        let restored = KeyTable::new();
+        assert_eq!(restored.decode(&buf).done(), Some((STORAGE_LEN, 1024)));
+        assert_eq!(restored.get_key(0), Some(key));
+        assert_eq!(restored.get_key(1), Some(other));
+        assert_eq!(restored.get_key(2), None);
+        assert!(!restored.frame_counter_valid(MacAddress::Short(0x1009), 1030));
+        assert!(restored.frame_counter_valid(MacAddress::Short(0x1009), 1031));
+
+        // Erased or zeroed storage holds no table.
+        assert!(restored.decode(&[0; STORAGE_LEN]).is_err());
+    }
+
+    #[test]
+    pub fn neighbor_frame_counters_are_stored_ahead() {
+        let table = KeyTable::new();
+        let storage = MockStorage {
+            fail: false,
+            buf: TakeCell::empty(),
+        };
+        let buf = Box::leak(Box::new([0u8; STORAGE_LEN]));
+        assert_eq!(table.set_storage(&storage, 0, buf), ReturnCode::SUCCESS);
+        let neighbor = MacAddress::Short(0x1009);
+        table.add_device(DeviceDescriptor {
+            short_addr: 0x1009,
+            long_addr: [8; 8],
+            frame_counter: 0,
+        });
+        // The table added while loading is stored once storage held none.
+        table.read_done(storage.buf.take().unwrap(), STORAGE_LEN);
+        let stored = |buf: &[u8]| {
+            let restored = KeyTable::new();
+            assert!(restored.decode(buf).done().is_some());
+            restored.get_device(0).unwrap().frame_counter
+        };
+        let buf = storage.buf.take().unwrap();
+        assert_eq!(stored(buf), FRAME_COUNTER_RESERVE);
+        table.write_done(buf, STORAGE_LEN);
+
+        // Frames far from the stored limit do not write the table.
+        table.update_frame_counter(neighbor, 100);
+        assert!(storage.buf.is_none());
+        // Once the frame counter gets within half the reserve of the limit, a
+        // higher limit is stored, and more frames do not store it again.
+        table.update_frame_counter(neighbor, 600);
+        let buf = storage.buf.take().unwrap();
+        assert_eq!(stored(buf), 601 + FRAME_COUNTER_RESERVE);
+        table.update_frame_counter(neighbor, 700);
+        table.write_done(buf, STORAGE_LEN);
+        table.update_frame_counter(neighbor, 800);
+        assert!(storage.buf.is_none());
+        assert!(!table.frame_counter_valid(neighbor, 800));
+        assert!(table.frame_counter_valid(neighbor, 801));
+    }
+
+    #[test]
+    pub fn failed_storage_read_leaves_storage_unused() {
+        let table = KeyTable::new();
+        let failing = MockStorage {
+            fail: true,
+            buf: TakeCell::empty(),
+        };
+        let buf = Box::leak(Box::new([0u8; STORAGE_LEN]));
+        assert_eq!(table.set_storage(&failing, 0, buf), ReturnCode::FAIL);
+
+        // Frames are still secured, without storing frame counters.
+        assert_eq!(table.next_frame_counter(), Some(0));
+        assert_eq!(table.next_frame_counter(), Some(1));
+
+        // Storage can be set again with a new buffer.
+        let storage = MockStorage {
+            fail: false,
+            buf: TakeCell::empty(),
+        };
+        let buf = Box::leak(Box::new([0u8; STORAGE_LEN]));
+        assert_eq!(table.set_storage(&storage, 0, buf), ReturnCode::SUCCESS);
+        assert!(storage.buf.is_some());
+        assert_eq!(table.next_frame_counter(), None);
+    }
+}
@@ -2,6 +2,7 @@
 
 pub mod device;
 pub mod framer;
+pub mod key_table;
 pub mod mac;
 pub mod virtual_mac;
 pub mod xmac;
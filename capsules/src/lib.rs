@@ -57,9 +57,11 @@ pub mod sdcard;
 pub mod segger_rtt;
 pub mod si7021;
 pub mod spi;
+pub mod spi_slave;
 pub mod temperature;
 pub mod tmp006;
 pub mod tsl2561;
+pub mod uart;
 pub mod usb;
 pub mod virtual_alarm;
 pub mod virtual_digest;
@@ -67,5 +69,7 @@ pub mod virtual_flash;
 pub mod virtual_hmac;
 pub mod virtual_i2c;
 pub mod virtual_pwm;
+pub mod virtual_rng;
 pub mod virtual_spi;
 pub mod virtual_uart;
+pub mod wall_clock;
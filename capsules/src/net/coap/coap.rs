@@ -0,0 +1,517 @@
+//! This file contains the structs and methods to encode and decode CoAP
+//! messages (RFC 7252, section 3).
+//!
+//! A message is a 4 byte header, a token of up to 8 bytes, a sequence of
+//! options ordered by option number and an optional payload after the 0xff
+//! payload marker. `CoapHeader` holds the header and the token.
+//! `CoapMessage::decode` checks the framing of a whole message and gives
+//! access to its options and payload without copying them, and
+//! `CoapWriter` serializes a message into a buffer, one option at a time.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u8, encode_u16, encode_u8};
+use core::cmp;
+use kernel::ReturnCode;
+
+pub const VERSION: u8 = 1;
+pub const PAYLOAD_MARKER: u8 = 0xff;
+pub const MAX_TOKEN_LEN: usize = 8;
+
+/// The well-known UDP port of CoAP.
+pub const COAP_PORT: u16 = 5683;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+impl MessageType {
+    fn from_bits(bits: u8) -> MessageType {
+        match bits & 0x03 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        }
+    }
+}
+
+/// Method and response codes, as `class << 5 | detail` (RFC 7252, section
+/// 12.1).
+pub mod coap_code {
+    pub const EMPTY: u8 = 0x00;
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+    pub const CREATED: u8 = 0x41;
+    pub const DELETED: u8 = 0x42;
+    pub const VALID: u8 = 0x43;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTENT: u8 = 0x45;
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const BAD_OPTION: u8 = 0x82;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+    pub const INTERNAL_SERVER_ERROR: u8 = 0xa0;
+}
+
+/// Option numbers (RFC 7252, section 12.2, and RFC 7641 for Observe).
+pub mod coap_option {
+    pub const IF_MATCH: u16 = 1;
+    pub const URI_HOST: u16 = 3;
+    pub const ETAG: u16 = 4;
+    pub const IF_NONE_MATCH: u16 = 5;
+    pub const OBSERVE: u16 = 6;
+    pub const URI_PORT: u16 = 7;
+    pub const LOCATION_PATH: u16 = 8;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const MAX_AGE: u16 = 14;
+    pub const URI_QUERY: u16 = 15;
+    pub const ACCEPT: u16 = 17;
+}
+
+/// Whether a recipient that does not understand option `number` must reject
+/// the message, rather than ignore the option.
+pub fn is_critical(number: u16) -> bool {
+    number & 1 == 1
+}
+
+/// The header and token of a CoAP message.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CoapHeader {
+    msg_type: MessageType,
+    code: u8,
+    message_id: u16,
+    token_len: u8,
+    token: [u8; MAX_TOKEN_LEN],
+}
+
+impl CoapHeader {
+    /// Creates a header with `token`, of which only the first
+    /// `MAX_TOKEN_LEN` bytes are kept.
+    pub fn new(msg_type: MessageType, code: u8, message_id: u16, token: &[u8]) -> CoapHeader {
+        let token_len = cmp::min(token.len(), MAX_TOKEN_LEN);
+        let mut header = CoapHeader {
+            msg_type: msg_type,
+            code: code,
+            message_id: message_id,
+            token_len: token_len as u8,
+            token: [0; MAX_TOKEN_LEN],
+        };
+        header.token[..token_len].copy_from_slice(&token[..token_len]);
+        header
+    }
+
+    pub fn get_type(&self) -> MessageType {
+        self.msg_type
+    }
+
+    pub fn get_code(&self) -> u8 {
+        self.code
+    }
+
+    pub fn get_message_id(&self) -> u16 {
+        self.message_id
+    }
+
+    pub fn get_token(&self) -> &[u8] {
+        &self.token[..self.token_len as usize]
+    }
+
+    /// Whether the message is a request: its code is a method.
+    pub fn is_request(&self) -> bool {
+        self.code != coap_code::EMPTY && self.code >> 5 == 0
+    }
+
+    /// Whether the message is a response: its code is of class 2, 4 or 5.
+    pub fn is_response(&self) -> bool {
+        match self.code >> 5 {
+            2 | 4 | 5 => true,
+            _ => false,
+        }
+    }
+
+    pub fn get_hdr_size(&self) -> usize {
+        4 + self.token_len as usize
+    }
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, self.get_hdr_size() + offset);
+
+        let first = VERSION << 6 | (self.msg_type as u8) << 4 | self.token_len;
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, first);
+        off = enc_consume!(buf, off; encode_u8, self.code);
+        off = enc_consume!(buf, off; encode_u16, self.message_id);
+        buf[off..off + self.token_len as usize].copy_from_slice(self.get_token());
+        stream_done!(off + self.token_len as usize, off + self.token_len as usize);
+    }
+
+    /// Decodes the header and token. Fails if the version is not 1 or the
+    /// token length is reserved.
+    pub fn decode(buf: &[u8]) -> SResult<CoapHeader> {
+        stream_len_cond!(buf, 4);
+        let off = 0;
+        let (off, first) = dec_try!(buf, off; decode_u8);
+        let (off, code) = dec_try!(buf, off; decode_u8);
+        let (off, message_id) = dec_try!(buf, off; decode_u16);
+        stream_cond!(first >> 6 == VERSION);
+        let token_len = first & 0x0f;
+        stream_cond!(token_len as usize <= MAX_TOKEN_LEN);
+        stream_len_cond!(buf, off + token_len as usize);
+        let header = CoapHeader::new(
+            MessageType::from_bits(first >> 4),
+            code,
+            message_id,
+            &buf[off..off + token_len as usize],
+        );
+        stream_done!(off + token_len as usize, header);
+    }
+}
+
+/// Reads the option at the start of `buf`, following option `prev`. Returns
+/// the length of the option, its number and its value, or None at the
+/// payload marker or if the option is malformed.
+fn parse_option(buf: &[u8], prev: u16) -> Option<(usize, u16, &[u8])> {
+    let first = *buf.first()?;
+    let mut off = 1;
+    let mut extended = |nibble: u8| -> Option<u32> {
+        match nibble {
+            13 => {
+                let value = *buf.get(off)? as u32 + 13;
+                off += 1;
+                Some(value)
+            }
+            14 => {
+                let bytes = buf.get(off..off + 2)?;
+                off += 2;
+                Some(u16::from_be_bytes([bytes[0], bytes[1]]) as u32 + 269)
+            }
+            15 => None,
+            nibble => Some(nibble as u32),
+        }
+    };
+    let delta = extended(first >> 4)?;
+    let len = extended(first & 0x0f)? as usize;
+    let number = prev as u32 + delta;
+    if number > u16::max_value() as u32 {
+        return None;
+    }
+    let value = buf.get(off..off + len)?;
+    Some((off + len, number as u16, value))
+}
+
+/// A decoded CoAP message, whose options and payload point into the buffer
+/// it was decoded from.
+#[derive(Copy, Clone, Debug)]
+pub struct CoapMessage<'b> {
+    pub header: CoapHeader,
+    options: &'b [u8],
+    pub payload: &'b [u8],
+}
+
+impl<'b> CoapMessage<'b> {
+    /// Decodes a whole message. Fails on a malformed option, a payload
+    /// marker followed by no payload, and an Empty message that is not only
+    /// a header.
+    pub fn decode(buf: &'b [u8]) -> SResult<CoapMessage<'b>> {
+        let (off, header) = dec_try!(buf; CoapHeader::decode);
+        let mut end = off;
+        let mut number = 0;
+        while end < buf.len() && buf[end] != PAYLOAD_MARKER {
+            let (len, option, _) = stream_from_option!(parse_option(&buf[end..], number));
+            end += len;
+            number = option;
+        }
+        let payload = if end < buf.len() {
+            stream_cond!(end + 1 < buf.len());
+            &buf[end + 1..]
+        } else {
+            &buf[end..]
+        };
+        stream_cond!(header.code != coap_code::EMPTY || buf.len() == 4);
+        stream_done!(
+            buf.len(),
+            CoapMessage {
+                header: header,
+                options: &buf[off..end],
+                payload: payload,
+            }
+        );
+    }
+
+    /// The options of the message, with their numbers, in order.
+    pub fn options(&self) -> Options<'b> {
+        Options {
+            buf: self.options,
+            number: 0,
+        }
+    }
+
+    /// The value of the first option numbered `number`.
+    pub fn get_option(&self, number: u16) -> Option<&'b [u8]> {
+        self.options()
+            .find(|&(option, _)| option == number)
+            .map(|(_, value)| value)
+    }
+
+    /// The value of the first option numbered `number`, if it is an unsigned
+    /// integer, which is at most 4 bytes long.
+    pub fn get_uint_option(&self, number: u16) -> Option<u32> {
+        let value = self.get_option(number)?;
+        if value.len() > 4 {
+            return None;
+        }
+        Some(value.iter().fold(0, |acc, &byte| acc << 8 | byte as u32))
+    }
+
+    /// The first critical option of the message whose number is not in
+    /// `known`.
+    pub fn unknown_critical_option(&self, known: &[u16]) -> Option<u16> {
+        self.options()
+            .map(|(number, _)| number)
+            .find(|number| is_critical(*number) && !known.contains(number))
+    }
+
+    /// Whether the Uri-Path options of the message spell `path`, whose
+    /// segments are separated by '/'. Empty segments of `path` are ignored,
+    /// so "/a/b" and "a/b" are the same path.
+    pub fn uri_path_matches(&self, path: &[u8]) -> bool {
+        let mut segments = path.split(|&byte| byte == b'/').filter(|s| !s.is_empty());
+        let mut options = self
+            .options()
+            .filter(|&(number, _)| number == coap_option::URI_PATH);
+        loop {
+            match (segments.next(), options.next()) {
+                (None, None) => return true,
+                (Some(segment), Some((_, value))) if segment == value => {}
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// Iterates over the options of a decoded message.
+pub struct Options<'b> {
+    buf: &'b [u8],
+    number: u16,
+}
+
+impl<'b> Iterator for Options<'b> {
+    type Item = (u16, &'b [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'b [u8])> {
+        let (len, number, value) = parse_option(self.buf, self.number)?;
+        self.buf = &self.buf[len..];
+        self.number = number;
+        Some((number, value))
+    }
+}
+
+/// Serializes a CoAP message into a buffer. Options must be added in order
+/// of their numbers, and before the payload.
+pub struct CoapWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    last_option: u16,
+    has_payload: bool,
+}
+
+impl<'b> CoapWriter<'b> {
+    /// Writes `header` at the start of `buf`. Returns ESIZE if it does not
+    /// fit.
+    pub fn new(buf: &'b mut [u8], header: &CoapHeader) -> Result<CoapWriter<'b>, ReturnCode> {
+        let len = header
+            .encode(buf, 0)
+            .done()
+            .map(|(off, _)| off)
+            .ok_or(ReturnCode::ESIZE)?;
+        Ok(CoapWriter {
+            buf: buf,
+            len: len,
+            last_option: 0,
+            has_payload: false,
+        })
+    }
+
+    /// The length of the message written so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The length of the longest payload that still fits.
+    pub fn payload_space(&self) -> usize {
+        self.buf.len().saturating_sub(self.len + 1)
+    }
+
+    /// Adds an option. Returns EINVAL if its number is lower than that of
+    /// the last option or the payload was added, and ESIZE if it does not
+    /// fit.
+    pub fn add_option(&mut self, number: u16, value: &[u8]) -> ReturnCode {
+        if number < self.last_option || self.has_payload || value.len() > 0xffff - 269 {
+            return ReturnCode::EINVAL;
+        }
+        let delta = (number - self.last_option) as usize;
+        let mut head = [0u8; 5];
+        let mut head_len = 1;
+        let mut nibble = |value: usize, head: &mut [u8; 5]| -> u8 {
+            if value < 13 {
+                value as u8
+            } else if value < 269 {
+                head[head_len] = (value - 13) as u8;
+                head_len += 1;
+                13
+            } else {
+                head[head_len..head_len + 2].copy_from_slice(&((value - 269) as u16).to_be_bytes());
+                head_len += 2;
+                14
+            }
+        };
+        let delta_nibble = nibble(delta, &mut head);
+        let len_nibble = nibble(value.len(), &mut head);
+        head[0] = delta_nibble << 4 | len_nibble;
+
+        let end = self.len + head_len + value.len();
+        if end > self.buf.len() {
+            return ReturnCode::ESIZE;
+        }
+        self.buf[self.len..self.len + head_len].copy_from_slice(&head[..head_len]);
+        self.buf[self.len + head_len..end].copy_from_slice(value);
+        self.len = end;
+        self.last_option = number;
+        ReturnCode::SUCCESS
+    }
+
+    /// Adds an option holding an unsigned integer in as few bytes as
+    /// possible.
+    pub fn add_uint_option(&mut self, number: u16, value: u32) -> ReturnCode {
+        let bytes = value.to_be_bytes();
+        let skip = (value.leading_zeros() / 8) as usize;
+        self.add_option(number, &bytes[skip..])
+    }
+
+    /// Adds a Uri-Path option for each segment of `path`, whose segments
+    /// are separated by '/'. Empty segments are skipped.
+    pub fn add_uri_path(&mut self, path: &[u8]) -> ReturnCode {
+        for segment in path.split(|&byte| byte == b'/').filter(|s| !s.is_empty()) {
+            if segment.len() > 255 {
+                return ReturnCode::EINVAL;
+            }
+            let result = self.add_option(coap_option::URI_PATH, segment);
+            if result != ReturnCode::SUCCESS {
+                return result;
+            }
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Adds the payload marker and the payload, unless it is empty. Returns
+    /// ESIZE if it does not fit.
+    pub fn add_payload(&mut self, payload: &[u8]) -> ReturnCode {
+        if self.has_payload {
+            return ReturnCode::EINVAL;
+        }
+        if payload.is_empty() {
+            return ReturnCode::SUCCESS;
+        }
+        if payload.len() > self.payload_space() {
+            return ReturnCode::ESIZE;
+        }
+        self.buf[self.len] = PAYLOAD_MARKER;
+        self.buf[self.len + 1..self.len + 1 + payload.len()].copy_from_slice(payload);
+        self.len += 1 + payload.len();
+        self.has_payload = true;
+        ReturnCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn messages_encode_and_decode() {
+        let mut buf = [0; 64];
+        let header = CoapHeader::new(
+            MessageType::Confirmable,
+            coap_code::GET,
+            0x1234,
+            &[0xde, 0xad],
+        );
+        let mut writer = CoapWriter::new(&mut buf, &header).unwrap();
+        assert_eq!(
+            writer.add_uint_option(coap_option::OBSERVE, 0),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(writer.add_uri_path(b"/sensors//temp"), ReturnCode::SUCCESS);
+        assert_eq!(
+            writer.add_option(coap_option::OBSERVE, &[]),
+            ReturnCode::EINVAL
+        );
+        // Option 300 needs a two byte delta, and a 20 byte value a one byte
+        // length.
+        assert_eq!(writer.add_option(300, &[7; 20]), ReturnCode::SUCCESS);
+        assert_eq!(writer.add_payload(b"hi"), ReturnCode::SUCCESS);
+        assert_eq!(writer.add_uri_path(b"late"), ReturnCode::EINVAL);
+        let len = writer.len();
+        assert_eq!(
+            buf[..13],
+            [0x42, 0x01, 0x12, 0x34, 0xde, 0xad, 0x60, 0x57, b's', b'e', b'n', b's', b'o']
+        );
+        assert_eq!(buf[15..17], [0x04, b't']);
+        // Deltas over 268 are extended by two bytes, lengths over 12 by one.
+        assert_eq!(buf[20..24], [0xed, 0x00, 20, 7]);
+        assert_eq!(buf[44..47], [PAYLOAD_MARKER, b'h', b'i']);
+        assert_eq!(len, 47);
+
+        let message = CoapMessage::decode(&buf[..len]).done().unwrap().1;
+        assert_eq!(message.header, header);
+        assert_eq!(message.header.get_token(), [0xde, 0xad]);
+        assert!(message.header.is_request());
+        assert_eq!(message.get_uint_option(coap_option::OBSERVE), Some(0));
+        assert!(message.uri_path_matches(b"sensors/temp"));
+        assert!(!message.uri_path_matches(b"sensors"));
+        assert!(!message.uri_path_matches(b"sensors/temp/x"));
+        assert_eq!(message.get_option(300), Some(&[7; 20][..]));
+        assert_eq!(
+            message.unknown_critical_option(&[coap_option::URI_PATH]),
+            None
+        );
+        assert_eq!(message.payload, b"hi");
+        assert_eq!(message.options().count(), 4);
+    }
+
+    #[test]
+    pub fn malformed_messages_are_rejected() {
+        let decode = |buf: &[u8]| CoapMessage::decode(buf).done().is_some();
+        // An Empty message is only a header.
+        assert!(decode(&[0x40, 0x00, 0x00, 0x01]));
+        assert!(!decode(&[0x41, 0x00, 0x00, 0x01, 0xaa]));
+        // Version 2, and a reserved token length.
+        assert!(!decode(&[0x80, 0x01, 0x00, 0x01]));
+        assert!(!decode(&[
+            0x49, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0
+        ]));
+        // A truncated token, a truncated option and a reserved delta.
+        assert!(!decode(&[0x42, 0x01, 0x00, 0x01, 0xaa]));
+        assert!(!decode(&[0x40, 0x01, 0x00, 0x01, 0xb3, b'a']));
+        assert!(!decode(&[0x40, 0x01, 0x00, 0x01, 0xf1, 0x00]));
+        // A payload marker without a payload.
+        assert!(!decode(&[0x40, 0x01, 0x00, 0x01, 0xff]));
+        assert!(decode(&[0x40, 0x01, 0x00, 0x01, 0xff, 0x00]));
+
+        let message = CoapMessage::decode(&[0x50, 0x45, 0x00, 0x01, 0xd1, 0x02, 0x01])
+            .done()
+            .unwrap()
+            .1;
+        assert_eq!(message.header.get_type(), MessageType::NonConfirmable);
+        assert!(message.header.is_response());
+        assert_eq!(message.unknown_critical_option(&[]), Some(15));
+    }
+}
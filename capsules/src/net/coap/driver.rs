@@ -0,0 +1,1587 @@
+//! CoAP userspace interface.
+//!
+//! Implements the messaging layer of CoAP (RFC 7252) over a UDP port bound
+//! by the kernel, and lets processes act both as clients and as servers.
+//!
+//! As a client, a process shares the endpoint to send to, the path of the
+//! resource and, for PUT and POST, the payload, and asks the driver to send a
+//! GET, PUT, POST or DELETE request, confirmable or not. It is called back
+//! once the response arrives, with the response code and the payload copied
+//! into its response buffer, or with an error if the server did not answer.
+//! A GET request can also ask to observe the resource (RFC 7641), in which
+//! case every notification the server sends afterwards is delivered the same
+//! way, until the process cancels the request. Each process can have one
+//! request outstanding at a time.
+//!
+//! As a server, a process registers a resource under a path, with a buffer
+//! holding its representation. The driver answers requests for the resource
+//! itself, without waking the process: GET requests get the representation,
+//! PUT requests replace it and the process is told. Processes that update
+//! the representation of their resource tell the driver, which notifies the
+//! clients observing it. Other methods are not allowed. Each process can
+//! register one resource.
+//!
+//! Confirmable requests are retransmitted with exponential backoff until
+//! they are acknowledged, and received messages are deduplicated by message
+//! ID so that a retransmitted request is answered again, with the code of
+//! the original response, without being applied twice. Responses and Empty
+//! messages wait in a small queue while another message is being sent, and
+//! go out before requests and notifications. Retransmissions are rebuilt
+//! from the buffers of the process, which it should not change while its
+//! request is outstanding. Notifications are
+//! sent as non-confirmable messages, and a client that answers one with a
+//! reset is no longer notified. Requests are not split into blocks (RFC
+//! 7959), so messages must fit in the transmit buffer of the driver.
+
+use crate::net::coap::coap::{
+    coap_code, coap_option, CoapHeader, CoapMessage, CoapWriter, MessageType, COAP_PORT,
+    MAX_TOKEN_LEN,
+};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_port_table::UdpPortManager;
+use crate::net::udp::udp_recv::{UDPReceiver, UDPRecvClient};
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use crate::net::util::host_slice_to_u16;
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::MapCell;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::{self, Frequency};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+/// The shortest time to wait for the acknowledgement of a confirmable
+/// message, in milliseconds. The first timeout is chosen at random between
+/// this and 1.5 times this.
+pub const ACK_TIMEOUT_MS: u32 = 2000;
+/// How many times a confirmable request is retransmitted.
+pub const MAX_RETRANSMIT: u8 = 4;
+/// Seconds to wait for a response once a request was acknowledged or, for
+/// a non-confirmable request, sent (MAX_TRANSMIT_WAIT).
+pub const RESPONSE_TIMEOUT: u32 = 93;
+/// Seconds for which the message ID of a received message is remembered to
+/// detect duplicates (EXCHANGE_LIFETIME).
+pub const EXCHANGE_LIFETIME: u32 = 247;
+/// The longest path of a registered resource.
+pub const MAX_PATH_LEN: usize = 32;
+/// The number of clients that can observe resources.
+pub const MAX_OBSERVERS: usize = 4;
+
+/// How many received messages are remembered to detect duplicates.
+const RECENT_MESSAGES: usize = 8;
+/// How many responses and Empty messages can wait for the transmit buffer.
+const PENDING_RESPONSES: usize = 4;
+const TOKEN_LEN: usize = 4;
+/// An IPv6 address followed by a port.
+const ENDPOINT_LEN: usize = mem::size_of::<IPAddr>() + mem::size_of::<u16>();
+/// The critical options the server handles, or safely ignores.
+const KNOWN_CRITICAL: [u16; 3] = [
+    coap_option::URI_HOST,
+    coap_option::URI_PORT,
+    coap_option::URI_PATH,
+];
+
+#[derive(Copy, Clone, PartialEq)]
+enum RequestState {
+    /// Waiting for the transmit buffer to be sent or retransmitted.
+    Queued,
+    AwaitingAck,
+    AwaitingResponse,
+    /// The server accepted to notify the process of changes.
+    Observing,
+}
+
+#[derive(Copy, Clone)]
+struct Request {
+    dst: IPAddr,
+    port: u16,
+    method: u8,
+    confirmable: bool,
+    observe: bool,
+    token: [u8; TOKEN_LEN],
+    message_id: u16,
+    state: RequestState,
+    transmissions: u8,
+    /// The retransmission timeout in tics, doubled after every timeout.
+    timeout: u32,
+    deadline: u32,
+}
+
+#[derive(Copy, Clone)]
+struct Resource {
+    path: [u8; MAX_PATH_LEN],
+    path_len: usize,
+    /// The length of the representation, at the start of the representation
+    /// buffer.
+    len: usize,
+    /// The sequence number of notifications, incremented on every change.
+    observe_seq: u32,
+}
+
+#[derive(Copy, Clone)]
+struct Observer {
+    appid: AppId,
+    addr: IPAddr,
+    port: u16,
+    token: [u8; MAX_TOKEN_LEN],
+    token_len: usize,
+    /// The message ID of the last notification, which a reset refers to.
+    message_id: u16,
+    /// Whether a notification is waiting for the transmit buffer.
+    pending: bool,
+}
+
+#[derive(Copy, Clone)]
+struct Received {
+    addr: IPAddr,
+    port: u16,
+    message_id: u16,
+    /// The code of the response to the message, which duplicates of
+    /// requests other than GET are answered with.
+    code: u8,
+    /// Forgotten once due, so that it is never compared across wraparound.
+    expires: u32,
+}
+
+/// A response or an Empty message, sent once the transmit buffer is free.
+#[derive(Copy, Clone)]
+struct Response {
+    addr: IPAddr,
+    port: u16,
+    msg_type: MessageType,
+    code: u8,
+    message_id: u16,
+    token: [u8; MAX_TOKEN_LEN],
+    token_len: usize,
+    /// The process whose representation is the payload, read when the
+    /// response is sent.
+    content: Option<AppId>,
+    /// Whether to add the Observe option, with the sequence number of the
+    /// representation.
+    observe: bool,
+}
+
+impl Response {
+    fn new(addr: IPAddr, port: u16, msg_type: MessageType, code: u8, message_id: u16) -> Response {
+        Response {
+            addr: addr,
+            port: port,
+            msg_type: msg_type,
+            code: code,
+            message_id: message_id,
+            token: [0; MAX_TOKEN_LEN],
+            token_len: 0,
+            content: None,
+            observe: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    client_callback: Option<Callback>,
+    server_callback: Option<Callback>,
+    dst: Option<AppSlice<Shared, u8>>,
+    uri_path: Option<AppSlice<Shared, u8>>,
+    request_payload: Option<AppSlice<Shared, u8>>,
+    response: Option<AppSlice<Shared, u8>>,
+    resource_path: Option<AppSlice<Shared, u8>>,
+    representation: Option<AppSlice<Shared, u8>>,
+    request: Option<Request>,
+    resource: Option<Resource>,
+}
+
+pub struct CoapDriver<'a, A: time::Alarm<'a>> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    udp_receiver: &'a UDPReceiver<'a>,
+    port_table: &'static UdpPortManager,
+    alarm: &'a A,
+    apps: Grant<App>,
+    /// Held while a message is being sent.
+    tx_buf: MapCell<LeasableBuffer<'static, u8>>,
+    observers: [Cell<Option<Observer>>; MAX_OBSERVERS],
+    recent: [Cell<Option<Received>>; RECENT_MESSAGES],
+    /// Responses waiting for the transmit buffer, oldest first.
+    responses: [Cell<Option<Response>>; PENDING_RESPONSES],
+    message_id: Cell<u16>,
+    rng: Cell<u32>,
+    net_cap: &'static NetworkCapability,
+}
+
+/// Turns the result of a `CoapWriter` call into a `Result`, to chain them.
+fn check(result: ReturnCode) -> Result<(), ReturnCode> {
+    if result == ReturnCode::SUCCESS {
+        Ok(())
+    } else {
+        Err(result)
+    }
+}
+
+fn is_due(deadline: u32, now: u32) -> bool {
+    deadline.wrapping_sub(now) as i32 <= 0
+}
+
+/// Whether two resource paths have the same segments.
+fn same_path(a: &[u8], b: &[u8]) -> bool {
+    let a = a
+        .split(|&byte| byte == b'/')
+        .filter(|segment| !segment.is_empty());
+    let b = b
+        .split(|&byte| byte == b'/')
+        .filter(|segment| !segment.is_empty());
+    a.eq(b)
+}
+
+impl<'a, A: time::Alarm<'a>> CoapDriver<'a, A> {
+    /// `tx_buf` bounds the size of the messages the driver sends, and so of
+    /// requests, responses and representations.
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        udp_receiver: &'a UDPReceiver<'a>,
+        port_table: &'static UdpPortManager,
+        alarm: &'a A,
+        grant: Grant<App>,
+        tx_buf: LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> CoapDriver<'a, A> {
+        CoapDriver {
+            udp_sender: udp_sender,
+            udp_receiver: udp_receiver,
+            port_table: port_table,
+            alarm: alarm,
+            apps: grant,
+            tx_buf: MapCell::new(tx_buf),
+            observers: Default::default(),
+            recent: Default::default(),
+            responses: Default::default(),
+            message_id: Cell::new(0),
+            rng: Cell::new(1),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Binds the UDP sender and receiver of the driver to `port`, usually
+    /// `COAP_PORT`, from which requests are sent and on which they are
+    /// served. Returns EALREADY if the driver is already bound and EBUSY if
+    /// the port is taken.
+    pub fn bind(&self, port: u16) -> ReturnCode {
+        if self.udp_sender.is_bound() {
+            return ReturnCode::EALREADY;
+        }
+        let socket = match self.port_table.create_socket() {
+            Ok(socket) => socket,
+            Err(err) => return err,
+        };
+        match self.port_table.bind(socket, port, self.net_cap) {
+            Ok((send_binding, recv_binding)) => {
+                self.udp_sender.set_binding(send_binding);
+                self.udp_receiver.set_binding(recv_binding);
+                // Message IDs start at random, so that they do not repeat
+                // those used before a reboot.
+                self.message_id.set(self.random() as u16);
+                ReturnCode::SUCCESS
+            }
+            Err(_socket) => ReturnCode::EBUSY,
+        }
+    }
+
+    /// Converts seconds to tics, clamped so that deadlines compare correctly
+    /// across wraparound.
+    fn tics(secs: u32) -> u32 {
+        let tics = secs as u64 * <A::Frequency>::frequency() as u64;
+        cmp::min(tics, i32::max_value() as u64) as u32
+    }
+
+    fn tics_ms(ms: u32) -> u32 {
+        let tics = ms as u64 * <A::Frequency>::frequency() as u64 / 1000;
+        cmp::min(tics, i32::max_value() as u64) as u32
+    }
+
+    /// A xorshift generator mixed with the time, for tokens and timeouts.
+    fn random(&self) -> u32 {
+        let mut x = self.rng.get() ^ self.alarm.now();
+        if x == 0 {
+            x = 1;
+        }
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng.set(x);
+        x
+    }
+
+    fn next_message_id(&self) -> u16 {
+        let message_id = self.message_id.get().wrapping_add(1);
+        self.message_id.set(message_id);
+        message_id
+    }
+
+    /// Serializes a message into the transmit buffer with `fill`, which
+    /// returns its length, and sends it. Returns EBUSY if another message is
+    /// being sent.
+    fn send_message<F>(&self, addr: IPAddr, port: u16, fill: F) -> ReturnCode
+    where
+        F: FnOnce(&mut [u8]) -> Result<usize, ReturnCode>,
+    {
+        let mut buf = match self.tx_buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        let len = match fill(&mut buf[..]) {
+            Ok(len) => len,
+            Err(err) => {
+                self.tx_buf.replace(buf);
+                return err;
+            }
+        };
+        buf.slice(0..len);
+        match self.udp_sender.send_to(addr, port, buf, self.net_cap) {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err(mut buf) => {
+                buf.reset();
+                self.tx_buf.replace(buf);
+                ReturnCode::FAIL
+            }
+        }
+    }
+
+    /// Sends an Empty message: an acknowledgement or a reset.
+    fn send_empty(&self, addr: IPAddr, port: u16, msg_type: MessageType, message_id: u16) {
+        self.send_response(Response::new(
+            addr,
+            port,
+            msg_type,
+            coap_code::EMPTY,
+            message_id,
+        ));
+    }
+
+    /// Sends a response, or queues it behind those already waiting for the
+    /// transmit buffer. A response that does not fit in the queue is
+    /// dropped, and the peer retransmits confirmable requests.
+    fn send_response(&self, response: Response) {
+        if self.tx_buf.is_some() && self.responses[0].get().is_none() {
+            return self.transmit_response(response);
+        }
+        if let Some(slot) = self.responses.iter().find(|slot| slot.get().is_none()) {
+            slot.set(Some(response));
+        }
+    }
+
+    /// Takes the oldest response waiting for the transmit buffer.
+    fn next_response(&self) -> Option<Response> {
+        let response = self.responses[0].get();
+        for i in 1..PENDING_RESPONSES {
+            self.responses[i - 1].set(self.responses[i].get());
+        }
+        self.responses[PENDING_RESPONSES - 1].set(None);
+        response
+    }
+
+    /// Serializes and sends a response. A representation that does not fit
+    /// in the transmit buffer is answered with 5.00 Internal Server Error,
+    /// and one that is no longer registered with 4.04 Not Found.
+    fn transmit_response(&self, response: Response) {
+        let header = CoapHeader::new(
+            response.msg_type,
+            response.code,
+            response.message_id,
+            &response.token[..response.token_len],
+        );
+        let send = |observe: Option<u32>, payload: &[u8]| {
+            self.send_message(response.addr, response.port, |buf| {
+                let mut writer = CoapWriter::new(buf, &header)?;
+                if let Some(seq) = observe {
+                    check(writer.add_uint_option(coap_option::OBSERVE, seq & 0xff_ffff))?;
+                }
+                check(writer.add_payload(payload))?;
+                Ok(writer.len())
+            })
+        };
+        let appid = match response.content {
+            Some(appid) => appid,
+            None => {
+                send(None, &[]);
+                return;
+            }
+        };
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                app.resource.map(|resource| {
+                    let representation = app.representation.as_ref().map_or(&[][..], |buf| {
+                        &buf.as_ref()[..cmp::min(resource.len, buf.len())]
+                    });
+                    let observe = if response.observe {
+                        Some(resource.observe_seq)
+                    } else {
+                        None
+                    };
+                    send(observe, representation)
+                })
+            })
+            .unwrap_or(None);
+        let code = match result {
+            Some(ReturnCode::ESIZE) => coap_code::INTERNAL_SERVER_ERROR,
+            None => coap_code::NOT_FOUND,
+            Some(_) => return,
+        };
+        self.transmit_response(Response {
+            code: code,
+            content: None,
+            observe: false,
+            ..response
+        })
+    }
+
+    /// Sends the queued request of `appid`, and waits for its
+    /// acknowledgement or response.
+    fn transmit_request(&self, appid: AppId) -> ReturnCode {
+        let now = self.alarm.now();
+        self.apps
+            .enter(appid, |app, _| {
+                let mut request = match app.request {
+                    Some(request) if request.state == RequestState::Queued => request,
+                    _ => return ReturnCode::SUCCESS,
+                };
+                let msg_type = if request.confirmable {
+                    MessageType::Confirmable
+                } else {
+                    MessageType::NonConfirmable
+                };
+                let header =
+                    CoapHeader::new(msg_type, request.method, request.message_id, &request.token);
+                let path = app.uri_path.as_ref().map_or(&[][..], |path| path.as_ref());
+                let payload = match request.method {
+                    coap_code::PUT | coap_code::POST => app
+                        .request_payload
+                        .as_ref()
+                        .map_or(&[][..], |payload| payload.as_ref()),
+                    _ => &[],
+                };
+                let result = self.send_message(request.dst, request.port, |buf| {
+                    let mut writer = CoapWriter::new(buf, &header)?;
+                    if request.observe {
+                        check(writer.add_uint_option(coap_option::OBSERVE, 0))?;
+                    }
+                    check(writer.add_uri_path(path))?;
+                    check(writer.add_payload(payload))?;
+                    Ok(writer.len())
+                });
+                if result == ReturnCode::SUCCESS {
+                    request.transmissions += 1;
+                    if request.confirmable {
+                        request.state = RequestState::AwaitingAck;
+                        request.deadline = now.wrapping_add(request.timeout);
+                    } else {
+                        request.state = RequestState::AwaitingResponse;
+                        request.deadline = now.wrapping_add(Self::tics(RESPONSE_TIMEOUT));
+                    }
+                    app.request = Some(request);
+                }
+                result
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Ends the request of `appid` and calls the process back.
+    fn complete_request(&self, appid: AppId, result: ReturnCode) {
+        let _ = self.apps.enter(appid, |app, _| {
+            if app.request.take().is_some() {
+                app.client_callback
+                    .map(|mut cb| cb.schedule(result.into(), 0, 0));
+            }
+        });
+    }
+
+    /// Sends the notification waiting for observer `index`. Observers of
+    /// processes that no longer have a resource are removed.
+    fn send_notification(&self, index: usize) {
+        let mut observer = match self.observers[index].get() {
+            Some(observer) => observer,
+            None => return,
+        };
+        observer.pending = false;
+        observer.message_id = self.next_message_id();
+        let result =
+            self.apps
+                .enter(observer.appid, |app, _| {
+                    let resource = match app.resource {
+                        Some(resource) => resource,
+                        None => return ReturnCode::FAIL,
+                    };
+                    let header = CoapHeader::new(
+                        MessageType::NonConfirmable,
+                        coap_code::CONTENT,
+                        observer.message_id,
+                        &observer.token[..observer.token_len],
+                    );
+                    let representation = app.representation.as_ref().map_or(&[][..], |buf| {
+                        &buf.as_ref()[..cmp::min(resource.len, buf.len())]
+                    });
+                    self.send_message(observer.addr, observer.port, |buf| {
+                        let mut writer = CoapWriter::new(buf, &header)?;
+                        check(writer.add_uint_option(
+                            coap_option::OBSERVE,
+                            resource.observe_seq & 0xff_ffff,
+                        ))?;
+                        check(writer.add_payload(representation))?;
+                        Ok(writer.len())
+                    })
+                })
+                .unwrap_or(ReturnCode::FAIL);
+        if result == ReturnCode::FAIL {
+            self.observers[index].set(None);
+        } else {
+            self.observers[index].set(Some(observer));
+        }
+    }
+
+    /// Sends queued responses, then queued requests, then pending
+    /// notifications, while the transmit buffer is free.
+    fn send_queued(&self) {
+        while self.tx_buf.is_some() {
+            if let Some(response) = self.next_response() {
+                self.transmit_response(response);
+                continue;
+            }
+            let queued = self.apps.iter().find_map(|app| {
+                app.enter(|app, _| match app.request {
+                    Some(request) if request.state == RequestState::Queued => Some(app.appid()),
+                    _ => None,
+                })
+            });
+            if let Some(appid) = queued {
+                let result = self.transmit_request(appid);
+                if result != ReturnCode::SUCCESS {
+                    self.complete_request(appid, result);
+                }
+                continue;
+            }
+            match self
+                .observers
+                .iter()
+                .position(|slot| slot.get().map_or(false, |observer| observer.pending))
+            {
+                Some(index) => self.send_notification(index),
+                None => break,
+            }
+        }
+    }
+
+    /// Sets the alarm for the earliest retransmission or response timeout,
+    /// or for when the earliest received message is forgotten.
+    fn reschedule(&self) {
+        let now = self.alarm.now();
+        let remaining = self
+            .apps
+            .iter()
+            .filter_map(|app| {
+                app.enter(|app, _| match app.request {
+                    Some(request)
+                        if request.state == RequestState::AwaitingAck
+                            || request.state == RequestState::AwaitingResponse =>
+                    {
+                        Some(request.deadline)
+                    }
+                    _ => None,
+                })
+            })
+            .chain(
+                self.recent
+                    .iter()
+                    .filter_map(|slot| slot.get().map(|received| received.expires)),
+            )
+            .map(|deadline| {
+                let remaining = deadline.wrapping_sub(now);
+                if remaining as i32 > 0 {
+                    remaining
+                } else {
+                    1
+                }
+            })
+            .min();
+        match remaining {
+            Some(remaining) => self.alarm.set_alarm(now.wrapping_add(remaining)),
+            None => self.alarm.disable(),
+        }
+    }
+
+    /// Forgets the received messages whose exchange lifetime is over.
+    fn forget_expired(&self) {
+        let now = self.alarm.now();
+        for slot in self.recent.iter() {
+            if slot
+                .get()
+                .map_or(false, |received| is_due(received.expires, now))
+            {
+                slot.set(None);
+            }
+        }
+    }
+
+    /// Returns the earlier message from `addr`:`port` with `message_id`, if
+    /// this one is a duplicate of it.
+    fn find_received(&self, addr: IPAddr, port: u16, message_id: u16) -> Option<Received> {
+        self.forget_expired();
+        self.recent.iter().find_map(|slot| {
+            slot.get().filter(|received| {
+                received.addr == addr && received.port == port && received.message_id == message_id
+            })
+        })
+    }
+
+    /// Remembers the message ID of a received confirmable or
+    /// non-confirmable message, and the code of the response to it.
+    fn remember_received(&self, addr: IPAddr, port: u16, message_id: u16, code: u8) {
+        let now = self.alarm.now();
+        // Replace a forgotten message, or else the one that expires first.
+        let slot = self.recent.iter().min_by_key(|slot| {
+            slot.get()
+                .map_or(0, |received| received.expires.wrapping_sub(now))
+        });
+        slot.map(|slot| {
+            slot.set(Some(Received {
+                addr: addr,
+                port: port,
+                message_id: message_id,
+                code: code,
+                expires: now.wrapping_add(Self::tics(EXCHANGE_LIFETIME)),
+            }))
+        });
+    }
+
+    /// Starts notifying the client at `addr`:`port` of changes of the
+    /// resource of `appid`, replacing its previous registration. Returns
+    /// whether there was room for it.
+    fn add_observer(&self, appid: AppId, addr: IPAddr, port: u16, token: &[u8]) -> bool {
+        let slot = self
+            .observers
+            .iter()
+            .find(|slot| {
+                slot.get().map_or(false, |observer| {
+                    observer.appid == appid && observer.addr == addr && observer.port == port
+                })
+            })
+            .or_else(|| self.observers.iter().find(|slot| slot.get().is_none()));
+        let slot = match slot {
+            Some(slot) => slot,
+            None => return false,
+        };
+        let mut observer = Observer {
+            appid: appid,
+            addr: addr,
+            port: port,
+            token: [0; MAX_TOKEN_LEN],
+            token_len: token.len(),
+            message_id: 0,
+            pending: false,
+        };
+        observer.token[..token.len()].copy_from_slice(token);
+        slot.set(Some(observer));
+        true
+    }
+
+    fn remove_observers<F: Fn(&Observer) -> bool>(&self, matches: F) {
+        for slot in self.observers.iter() {
+            if slot.get().map_or(false, |observer| matches(&observer)) {
+                slot.set(None);
+            }
+        }
+    }
+
+    /// Queues a notification to every client observing the resource of
+    /// `appid`.
+    fn notify_observers(&self, appid: AppId) {
+        for slot in self.observers.iter() {
+            slot.set(slot.get().map(|mut observer| {
+                if observer.appid == appid {
+                    observer.pending = true;
+                }
+                observer
+            }));
+        }
+    }
+
+    /// Answers a request for a registered resource, and returns the code of
+    /// the response. Duplicates of requests other than GET are answered with
+    /// `answered`, the code of the response to the original, without being
+    /// applied again.
+    fn handle_request(
+        &self,
+        src: IPAddr,
+        port: u16,
+        request: &CoapMessage,
+        answered: Option<u8>,
+    ) -> u8 {
+        let header = request.header;
+        let (msg_type, message_id) = if header.get_type() == MessageType::Confirmable {
+            (MessageType::Acknowledgement, header.get_message_id())
+        } else {
+            (MessageType::NonConfirmable, self.next_message_id())
+        };
+        let token = header.get_token();
+        let respond = |code: u8, content: Option<AppId>, observe: bool| {
+            let mut response = Response::new(src, port, msg_type, code, message_id);
+            response.token[..token.len()].copy_from_slice(token);
+            response.token_len = token.len();
+            response.content = content;
+            response.observe = observe;
+            self.send_response(response);
+            code
+        };
+
+        match answered {
+            Some(code) if header.get_code() != coap_code::GET => {
+                return respond(code, None, false);
+            }
+            _ => {}
+        }
+        if request.unknown_critical_option(&KNOWN_CRITICAL).is_some() {
+            return respond(coap_code::BAD_OPTION, None, false);
+        }
+        let owner = self.apps.iter().find_map(|app| {
+            app.enter(|app, _| match app.resource {
+                Some(resource) if request.uri_path_matches(&resource.path[..resource.path_len]) => {
+                    Some(app.appid())
+                }
+                _ => None,
+            })
+        });
+        let appid = match owner {
+            Some(appid) => appid,
+            None => return respond(coap_code::NOT_FOUND, None, false),
+        };
+
+        match header.get_code() {
+            coap_code::GET => {
+                let observing = match request.get_uint_option(coap_option::OBSERVE) {
+                    Some(0) => self.add_observer(appid, src, port, token),
+                    Some(1) => {
+                        self.remove_observers(|observer| {
+                            observer.appid == appid && observer.addr == src && observer.port == port
+                        });
+                        false
+                    }
+                    _ => false,
+                };
+                respond(coap_code::CONTENT, Some(appid), observing)
+            }
+            coap_code::PUT => {
+                let stored = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        let app: &mut App = app;
+                        let (resource, buf) =
+                            match (app.resource.as_mut(), app.representation.as_mut()) {
+                                (Some(resource), Some(buf)) => (resource, buf),
+                                _ => return false,
+                            };
+                        let len = request.payload.len();
+                        if len > buf.len() {
+                            return false;
+                        }
+                        buf.as_mut()[..len].copy_from_slice(request.payload);
+                        resource.len = len;
+                        resource.observe_seq = resource.observe_seq.wrapping_add(1);
+                        app.server_callback
+                            .map(|mut cb| cb.schedule(coap_code::PUT as usize, len, 0));
+                        true
+                    })
+                    .unwrap_or(false);
+                if stored {
+                    self.notify_observers(appid);
+                    respond(coap_code::CHANGED, None, false)
+                } else {
+                    respond(coap_code::REQUEST_ENTITY_TOO_LARGE, None, false)
+                }
+            }
+            _ => respond(coap_code::METHOD_NOT_ALLOWED, None, false),
+        }
+    }
+
+    /// Finds the process whose request `matches` accepts.
+    fn find_request<F: Fn(&Request) -> bool>(&self, matches: F) -> Option<AppId> {
+        self.apps.iter().find_map(|app| {
+            app.enter(|app, _| match app.request {
+                Some(request) if request.transmissions > 0 && matches(&request) => {
+                    Some(app.appid())
+                }
+                _ => None,
+            })
+        })
+    }
+
+    /// Delivers a response to the request of `appid`. The request stays
+    /// outstanding while the server keeps notifying the process.
+    fn deliver_response(&self, appid: AppId, response: &CoapMessage) {
+        let _ = self.apps.enter(appid, |app, _| {
+            let mut request = match app.request {
+                Some(request) => request,
+                None => return,
+            };
+            let payload = response.payload;
+            let len = app.response.as_mut().map_or(0, |buf| {
+                let len = cmp::min(buf.len(), payload.len());
+                buf.as_mut()[..len].copy_from_slice(&payload[..len]);
+                len
+            });
+            let code = response.header.get_code();
+            if request.observe
+                && code >> 5 == 2
+                && response.get_option(coap_option::OBSERVE).is_some()
+            {
+                request.state = RequestState::Observing;
+                app.request = Some(request);
+            } else {
+                app.request = None;
+            }
+            app.client_callback
+                .map(|mut cb| cb.schedule(ReturnCode::SUCCESS.into(), code as usize, len));
+        });
+    }
+
+    fn handle_ack(&self, src: IPAddr, port: u16, ack: &CoapMessage) {
+        let message_id = ack.header.get_message_id();
+        let appid = match self.find_request(|request| {
+            request.confirmable
+                && request.message_id == message_id
+                && request.dst == src
+                && request.port == port
+                && (request.state == RequestState::AwaitingAck
+                    || request.state == RequestState::Queued)
+        }) {
+            Some(appid) => appid,
+            None => return,
+        };
+        if ack.header.get_code() == coap_code::EMPTY {
+            // The response will follow in a separate message.
+            let deadline = self.alarm.now().wrapping_add(Self::tics(RESPONSE_TIMEOUT));
+            let _ = self.apps.enter(appid, |app, _| {
+                app.request = app.request.map(|mut request| {
+                    request.state = RequestState::AwaitingResponse;
+                    request.deadline = deadline;
+                    request
+                });
+            });
+        } else if ack.header.is_response() {
+            self.deliver_response(appid, ack);
+        }
+    }
+
+    fn handle_reset(&self, src: IPAddr, port: u16, message_id: u16) {
+        let rejected = self.find_request(|request| {
+            request.confirmable
+                && request.message_id == message_id
+                && request.dst == src
+                && request.port == port
+                && request.state != RequestState::Observing
+        });
+        if let Some(appid) = rejected {
+            self.complete_request(appid, ReturnCode::FAIL);
+        }
+        self.remove_observers(|observer| {
+            observer.addr == src && observer.port == port && observer.message_id == message_id
+        });
+    }
+
+    /// Handles a response in a confirmable or non-confirmable message.
+    /// Returns whether a request was waiting for it.
+    fn handle_response(&self, src: IPAddr, port: u16, response: &CoapMessage) -> bool {
+        let token = response.header.get_token();
+        match self.find_request(|request| {
+            request.dst == src && request.port == port && request.token[..] == *token
+        }) {
+            Some(appid) => {
+                self.deliver_response(appid, response);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn request(&self, appid: AppId, method: usize, flags: usize) -> ReturnCode {
+        let method = method as u8;
+        let confirmable = flags & 1 != 0;
+        let observe = flags & 2 != 0;
+        if method < coap_code::GET || method > coap_code::DELETE {
+            return ReturnCode::EINVAL;
+        }
+        if observe && method != coap_code::GET {
+            return ReturnCode::EINVAL;
+        }
+        let token = self.random().to_be_bytes();
+        let message_id = self.next_message_id();
+        let timeout = Self::tics_ms(ACK_TIMEOUT_MS + self.random() % (ACK_TIMEOUT_MS / 2));
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.request.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                let (dst, port) = match app.dst.as_ref().filter(|dst| dst.len() == ENDPOINT_LEN) {
+                    Some(endpoint) => {
+                        let endpoint = endpoint.as_ref();
+                        let mut addr = IPAddr::new();
+                        addr.0
+                            .copy_from_slice(&endpoint[..mem::size_of::<IPAddr>()]);
+                        (
+                            addr,
+                            host_slice_to_u16(&endpoint[mem::size_of::<IPAddr>()..]),
+                        )
+                    }
+                    None => return ReturnCode::EINVAL,
+                };
+                app.request = Some(Request {
+                    dst: dst,
+                    port: if port == 0 { COAP_PORT } else { port },
+                    method: method,
+                    confirmable: confirmable,
+                    observe: observe,
+                    token: token,
+                    message_id: message_id,
+                    state: RequestState::Queued,
+                    transmissions: 0,
+                    timeout: timeout,
+                    deadline: 0,
+                });
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into());
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        // Otherwise, the request is sent once the transmit buffer is free.
+        if self.tx_buf.is_some() {
+            let result = self.transmit_request(appid);
+            if result != ReturnCode::SUCCESS {
+                let _ = self.apps.enter(appid, |app, _| app.request = None);
+                return result;
+            }
+            self.reschedule();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn register(&self, appid: AppId, len: usize) -> ReturnCode {
+        let resource = self
+            .apps
+            .enter(appid, |app, _| {
+                let path = match app.resource_path.as_ref() {
+                    Some(path) if path.len() > 0 && path.len() <= MAX_PATH_LEN => path,
+                    _ => return Err(ReturnCode::EINVAL),
+                };
+                if len > app.representation.as_ref().map_or(0, |buf| buf.len()) {
+                    return Err(ReturnCode::ESIZE);
+                }
+                let mut resource = Resource {
+                    path: [0; MAX_PATH_LEN],
+                    path_len: path.len(),
+                    len: len,
+                    observe_seq: 0,
+                };
+                resource.path[..path.len()].copy_from_slice(path.as_ref());
+                Ok(resource)
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        let resource = match resource {
+            Ok(resource) => resource,
+            Err(err) => return err,
+        };
+        let path = &resource.path[..resource.path_len];
+        let taken = self.apps.iter().any(|app| {
+            app.enter(|app, _| {
+                app.appid() != appid
+                    && app.resource.map_or(false, |other| {
+                        same_path(&other.path[..other.path_len], path)
+                    })
+            })
+        });
+        if taken {
+            return ReturnCode::EBUSY;
+        }
+        // Clients observing the previous resource of the process are not
+        // notified of the new one.
+        self.remove_observers(|observer| observer.appid == appid);
+        self.apps
+            .enter(appid, |app, _| {
+                app.resource = Some(resource);
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn update(&self, appid: AppId, len: usize) -> ReturnCode {
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                let max_len = app.representation.as_ref().map_or(0, |buf| buf.len());
+                match app.resource.as_mut() {
+                    None => ReturnCode::EINVAL,
+                    Some(_) if len > max_len => ReturnCode::ESIZE,
+                    Some(resource) => {
+                        resource.len = len;
+                        resource.observe_seq = resource.observe_seq.wrapping_add(1);
+                        ReturnCode::SUCCESS
+                    }
+                }
+            })
+            .unwrap_or_else(|err| err.into());
+        if result == ReturnCode::SUCCESS {
+            self.notify_observers(appid);
+            self.send_queued();
+        }
+        result
+    }
+
+    fn unregister(&self, appid: AppId) -> ReturnCode {
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.resource.take().is_some() {
+                    ReturnCode::SUCCESS
+                } else {
+                    ReturnCode::EALREADY
+                }
+            })
+            .unwrap_or_else(|err| err.into());
+        self.remove_observers(|observer| observer.appid == appid);
+        result
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Driver for CoapDriver<'a, A> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Destination buffer. Holds the 16 byte IPv6 address and the
+    ///        port, in host byte order, of the server to send requests to.
+    ///        A port of 0 is the CoAP port, 5683.
+    /// - `1`: URI path buffer. Holds the path of the requested resource,
+    ///        with segments separated by '/'.
+    /// - `2`: Request payload buffer. Holds the payload of PUT and POST
+    ///        requests.
+    /// - `3`: Response buffer. Receives the payload of responses, truncated
+    ///        to its length.
+    /// - `4`: Resource path buffer. Holds the path under which to register
+    ///        a resource, at most `MAX_PATH_LEN` bytes long.
+    /// - `5`: Representation buffer. Holds the representation of the
+    ///        registered resource, which PUT requests replace.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.dst = slice,
+                    1 => app.uri_path = slice,
+                    2 => app.request_payload = slice,
+                    3 => app.response = slice,
+                    4 => app.resource_path = slice,
+                    5 => app.representation = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Callback for responses to the request of the process. The
+    ///        arguments are the result, the response code and the length of
+    ///        the payload copied into the response buffer. The result is
+    ///        SUCCESS when a response arrives, ENOACK if the server did not
+    ///        acknowledge or answer the request in time, FAIL if it rejected
+    ///        the request with a reset and any other error if the request
+    ///        could not be sent.
+    /// - `1`: Callback for when a PUT request replaced the representation of
+    ///        the resource of the process. The arguments are the method code
+    ///        and the length of the new representation.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match subscribe_num {
+                    0 => app.client_callback = callback,
+                    1 => app.server_callback = callback,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// CoAP control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send a request with method code `arg1` (1 for GET, 2 for POST,
+    ///        3 for PUT and 4 for DELETE) to the server in the destination
+    ///        buffer, for the resource in the URI path buffer. Bit 0 of
+    ///        `arg2` makes the request confirmable and bit 1 observes the
+    ///        resource, which only GET requests can do. Returns EBUSY if
+    ///        this process already has a request outstanding, EINVAL if the
+    ///        method or the destination buffer is invalid and ESIZE if the
+    ///        request does not fit in a message.
+    /// - `2`: Cancel the request of this process, and stop receiving
+    ///        notifications. Returns EALREADY if there is no request.
+    /// - `3`: Register a resource under the path in the resource path
+    ///        buffer, whose representation is the first `arg1` bytes of the
+    ///        representation buffer. Replaces the resource this process
+    ///        registered before. Returns EINVAL if the path is empty or too
+    ///        long, ESIZE if `arg1` is larger than the representation buffer
+    ///        and EBUSY if another process registered the same path.
+    /// - `4`: Set the length of the representation to `arg1` bytes, after
+    ///        the process changed it, and notify the clients observing the
+    ///        resource. Returns EINVAL if this process has no resource.
+    /// - `5`: Unregister the resource of this process. Returns EALREADY if
+    ///        there is none.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.request(appid, arg1, arg2),
+            2 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if app.request.take().is_some() {
+                        ReturnCode::SUCCESS
+                    } else {
+                        ReturnCode::EALREADY
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+            3 => self.register(appid, arg1),
+            4 => self.update(appid, arg1),
+            5 => self.unregister(appid),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPRecvClient for CoapDriver<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let message = match CoapMessage::decode(payload).done() {
+            Some((_, message)) => message,
+            None => {
+                // Malformed confirmable messages are rejected, others are
+                // ignored.
+                if let Some((_, header)) = CoapHeader::decode(payload).done() {
+                    if header.get_type() == MessageType::Confirmable {
+                        self.send_empty(
+                            src_addr,
+                            src_port,
+                            MessageType::Reset,
+                            header.get_message_id(),
+                        );
+                    }
+                }
+                return;
+            }
+        };
+        let header = message.header;
+        let message_id = header.get_message_id();
+        let confirmable = header.get_type() == MessageType::Confirmable;
+        match header.get_type() {
+            MessageType::Reset => self.handle_reset(src_addr, src_port, message_id),
+            MessageType::Acknowledgement => self.handle_ack(src_addr, src_port, &message),
+            _ if header.is_request() => match self.find_received(src_addr, src_port, message_id) {
+                Some(original) => {
+                    if confirmable {
+                        self.handle_request(src_addr, src_port, &message, Some(original.code));
+                    }
+                }
+                None => {
+                    let code = self.handle_request(src_addr, src_port, &message, None);
+                    self.remember_received(src_addr, src_port, message_id, code);
+                }
+            },
+            _ if header.is_response() => {
+                let duplicate = self.find_received(src_addr, src_port, message_id).is_some();
+                if !duplicate {
+                    self.remember_received(src_addr, src_port, message_id, coap_code::EMPTY);
+                }
+                if duplicate || self.handle_response(src_addr, src_port, &message) {
+                    if confirmable {
+                        self.send_empty(
+                            src_addr,
+                            src_port,
+                            MessageType::Acknowledgement,
+                            message_id,
+                        );
+                    }
+                } else {
+                    // Unexpected responses, such as notifications for
+                    // cancelled requests, are rejected.
+                    self.send_empty(src_addr, src_port, MessageType::Reset, message_id);
+                }
+            }
+            // Empty confirmable messages (pings) and messages with reserved
+            // codes are rejected.
+            _ if confirmable => self.send_empty(src_addr, src_port, MessageType::Reset, message_id),
+            _ => {}
+        }
+        self.send_queued();
+        self.reschedule();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPSendClient for CoapDriver<'a, A> {
+    fn send_done(&self, _result: ReturnCode, mut dgram: LeasableBuffer<'static, u8>) {
+        // Lost requests are retransmitted or time out, so the result is not
+        // reported.
+        dgram.reset();
+        self.tx_buf.replace(dgram);
+        self.send_queued();
+        self.reschedule();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for CoapDriver<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        self.apps.each(|app| {
+            let mut request = match app.request {
+                Some(request) => request,
+                None => return,
+            };
+            let waiting = request.state == RequestState::AwaitingAck
+                || request.state == RequestState::AwaitingResponse;
+            if !waiting || !is_due(request.deadline, now) {
+                return;
+            }
+            if request.state == RequestState::AwaitingAck && request.transmissions <= MAX_RETRANSMIT
+            {
+                request.timeout =
+                    cmp::min(request.timeout.saturating_mul(2), i32::max_value() as u32);
+                request.state = RequestState::Queued;
+                app.request = Some(request);
+            } else {
+                app.request = None;
+                app.client_callback
+                    .map(|mut cb| cb.schedule(ReturnCode::ENOACK.into(), 0, 0));
+            }
+        });
+        self.forget_expired();
+        self.send_queued();
+        self.reschedule();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::net::coap::coap::{
+        coap_code, coap_option, CoapHeader, CoapMessage, CoapWriter, MessageType, COAP_PORT,
+    };
+    use crate::net::loopback::{
+        any_network_capability, link_local_addr, LoopbackUdpSender, NoUserPorts,
+    };
+    use crate::net::network_capabilities::UdpVisibilityCapability;
+    use crate::net::udp::udp_port_table::{UdpPortManager, MAX_NUM_BOUND_PORTS};
+    use crate::net::udp::udp_recv::{UDPReceiver, UDPRecvClient};
+    use crate::net::udp::udp_send::UDPSender;
+    use host::chip::HostChip;
+    use host::harness::{
+        create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp,
+        TestCapabilities, TestPlatform,
+    };
+    use host::mock::alarm::MockAlarm;
+    use host::syscall::{HostApp, Resumption};
+    use kernel::common::leasable_buffer::LeasableBuffer;
+    use kernel::hil::time::Alarm;
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::ReturnCode;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Serializes a request for `path` with a one byte token.
+    fn coap_request(
+        msg_type: MessageType,
+        code: u8,
+        message_id: u16,
+        path: &[u8],
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut buf = vec![0; 64];
+        let header = CoapHeader::new(msg_type, code, message_id, &[0x5a]);
+        let mut writer = CoapWriter::new(&mut buf, &header).unwrap();
+        assert_eq!(writer.add_uri_path(path), ReturnCode::SUCCESS);
+        assert_eq!(writer.add_payload(payload), ReturnCode::SUCCESS);
+        let len = writer.len();
+        buf.truncate(len);
+        buf
+    }
+
+    #[test]
+    pub fn coap_driver_retransmits_deduplicates_and_notifies() {
+        let (kernel, processes) = create_kernel();
+        let (addr, peer_addr) = (link_local_addr(1), link_local_addr(2));
+
+        let port_table = leak(UdpPortManager::new(
+            &TestCapabilities,
+            Box::leak(Box::new([None; MAX_NUM_BOUND_PORTS])),
+            leak(UdpVisibilityCapability::new(&TestCapabilities)),
+        ));
+        port_table.set_user_ports(leak(NoUserPorts), &TestCapabilities);
+        let sender = leak(LoopbackUdpSender::new(addr));
+        let receiver = leak(UDPReceiver::new());
+        let alarm: &MockAlarm = leak(MockAlarm::new());
+        let coap = leak(crate::net::coap::CoapDriver::new(
+            sender,
+            receiver,
+            port_table,
+            alarm,
+            create_grant(kernel),
+            LeasableBuffer::new(Box::leak(Box::new([0; 128]))),
+            any_network_capability(),
+        ));
+        sender.set_client(coap);
+        receiver.set_client(coap);
+        alarm.set_client(coap);
+        assert_eq!(coap.bind(COAP_PORT), ReturnCode::SUCCESS);
+        assert_eq!(coap.bind(COAP_PORT), ReturnCode::EALREADY);
+
+        let allow = |allow_num, offset, size| Syscall::ALLOW {
+            driver_number: crate::net::coap::DRIVER_NUM,
+            subdriver_number: allow_num,
+            allow_address: offset as *mut u8,
+            allow_size: size,
+        };
+        let subscribe = |subscribe_num, callback| Syscall::SUBSCRIBE {
+            driver_number: crate::net::coap::DRIVER_NUM,
+            subdriver_number: subscribe_num,
+            callback_ptr: callback as *mut (),
+            appdata: 0,
+        };
+        let coap_command = |command_num, arg0, arg1| Syscall::COMMAND {
+            driver_number: crate::net::coap::DRIVER_NUM,
+            subdriver_number: command_num,
+            arg0: arg0,
+            arg1: arg1,
+        };
+
+        // The server serves "temp", and tells the driver when it updates it
+        // after a PUT.
+        let mut server_memory = vec![0; 32];
+        server_memory[..4].copy_from_slice(b"temp");
+        server_memory[16..21].copy_from_slice(b"21.5C");
+        let server = leak(ScriptedApp::new_with_memory(
+            vec![
+                allow(4, 0, 4),
+                allow(5, 16, 16),
+                subscribe(1, 0x2000),
+                coap_command(3, 17, 0),
+                coap_command(3, 5, 0),
+                Syscall::YIELD,
+                coap_command(4, 3, 0),
+            ],
+            Box::leak(server_memory.into_boxed_slice()),
+        ));
+        // The client asks the server on its own node for "temp", then observes
+        // it.
+        let mut client_memory = vec![0; 80];
+        client_memory[..16].copy_from_slice(&addr.0);
+        client_memory[32..36].copy_from_slice(b"temp");
+        let client = leak(ScriptedApp::new_with_memory(
+            vec![
+                allow(0, 0, 18),
+                allow(1, 32, 4),
+                allow(3, 64, 16),
+                subscribe(0, 0x1000),
+                coap_command(1, 3, 2),
+                coap_command(1, 1, 1),
+                Syscall::YIELD,
+                coap_command(1, 1, 3),
+            ],
+            Box::leak(client_memory.into_boxed_slice()),
+        ));
+        let apps: &'static [&'static dyn HostApp] =
+            leak([server as &dyn HostApp, client as &dyn HostApp]);
+        let chip = leak(HostChip::new(apps, None));
+        load_apps(
+            kernel,
+            chip,
+            processes,
+            &[("server", 0), ("client", 1)],
+            FaultResponse::Stop,
+        );
+        let platform = TestPlatform::new().driver(crate::net::coap::DRIVER_NUM, coap);
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            *server.returns.borrow(),
+            [0, 0, 0, isize::from(ReturnCode::ESIZE), 0]
+        );
+        assert_eq!(
+            *client.returns.borrow(),
+            [0, 0, 0, 0, isize::from(ReturnCode::EINVAL), 0]
+        );
+
+        let callback_args = |app: &ScriptedApp| -> Vec<(usize, usize, usize, usize)> {
+            app.callbacks
+                .borrow()
+                .iter()
+                .map(|callback| match *callback {
+                    Resumption::Callback {
+                        pc,
+                        argument0,
+                        argument1,
+                        argument2,
+                        ..
+                    } => (pc, argument0, argument1, argument2),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        // The confirmable GET is lost and retransmitted with the same message
+        // ID.
+        let (_, request) = sender.transfer(coap, true).unwrap();
+        assert!(alarm.advance_to_alarm());
+        run_until_idle(kernel, &platform, chip);
+        let (_, retransmission) = sender.transfer(coap, false).unwrap();
+        assert_eq!(retransmission, request);
+        // The response is piggybacked on the acknowledgement.
+        let (_, response) = sender.transfer(coap, false).unwrap();
+        let response = CoapMessage::decode(&response).done().unwrap().1;
+        assert_eq!(response.header.get_type(), MessageType::Acknowledgement);
+        assert_eq!(response.header.get_code(), coap_code::CONTENT);
+        assert_eq!(response.payload, b"21.5C");
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            callback_args(client),
+            [(0x1000, 0, coap_code::CONTENT as usize, 5)]
+        );
+        let response = client.memory(64, 5);
+        assert_eq!(response, b"21.5C");
+
+        // The observe request registers the client, which stays subscribed
+        // after the first response.
+        assert!(sender.transfer(coap, false).is_some());
+        let (_, response) = sender.transfer(coap, false).unwrap();
+        let response = CoapMessage::decode(&response).done().unwrap().1;
+        assert_eq!(response.get_uint_option(coap_option::OBSERVE), Some(0));
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(client.callbacks.borrow().len(), 2);
+        assert!(sender.outbox.borrow().is_empty());
+
+        // A PUT from a peer replaces the representation, and the server updates
+        // it, which notifies the client once.
+        let put = coap_request(
+            MessageType::Confirmable,
+            coap_code::PUT,
+            0x7000,
+            b"temp",
+            b"25C",
+        );
+        coap.receive(peer_addr, addr, COAP_PORT, COAP_PORT, &put);
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(*server.returns.borrow().last().unwrap(), 0);
+        let (dst, ack) = sender.transfer(coap, false).unwrap();
+        assert_eq!(dst, peer_addr);
+        assert_eq!(ack[..4], [0x61, coap_code::CHANGED, 0x70, 0x00]);
+        let (dst, notification) = sender.transfer(coap, false).unwrap();
+        assert_eq!(dst, addr);
+        let notification = CoapMessage::decode(&notification).done().unwrap().1;
+        assert_eq!(notification.header.get_type(), MessageType::NonConfirmable);
+        assert_eq!(notification.get_uint_option(coap_option::OBSERVE), Some(2));
+        assert_eq!(notification.payload, b"25C");
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(client.callbacks.borrow().len(), 3);
+        assert!(sender.outbox.borrow().is_empty());
+
+        // The retransmission of the PUT is acknowledged again, without being
+        // applied twice.
+        coap.receive(peer_addr, addr, COAP_PORT, COAP_PORT, &put);
+        let (dst, ack) = sender.transfer(coap, false).unwrap();
+        assert_eq!(dst, peer_addr);
+        assert_eq!(ack[..4], [0x61, coap_code::CHANGED, 0x70, 0x00]);
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            callback_args(server),
+            [(0x2000, coap_code::PUT as usize, 3, 0)]
+        );
+        assert!(sender.outbox.borrow().is_empty());
+
+        // Unknown resources are not found.
+        let get = coap_request(
+            MessageType::NonConfirmable,
+            coap_code::GET,
+            0x7001,
+            b"hum",
+            b"",
+        );
+        coap.receive(peer_addr, addr, COAP_PORT, COAP_PORT, &get);
+        let (dst, response) = sender.transfer(coap, false).unwrap();
+        assert_eq!(dst, peer_addr);
+        assert_eq!(response[..2], [0x51, coap_code::NOT_FOUND]);
+
+        // A PUT too large for the representation is retransmitted while the
+        // response to it is being sent. The retransmission waits for the
+        // transmit buffer and is answered with the original code.
+        let large_put = coap_request(
+            MessageType::Confirmable,
+            coap_code::PUT,
+            0x7002,
+            b"temp",
+            &[b'9'; 20],
+        );
+        coap.receive(peer_addr, addr, COAP_PORT, COAP_PORT, &large_put);
+        coap.receive(peer_addr, addr, COAP_PORT, COAP_PORT, &large_put);
+        for _ in 0..2 {
+            let (dst, ack) = sender.transfer(coap, false).unwrap();
+            assert_eq!(dst, peer_addr);
+            assert_eq!(
+                ack[..4],
+                [0x61, coap_code::REQUEST_ENTITY_TOO_LARGE, 0x70, 0x02]
+            );
+        }
+        assert!(sender.outbox.borrow().is_empty());
+
+        // Message IDs are forgotten once their exchange lifetime is over, and
+        // stay forgotten however long the driver then waits, so the PUT is
+        // applied again.
+        // Each alarm forgets at least one message.
+        for _ in 0..super::RECENT_MESSAGES {
+            alarm.advance_to_alarm();
+        }
+        assert!(!alarm.advance_to_alarm());
+        alarm.advance((1 << 31) + 1);
+        coap.receive(peer_addr, addr, COAP_PORT, COAP_PORT, &put);
+        let (_, ack) = sender.transfer(coap, false).unwrap();
+        assert_eq!(ack[..4], [0x61, coap_code::CHANGED, 0x70, 0x00]);
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            callback_args(server),
+            [
+                (0x2000, coap_code::PUT as usize, 3, 0),
+                (0x2000, coap_code::PUT as usize, 3, 0)
+            ]
+        );
+    }
+}
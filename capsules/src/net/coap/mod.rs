@@ -0,0 +1,5 @@
+pub mod coap;
+pub mod driver;
+
+pub use self::driver::CoapDriver;
+pub use self::driver::DRIVER_NUM;
@@ -0,0 +1,282 @@
+//! This file contains the functions to encode DNS (RFC 1035) queries for the
+//! IPv6 address of a host (AAAA records, RFC 3596) and to find the address in
+//! the responses to them.
+//!
+//! A message is a 12 byte header followed by the question, which holds the
+//! name that is looked up, and by sections of resource records. Names are
+//! sequences of length-prefixed labels, and in responses can end with a
+//! pointer to a name earlier in the message. Only the answer section of
+//! responses is read; a stub resolver relies on the recursive server it asks
+//! to follow aliases, which it returns in the same section.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u32, encode_u16};
+
+/// The well-known UDP port of DNS servers.
+pub const DNS_PORT: u16 = 53;
+pub const HEADER_LEN: usize = 12;
+/// The longest name, once encoded as labels.
+pub const MAX_NAME_LEN: usize = 255;
+pub const MAX_LABEL_LEN: usize = 63;
+
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_AAAA: u16 = 28;
+pub const CLASS_IN: u16 = 1;
+
+/// Response codes (RCODE) of the header.
+pub mod rcode {
+    pub const NO_ERROR: u16 = 0;
+    pub const FORMAT_ERROR: u16 = 1;
+    pub const SERVER_FAILURE: u16 = 2;
+    pub const NAME_ERROR: u16 = 3;
+    pub const NOT_IMPLEMENTED: u16 = 4;
+    pub const REFUSED: u16 = 5;
+}
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+const RCODE_MASK: u16 = 0x000f;
+/// How many compression pointers are followed in a name, so that a loop of
+/// pointers is rejected.
+const MAX_POINTERS: usize = 16;
+
+/// What a response says about the name that was looked up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Answer {
+    /// The name has this address, which can be cached for `ttl` seconds.
+    Address { addr: IPAddr, ttl: u32 },
+    /// The name does not exist, or has no IPv6 address.
+    NoAddress,
+    /// The server could not answer the query.
+    ServerFailure,
+}
+
+/// Removes the trailing dot of a fully qualified name.
+fn trim(name: &[u8]) -> &[u8] {
+    match name.split_last() {
+        Some((&b'.', rest)) => rest,
+        _ => name,
+    }
+}
+
+fn labels(name: &[u8]) -> impl Iterator<Item = &[u8]> {
+    trim(name).split(|&byte| byte == b'.')
+}
+
+/// Whether `name` is a host name that can be looked up: labels of 1 to 63
+/// letters, digits, hyphens or underscores separated by dots, with an
+/// optional trailing dot, at most `MAX_NAME_LEN` bytes long once encoded.
+pub fn is_valid_name(name: &[u8]) -> bool {
+    let valid_byte = |&byte: &u8| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_';
+    trim(name).len() + 2 <= MAX_NAME_LEN
+        && labels(name).all(|label| {
+            !label.is_empty() && label.len() <= MAX_LABEL_LEN && label.iter().all(valid_byte)
+        })
+}
+
+/// Whether two names are the same, ignoring case and a trailing dot.
+pub fn same_name(a: &[u8], b: &[u8]) -> bool {
+    trim(a).eq_ignore_ascii_case(trim(b))
+}
+
+/// Returns the offset after the name at `off` in `buf`, which ends with a
+/// zero length label or a compression pointer.
+fn skip_name(buf: &[u8], mut off: usize) -> Option<usize> {
+    loop {
+        let len = *buf.get(off)? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => return Some(off + 1),
+            0x00 => off += 1 + len,
+            0xc0 => {
+                buf.get(off + 1)?;
+                return Some(off + 2);
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Whether the possibly compressed name at `off` in `buf` is `name`,
+/// ignoring case. Returns None if the name is malformed.
+fn name_matches(buf: &[u8], mut off: usize, name: &[u8]) -> Option<bool> {
+    let mut expected = labels(name);
+    let mut pointers = 0;
+    loop {
+        let len = *buf.get(off)? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => return Some(expected.next().is_none()),
+            0x00 => {
+                let label = buf.get(off + 1..off + 1 + len)?;
+                match expected.next() {
+                    Some(expected) if expected.eq_ignore_ascii_case(label) => {}
+                    _ => return Some(false),
+                }
+                off += 1 + len;
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                off = (len & 0x3f) << 8 | *buf.get(off + 1)? as usize;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Encodes a recursive query for the AAAA records of `name`. Fails if the
+/// name is invalid or `buf` is too short.
+pub fn encode_query(buf: &mut [u8], id: u16, name: &[u8]) -> SResult<usize> {
+    stream_cond!(is_valid_name(name));
+    stream_len_cond!(buf, HEADER_LEN + trim(name).len() + 2 + 4);
+
+    let mut off = 0;
+    off = enc_consume!(buf, off; encode_u16, id);
+    off = enc_consume!(buf, off; encode_u16, FLAG_RECURSION_DESIRED);
+    // One question, and no answer, authority or additional records.
+    off = enc_consume!(buf, off; encode_u16, 1);
+    off = enc_consume!(buf, off; encode_u16, 0);
+    off = enc_consume!(buf, off; encode_u16, 0);
+    off = enc_consume!(buf, off; encode_u16, 0);
+    for label in labels(name) {
+        buf[off] = label.len() as u8;
+        buf[off + 1..off + 1 + label.len()].copy_from_slice(label);
+        off += 1 + label.len();
+    }
+    buf[off] = 0;
+    off += 1;
+    off = enc_consume!(buf, off; encode_u16, TYPE_AAAA);
+    off = enc_consume!(buf, off; encode_u16, CLASS_IN);
+    stream_done!(off, off);
+}
+
+/// Finds what the response in `buf` says about `name`. Fails if `buf` is
+/// malformed or is not the response to the query with ID `id` for `name`.
+/// The first AAAA record of the answer section is used.
+pub fn decode_response(buf: &[u8], id: u16, name: &[u8]) -> SResult<Answer> {
+    stream_len_cond!(buf, HEADER_LEN);
+    let off = 0;
+    let (off, response_id) = dec_try!(buf, off; decode_u16);
+    let (off, flags) = dec_try!(buf, off; decode_u16);
+    let (off, questions) = dec_try!(buf, off; decode_u16);
+    let (off, answers) = dec_try!(buf, off; decode_u16);
+    // Skip the counts of authority and additional records.
+    let off = off + 4;
+    stream_cond!(response_id == id);
+    stream_cond!(flags & FLAG_RESPONSE != 0 && flags & OPCODE_MASK == 0);
+    stream_cond!(questions == 1 && name_matches(buf, off, name) == Some(true));
+    let off = stream_from_option!(skip_name(buf, off));
+    let (off, qtype) = dec_try!(buf, off; decode_u16);
+    let (mut off, qclass) = dec_try!(buf, off; decode_u16);
+    stream_cond!(qtype == TYPE_AAAA && qclass == CLASS_IN);
+
+    match flags & RCODE_MASK {
+        rcode::NO_ERROR => {}
+        rcode::NAME_ERROR => stream_done!(off, Answer::NoAddress),
+        _ => stream_done!(off, Answer::ServerFailure),
+    }
+    for _ in 0..answers {
+        off = stream_from_option!(skip_name(buf, off));
+        let (rdata, rtype) = dec_try!(buf, off; decode_u16);
+        let (rdata, rclass) = dec_try!(buf, rdata; decode_u16);
+        let (rdata, ttl) = dec_try!(buf, rdata; decode_u32);
+        let (rdata, rdlength) = dec_try!(buf, rdata; decode_u16);
+        let rdlength = rdlength as usize;
+        stream_len_cond!(buf, rdata + rdlength);
+        if rtype == TYPE_AAAA && rclass == CLASS_IN && rdlength == 16 {
+            let mut addr = IPAddr::new();
+            addr.0.copy_from_slice(&buf[rdata..rdata + 16]);
+            // TTLs with the top bit set are treated as zero (RFC 2181).
+            let ttl = if ttl > i32::max_value() as u32 {
+                0
+            } else {
+                ttl
+            };
+            stream_done!(rdata + 16, Answer::Address { addr, ttl });
+        }
+        off = rdata + rdlength;
+    }
+    stream_done!(off, Answer::NoAddress);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const QUESTION: [u8; 21] = [
+        3, b'a', b'p', b'i', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+        0, 28, 0, 1,
+    ];
+
+    #[test]
+    pub fn queries_are_encoded() {
+        let mut buf = [0; 64];
+        assert_eq!(
+            encode_query(&mut buf, 0xbeef, b"api.example.com.").done(),
+            Some((33, 33))
+        );
+        assert_eq!(buf[..12], [0xbe, 0xef, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(buf[12..33], QUESTION);
+        assert!(encode_query(&mut buf[..32], 0xbeef, b"api.example.com").is_needed());
+
+        assert!(is_valid_name(b"localhost"));
+        assert!(!is_valid_name(b""));
+        assert!(!is_valid_name(b"."));
+        assert!(!is_valid_name(b"api..example.com"));
+        assert!(!is_valid_name(b"api example.com"));
+        assert!(!is_valid_name(b"api.example.com\0"));
+        assert!(!is_valid_name(&[b'a'; 64]));
+        assert!(is_valid_name(&[b'a'; 63]));
+    }
+
+    #[test]
+    pub fn responses_are_decoded() {
+        // api.example.com is an alias of host.example.com, compressed, which
+        // has an address.
+        let mut addr = IPAddr::new();
+        addr.0[0] = 0x20;
+        addr.0[1] = 0x01;
+        addr.0[15] = 0x42;
+        let mut response = [0; 80];
+        response[..12].copy_from_slice(&[0xbe, 0xef, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0]);
+        response[12..33].copy_from_slice(&QUESTION);
+        response[33..45].copy_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 7]);
+        response[45..52].copy_from_slice(&[4, b'h', b'o', b's', b't', 0xc0, 16]);
+        response[52..64].copy_from_slice(&[0xc0, 45, 0, 28, 0, 1, 0, 0, 1, 0x2c, 0, 16]);
+        response[64..80].copy_from_slice(&addr.0);
+
+        let answer = Answer::Address { addr, ttl: 300 };
+        assert_eq!(
+            decode_response(&response, 0xbeef, b"API.example.com").done(),
+            Some((80, answer))
+        );
+        // The response must be for this query.
+        assert!(decode_response(&response, 0xbeee, b"api.example.com").is_err());
+        assert!(decode_response(&response, 0xbeef, b"web.example.com").is_err());
+        assert!(decode_response(&response[..79], 0xbeef, b"api.example.com").is_needed());
+
+        // Without the address record, and when the name does not exist.
+        response[7] = 1;
+        assert_eq!(
+            decode_response(&response, 0xbeef, b"api.example.com").done(),
+            Some((52, Answer::NoAddress))
+        );
+        response[3] = 0x83;
+        assert_eq!(
+            decode_response(&response, 0xbeef, b"api.example.com").done(),
+            Some((33, Answer::NoAddress))
+        );
+        response[3] = 0x82;
+        assert_eq!(
+            decode_response(&response, 0xbeef, b"api.example.com").done(),
+            Some((33, Answer::ServerFailure))
+        );
+
+        // A question whose name points to itself.
+        response[12..14].copy_from_slice(&[0xc0, 12]);
+        assert!(decode_response(&response, 0xbeef, b"api.example.com").is_err());
+    }
+}
@@ -0,0 +1,785 @@
+//! DNS resolver userspace interface.
+//!
+//! Resolves host names to IPv6 addresses for processes, so that they do not
+//! need to know the addresses of the nodes they talk to. The driver is a stub
+//! resolver: it sends queries for AAAA records over UDP to one recursive DNS
+//! server, set by the board, which does the actual resolution.
+//!
+//! A process shares the name to resolve and a buffer for the address, and is
+//! called back once the address is known, the name turns out not to exist or
+//! the server does not answer. Queries are retransmitted with exponential
+//! backoff until a response arrives. Resolved addresses are cached for as
+//! long as the server allows, up to `MAX_TTL`, so that repeated lookups of a
+//! name are answered without querying the server again. Each process can
+//! have one lookup outstanding at a time.
+//!
+//! Queries are sent from the port the driver is bound to, with a random ID,
+//! and responses are only accepted from the server if they match both the ID
+//! and the name that was looked up.
+
+use crate::net::dns::dns::{self, Answer, DNS_PORT};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_port_table::UdpPortManager;
+use crate::net::udp::udp_recv::{UDPReceiver, UDPRecvClient};
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::MapCell;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::{self, Frequency};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Dns as usize;
+
+/// The longest name processes can resolve.
+pub const MAX_HOSTNAME_LEN: usize = 64;
+/// The length of the transmit buffer, which holds a query for the longest
+/// name: the header, the encoded name, its type and its class.
+pub const TX_BUF_LEN: usize = dns::HEADER_LEN + MAX_HOSTNAME_LEN + 2 + 4;
+/// The number of resolved names that are cached.
+pub const CACHE_SIZE: usize = 4;
+/// How long to wait for the response to the first query, in milliseconds.
+/// The timeout doubles after every retransmission.
+pub const QUERY_TIMEOUT_MS: u32 = 1000;
+/// How many times a query is sent before giving up.
+pub const MAX_TRANSMISSIONS: u8 = 4;
+/// The longest time, in seconds, for which an address is cached, whatever
+/// the TTL the server gives.
+pub const MAX_TTL: u32 = 3600;
+
+#[derive(Copy, Clone, PartialEq)]
+enum QueryState {
+    /// Waiting for the transmit buffer to be sent or retransmitted.
+    Queued,
+    AwaitingResponse,
+}
+
+#[derive(Copy, Clone)]
+struct Query {
+    id: u16,
+    name: [u8; MAX_HOSTNAME_LEN],
+    name_len: usize,
+    state: QueryState,
+    transmissions: u8,
+    /// The retransmission timeout in tics, doubled after every timeout.
+    timeout: u32,
+    deadline: u32,
+}
+
+#[derive(Copy, Clone)]
+struct CacheEntry {
+    name: [u8; MAX_HOSTNAME_LEN],
+    name_len: usize,
+    addr: IPAddr,
+    /// Evicted once due, so that it is never compared across wraparound.
+    expires: u32,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    hostname: Option<AppSlice<Shared, u8>>,
+    address: Option<AppSlice<Shared, u8>>,
+    query: Option<Query>,
+}
+
+pub struct DnsDriver<'a, A: time::Alarm<'a>> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    udp_receiver: &'a UDPReceiver<'a>,
+    port_table: &'static UdpPortManager,
+    alarm: &'a A,
+    apps: Grant<App>,
+    /// Held while a query is being sent.
+    tx_buf: MapCell<LeasableBuffer<'static, u8>>,
+    server: Cell<IPAddr>,
+    cache: [Cell<Option<CacheEntry>>; CACHE_SIZE],
+    rng: Cell<u32>,
+    net_cap: &'static NetworkCapability,
+}
+
+fn is_due(deadline: u32, now: u32) -> bool {
+    deadline.wrapping_sub(now) as i32 <= 0
+}
+
+impl<'a, A: time::Alarm<'a>> DnsDriver<'a, A> {
+    /// `server` is the address of the recursive DNS server to query, usually
+    /// the border router of the network. `tx_buf` should be `TX_BUF_LEN`
+    /// bytes long.
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        udp_receiver: &'a UDPReceiver<'a>,
+        port_table: &'static UdpPortManager,
+        alarm: &'a A,
+        grant: Grant<App>,
+        tx_buf: LeasableBuffer<'static, u8>,
+        server: IPAddr,
+        net_cap: &'static NetworkCapability,
+    ) -> DnsDriver<'a, A> {
+        DnsDriver {
+            udp_sender: udp_sender,
+            udp_receiver: udp_receiver,
+            port_table: port_table,
+            alarm: alarm,
+            apps: grant,
+            tx_buf: MapCell::new(tx_buf),
+            server: Cell::new(server),
+            cache: Default::default(),
+            rng: Cell::new(1),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Binds the UDP sender and receiver of the driver to `port`, from which
+    /// queries are sent. Returns EALREADY if the driver is already bound and
+    /// EBUSY if the port is taken.
+    pub fn bind(&self, port: u16) -> ReturnCode {
+        if self.udp_sender.is_bound() {
+            return ReturnCode::EALREADY;
+        }
+        let socket = match self.port_table.create_socket() {
+            Ok(socket) => socket,
+            Err(err) => return err,
+        };
+        match self.port_table.bind(socket, port, self.net_cap) {
+            Ok((send_binding, recv_binding)) => {
+                self.udp_sender.set_binding(send_binding);
+                self.udp_receiver.set_binding(recv_binding);
+                ReturnCode::SUCCESS
+            }
+            Err(_socket) => ReturnCode::EBUSY,
+        }
+    }
+
+    /// Changes the DNS server, and forgets the names resolved by the previous
+    /// one. Outstanding queries are sent to the new server when they are
+    /// retransmitted.
+    pub fn set_server(&self, server: IPAddr) {
+        self.server.set(server);
+        for entry in self.cache.iter() {
+            entry.set(None);
+        }
+    }
+
+    /// Converts seconds to tics, clamped so that deadlines compare correctly
+    /// across wraparound.
+    fn tics(secs: u32) -> u32 {
+        let tics = secs as u64 * <A::Frequency>::frequency() as u64;
+        cmp::min(tics, i32::max_value() as u64) as u32
+    }
+
+    fn tics_ms(ms: u32) -> u32 {
+        let tics = ms as u64 * <A::Frequency>::frequency() as u64 / 1000;
+        cmp::min(tics, i32::max_value() as u64) as u32
+    }
+
+    /// A xorshift generator mixed with the time, for query IDs.
+    fn random(&self) -> u32 {
+        let mut x = self.rng.get() ^ self.alarm.now();
+        if x == 0 {
+            x = 1;
+        }
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng.set(x);
+        x
+    }
+
+    /// Evicts the cached addresses that expired.
+    fn evict_expired(&self) {
+        let now = self.alarm.now();
+        for slot in self.cache.iter() {
+            if slot.get().map_or(false, |entry| is_due(entry.expires, now)) {
+                slot.set(None);
+            }
+        }
+    }
+
+    /// Returns the cached address of `name` and how many seconds it stays
+    /// cached.
+    fn lookup_cache(&self, name: &[u8]) -> Option<(IPAddr, u32)> {
+        self.evict_expired();
+        let now = self.alarm.now();
+        self.cache
+            .iter()
+            .filter_map(|entry| entry.get())
+            .find(|entry| dns::same_name(&entry.name[..entry.name_len], name))
+            .map(|entry| {
+                let remaining = entry.expires.wrapping_sub(now) as u64;
+                let secs = remaining / <A::Frequency>::frequency() as u64;
+                (entry.addr, secs as u32)
+            })
+    }
+
+    /// Caches the address of `name` for `ttl` seconds, replacing the entry of
+    /// the same name, or else a free entry or the one that expires first.
+    fn insert_cache(&self, name: &[u8], addr: IPAddr, ttl: u32) {
+        let ttl = cmp::min(ttl, MAX_TTL);
+        if ttl == 0 {
+            return;
+        }
+        self.evict_expired();
+        let now = self.alarm.now();
+        let slot = self
+            .cache
+            .iter()
+            .find(|slot| {
+                slot.get()
+                    .map_or(false, |e| dns::same_name(&e.name[..e.name_len], name))
+            })
+            .or_else(|| {
+                self.cache
+                    .iter()
+                    .min_by_key(|slot| slot.get().map_or(0, |e| e.expires.wrapping_sub(now)))
+            });
+        if let Some(slot) = slot {
+            let mut entry = CacheEntry {
+                name: [0; MAX_HOSTNAME_LEN],
+                name_len: name.len(),
+                addr: addr,
+                expires: now.wrapping_add(Self::tics(ttl)),
+            };
+            entry.name[..name.len()].copy_from_slice(name);
+            slot.set(Some(entry));
+        }
+    }
+
+    /// Copies `addr` into the address buffer of the process and calls it
+    /// back.
+    fn resolved(app: &mut App, addr: IPAddr, ttl: u32) {
+        app.address.as_mut().map(|buf| {
+            let len = cmp::min(buf.len(), addr.0.len());
+            buf.as_mut()[..len].copy_from_slice(&addr.0[..len]);
+        });
+        app.callback
+            .map(|mut cb| cb.schedule(ReturnCode::SUCCESS.into(), ttl as usize, 0));
+    }
+
+    /// Ends the lookup of `appid` and calls the process back with an error.
+    fn fail_query(&self, appid: AppId, result: ReturnCode) {
+        let _ = self.apps.enter(appid, |app, _| {
+            if app.query.take().is_some() {
+                app.callback.map(|mut cb| cb.schedule(result.into(), 0, 0));
+            }
+        });
+    }
+
+    /// Sends the queued query of `appid`, and waits for its response.
+    fn transmit_query(&self, appid: AppId) -> ReturnCode {
+        let now = self.alarm.now();
+        let server = self.server.get();
+        self.apps
+            .enter(appid, |app, _| {
+                let mut query = match app.query {
+                    Some(query) if query.state == QueryState::Queued => query,
+                    _ => return ReturnCode::SUCCESS,
+                };
+                let mut buf = match self.tx_buf.take() {
+                    Some(buf) => buf,
+                    None => return ReturnCode::EBUSY,
+                };
+                let len =
+                    match dns::encode_query(&mut buf[..], query.id, &query.name[..query.name_len])
+                        .done()
+                    {
+                        Some((len, _)) => len,
+                        None => {
+                            self.tx_buf.replace(buf);
+                            return ReturnCode::ESIZE;
+                        }
+                    };
+                buf.slice(0..len);
+                if let Err(mut buf) = self.udp_sender.send_to(server, DNS_PORT, buf, self.net_cap) {
+                    buf.reset();
+                    self.tx_buf.replace(buf);
+                    return ReturnCode::FAIL;
+                }
+                query.transmissions += 1;
+                query.state = QueryState::AwaitingResponse;
+                query.deadline = now.wrapping_add(query.timeout);
+                app.query = Some(query);
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Sends queued queries while the transmit buffer is free.
+    fn send_queued(&self) {
+        while self.tx_buf.is_some() {
+            let queued = self.apps.iter().find_map(|app| {
+                app.enter(|app, _| match app.query {
+                    Some(query) if query.state == QueryState::Queued => Some(app.appid()),
+                    _ => None,
+                })
+            });
+            match queued {
+                Some(appid) => {
+                    let result = self.transmit_query(appid);
+                    if result != ReturnCode::SUCCESS {
+                        self.fail_query(appid, result);
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Sets the alarm for the earliest retransmission, or for when the
+    /// earliest cached address expires.
+    fn reschedule(&self) {
+        let now = self.alarm.now();
+        let remaining = self
+            .apps
+            .iter()
+            .filter_map(|app| {
+                app.enter(|app, _| match app.query {
+                    Some(query) if query.state == QueryState::AwaitingResponse => {
+                        Some(query.deadline)
+                    }
+                    _ => None,
+                })
+            })
+            .chain(
+                self.cache
+                    .iter()
+                    .filter_map(|slot| slot.get().map(|entry| entry.expires)),
+            )
+            .map(|deadline| {
+                let remaining = deadline.wrapping_sub(now);
+                if remaining as i32 > 0 {
+                    remaining
+                } else {
+                    1
+                }
+            })
+            .min();
+        match remaining {
+            Some(remaining) => self.alarm.set_alarm(now.wrapping_add(remaining)),
+            None => self.alarm.disable(),
+        }
+    }
+
+    fn resolve(&self, appid: AppId, len: usize) -> ReturnCode {
+        let id = self.random() as u16;
+        let timeout = Self::tics_ms(QUERY_TIMEOUT_MS);
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.query.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                let name = match app.hostname.as_ref() {
+                    Some(hostname) if len <= hostname.len() => &hostname.as_ref()[..len],
+                    _ => return ReturnCode::EINVAL,
+                };
+                if len > MAX_HOSTNAME_LEN {
+                    return ReturnCode::ESIZE;
+                }
+                if !dns::is_valid_name(name) {
+                    return ReturnCode::EINVAL;
+                }
+                if app.address.as_ref().map_or(0, |buf| buf.len()) < mem::size_of::<IPAddr>() {
+                    return ReturnCode::EINVAL;
+                }
+                if let Some((addr, ttl)) = self.lookup_cache(name) {
+                    Self::resolved(app, addr, ttl);
+                    return ReturnCode::SUCCESS;
+                }
+                let mut query = Query {
+                    id: id,
+                    name: [0; MAX_HOSTNAME_LEN],
+                    name_len: len,
+                    state: QueryState::Queued,
+                    transmissions: 0,
+                    timeout: timeout,
+                    deadline: 0,
+                };
+                query.name[..len].copy_from_slice(name);
+                app.query = Some(query);
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into());
+        if result == ReturnCode::SUCCESS {
+            // Otherwise, the query is sent once the transmit buffer is free.
+            self.send_queued();
+            self.reschedule();
+        }
+        result
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Driver for DnsDriver<'a, A> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Hostname buffer. Holds the name to resolve, such as
+    ///        "example.com".
+    /// - `1`: Address buffer. Receives the 16 byte IPv6 address of the name,
+    ///        in network byte order.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.hostname = slice,
+                    1 => app.address = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Callback for when a lookup completes. The arguments are the
+    ///        result and, on success, how many seconds the address stays
+    ///        valid. The result is SUCCESS if the address was written to the
+    ///        address buffer, FAIL if the name does not exist or has no IPv6
+    ///        address, ENOACK if the server did not answer and any other
+    ///        error if the query could not be sent.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// DNS control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Resolve the name in the first `arg1` bytes of the hostname
+    ///        buffer. The process is called back once the lookup completes,
+    ///        right away if the address is cached. Returns EBUSY if this
+    ///        process already has a lookup outstanding, EINVAL if the name is
+    ///        invalid or the address buffer is shorter than 16 bytes and ESIZE
+    ///        if the name is longer than `MAX_HOSTNAME_LEN`.
+    /// - `2`: Cancel the lookup of this process. Returns EALREADY if there is
+    ///        none.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.resolve(appid, arg1),
+            2 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if app.query.take().is_some() {
+                        ReturnCode::SUCCESS
+                    } else {
+                        ReturnCode::EALREADY
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPRecvClient for DnsDriver<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        if src_addr != self.server.get() || src_port != DNS_PORT {
+            return;
+        }
+        self.apps.each(|app| {
+            let query = match app.query {
+                Some(query) if query.transmissions > 0 => query,
+                _ => return,
+            };
+            let name = &query.name[..query.name_len];
+            match dns::decode_response(payload, query.id, name).done() {
+                Some((_, Answer::Address { addr, ttl })) => {
+                    app.query = None;
+                    Self::resolved(app, addr, cmp::min(ttl, MAX_TTL));
+                    self.insert_cache(name, addr, ttl);
+                }
+                Some((_, Answer::NoAddress)) => {
+                    app.query = None;
+                    app.callback
+                        .map(|mut cb| cb.schedule(ReturnCode::FAIL.into(), 0, 0));
+                }
+                // The server may answer a retransmission.
+                Some((_, Answer::ServerFailure)) | None => {}
+            }
+        });
+        self.reschedule();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPSendClient for DnsDriver<'a, A> {
+    fn send_done(&self, _result: ReturnCode, mut dgram: LeasableBuffer<'static, u8>) {
+        // Lost queries are retransmitted, so the result is not reported.
+        dgram.reset();
+        self.tx_buf.replace(dgram);
+        self.send_queued();
+        self.reschedule();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for DnsDriver<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        self.apps.each(|app| {
+            let mut query = match app.query {
+                Some(query) if query.state == QueryState::AwaitingResponse => query,
+                _ => return,
+            };
+            if !is_due(query.deadline, now) {
+                return;
+            }
+            if query.transmissions < MAX_TRANSMISSIONS {
+                query.timeout = cmp::min(query.timeout.saturating_mul(2), i32::max_value() as u32);
+                query.state = QueryState::Queued;
+                app.query = Some(query);
+            } else {
+                app.query = None;
+                app.callback
+                    .map(|mut cb| cb.schedule(ReturnCode::ENOACK.into(), 0, 0));
+            }
+        });
+        self.evict_expired();
+        self.send_queued();
+        self.reschedule();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::TX_BUF_LEN;
+    use crate::net::dns::dns::DNS_PORT;
+    use crate::net::ipv6::ip_utils::IPAddr;
+    use crate::net::loopback::{
+        any_network_capability, link_local_addr, LoopbackUdpSender, NoUserPorts,
+    };
+    use crate::net::network_capabilities::UdpVisibilityCapability;
+    use crate::net::udp::udp_port_table::{UdpPortManager, MAX_NUM_BOUND_PORTS};
+    use crate::net::udp::udp_recv::{UDPReceiver, UDPRecvClient};
+    use crate::net::udp::udp_send::UDPSender;
+    use host::chip::HostChip;
+    use host::harness::{
+        create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp,
+        TestCapabilities, TestPlatform,
+    };
+    use host::mock::alarm::MockAlarm;
+    use host::syscall::{HostApp, Resumption};
+    use kernel::common::leasable_buffer::LeasableBuffer;
+    use kernel::hil::time::{Alarm, Time};
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::ReturnCode;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Answers a DNS `query` with `rcode` and, if given, one address record.
+    fn dns_response(query: &[u8], rcode: u8, answer: Option<(IPAddr, u32)>) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2] |= 0x80;
+        response[3] = 0x80 | rcode;
+        if let Some((addr, ttl)) = answer {
+            response[7] = 1;
+            // The name points to the question.
+            response.extend_from_slice(&[0xc0, 12, 0, 28, 0, 1]);
+            response.extend_from_slice(&ttl.to_be_bytes());
+            response.extend_from_slice(&[0, 16]);
+            response.extend_from_slice(&addr.0);
+        }
+        response
+    }
+
+    #[test]
+    pub fn dns_resolver_caches_retries_and_fails() {
+        let (kernel, processes) = create_kernel();
+        let addr = link_local_addr(1);
+        let mut server = IPAddr([0; 16]);
+        server.0[..2].copy_from_slice(&[0x20, 0x01]);
+        server.0[15] = 0x53;
+        let mut resolved = server;
+        resolved.0[15] = 0x80;
+
+        let port_table = leak(UdpPortManager::new(
+            &TestCapabilities,
+            Box::leak(Box::new([None; MAX_NUM_BOUND_PORTS])),
+            leak(UdpVisibilityCapability::new(&TestCapabilities)),
+        ));
+        port_table.set_user_ports(leak(NoUserPorts), &TestCapabilities);
+        let sender = leak(LoopbackUdpSender::new(addr));
+        let receiver = leak(UDPReceiver::new());
+        let alarm: &MockAlarm = leak(MockAlarm::new());
+        let dns = leak(crate::net::dns::DnsDriver::new(
+            sender,
+            receiver,
+            port_table,
+            alarm,
+            create_grant(kernel),
+            LeasableBuffer::new(Box::leak(Box::new([0; TX_BUF_LEN]))),
+            server,
+            any_network_capability(),
+        ));
+        sender.set_client(dns);
+        receiver.set_client(dns);
+        alarm.set_client(dns);
+        assert_eq!(dns.bind(49153), ReturnCode::SUCCESS);
+
+        let dns_command = |command_num, arg0| Syscall::COMMAND {
+            driver_number: crate::net::dns::DRIVER_NUM,
+            subdriver_number: command_num,
+            arg0: arg0,
+            arg1: 0,
+        };
+        let mut memory = vec![0; 48];
+        memory[..15].copy_from_slice(b"api.example.com");
+        let app = leak(ScriptedApp::new_with_memory(
+            vec![
+                Syscall::ALLOW {
+                    driver_number: crate::net::dns::DRIVER_NUM,
+                    subdriver_number: 0,
+                    allow_address: 0 as *mut u8,
+                    allow_size: 16,
+                },
+                Syscall::ALLOW {
+                    driver_number: crate::net::dns::DRIVER_NUM,
+                    subdriver_number: 1,
+                    allow_address: 32 as *mut u8,
+                    allow_size: 16,
+                },
+                Syscall::SUBSCRIBE {
+                    driver_number: crate::net::dns::DRIVER_NUM,
+                    subdriver_number: 0,
+                    callback_ptr: 0x1000 as *mut (),
+                    appdata: 0,
+                },
+                dns_command(1, 16),
+                dns_command(1, 15),
+                dns_command(1, 15),
+                Syscall::YIELD,
+                // Answered from the cache.
+                dns_command(1, 15),
+                Syscall::YIELD,
+                dns_command(1, 3),
+                Syscall::YIELD,
+                // The cached address expired.
+                dns_command(1, 15),
+            ],
+            Box::leak(memory.into_boxed_slice()),
+        ));
+        let apps: &'static [&'static dyn HostApp] = leak([app as &dyn HostApp]);
+        let chip = leak(HostChip::new(apps, None));
+        load_apps(kernel, chip, processes, &[("dns", 0)], FaultResponse::Stop);
+        let platform = TestPlatform::new().driver(crate::net::dns::DRIVER_NUM, dns);
+        let callback_args = || -> Vec<(usize, usize)> {
+            app.callbacks
+                .borrow()
+                .iter()
+                .map(|callback| match *callback {
+                    Resumption::Callback {
+                        argument0,
+                        argument1,
+                        ..
+                    } => (argument0, argument1),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        // The name with its trailing zero byte is invalid.
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            *app.returns.borrow(),
+            [
+                0,
+                0,
+                0,
+                isize::from(ReturnCode::EINVAL),
+                0,
+                isize::from(ReturnCode::EBUSY)
+            ]
+        );
+        let (dst, query) = sender.transfer(dns, false).unwrap();
+        assert_eq!(dst, server);
+        assert_eq!(query[2..6], [0x01, 0x00, 0, 1]);
+        assert_eq!(query[12..16], [3, b'a', b'p', b'i']);
+
+        // Responses that are not from the server, or are for another query, are
+        // ignored.
+        let response = dns_response(&query, 0, Some((resolved, 10)));
+        dns.receive(link_local_addr(2), addr, DNS_PORT, 49153, &response);
+        let mut other = response.clone();
+        other[0] ^= 0xff;
+        dns.receive(server, addr, DNS_PORT, 49153, &other);
+        run_until_idle(kernel, &platform, chip);
+        assert!(app.callbacks.borrow().is_empty());
+
+        // The address is delivered, then answered from the cache.
+        dns.receive(server, addr, DNS_PORT, 49153, &response);
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(callback_args(), [(0, 10), (0, 10)]);
+        let address = app.memory(32, 16);
+        assert_eq!(address, resolved.0);
+
+        // The query for "api" is retransmitted three times, then given up.
+        let (_, first) = sender.transfer(dns, true).unwrap();
+        assert_eq!(first[12..17], [3, b'a', b'p', b'i', 0]);
+        for _ in 0..3 {
+            assert!(alarm.advance_to_alarm());
+            run_until_idle(kernel, &platform, chip);
+            let (_, retransmission) = sender.transfer(dns, true).unwrap();
+            assert_eq!(retransmission, first);
+        }
+        // The cached address expires after 10 seconds, on its own alarm, so
+        // that it is evicted before its expiry time could wrap around.
+        assert!(alarm.advance_to_alarm());
+        assert_eq!(alarm.now(), 10 * 32768);
+        assert!(dns.cache.iter().all(|slot| slot.get().is_none()));
+        assert!(alarm.advance_to_alarm());
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(callback_args()[2], (usize::from(ReturnCode::ENOACK), 0));
+
+        // After 15 seconds of retries, the name is looked up again. This time it
+        // does not exist.
+        let (_, query) = sender.transfer(dns, false).unwrap();
+        assert_eq!(query[12..16], [3, b'a', b'p', b'i']);
+        assert_eq!(query[16], 7);
+        dns.receive(
+            server,
+            addr,
+            DNS_PORT,
+            49153,
+            &dns_response(&query, 3, None),
+        );
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(callback_args()[3], (usize::from(ReturnCode::FAIL), 0));
+        assert!(sender.outbox.borrow().is_empty());
+    }
+}
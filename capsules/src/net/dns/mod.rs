@@ -0,0 +1,5 @@
+pub mod dns;
+pub mod driver;
+
+pub use self::driver::DnsDriver;
+pub use self::driver::DRIVER_NUM;
@@ -37,9 +37,12 @@ impl Bitmap {
     // must be in 8-byte groups), and thus we can store 8*8 = 64 "bytes" per
     // byte in the bitmap.
     pub fn set_bits(&mut self, start_idx: usize, end_idx: usize) -> bool {
-        if start_idx > end_idx {
+        if start_idx > end_idx || end_idx > BITMAP_SIZE * 8 {
             return false;
         }
+        if start_idx == end_idx {
+            return true;
+        }
         let start_byte_idx = start_idx / 8;
         let end_byte_idx = end_idx / 8;
         let first = 0xff << (start_idx % 8);
@@ -54,9 +57,12 @@ impl Bitmap {
             result
         } else {
             let mut result = (self.map[start_byte_idx] & first) == 0;
-            result = result && ((self.map[end_byte_idx] & second) == 0);
             self.map[start_byte_idx] |= first;
-            self.map[end_byte_idx] |= second;
+            // The end byte may be past the map if end_idx is at its end.
+            if second != 0 {
+                result = result && ((self.map[end_byte_idx] & second) == 0);
+                self.map[end_byte_idx] |= second;
+            }
             // Set all bytes between start and end bytes.
             for i in start_byte_idx + 1..end_byte_idx {
                 result = result && (self.map[i] == 0);
@@ -67,13 +73,23 @@ impl Bitmap {
     }
 
     pub fn is_complete(&self, total_length: usize) -> bool {
+        if total_length > BITMAP_SIZE * 8 {
+            return false;
+        }
         let mut result = true;
         for i in 0..total_length / 8 {
             result = result && (self.map[i] == 0xff);
         }
         // Check last byte.
-        let mask = 0xff >> (8 - (total_length % 8));
-        result = result && (self.map[total_length / 8] == mask);
+        let mask = if total_length % 8 == 0 {
+            0
+        } else {
+            0xff >> (8 - (total_length % 8))
+        };
         result
+            && self
+                .map
+                .get(total_length / 8)
+                .map_or(mask == 0, |&b| b == mask)
     }
 }
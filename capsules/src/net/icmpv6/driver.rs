@@ -0,0 +1,413 @@
+//! ICMPv6 echo (ping) userspace interface.
+//!
+//! A process shares the IPv6 address to ping and asks the driver to send an
+//! echo request carrying a given amount of data. The process is called back
+//! with the round trip time once the matching echo reply arrives, or with an
+//! error if no reply arrives before the timeout.
+//!
+//! Each process can have one request outstanding at a time. Requests carry
+//! the process's identifier and a sequence number that is incremented for
+//! every request, so replies to other processes and late replies to earlier
+//! requests are ignored.
+
+use crate::net::icmpv6::icmpv6_echo::{ICMP6Echo, ICMP6EchoClient};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use core::mem;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Frequency};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Ping as usize;
+
+/// How long to wait for a reply when the process does not say.
+pub const DEFAULT_TIMEOUT_MS: usize = 1000;
+
+#[derive(Copy, Clone)]
+struct Request {
+    dst: IPAddr,
+    seqno: u16,
+    sent_at: u32,
+    deadline: u32,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    dst: Option<AppSlice<Shared, u8>>,
+    seqno: u16,
+    request: Option<Request>,
+}
+
+pub struct PingDriver<'a, A: time::Alarm<'a>> {
+    echo: &'a ICMP6Echo<'a>,
+    alarm: &'a A,
+    apps: Grant<App>,
+    /// The process whose request is being sent.
+    current: OptionalCell<AppId>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> PingDriver<'a, A> {
+    pub fn new(
+        echo: &'a ICMP6Echo<'a>,
+        alarm: &'a A,
+        grant: Grant<App>,
+        net_cap: &'static NetworkCapability,
+    ) -> PingDriver<'a, A> {
+        PingDriver {
+            echo: echo,
+            alarm: alarm,
+            apps: grant,
+            current: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Requests are identified by the process that sent them.
+    fn identifier(appid: AppId) -> u16 {
+        appid.id() as u16
+    }
+
+    fn tics(ms: usize) -> u32 {
+        (ms as u64 * <A::Frequency>::frequency() as u64 / 1000) as u32
+    }
+
+    fn ms(tics: u32) -> usize {
+        (tics as u64 * 1000 / <A::Frequency>::frequency() as u64) as usize
+    }
+
+    fn ping(&self, appid: AppId, len: usize, timeout_ms: usize) -> ReturnCode {
+        if len > self.echo.max_data_len() {
+            return ReturnCode::ESIZE;
+        }
+        let timeout_ms = if timeout_ms == 0 {
+            DEFAULT_TIMEOUT_MS
+        } else {
+            timeout_ms
+        };
+        let request = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.request.is_some() {
+                    return Err(ReturnCode::EBUSY);
+                }
+                let dst = app
+                    .dst
+                    .as_ref()
+                    .filter(|dst| dst.len() == mem::size_of::<IPAddr>())
+                    .map(|dst| {
+                        let mut addr = IPAddr::new();
+                        addr.0.copy_from_slice(dst.as_ref());
+                        addr
+                    })
+                    .ok_or(ReturnCode::EINVAL)?;
+                let now = self.alarm.now();
+                let request = Request {
+                    dst: dst,
+                    seqno: app.seqno.wrapping_add(1),
+                    sent_at: now,
+                    deadline: now.wrapping_add(Self::tics(timeout_ms)),
+                };
+                app.seqno = request.seqno;
+                app.request = Some(request);
+                Ok(request)
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        let request = match request {
+            Ok(request) => request,
+            Err(err) => return err,
+        };
+
+        // The request is recorded first, as the sender may report that it was
+        // sent before returning.
+        self.current.set(appid);
+        let result = self.echo.send_request(
+            request.dst,
+            Self::identifier(appid),
+            request.seqno,
+            len,
+            self.net_cap,
+        );
+        if result != ReturnCode::SUCCESS {
+            self.current.clear();
+            let _ = self.apps.enter(appid, |app, _| app.request = None);
+            return result;
+        }
+        self.reschedule();
+        ReturnCode::SuccessWithValue {
+            value: request.seqno as usize,
+        }
+    }
+
+    /// Sets the alarm for the earliest timeout of any process.
+    fn reschedule(&self) {
+        let now = self.alarm.now();
+        let remaining = self
+            .apps
+            .iter()
+            .filter_map(|app| app.enter(|app, _| app.request.map(|request| request.deadline)))
+            .map(|deadline| {
+                let remaining = deadline.wrapping_sub(now);
+                if remaining as i32 > 0 {
+                    remaining
+                } else {
+                    1
+                }
+            })
+            .min();
+        match remaining {
+            Some(remaining) => self.alarm.set_alarm(now.wrapping_add(remaining)),
+            None => self.alarm.disable(),
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Driver for PingDriver<'a, A> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Destination buffer. Holds the 16 byte IPv6 address to ping.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.dst = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Callback for when a request completes. The arguments are the
+    ///        result, the sequence number of the request and the round trip
+    ///        time in milliseconds. The result is SUCCESS once the reply
+    ///        arrives, ENOACK if the request timed out and any other error if
+    ///        the request could not be sent.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Ping control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send an echo request to the address in the destination buffer,
+    ///        carrying `arg1` bytes of data, and wait `arg2` milliseconds for
+    ///        the reply, or `DEFAULT_TIMEOUT_MS` if `arg2` is 0. Returns the
+    ///        sequence number of the request. Returns EBUSY if this process
+    ///        already has a request outstanding or another packet is being
+    ///        sent, EINVAL if the destination buffer is not 16 bytes long and
+    ///        ESIZE if `arg1` is larger than the maximum.
+    /// - `2`: Returns the maximum amount of data a request can carry.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.ping(appid, arg1, arg2),
+            2 => ReturnCode::SuccessWithValue {
+                value: self.echo.max_data_len(),
+            },
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6EchoClient for PingDriver<'a, A> {
+    fn request_sent(&self, result: ReturnCode) {
+        self.current.take().map(|appid| {
+            if result == ReturnCode::SUCCESS {
+                return;
+            }
+            let _ = self.apps.enter(appid, |app, _| {
+                app.request.take().map(|request| {
+                    app.callback
+                        .map(|mut cb| cb.schedule(result.into(), request.seqno as usize, 0));
+                });
+            });
+            self.reschedule();
+        });
+    }
+
+    fn reply_received(&self, src: IPAddr, id: u16, seqno: u16, _data: &[u8]) {
+        let now = self.alarm.now();
+        self.apps.each(|app| {
+            if Self::identifier(app.appid()) != id {
+                return;
+            }
+            match app.request {
+                Some(request) if request.seqno == seqno && request.dst == src => {
+                    app.request = None;
+                    let rtt = Self::ms(now.wrapping_sub(request.sent_at));
+                    app.callback
+                        .map(|mut cb| cb.schedule(ReturnCode::SUCCESS.into(), seqno as usize, rtt));
+                }
+                _ => {}
+            }
+        });
+        self.reschedule();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for PingDriver<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        self.apps.each(|app| match app.request {
+            Some(request) if request.deadline.wrapping_sub(now) as i32 <= 0 => {
+                app.request = None;
+                app.callback.map(|mut cb| {
+                    cb.schedule(ReturnCode::ENOACK.into(), request.seqno as usize, 0)
+                });
+            }
+            _ => {}
+        });
+        self.reschedule();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::net::loopback::{any_network_capability, echo_host, link_local_addr};
+    use host::chip::HostChip;
+    use host::harness::{
+        create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp, TestPlatform,
+    };
+    use host::mock::alarm::MockAlarm;
+    use host::syscall::{HostApp, Resumption};
+    use kernel::hil::time::Alarm;
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::ReturnCode;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    pub fn ping_driver_reports_round_trips_and_timeouts() {
+        let (kernel, processes) = create_kernel();
+        let (a_addr, b_addr) = (link_local_addr(1), link_local_addr(2));
+        let (a_sender, a_receiver, a_echo) = echo_host(a_addr);
+        let (b_sender, b_receiver, b_echo) = echo_host(b_addr);
+        b_echo.set_responder_enabled(true);
+
+        let alarm: &MockAlarm = leak(MockAlarm::new());
+        let ping = leak(crate::net::icmpv6::PingDriver::new(
+            a_echo,
+            alarm,
+            create_grant(kernel),
+            any_network_capability(),
+        ));
+        a_echo.set_client(ping);
+        alarm.set_client(ping);
+
+        let ping_command = |command_num, arg0, arg1| Syscall::COMMAND {
+            driver_number: crate::net::icmpv6::DRIVER_NUM,
+            subdriver_number: command_num,
+            arg0: arg0,
+            arg1: arg1,
+        };
+        let app = leak(ScriptedApp::new_with_memory(
+            vec![
+                Syscall::ALLOW {
+                    driver_number: crate::net::icmpv6::DRIVER_NUM,
+                    subdriver_number: 0,
+                    allow_address: 0 as *mut u8,
+                    allow_size: 16,
+                },
+                Syscall::SUBSCRIBE {
+                    driver_number: crate::net::icmpv6::DRIVER_NUM,
+                    subdriver_number: 0,
+                    callback_ptr: 0x1000 as *mut (),
+                    appdata: 0,
+                },
+                ping_command(2, 0, 0),
+                ping_command(1, 33, 0),
+                ping_command(1, 8, 0),
+                ping_command(1, 8, 0),
+                Syscall::YIELD,
+                ping_command(1, 8, 500),
+            ],
+            leak(b_addr.0),
+        ));
+        let apps: &'static [&'static dyn HostApp] = leak([app as &dyn HostApp]);
+        let chip = leak(HostChip::new(apps, None));
+        load_apps(kernel, chip, processes, &[("ping", 0)], FaultResponse::Stop);
+        let platform = TestPlatform::new().driver(crate::net::icmpv6::DRIVER_NUM, ping);
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            *app.returns.borrow(),
+            [
+                0,
+                0,
+                32,
+                isize::from(ReturnCode::ESIZE),
+                1,
+                isize::from(ReturnCode::EBUSY),
+            ]
+        );
+
+        // The reply arrives 100ms later.
+        assert!(!alarm.advance(3277));
+        assert!(a_sender.transfer(b_receiver, false));
+        assert!(b_sender.transfer(a_receiver, false));
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(app.returns.borrow().last(), Some(&2));
+
+        // The second request is lost and times out after 500ms.
+        assert!(a_sender.transfer(b_receiver, true));
+        assert!(!alarm.advance(3277));
+        assert!(alarm.advance(13107));
+        run_until_idle(kernel, &platform, chip);
+
+        let callbacks: Vec<(usize, usize, usize, usize)> = app
+            .callbacks
+            .borrow()
+            .iter()
+            .map(|callback| match *callback {
+                Resumption::Callback {
+                    pc,
+                    argument0,
+                    argument1,
+                    argument2,
+                    ..
+                } => (pc, argument0, argument1, argument2),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            callbacks,
+            [
+                (0x1000, 0, 1, 100),
+                (0x1000, usize::from(ReturnCode::ENOACK), 2, 0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! This file contains an in-kernel implementation of ICMPv6 echo (RFC 4443,
+//! section 4), the protocol behind ping.
+//!
+//! `ICMP6Echo` receives packets as a client of an `IP6Receiver`. When its
+//! responder is enabled, it answers every echo request with an echo reply
+//! carrying the same identifier, sequence number and data. It also sends echo
+//! requests on behalf of an [ICMP6EchoClient](trait.ICMP6EchoClient.html),
+//! such as the userspace [ping driver](../driver/index.html), and passes it the
+//! echo replies that arrive.
+//!
+//! As the client of the receiver that sends ICMPv6 messages, it also sends the
+//! Parameter Problem errors (RFC 4443, section 3.4) the receiver asks for when
+//! it discards a packet because of its extension headers. An error carries as
+//! much of the discarded packet as fits in the transmit buffer.
+//!
+//! Packets are sent one at a time through an `IP6Sender` dedicated to ICMPv6.
+//! Like ICMP itself, the responder is best effort: an echo request that
+//! arrives while a packet is being sent, or whose data does not fit in the
+//! transmit buffer, is not answered, and errors are not sent while a packet
+//! is being sent.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let icmp_echo = static_init!(
+//!     ICMP6Echo<'static>,
+//!     ICMP6Echo::new(icmp_ip_send, &mut ICMP_ECHO_BUF, net_cap)
+//! );
+//! icmp_ip_send.set_client(icmp_echo);
+//! ip_receive.add_client(icmp_echo);
+//! icmp_echo.set_responder_enabled(true);
+//! ```
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::network_capabilities::NetworkCapability;
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::ReturnCode;
+
+/// The client that sends echo requests through an `ICMP6Echo`.
+pub trait ICMP6EchoClient {
+    /// Called once an echo request passed to `send_request` has been sent,
+    /// with the result reported by the `IP6Sender`.
+    fn request_sent(&self, result: ReturnCode);
+
+    /// Called for every echo reply received, whether or not it answers a
+    /// request of this client.
+    fn reply_received(&self, src: IPAddr, id: u16, seqno: u16, data: &[u8]);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Sending {
+    Nothing,
+    Request,
+    Reply,
+    Error,
+}
+
+pub struct ICMP6Echo<'a> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    tx_buf: TakeCell<'static, [u8]>,
+    sending: Cell<Sending>,
+    responder_enabled: Cell<bool>,
+    client: OptionalCell<&'a dyn ICMP6EchoClient>,
+    /// Capability used to send echo replies.
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a> ICMP6Echo<'a> {
+    /// The length of `tx_buf` limits the data of both the requests sent and
+    /// the requests answered.
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        tx_buf: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> ICMP6Echo<'a> {
+        ICMP6Echo {
+            ip_sender: ip_sender,
+            tx_buf: TakeCell::new(tx_buf),
+            sending: Cell::new(Sending::Nothing),
+            responder_enabled: Cell::new(false),
+            client: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ICMP6EchoClient) {
+        self.client.set(client);
+    }
+
+    /// Sets whether echo requests are answered. The responder is disabled
+    /// until a board enables it.
+    pub fn set_responder_enabled(&self, enabled: bool) {
+        self.responder_enabled.set(enabled);
+    }
+
+    /// The largest amount of data an echo request can carry.
+    pub fn max_data_len(&self) -> usize {
+        self.tx_buf.map_or(0, |buf| buf.len())
+    }
+
+    /// Sends an echo request to `dst` carrying `len` bytes of data. Returns
+    /// EBUSY if a packet is being sent and ESIZE if `len` is larger than
+    /// `max_data_len()`. On success, the client's `request_sent` is called
+    /// once the request has been sent.
+    pub fn send_request(
+        &self,
+        dst: IPAddr,
+        id: u16,
+        seqno: u16,
+        len: usize,
+        net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        let options = ICMP6HeaderOptions::Type128 { id, seqno };
+        self.send(dst, options, 0, Sending::Request, len, net_cap, |data| {
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+        })
+    }
+
+    fn send<F: FnOnce(&mut [u8])>(
+        &self,
+        dst: IPAddr,
+        options: ICMP6HeaderOptions,
+        code: u8,
+        kind: Sending,
+        len: usize,
+        net_cap: &'static NetworkCapability,
+        fill: F,
+    ) -> ReturnCode {
+        if self.sending.get() != Sending::Nothing {
+            return ReturnCode::EBUSY;
+        }
+        let buf = match self.tx_buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        if len > buf.len() {
+            self.tx_buf.replace(buf);
+            return ReturnCode::ESIZE;
+        }
+        fill(&mut buf[..len]);
+
+        let mut header = ICMP6Header::new(ICMP6Type::Type128);
+        header.set_options(options);
+        header.set_code(code);
+        let mut payload = LeasableBuffer::new(buf);
+        payload.slice(..len);
+        // The sender may complete synchronously, so this is set first.
+        self.sending.set(kind);
+        let result = self
+            .ip_sender
+            .send_to(dst, TransportHeader::ICMP(header), &payload, net_cap);
+        self.tx_buf.replace(payload.take());
+        if result != ReturnCode::SUCCESS {
+            self.sending.set(Sending::Nothing);
+        }
+        result
+    }
+}
+
+impl<'a> IP6RecvClient for ICMP6Echo<'a> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        let (offset, header) = match ICMP6Header::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let src = ip_header.get_src_addr();
+        let data = &payload[offset..];
+        match header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                if self.responder_enabled.get() {
+                    let options = ICMP6HeaderOptions::Type129 { id, seqno };
+                    self.send(
+                        src,
+                        options,
+                        0,
+                        Sending::Reply,
+                        data.len(),
+                        self.net_cap,
+                        |buf| buf.copy_from_slice(data),
+                    );
+                }
+            }
+            ICMP6HeaderOptions::Type129 { id, seqno } => {
+                self.client
+                    .map(|client| client.reply_received(src, id, seqno, data));
+            }
+            _ => {}
+        }
+    }
+
+    fn parameter_problem(&self, header: IP6Header, packet: &[u8], code: u8, pointer: u32) {
+        // No errors are sent to sources that do not identify a single node
+        // (RFC 4443, section 2.4).
+        let src = header.get_src_addr();
+        if src.is_unspecified() || src.is_multicast() {
+            return;
+        }
+        let len = cmp::min(packet.len(), self.max_data_len());
+        let options = ICMP6HeaderOptions::Type4 { pointer };
+        self.send(
+            src,
+            options,
+            code,
+            Sending::Error,
+            len,
+            self.net_cap,
+            |buf| buf.copy_from_slice(&packet[..len]),
+        );
+    }
+}
+
+impl<'a> IP6SendClient for ICMP6Echo<'a> {
+    fn send_done(&self, result: ReturnCode) {
+        let sent = self.sending.replace(Sending::Nothing);
+        if sent == Sending::Request {
+            self.client.map(|client| client.request_sent(result));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::net::loopback::{any_network_capability, echo_host, link_local_addr, EchoReplies};
+    use host::harness::leak;
+    use kernel::ReturnCode;
+    use std::vec;
+
+    #[test]
+    pub fn icmp_echo_requests_are_answered_when_enabled() {
+        let net_cap = any_network_capability();
+        let (a_addr, b_addr) = (link_local_addr(1), link_local_addr(2));
+        let (a_sender, a_receiver, a_echo) = echo_host(a_addr);
+        let (b_sender, b_receiver, b_echo) = echo_host(b_addr);
+        let a_replies = leak(EchoReplies::default());
+        a_echo.set_client(a_replies);
+        b_echo.set_responder_enabled(true);
+
+        // An odd amount of data, so the checksum covers a padding byte.
+        assert_eq!(
+            a_echo.send_request(b_addr, 7, 1, 5, net_cap),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(
+            a_echo.send_request(b_addr, 7, 2, 5, net_cap),
+            ReturnCode::EBUSY
+        );
+        assert!(a_sender.transfer(b_receiver, false));
+        assert!(b_sender.transfer(a_receiver, false));
+        assert_eq!(
+            *a_replies.replies.borrow(),
+            [(b_addr, 7, 1, vec![0, 1, 2, 3, 4])]
+        );
+        assert_eq!(
+            a_echo.send_request(b_addr, 7, 2, 33, net_cap),
+            ReturnCode::ESIZE
+        );
+
+        // The responder is disabled by default.
+        assert_eq!(
+            b_echo.send_request(a_addr, 9, 1, 4, net_cap),
+            ReturnCode::SUCCESS
+        );
+        assert!(b_sender.transfer(a_receiver, false));
+        assert!(!a_sender.transfer(b_receiver, false));
+        assert_eq!(a_replies.replies.borrow().len(), 1);
+    }
+}
@@ -1,2 +1,7 @@
+pub mod driver;
 pub mod icmpv6;
+pub mod icmpv6_echo;
 pub mod icmpv6_send;
+
+pub use self::driver::PingDriver;
+pub use self::driver::DRIVER_NUM;
@@ -0,0 +1,606 @@
+//! This file contains the parsing and generation of IPv6 extension headers
+//! (RFC 8200, section 4): Hop-by-Hop Options, Routing, Fragment and
+//! Destination Options headers.
+//!
+//! On receive, `IP6RecvStruct` walks the chain of extension headers with
+//! [parse](fn.parse.html) to find the upper-layer header. Options this stack
+//! does not recognize are handled as their type requires: skipped, or the
+//! packet is discarded, possibly with an ICMPv6 Parameter Problem message to
+//! its source, which `ICMP6Echo` sends. A packet that still has segments left to visit is
+//! discarded unless its Routing header is an RPL source route (RFC 6554),
+//! which a router forwards. Fragments other than atomic ones are discarded,
+//! since fragmented IPv6 packets are not reassembled. Malformed headers are
+//! discarded as well.
+//!
+//! On send, an [ExtHeaders](struct.ExtHeaders.html) holds the serialized
+//! extension headers of an `IP6Packet`, which are encoded between the IPv6
+//! header and the transport header. A forwarded packet keeps the extension
+//! headers it was received with, copied by `ExtHeaders::decode`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mut ext_headers = ExtHeaders::new();
+//! ext_headers.add_hop_by_hop(&[0x3e, 0x02, 0x00, 0x00]);
+//! ip_send.set_ext_headers(ext_headers);
+//! ```
+
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::stream::encode_bytes;
+use crate::net::stream::SResult;
+use kernel::ReturnCode;
+
+/// The most bytes of extension headers an `IP6Packet` can carry.
+pub const MAX_EXT_HDRS_LEN: usize = 40;
+
+/// Option types of the Hop-by-Hop and Destination Options headers.
+pub mod ip6_opt {
+    pub const PAD1: u8 = 0;
+    pub const PADN: u8 = 1;
+    /// The RPL Option (RFC 6553), which is recognized but not checked.
+    pub const RPL: u8 = 0x63;
+}
+
+/// The Routing header type of an RPL source route (RFC 6554).
+pub const SOURCE_ROUTE: u8 = 3;
+
+/// Codes of the ICMPv6 Parameter Problem message (RFC 4443, section 3.4).
+pub mod param_problem {
+    pub const ERRONEOUS_HEADER_FIELD: u8 = 0;
+    pub const UNRECOGNIZED_NEXT_HEADER: u8 = 1;
+    pub const UNRECOGNIZED_OPTION: u8 = 2;
+}
+
+/// Why a packet was discarded while its extension headers were parsed.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ExtHeaderError {
+    /// The packet is silently discarded.
+    Drop,
+    /// The packet is discarded and the sender should be sent an ICMPv6
+    /// Parameter Problem message with `code`, where `pointer` is the offset
+    /// of the offending byte from the start of the IPv6 header.
+    ParameterProblem { code: u8, pointer: u32 },
+    /// The packet has an RPL source route with segments left, starting
+    /// `offset` bytes after the IPv6 header, and is forwarded if this node is
+    /// a router.
+    SourceRoute { offset: usize },
+}
+
+const IP6_HDR_LEN: usize = 40;
+const FRAGMENT_HDR_LEN: usize = 8;
+
+/// Walks the extension headers of a received packet, where `buf` holds
+/// everything after the IPv6 header. Returns the next header value of the
+/// upper-layer header and the offset of that header in `buf`.
+pub fn parse(next_header: u8, dst_addr: IPAddr, buf: &[u8]) -> Result<(u8, usize), ExtHeaderError> {
+    let mut next_header = next_header;
+    let mut offset = 0;
+    // The offset of the field holding `next_header`, from the start of the
+    // IPv6 header.
+    let mut next_header_field = 6;
+    loop {
+        match next_header {
+            ip6_nh::HOP_OPTS | ip6_nh::ROUTING | ip6_nh::DST_OPTS => {
+                if next_header == ip6_nh::HOP_OPTS && offset != 0 {
+                    // Hop-by-Hop Options may only follow the IPv6 header.
+                    return Err(ExtHeaderError::ParameterProblem {
+                        code: param_problem::UNRECOGNIZED_NEXT_HEADER,
+                        pointer: next_header_field,
+                    });
+                }
+                if buf.len() < offset + 2 {
+                    return Err(ExtHeaderError::Drop);
+                }
+                let len = (buf[offset + 1] as usize + 1) * 8;
+                if buf.len() < offset + len {
+                    return Err(ExtHeaderError::Drop);
+                }
+                let header = &buf[offset..offset + len];
+                if next_header == ip6_nh::ROUTING {
+                    // A host is the final destination of every packet it
+                    // accepts. Only routers implement a routing type.
+                    if header[3] != 0 && header[2] == SOURCE_ROUTE {
+                        return Err(ExtHeaderError::SourceRoute { offset: offset });
+                    } else if header[3] != 0 {
+                        return Err(ExtHeaderError::ParameterProblem {
+                            code: param_problem::ERRONEOUS_HEADER_FIELD,
+                            pointer: (IP6_HDR_LEN + offset + 2) as u32,
+                        });
+                    }
+                } else {
+                    check_options(header, IP6_HDR_LEN + offset, dst_addr)?;
+                }
+                next_header = header[0];
+                next_header_field = (IP6_HDR_LEN + offset) as u32;
+                offset += len;
+            }
+            ip6_nh::FRAGMENT => {
+                if buf.len() < offset + FRAGMENT_HDR_LEN {
+                    return Err(ExtHeaderError::Drop);
+                }
+                let header = &buf[offset..offset + FRAGMENT_HDR_LEN];
+                let offset_and_more = (header[2] as u16) << 8 | header[3] as u16;
+                // Only atomic fragments, which carry the whole packet, are
+                // accepted; fragments are not reassembled.
+                if offset_and_more & 0xfff9 != 0 {
+                    return Err(ExtHeaderError::Drop);
+                }
+                next_header = header[0];
+                next_header_field = (IP6_HDR_LEN + offset) as u32;
+                offset += FRAGMENT_HDR_LEN;
+            }
+            _ => return Ok((next_header, offset)),
+        }
+    }
+}
+
+/// Checks the options of a Hop-by-Hop or Destination Options `header`, which
+/// starts `header_offset` bytes into the packet. The top two bits of the type
+/// of an unrecognized option say how to handle it (RFC 8200, section 4.2).
+fn check_options(
+    header: &[u8],
+    header_offset: usize,
+    dst_addr: IPAddr,
+) -> Result<(), ExtHeaderError> {
+    let mut offset = 2;
+    while offset < header.len() {
+        let option_type = header[offset];
+        if option_type == ip6_opt::PAD1 {
+            offset += 1;
+            continue;
+        }
+        if header.len() < offset + 2 || header.len() < offset + 2 + header[offset + 1] as usize {
+            return Err(ExtHeaderError::Drop);
+        }
+        let unrecognized = ExtHeaderError::ParameterProblem {
+            code: param_problem::UNRECOGNIZED_OPTION,
+            pointer: (header_offset + offset) as u32,
+        };
+        match (option_type, option_type >> 6) {
+            (ip6_opt::PADN, _) | (ip6_opt::RPL, _) | (_, 0b00) => {}
+            (_, 0b01) => return Err(ExtHeaderError::Drop),
+            (_, 0b10) => return Err(unrecognized),
+            _ if dst_addr.is_multicast() => return Err(ExtHeaderError::Drop),
+            _ => return Err(unrecognized),
+        }
+        offset += 2 + header[offset + 1] as usize;
+    }
+    Ok(())
+}
+
+/// The serialized extension headers of a packet to send, in the order they
+/// were added. The Next Header field of the last one is filled in when the
+/// packet is encoded.
+#[derive(Copy, Clone)]
+pub struct ExtHeaders {
+    buf: [u8; MAX_EXT_HDRS_LEN],
+    len: usize,
+    first: Option<u8>,
+    /// The offset of the Next Header field of the last header.
+    last: usize,
+}
+
+impl ExtHeaders {
+    pub fn new() -> ExtHeaders {
+        ExtHeaders {
+            buf: [0; MAX_EXT_HDRS_LEN],
+            len: 0,
+            first: None,
+            last: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The type of the first header, which the IPv6 header points to, or
+    /// None if there are no headers.
+    pub fn next_header(&self) -> Option<u8> {
+        self.first
+    }
+
+    /// Adds a Hop-by-Hop Options header carrying the serialized `options`,
+    /// padded as needed. Returns EINVAL if other headers were already added,
+    /// since it must come first, and ESIZE if it does not fit.
+    pub fn add_hop_by_hop(&mut self, options: &[u8]) -> ReturnCode {
+        if !self.is_empty() {
+            return ReturnCode::EINVAL;
+        }
+        self.add_options(ip6_nh::HOP_OPTS, options)
+    }
+
+    /// Adds a Destination Options header carrying the serialized `options`,
+    /// padded as needed. Returns ESIZE if it does not fit.
+    pub fn add_dst_opts(&mut self, options: &[u8]) -> ReturnCode {
+        self.add_options(ip6_nh::DST_OPTS, options)
+    }
+
+    /// Adds a Routing header of `routing_type` with `segments_left` and the
+    /// type-specific `data`, padded with zeros to a multiple of 8 bytes.
+    /// Returns ESIZE if it does not fit.
+    pub fn add_routing(&mut self, routing_type: u8, segments_left: u8, data: &[u8]) -> ReturnCode {
+        let len = round_up(4 + data.len());
+        let offset = match self.push(ip6_nh::ROUTING, len) {
+            Some(offset) => offset,
+            None => return ReturnCode::ESIZE,
+        };
+        self.buf[offset + 2] = routing_type;
+        self.buf[offset + 3] = segments_left;
+        self.buf[offset + 4..offset + 4 + data.len()].copy_from_slice(data);
+        ReturnCode::SUCCESS
+    }
+
+    /// Adds the Fragment header of an atomic fragment, a packet that is not
+    /// fragmented, with `identification`. Returns ESIZE if it does not fit.
+    pub fn add_atomic_fragment(&mut self, identification: u32) -> ReturnCode {
+        let offset = match self.push(ip6_nh::FRAGMENT, FRAGMENT_HDR_LEN) {
+            Some(offset) => offset,
+            None => return ReturnCode::ESIZE,
+        };
+        self.buf[offset + 4..offset + 8].copy_from_slice(&identification.to_be_bytes());
+        ReturnCode::SUCCESS
+    }
+
+    /// Copies the extension headers at the start of `buf`, where the first
+    /// is of type `next_header`, to forward them. Returns the headers and the
+    /// type of the header that follows them. Fails if the headers are
+    /// truncated or do not fit in an `ExtHeaders`.
+    pub fn decode(next_header: u8, buf: &[u8]) -> SResult<(ExtHeaders, u8)> {
+        let mut ext_headers = ExtHeaders::new();
+        let mut next_header = next_header;
+        let mut offset = 0;
+        loop {
+            let len = match next_header {
+                ip6_nh::HOP_OPTS | ip6_nh::ROUTING | ip6_nh::DST_OPTS => {
+                    stream_len_cond!(buf, offset + 2);
+                    (buf[offset + 1] as usize + 1) * 8
+                }
+                ip6_nh::FRAGMENT => FRAGMENT_HDR_LEN,
+                _ => break,
+            };
+            stream_len_cond!(buf, offset + len);
+            let start = stream_from_option!(ext_headers.push(next_header, len));
+            ext_headers.buf[start..start + len].copy_from_slice(&buf[offset..offset + len]);
+            next_header = buf[offset];
+            offset += len;
+        }
+        stream_done!(offset, (ext_headers, next_header))
+    }
+
+    /// The serialized headers, for a router that updates them in place.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf[..self.len]
+    }
+
+    /// Encodes the headers into `buf` at `offset`, where `next_header` is the
+    /// type of the header that follows them.
+    pub fn encode(&self, buf: &mut [u8], offset: usize, next_header: u8) -> SResult<usize> {
+        let start = offset;
+        let offset = enc_consume!(buf, offset; encode_bytes, &self.buf[..self.len]);
+        if !self.is_empty() {
+            buf[start + self.last] = next_header;
+        }
+        stream_done!(offset, offset)
+    }
+
+    fn add_options(&mut self, header_type: u8, options: &[u8]) -> ReturnCode {
+        let len = round_up(2 + options.len());
+        let offset = match self.push(header_type, len) {
+            Some(offset) => offset,
+            None => return ReturnCode::ESIZE,
+        };
+        let end = offset + 2 + options.len();
+        self.buf[offset + 2..end].copy_from_slice(options);
+        match offset + len - end {
+            0 => {}
+            1 => self.buf[end] = ip6_opt::PAD1,
+            padding => {
+                self.buf[end] = ip6_opt::PADN;
+                self.buf[end + 1] = (padding - 2) as u8;
+            }
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Appends a zeroed header of `header_type` that is `len` bytes long,
+    /// a multiple of 8, and links it into the chain. Returns its offset, or
+    /// None if it does not fit.
+    fn push(&mut self, header_type: u8, len: usize) -> Option<usize> {
+        let offset = self.len;
+        if offset + len > MAX_EXT_HDRS_LEN {
+            return None;
+        }
+        for byte in self.buf[offset..offset + len].iter_mut() {
+            *byte = 0;
+        }
+        if header_type != ip6_nh::FRAGMENT {
+            self.buf[offset + 1] = (len / 8 - 1) as u8;
+        }
+        match self.first {
+            None => self.first = Some(header_type),
+            Some(_) => self.buf[self.last] = header_type,
+        }
+        self.last = offset;
+        self.len += len;
+        Some(offset)
+    }
+}
+
+fn round_up(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{ip6_opt, param_problem, parse, ExtHeaderError, ExtHeaders, MAX_EXT_HDRS_LEN};
+    use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+    use crate::net::loopback::{
+        any_network_capability, echo_host, link_local_addr, pending_icmp, EchoReplies,
+    };
+    use host::harness::leak;
+    use kernel::ReturnCode;
+
+    fn encode(ext_headers: &ExtHeaders, next_header: u8) -> [u8; MAX_EXT_HDRS_LEN] {
+        let mut buf = [0; MAX_EXT_HDRS_LEN];
+        let len = ext_headers.len();
+        assert_eq!(
+            ext_headers.encode(&mut buf, 0, next_header).done(),
+            Some((len, len))
+        );
+        buf
+    }
+
+    #[test]
+    pub fn generated_headers_parse_back() {
+        let unicast = IPAddr([0x20, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let mut ext_headers = ExtHeaders::new();
+        assert_eq!(ext_headers.next_header(), None);
+        assert_eq!(
+            ext_headers.add_dst_opts(&[0x1e, 0x01, 0xaa]),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(ext_headers.add_hop_by_hop(&[]), ReturnCode::EINVAL);
+        assert_eq!(
+            ext_headers.add_atomic_fragment(0x1234_5678),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(ext_headers.add_routing(3, 0, &[0; 20]), ReturnCode::SUCCESS);
+        assert_eq!(ext_headers.add_dst_opts(&[]), ReturnCode::ESIZE);
+        assert_eq!(ext_headers.len(), MAX_EXT_HDRS_LEN);
+        assert_eq!(ext_headers.next_header(), Some(ip6_nh::DST_OPTS));
+
+        let buf = encode(&ext_headers, ip6_nh::UDP);
+        // The option is followed by a PadN option of one byte.
+        assert_eq!(
+            &buf[..8],
+            &[ip6_nh::FRAGMENT, 0, 0x1e, 0x01, 0xaa, 0x01, 0x01, 0x00]
+        );
+        assert_eq!(
+            &buf[8..16],
+            &[ip6_nh::ROUTING, 0, 0, 0, 0x12, 0x34, 0x56, 0x78]
+        );
+        assert_eq!(&buf[16..20], &[ip6_nh::UDP, 2, 3, 0]);
+        assert_eq!(
+            parse(ip6_nh::DST_OPTS, unicast, &buf),
+            Ok((ip6_nh::UDP, MAX_EXT_HDRS_LEN))
+        );
+
+        let mut ext_headers = ExtHeaders::new();
+        assert_eq!(ext_headers.add_hop_by_hop(&[0x00]), ReturnCode::SUCCESS);
+        let buf = encode(&ext_headers, ip6_nh::ICMP);
+        assert_eq!(ext_headers.len(), 8);
+        assert_eq!(
+            &buf[..8],
+            &[ip6_nh::ICMP, 0, 0x00, 0x01, 0x03, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            parse(ip6_nh::HOP_OPTS, unicast, &buf[..8]),
+            Ok((ip6_nh::ICMP, 8))
+        );
+        assert_eq!(parse(ip6_nh::UDP, unicast, &buf[..8]), Ok((ip6_nh::UDP, 0)));
+    }
+
+    #[test]
+    pub fn decoded_headers_encode_unchanged() {
+        let mut ext_headers = ExtHeaders::new();
+        assert_eq!(
+            ext_headers.add_hop_by_hop(&[ip6_opt::RPL, 0x04, 0, 0, 0x01, 0x00]),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(ext_headers.add_routing(3, 2, &[0; 12]), ReturnCode::SUCCESS);
+        let buf = encode(&ext_headers, ip6_nh::ICMP);
+        let len = ext_headers.len();
+
+        let (offset, (decoded, next_header)) = ExtHeaders::decode(ip6_nh::HOP_OPTS, &buf[..len])
+            .done()
+            .unwrap();
+        assert_eq!((offset, next_header), (len, ip6_nh::ICMP));
+        assert_eq!(decoded.next_header(), Some(ip6_nh::HOP_OPTS));
+        assert_eq!(encode(&decoded, ip6_nh::ICMP)[..len], buf[..len]);
+        let mut copy = decoded;
+        assert_eq!(copy.as_mut_slice(), &buf[..len]);
+
+        assert!(ExtHeaders::decode(ip6_nh::HOP_OPTS, &buf[..len - 1])
+            .done()
+            .is_none());
+        let (offset, (decoded, next_header)) =
+            ExtHeaders::decode(ip6_nh::UDP, &buf).done().unwrap();
+        assert_eq!((offset, next_header), (0, ip6_nh::UDP));
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    pub fn unknown_options_follow_their_type() {
+        let unicast = IPAddr([0x20, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let mut multicast = IPAddr([0; 16]);
+        multicast.0[0] = 0xff;
+        multicast.0[1] = 0x02;
+        multicast.0[15] = 1;
+        let options = |option_type: u8| [ip6_nh::UDP, 0, 0x00, option_type, 0x02, 0xaa, 0xbb, 0x00];
+
+        assert_eq!(
+            parse(ip6_nh::DST_OPTS, unicast, &options(0x3e)),
+            Ok((ip6_nh::UDP, 8))
+        );
+        assert_eq!(
+            parse(ip6_nh::DST_OPTS, unicast, &options(0x7e)),
+            Err(ExtHeaderError::Drop)
+        );
+        assert_eq!(
+            parse(ip6_nh::HOP_OPTS, unicast, &options(ip6_opt::RPL)),
+            Ok((ip6_nh::UDP, 8))
+        );
+        let problem = Err(ExtHeaderError::ParameterProblem {
+            code: param_problem::UNRECOGNIZED_OPTION,
+            pointer: 43,
+        });
+        assert_eq!(parse(ip6_nh::DST_OPTS, multicast, &options(0xbe)), problem);
+        assert_eq!(parse(ip6_nh::DST_OPTS, unicast, &options(0xfe)), problem);
+        assert_eq!(
+            parse(ip6_nh::DST_OPTS, multicast, &options(0xfe)),
+            Err(ExtHeaderError::Drop)
+        );
+    }
+
+    #[test]
+    pub fn malformed_headers_are_dropped() {
+        let addr = IPAddr([0x20, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        // Truncated headers and options.
+        assert_eq!(
+            parse(ip6_nh::HOP_OPTS, addr, &[]),
+            Err(ExtHeaderError::Drop)
+        );
+        assert_eq!(
+            parse(ip6_nh::HOP_OPTS, addr, &[ip6_nh::UDP, 1, 0, 0, 0, 0, 0, 0]),
+            Err(ExtHeaderError::Drop)
+        );
+        assert_eq!(
+            parse(ip6_nh::HOP_OPTS, addr, &[ip6_nh::UDP, 0, 1, 5, 0, 0, 0, 0]),
+            Err(ExtHeaderError::Drop)
+        );
+        assert_eq!(
+            parse(ip6_nh::FRAGMENT, addr, &[ip6_nh::UDP, 0, 0, 0]),
+            Err(ExtHeaderError::Drop)
+        );
+        // A fragment that is not atomic.
+        assert_eq!(
+            parse(ip6_nh::FRAGMENT, addr, &[ip6_nh::UDP, 0, 0, 1, 0, 0, 0, 1]),
+            Err(ExtHeaderError::Drop)
+        );
+        // Hop-by-Hop Options after another header.
+        assert_eq!(
+            parse(
+                ip6_nh::DST_OPTS,
+                addr,
+                &[ip6_nh::HOP_OPTS, 0, 1, 4, 0, 0, 0, 0]
+            ),
+            Err(ExtHeaderError::ParameterProblem {
+                code: param_problem::UNRECOGNIZED_NEXT_HEADER,
+                pointer: 40,
+            })
+        );
+        // A routing header of an unknown type with segments left.
+        assert_eq!(
+            parse(ip6_nh::ROUTING, addr, &[ip6_nh::UDP, 0, 0, 1, 0, 0, 0, 0]),
+            Err(ExtHeaderError::ParameterProblem {
+                code: param_problem::ERRONEOUS_HEADER_FIELD,
+                pointer: 42,
+            })
+        );
+        assert_eq!(
+            parse(ip6_nh::ROUTING, addr, &[ip6_nh::UDP, 0, 3, 1, 0, 0, 0, 0]),
+            Err(ExtHeaderError::SourceRoute { offset: 0 })
+        );
+    }
+
+    #[test]
+    pub fn extension_headers_are_skipped_or_discard_packets() {
+        let net_cap = any_network_capability();
+        let (a_addr, b_addr) = (link_local_addr(1), link_local_addr(2));
+        let (a_sender, a_receiver, a_echo) = echo_host(a_addr);
+        let (b_sender, b_receiver, b_echo) = echo_host(b_addr);
+        let a_replies = leak(EchoReplies::default());
+        a_echo.set_client(a_replies);
+        b_echo.set_responder_enabled(true);
+
+        // An unrecognized option whose type says to skip it.
+        let mut ext_headers = ExtHeaders::new();
+        assert_eq!(
+            ext_headers.add_hop_by_hop(&[0x1e, 0x02, 0xaa, 0xbb]),
+            ReturnCode::SUCCESS
+        );
+        a_sender.ext_headers.set(ext_headers);
+        assert_eq!(
+            a_echo.send_request(b_addr, 7, 1, 4, net_cap),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(a_sender.outbox.borrow()[0][6], ip6_nh::HOP_OPTS);
+        assert_eq!(a_sender.outbox.borrow()[0][40], ip6_nh::ICMP);
+        assert!(a_sender.transfer(b_receiver, false));
+        assert!(b_sender.transfer(a_receiver, false));
+        assert_eq!(a_replies.replies.borrow().len(), 1);
+
+        // An atomic fragment is accepted, but an option whose type says to
+        // discard the packet is not.
+        let mut ext_headers = ExtHeaders::new();
+        assert_eq!(ext_headers.add_atomic_fragment(1), ReturnCode::SUCCESS);
+        a_sender.ext_headers.set(ext_headers);
+        assert_eq!(
+            a_echo.send_request(b_addr, 7, 2, 4, net_cap),
+            ReturnCode::SUCCESS
+        );
+        assert!(a_sender.transfer(b_receiver, false));
+        assert!(b_sender.transfer(a_receiver, false));
+        assert_eq!(a_replies.replies.borrow().len(), 2);
+
+        assert_eq!(ext_headers.add_dst_opts(&[0x5e, 0x00]), ReturnCode::SUCCESS);
+        a_sender.ext_headers.set(ext_headers);
+        assert_eq!(
+            a_echo.send_request(b_addr, 7, 3, 4, net_cap),
+            ReturnCode::SUCCESS
+        );
+        assert!(a_sender.transfer(b_receiver, false));
+        assert!(!b_sender.transfer(a_receiver, false));
+        assert_eq!(a_replies.replies.borrow().len(), 2);
+
+        // An option whose type asks for a Parameter Problem message is
+        // answered with one, pointing at the option and carrying the start
+        // of the packet.
+        let mut ext_headers = ExtHeaders::new();
+        assert_eq!(ext_headers.add_dst_opts(&[0x9e, 0x00]), ReturnCode::SUCCESS);
+        a_sender.ext_headers.set(ext_headers);
+        assert_eq!(
+            a_echo.send_request(b_addr, 7, 4, 4, net_cap),
+            ReturnCode::SUCCESS
+        );
+        let request = a_sender.outbox.borrow()[0].clone();
+        assert!(a_sender.transfer(b_receiver, false));
+        let (dst, icmp_type, body) = pending_icmp(b_sender);
+        assert_eq!((dst, icmp_type), (a_addr, 4));
+        assert_eq!(&body[..], &request[..32]);
+        let error = b_sender.outbox.borrow()[0].clone();
+        assert_eq!(
+            &error[40..48],
+            &[
+                4,
+                param_problem::UNRECOGNIZED_OPTION,
+                error[42],
+                error[43],
+                0,
+                0,
+                0,
+                42
+            ]
+        );
+        assert!(b_sender.transfer(a_receiver, false));
+        assert_eq!(a_replies.replies.borrow().len(), 2);
+    }
+}
@@ -0,0 +1,250 @@
+//! This file contains the table of IPv6 addresses assigned to a network
+//! interface.
+//!
+//! An interface holds several addresses at once: addresses configured by the
+//! board, the link-local address formed from the 802.15.4 long address, and
+//! global addresses formed from the prefixes routers advertise (stateless
+//! address autoconfiguration, RFC 4862). Autoconfigured addresses expire with
+//! the valid lifetime of their prefix. The table also holds the multicast
+//! groups the interface joins, such as the all-RPL-nodes group, which are
+//! never chosen as a source address.
+//!
+//! `IP6SendStruct` uses the table to choose the source address of each packet
+//! and `IP6RecvStruct` uses it to drop packets that are not addressed to the
+//! interface.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let interface_addrs = static_init!(InterfaceAddresses, InterfaceAddresses::new());
+//! interface_addrs.add(IPAddr::generate_from_mac(MacAddress::Long(eui64)), None);
+//! ip_send.set_interface_addresses(interface_addrs);
+//! ip_receive.set_interface_addresses(interface_addrs);
+//! ```
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use core::cell::Cell;
+use kernel::ReturnCode;
+
+/// The number of addresses an interface can hold.
+pub const MAX_INTERFACE_ADDRS: usize = 6;
+
+#[derive(Copy, Clone)]
+struct Entry {
+    addr: IPAddr,
+    /// The time, in alarm tics, at which the address expires, if it does.
+    expires: Option<u32>,
+}
+
+pub struct InterfaceAddresses {
+    entries: [Cell<Option<Entry>>; MAX_INTERFACE_ADDRS],
+}
+
+impl InterfaceAddresses {
+    pub fn new() -> InterfaceAddresses {
+        InterfaceAddresses {
+            entries: Default::default(),
+        }
+    }
+
+    /// Adds an address that expires at `expires`, or never if it is None.
+    /// Adding an address the interface already holds updates its expiry.
+    /// Returns ENOMEM if the table is full.
+    pub fn add(&self, addr: IPAddr, expires: Option<u32>) -> ReturnCode {
+        let slot = self
+            .entries
+            .iter()
+            .find(|slot| slot.get().map_or(false, |entry| entry.addr == addr))
+            .or_else(|| self.entries.iter().find(|slot| slot.get().is_none()));
+        match slot {
+            Some(slot) => {
+                slot.set(Some(Entry {
+                    addr: addr,
+                    expires: expires,
+                }));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    pub fn remove(&self, addr: IPAddr) {
+        self.entries
+            .iter()
+            .filter(|slot| slot.get().map_or(false, |entry| entry.addr == addr))
+            .for_each(|slot| slot.set(None));
+    }
+
+    pub fn contains(&self, addr: IPAddr) -> bool {
+        self.iter().any(|local| local == addr)
+    }
+
+    /// The addresses of the interface.
+    pub fn iter(&self) -> impl Iterator<Item = IPAddr> + '_ {
+        self.entries
+            .iter()
+            .filter_map(|slot| slot.get())
+            .map(|entry| entry.addr)
+    }
+
+    /// Whether a packet sent to `dst` is for this interface: `dst` is one of
+    /// its addresses or groups, the all-nodes multicast address or the
+    /// solicited-node multicast address of one of its addresses.
+    pub fn accepts(&self, dst: IPAddr) -> bool {
+        if dst.is_multicast() {
+            let all_nodes = (dst.0[1] == 0x01 || dst.0[1] == 0x02)
+                && dst.0[2..15].iter().all(|&b| b == 0)
+                && dst.0[15] == 1;
+            all_nodes
+                || self
+                    .iter()
+                    .any(|local| dst == local || dst == solicited_node(local))
+        } else {
+            self.contains(dst)
+        }
+    }
+
+    /// Chooses the source address for a packet to `dst`, following a subset
+    /// of the rules of RFC 6724, section 5: `dst` itself if it is local, then
+    /// an address of the same scope as `dst`, then the address sharing the
+    /// longest prefix with `dst`. Returns None if the interface has no
+    /// addresses.
+    pub fn select_source(&self, dst: IPAddr) -> Option<IPAddr> {
+        let link_scope = is_link_scope(dst);
+        self.iter()
+            .filter(|local| !local.is_multicast())
+            .max_by_key(|&local| {
+                (
+                    local == dst,
+                    local.is_unicast_link_local() == link_scope,
+                    local.common_prefix_len(&dst),
+                )
+            })
+    }
+
+    /// Removes the addresses that expired at or before `now`.
+    pub fn expire(&self, now: u32) {
+        self.entries
+            .iter()
+            .filter(|slot| {
+                slot.get()
+                    .and_then(|entry| entry.expires)
+                    .map_or(false, |expires| expires.wrapping_sub(now) as i32 <= 0)
+            })
+            .for_each(|slot| slot.set(None));
+    }
+
+    /// The earliest expiry of any address.
+    pub fn next_expiry(&self, now: u32) -> Option<u32> {
+        self.entries
+            .iter()
+            .filter_map(|slot| slot.get().and_then(|entry| entry.expires))
+            .min_by_key(|expires| expires.wrapping_sub(now) as i32)
+    }
+}
+
+/// Whether `addr` only reaches the link: a link-local unicast address or a
+/// multicast address of interface-local or link-local scope.
+fn is_link_scope(addr: IPAddr) -> bool {
+    addr.is_unicast_link_local() || (addr.is_multicast() && (addr.0[1] & 0x0f) <= 0x02)
+}
+
+/// The solicited-node multicast address of `addr`, ff02::1:ffXX:XXXX
+/// (RFC 4291, section 2.7.1).
+fn solicited_node(addr: IPAddr) -> IPAddr {
+    let mut group = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, 0, 0, 0]);
+    group.0[13..16].copy_from_slice(&addr.0[13..16]);
+    group
+}
+
+#[cfg(test)]
+mod test {
+    use super::InterfaceAddresses;
+    use crate::net::ieee802154::MacAddress;
+    use crate::net::ipv6::ip_utils::IPAddr;
+    use kernel::ReturnCode;
+
+    fn global_addr(prefix: u8, last: u8) -> IPAddr {
+        let mut addr = IPAddr([0; 16]);
+        addr.0[0] = 0x20;
+        addr.0[1] = 0x01;
+        addr.0[2] = prefix;
+        addr.0[15] = last;
+        addr
+    }
+
+    #[test]
+    pub fn sources_match_scope_and_prefix() {
+        let addrs = InterfaceAddresses::new();
+        assert_eq!(addrs.select_source(global_addr(1, 9)), None);
+
+        let link_local = IPAddr::generate_from_mac(MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(addrs.add(link_local, None), ReturnCode::SUCCESS);
+        assert_eq!(addrs.add(global_addr(1, 1), None), ReturnCode::SUCCESS);
+        assert_eq!(addrs.add(global_addr(0x80, 1), None), ReturnCode::SUCCESS);
+
+        assert_eq!(
+            addrs.select_source(global_addr(1, 9)),
+            Some(global_addr(1, 1))
+        );
+        assert_eq!(
+            addrs.select_source(global_addr(0x81, 9)),
+            Some(global_addr(0x80, 1))
+        );
+        assert_eq!(
+            addrs.select_source(global_addr(0x80, 1)),
+            Some(global_addr(0x80, 1))
+        );
+        let mut neighbor = link_local;
+        neighbor.0[15] ^= 0xff;
+        assert_eq!(addrs.select_source(neighbor), Some(link_local));
+        let mut all_routers = IPAddr([0; 16]);
+        all_routers.0[0] = 0xff;
+        all_routers.0[1] = 0x02;
+        all_routers.0[15] = 2;
+        assert_eq!(addrs.select_source(all_routers), Some(link_local));
+
+        // Groups are accepted but never chosen as a source.
+        let mut all_rpl_nodes = all_routers;
+        all_rpl_nodes.0[15] = 0x1a;
+        assert!(!addrs.accepts(all_rpl_nodes));
+        assert_eq!(addrs.add(all_rpl_nodes, None), ReturnCode::SUCCESS);
+        assert!(addrs.accepts(all_rpl_nodes));
+        assert_eq!(addrs.select_source(all_rpl_nodes), Some(link_local));
+    }
+
+    #[test]
+    pub fn accepts_local_and_multicast_destinations() {
+        let addrs = InterfaceAddresses::new();
+        let local = global_addr(1, 0x42);
+        assert_eq!(addrs.add(local, Some(100)), ReturnCode::SUCCESS);
+
+        let mut all_nodes = IPAddr([0; 16]);
+        all_nodes.0[0] = 0xff;
+        all_nodes.0[1] = 0x02;
+        all_nodes.0[15] = 1;
+        let mut solicited = IPAddr([0; 16]);
+        solicited.0[0] = 0xff;
+        solicited.0[1] = 0x02;
+        solicited.0[11] = 0x01;
+        solicited.0[12] = 0xff;
+        solicited.0[15] = 0x42;
+        assert!(addrs.accepts(local));
+        assert!(addrs.accepts(all_nodes));
+        assert!(addrs.accepts(solicited));
+        solicited.0[15] = 0x43;
+        assert!(!addrs.accepts(solicited));
+        assert!(!addrs.accepts(global_addr(1, 0x43)));
+
+        // Adding the address again only updates its expiry.
+        assert_eq!(addrs.add(local, Some(200)), ReturnCode::SUCCESS);
+        assert_eq!(addrs.iter().count(), 1);
+        assert_eq!(addrs.next_expiry(0), Some(200));
+        addrs.expire(150);
+        assert!(addrs.accepts(local));
+        addrs.expire(200);
+        assert!(!addrs.accepts(local));
+        assert_eq!(addrs.next_expiry(0), None);
+    }
+}
@@ -5,6 +5,7 @@
 use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::ipv6::IP6Header;
+use crate::net::tcp::tcp::{TCPHeader, TCP_HDR_LEN};
 use crate::net::udp::udp::UDPHeader;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -74,6 +75,19 @@ impl IPAddr {
         ip_addr
     }
 
+    /// The inverse of `generate_from_mac`: the 15.4 MAC address from which
+    /// the interface identifier of this address was derived.
+    pub fn mac_from_iid(&self) -> MacAddress {
+        if self.0[8..14] == [0x00, 0x00, 0x00, 0xff, 0xfe, 0x00] {
+            MacAddress::Short((self.0[14] as u16) << 8 | self.0[15] as u16)
+        } else {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(&self.0[8..16]);
+            long_addr[0] ^= 0b00000010;
+            MacAddress::Long(long_addr)
+        }
+    }
+
     pub fn is_unspecified(&self) -> bool {
         self.0.iter().all(|&b| b == 0)
     }
@@ -111,6 +125,14 @@ impl IPAddr {
     pub fn is_multicast(&self) -> bool {
         self.0[0] == 0xff
     }
+
+    /// The number of leading bits this address shares with `other`.
+    pub fn common_prefix_len(&self, other: &IPAddr) -> u8 {
+        match self.0.iter().zip(other.0.iter()).position(|(a, b)| a != b) {
+            Some(i) => (i * 8) as u8 + (self.0[i] ^ other.0[i]).leading_zeros() as u8,
+            None => 128,
+        }
+    }
 }
 
 pub fn compute_udp_checksum(
@@ -197,10 +219,24 @@ pub fn compute_icmp_checksum(
 
     // add options
     match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
+        ICMP6HeaderOptions::Type1 { unused }
+        | ICMP6HeaderOptions::Type3 { unused }
+        | ICMP6HeaderOptions::Type4 { pointer: unused }
+        | ICMP6HeaderOptions::Type133 { reserved: unused }
+        | ICMP6HeaderOptions::Type135 { reserved: unused }
+        | ICMP6HeaderOptions::Type136 { flags: unused }
+        | ICMP6HeaderOptions::Type155 { base: unused } => {
             sum += unused >> 16; // upper 16 bits
             sum += unused & 0xffff; // lower 16 bits
         }
+        ICMP6HeaderOptions::Type134 {
+            cur_hop_limit,
+            flags,
+            router_lifetime,
+        } => {
+            sum += (cur_hop_limit as u32) << 8 | flags as u32;
+            sum += router_lifetime as u32;
+        }
         ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
             sum += id as u32;
             sum += seqno as u32;
@@ -215,7 +251,7 @@ pub fn compute_icmp_checksum(
     while sum > 0xffff {
         let sum_upper = sum >> 16;
         let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = sum_upper + sum_lower;
     }
 
     sum = !sum;
@@ -224,6 +260,50 @@ pub fn compute_icmp_checksum(
     sum as u16
 }
 
+/// Computes the checksum of a TCP segment. `tcp_length` is the length of the
+/// segment, header included, and `payload` holds everything after the first 20
+/// bytes of the header. A received segment is intact if its checksum, computed
+/// with the checksum field as received, is 0.
+pub fn compute_tcp_checksum(
+    ip6_header: &IP6Header,
+    tcp_header: &TCPHeader,
+    tcp_length: u16,
+    payload: &[u8],
+) -> u16 {
+    let mut sum: u32 = 0;
+
+    // add the pseudo-header
+    for i in (0..16).step_by(2) {
+        sum += (ip6_header.src_addr.0[i] as u32) << 8 | ip6_header.src_addr.0[i + 1] as u32;
+        sum += (ip6_header.dst_addr.0[i] as u32) << 8 | ip6_header.dst_addr.0[i + 1] as u32;
+    }
+    sum += tcp_length as u32;
+    sum += ip6_nh::TCP as u32;
+
+    // add the header
+    sum += tcp_header.src_port as u32;
+    sum += tcp_header.dst_port as u32;
+    sum += tcp_header.seq_num >> 16;
+    sum += tcp_header.seq_num & 0xffff;
+    sum += tcp_header.ack_num >> 16;
+    sum += tcp_header.ack_num & 0xffff;
+    sum += tcp_header.offset_and_control as u32;
+    sum += tcp_header.window as u32;
+    sum += tcp_header.cksum as u32;
+    sum += tcp_header.urg_ptr as u32;
+
+    // add the payload, padded with a zero byte if its length is odd
+    let payload_len = (tcp_length as usize).saturating_sub(TCP_HDR_LEN);
+    for word in payload[..payload_len].chunks(2) {
+        sum += (word[0] as u32) << 8 | word.get(1).map_or(0, |&b| b as u32);
+    }
+
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+    !(sum as u16)
+}
+
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
     let mut sum: u32 = 0;
 
@@ -241,7 +321,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -253,7 +333,12 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        // An odd length is padded with a zero byte
+        let lsb = if i + 1 < len as usize {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
@@ -21,7 +21,9 @@
 //! [IPPayload](struct.IPPayload.html) struct, with the `IPPayload` struct
 //! also containing a [TransportHeader](enum.TransportHeader.html) enum and
 //! a `Payload` buffer. Note that transport-level headers are contained inside
-//! the `TransportHeader`.
+//! the `TransportHeader`. IPv6 extension headers to send are carried,
+//! serialized, in the [ExtHeaders](../ext_headers/struct.ExtHeaders.html) of
+//! the `IP6Packet` and encoded between the `IP6Header` and the `IPPayload`.
 //!
 //! For a client interested in using this interface, they first statically
 //! allocate an `IP6Packet` struct, then set the appropriate headers and
@@ -62,14 +64,18 @@
 // stack, as the network send interface is asynchronous, so anything allocated
 // on the stack would eventually be popped/disappear. Although this is not
 // a major problem in general, it makes handling encapsulated IPv6 packets
-// (as required by 6LoWPAN) difficult.
+// (as required by 6LoWPAN) difficult. Extension headers work around this by
+// being stored pre-serialized in a fixed-size buffer in the `IP6Packet`.
 
 use crate::net::icmpv6::icmpv6::ICMP6Header;
-use crate::net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ext_headers::ExtHeaders;
+use crate::net::ipv6::ip_utils::{
+    compute_icmp_checksum, compute_tcp_checksum, compute_udp_checksum, ip6_nh, IPAddr,
+};
 use crate::net::stream::SResult;
 use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
 use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
-use crate::net::tcp::TCPHeader;
+use crate::net::tcp::tcp::{TCPHeader, TCP_HDR_LEN};
 use crate::net::udp::udp::UDPHeader;
 use kernel::common::leasable_buffer::LeasableBuffer;
 use kernel::ReturnCode;
@@ -262,9 +268,7 @@ impl IP6Header {
     pub fn check_transport_checksum(&self, buf: &[u8]) -> ReturnCode {
         match self.next_header {
             ip6_nh::UDP => {
-                let mut udp_header: [u8; UDP_HDR_LEN] = [0; UDP_HDR_LEN];
-                udp_header.copy_from_slice(&buf[..UDP_HDR_LEN]);
-                let checksum = match UDPHeader::decode(&udp_header).done() {
+                let checksum = match UDPHeader::decode(buf).done() {
                     Some((_offset, hdr)) => u16::from_be(compute_udp_checksum(
                         &self,
                         &hdr,
@@ -279,15 +283,25 @@ impl IP6Header {
                 ReturnCode::SUCCESS
             }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
-                    Some((_offset, mut hdr)) => {
-                        hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
+                // The checksum is computed as if the checksum field was 0, so
+                // it has to match the received checksum.
+                match ICMP6Header::decode(buf).done() {
+                    Some((_offset, hdr))
+                        if compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..])
+                            == hdr.get_cksum() =>
+                    {
+                        ReturnCode::SUCCESS
                     }
-                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
+                    _ => ReturnCode::FAIL,
+                }
+            }
+            ip6_nh::TCP => {
+                let checksum = match TCPHeader::decode(buf).done() {
+                    // The options, if any, are summed as part of the payload
+                    Some((_offset, hdr)) => {
+                        compute_tcp_checksum(&self, &hdr, buf.len() as u16, &buf[TCP_HDR_LEN..])
+                    }
+                    None => 0xffff,
                 };
                 if checksum != 0 {
                     return ReturnCode::FAIL; //Incorrect cksum
@@ -301,7 +315,8 @@ impl IP6Header {
 
 /// This defines the currently supported `TransportHeader` types. The contents
 /// of each header is encapsulated by the enum type. Note that this definition
-/// of `TransportHeader`s means that recursive headers are not supported.
+/// of `TransportHeader`s means that recursive headers are not supported;
+/// extension headers are carried separately in the `IP6Packet`.
 /// As of now, there is no support for sending raw IP packets without a transport header.
 /// Currently we accept the overhead of copying these structs in/out of an OptionalCell
 /// in `udp_send.rs`.
@@ -343,18 +358,22 @@ impl<'a> IPPayload<'a> {
     ///
     /// # Return Value
     ///
-    /// `(u8, u16)` - Returns a tuple of the `ip6_nh` type of the
-    /// `transport_header` and the total length of the `IPPayload`
-    /// (when serialized)
+    /// `Result<(u8, u16), ReturnCode>` - Returns a tuple of the `ip6_nh` type
+    /// of the `transport_header` and the total length of the `IPPayload`
+    /// (when serialized), or ESIZE if `payload` does not fit in the payload
+    /// buffer
     pub fn set_payload(
         &mut self,
         transport_header: TransportHeader,
         payload: &LeasableBuffer<'static, u8>,
-    ) -> (u8, u16) {
+    ) -> Result<(u8, u16), ReturnCode> {
+        if payload.len() > self.payload.len() {
+            return Err(ReturnCode::ESIZE);
+        }
         for i in 0..payload.len() {
             self.payload[i] = payload[i];
         }
-        match transport_header {
+        Ok(match transport_header {
             TransportHeader::UDP(mut udp_header) => {
                 let length = (payload.len() + udp_header.get_hdr_size()) as u16;
                 udp_header.set_len(length);
@@ -364,11 +383,62 @@ impl<'a> IPPayload<'a> {
             TransportHeader::ICMP(mut icmp_header) => {
                 let length = (payload.len() + icmp_header.get_hdr_size()) as u16;
                 icmp_header.set_len(length);
-                self.header = transport_header;
+                self.header = TransportHeader::ICMP(icmp_header);
                 (ip6_nh::ICMP, length)
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
+            TransportHeader::TCP(mut tcp_header) => {
+                let length = (payload.len() + tcp_header.get_hdr_size()) as u16;
+                tcp_header.set_len(length);
+                self.header = TransportHeader::TCP(tcp_header);
+                (ip6_nh::TCP, length)
+            }
+        })
+    }
+
+    /// Sets the transport header and payload of a packet to forward from
+    /// `buf`, which holds them as received, leaving the checksum as it is.
+    ///
+    /// # Arguments
+    ///
+    /// `next_header` - The `ip6_nh` type of the transport header
+    /// `buf` - The transport header and payload
+    ///
+    /// # Return Value
+    ///
+    /// ENOSUPPORT if the transport protocol is not one of the supported
+    /// `TransportHeader` types, EINVAL if the transport header cannot be
+    /// decoded or does not agree with the length of `buf`, and ESIZE if the
+    /// payload does not fit in the payload buffer
+    pub fn set_forwarded(&mut self, next_header: u8, buf: &[u8]) -> ReturnCode {
+        let decoded = match next_header {
+            ip6_nh::UDP => UDPHeader::decode(buf)
+                .done()
+                .filter(|(_, udp_header)| udp_header.get_len() as usize == buf.len())
+                .map(|(offset, udp_header)| (offset, TransportHeader::UDP(udp_header))),
+            ip6_nh::ICMP => ICMP6Header::decode(buf)
+                .done()
+                .map(|(offset, icmp_header)| (offset, TransportHeader::ICMP(icmp_header))),
+            // Options are not carried by a `TCPHeader`.
+            ip6_nh::TCP => TCPHeader::decode(buf)
+                .done()
+                .filter(|(_, tcp_header)| tcp_header.get_hdr_size() == TCP_HDR_LEN)
+                .map(|(offset, mut tcp_header)| {
+                    tcp_header.set_len(buf.len() as u16);
+                    (offset, TransportHeader::TCP(tcp_header))
+                }),
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+        let (offset, transport_header) = match decoded {
+            Some(decoded) => decoded,
+            None => return ReturnCode::EINVAL,
+        };
+        let payload = &buf[offset..];
+        match self.payload.get_mut(..payload.len()) {
+            Some(dst) => dst.copy_from_slice(payload),
+            None => return ReturnCode::ESIZE,
         }
+        self.header = transport_header;
+        ReturnCode::SUCCESS
     }
 
     /// This function encodes the `IPPayload` as a byte array
@@ -384,36 +454,45 @@ impl<'a> IPPayload<'a> {
     /// wrapped in an SResult
     pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
         let (offset, _) = match self.header {
-            TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
-            TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
+            TransportHeader::UDP(udp_header) => enc_try!(udp_header.encode(buf, offset)),
+            TransportHeader::ICMP(icmp_header) => enc_try!(icmp_header.encode(buf, offset)),
+            TransportHeader::TCP(tcp_header) => enc_try!(tcp_header.encode(buf, offset)),
         };
         let payload_length = self.get_payload_length();
-        let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
+        let payload = stream_from_option!(self.payload.get(..payload_length));
+        let offset = enc_consume!(buf, offset; encode_bytes, payload);
         stream_done!(offset, offset)
     }
 
+    /// The `ip6_nh` type of the transport header.
+    fn next_header(&self) -> u8 {
+        match self.header {
+            TransportHeader::UDP(_) => ip6_nh::UDP,
+            TransportHeader::ICMP(_) => ip6_nh::ICMP,
+            TransportHeader::TCP(_) => ip6_nh::TCP,
+        }
+    }
+
     fn get_payload_length(&self) -> usize {
         match self.header {
             TransportHeader::UDP(udp_header) => {
-                udp_header.get_len() as usize - udp_header.get_hdr_size()
+                (udp_header.get_len() as usize).saturating_sub(udp_header.get_hdr_size())
             }
             TransportHeader::ICMP(icmp_header) => {
-                icmp_header.get_len() as usize - icmp_header.get_hdr_size()
+                (icmp_header.get_len() as usize).saturating_sub(icmp_header.get_hdr_size())
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(tcp_header) => {
+                (tcp_header.get_len() as usize).saturating_sub(tcp_header.get_hdr_size())
             }
         }
     }
 }
 
-/// This struct defines the `IP6Packet` format, and contains an `IP6Header`
-/// and an `IPPayload`.
+/// This struct defines the `IP6Packet` format, and contains an `IP6Header`,
+/// the extension headers that follow it and an `IPPayload`.
 pub struct IP6Packet<'a> {
     pub header: IP6Header,
+    pub ext_headers: ExtHeaders,
     pub payload: IPPayload<'a>,
 }
 
@@ -432,6 +511,7 @@ impl<'a> IP6Packet<'a> {
     pub fn new(payload: IPPayload<'a>) -> IP6Packet<'a> {
         IP6Packet {
             header: IP6Header::default(),
+            ext_headers: ExtHeaders::new(),
             payload: payload,
         }
     }
@@ -452,9 +532,25 @@ impl<'a> IP6Packet<'a> {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
+            TransportHeader::TCP(tcp_header) => tcp_header.get_hdr_size(),
         };
-        40 + transport_hdr_size
+        40 + self.ext_headers.len() + transport_hdr_size
+    }
+
+    /// Returns the `IP6Header` as the transport layer sees it, with the next
+    /// header and payload length of the `IPPayload` rather than of the
+    /// extension headers. Transport checksums are computed over this header.
+    pub fn get_upper_layer_header(&self) -> IP6Header {
+        let mut header = self.header;
+        if !self.ext_headers.is_empty() {
+            header.set_next_header(self.payload.next_header());
+            header.set_payload_len(
+                header
+                    .get_payload_len()
+                    .saturating_sub(self.ext_headers.len() as u16),
+            );
+        }
+        header
     }
 
     pub fn set_transport_checksum(&mut self) {
@@ -464,10 +560,11 @@ impl<'a> IP6Packet<'a> {
         // psuedoheader cksum and calls the appropriate transport packet function
         // using this pseudoheader cksum to set the transport packet cksum
 
+        let header = self.get_upper_layer_header();
         match self.payload.header {
             TransportHeader::UDP(ref mut udp_header) => {
                 let cksum = compute_udp_checksum(
-                    &self.header,
+                    &header,
                     &udp_header,
                     udp_header.get_len(),
                     self.payload.payload,
@@ -475,11 +572,18 @@ impl<'a> IP6Packet<'a> {
                 udp_header.set_cksum(cksum);
             }
             TransportHeader::ICMP(ref mut icmp_header) => {
-                let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
+                let cksum = compute_icmp_checksum(&header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(ref mut tcp_header) => {
+                tcp_header.set_cksum(0);
+                let cksum = compute_tcp_checksum(
+                    &header,
+                    &tcp_header,
+                    tcp_header.get_len(),
+                    self.payload.payload,
+                );
+                tcp_header.set_cksum(cksum);
             }
         }
     }
@@ -489,7 +593,8 @@ impl<'a> IP6Packet<'a> {
     /// method to set the transport header and transport payload, which then
     /// returns the `ip6_nh` value for the `TransportHeader` and the length of
     /// the serialized `IPPayload` region. This function then sets the
-    /// `IP6Header` next header field correctly. **Without using this function,
+    /// `IP6Header` next header field correctly, pointing to the first
+    /// extension header if there are any. **Without using this function,
     /// the `IP6Header.next_header` field may not agree with the actual
     /// next header (`IP6Header.payload.header`)**
     ///
@@ -498,14 +603,51 @@ impl<'a> IP6Packet<'a> {
     /// `transport_header` - The `TransportHeader` to be set as the next header
     /// `payload` - The transport payload to be copied into the `IPPayload`
     /// transport payload
+    ///
+    /// # Return Value
+    ///
+    /// ESIZE if `payload` does not fit in the `IPPayload`, SUCCESS otherwise
     pub fn set_payload(
         &mut self,
         transport_header: TransportHeader,
         payload: &LeasableBuffer<'static, u8>,
-    ) {
-        let (next_header, payload_len) = self.payload.set_payload(transport_header, payload);
-        self.header.set_next_header(next_header);
-        self.header.set_payload_len(payload_len);
+    ) -> ReturnCode {
+        match self.payload.set_payload(transport_header, payload) {
+            Ok((next_header, payload_len)) => {
+                self.header
+                    .set_next_header(self.ext_headers.next_header().unwrap_or(next_header));
+                self.header
+                    .set_payload_len(payload_len + self.ext_headers.len() as u16);
+                ReturnCode::SUCCESS
+            }
+            Err(code) => code,
+        }
+    }
+
+    /// Sets the packet to a received packet that is forwarded: its
+    /// `IP6Header`, as updated by the router, the extension headers it was
+    /// received with and its transport header and payload in `buf`, with
+    /// `next_header` giving the transport protocol. The transport checksum is
+    /// not recomputed.
+    ///
+    /// # Return Value
+    ///
+    /// SUCCESS, or an error from `IPPayload::set_forwarded`
+    pub fn set_forwarded(
+        &mut self,
+        header: IP6Header,
+        ext_headers: ExtHeaders,
+        next_header: u8,
+        buf: &[u8],
+    ) -> ReturnCode {
+        let ret = self.payload.set_forwarded(next_header, buf);
+        if ret == ReturnCode::SUCCESS {
+            self.header = header;
+            self.header
+                .set_payload_len((ext_headers.len() + buf.len()) as u16);
+            self.ext_headers = ext_headers;
+        }
+        ret
     }
 
     // TODO: Do we need a decode equivalent? I don't think so, but we might
@@ -513,8 +655,10 @@ impl<'a> IP6Packet<'a> {
     pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
         let ip6_header = self.header;
 
-        // TODO: Handle unwrap safely
-        let (off, _) = ip6_header.encode(buf).done().unwrap();
+        let (off, _) = enc_try!(ip6_header.encode(buf));
+        let (off, _) = enc_try!(self
+            .ext_headers
+            .encode(buf, off, self.payload.next_header()));
         self.payload.encode(buf, off)
     }
 }
@@ -0,0 +1,870 @@
+//! This file contains a host implementation of 6LoWPAN Neighbor Discovery
+//! (RFC 6775), which replaces the multicast based address resolution of
+//! IPv6 Neighbor Discovery (RFC 4861) on low-power links.
+//!
+//! Once started, `NeighborDiscovery` sends router solicitations until a
+//! router advertisement arrives. The router is added to the
+//! [NeighborCache](../neighbor_cache/struct.NeighborCache.html) as the default
+//! router, and the host then registers its address with the router by sending
+//! it a neighbor solicitation carrying an Address Registration Option (ARO).
+//! The registration is refreshed before its lifetime runs out. The client is
+//! told when the address is first registered, and when registration fails or
+//! is lost.
+//!
+//! Retransmissions follow RFC 6775, section 5.3: router solicitations are
+//! sent every `RTR_SOLICITATION_INTERVAL` seconds, backing off exponentially
+//! up to `MAX_RTR_SOLICITATION_INTERVAL` seconds after
+//! `MAX_RTR_SOLICITATIONS`, and a registration that is not acknowledged after
+//! `MAX_UNICAST_SOLICIT` attempts is abandoned in favor of soliciting a router
+//! again.
+//!
+//! When given the interface's address table, `NeighborDiscovery` also
+//! performs stateless address autoconfiguration (RFC 4862): for every 64 bit
+//! prefix a router advertises for autonomous configuration, it adds a global
+//! address formed from the prefix and the 802.15.4 long address. The address
+//! expires with the valid lifetime of the prefix; preferred lifetimes are not
+//! tracked. Once formed, the global address is registered with the router in
+//! place of the address passed to `start`, as RFC 6775 requires hosts to
+//! register their non-link-local addresses. Contexts advertised for header
+//! compression are not processed yet.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let nd = static_init!(
+//!     NeighborDiscovery<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     NeighborDiscovery::new(
+//!         nd_ip_send,
+//!         nd_alarm,
+//!         neighbor_cache,
+//!         &mut ND_BUF,
+//!         src_mac_addr,
+//!         eui64,
+//!         net_cap,
+//!     )
+//! );
+//! nd_ip_send.set_client(nd);
+//! nd_alarm.set_client(nd);
+//! ip_receive.add_client(nd);
+//! nd.set_interface_addresses(interface_addrs);
+//! nd.start(IPAddr::generate_from_mac(MacAddress::Long(eui64)));
+//! ```
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::interface_addrs::InterfaceAddresses;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::neighbor_cache::{Neighbor, NeighborCache};
+use crate::net::network_capabilities::NetworkCapability;
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// Seconds between the first router solicitations.
+pub const RTR_SOLICITATION_INTERVAL: u32 = 10;
+/// Router solicitations sent before backing off.
+pub const MAX_RTR_SOLICITATIONS: u8 = 3;
+/// The longest interval, in seconds, between router solicitations.
+pub const MAX_RTR_SOLICITATION_INTERVAL: u32 = 60;
+/// Seconds between retransmissions of a registration.
+pub const RETRANS_TIMER: u32 = 1;
+/// Registrations sent before giving up on a router.
+pub const MAX_UNICAST_SOLICIT: u8 = 3;
+/// The lifetime, in minutes, requested for the registered address.
+pub const REGISTRATION_LIFETIME: u16 = 15;
+
+/// The smallest transmit buffer that fits every message sent: a neighbor
+/// solicitation with its target address, an ARO and an SLLAO for a long
+/// address.
+pub const TX_BUF_LEN: usize = 48;
+
+/// The all-routers link-local multicast address, ff02::2.
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+// Neighbor discovery option types.
+const OPT_SLLAO: u8 = 1;
+const OPT_PIO: u8 = 3;
+const OPT_ARO: u8 = 33;
+
+// ARO status values (RFC 6775, section 4.1).
+const ARO_SUCCESS: u8 = 0;
+const ARO_DUPLICATE: u8 = 1;
+const ARO_CACHE_FULL: u8 = 2;
+
+// Prefix information option flags (RFC 4861, section 4.6.2).
+const PIO_AUTONOMOUS: u8 = 0x40;
+
+/// Length of the reachable time and retransmission timer fields that start
+/// the body of a router advertisement.
+const RA_TIMERS_LEN: usize = 8;
+
+/// The client of a `NeighborDiscovery`, told about the registration of its
+/// address.
+pub trait NeighborDiscoveryClient {
+    /// Called with SUCCESS once the address is registered with a router, and
+    /// again whenever an autoconfigured address is registered in its place.
+    /// Afterwards, called with EALREADY if the router reports the address as
+    /// a duplicate, ENOMEM if the router's neighbor cache is full, and ENOACK
+    /// if the router stopped acknowledging the registration.
+    fn registered(&self, addr: IPAddr, result: ReturnCode);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    Soliciting { tries: u8 },
+    Registering { router: IPAddr, tries: u8 },
+    Registered { router: IPAddr },
+}
+
+pub struct NeighborDiscovery<'a, A: time::Alarm<'a>> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    cache: &'a NeighborCache,
+    tx_buf: TakeCell<'static, [u8]>,
+    sending: Cell<bool>,
+    src_mac: MacAddress,
+    eui64: [u8; 8],
+    interface_addrs: OptionalCell<&'a InterfaceAddresses>,
+    /// The address passed to `start`.
+    start_addr: Cell<IPAddr>,
+    /// The address being registered: `start_addr` or an autoconfigured
+    /// global address.
+    addr: Cell<IPAddr>,
+    /// Whether `addr` is currently registered with a router.
+    registered: Cell<bool>,
+    state: Cell<State>,
+    /// When the next solicitation or registration is due.
+    deadline: Cell<Option<u32>>,
+    client: OptionalCell<&'a dyn NeighborDiscoveryClient>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> NeighborDiscovery<'a, A> {
+    /// `tx_buf` must be at least `TX_BUF_LEN` bytes long. `eui64` is the
+    /// 802.15.4 long address of the interface: autoconfigured addresses are
+    /// formed from it and addresses are registered under it.
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        cache: &'a NeighborCache,
+        tx_buf: &'static mut [u8],
+        src_mac: MacAddress,
+        eui64: [u8; 8],
+        net_cap: &'static NetworkCapability,
+    ) -> NeighborDiscovery<'a, A> {
+        NeighborDiscovery {
+            ip_sender: ip_sender,
+            alarm: alarm,
+            cache: cache,
+            tx_buf: TakeCell::new(tx_buf),
+            sending: Cell::new(false),
+            src_mac: src_mac,
+            eui64: eui64,
+            interface_addrs: OptionalCell::empty(),
+            start_addr: Cell::new(IPAddr::new()),
+            addr: Cell::new(IPAddr::new()),
+            registered: Cell::new(false),
+            state: Cell::new(State::Idle),
+            deadline: Cell::new(None),
+            client: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn NeighborDiscoveryClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the address table of the interface, enabling stateless address
+    /// autoconfiguration.
+    pub fn set_interface_addresses(&self, interface_addrs: &'a InterfaceAddresses) {
+        self.interface_addrs.set(interface_addrs);
+    }
+
+    /// Starts looking for a router and registering `addr` with it. Returns
+    /// EALREADY if discovery is already running.
+    pub fn start(&self, addr: IPAddr) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EALREADY;
+        }
+        self.start_addr.set(addr);
+        self.addr.set(addr);
+        self.solicit(0);
+        self.reschedule();
+        ReturnCode::SUCCESS
+    }
+
+    /// Stops discovery. The neighbor cache is left as it is.
+    pub fn stop(&self) {
+        self.state.set(State::Idle);
+        self.registered.set(false);
+        self.deadline.set(None);
+        self.reschedule();
+    }
+
+    /// Converts seconds to tics, clamped so that deadlines compare correctly
+    /// across wraparound.
+    fn tics(secs: u32) -> u32 {
+        let tics = secs as u64 * <A::Frequency>::frequency() as u64;
+        cmp::min(tics, i32::max_value() as u64) as u32
+    }
+
+    fn set_timer(&self, secs: u32) {
+        self.deadline
+            .set(Some(self.alarm.now().wrapping_add(Self::tics(secs))));
+    }
+
+    /// Sends a router solicitation, the `tries`th since the last router was
+    /// lost.
+    fn solicit(&self, tries: u8) {
+        self.state.set(State::Soliciting { tries: tries });
+        let interval = if tries < MAX_RTR_SOLICITATIONS {
+            RTR_SOLICITATION_INTERVAL
+        } else {
+            let backoff = cmp::min(tries - MAX_RTR_SOLICITATIONS + 1, 8);
+            cmp::min(
+                RTR_SOLICITATION_INTERVAL << backoff,
+                MAX_RTR_SOLICITATION_INTERVAL,
+            )
+        };
+        self.set_timer(interval);
+
+        let src = IPAddr::generate_from_mac(self.src_mac);
+        let header = ICMP6HeaderOptions::Type133 { reserved: 0 };
+        self.send(src, ALL_ROUTERS, ICMP6Type::Type133, header, |buf| {
+            self.encode_sllao(buf)
+        });
+    }
+
+    /// Sends a neighbor solicitation registering the address with `router`.
+    fn register(&self, router: IPAddr, tries: u8) {
+        self.state.set(State::Registering {
+            router: router,
+            tries: tries,
+        });
+        self.set_timer(RETRANS_TIMER);
+
+        let addr = self.addr.get();
+        let header = ICMP6HeaderOptions::Type135 { reserved: 0 };
+        self.send(addr, router, ICMP6Type::Type135, header, |buf| {
+            buf[..16].copy_from_slice(&addr.0);
+            let aro = &mut buf[16..32];
+            aro[0] = OPT_ARO;
+            aro[1] = 2;
+            aro[2] = ARO_SUCCESS;
+            aro[3..6].copy_from_slice(&[0; 3]);
+            aro[6..8].copy_from_slice(&REGISTRATION_LIFETIME.to_be_bytes());
+            aro[8..16].copy_from_slice(&self.eui64);
+            32 + self.encode_sllao(&mut buf[32..])
+        });
+    }
+
+    /// Encodes a Source Link-Layer Address Option (RFC 4944, section 8) and
+    /// returns its length.
+    fn encode_sllao(&self, buf: &mut [u8]) -> usize {
+        match self.src_mac {
+            MacAddress::Short(short_addr) => {
+                buf[..8].copy_from_slice(&[0; 8]);
+                buf[0] = OPT_SLLAO;
+                buf[1] = 1;
+                buf[2..4].copy_from_slice(&short_addr.to_be_bytes());
+                8
+            }
+            MacAddress::Long(long_addr) => {
+                buf[..16].copy_from_slice(&[0; 16]);
+                buf[0] = OPT_SLLAO;
+                buf[1] = 2;
+                buf[2..10].copy_from_slice(&long_addr);
+                16
+            }
+        }
+    }
+
+    /// Sends a message whose body is written by `fill`, which returns its
+    /// length. Nothing is sent if a message is still being sent; the
+    /// retransmission timer covers for it.
+    fn send<F: FnOnce(&mut [u8]) -> usize>(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        icmp_type: ICMP6Type,
+        options: ICMP6HeaderOptions,
+        fill: F,
+    ) {
+        if self.sending.get() {
+            return;
+        }
+        let buf = match self.tx_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let len = fill(buf);
+
+        let mut header = ICMP6Header::new(icmp_type);
+        header.set_options(options);
+        let mut payload = LeasableBuffer::new(buf);
+        payload.slice(..len);
+        self.ip_sender.set_addr(src);
+        // The sender may complete synchronously, so this is set first.
+        self.sending.set(true);
+        let result =
+            self.ip_sender
+                .send_to(dst, TransportHeader::ICMP(header), &payload, self.net_cap);
+        self.tx_buf.replace(payload.take());
+        if result != ReturnCode::SUCCESS {
+            self.sending.set(false);
+        }
+    }
+
+    fn report(&self, result: ReturnCode) {
+        let was_registered = self.registered.replace(result == ReturnCode::SUCCESS);
+        if result != ReturnCode::SUCCESS || !was_registered {
+            let addr = self.addr.get();
+            self.client.map(|client| client.registered(addr, result));
+        }
+    }
+
+    /// Registers `addr` in place of the address registered so far.
+    fn change_addr(&self, addr: IPAddr) {
+        self.addr.set(addr);
+        self.registered.set(false);
+        match self.state.get() {
+            State::Registering { router, .. } | State::Registered { router } => {
+                self.register(router, 0)
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the alarm for the next solicitation or registration, or the next
+    /// expiry in the neighbor cache or the address table, whichever comes
+    /// first.
+    fn reschedule(&self) {
+        let now = self.alarm.now();
+        let remaining = self
+            .deadline
+            .get()
+            .into_iter()
+            .chain(self.cache.next_expiry(now))
+            .chain(
+                self.interface_addrs
+                    .and_then(|interface_addrs| interface_addrs.next_expiry(now)),
+            )
+            .map(|deadline| {
+                let remaining = deadline.wrapping_sub(now);
+                if remaining as i32 > 0 {
+                    remaining
+                } else {
+                    1
+                }
+            })
+            .min();
+        match remaining {
+            Some(remaining) => self.alarm.set_alarm(now.wrapping_add(remaining)),
+            None => self.alarm.disable(),
+        }
+    }
+
+    fn receive_router_advertisement(&self, src: IPAddr, router_lifetime: u16, body: &[u8]) {
+        // Routers advertise from their link-local address.
+        if !src.is_unicast_link_local() || body.len() < RA_TIMERS_LEN {
+            return;
+        }
+        if router_lifetime == 0 {
+            self.cache.remove(src);
+            self.router_lost(src);
+            return;
+        }
+        let options = &body[RA_TIMERS_LEN..];
+        self.interface_addrs.map(|interface_addrs| {
+            Options(options)
+                .filter(|option| option[0] == OPT_PIO)
+                .for_each(|pio| self.receive_prefix(interface_addrs, pio))
+        });
+        let mac = find_option(options, OPT_SLLAO)
+            .and_then(decode_sllao)
+            .unwrap_or_else(|| src.mac_from_iid());
+        let router = Neighbor {
+            ip: src,
+            mac: mac,
+            is_router: true,
+            expires: self
+                .alarm
+                .now()
+                .wrapping_add(Self::tics(router_lifetime as u32)),
+        };
+        if self.cache.insert(router) != ReturnCode::SUCCESS {
+            return;
+        }
+        if let State::Soliciting { .. } = self.state.get() {
+            self.register(src, 0);
+        }
+    }
+
+    /// Forms, refreshes or removes the address for the prefix in a prefix
+    /// information option.
+    fn receive_prefix(&self, interface_addrs: &InterfaceAddresses, pio: &[u8]) {
+        if pio.len() != 32 || pio[2] != 64 || pio[3] & PIO_AUTONOMOUS == 0 {
+            return;
+        }
+        let mut addr = IPAddr::generate_from_mac(MacAddress::Long(self.eui64));
+        addr.set_prefix(&pio[16..32], 64);
+        if addr.is_unicast_link_local() || addr.is_multicast() {
+            return;
+        }
+        let valid_lifetime = u32::from_be_bytes([pio[4], pio[5], pio[6], pio[7]]);
+        if valid_lifetime == 0 {
+            interface_addrs.remove(addr);
+            if self.addr.get() == addr {
+                self.change_addr(self.start_addr.get());
+            }
+            return;
+        }
+        let expires = if valid_lifetime == u32::max_value() {
+            None
+        } else {
+            Some(self.alarm.now().wrapping_add(Self::tics(valid_lifetime)))
+        };
+        if interface_addrs.add(addr, expires) == ReturnCode::SUCCESS
+            && self.addr.get() == self.start_addr.get()
+        {
+            self.change_addr(addr);
+        }
+    }
+
+    fn receive_neighbor_advertisement(&self, src: IPAddr, body: &[u8]) {
+        let router = match self.state.get() {
+            State::Registering { router, .. } => router,
+            _ => return,
+        };
+        if src != router || body.len() < 16 || body[..16] != self.addr.get().0 {
+            return;
+        }
+        let aro = match find_option(&body[16..], OPT_ARO) {
+            Some(aro) if aro.len() >= 16 && aro[8..16] == self.eui64 => aro,
+            _ => return,
+        };
+        let lifetime = u16::from_be_bytes([aro[6], aro[7]]);
+        match aro[2] {
+            ARO_SUCCESS => {
+                self.state.set(State::Registered { router: router });
+                // Refresh once three quarters of the lifetime have passed.
+                self.set_timer(lifetime as u32 * 60 * 3 / 4);
+                self.report(ReturnCode::SUCCESS);
+            }
+            ARO_DUPLICATE => {
+                self.state.set(State::Idle);
+                self.deadline.set(None);
+                self.report(ReturnCode::EALREADY);
+            }
+            ARO_CACHE_FULL => {
+                self.state.set(State::Idle);
+                self.deadline.set(None);
+                self.report(ReturnCode::ENOMEM);
+            }
+            _ => {}
+        }
+    }
+
+    /// Starts soliciting again if `router` is the router the address is
+    /// registered with.
+    fn router_lost(&self, router: IPAddr) {
+        match self.state.get() {
+            State::Registering {
+                router: current, ..
+            }
+            | State::Registered { router: current }
+                if current == router =>
+            {
+                self.solicit(0)
+            }
+            _ => {}
+        }
+    }
+
+    fn timer_expired(&self) {
+        match self.state.get() {
+            State::Idle => {}
+            State::Soliciting { tries } => self.solicit(tries.saturating_add(1)),
+            State::Registering { router, tries } => {
+                if tries + 1 < MAX_UNICAST_SOLICIT {
+                    self.register(router, tries + 1);
+                } else {
+                    self.cache.remove(router);
+                    self.solicit(0);
+                    self.report(ReturnCode::ENOACK);
+                }
+            }
+            State::Registered { router } => self.register(router, 0),
+        }
+    }
+}
+
+/// Iterates over a list of neighbor discovery options, each including its
+/// type and length bytes. Iteration stops at the first malformed option.
+struct Options<'b>(&'b [u8]);
+
+impl<'b> Iterator for Options<'b> {
+    type Item = &'b [u8];
+
+    fn next(&mut self) -> Option<&'b [u8]> {
+        if self.0.len() < 2 {
+            return None;
+        }
+        let len = self.0[1] as usize * 8;
+        if len == 0 || len > self.0.len() {
+            self.0 = &[];
+            return None;
+        }
+        let (option, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(option)
+    }
+}
+
+/// Returns the first option of type `opt_type` in a list of neighbor
+/// discovery options.
+fn find_option(options: &[u8], opt_type: u8) -> Option<&[u8]> {
+    Options(options).find(|option| option[0] == opt_type)
+}
+
+fn decode_sllao(option: &[u8]) -> Option<MacAddress> {
+    match option[1] {
+        1 => Some(MacAddress::Short(u16::from_be_bytes([
+            option[2], option[3],
+        ]))),
+        2 => {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(&option[2..10]);
+            Some(MacAddress::Long(long_addr))
+        }
+        _ => None,
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for NeighborDiscovery<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        // Neighbor discovery messages never leave the link (RFC 4861, section
+        // 6.1).
+        if ip_header.get_next_header() != ip6_nh::ICMP || ip_header.get_hop_limit() != 255 {
+            return;
+        }
+        let (offset, header) = match ICMP6Header::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        if header.get_code() != 0 {
+            return;
+        }
+        let src = ip_header.get_src_addr();
+        let body = &payload[offset..];
+        match header.get_options() {
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } => self.receive_router_advertisement(src, router_lifetime, body),
+            ICMP6HeaderOptions::Type136 { .. } => self.receive_neighbor_advertisement(src, body),
+            _ => return,
+        }
+        self.reschedule();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for NeighborDiscovery<'a, A> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for NeighborDiscovery<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        let router = match self.state.get() {
+            State::Registering { router, .. } | State::Registered { router } => Some(router),
+            _ => None,
+        };
+        self.cache.expire(now);
+        let expired = self.interface_addrs.map_or(false, |interface_addrs| {
+            interface_addrs.expire(now);
+            !interface_addrs.contains(self.addr.get())
+        });
+        if expired && self.addr.get() != self.start_addr.get() {
+            self.change_addr(self.start_addr.get());
+        }
+        match router {
+            Some(router) if self.cache.lookup(router).is_none() => self.router_lost(router),
+            _ => match self.deadline.get() {
+                Some(deadline) if deadline.wrapping_sub(now) as i32 <= 0 => self.timer_expired(),
+                _ => {}
+            },
+        }
+        self.reschedule();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{NeighborDiscovery, NeighborDiscoveryClient, TX_BUF_LEN};
+    use crate::net::icmpv6::icmpv6::{ICMP6HeaderOptions, ICMP6Type};
+    use crate::net::ieee802154::MacAddress;
+    use crate::net::ipv6::interface_addrs::InterfaceAddresses;
+    use crate::net::ipv6::ip_utils::IPAddr;
+    use crate::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+    use crate::net::ipv6::ipv6_send::IP6Sender;
+    use crate::net::ipv6::neighbor_cache::NeighborCache;
+    use crate::net::loopback::{
+        any_network_capability, link_local_addr, pending_icmp, send_icmp, LoopbackIP6Sender,
+    };
+    use core::cell::RefCell;
+    use host::harness::leak;
+    use host::mock::alarm::MockAlarm;
+    use kernel::hil::time::Alarm;
+    use kernel::ReturnCode;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Registrations {
+        results: RefCell<Vec<(IPAddr, ReturnCode)>>,
+    }
+
+    impl NeighborDiscoveryClient for Registrations {
+        fn registered(&self, addr: IPAddr, result: ReturnCode) {
+            self.results.borrow_mut().push((addr, result));
+        }
+    }
+
+    #[test]
+    pub fn neighbor_discovery_registers_with_router() {
+        let (host_addr, router_addr) = (link_local_addr(1), link_local_addr(2));
+        let eui64 = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut all_routers = IPAddr([0; 16]);
+        all_routers.0[0] = 0xff;
+        all_routers.0[1] = 0x02;
+        all_routers.0[15] = 2;
+        let mut remote_addr = IPAddr([0; 16]);
+        remote_addr.0[0] = 0x20;
+        remote_addr.0[15] = 9;
+
+        let host_sender = leak(LoopbackIP6Sender::new(host_addr));
+        let host_receiver = leak(IP6RecvStruct::new());
+        let router_sender = leak(LoopbackIP6Sender::new(router_addr));
+        let router_receiver = leak(IP6RecvStruct::new());
+        let alarm: &MockAlarm = leak(MockAlarm::new());
+        let cache = leak(NeighborCache::new());
+        let nd = leak(NeighborDiscovery::new(
+            host_sender,
+            alarm,
+            cache,
+            Box::leak(Box::new([0; TX_BUF_LEN])),
+            MacAddress::Short(0x0001),
+            eui64,
+            any_network_capability(),
+        ));
+        let registrations = leak(Registrations::default());
+        host_sender.set_client(nd);
+        alarm.set_client(nd);
+        assert_eq!(host_receiver.add_client(nd), ReturnCode::SUCCESS);
+        nd.set_client(registrations);
+
+        // Router solicitations are repeated until a router answers.
+        assert_eq!(nd.start(host_addr), ReturnCode::SUCCESS);
+        assert_eq!(nd.start(host_addr), ReturnCode::EALREADY);
+        assert_eq!(
+            pending_icmp(host_sender),
+            (all_routers, 133, vec![1, 1, 0, 1, 0, 0, 0, 0])
+        );
+        assert!(host_sender.transfer(router_receiver, false));
+        assert!(!alarm.advance(10 * 32768 - 1));
+        assert!(alarm.advance(1));
+        assert_eq!(pending_icmp(host_sender).1, 133);
+        assert!(host_sender.transfer(router_receiver, false));
+        assert_eq!(cache.next_hop(remote_addr), None);
+
+        // The advertisement makes the router the default router, and the host
+        // registers its address with it.
+        let router_lifetime = 1800;
+        send_icmp(
+            router_sender,
+            host_addr,
+            ICMP6Type::Type134,
+            ICMP6HeaderOptions::Type134 {
+                cur_hop_limit: 64,
+                flags: 0,
+                router_lifetime: router_lifetime,
+            },
+            &[0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0x12, 0x34, 0, 0, 0, 0],
+        );
+        assert!(router_sender.transfer(host_receiver, false));
+        assert_eq!(cache.next_hop(remote_addr), Some(MacAddress::Short(0x1234)));
+
+        let mut aro = vec![33, 2, 0, 0, 0, 0, 0, 15];
+        aro.extend_from_slice(&eui64);
+        let mut ns_body = host_addr.0.to_vec();
+        ns_body.extend_from_slice(&aro);
+        ns_body.extend_from_slice(&[1, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(pending_icmp(host_sender), (router_addr, 135, ns_body));
+        assert!(host_sender.transfer(router_receiver, false));
+
+        let mut na_body = host_addr.0.to_vec();
+        na_body.extend_from_slice(&aro);
+        let neighbor_advertisement = || {
+            send_icmp(
+                router_sender,
+                host_addr,
+                ICMP6Type::Type136,
+                ICMP6HeaderOptions::Type136 { flags: 0x6000_0000 },
+                &na_body,
+            );
+            assert!(router_sender.transfer(host_receiver, false));
+        };
+        neighbor_advertisement();
+        assert_eq!(
+            *registrations.results.borrow(),
+            [(host_addr, ReturnCode::SUCCESS)]
+        );
+
+        // The registration is refreshed after three quarters of its lifetime,
+        // without telling the client again.
+        assert!(!alarm.advance(675 * 32768 - 1));
+        assert!(alarm.advance(1));
+        assert_eq!(pending_icmp(host_sender).1, 135);
+        assert!(host_sender.transfer(router_receiver, false));
+        neighbor_advertisement();
+        assert_eq!(registrations.results.borrow().len(), 1);
+
+        // A router that stops acknowledging the registration is abandoned after
+        // three attempts.
+        assert!(alarm.advance_to_alarm());
+        for _ in 0..3 {
+            assert_eq!(pending_icmp(host_sender).1, 135);
+            assert!(host_sender.transfer(router_receiver, true));
+            assert!(alarm.advance_to_alarm());
+        }
+        assert_eq!(
+            *registrations.results.borrow(),
+            [
+                (host_addr, ReturnCode::SUCCESS),
+                (host_addr, ReturnCode::ENOACK)
+            ]
+        );
+        assert_eq!(cache.next_hop(remote_addr), None);
+        assert_eq!(pending_icmp(host_sender).1, 133);
+    }
+
+    #[test]
+    pub fn neighbor_discovery_autoconfigures_global_addresses() {
+        let eui64 = [1, 2, 3, 4, 5, 6, 7, 8];
+        let host_addr = IPAddr::generate_from_mac(MacAddress::Long(eui64));
+        let router_addr = link_local_addr(2);
+        let mut all_nodes = IPAddr([0; 16]);
+        all_nodes.0[0] = 0xff;
+        all_nodes.0[1] = 0x02;
+        all_nodes.0[15] = 1;
+        let prefix = [0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 0];
+        let mut global_addr = host_addr;
+        global_addr.0[..8].copy_from_slice(&prefix);
+
+        let host_sender = leak(LoopbackIP6Sender::new(host_addr));
+        let host_receiver = leak(IP6RecvStruct::new());
+        let router_sender = leak(LoopbackIP6Sender::new(router_addr));
+        let router_receiver = leak(IP6RecvStruct::new());
+        let alarm: &MockAlarm = leak(MockAlarm::new());
+        let cache = leak(NeighborCache::new());
+        let interface_addrs = leak(InterfaceAddresses::new());
+        assert_eq!(interface_addrs.add(host_addr, None), ReturnCode::SUCCESS);
+        host_receiver.set_interface_addresses(interface_addrs);
+        let nd = leak(NeighborDiscovery::new(
+            host_sender,
+            alarm,
+            cache,
+            Box::leak(Box::new([0; TX_BUF_LEN])),
+            MacAddress::Short(0x0001),
+            eui64,
+            any_network_capability(),
+        ));
+        let registrations = leak(Registrations::default());
+        host_sender.set_client(nd);
+        alarm.set_client(nd);
+        assert_eq!(host_receiver.add_client(nd), ReturnCode::SUCCESS);
+        nd.set_client(registrations);
+        nd.set_interface_addresses(interface_addrs);
+
+        assert_eq!(nd.start(host_addr), ReturnCode::SUCCESS);
+        assert!(host_sender.transfer(router_receiver, false));
+
+        let router_advertisement = |dst: IPAddr, valid_lifetime: u32| {
+            let mut body = vec![0; 8];
+            body.extend_from_slice(&[3, 4, 64, 0xc0]);
+            body.extend_from_slice(&valid_lifetime.to_be_bytes());
+            body.extend_from_slice(&valid_lifetime.to_be_bytes());
+            body.extend_from_slice(&[0; 4]);
+            body.extend_from_slice(&prefix);
+            body.extend_from_slice(&[0; 8]);
+            send_icmp(
+                router_sender,
+                dst,
+                ICMP6Type::Type134,
+                ICMP6HeaderOptions::Type134 {
+                    cur_hop_limit: 64,
+                    flags: 0,
+                    router_lifetime: 1800,
+                },
+                &body,
+            );
+            assert!(router_sender.transfer(host_receiver, false));
+        };
+
+        // Packets to addresses the interface does not hold are dropped.
+        let mut other_addr = global_addr;
+        other_addr.0[15] ^= 0xff;
+        router_advertisement(other_addr, 3600);
+        assert!(cache.lookup(router_addr).is_none());
+        assert_eq!(interface_addrs.iter().count(), 1);
+
+        // The advertised prefix forms a global address, which is registered
+        // instead of the link-local one.
+        router_advertisement(all_nodes, 3600);
+        assert!(cache.lookup(router_addr).is_some());
+        assert!(interface_addrs.contains(global_addr));
+        assert!(interface_addrs.contains(host_addr));
+        let (dst, icmp_type, body) = pending_icmp(host_sender);
+        assert_eq!((dst, icmp_type), (router_addr, 135));
+        assert_eq!(body[..16], global_addr.0);
+        assert!(host_sender.transfer(router_receiver, false));
+
+        let mut na_body = global_addr.0.to_vec();
+        na_body.extend_from_slice(&[33, 2, 0, 0, 0, 0, 0, 15]);
+        na_body.extend_from_slice(&eui64);
+        send_icmp(
+            router_sender,
+            global_addr,
+            ICMP6Type::Type136,
+            ICMP6HeaderOptions::Type136 { flags: 0x6000_0000 },
+            &na_body,
+        );
+        assert!(router_sender.transfer(host_receiver, false));
+        assert_eq!(
+            *registrations.results.borrow(),
+            [(global_addr, ReturnCode::SUCCESS)]
+        );
+
+        // Withdrawing the prefix removes the address, and the link-local address
+        // is registered again.
+        router_advertisement(global_addr, 0);
+        assert!(!interface_addrs.contains(global_addr));
+        let (dst, icmp_type, body) = pending_icmp(host_sender);
+        assert_eq!((dst, icmp_type), (router_addr, 135));
+        assert_eq!(body[..16], host_addr.0);
+    }
+}
@@ -1,9 +1,15 @@
+use crate::net::ipv6::ext_headers::{self, ExtHeaderError};
+use crate::net::ipv6::interface_addrs::InterfaceAddresses;
 use crate::net::ipv6::ipv6::IP6Header;
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
-use kernel::debug;
 use kernel::ReturnCode;
 
+/// Maximum number of clients of an `IP6RecvStruct`, e.g. one per transport
+/// protocol.
+pub const MAX_RECV_CLIENTS: usize = 6;
+
 // To provide some context for the entire rx chain:
 /*
 - The radio in the kernel has a single `RxClient`, which is set as the mac layer
@@ -21,66 +27,188 @@ use kernel::ReturnCode;
 - The udp_mac MacUser has a single receive client, which is the `sixlowpan_state` struct
 - `sixlowpan_state` has a single rx_client, which in our case is a single struct that
   implements the `ip_receive ` trait.
-- the `ip_receive` implementing struct (`IP6RecvStruct`) has a single client, which is
-  udp_recv, a `UDPReceive` struct.
+- the `ip_receive` implementing struct (`IP6RecvStruct`) passes every packet to all
+  of its clients, which filter them by next header. One of them is udp_recv, a
+  `MuxUdpReceiver` struct, and TCP adds a `MuxTcp`.
 - The UDPReceive struct is a field of the UDPDriver, which ultimately passes the
   packets up to userland.
 */
 
+/// Extension headers are processed by the receiver and not passed to clients:
+/// the `header` a client receives has the next header and payload length of
+/// the upper-layer header that starts `payload`.
 pub trait IP6RecvClient {
     fn receive(&self, header: IP6Header, payload: &[u8]);
+
+    /// Called when a received packet is dropped because it is malformed:
+    /// ESIZE if it is truncated or too large, EINVAL if its headers cannot be
+    /// decoded and FAIL if its transport checksum is wrong. Any other error
+    /// is passed on from the 6LoWPAN layer.
+    fn receive_error(&self, _error: ReturnCode) {}
+
+    /// Called when a received packet is dropped because of its extension
+    /// headers, and RFC 8200 asks for an ICMPv6 Parameter Problem message
+    /// with `code` and `pointer` to be sent to its source. `packet` is the
+    /// packet as received, starting with `header`. The client that sends
+    /// ICMPv6 messages sends it; `receive_error` is called as well.
+    fn parameter_problem(&self, _header: IP6Header, _packet: &[u8], _code: u8, _pointer: u32) {}
+}
+
+/// Receives the packets a router has to forward: those that are not
+/// addressed to the interface, and those addressed to it that carry an RPL
+/// source route with segments left.
+pub trait IP6ForwardClient {
+    /// `header` is the IPv6 header of the packet as received and `buf` holds
+    /// everything after it. `source_route` is the offset in `buf` of the
+    /// source route, if the packet has one with segments left.
+    fn forward(&self, header: IP6Header, buf: &[u8], source_route: Option<usize>);
 }
 
 /// Currently only one implementation of this trait should exist,
 /// as we do not multiplex received packets based on the address.
 /// The receiver receives IP packets destined for any local address.
-/// The receiver should drop any packets with destination addresses
+/// The receiver drops any packets with destination addresses
 /// that are not among the local addresses of this device.
+///
+/// Like the users of a `MuxMac`, every client receives every packet and is
+/// expected to ignore packets whose next header it does not handle.
+///
+/// Packets to other destinations are passed to the forward client instead, if
+/// one is set.
 pub trait IP6Receiver<'a> {
-    fn set_client(&self, client: &'a dyn IP6RecvClient);
+    /// Adds a client to receive packets. Returns ENOMEM if the receiver
+    /// already has `MAX_RECV_CLIENTS` clients.
+    fn add_client(&self, client: &'a dyn IP6RecvClient) -> ReturnCode;
 }
 
 pub struct IP6RecvStruct<'a> {
-    client: OptionalCell<&'a dyn IP6RecvClient>,
+    clients: [Cell<Option<&'a dyn IP6RecvClient>>; MAX_RECV_CLIENTS],
+    interface_addrs: OptionalCell<&'a InterfaceAddresses>,
+    forward_client: OptionalCell<&'a dyn IP6ForwardClient>,
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
-    fn set_client(&self, client: &'a dyn IP6RecvClient) {
-        self.client.set(client);
+    fn add_client(&self, client: &'a dyn IP6RecvClient) -> ReturnCode {
+        match self.clients.iter().find(|slot| slot.get().is_none()) {
+            Some(slot) => {
+                slot.set(Some(client));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
     }
 }
 
 impl<'a> IP6RecvStruct<'a> {
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
-            client: OptionalCell::empty(),
+            clients: Default::default(),
+            interface_addrs: OptionalCell::empty(),
+            forward_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the client that forwards the packets this node receives as a
+    /// router. Without one, those packets are dropped.
+    pub fn set_forward_client(&self, forward_client: &'a dyn IP6ForwardClient) {
+        self.forward_client.set(forward_client);
+    }
+
+    /// Sets the addresses of the interface. Packets to other destinations
+    /// are dropped. Until this is called, every packet is accepted.
+    pub fn set_interface_addresses(&self, interface_addrs: &'a InterfaceAddresses) {
+        self.interface_addrs.set(interface_addrs);
+    }
+}
+
+impl<'a> IP6RecvStruct<'a> {
+    fn receive_error(&self, error: ReturnCode) {
+        for client in self.clients.iter().filter_map(|slot| slot.get()) {
+            client.receive_error(error);
         }
     }
 }
 
 impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
     fn receive(&self, buf: &[u8], len: usize, result: ReturnCode) {
-        // TODO: Drop here?
-        if len > buf.len() || result != ReturnCode::SUCCESS {
+        if result != ReturnCode::SUCCESS {
+            self.receive_error(result);
+            return;
+        }
+        if len > buf.len() {
+            self.receive_error(ReturnCode::ESIZE);
             return;
         }
+        let buf = &buf[..len];
         match IP6Header::decode(buf).done() {
             Some((offset, ip6_header)) => {
-                let checksum_result = ip6_header.check_transport_checksum(&buf[offset..len]);
+                let payload_len = ip6_header.get_payload_len() as usize;
+                if payload_len > len - offset {
+                    self.receive_error(ReturnCode::ESIZE);
+                    return;
+                }
+                // Anything after the payload is link layer padding.
+                let len = offset + payload_len;
+                let buf = &buf[..len];
+                let dst_addr = ip6_header.get_dst_addr();
+                if !self
+                    .interface_addrs
+                    .map_or(true, |interface_addrs| interface_addrs.accepts(dst_addr))
+                {
+                    // Not for us.
+                    self.forward_client
+                        .map(|client| client.forward(ip6_header, &buf[offset..], None));
+                    return;
+                }
+                // Packets with unrecognized options, segments left to visit
+                // that are not forwarded or that need reassembly are
+                // dropped, and the clients are asked to send a Parameter
+                // Problem message where RFC 8200 requires one.
+                let (next_header, ext_len) = match ext_headers::parse(
+                    ip6_header.get_next_header(),
+                    dst_addr,
+                    &buf[offset..],
+                ) {
+                    Ok(upper_layer) => upper_layer,
+                    Err(ExtHeaderError::SourceRoute {
+                        offset: source_route,
+                    }) if self.forward_client.is_some() => {
+                        self.forward_client.map(|client| {
+                            client.forward(ip6_header, &buf[offset..], Some(source_route))
+                        });
+                        return;
+                    }
+                    Err(ExtHeaderError::ParameterProblem { code, pointer }) => {
+                        self.receive_error(ReturnCode::EINVAL);
+                        for client in self.clients.iter().filter_map(|slot| slot.get()) {
+                            client.parameter_problem(ip6_header, buf, code, pointer);
+                        }
+                        return;
+                    }
+                    Err(_) => {
+                        self.receive_error(ReturnCode::EINVAL);
+                        return;
+                    }
+                };
+                let mut ip6_header = ip6_header;
+                let offset = offset + ext_len;
+                if ext_len > 0 {
+                    ip6_header.set_next_header(next_header);
+                    ip6_header.set_payload_len((len - offset) as u16);
+                }
+                let checksum_result = ip6_header.check_transport_checksum(&buf[offset..]);
                 if checksum_result == ReturnCode::FAIL {
-                    debug!("cksum fail!: {:?}", checksum_result);
+                    self.receive_error(ReturnCode::FAIL);
                     return; //Dropped.
                 }
-                // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
+                // Note: Protocols for which checksum verification is not implemented
                 // are automatically assumed as fine, rather than dropped
 
-                self.client
-                    .map(|client| client.receive(ip6_header, &buf[offset..len]));
-            }
-            None => {
-                debug!("failed to decode ipv6 header");
-                // TODO: Report the error somewhere...
+                for client in self.clients.iter().filter_map(|slot| slot.get()) {
+                    client.receive(ip6_header, &buf[offset..]);
+                }
             }
+            None => self.receive_error(ReturnCode::EINVAL),
         }
     }
 }
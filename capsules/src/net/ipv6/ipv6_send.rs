@@ -18,8 +18,11 @@
 
 use crate::ieee802154::device::{MacDevice, TxClient};
 use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ext_headers::ExtHeaders;
+use crate::net::ipv6::interface_addrs::InterfaceAddresses;
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::ipv6::neighbor_cache::NeighborCache;
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
 use crate::net::sixlowpan::sixlowpan_state::TxState;
 use core::cell::Cell;
@@ -51,7 +54,9 @@ pub trait IP6Sender<'a> {
     fn set_client(&self, client: &'a dyn IP6SendClient);
 
     /// This method sets the source address for packets sent from the
-    /// `IP6Sender` instance.
+    /// `IP6Sender` instance. If it is the unspecified address, implementations
+    /// that know the addresses of their interface choose the source address
+    /// of each packet from them.
     ///
     /// # Arguments
     /// `src_addr` - `IPAddr` to set as the source address for packets sent
@@ -59,7 +64,8 @@ pub trait IP6Sender<'a> {
     fn set_addr(&self, src_addr: IPAddr);
 
     /// This method sets the gateway/next hop MAC address for this `IP6Sender`
-    /// instance.
+    /// instance. It is used for destinations the neighbor cache, if any,
+    /// cannot resolve.
     ///
     /// # Arguments
     /// `gateway` - MAC address to send the constructed packet to
@@ -86,6 +92,26 @@ pub trait IP6Sender<'a> {
         payload: &LeasableBuffer<'static, u8>,
         net_cap: &'static NetworkCapability,
     ) -> ReturnCode;
+
+    /// This method sends a packet received from another node on towards its
+    /// destination, as a router does. The transport checksum is left as it
+    /// was received.
+    ///
+    /// # Arguments
+    /// `header` - The `IP6Header` of the packet, with the hop limit already
+    /// decremented
+    /// `ext_headers` - The extension headers the packet was received with
+    /// `next_header` - The `ip6_nh` type of the transport header
+    /// `payload` - The transport header and payload of the packet
+    /// `next_hop` - MAC address of the neighbor to send the packet to
+    fn forward(
+        &self,
+        header: IP6Header,
+        ext_headers: ExtHeaders,
+        next_header: u8,
+        payload: &[u8],
+        next_hop: MacAddress,
+    ) -> ReturnCode;
 }
 
 /// This struct is a specific implementation of the `IP6Sender` trait. This
@@ -97,12 +123,14 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     // successful reception on receivers with slow copies out of the radio buffer
     // (imix)
     src_addr: Cell<IPAddr>,
+    ext_headers: Cell<ExtHeaders>,
+    interface_addrs: OptionalCell<&'a InterfaceAddresses>,
     gateway: Cell<MacAddress>,
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
+    neighbors: OptionalCell<&'a NeighborCache>,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
 }
@@ -135,15 +163,37 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return ReturnCode::FAIL;
         }
-        self.sixlowpan.init(
-            self.src_mac_addr,
-            self.dst_mac_addr,
-            self.radio.get_pan(),
-            None,
-        );
-        self.init_packet(dst, transport_header, payload);
-        let ret = self.send_next_fragment();
-        ret
+        let next_hop = self
+            .neighbors
+            .and_then(|neighbors| neighbors.next_hop(dst))
+            .unwrap_or(self.gateway.get());
+        self.sixlowpan
+            .init(self.src_mac_addr, next_hop, self.radio.get_pan(), None);
+        let ret = self.init_packet(dst, transport_header, payload);
+        if ret != ReturnCode::SUCCESS {
+            return ret;
+        }
+        self.send_next_fragment()
+    }
+
+    fn forward(
+        &self,
+        header: IP6Header,
+        ext_headers: ExtHeaders,
+        next_header: u8,
+        payload: &[u8],
+        next_hop: MacAddress,
+    ) -> ReturnCode {
+        self.sixlowpan
+            .init(self.src_mac_addr, next_hop, self.radio.get_pan(), None);
+        let ret = self
+            .ip6_packet
+            .map(|ip6_packet| ip6_packet.set_forwarded(header, ext_headers, next_header, payload))
+            .unwrap_or(ReturnCode::EBUSY);
+        if ret != ReturnCode::SUCCESS {
+            return ret;
+        }
+        self.send_next_fragment()
     }
 }
 
@@ -162,35 +212,64 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             ip6_packet: TakeCell::new(ip6_packet),
             alarm: alarm,
             src_addr: Cell::new(IPAddr::new()),
+            ext_headers: Cell::new(ExtHeaders::new()),
+            interface_addrs: OptionalCell::empty(),
             gateway: Cell::new(dst_mac_addr),
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
+            neighbors: OptionalCell::empty(),
             client: OptionalCell::empty(),
             ip_vis: ip_vis,
         }
     }
 
+    /// Sets the addresses of the interface, from which the source address of
+    /// each packet is chosen unless `set_addr` fixed it.
+    pub fn set_interface_addresses(&self, interface_addrs: &'a InterfaceAddresses) {
+        self.interface_addrs.set(interface_addrs);
+    }
+
+    /// Sets the extension headers sent with every subsequent packet, between
+    /// the IPv6 header and the transport header.
+    pub fn set_ext_headers(&self, ext_headers: ExtHeaders) {
+        self.ext_headers.set(ext_headers);
+    }
+
+    /// Sets the neighbor cache used to choose the MAC address of the next
+    /// hop. Without one, every packet is sent to the gateway.
+    pub fn set_neighbor_cache(&self, neighbors: &'a NeighborCache) {
+        self.neighbors.set(neighbors);
+    }
+
+    // Returns ESIZE if the payload does not fit in the packet
     fn init_packet(
         &self,
         dst_addr: IPAddr,
         transport_header: TransportHeader,
         payload: &LeasableBuffer<'static, u8>,
-    ) {
-        self.ip6_packet.map_or_else(
-            || {
-                debug!("init packet failed.");
-            },
-            |ip6_packet| {
+    ) -> ReturnCode {
+        self.ip6_packet
+            .map(|ip6_packet| {
                 ip6_packet.header = IP6Header::default();
-                ip6_packet.header.src_addr = self.src_addr.get();
+                let src_addr = self.src_addr.get();
+                ip6_packet.header.src_addr = if src_addr.is_unspecified() {
+                    self.interface_addrs
+                        .and_then(|interface_addrs| interface_addrs.select_source(dst_addr))
+                        .unwrap_or(src_addr)
+                } else {
+                    src_addr
+                };
                 ip6_packet.header.dst_addr = dst_addr;
-                ip6_packet.set_payload(transport_header, payload);
-                ip6_packet.set_transport_checksum();
-            },
-        );
+                ip6_packet.ext_headers = self.ext_headers.get();
+                let ret = ip6_packet.set_payload(transport_header, payload);
+                if ret == ReturnCode::SUCCESS {
+                    ip6_packet.set_transport_checksum();
+                }
+                ret
+            })
+            .unwrap_or(ReturnCode::EBUSY)
     }
 
     // Returns EBUSY if the tx_buf is not there
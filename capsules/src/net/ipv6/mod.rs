@@ -1,4 +1,9 @@
+pub mod ext_headers;
+pub mod interface_addrs;
 pub mod ip_utils;
 pub mod ipv6;
+pub mod ipv6_nd;
 pub mod ipv6_recv;
 pub mod ipv6_send;
+pub mod neighbor_cache;
+pub mod rpl;
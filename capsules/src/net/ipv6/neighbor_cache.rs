@@ -0,0 +1,205 @@
+//! This file contains a small neighbor cache (RFC 4861, section 5.1) mapping
+//! the IPv6 addresses of on-link neighbors to their 15.4 MAC addresses.
+//!
+//! Following 6LoWPAN-ND (RFC 6775), hosts do not resolve addresses by
+//! multicasting neighbor solicitations. Entries are instead learned from
+//! router advertisements and address registration, and link-local addresses
+//! are resolved directly from their interface identifier. The cache is filled
+//! by [NeighborDiscovery](../ipv6_nd/struct.NeighborDiscovery.html) and
+//! consulted by `IP6SendStruct` to choose the MAC address of the next hop.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let neighbor_cache = static_init!(NeighborCache, NeighborCache::new());
+//! ip_send.set_neighbor_cache(neighbor_cache);
+//! ```
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use core::cell::Cell;
+use kernel::ReturnCode;
+
+/// The number of neighbors the cache can hold.
+pub const NEIGHBOR_CACHE_SIZE: usize = 8;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Neighbor {
+    pub ip: IPAddr,
+    pub mac: MacAddress,
+    pub is_router: bool,
+    /// The time, in alarm tics, at which the entry expires.
+    pub expires: u32,
+}
+
+pub struct NeighborCache {
+    entries: [Cell<Option<Neighbor>>; NEIGHBOR_CACHE_SIZE],
+}
+
+impl NeighborCache {
+    pub fn new() -> NeighborCache {
+        NeighborCache {
+            entries: Default::default(),
+        }
+    }
+
+    /// Adds a neighbor, replacing any entry for the same address. When the
+    /// cache is full, an entry for a host is evicted to make room. Returns
+    /// ENOMEM if every entry is a router.
+    pub fn insert(&self, neighbor: Neighbor) -> ReturnCode {
+        let slot = self
+            .entries
+            .iter()
+            .find(|slot| slot.get().map_or(false, |entry| entry.ip == neighbor.ip))
+            .or_else(|| self.entries.iter().find(|slot| slot.get().is_none()))
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .find(|slot| slot.get().map_or(false, |entry| !entry.is_router))
+            });
+        match slot {
+            Some(slot) => {
+                slot.set(Some(neighbor));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    pub fn remove(&self, ip: IPAddr) {
+        self.entries
+            .iter()
+            .filter(|slot| slot.get().map_or(false, |entry| entry.ip == ip))
+            .for_each(|slot| slot.set(None));
+    }
+
+    pub fn lookup(&self, ip: IPAddr) -> Option<Neighbor> {
+        self.entries
+            .iter()
+            .filter_map(|slot| slot.get())
+            .find(|entry| entry.ip == ip)
+    }
+
+    /// The router used for destinations that are not on-link.
+    pub fn default_router(&self) -> Option<Neighbor> {
+        self.entries
+            .iter()
+            .filter_map(|slot| slot.get())
+            .find(|entry| entry.is_router)
+    }
+
+    /// Removes the entries that expired at or before `now`.
+    pub fn expire(&self, now: u32) {
+        self.entries
+            .iter()
+            .filter(|slot| {
+                slot.get()
+                    .map_or(false, |entry| entry.expires.wrapping_sub(now) as i32 <= 0)
+            })
+            .for_each(|slot| slot.set(None));
+    }
+
+    /// The earliest expiry of any entry.
+    pub fn next_expiry(&self, now: u32) -> Option<u32> {
+        self.entries
+            .iter()
+            .filter_map(|slot| slot.get())
+            .min_by_key(|entry| entry.expires.wrapping_sub(now) as i32)
+            .map(|entry| entry.expires)
+    }
+
+    /// The MAC address to send a packet for `dst` to. Multicast packets are
+    /// broadcast, neighbors in the cache are sent to directly, link-local
+    /// addresses are resolved from their interface identifier and everything
+    /// else goes through the default router. Returns None if there is no
+    /// default router.
+    pub fn next_hop(&self, dst: IPAddr) -> Option<MacAddress> {
+        if dst.is_multicast() {
+            Some(MacAddress::Short(0xffff))
+        } else if let Some(neighbor) = self.lookup(dst) {
+            Some(neighbor.mac)
+        } else if dst.is_unicast_link_local() {
+            Some(dst.mac_from_iid())
+        } else {
+            self.default_router().map(|router| router.mac)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Neighbor, NeighborCache, NEIGHBOR_CACHE_SIZE};
+    use crate::net::ieee802154::MacAddress;
+    use crate::net::ipv6::ip_utils::IPAddr;
+    use kernel::ReturnCode;
+
+    fn global_addr(last: u8) -> IPAddr {
+        let mut addr = IPAddr([0; 16]);
+        addr.0[0] = 0x20;
+        addr.0[1] = 0x01;
+        addr.0[15] = last;
+        addr
+    }
+
+    fn neighbor(last: u8, is_router: bool, expires: u32) -> Neighbor {
+        Neighbor {
+            ip: global_addr(last),
+            mac: MacAddress::Short(last as u16),
+            is_router: is_router,
+            expires: expires,
+        }
+    }
+
+    #[test]
+    pub fn next_hop_prefers_cache_then_iid_then_router() {
+        let cache = NeighborCache::new();
+        let link_local = IPAddr::generate_from_mac(MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(
+            cache.next_hop(link_local),
+            Some(MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]))
+        );
+        assert_eq!(
+            cache.next_hop(IPAddr::generate_from_mac(MacAddress::Short(0xbeef))),
+            Some(MacAddress::Short(0xbeef))
+        );
+        let mut all_nodes = IPAddr([0; 16]);
+        all_nodes.0[0] = 0xff;
+        all_nodes.0[1] = 0x02;
+        all_nodes.0[15] = 1;
+        assert_eq!(cache.next_hop(all_nodes), Some(MacAddress::Short(0xffff)));
+        assert_eq!(cache.next_hop(global_addr(9)), None);
+
+        assert_eq!(cache.insert(neighbor(1, true, 100)), ReturnCode::SUCCESS);
+        assert_eq!(cache.insert(neighbor(9, false, 100)), ReturnCode::SUCCESS);
+        assert_eq!(cache.next_hop(global_addr(9)), Some(MacAddress::Short(9)));
+        assert_eq!(cache.next_hop(global_addr(5)), Some(MacAddress::Short(1)));
+    }
+
+    #[test]
+    pub fn full_cache_keeps_routers_and_expires_entries() {
+        let cache = NeighborCache::new();
+        for i in 0..NEIGHBOR_CACHE_SIZE as u8 {
+            assert_eq!(
+                cache.insert(neighbor(i, i != 3, 100 + i as u32)),
+                ReturnCode::SUCCESS
+            );
+        }
+        // Only the host is evicted.
+        assert_eq!(cache.insert(neighbor(50, true, 10)), ReturnCode::SUCCESS);
+        assert_eq!(cache.lookup(global_addr(3)), None);
+        assert_eq!(cache.insert(neighbor(51, true, 10)), ReturnCode::ENOMEM);
+
+        // Replacing an entry does not need room.
+        assert_eq!(cache.insert(neighbor(50, true, 20)), ReturnCode::SUCCESS);
+        assert_eq!(cache.next_expiry(0), Some(20));
+
+        cache.expire(100);
+        assert_eq!(cache.lookup(global_addr(50)), None);
+        assert_eq!(cache.lookup(global_addr(0)), None);
+        assert!(cache.lookup(global_addr(1)).is_some());
+        cache.remove(global_addr(1));
+        assert_eq!(cache.lookup(global_addr(1)), None);
+        assert_eq!(cache.next_expiry(0), Some(102));
+    }
+}
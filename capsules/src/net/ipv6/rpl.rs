@@ -0,0 +1,1436 @@
+//! This file contains an implementation of RPL, the IPv6 Routing Protocol for
+//! Low-Power and Lossy Networks (RFC 6550), for a router that joins a
+//! Destination-Oriented DAG (DODAG) operating in non-storing mode.
+//!
+//! Once started, `Rpl` multicasts DODAG Information Solicitations (DIS) until
+//! it hears a DODAG Information Object (DIO). It then joins the DODAG, forms a
+//! global address from the prefix advertised in the DIO and keeps a small set
+//! of candidate parents. Objective Function Zero (RFC 6552) computes the rank
+//! the node would have through each of them, and the one giving the lowest
+//! rank becomes the preferred parent, which is kept on ties. The preferred
+//! parent is the default router in the
+//! [NeighborCache](../neighbor_cache/struct.NeighborCache.html), so packets
+//! for destinations that are not on-link are sent towards the root. A node
+//! that loses every parent advertises an infinite rank, so that its children
+//! look for other parents, and starts soliciting again.
+//!
+//! DIOs are multicast on a Trickle timer (RFC 6206), reset whenever the
+//! preferred parent changes, the DODAG is rebuilt with a new version or a
+//! neighbor solicits DIOs.
+//!
+//! In non-storing mode, only the root keeps downward routes. The node reports
+//! its global address and its preferred parent to the root in Destination
+//! Advertisement Objects (DAO), retransmitted until the root acknowledges
+//! them and refreshed before the route expires. The root then reaches the
+//! node with source routes (RFC 6554).
+//!
+//! As the forward client of the IPv6 receiver, `Rpl` forwards packets for
+//! other nodes: packets carrying a source route to the next address on the
+//! route, and other packets up to the preferred parent. Forwarded packets are
+//! sent through the same `IP6Sender` as RPL messages, and are dropped while
+//! it is busy.
+//!
+//! Only the OF0 objective function is implemented, with the default step of
+//! rank since link quality is not measured. The node does not act as a root,
+//! does not insert RPL Options (RFC 6553) into the packets it sends and does
+//! not support storing mode, DODAG metric containers or secured messages.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let rpl = static_init!(
+//!     Rpl<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     Rpl::new(
+//!         rpl_ip_send,
+//!         rpl_alarm,
+//!         neighbor_cache,
+//!         &mut RPL_BUF,
+//!         src_mac_addr,
+//!         eui64,
+//!         net_cap,
+//!     )
+//! );
+//! rpl_ip_send.set_client(rpl);
+//! rpl_alarm.set_client(rpl);
+//! ip_receive.add_client(rpl);
+//! ip_receive.set_forward_client(rpl);
+//! rpl.set_interface_addresses(interface_addrs);
+//! rpl.start();
+//! ```
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ext_headers::ExtHeaders;
+use crate::net::ipv6::interface_addrs::InterfaceAddresses;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_recv::{IP6ForwardClient, IP6RecvClient};
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::neighbor_cache::{Neighbor, NeighborCache};
+use crate::net::network_capabilities::NetworkCapability;
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// Seconds between solicitations while the node has not joined a DODAG.
+pub const DIS_INTERVAL: u32 = 10;
+/// Seconds between a change of parent and the DAO reporting it.
+pub const DAO_DELAY: u32 = 1;
+/// Seconds to wait for the acknowledgement of a DAO.
+pub const DAO_ACK_TIMEOUT: u32 = 4;
+/// DAOs sent before giving up on an acknowledgement.
+pub const MAX_DAO_TRANSMISSIONS: u8 = 3;
+/// The number of candidate parents kept.
+pub const MAX_PARENTS: usize = 3;
+/// The rank of a node that cannot reach the root.
+pub const INFINITE_RANK: u16 = 0xffff;
+
+/// The smallest transmit buffer that fits every message sent: a DIO with a
+/// DODAG configuration and a prefix information option.
+pub const TX_BUF_LEN: usize = 68;
+
+/// The all-RPL-nodes link-local multicast address, ff02::1a.
+pub const ALL_RPL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a]);
+
+/// Codes of the RPL control message (RFC 6550, section 6).
+pub mod rpl_code {
+    pub const DIS: u8 = 0x00;
+    pub const DIO: u8 = 0x01;
+    pub const DAO: u8 = 0x02;
+    pub const DAO_ACK: u8 = 0x03;
+}
+
+// RPL control message option types (RFC 6550, section 6.7).
+const OPT_PAD1: u8 = 0;
+const OPT_DODAG_CONF: u8 = 4;
+const OPT_TARGET: u8 = 5;
+const OPT_TRANSIT: u8 = 6;
+const OPT_PREFIX_INFO: u8 = 8;
+
+// DIO flags (RFC 6550, section 6.3.1).
+const DIO_GROUNDED: u8 = 0x80;
+const MOP_NON_STORING: u8 = 1;
+
+// DAO flags (RFC 6550, section 6.4.1).
+const DAO_ACK_REQUESTED: u8 = 0x80;
+const DAO_DODAG_ID: u8 = 0x40;
+
+// Prefix information option flags (RFC 4861, section 4.6.2).
+const PIO_AUTONOMOUS: u8 = 0x40;
+
+/// The length of the DIO base after the first four bytes, which the ICMPv6
+/// header carries.
+const DIO_BODY_LEN: usize = 20;
+
+// Objective Function Zero (RFC 6552).
+const OCP_OF0: u16 = 0;
+const RANK_FACTOR: u16 = 1;
+const STEP_OF_RANK: u16 = 3;
+const RANK_STRETCH: u16 = 0;
+
+/// The parameters of a DODAG, from its DODAG Configuration option (RFC
+/// 6550, section 6.7.6).
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Config {
+    dio_int_doublings: u8,
+    dio_int_min: u8,
+    dio_redundancy: u8,
+    max_rank_increase: u16,
+    min_hop_rank_increase: u16,
+    ocp: u16,
+    default_lifetime: u8,
+    lifetime_unit: u16,
+}
+
+impl Config {
+    /// The defaults of RFC 6550, section 17, used until a DODAG
+    /// Configuration option is received.
+    const DEFAULT: Config = Config {
+        dio_int_doublings: 20,
+        dio_int_min: 3,
+        dio_redundancy: 10,
+        max_rank_increase: 7 * 256,
+        min_hop_rank_increase: 256,
+        ocp: OCP_OF0,
+        default_lifetime: 30,
+        lifetime_unit: 60,
+    };
+
+    const OPTION_LEN: usize = 16;
+
+    fn decode(option: &[u8]) -> Config {
+        Config {
+            dio_int_doublings: option[3],
+            dio_int_min: option[4],
+            dio_redundancy: option[5],
+            max_rank_increase: u16::from_be_bytes([option[6], option[7]]),
+            min_hop_rank_increase: u16::from_be_bytes([option[8], option[9]]),
+            ocp: u16::from_be_bytes([option[10], option[11]]),
+            default_lifetime: option[13],
+            lifetime_unit: u16::from_be_bytes([option[14], option[15]]),
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = OPT_DODAG_CONF;
+        buf[1] = (Self::OPTION_LEN - 2) as u8;
+        buf[2] = 0;
+        buf[3] = self.dio_int_doublings;
+        buf[4] = self.dio_int_min;
+        buf[5] = self.dio_redundancy;
+        buf[6..8].copy_from_slice(&self.max_rank_increase.to_be_bytes());
+        buf[8..10].copy_from_slice(&self.min_hop_rank_increase.to_be_bytes());
+        buf[10..12].copy_from_slice(&self.ocp.to_be_bytes());
+        buf[12] = 0;
+        buf[13] = self.default_lifetime;
+        buf[14..16].copy_from_slice(&self.lifetime_unit.to_be_bytes());
+    }
+
+    /// The lifetime of routes, in seconds.
+    fn route_lifetime(&self) -> u32 {
+        self.default_lifetime as u32 * self.lifetime_unit as u32
+    }
+
+    /// The shortest Trickle interval, in milliseconds.
+    fn imin(&self) -> u32 {
+        1u32.checked_shl(self.dio_int_min as u32)
+            .unwrap_or(u32::max_value())
+    }
+
+    /// The longest Trickle interval, in milliseconds.
+    fn imax(&self) -> u32 {
+        (self.imin() as u64)
+            .checked_shl(self.dio_int_doublings as u32)
+            .map_or(u32::max_value(), |imax| {
+                cmp::min(imax, u32::max_value() as u64) as u32
+            })
+    }
+
+    /// The rank of a node whose preferred parent has `parent_rank`, under
+    /// Objective Function Zero (RFC 6552, section 4.1).
+    fn rank_through(&self, parent_rank: u16) -> u16 {
+        let increase =
+            (RANK_FACTOR * STEP_OF_RANK + RANK_STRETCH) as u32 * self.min_hop_rank_increase as u32;
+        cmp::min(parent_rank as u32 + increase, INFINITE_RANK as u32) as u16
+    }
+
+    /// The integer part of `rank`, which orders nodes in the DODAG (RFC
+    /// 6550, section 3.5.1).
+    fn dag_rank(&self, rank: u16) -> u16 {
+        rank / cmp::max(self.min_hop_rank_increase, 1)
+    }
+}
+
+/// The DODAG the node has joined.
+#[derive(Copy, Clone, PartialEq)]
+struct Dodag {
+    instance: u8,
+    id: IPAddr,
+    version: u8,
+    grounded: bool,
+    config: Config,
+    /// The last prefix information option received from the preferred
+    /// parent, readvertised to children.
+    prefix: Option<[u8; 32]>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+struct Parent {
+    /// The link-local address the parent sends DIOs from.
+    addr: IPAddr,
+    rank: u16,
+    dtsn: u8,
+}
+
+/// The state of the Trickle timer driving DIOs (RFC 6206, section 4.2).
+#[derive(Copy, Clone)]
+struct Trickle {
+    /// The length of the current interval, in milliseconds.
+    interval: u32,
+    /// Consistent DIOs heard during the interval.
+    counter: u8,
+    /// When to send a DIO in this interval, unless it was already sent.
+    transmit_at: Option<u32>,
+    ends_at: u32,
+}
+
+/// The client of an `Rpl`, told whether the root has a route to the node.
+pub trait RplClient {
+    /// Called with SUCCESS once the root acknowledges a DAO for `addr`.
+    /// Afterwards, called with FAIL if the root rejects a DAO, and ENOACK if
+    /// it stopped acknowledging them.
+    fn route_registered(&self, addr: IPAddr, result: ReturnCode);
+}
+
+pub struct Rpl<'a, A: time::Alarm<'a>> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    cache: &'a NeighborCache,
+    tx_buf: TakeCell<'static, [u8]>,
+    sending: Cell<bool>,
+    src_mac: MacAddress,
+    eui64: [u8; 8],
+    interface_addrs: OptionalCell<&'a InterfaceAddresses>,
+    started: Cell<bool>,
+    dodag: Cell<Option<Dodag>>,
+    rank: Cell<u16>,
+    /// The lowest rank the node had in this version of the DODAG, which
+    /// bounds how far its rank may grow.
+    lowest_rank: Cell<u16>,
+    dtsn: Cell<u8>,
+    parents: [Cell<Option<Parent>>; MAX_PARENTS],
+    preferred: Cell<Option<IPAddr>>,
+    /// The global address formed from the prefix of the DODAG.
+    global_addr: Cell<Option<IPAddr>>,
+    trickle: Cell<Option<Trickle>>,
+    /// When the next DIS is due.
+    dis_deadline: Cell<Option<u32>>,
+    /// When the next DAO is due.
+    dao_deadline: Cell<Option<u32>>,
+    dao_seq: Cell<u8>,
+    dao_tries: Cell<u8>,
+    awaiting_ack: Cell<bool>,
+    /// Whether the root acknowledged the last DAO.
+    registered: Cell<bool>,
+    rng: Cell<u32>,
+    client: OptionalCell<&'a dyn RplClient>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> Rpl<'a, A> {
+    /// `tx_buf` must be at least `TX_BUF_LEN` bytes long. `eui64` is the
+    /// 802.15.4 long address of the interface, from which the global address
+    /// is formed; it also seeds the random Trickle intervals.
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        cache: &'a NeighborCache,
+        tx_buf: &'static mut [u8],
+        src_mac: MacAddress,
+        eui64: [u8; 8],
+        net_cap: &'static NetworkCapability,
+    ) -> Rpl<'a, A> {
+        let seed = u32::from_be_bytes([eui64[4], eui64[5], eui64[6], eui64[7]])
+            ^ u32::from_be_bytes([eui64[0], eui64[1], eui64[2], eui64[3]]);
+        Rpl {
+            ip_sender: ip_sender,
+            alarm: alarm,
+            cache: cache,
+            tx_buf: TakeCell::new(tx_buf),
+            sending: Cell::new(false),
+            src_mac: src_mac,
+            eui64: eui64,
+            interface_addrs: OptionalCell::empty(),
+            started: Cell::new(false),
+            dodag: Cell::new(None),
+            rank: Cell::new(INFINITE_RANK),
+            lowest_rank: Cell::new(INFINITE_RANK),
+            dtsn: Cell::new(0),
+            parents: Default::default(),
+            preferred: Cell::new(None),
+            global_addr: Cell::new(None),
+            trickle: Cell::new(None),
+            dis_deadline: Cell::new(None),
+            dao_deadline: Cell::new(None),
+            dao_seq: Cell::new(0),
+            dao_tries: Cell::new(0),
+            awaiting_ack: Cell::new(false),
+            registered: Cell::new(false),
+            rng: Cell::new(if seed == 0 { 1 } else { seed }),
+            client: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn RplClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the address table of the interface, to which the global address
+    /// and the all-RPL-nodes group are added. Without it, DIOs sent to the
+    /// group are not received.
+    pub fn set_interface_addresses(&self, interface_addrs: &'a InterfaceAddresses) {
+        self.interface_addrs.set(interface_addrs);
+    }
+
+    /// Starts soliciting DIOs to join a DODAG. Returns EALREADY if RPL is
+    /// already running.
+    pub fn start(&self) -> ReturnCode {
+        if self.started.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.started.set(true);
+        self.interface_addrs
+            .map(|interface_addrs| interface_addrs.add(ALL_RPL_NODES, None));
+        self.solicit();
+        self.reschedule();
+        ReturnCode::SUCCESS
+    }
+
+    /// Stops RPL and leaves the DODAG without poisoning it.
+    pub fn stop(&self) {
+        self.started.set(false);
+        self.leave();
+        self.dis_deadline.set(None);
+        self.reschedule();
+    }
+
+    /// The rank of the node, INFINITE_RANK if it has not joined a DODAG.
+    pub fn rank(&self) -> u16 {
+        self.rank.get()
+    }
+
+    /// The DODAGID, the address of the root, of the DODAG the node joined.
+    pub fn dodag_id(&self) -> Option<IPAddr> {
+        self.dodag.get().map(|dodag| dodag.id)
+    }
+
+    /// The link-local address of the preferred parent.
+    pub fn preferred_parent(&self) -> Option<IPAddr> {
+        self.preferred.get()
+    }
+
+    /// Converts seconds to tics, clamped so that deadlines compare correctly
+    /// across wraparound.
+    fn tics(secs: u32) -> u32 {
+        let tics = secs as u64 * <A::Frequency>::frequency() as u64;
+        cmp::min(tics, i32::max_value() as u64) as u32
+    }
+
+    fn tics_ms(ms: u32) -> u32 {
+        let tics = ms as u64 * <A::Frequency>::frequency() as u64 / 1000;
+        cmp::min(tics, i32::max_value() as u64) as u32
+    }
+
+    fn deadline(&self, tics: u32) -> Option<u32> {
+        Some(self.alarm.now().wrapping_add(tics))
+    }
+
+    /// A xorshift generator; Trickle only needs the nodes of a neighborhood
+    /// to pick different times.
+    fn random(&self) -> u32 {
+        let mut x = self.rng.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng.set(x);
+        x
+    }
+
+    fn link_local_addr(&self) -> IPAddr {
+        IPAddr::generate_from_mac(self.src_mac)
+    }
+
+    /// The address of `parent` under the prefix of the DODAG, which DAOs
+    /// report to the root.
+    fn global_addr_of(&self, parent: IPAddr) -> Option<IPAddr> {
+        let prefix = self.dodag.get()?.prefix?;
+        if prefix[2] > 128 {
+            return None;
+        }
+        let mut addr = parent;
+        addr.set_prefix(&prefix[16..32], prefix[2]);
+        Some(addr)
+    }
+
+    /// The MAC address of the on-link neighbor `addr`.
+    fn neighbor_mac(&self, addr: IPAddr) -> MacAddress {
+        self.cache
+            .lookup(addr)
+            .map_or_else(|| addr.mac_from_iid(), |neighbor| neighbor.mac)
+    }
+
+    fn solicit(&self) {
+        self.dis_deadline
+            .set(self.deadline(Self::tics(DIS_INTERVAL)));
+        // The flags and reserved byte are followed by a PadN option, so that
+        // the base fills the ICMPv6 header.
+        self.send(
+            self.link_local_addr(),
+            ALL_RPL_NODES,
+            rpl_code::DIS,
+            0x0000_0100,
+            |_| 0,
+        );
+    }
+
+    fn send_dio(&self, dst: IPAddr) {
+        let dodag = match self.dodag.get() {
+            Some(dodag) => dodag,
+            None => return,
+        };
+        let base =
+            (dodag.instance as u32) << 24 | (dodag.version as u32) << 16 | self.rank.get() as u32;
+        let flags = if dodag.grounded { DIO_GROUNDED } else { 0 } | MOP_NON_STORING << 3;
+        let dtsn = self.dtsn.get();
+        self.send(self.link_local_addr(), dst, rpl_code::DIO, base, |buf| {
+            buf[0] = flags;
+            buf[1] = dtsn;
+            buf[2] = 0;
+            buf[3] = 0;
+            buf[4..DIO_BODY_LEN].copy_from_slice(&dodag.id.0);
+            let mut len = DIO_BODY_LEN;
+            dodag.config.encode(&mut buf[len..len + Config::OPTION_LEN]);
+            len += Config::OPTION_LEN;
+            if let Some(prefix) = dodag.prefix {
+                buf[len..len + prefix.len()].copy_from_slice(&prefix);
+                len += prefix.len();
+            }
+            len
+        });
+    }
+
+    /// Sends a DAO reporting the global address of the node and of its
+    /// preferred parent to the root.
+    fn send_dao(&self) {
+        let (dodag, target, parent) = match (
+            self.dodag.get(),
+            self.global_addr.get(),
+            self.preferred
+                .get()
+                .and_then(|addr| self.global_addr_of(addr)),
+        ) {
+            (Some(dodag), Some(target), Some(parent)) => (dodag, target, parent),
+            _ => return,
+        };
+        let seq = self.dao_seq.get().wrapping_add(1);
+        self.dao_seq.set(seq);
+        self.awaiting_ack.set(true);
+        self.dao_deadline
+            .set(self.deadline(Self::tics(DAO_ACK_TIMEOUT)));
+
+        let base = (dodag.instance as u32) << 24
+            | ((DAO_ACK_REQUESTED | DAO_DODAG_ID) as u32) << 16
+            | seq as u32;
+        self.send(target, dodag.id, rpl_code::DAO, base, |buf| {
+            buf[..16].copy_from_slice(&dodag.id.0);
+            let target_opt = &mut buf[16..36];
+            target_opt[0] = OPT_TARGET;
+            target_opt[1] = 18;
+            target_opt[2] = 0;
+            target_opt[3] = 128;
+            target_opt[4..20].copy_from_slice(&target.0);
+            let transit = &mut buf[36..58];
+            transit[0] = OPT_TRANSIT;
+            transit[1] = 20;
+            transit[2] = 0;
+            transit[3] = 0;
+            transit[4] = seq;
+            transit[5] = dodag.config.default_lifetime;
+            transit[6..22].copy_from_slice(&parent.0);
+            58
+        });
+    }
+
+    /// Sends an RPL control message whose body, after the base carried in
+    /// the ICMPv6 header, is written by `fill`, which returns its length.
+    /// Nothing is sent if a message is still being sent; the timers cover
+    /// for it.
+    fn send<F: FnOnce(&mut [u8]) -> usize>(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        code: u8,
+        base: u32,
+        fill: F,
+    ) {
+        if self.sending.get() {
+            return;
+        }
+        let buf = match self.tx_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let len = fill(buf);
+
+        let mut header = ICMP6Header::new(ICMP6Type::Type155);
+        header.set_code(code);
+        header.set_options(ICMP6HeaderOptions::Type155 { base: base });
+        let mut payload = LeasableBuffer::new(buf);
+        payload.slice(..len);
+        self.ip_sender.set_addr(src);
+        // The sender may complete synchronously, so this is set first.
+        self.sending.set(true);
+        let result =
+            self.ip_sender
+                .send_to(dst, TransportHeader::ICMP(header), &payload, self.net_cap);
+        self.tx_buf.replace(payload.take());
+        if result != ReturnCode::SUCCESS {
+            self.sending.set(false);
+        }
+    }
+
+    fn report(&self, result: ReturnCode) {
+        let was_registered = self.registered.replace(result == ReturnCode::SUCCESS);
+        if result != ReturnCode::SUCCESS || !was_registered {
+            if let Some(addr) = self.global_addr.get() {
+                self.client
+                    .map(|client| client.route_registered(addr, result));
+            }
+        }
+    }
+
+    /// Starts a new Trickle interval of `interval` milliseconds.
+    fn trickle_begin(&self, interval: u32) {
+        let half = interval / 2;
+        let transmit = half + self.random() % cmp::max(interval - half, 1);
+        self.trickle.set(Some(Trickle {
+            interval: interval,
+            counter: 0,
+            transmit_at: self.deadline(Self::tics_ms(transmit)),
+            ends_at: self.alarm.now().wrapping_add(Self::tics_ms(interval)),
+        }));
+    }
+
+    /// Restarts Trickle with the shortest interval after an inconsistency,
+    /// unless it is already running with it.
+    fn trickle_reset(&self) {
+        let imin = match self.dodag.get() {
+            Some(dodag) => dodag.config.imin(),
+            None => return,
+        };
+        match self.trickle.get() {
+            Some(trickle) if trickle.interval == imin => {}
+            _ => self.trickle_begin(imin),
+        }
+    }
+
+    fn trickle_expired(&self, now: u32) {
+        let (mut trickle, config) = match (self.trickle.get(), self.dodag.get()) {
+            (Some(trickle), Some(dodag)) => (trickle, dodag.config),
+            _ => return,
+        };
+        if is_due(trickle.transmit_at, now) {
+            trickle.transmit_at = None;
+            self.trickle.set(Some(trickle));
+            if config.dio_redundancy == 0 || trickle.counter < config.dio_redundancy {
+                self.send_dio(ALL_RPL_NODES);
+            }
+        }
+        if is_due(Some(trickle.ends_at), now) {
+            self.trickle_begin(cmp::min(trickle.interval.saturating_mul(2), config.imax()));
+        }
+    }
+
+    /// Sends a DAO after `DAO_DELAY`, so that changes made together are
+    /// reported once.
+    fn schedule_dao(&self) {
+        self.dao_tries.set(0);
+        self.awaiting_ack.set(false);
+        self.dao_deadline.set(self.deadline(Self::tics(DAO_DELAY)));
+    }
+
+    fn dao_expired(&self) {
+        if !self.awaiting_ack.get() {
+            self.dao_tries.set(0);
+            self.send_dao();
+        } else if self.dao_tries.get() + 1 < MAX_DAO_TRANSMISSIONS {
+            self.dao_tries.set(self.dao_tries.get() + 1);
+            self.send_dao();
+        } else {
+            self.awaiting_ack.set(false);
+            self.refresh_dao_later();
+            self.report(ReturnCode::ENOACK);
+        }
+    }
+
+    /// Sends the next DAO once three quarters of the route lifetime have
+    /// passed.
+    fn refresh_dao_later(&self) {
+        let lifetime = self
+            .dodag
+            .get()
+            .map_or(Config::DEFAULT, |dodag| dodag.config)
+            .route_lifetime();
+        self.dao_deadline
+            .set(self.deadline(Self::tics(lifetime / 4 * 3)));
+    }
+
+    /// Forms, refreshes or removes the global address for the prefix in a
+    /// prefix information option.
+    fn receive_prefix(&self, pio: &[u8; 32]) {
+        if pio[2] != 64 || pio[3] & PIO_AUTONOMOUS == 0 {
+            return;
+        }
+        let mut addr = IPAddr::generate_from_mac(MacAddress::Long(self.eui64));
+        addr.set_prefix(&pio[16..32], 64);
+        if addr.is_unicast_link_local() || addr.is_multicast() {
+            return;
+        }
+        let valid_lifetime = u32::from_be_bytes([pio[4], pio[5], pio[6], pio[7]]);
+        if valid_lifetime == 0 {
+            self.interface_addrs
+                .map(|interface_addrs| interface_addrs.remove(addr));
+            if self.global_addr.get() == Some(addr) {
+                self.global_addr.set(None);
+            }
+            return;
+        }
+        let expires = if valid_lifetime == u32::max_value() {
+            None
+        } else {
+            self.deadline(Self::tics(valid_lifetime))
+        };
+        self.interface_addrs
+            .map(|interface_addrs| interface_addrs.add(addr, expires));
+        if self.global_addr.replace(Some(addr)) != Some(addr) {
+            self.schedule_dao();
+        }
+    }
+
+    fn receive_dio(&self, src: IPAddr, base: u32, body: &[u8]) {
+        // DIOs are sent from link-local addresses.
+        if !src.is_unicast_link_local() || body.len() < DIO_BODY_LEN {
+            return;
+        }
+        let instance = (base >> 24) as u8;
+        let version = (base >> 16) as u8;
+        let rank = base as u16;
+        let mut id = IPAddr::new();
+        id.0.copy_from_slice(&body[4..20]);
+        let mut config = None;
+        let mut prefix = None;
+        for option in Options(&body[DIO_BODY_LEN..]) {
+            match option[0] {
+                OPT_DODAG_CONF if option.len() >= Config::OPTION_LEN => {
+                    config = Some(Config::decode(option))
+                }
+                OPT_PREFIX_INFO if option.len() == 32 => {
+                    let mut pio = [0; 32];
+                    pio.copy_from_slice(option);
+                    prefix = Some(pio);
+                }
+                _ => {}
+            }
+        }
+        let parent = Parent {
+            addr: src,
+            rank: rank,
+            dtsn: body[1],
+        };
+
+        let mut dodag = match self.dodag.get() {
+            None => {
+                let config = config.unwrap_or(Config::DEFAULT);
+                let mode_of_operation = (body[0] >> 3) & 0x07;
+                if rank == INFINITE_RANK
+                    || mode_of_operation != MOP_NON_STORING
+                    || config.ocp != OCP_OF0
+                {
+                    return;
+                }
+                self.dis_deadline.set(None);
+                self.dodag.set(Some(Dodag {
+                    instance: instance,
+                    id: id,
+                    version: version,
+                    grounded: body[0] & DIO_GROUNDED != 0,
+                    config: config,
+                    prefix: prefix,
+                }));
+                prefix.map(|pio| self.receive_prefix(&pio));
+                self.insert_parent(parent);
+                self.select_parent();
+                return;
+            }
+            Some(dodag) if dodag.instance == instance && dodag.id == id => dodag,
+            // Only one DODAG is joined at a time.
+            Some(_) => return,
+        };
+
+        if (version.wrapping_sub(dodag.version) as i8) > 0 {
+            // The root started a new version: the DODAG is rebuilt from
+            // scratch (a global repair).
+            self.clear_parents();
+            self.lowest_rank.set(INFINITE_RANK);
+            dodag.version = version;
+            dodag.config = config.unwrap_or(dodag.config);
+            self.dodag.set(Some(dodag));
+            if rank != INFINITE_RANK {
+                self.insert_parent(parent);
+            }
+            self.select_parent();
+            self.trickle_reset();
+            self.schedule_dao();
+            return;
+        } else if version != dodag.version {
+            return;
+        }
+
+        let from_preferred = self.preferred.get() == Some(src);
+        if from_preferred {
+            dodag.config = config.unwrap_or(dodag.config);
+            if prefix.is_some() {
+                dodag.prefix = prefix;
+            }
+            self.dodag.set(Some(dodag));
+            prefix.map(|pio| self.receive_prefix(&pio));
+        }
+        let previous = self.find_parent(src);
+        if rank == INFINITE_RANK {
+            self.remove_parent(src);
+        } else {
+            self.insert_parent(parent);
+        }
+        let preferred = self.preferred.get();
+        self.select_parent();
+        if self.dodag.get().is_none() || self.preferred.get() != preferred {
+            return;
+        }
+        if from_preferred {
+            self.refresh_route(parent);
+            // A new DTSN from the parent asks for new DAOs, and is passed on
+            // to the children.
+            if previous.map_or(false, |previous| previous.dtsn != parent.dtsn) {
+                self.dtsn.set(self.dtsn.get().wrapping_add(1));
+                self.schedule_dao();
+            }
+        }
+        if rank != INFINITE_RANK {
+            self.trickle.set(self.trickle.get().map(|mut trickle| {
+                trickle.counter = trickle.counter.saturating_add(1);
+                trickle
+            }));
+        }
+    }
+
+    fn receive_dis(&self, src: IPAddr, dst: IPAddr) {
+        if self.dodag.get().is_none() {
+            return;
+        }
+        if dst.is_multicast() {
+            self.trickle_reset();
+        } else {
+            self.send_dio(src);
+        }
+    }
+
+    fn receive_dao_ack(&self, base: u32) {
+        let instance = (base >> 24) as u8;
+        let seq = (base >> 8) as u8;
+        let status = base as u8;
+        let expected = self
+            .dodag
+            .get()
+            .map_or(false, |dodag| dodag.instance == instance);
+        if !expected || !self.awaiting_ack.get() || seq != self.dao_seq.get() {
+            return;
+        }
+        self.awaiting_ack.set(false);
+        // Statuses of 128 and above reject the DAO (RFC 6550, section 6.5).
+        if status < 128 {
+            self.refresh_dao_later();
+            self.report(ReturnCode::SUCCESS);
+        } else {
+            self.dao_deadline.set(None);
+            self.report(ReturnCode::FAIL);
+        }
+    }
+
+    fn find_parent(&self, addr: IPAddr) -> Option<Parent> {
+        self.parents
+            .iter()
+            .filter_map(|slot| slot.get())
+            .find(|parent| parent.addr == addr)
+    }
+
+    /// Adds a candidate parent, or updates it. When the set is full, the
+    /// candidate with the highest rank is replaced if `parent` is better.
+    fn insert_parent(&self, parent: Parent) {
+        let slot = self
+            .parents
+            .iter()
+            .find(|slot| slot.get().map_or(false, |entry| entry.addr == parent.addr))
+            .or_else(|| self.parents.iter().find(|slot| slot.get().is_none()))
+            .or_else(|| {
+                self.parents
+                    .iter()
+                    .filter(|slot| slot.get().map_or(false, |entry| entry.rank > parent.rank))
+                    .filter(|slot| slot.get().map(|entry| entry.addr) != self.preferred.get())
+                    .max_by_key(|slot| slot.get().map_or(0, |entry| entry.rank))
+            });
+        slot.map(|slot| slot.set(Some(parent)));
+    }
+
+    fn remove_parent(&self, addr: IPAddr) {
+        self.parents
+            .iter()
+            .filter(|slot| slot.get().map_or(false, |entry| entry.addr == addr))
+            .for_each(|slot| slot.set(None));
+    }
+
+    fn clear_parents(&self) {
+        self.parents.iter().for_each(|slot| slot.set(None));
+        self.preferred.take().map(|addr| self.cache.remove(addr));
+        self.rank.set(INFINITE_RANK);
+    }
+
+    /// Makes the preferred parent the default router until the route
+    /// lifetime runs out.
+    fn refresh_route(&self, parent: Parent) {
+        let lifetime = self
+            .dodag
+            .get()
+            .map_or(Config::DEFAULT, |dodag| dodag.config)
+            .route_lifetime();
+        self.cache.insert(Neighbor {
+            ip: parent.addr,
+            mac: self.neighbor_mac(parent.addr),
+            is_router: true,
+            expires: self.alarm.now().wrapping_add(Self::tics(lifetime)),
+        });
+    }
+
+    /// Chooses the candidate that gives the node the lowest rank as the
+    /// preferred parent, keeping the current one on ties. Candidates must
+    /// rank lower than the node, so that its children are never chosen, and
+    /// the rank of the node may not grow more than the DODAG allows. Without
+    /// a candidate, the node leaves the DODAG.
+    fn select_parent(&self) {
+        let config = match self.dodag.get() {
+            Some(dodag) => dodag.config,
+            None => return,
+        };
+        let current = self.preferred.get();
+        let own_rank = self.rank.get();
+        let max_rank = if config.max_rank_increase == 0 {
+            INFINITE_RANK
+        } else {
+            self.lowest_rank
+                .get()
+                .saturating_add(config.max_rank_increase)
+        };
+        let best = self
+            .parents
+            .iter()
+            .filter_map(|slot| slot.get())
+            .filter(|parent| {
+                let rank = config.rank_through(parent.rank);
+                rank < INFINITE_RANK
+                    && rank <= max_rank
+                    && (Some(parent.addr) == current
+                        || own_rank == INFINITE_RANK
+                        || config.dag_rank(parent.rank) < config.dag_rank(own_rank))
+            })
+            .min_by_key(|parent| {
+                (
+                    config.rank_through(parent.rank),
+                    Some(parent.addr) != current,
+                )
+            });
+        let parent = match best {
+            Some(parent) => parent,
+            None => return self.detach(),
+        };
+        let rank = config.rank_through(parent.rank);
+        self.rank.set(rank);
+        self.lowest_rank.set(cmp::min(self.lowest_rank.get(), rank));
+        if Some(parent.addr) != current {
+            current.map(|addr| self.cache.remove(addr));
+            self.preferred.set(Some(parent.addr));
+            self.refresh_route(parent);
+            self.trickle_reset();
+            self.schedule_dao();
+        }
+    }
+
+    /// Leaves the DODAG after losing every parent: the node advertises an
+    /// infinite rank so that its children choose other parents, and looks
+    /// for a DODAG again.
+    fn detach(&self) {
+        if self.dodag.get().is_none() {
+            return;
+        }
+        self.rank.set(INFINITE_RANK);
+        self.send_dio(ALL_RPL_NODES);
+        self.leave();
+        if self.started.get() {
+            self.dis_deadline
+                .set(self.deadline(Self::tics(DIS_INTERVAL)));
+        }
+    }
+
+    fn leave(&self) {
+        self.clear_parents();
+        self.lowest_rank.set(INFINITE_RANK);
+        self.dodag.set(None);
+        self.trickle.set(None);
+        self.dao_deadline.set(None);
+        self.awaiting_ack.set(false);
+        if self.registered.get() {
+            self.report(ReturnCode::ENOACK);
+        }
+    }
+
+    /// Sets the alarm for the earliest of the DIS, DAO and Trickle timers.
+    fn reschedule(&self) {
+        let now = self.alarm.now();
+        let trickle = self.trickle.get();
+        let remaining = self
+            .dis_deadline
+            .get()
+            .into_iter()
+            .chain(self.dao_deadline.get())
+            .chain(trickle.and_then(|trickle| trickle.transmit_at))
+            .chain(trickle.map(|trickle| trickle.ends_at))
+            .map(|deadline| {
+                let remaining = deadline.wrapping_sub(now);
+                if remaining as i32 > 0 {
+                    remaining
+                } else {
+                    1
+                }
+            })
+            .min();
+        match remaining {
+            Some(remaining) => self.alarm.set_alarm(now.wrapping_add(remaining)),
+            None => self.alarm.disable(),
+        }
+    }
+}
+
+fn is_due(deadline: Option<u32>, now: u32) -> bool {
+    deadline.map_or(false, |deadline| deadline.wrapping_sub(now) as i32 <= 0)
+}
+
+/// Processes the source route `srh` of a packet addressed to this node (RFC
+/// 6554, section 4.2): the next address on the route becomes the destination,
+/// and the destination takes its place on the route. Returns the new
+/// destination, or None if the route is malformed or leads to a multicast
+/// address.
+fn follow_source_route(header: &mut IP6Header, srh: &mut [u8]) -> Option<IPAddr> {
+    let len = (*srh.get(1)? as usize + 1) * 8;
+    if srh.len() < len || len < 8 {
+        return None;
+    }
+    let cmpr_i = (srh[4] >> 4) as usize;
+    let cmpr_e = (srh[4] & 0x0f) as usize;
+    let pad = (srh[5] >> 4) as usize;
+    let addrs_len = len.checked_sub(8 + pad + (16 - cmpr_e))?;
+    let n = addrs_len / (16 - cmpr_i) + 1;
+    let segments_left = srh[3] as usize;
+    if segments_left == 0 || segments_left > n {
+        return None;
+    }
+    srh[3] -= 1;
+    let i = n - (segments_left - 1);
+    let cmpr = if i == n { cmpr_e } else { cmpr_i };
+    let start = 8 + (i - 1) * (16 - cmpr_i);
+    let field = srh.get_mut(start..start + 16 - cmpr)?;
+    let dst = header.get_dst_addr();
+    let mut next = dst;
+    next.0[cmpr..].copy_from_slice(field);
+    if dst.is_multicast() || next.is_multicast() {
+        return None;
+    }
+    field.copy_from_slice(&dst.0[cmpr..]);
+    header.dst_addr = next;
+    Some(next)
+}
+
+/// Iterates over the options of an RPL control message, each including its
+/// type and length bytes. Pad1 options are skipped and iteration stops at
+/// the first malformed option.
+struct Options<'b>(&'b [u8]);
+
+impl<'b> Iterator for Options<'b> {
+    type Item = &'b [u8];
+
+    fn next(&mut self) -> Option<&'b [u8]> {
+        while self.0.first() == Some(&OPT_PAD1) {
+            self.0 = &self.0[1..];
+        }
+        if self.0.len() < 2 {
+            return None;
+        }
+        let len = self.0[1] as usize + 2;
+        if len > self.0.len() {
+            self.0 = &[];
+            return None;
+        }
+        let (option, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(option)
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for Rpl<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if !self.started.get() || ip_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        let (offset, header) = match ICMP6Header::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let base = match header.get_options() {
+            ICMP6HeaderOptions::Type155 { base } => base,
+            _ => return,
+        };
+        let src = ip_header.get_src_addr();
+        let body = &payload[offset..];
+        match header.get_code() {
+            rpl_code::DIS => self.receive_dis(src, ip_header.get_dst_addr()),
+            rpl_code::DIO => self.receive_dio(src, base, body),
+            rpl_code::DAO_ACK => self.receive_dao_ack(base),
+            _ => return,
+        }
+        self.reschedule();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6ForwardClient for Rpl<'a, A> {
+    fn forward(&self, header: IP6Header, buf: &[u8], source_route: Option<usize>) {
+        let hop_limit = header.get_hop_limit();
+        if self.dodag.get().is_none() || self.sending.get() || hop_limit <= 1 {
+            return;
+        }
+        let (offset, (mut ext_headers, next_header)) =
+            match ExtHeaders::decode(header.get_next_header(), buf).done() {
+                Some(decoded) => decoded,
+                None => return,
+            };
+        let mut header = header;
+        header.set_hop_limit(hop_limit - 1);
+        let next_hop = match source_route {
+            // Source routes only visit on-link neighbors.
+            Some(route_offset) => {
+                let srh = match ext_headers.as_mut_slice().get_mut(route_offset..) {
+                    Some(srh) => srh,
+                    None => return,
+                };
+                match follow_source_route(&mut header, srh) {
+                    Some(next) => self.neighbor_mac(next),
+                    None => return,
+                }
+            }
+            None => {
+                let dst = header.get_dst_addr();
+                if dst.is_multicast() || dst.is_unicast_link_local() {
+                    return;
+                }
+                match self.preferred.get() {
+                    Some(parent) => self.neighbor_mac(parent),
+                    None => return,
+                }
+            }
+        };
+        self.sending.set(true);
+        let result =
+            self.ip_sender
+                .forward(header, ext_headers, next_header, &buf[offset..], next_hop);
+        if result != ReturnCode::SUCCESS {
+            self.sending.set(false);
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for Rpl<'a, A> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for Rpl<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        if is_due(self.dis_deadline.get(), now) {
+            self.solicit();
+        }
+        self.trickle_expired(now);
+        if is_due(self.dao_deadline.get(), now) {
+            self.dao_deadline.set(None);
+            self.dao_expired();
+        }
+        self.reschedule();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{
+        follow_source_route, rpl_code, Config, Rpl, RplClient, ALL_RPL_NODES, INFINITE_RANK,
+        TX_BUF_LEN,
+    };
+    use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+    use crate::net::ieee802154::MacAddress;
+    use crate::net::ipv6::ext_headers::ExtHeaders;
+    use crate::net::ipv6::interface_addrs::InterfaceAddresses;
+    use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+    use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+    use crate::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+    use crate::net::ipv6::ipv6_send::IP6Sender;
+    use crate::net::ipv6::neighbor_cache::NeighborCache;
+    use crate::net::loopback::{
+        any_network_capability, pending_icmp, send_icmp, LoopbackIP6Sender,
+    };
+    use core::cell::RefCell;
+    use host::harness::leak;
+    use host::mock::alarm::MockAlarm;
+    use kernel::common::leasable_buffer::LeasableBuffer;
+    use kernel::hil::time::Alarm;
+    use kernel::ReturnCode;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    pub fn of0_adds_three_hops_of_rank() {
+        let config = Config::DEFAULT;
+        assert_eq!(config.rank_through(256), 1024);
+        assert_eq!(config.rank_through(0xff00), INFINITE_RANK);
+        assert_eq!(config.dag_rank(1023), 3);
+        assert_eq!(config.imin(), 8);
+        assert_eq!(config.imax(), 8 << 20);
+        assert_eq!(config.route_lifetime(), 30 * 60);
+    }
+
+    #[test]
+    pub fn source_routes_swap_the_destination() {
+        let addr = |last: u8| {
+            IPAddr([
+                0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, last,
+            ])
+        };
+        let mut header = IP6Header::new();
+        header.dst_addr = addr(1);
+        // Two addresses compressed to their last byte (CmprI = CmprE = 15),
+        // followed by 6 bytes of padding.
+        let mut srh = [0x3a, 1, 3, 2, 0xff, 0x60, 0, 0, 2, 3, 0, 0, 0, 0, 0, 0];
+        assert_eq!(follow_source_route(&mut header, &mut srh[..8]), None);
+        assert_eq!(follow_source_route(&mut header, &mut srh), Some(addr(2)));
+        assert_eq!((srh[3], srh[8], header.dst_addr), (1, 1, addr(2)));
+        assert_eq!(follow_source_route(&mut header, &mut srh), Some(addr(3)));
+        assert_eq!((srh[3], srh[9], header.dst_addr), (0, 2, addr(3)));
+        assert_eq!(follow_source_route(&mut header, &mut srh), None);
+    }
+
+    #[derive(Default)]
+    struct Registrations {
+        results: RefCell<Vec<(IPAddr, ReturnCode)>>,
+    }
+
+    impl RplClient for Registrations {
+        fn route_registered(&self, addr: IPAddr, result: ReturnCode) {
+            self.results.borrow_mut().push((addr, result));
+        }
+    }
+
+    /// The link-local address formed from the short MAC address `short_addr`.
+    fn short_link_local_addr(short_addr: u16) -> IPAddr {
+        IPAddr::generate_from_mac(MacAddress::Short(short_addr))
+    }
+
+    fn send_rpl(sender: &LoopbackIP6Sender, dst: IPAddr, code: u8, base: u32, body: &[u8]) {
+        let mut header = ICMP6Header::new(ICMP6Type::Type155);
+        header.set_code(code);
+        header.set_options(ICMP6HeaderOptions::Type155 { base: base });
+        let payload = LeasableBuffer::new(Box::leak(body.to_vec().into_boxed_slice()));
+        assert_eq!(
+            sender.send_to(
+                dst,
+                TransportHeader::ICMP(header),
+                &payload,
+                any_network_capability()
+            ),
+            ReturnCode::SUCCESS
+        );
+    }
+
+    #[test]
+    pub fn rpl_joins_dodag_and_forwards_packets() {
+        let eui64 = [1, 2, 3, 4, 5, 6, 7, 8];
+        let node_addr = short_link_local_addr(1);
+        let (root_addr, other_addr) = (short_link_local_addr(2), short_link_local_addr(3));
+        let prefix = [0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 0];
+        let global = |link_local: IPAddr| {
+            let mut addr = link_local;
+            addr.0[..8].copy_from_slice(&prefix);
+            addr
+        };
+        let node_global = global(IPAddr::generate_from_mac(MacAddress::Long(eui64)));
+        let dodag_id = global(root_addr);
+        let mut remote_addr = IPAddr([0; 16]);
+        remote_addr.0[0] = 0x20;
+        remote_addr.0[15] = 9;
+
+        let node_sender = leak(LoopbackIP6Sender::new(node_addr));
+        let node_receiver = leak(IP6RecvStruct::new());
+        let root_sender = leak(LoopbackIP6Sender::new(root_addr));
+        let other_sender = leak(LoopbackIP6Sender::new(other_addr));
+        let sink = leak(IP6RecvStruct::new());
+        let alarm: &MockAlarm = leak(MockAlarm::new());
+        let cache = leak(NeighborCache::new());
+        let interface_addrs = leak(InterfaceAddresses::new());
+        assert_eq!(interface_addrs.add(node_addr, None), ReturnCode::SUCCESS);
+        node_receiver.set_interface_addresses(interface_addrs);
+        let rpl = leak(Rpl::new(
+            node_sender,
+            alarm,
+            cache,
+            Box::leak(Box::new([0; TX_BUF_LEN])),
+            MacAddress::Short(0x0001),
+            eui64,
+            any_network_capability(),
+        ));
+        let registrations = leak(Registrations::default());
+        node_sender.set_client(rpl);
+        alarm.set_client(rpl);
+        assert_eq!(node_receiver.add_client(rpl), ReturnCode::SUCCESS);
+        node_receiver.set_forward_client(rpl);
+        rpl.set_client(registrations);
+        rpl.set_interface_addresses(interface_addrs);
+
+        // DODAG information is solicited until a DIO is heard.
+        assert_eq!(rpl.start(), ReturnCode::SUCCESS);
+        assert_eq!(rpl.start(), ReturnCode::EALREADY);
+        assert_eq!(pending_icmp(node_sender), (ALL_RPL_NODES, 155, vec![]));
+        assert!(node_sender.transfer(sink, true));
+        assert!(alarm.advance(10 * 32768));
+        assert_eq!(pending_icmp(node_sender).0, ALL_RPL_NODES);
+        assert!(node_sender.transfer(sink, true));
+        assert_eq!(rpl.rank(), INFINITE_RANK);
+
+        // Grounded, non-storing mode, with a DODAG configuration (Imin of 4
+        // seconds) and a prefix to autoconfigure from.
+        let dio = |sender: &LoopbackIP6Sender, rank: u16, dtsn: u8, options: bool| {
+            let mut body = vec![0x88, dtsn, 0, 0];
+            body.extend_from_slice(&dodag_id.0);
+            if options {
+                body.extend_from_slice(&[4, 14, 0, 4, 12, 10, 7, 0, 1, 0, 0, 0, 0, 30, 0, 60]);
+                body.extend_from_slice(&[8, 30, 64, 0xc0]);
+                body.extend_from_slice(&3600u32.to_be_bytes());
+                body.extend_from_slice(&3600u32.to_be_bytes());
+                body.extend_from_slice(&[0; 4]);
+                body.extend_from_slice(&prefix);
+                body.extend_from_slice(&[0; 8]);
+            }
+            send_rpl(
+                sender,
+                ALL_RPL_NODES,
+                rpl_code::DIO,
+                0x0101_0000 | rank as u32,
+                &body,
+            );
+            assert!(sender.transfer(node_receiver, false));
+        };
+        dio(root_sender, 256, 0, true);
+        assert_eq!(rpl.rank(), 1024);
+        assert_eq!(rpl.dodag_id(), Some(dodag_id));
+        assert_eq!(rpl.preferred_parent(), Some(root_addr));
+        assert!(interface_addrs.contains(node_global));
+        assert!(interface_addrs.contains(ALL_RPL_NODES));
+        assert_eq!(cache.next_hop(remote_addr), Some(MacAddress::Short(2)));
+
+        // The route through the root is reported to it after a second, until
+        // it acknowledges the report.
+        assert!(!alarm.advance(32768 - 1));
+        assert!(alarm.advance(1));
+        let (dst, icmp_type, body) = pending_icmp(node_sender);
+        assert_eq!((dst, icmp_type), (dodag_id, 155));
+        assert_eq!(body[..16], dodag_id.0);
+        assert_eq!(body[16..20], [5, 18, 0, 128]);
+        assert_eq!(body[20..36], node_global.0);
+        assert_eq!(body[36..38], [6, 20]);
+        assert_eq!(body[42..58], dodag_id.0);
+        assert!(node_sender.transfer(sink, true));
+
+        // Meanwhile, the node advertises the DODAG with its own rank.
+        let mut dio_sent = false;
+        loop {
+            assert!(alarm.advance_to_alarm());
+            if node_sender.outbox.borrow().is_empty() {
+                continue;
+            }
+            let (dst, icmp_type, body) = pending_icmp(node_sender);
+            assert_eq!(icmp_type, 155);
+            if dst == dodag_id {
+                break;
+            }
+            assert_eq!(dst, ALL_RPL_NODES);
+            assert_eq!(node_sender.outbox.borrow()[0][44..48], [1, 1, 0x04, 0x00]);
+            assert_eq!(body[4..20], dodag_id.0);
+            dio_sent = true;
+            assert!(node_sender.transfer(sink, true));
+        }
+        assert!(dio_sent);
+        assert!(node_sender.transfer(sink, true));
+        send_rpl(
+            root_sender,
+            node_global,
+            rpl_code::DAO_ACK,
+            0x0100_0100,
+            &[],
+        );
+        assert!(root_sender.transfer(node_receiver, false));
+        assert!(registrations.results.borrow().is_empty());
+        send_rpl(
+            root_sender,
+            node_global,
+            rpl_code::DAO_ACK,
+            0x0100_0200,
+            &[],
+        );
+        assert!(root_sender.transfer(node_receiver, false));
+        assert_eq!(
+            *registrations.results.borrow(),
+            [(node_global, ReturnCode::SUCCESS)]
+        );
+
+        // A worse candidate is kept, and becomes the preferred parent when the
+        // root poisons its route.
+        dio(other_sender, 512, 0, false);
+        assert_eq!(rpl.preferred_parent(), Some(root_addr));
+        dio(root_sender, INFINITE_RANK, 0, false);
+        assert_eq!(rpl.preferred_parent(), Some(other_addr));
+        assert_eq!(rpl.rank(), 1280);
+        assert_eq!(cache.next_hop(remote_addr), Some(MacAddress::Short(3)));
+        while node_sender.transfer(sink, true) {}
+
+        // Packets for other destinations go up to the preferred parent.
+        let child_addr = global(short_link_local_addr(5));
+        let child_sender = leak(LoopbackIP6Sender::new(child_addr));
+        send_icmp(
+            child_sender,
+            remote_addr,
+            ICMP6Type::Type128,
+            ICMP6HeaderOptions::Type128 { id: 1, seqno: 1 },
+            &[],
+        );
+        assert!(child_sender.transfer(node_receiver, false));
+        assert_eq!(*node_sender.next_hops.borrow(), [MacAddress::Short(3)]);
+        assert_eq!(pending_icmp(node_sender).0, remote_addr);
+        assert_eq!(node_sender.outbox.borrow()[0][7], 254);
+        assert!(node_sender.transfer(sink, true));
+
+        // Packets source routed through the node go to the next address on the
+        // route, whose first 8 bytes are elided.
+        let mut ext_headers = ExtHeaders::new();
+        let mut route = vec![0x88, 0, 0, 0];
+        route.extend_from_slice(&child_addr.0[8..]);
+        assert_eq!(ext_headers.add_routing(3, 1, &route), ReturnCode::SUCCESS);
+        root_sender.ext_headers.set(ext_headers);
+        send_icmp(
+            root_sender,
+            node_global,
+            ICMP6Type::Type128,
+            ICMP6HeaderOptions::Type128 { id: 1, seqno: 2 },
+            &[],
+        );
+        assert!(root_sender.transfer(node_receiver, false));
+        assert_eq!(
+            *node_sender.next_hops.borrow(),
+            [MacAddress::Short(3), MacAddress::Short(5)]
+        );
+        let outbox = node_sender.outbox.borrow();
+        assert_eq!(outbox[0][24..40], child_addr.0);
+        assert_eq!(outbox[0][40..48], [ip6_nh::ICMP, 1, 3, 0, 0x88, 0, 0, 0]);
+        assert_eq!(outbox[0][48..56], node_global.0[8..]);
+    }
+}
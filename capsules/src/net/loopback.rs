@@ -0,0 +1,479 @@
+//! Simulated links and endpoints for testing the network stack.
+
+extern crate std;
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::icmpv6::icmpv6_echo::{ICMP6Echo, ICMP6EchoClient};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ext_headers::ExtHeaders;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+use crate::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, TcpVisibilityCapability,
+};
+use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use crate::net::tcp::tcp::TCPHeader;
+use crate::net::tcp::tcp_connection::{MuxTcp, TcpClient, TcpConnection};
+use crate::net::tcp::tcp_port_table::{TcpPortManager, MAX_NUM_BOUND_PORTS};
+use crate::net::udp::udp::UDPHeader;
+use crate::net::udp::udp_port_table::{PortQuery, UdpPortBindingTx};
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::{Cell, RefCell};
+use host::harness::{leak, TestCapabilities};
+use host::mock::alarm::MockAlarm;
+use host::mock::rng::MockRng;
+use kernel::capabilities::UdpDriverCapability;
+use kernel::common::cells::OptionalCell;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::rng::Rng;
+use kernel::hil::time::Alarm;
+use kernel::ReturnCode;
+use std::boxed::Box;
+use std::vec;
+use std::vec::Vec;
+
+pub fn any_network_capability() -> &'static NetworkCapability {
+    leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &TestCapabilities,
+    ))
+}
+
+pub fn link_local_addr(last_byte: u8) -> IPAddr {
+    let mut addr = IPAddr([0; 16]);
+    addr.0[0] = 0xfe;
+    addr.0[1] = 0x80;
+    addr.0[15] = last_byte;
+    addr
+}
+
+/// An `IP6Sender` that serializes each packet and holds it until the test
+/// transfers it, standing in for 6LoWPAN and the radio.
+pub struct LoopbackIP6Sender {
+    pub addr: IPAddr,
+    pub packet: RefCell<IP6Packet<'static>>,
+    pub ext_headers: Cell<ExtHeaders>,
+    pub outbox: RefCell<Vec<Vec<u8>>>,
+    /// The MAC address each forwarded packet was sent to.
+    pub next_hops: RefCell<Vec<MacAddress>>,
+    pub client: OptionalCell<&'static dyn IP6SendClient>,
+}
+
+impl LoopbackIP6Sender {
+    pub fn new(addr: IPAddr) -> LoopbackIP6Sender {
+        let payload = IPPayload::new(
+            TransportHeader::TCP(TCPHeader::new()),
+            Box::leak(Box::new([0; 256])),
+        );
+        LoopbackIP6Sender {
+            addr: addr,
+            packet: RefCell::new(IP6Packet::new(payload)),
+            ext_headers: Cell::new(ExtHeaders::new()),
+            outbox: RefCell::new(Vec::new()),
+            next_hops: RefCell::new(Vec::new()),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Completes the oldest packet sent, and delivers it to `to` unless it is
+    /// lost. Returns whether there was a packet to transfer.
+    pub fn transfer(&self, to: &IP6RecvStruct<'static>, lost: bool) -> bool {
+        if self.outbox.borrow().is_empty() {
+            return false;
+        }
+        let bytes = self.outbox.borrow_mut().remove(0);
+        self.client
+            .map(|client| client.send_done(ReturnCode::SUCCESS));
+        if !lost {
+            to.receive(&bytes, bytes.len(), ReturnCode::SUCCESS);
+        }
+        true
+    }
+}
+
+impl IP6Sender<'static> for LoopbackIP6Sender {
+    fn set_client(&self, client: &'static dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, _src_addr: IPAddr) {}
+
+    fn set_gateway(&self, _gateway: MacAddress) {}
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {}
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        let mut packet = self.packet.borrow_mut();
+        packet.reset();
+        packet.header.src_addr = self.addr;
+        packet.header.dst_addr = dst;
+        packet.ext_headers = self.ext_headers.get();
+        let ret = packet.set_payload(transport_header, payload);
+        if ret != ReturnCode::SUCCESS {
+            return ret;
+        }
+        packet.set_transport_checksum();
+        let mut bytes = vec![0; packet.get_total_len() as usize];
+        packet.encode(&mut bytes);
+        self.outbox.borrow_mut().push(bytes);
+        ReturnCode::SUCCESS
+    }
+
+    fn forward(
+        &self,
+        header: IP6Header,
+        ext_headers: ExtHeaders,
+        next_header: u8,
+        payload: &[u8],
+        next_hop: MacAddress,
+    ) -> ReturnCode {
+        let mut packet = self.packet.borrow_mut();
+        let ret = packet.set_forwarded(header, ext_headers, next_header, payload);
+        if ret != ReturnCode::SUCCESS {
+            return ret;
+        }
+        let mut bytes = vec![0; packet.get_total_len() as usize];
+        packet.encode(&mut bytes);
+        self.outbox.borrow_mut().push(bytes);
+        self.next_hops.borrow_mut().push(next_hop);
+        ReturnCode::SUCCESS
+    }
+}
+
+#[derive(Default)]
+pub struct TcpEvents {
+    pub connected: Cell<usize>,
+    pub received: RefCell<Vec<u8>>,
+    pub sent: RefCell<Vec<ReturnCode>>,
+    pub peer_closed: Cell<bool>,
+    pub closed: RefCell<Vec<ReturnCode>>,
+}
+
+impl TcpClient for TcpEvents {
+    fn connected(&self) {
+        self.connected.set(self.connected.get() + 1);
+    }
+
+    fn received(&self, data: &[u8]) {
+        self.received.borrow_mut().extend_from_slice(data);
+    }
+
+    fn send_done(&self, result: ReturnCode, _buf: LeasableBuffer<'static, u8>) {
+        self.sent.borrow_mut().push(result);
+    }
+
+    fn peer_closed(&self) {
+        self.peer_closed.set(true);
+    }
+
+    fn closed(&self, result: ReturnCode) {
+        self.closed.borrow_mut().push(result);
+    }
+}
+
+/// One end of a simulated link, with its own TCP stack and a single
+/// connection bound to `port`.
+pub struct TcpHost {
+    pub addr: IPAddr,
+    pub sender: &'static LoopbackIP6Sender,
+    pub receiver: &'static IP6RecvStruct<'static>,
+    pub alarm: &'static MockAlarm<'static>,
+    pub connection: &'static TcpConnection<'static, MockAlarm<'static>>,
+    pub events: &'static TcpEvents,
+    pub net_cap: &'static NetworkCapability,
+}
+
+/// The TCP stack of one end of a simulated link.
+pub struct TcpStack {
+    pub sender: &'static LoopbackIP6Sender,
+    pub receiver: &'static IP6RecvStruct<'static>,
+    pub alarm: &'static MockAlarm<'static>,
+    pub rng: &'static MockRng,
+    pub mux: &'static MuxTcp<'static, MockAlarm<'static>>,
+    pub port_table: &'static TcpPortManager,
+}
+
+impl TcpStack {
+    pub fn new(addr: IPAddr, net_cap: &'static NetworkCapability) -> TcpStack {
+        let stack = TcpStack::new_without_key(addr, net_cap);
+        // Every stack gets its own key for initial sequence numbers.
+        assert_eq!(stack.mux.initialize(), ReturnCode::SUCCESS);
+        let seed = u32::from_be_bytes([addr.0[12], addr.0[13], addr.0[14], addr.0[15]]);
+        assert!(stack.rng.complete(&[seed, !seed, seed << 8, seed >> 8]));
+        assert!(!stack.rng.is_requested());
+        stack
+    }
+
+    /// A stack whose key for initial sequence numbers was not set up yet.
+    pub fn new_without_key(addr: IPAddr, net_cap: &'static NetworkCapability) -> TcpStack {
+        let tcp_vis = leak(TcpVisibilityCapability::new(&TestCapabilities));
+        let port_table = leak(TcpPortManager::new(
+            &TestCapabilities,
+            Box::leak(Box::new([None; MAX_NUM_BOUND_PORTS])),
+            tcp_vis,
+        ));
+
+        let sender = leak(LoopbackIP6Sender::new(addr));
+        let receiver = leak(IP6RecvStruct::new());
+        let alarm = leak(MockAlarm::new());
+        let rng = leak(MockRng::new());
+        // A small transmit buffer, so that sends are split into segments.
+        let mux = leak(MuxTcp::new(
+            sender,
+            alarm,
+            rng,
+            Box::leak(Box::new([0; 32])),
+            net_cap,
+            tcp_vis,
+        ));
+        sender.set_client(mux);
+        alarm.set_client(mux);
+        rng.set_client(mux);
+        assert_eq!(receiver.add_client(mux), ReturnCode::SUCCESS);
+        TcpStack {
+            sender: sender,
+            receiver: receiver,
+            alarm: alarm,
+            rng: rng,
+            mux: mux,
+            port_table: port_table,
+        }
+    }
+}
+
+impl TcpHost {
+    pub fn new(last_byte: u8, port: u16) -> TcpHost {
+        let net_cap = any_network_capability();
+        let addr = link_local_addr(last_byte);
+        let TcpStack {
+            sender,
+            receiver,
+            alarm,
+            mux,
+            port_table,
+            ..
+        } = TcpStack::new(addr, net_cap);
+
+        let connection = leak(TcpConnection::new(mux));
+        mux.add_connection(connection);
+        let events = leak(TcpEvents::default());
+        connection.set_client(events);
+        let socket = port_table.create_socket().unwrap();
+        assert!(connection
+            .set_binding(port_table.bind(socket, port, net_cap).unwrap())
+            .is_none());
+        TcpHost {
+            addr: addr,
+            sender: sender,
+            receiver: receiver,
+            alarm: alarm,
+            connection: connection,
+            events: events,
+            net_cap: net_cap,
+        }
+    }
+}
+
+/// Transfers packets between `a` and `b` until neither has anything left to
+/// send.
+pub fn tcp_settle(a: &TcpHost, b: &TcpHost) {
+    loop {
+        let a_sent = a.sender.transfer(b.receiver, false);
+        let b_sent = b.sender.transfer(a.receiver, false);
+        if !a_sent && !b_sent {
+            break;
+        }
+    }
+}
+
+pub fn tcp_send(host: &TcpHost, data: &[u8]) {
+    let buf = Box::leak(data.to_vec().into_boxed_slice());
+    assert!(host.connection.send(LeasableBuffer::new(buf)).is_ok());
+}
+
+#[derive(Default)]
+pub struct EchoReplies {
+    pub replies: RefCell<Vec<(IPAddr, u16, u16, Vec<u8>)>>,
+}
+
+impl ICMP6EchoClient for EchoReplies {
+    fn request_sent(&self, _result: ReturnCode) {}
+
+    fn reply_received(&self, src: IPAddr, id: u16, seqno: u16, data: &[u8]) {
+        self.replies
+            .borrow_mut()
+            .push((src, id, seqno, data.to_vec()));
+    }
+}
+
+/// An ICMPv6 echo endpoint on a simulated link.
+pub fn echo_host(
+    addr: IPAddr,
+) -> (
+    &'static LoopbackIP6Sender,
+    &'static IP6RecvStruct<'static>,
+    &'static ICMP6Echo<'static>,
+) {
+    let sender = leak(LoopbackIP6Sender::new(addr));
+    let receiver = leak(IP6RecvStruct::new());
+    let echo = leak(ICMP6Echo::new(
+        sender,
+        Box::leak(Box::new([0; 32])),
+        any_network_capability(),
+    ));
+    sender.set_client(echo);
+    assert_eq!(receiver.add_client(echo), ReturnCode::SUCCESS);
+    (sender, receiver, echo)
+}
+
+/// The destination, ICMPv6 type and body of the oldest packet `sender` has
+/// not transferred yet.
+pub fn pending_icmp(sender: &LoopbackIP6Sender) -> (IPAddr, u8, Vec<u8>) {
+    let outbox = sender.outbox.borrow();
+    let bytes = &outbox[0];
+    let (offset, ip_header) = IP6Header::decode(bytes).done().unwrap();
+    let (icmp_offset, icmp_header) = ICMP6Header::decode(&bytes[offset..]).done().unwrap();
+    (
+        ip_header.get_dst_addr(),
+        icmp_header.get_type_as_int(),
+        bytes[offset + icmp_offset..].to_vec(),
+    )
+}
+
+pub fn send_icmp(
+    sender: &LoopbackIP6Sender,
+    dst: IPAddr,
+    icmp_type: ICMP6Type,
+    options: ICMP6HeaderOptions,
+    body: &[u8],
+) {
+    let mut header = ICMP6Header::new(icmp_type);
+    header.set_options(options);
+    let payload = LeasableBuffer::new(Box::leak(body.to_vec().into_boxed_slice()));
+    assert_eq!(
+        sender.send_to(
+            dst,
+            TransportHeader::ICMP(header),
+            &payload,
+            any_network_capability()
+        ),
+        ReturnCode::SUCCESS
+    );
+}
+
+/// A `UDPSender` that holds each datagram until the test transfers it,
+/// standing in for the UDP stack of a node at `addr`.
+pub struct LoopbackUdpSender {
+    pub addr: IPAddr,
+    pub binding: RefCell<Option<UdpPortBindingTx>>,
+    pub outbox: RefCell<Vec<(IPAddr, u16, Vec<u8>)>>,
+    pub buffers: RefCell<Vec<LeasableBuffer<'static, u8>>>,
+    pub client: OptionalCell<&'static dyn UDPSendClient>,
+}
+
+impl LoopbackUdpSender {
+    pub fn new(addr: IPAddr) -> LoopbackUdpSender {
+        LoopbackUdpSender {
+            addr: addr,
+            binding: RefCell::new(None),
+            outbox: RefCell::new(Vec::new()),
+            buffers: RefCell::new(Vec::new()),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Completes the oldest datagram sent, and delivers it to `to` if it is
+    /// addressed to this node and not lost. Returns its destination and
+    /// payload.
+    pub fn transfer(&self, to: &dyn UDPRecvClient, lost: bool) -> Option<(IPAddr, Vec<u8>)> {
+        if self.outbox.borrow().is_empty() {
+            return None;
+        }
+        let (dst, dst_port, bytes) = self.outbox.borrow_mut().remove(0);
+        let buf = self.buffers.borrow_mut().remove(0);
+        self.client
+            .map(|client| client.send_done(ReturnCode::SUCCESS, buf));
+        if !lost && dst == self.addr {
+            let src_port = self.binding.borrow().as_ref().map_or(0, |b| b.get_port());
+            to.receive(self.addr, dst, src_port, dst_port, &bytes);
+        }
+        Some((dst, bytes))
+    }
+}
+
+impl UDPSender<'static> for LoopbackUdpSender {
+    fn set_client(&self, client: &'static dyn UDPSendClient) {
+        self.client.set(client);
+    }
+
+    fn send_to(
+        &'static self,
+        dest: IPAddr,
+        dst_port: u16,
+        buf: LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        if self.binding.borrow().is_none() {
+            return Err(buf);
+        }
+        self.outbox
+            .borrow_mut()
+            .push((dest, dst_port, buf[..].to_vec()));
+        self.buffers.borrow_mut().push(buf);
+        Ok(())
+    }
+
+    fn driver_send_to(
+        &'static self,
+        _dest: IPAddr,
+        _dst_port: u16,
+        _src_port: u16,
+        buf: LeasableBuffer<'static, u8>,
+        _driver_send_cap: &dyn UdpDriverCapability,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        Err(buf)
+    }
+
+    fn send(
+        &'static self,
+        _dest: IPAddr,
+        _udp_header: UDPHeader,
+        buf: LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        Err(buf)
+    }
+
+    fn get_binding(&self) -> Option<UdpPortBindingTx> {
+        self.binding.borrow_mut().take()
+    }
+
+    fn is_bound(&self) -> bool {
+        self.binding.borrow().is_some()
+    }
+
+    fn set_binding(&self, binding: UdpPortBindingTx) -> Option<UdpPortBindingTx> {
+        self.binding.borrow_mut().replace(binding)
+    }
+}
+
+/// Stands in for the UDP driver, whose apps have not bound any port.
+pub struct NoUserPorts;
+
+impl PortQuery for NoUserPorts {
+    fn is_bound(&self, _port: u16) -> bool {
+        false
+    }
+}
@@ -5,9 +5,13 @@ pub mod sixlowpan;
 pub mod util;
 #[macro_use]
 pub mod stream;
+pub mod coap;
+pub mod dns;
 pub mod icmpv6;
 pub mod ieee802154;
 pub mod ipv6;
+#[cfg(test)]
+mod loopback;
 pub mod network_capabilities;
 pub mod tcp;
 pub mod thread;
@@ -1,8 +1,8 @@
 //! Capabilities for specifying capsule access to network resources
 //!
 //! A network capability specifies (1) with what IP addresses the holder of the
-//! capability may communicate, (2) from which UDP ports the holder may send,
-//! and (3) to which UDP ports the holder may send. In order to express various
+//! capability may communicate, (2) from which UDP or TCP ports the holder may
+//! send, and (3) to which UDP or TCP ports the holder may send. In order to express various
 //! ranges of IP addresses, one uses the AddrRange enum. One specifies ranges of
 //! ports using the PortRange enum.
 //!
@@ -10,7 +10,8 @@
 //! code (i.e. code that must use the unsafe keyword) since the constructor of
 //! a network capability requires the NetworkCapabilityCreationCapability capability. Code that
 //! checks these capabilities must possess the appropriate visibilty privileges.
-//! UDP visibility privileges are given through the UdpVisibilityCapability capability and IP
+//! UDP visibility privileges are given through the UdpVisibilityCapability capability, TCP
+//! visibility privileges through the TcpVisibilityCapability capability, and IP
 //! visibility privileges are given through the IpVisibilityCapability capability.
 //!
 //! An example of the visibility capabilities can be found in udp_port_table.rs.
@@ -79,13 +80,18 @@ impl PortRange {
     }
 }
 
-/// The UdpVisibilityCapability and IpVisibilityCapability has an empty private
-/// field to make it so the only way to create these structs is via a call to
-/// `new` which requires a NetworkCapabilityCreationCapability.
+/// The UdpVisibilityCapability, TcpVisibilityCapability and
+/// IpVisibilityCapability have an empty private field to make it so the only
+/// way to create these structs is via a call to `new` which requires a
+/// NetworkCapabilityCreationCapability.
 pub struct UdpVisibilityCapability {
     _priv: (), // an empty private field
 }
 
+pub struct TcpVisibilityCapability {
+    _priv: (), // an empty private field
+}
+
 pub struct IpVisibilityCapability {
     _priv: (), // an empty private field
 }
@@ -98,6 +104,14 @@ impl UdpVisibilityCapability {
     }
 }
 
+impl TcpVisibilityCapability {
+    pub fn new(
+        _create_net_cap: &dyn NetworkCapabilityCreationCapability,
+    ) -> TcpVisibilityCapability {
+        TcpVisibilityCapability { _priv: () }
+    }
+}
+
 impl IpVisibilityCapability {
     pub fn new(
         _create_net_cap: &dyn NetworkCapabilityCreationCapability,
@@ -106,9 +120,10 @@ impl IpVisibilityCapability {
     }
 }
 
-/// The NetworkCapability specifies access to network resourcess across the UDP
-/// and IP layers. Access to layer-specific information is mediated by the
-/// UdpVsibilityCapability and the IpVisibilityCapability.
+/// The NetworkCapability specifies access to network resourcess across the UDP,
+/// TCP and IP layers. Access to layer-specific information is mediated by the
+/// UdpVsibilityCapability, the TcpVisibilityCapability and the
+/// IpVisibilityCapability. The same port ranges apply to UDP and TCP.
 pub struct NetworkCapability {
     // can potentially add more
     remote_addrs: AddrRange, // IP addresses with which the holder may communicate
@@ -165,4 +180,20 @@ impl NetworkCapability {
     ) -> bool {
         self.local_ports.is_port_valid(local_port)
     }
+
+    pub fn remote_tcp_port_valid(
+        &self,
+        remote_port: u16,
+        _tcp_cap: &'static TcpVisibilityCapability,
+    ) -> bool {
+        self.remote_ports.is_port_valid(remote_port)
+    }
+
+    pub fn local_tcp_port_valid(
+        &self,
+        local_port: u16,
+        _tcp_cap: &'static TcpVisibilityCapability,
+    ) -> bool {
+        self.local_ports.is_port_valid(local_port)
+    }
 }
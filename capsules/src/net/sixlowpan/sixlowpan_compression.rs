@@ -1,6 +1,6 @@
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::ip_utils::{compute_udp_checksum, ip6_nh, IPAddr};
-use crate::net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader, UDP_HDR_LEN};
 use crate::net::udp::udp::UDPHeader;
 use crate::net::util;
 use crate::net::util::{network_slice_to_u16, u16_to_network_slice};
@@ -8,6 +8,7 @@ use crate::net::util::{network_slice_to_u16, u16_to_network_slice};
 /// 802.15.4 packets efficiently, as detailed in RFC 6282.
 use core::mem;
 use core::result::Result;
+use kernel::ReturnCode;
 
 /// Contains bit masks and constants related to the two-byte header of the
 /// LoWPAN_IPHC encoding format.
@@ -97,11 +98,8 @@ pub struct Context {
 pub trait ContextStore {
     fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context>;
     fn get_context_from_id(&self, ctx_id: u8) -> Option<Context>;
-    fn get_context_0(&self) -> Context {
-        match self.get_context_from_id(0) {
-            Some(ctx) => ctx,
-            None => panic!("Context 0 not found"),
-        }
+    fn get_context_0(&self) -> Option<Context> {
+        self.get_context_from_id(0)
     }
     fn get_context_from_prefix(&self, prefix: &[u8], prefix_len: u8) -> Option<Context>;
 }
@@ -158,7 +156,7 @@ pub fn is_lowpan(packet: &[u8]) -> bool {
 
 /// Maps a LoWPAN_NHC header the corresponding IPv6 next header type,
 /// or an error if the NHC header is invalid
-fn nhc_to_ip6_nh(nhc: u8) -> Result<u8, ()> {
+fn nhc_to_ip6_nh(nhc: u8) -> Result<u8, ReturnCode> {
     match nhc & nhc::DISPATCH_MASK {
         nhc::DISPATCH_NHC => match nhc & nhc::EID_MASK {
             nhc::HOP_OPTS => Ok(ip6_nh::HOP_OPTS),
@@ -167,10 +165,10 @@ fn nhc_to_ip6_nh(nhc: u8) -> Result<u8, ()> {
             nhc::DST_OPTS => Ok(ip6_nh::DST_OPTS),
             nhc::MOBILITY => Ok(ip6_nh::MOBILITY),
             nhc::IP6 => Ok(ip6_nh::IP6),
-            _ => Err(()),
+            _ => Err(ReturnCode::EINVAL),
         },
         nhc::DISPATCH_UDP => Ok(ip6_nh::UDP),
-        _ => Err(()),
+        _ => Err(ReturnCode::EINVAL),
     }
 }
 
@@ -182,14 +180,15 @@ fn nhc_to_ip6_nh(nhc: u8) -> Result<u8, ()> {
 /// bytes consumed from the IPv6 datagram `written` is the number of
 /// compressed header bytes written into `buf`. Payload bytes and
 /// non-compressed next headers are not written, so the remaining `buf.len()
-/// - consumed` bytes must still be copied over to `buf`.
+/// - consumed` bytes must still be copied over to `buf`. Returns EINVAL if the
+/// IPv6 next header does not match the transport header of the packet.
 pub fn compress<'a>(
     ctx_store: &dyn ContextStore,
     ip6_packet: &'a IP6Packet<'a>,
     src_mac_addr: MacAddress,
     dst_mac_addr: MacAddress,
     mut buf: &mut [u8],
-) -> Result<(usize, usize), ()> {
+) -> Result<(usize, usize), ReturnCode> {
     // Note that consumed should be constant, and equal sizeof(IP6Header)
     //let (mut consumed, ip6_header) = IP6Header::decode(ip6_datagram).done().ok_or(())?;
     let mut consumed = 40; // TODO
@@ -281,7 +280,7 @@ pub fn compress<'a>(
             }
             // Return an error, as there is a conflict between IPv6 next
             // header and actual IPv6 payload
-            _ => return Err(()),
+            _ => return Err(ReturnCode::EINVAL),
         }
     }
     Ok((consumed, written))
@@ -599,6 +598,11 @@ fn compress_udp_checksum(udp_header: &UDPHeader, buf: &mut [u8], written: &mut u
 ///
 /// * `written` is the number of uncompressed header bytes written into
 /// `out_buf`.
+///
+/// `Err(ESIZE)` if `buf` ends before the compressed headers do, or if
+/// `out_buf` or `dgram_size` is too small for the uncompressed headers.
+///
+/// `Err(EINVAL)` if the headers use a reserved encoding or an unknown context.
 pub fn decompress(
     ctx_store: &dyn ContextStore,
     buf: &[u8],
@@ -607,23 +611,26 @@ pub fn decompress(
     out_buf: &mut [u8],
     dgram_size: u16,
     is_fragment: bool,
-) -> Result<(usize, usize), ()> {
+) -> Result<(usize, usize), ReturnCode> {
     // Get the LOWPAN_IPHC header (the first two bytes are the header)
-    let iphc_header_1: u8 = buf[0];
-    let iphc_header_2: u8 = buf[1];
+    let iphc_header_1: u8 = byte_at(buf, 0)?;
+    let iphc_header_2: u8 = byte_at(buf, 1)?;
     let mut consumed: usize = 2;
 
     let mut ip6_header = IP6Header::new();
     let mut written: usize = mem::size_of::<IP6Header>();
+    if out_buf.len() < written {
+        return Err(ReturnCode::ESIZE);
+    }
 
     // Decompress CID and CIE fields if they exist
     let (src_ctx, dst_ctx) = decompress_cie(ctx_store, iphc_header_1, &buf, &mut consumed)?;
 
     // Traffic Class & Flow Label
-    decompress_tf(&mut ip6_header, iphc_header_1, &buf, &mut consumed);
+    decompress_tf(&mut ip6_header, iphc_header_1, &buf, &mut consumed)?;
 
     // Next Header
-    let (mut is_nhc, mut next_header) = decompress_nh(iphc_header_1, &buf, &mut consumed);
+    let (mut is_nhc, mut next_header) = decompress_nh(iphc_header_1, &buf, &mut consumed)?;
 
     // Hop Limit
     decompress_hl(&mut ip6_header, iphc_header_1, &buf, &mut consumed)?;
@@ -661,7 +668,7 @@ pub fn decompress(
     // next_header is already set if is_nhc is false, otherwise it can be
     // determined from the LoWPAN NHC header byte
     if is_nhc {
-        next_header = nhc_to_ip6_nh(buf[consumed])?;
+        next_header = nhc_to_ip6_nh(byte_at(buf, consumed)?)?;
     }
     ip6_header.set_next_header(next_header);
 
@@ -670,11 +677,11 @@ pub fn decompress(
     // next header in buf.
     while is_nhc {
         // Advance past the LoWPAN NHC byte
-        let nhc_header = buf[consumed];
+        let nhc_header = byte_at(buf, consumed)?;
         consumed += 1;
 
         // Scoped mutable borrow of out_buf
-        let mut next_headers: &mut [u8] = &mut out_buf[written..];
+        let mut next_headers: &mut [u8] = out_buf.get_mut(written..).ok_or(ReturnCode::ESIZE)?;
 
         match next_header {
             ip6_nh::IP6 => {
@@ -692,17 +699,22 @@ pub fn decompress(
                 break;
             }
             ip6_nh::UDP => {
+                if next_headers.len() < UDP_HDR_LEN {
+                    return Err(ReturnCode::ESIZE);
+                }
                 // UDP length includes UDP header and data in bytes
                 // Below line works bc udp nh must be last nh per 6282
                 let mut udp_length = if is_fragment {
-                    dgram_size - written as u16
+                    dgram_size
+                        .checked_sub(written as u16)
+                        .ok_or(ReturnCode::ESIZE)?
                 } else {
-                    buf.len() as u16 - consumed as u16
+                    (buf.len() - consumed) as u16
                 };
 
                 // Decompress UDP header fields
                 let consumed_before_port_decompress = consumed;
-                let (src_port, dst_port) = decompress_udp_ports(nhc_header, &buf, &mut consumed);
+                let (src_port, dst_port) = decompress_udp_ports(nhc_header, &buf, &mut consumed)?;
 
                 //need to add any growth from decompression to the udp length if we used the buf
                 //len to calculate the length
@@ -736,10 +748,10 @@ pub fn decompress(
                     &buf,
                     &mut consumed,
                     is_fragment,
-                );
+                )?;
                 u16_to_network_slice(udp_checksum.to_be(), &mut next_headers[6..8]);
 
-                written += 8;
+                written += UDP_HDR_LEN;
                 break;
             }
             ip6_nh::FRAGMENT
@@ -750,59 +762,58 @@ pub fn decompress(
                 // True if the next header is also compressed
                 is_nhc = (nhc_header & nhc::NH) != 0;
 
+                // An uncompressed next header is carried inline, before the
+                // length field
+                let inline_next_header = if is_nhc {
+                    None
+                } else {
+                    let nh = byte_at(buf, consumed)?;
+                    consumed += 1;
+                    Some(nh)
+                };
+
                 // len is the number of octets following the length field
-                let len = buf[consumed] as usize;
+                let len = byte_at(buf, consumed)? as usize;
                 consumed += 1;
-
-                // Check that there is a next header in the buffer,
-                // which must be the case if the last next header specifies
-                // NH = 1
-                if consumed + len >= buf.len() {
-                    return Err(());
-                }
-
-                // Length in 8-octet units after the first 8 octets
-                // (per the IPv6 ext hdr spec)
-                let mut hdr_len_field = (len - 6) / 8;
-                if (len - 6) % 8 != 0 {
-                    hdr_len_field += 1;
-                }
-
-                // Gets the type of the subsequent next header.  If is_nhc
-                // is true, there must be a LoWPAN NHC header byte,
-                // otherwise there is either an uncompressed next header.
-                next_header = if is_nhc {
-                    // The next header is LoWPAN NHC-compressed
-                    nhc_to_ip6_nh(buf[consumed + len])?
-                } else {
-                    // The next header is uncompressed
-                    buf[consumed + len]
+                let options = slice_at(buf, consumed, len)?;
+
+                // Gets the type of the subsequent next header. If is_nhc is
+                // true, it is given by the LoWPAN NHC header byte that
+                // follows the options, which the next iteration consumes.
+                next_header = match inline_next_header {
+                    Some(nh) => nh,
+                    None => nhc_to_ip6_nh(byte_at(buf, consumed + len)?)?,
                 };
 
-                // Fill in the extended header in uncompressed IPv6 format
-                next_headers[0] = next_header;
-                next_headers[1] = hdr_len_field as u8;
+                // Fill in the extended header in uncompressed IPv6 format,
+                // padded to a multiple of 8 octets (per the IPv6 ext hdr spec)
+                let hdr_len = (2 + len + 7) & !7;
+                let header = next_headers.get_mut(..hdr_len).ok_or(ReturnCode::ESIZE)?;
+                header[0] = next_header;
+                // Length in 8-octet units after the first 8 octets
+                header[1] = (hdr_len / 8 - 1) as u8;
                 // Copies over the remaining options.
-                next_headers[2..2 + len].copy_from_slice(&buf[consumed..consumed + len]);
+                header[2..2 + len].copy_from_slice(options);
 
                 // Fill in padding
-                let pad_bytes = hdr_len_field * 8 - len + 6;
-                if pad_bytes == 1 {
+                match hdr_len - 2 - len {
+                    0 => {}
                     // Pad1
-                    next_headers[2 + len] = 0;
-                } else {
+                    1 => header[2 + len] = 0,
                     // PadN, 2 <= pad_bytes <= 7
-                    next_headers[2 + len] = 1;
-                    next_headers[2 + len + 1] = pad_bytes as u8 - 2;
-                    for i in 2..pad_bytes {
-                        next_headers[2 + len + i] = 0;
+                    pad_bytes => {
+                        header[2 + len] = 1;
+                        header[2 + len + 1] = pad_bytes as u8 - 2;
+                        for byte in header[2 + len + 2..].iter_mut() {
+                            *byte = 0;
+                        }
                     }
                 }
 
-                written += 8 + hdr_len_field * 8;
+                written += hdr_len;
                 consumed += len;
             }
-            _ => panic!("Unreachable case"),
+            _ => return Err(ReturnCode::EINVAL),
         }
     }
 
@@ -810,50 +821,75 @@ pub fn decompress(
     // including extension headers. This is thus the uncompressed
     // size of the IPv6 packet - the fixed IPv6 header.
     let payload_len = if is_fragment {
-        (dgram_size as usize) - mem::size_of::<IP6Header>()
+        (dgram_size as usize)
+            .checked_sub(mem::size_of::<IP6Header>())
+            .ok_or(ReturnCode::ESIZE)?
     } else {
         written + (buf.len() - consumed) - mem::size_of::<IP6Header>()
     };
     ip6_header.payload_len = (payload_len as u16).to_be();
-    IP6Header::encode(&ip6_header, out_buf).done().ok_or(())?;
+    IP6Header::encode(&ip6_header, out_buf)
+        .done()
+        .ok_or(ReturnCode::ESIZE)?;
     Ok((consumed, written))
 }
 
+/// Returns the byte of `buf` at `offset`, or ESIZE if `buf` is too short.
+fn byte_at(buf: &[u8], offset: usize) -> Result<u8, ReturnCode> {
+    buf.get(offset).copied().ok_or(ReturnCode::ESIZE)
+}
+
+/// Returns the `len` bytes of `buf` at `offset`, or ESIZE if `buf` is too
+/// short.
+fn slice_at(buf: &[u8], offset: usize, len: usize) -> Result<&[u8], ReturnCode> {
+    buf.get(offset..offset + len).ok_or(ReturnCode::ESIZE)
+}
+
 fn decompress_cie(
     ctx_store: &dyn ContextStore,
     iphc_header: u8,
     buf: &[u8],
     consumed: &mut usize,
-) -> Result<(Context, Context), ()> {
-    let ctx_0 = ctx_store.get_context_0();
+) -> Result<(Context, Context), ReturnCode> {
+    let ctx_0 = ctx_store.get_context_0().ok_or(ReturnCode::EINVAL)?;
     let (mut src_ctx, mut dst_ctx) = (ctx_0, ctx_0);
     if iphc_header & iphc::CID != 0 {
-        let sci = buf[*consumed] >> 4;
-        let dci = buf[*consumed] & 0xf;
+        let cie = byte_at(buf, *consumed)?;
+        let sci = cie >> 4;
+        let dci = cie & 0xf;
         *consumed += 1;
 
         if sci != 0 {
-            src_ctx = ctx_store.get_context_from_id(sci).ok_or(())?;
+            src_ctx = ctx_store
+                .get_context_from_id(sci)
+                .ok_or(ReturnCode::EINVAL)?;
         }
         if dci != 0 {
-            dst_ctx = ctx_store.get_context_from_id(dci).ok_or(())?;
+            dst_ctx = ctx_store
+                .get_context_from_id(dci)
+                .ok_or(ReturnCode::EINVAL)?;
         }
     }
     Ok((src_ctx, dst_ctx))
 }
 
-fn decompress_tf(ip6_header: &mut IP6Header, iphc_header: u8, buf: &[u8], consumed: &mut usize) {
+fn decompress_tf(
+    ip6_header: &mut IP6Header,
+    iphc_header: u8,
+    buf: &[u8],
+    consumed: &mut usize,
+) -> Result<(), ReturnCode> {
     let fl_compressed = (iphc_header & iphc::TF_FLOW_LABEL) != 0;
     let tc_compressed = (iphc_header & iphc::TF_TRAFFIC_CLASS) != 0;
 
     // Determine ECN and DSCP separately because the order is different
     // from the IPv6 traffic class field.
     if !fl_compressed || !tc_compressed {
-        let ecn = buf[*consumed] >> 6;
+        let ecn = byte_at(buf, *consumed)? >> 6;
         ip6_header.set_ecn(ecn);
     }
     if !tc_compressed {
-        let dscp = buf[*consumed] & 0b111111;
+        let dscp = byte_at(buf, *consumed)? & 0b111111;
         ip6_header.set_dscp(dscp);
         *consumed += 1;
     }
@@ -863,22 +899,28 @@ fn decompress_tf(ip6_header: &mut IP6Header, iphc_header: u8, buf: &[u8], consum
     if fl_compressed {
         ip6_header.set_flow_label(0);
     } else {
-        let flow = (((buf[*consumed] & 0x0f) as u32) << 16)
-            | ((buf[*consumed + 1] as u32) << 8)
-            | (buf[*consumed + 2] as u32);
+        let flow_bytes = slice_at(buf, *consumed, 3)?;
+        let flow = (((flow_bytes[0] & 0x0f) as u32) << 16)
+            | ((flow_bytes[1] as u32) << 8)
+            | (flow_bytes[2] as u32);
         *consumed += 3;
         ip6_header.set_flow_label(flow);
     }
+    Ok(())
 }
 
-fn decompress_nh(iphc_header: u8, buf: &[u8], consumed: &mut usize) -> (bool, u8) {
+fn decompress_nh(
+    iphc_header: u8,
+    buf: &[u8],
+    consumed: &mut usize,
+) -> Result<(bool, u8), ReturnCode> {
     let is_nhc = (iphc_header & iphc::NH) != 0;
     let mut next_header: u8 = 0;
     if !is_nhc {
-        next_header = buf[*consumed];
+        next_header = byte_at(buf, *consumed)?;
         *consumed += 1;
     }
-    (is_nhc, next_header)
+    Ok((is_nhc, next_header))
 }
 
 fn decompress_hl(
@@ -886,17 +928,17 @@ fn decompress_hl(
     iphc_header: u8,
     buf: &[u8],
     consumed: &mut usize,
-) -> Result<(), ()> {
+) -> Result<(), ReturnCode> {
     let hop_limit = match iphc_header & iphc::HLIM_MASK {
         iphc::HLIM_1 => 1,
         iphc::HLIM_64 => 64,
         iphc::HLIM_255 => 255,
-        iphc::HLIM_INLINE => {
-            let hl = buf[*consumed];
+        // iphc::HLIM_INLINE
+        _ => {
+            let hl = byte_at(buf, *consumed)?;
             *consumed += 1;
             hl
         }
-        _ => panic!("Unreachable case"),
     };
     ip6_header.set_hop_limit(hop_limit);
     Ok(())
@@ -909,7 +951,7 @@ fn decompress_src(
     ctx: &Context,
     buf: &[u8],
     consumed: &mut usize,
-) -> Result<(), ()> {
+) -> Result<(), ReturnCode> {
     let uses_context = (iphc_header & iphc::SAC) != 0;
     let sam_mode = iphc_header & iphc::SAM_MASK;
     if uses_context && sam_mode == iphc::SAM_INLINE {
@@ -938,12 +980,12 @@ fn decompress_dst(
     ctx: &Context,
     buf: &[u8],
     consumed: &mut usize,
-) -> Result<(), ()> {
+) -> Result<(), ReturnCode> {
     let uses_context = (iphc_header & iphc::DAC) != 0;
     let dam_mode = iphc_header & iphc::DAM_MASK;
     if uses_context && dam_mode == iphc::DAM_INLINE {
         // DAC = 1, DAM = 00: Reserved
-        return Err(());
+        return Err(ReturnCode::EINVAL);
     } else if uses_context {
         // DAC = 1, DAM = 01, 10, 11
         decompress_iid_context(
@@ -967,7 +1009,7 @@ fn decompress_multicast(
     ctx: &Context,
     buf: &[u8],
     consumed: &mut usize,
-) -> Result<(), ()> {
+) -> Result<(), ReturnCode> {
     let uses_context = (iphc_header & iphc::DAC) != 0;
     let dam_mode = iphc_header & iphc::DAM_MASK;
     let ip_addr: &mut IPAddr = &mut ip6_header.dst_addr;
@@ -981,55 +1023,55 @@ fn decompress_multicast(
                     // The maximum prefix length for this mode is 64 bits.
                     // If the specified prefix exceeds this length, the
                     // compression is invalid.
-                    return Err(());
+                    return Err(ReturnCode::EINVAL);
                 }
+                let inline = slice_at(buf, *consumed, 6)?;
                 ip_addr.0[0] = 0xff;
-                ip_addr.0[1] = buf[*consumed];
-                ip_addr.0[2] = buf[*consumed + 1];
+                ip_addr.0[1] = inline[0];
+                ip_addr.0[2] = inline[1];
                 ip_addr.0[3] = ctx.prefix_len;
                 ip_addr.0[4..4 + prefix_bytes].copy_from_slice(&ctx.prefix[0..prefix_bytes]);
-                ip_addr.0[12..16].copy_from_slice(&buf[*consumed + 2..*consumed + 6]);
+                ip_addr.0[12..16].copy_from_slice(&inline[2..6]);
                 *consumed += 6;
             }
             _ => {
                 // DAC = 1, DAM = 01, 10, 11: Reserved
-                return Err(());
+                return Err(ReturnCode::EINVAL);
             }
         }
     } else {
         match dam_mode {
             // DAC = 0, DAM = 00: Inline
             iphc::DAM_INLINE => {
-                ip_addr.0.copy_from_slice(&buf[*consumed..*consumed + 16]);
+                ip_addr.0.copy_from_slice(slice_at(buf, *consumed, 16)?);
                 *consumed += 16;
             }
             // DAC = 0, DAM = 01: 48 bits
             // ffXX::00XX:XXXX:XXXX
             iphc::DAM_MODE1 => {
+                let inline = slice_at(buf, *consumed, 6)?;
                 ip_addr.0[0] = 0xff;
-                ip_addr.0[1] = buf[*consumed];
-                *consumed += 1;
-                ip_addr.0[11..16].copy_from_slice(&buf[*consumed..*consumed + 5]);
-                *consumed += 5;
+                ip_addr.0[1] = inline[0];
+                ip_addr.0[11..16].copy_from_slice(&inline[1..6]);
+                *consumed += 6;
             }
             // DAC = 0, DAM = 10: 32 bits
             // ffXX::00XX:XXXX
             iphc::DAM_MODE2 => {
+                let inline = slice_at(buf, *consumed, 4)?;
                 ip_addr.0[0] = 0xff;
-                ip_addr.0[1] = buf[*consumed];
-                *consumed += 1;
-                ip_addr.0[13..16].copy_from_slice(&buf[*consumed..*consumed + 3]);
-                *consumed += 3;
+                ip_addr.0[1] = inline[0];
+                ip_addr.0[13..16].copy_from_slice(&inline[1..4]);
+                *consumed += 4;
             }
             // DAC = 0, DAM = 11: 8 bits
             // ff02::00XX
-            iphc::DAM_MODE3 => {
+            _ => {
                 ip_addr.0[0] = 0xff;
                 ip_addr.0[1] = 0x02;
-                ip_addr.0[15] = buf[*consumed];
+                ip_addr.0[15] = byte_at(buf, *consumed)?;
                 *consumed += 1;
             }
-            _ => panic!("Unreachable case"),
         }
     }
     Ok(())
@@ -1041,20 +1083,20 @@ fn decompress_iid_link_local(
     mac_addr: &MacAddress,
     buf: &[u8],
     consumed: &mut usize,
-) -> Result<(), ()> {
+) -> Result<(), ReturnCode> {
     let mode = addr_mode & (iphc::SAM_MASK | iphc::DAM_MASK);
     match mode {
         // SAM, DAM = 00: Inline
         iphc::SAM_INLINE => {
             // SAM_INLINE is equivalent to DAM_INLINE
-            ip_addr.0.copy_from_slice(&buf[*consumed..*consumed + 16]);
+            ip_addr.0.copy_from_slice(slice_at(buf, *consumed, 16)?);
             *consumed += 16;
         }
         // SAM, DAM = 01: 64 bits
         // Link-local prefix (64 bits) + 64 bits carried inline
         iphc::SAM_MODE1 | iphc::DAM_MODE1 => {
             ip_addr.set_unicast_link_local();
-            ip_addr.0[8..16].copy_from_slice(&buf[*consumed..*consumed + 8]);
+            ip_addr.0[8..16].copy_from_slice(slice_at(buf, *consumed, 8)?);
             *consumed += 8;
         }
         // SAM, DAM = 11: 16 bits
@@ -1062,16 +1104,15 @@ fn decompress_iid_link_local(
         iphc::SAM_MODE2 | iphc::DAM_MODE2 => {
             ip_addr.set_unicast_link_local();
             ip_addr.0[11..13].copy_from_slice(&iphc::MAC_BASE[3..5]);
-            ip_addr.0[14..16].copy_from_slice(&buf[*consumed..*consumed + 2]);
+            ip_addr.0[14..16].copy_from_slice(slice_at(buf, *consumed, 2)?);
             *consumed += 2;
         }
         // SAM, DAM = 11: 0 bits
         // Linx-local prefix (64 bits) + IID from outer header (64 bits)
-        iphc::SAM_MODE3 | iphc::DAM_MODE3 => {
+        _ => {
             ip_addr.set_unicast_link_local();
             ip_addr.0[8..16].copy_from_slice(&compute_iid(mac_addr));
         }
-        _ => panic!("Unreachable case"),
     }
     Ok(())
 }
@@ -1083,34 +1124,33 @@ fn decompress_iid_context(
     ctx: &Context,
     buf: &[u8],
     consumed: &mut usize,
-) -> Result<(), ()> {
+) -> Result<(), ReturnCode> {
     let mode = addr_mode & (iphc::SAM_MASK | iphc::DAM_MASK);
     match mode {
         // DAM = 00: Reserved
         // SAM = 0 is handled separately outside this method
         iphc::DAM_INLINE => {
-            return Err(());
+            return Err(ReturnCode::EINVAL);
         }
         // SAM, DAM = 01: 64 bits
         // Suffix is the 64 bits carried inline
         iphc::SAM_MODE1 | iphc::DAM_MODE1 => {
-            ip_addr.0[8..16].copy_from_slice(&buf[*consumed..*consumed + 8]);
+            ip_addr.0[8..16].copy_from_slice(slice_at(buf, *consumed, 8)?);
             *consumed += 8;
         }
         // SAM, DAM = 10: 16 bits
         // Suffix is 0000:00ff:fe00:XXXX
         iphc::SAM_MODE2 | iphc::DAM_MODE2 => {
             ip_addr.0[8..16].copy_from_slice(&iphc::MAC_BASE);
-            ip_addr.0[14..16].copy_from_slice(&buf[*consumed..*consumed + 2]);
+            ip_addr.0[14..16].copy_from_slice(slice_at(buf, *consumed, 2)?);
             *consumed += 2;
         }
         // SAM, DAM = 11: 0 bits
         // Suffix is the IID computed from the encapsulating header
-        iphc::SAM_MODE3 | iphc::DAM_MODE3 => {
+        _ => {
             let iid = compute_iid(mac_addr);
             ip_addr.0[8..16].copy_from_slice(&iid[0..8]);
         }
-        _ => panic!("Unreachable case"),
     }
     // The bits covered by the provided context are always used, so we copy
     // the context bits into the address after the non-context bits are set.
@@ -1119,7 +1159,11 @@ fn decompress_iid_context(
 }
 
 // Returns the UDP ports in host byte-order
-fn decompress_udp_ports(udp_nhc: u8, buf: &[u8], consumed: &mut usize) -> (u16, u16) {
+fn decompress_udp_ports(
+    udp_nhc: u8,
+    buf: &[u8],
+    consumed: &mut usize,
+) -> Result<(u16, u16), ReturnCode> {
     let src_compressed = (udp_nhc & nhc::UDP_SRC_PORT_FLAG) != 0;
     let dst_compressed = (udp_nhc & nhc::UDP_DST_PORT_FLAG) != 0;
 
@@ -1127,30 +1171,34 @@ fn decompress_udp_ports(udp_nhc: u8, buf: &[u8], consumed: &mut usize) -> (u16,
     let dst_port;
     if src_compressed && dst_compressed {
         // Both src and dst are compressed to 4 bits
-        let src_short = ((buf[*consumed] >> 4) & 0xf) as u16;
-        let dst_short = (buf[*consumed] & 0xf) as u16;
+        let ports = byte_at(buf, *consumed)?;
+        let src_short = ((ports >> 4) & 0xf) as u16;
+        let dst_short = (ports & 0xf) as u16;
         src_port = nhc::UDP_4BIT_PORT | src_short;
         dst_port = nhc::UDP_4BIT_PORT | dst_short;
         *consumed += 1;
     } else if src_compressed {
+        let ports = slice_at(buf, *consumed, 3)?;
         // Source port is compressed to 8 bits
-        src_port = nhc::UDP_8BIT_PORT | (buf[*consumed] as u16);
+        src_port = nhc::UDP_8BIT_PORT | (ports[0] as u16);
         // Destination port is uncompressed
-        dst_port = u16::from_be(network_slice_to_u16(&buf[*consumed + 1..*consumed + 3]));
+        dst_port = u16::from_be(network_slice_to_u16(&ports[1..3]));
         *consumed += 3;
     } else if dst_compressed {
+        let ports = slice_at(buf, *consumed, 3)?;
         // Source port is uncompressed
-        src_port = u16::from_be(network_slice_to_u16(&buf[*consumed..*consumed + 2]));
+        src_port = u16::from_be(network_slice_to_u16(&ports[0..2]));
         // Destination port is compressed to 8 bits
-        dst_port = nhc::UDP_8BIT_PORT | (buf[*consumed + 2] as u16);
+        dst_port = nhc::UDP_8BIT_PORT | (ports[2] as u16);
         *consumed += 3;
     } else {
         // Both ports are uncompressed
-        src_port = u16::from_be(network_slice_to_u16(&buf[*consumed..*consumed + 2]));
-        dst_port = u16::from_be(network_slice_to_u16(&buf[*consumed + 2..*consumed + 4]));
+        let ports = slice_at(buf, *consumed, 4)?;
+        src_port = u16::from_be(network_slice_to_u16(&ports[0..2]));
+        dst_port = u16::from_be(network_slice_to_u16(&ports[2..4]));
         *consumed += 4;
     }
-    (src_port, dst_port)
+    Ok((src_port, dst_port))
 }
 
 // Returns the UDP checksum in host byte-order
@@ -1162,7 +1210,7 @@ fn decompress_udp_checksum(
     buf: &[u8],
     consumed: &mut usize,
     is_fragment: bool,
-) -> u16 {
+) -> Result<u16, ReturnCode> {
     // TODO: In keeping with Postel's Law, we accept UDP packets that elide the
     // checksum (per RFC 6282). We are not sure if we should continue to support
     // this feature however.
@@ -1172,17 +1220,17 @@ fn decompress_udp_checksum(
         let mut udp_header_copy: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
         udp_header_copy.copy_from_slice(udp_header);
         match UDPHeader::decode(&udp_header_copy).done() {
-            Some((_offset, hdr)) => u16::from_be(compute_udp_checksum(
+            Some((_offset, hdr)) => Ok(u16::from_be(compute_udp_checksum(
                 ip6_header,
                 &hdr,
                 udp_length,
                 &buf[*consumed..],
-            )),
-            None => 0, //Will be dropped  by IP layer
+            ))),
+            None => Ok(0), //Will be dropped  by IP layer
         }
     } else {
-        let checksum = u16::from_be(network_slice_to_u16(&buf[*consumed..*consumed + 2]));
+        let checksum = u16::from_be(network_slice_to_u16(slice_at(buf, *consumed, 2)?));
         *consumed += 2;
-        checksum
+        Ok(checksum)
     }
 }
@@ -228,6 +228,7 @@ use crate::ieee802154::device::{MacDevice, RxClient};
 use crate::ieee802154::framer::Frame;
 use crate::net::frag_utils::Bitmap;
 use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
+use crate::net::ipv6::ext_headers::MAX_EXT_HDRS_LEN;
 use crate::net::ipv6::ipv6::IP6Packet;
 use crate::net::sixlowpan::sixlowpan_compression;
 use crate::net::sixlowpan::sixlowpan_compression::{is_lowpan, ContextStore};
@@ -481,7 +482,7 @@ impl<'a> TxState<'a> {
                 self.dst_mac_addr.get(),
                 &mut lowpan_packet,
             ) {
-                Err(_) => return Err((ReturnCode::FAIL, frame.into_buf())),
+                Err(code) => return Err((code, frame.into_buf())),
                 Ok(result) => result,
             }
         };
@@ -574,7 +575,7 @@ impl<'a> TxState<'a> {
             // statically allocate room on the stack. However, we do not know
             // how many additional headers we have until runtime. This
             // functionality should be fixed in the future.
-            let mut headers = [0 as u8; 60];
+            let mut headers = [0 as u8; 60 + MAX_EXT_HDRS_LEN];
             ip6_packet.encode(&mut headers);
             frame.append_payload(&headers[dgram_offset..dgram_offset + headers_to_write]);
             payload_len -= headers_to_write;
@@ -683,8 +684,9 @@ impl<'a> RxState<'a> {
     // Checks if a given RxState is free or expired (and thus, can be freed).
     // This function implements the reassembly timeout for 6LoWPAN lazily.
     fn is_busy(&self, frequency: u32, current_time: u32) -> bool {
-        let expired = current_time >= (self.start_time.get() + FRAG_TIMEOUT * frequency);
-        if expired {
+        let expired = current_time.wrapping_sub(self.start_time.get())
+            >= FRAG_TIMEOUT.saturating_mul(frequency);
+        if self.busy.get() && expired {
             self.end_receive(None, ReturnCode::FAIL);
         }
         self.busy.get()
@@ -719,27 +721,16 @@ impl<'a> RxState<'a> {
         ctx_store: &dyn ContextStore,
     ) -> Result<bool, ReturnCode> {
         let mut packet = self.packet.take().ok_or(ReturnCode::ENOMEM)?;
-        let uncompressed_len = if dgram_offset == 0 {
-            let (consumed, written) = sixlowpan_compression::decompress(
-                ctx_store,
-                &payload[0..payload_len as usize],
-                self.src_mac_addr.get(),
-                self.dst_mac_addr.get(),
-                &mut packet,
-                dgram_size,
-                true,
-            )
-            .map_err(|_| ReturnCode::FAIL)?;
-            let remaining = payload_len - consumed;
-            packet[written..written + remaining]
-                .copy_from_slice(&payload[consumed..consumed + remaining]);
-            written + remaining
-        } else {
-            packet[dgram_offset..dgram_offset + payload_len]
-                .copy_from_slice(&payload[0..payload_len]);
-            payload_len
-        };
+        let result = self.copy_fragment(
+            &mut packet,
+            payload,
+            payload_len,
+            dgram_size,
+            dgram_offset,
+            ctx_store,
+        );
         self.packet.replace(packet);
+        let uncompressed_len = result?;
         if !self.bitmap.map_or(false, |bitmap| {
             bitmap.set_bits(dgram_offset / 8, (dgram_offset + uncompressed_len) / 8)
         }) {
@@ -753,6 +744,43 @@ impl<'a> RxState<'a> {
         }
     }
 
+    // Decompresses or copies a received fragment into `packet`, and returns
+    // its uncompressed length. Fails with ESIZE if the fragment does not fit
+    // in the datagram or the datagram does not fit in `packet`.
+    fn copy_fragment(
+        &self,
+        packet: &mut [u8],
+        payload: &[u8],
+        payload_len: usize,
+        dgram_size: u16,
+        dgram_offset: usize,
+        ctx_store: &dyn ContextStore,
+    ) -> Result<usize, ReturnCode> {
+        let dgram_size = dgram_size as usize;
+        if dgram_size > packet.len() {
+            return Err(ReturnCode::ESIZE);
+        }
+        let (consumed, written) = if dgram_offset == 0 {
+            sixlowpan_compression::decompress(
+                ctx_store,
+                &payload[0..payload_len],
+                self.src_mac_addr.get(),
+                self.dst_mac_addr.get(),
+                &mut packet[..dgram_size],
+                dgram_size as u16,
+                true,
+            )?
+        } else {
+            (0, dgram_offset)
+        };
+        let remaining = payload_len - consumed;
+        if written + remaining > dgram_size {
+            return Err(ReturnCode::ESIZE);
+        }
+        packet[written..written + remaining].copy_from_slice(&payload[consumed..payload_len]);
+        Ok(written + remaining - dgram_offset)
+    }
+
     fn end_receive(&self, client: Option<&'a dyn SixlowpanRxClient>, result: ReturnCode) {
         self.busy.set(false);
         self.bitmap.map(|bitmap| bitmap.clear());
@@ -878,6 +906,9 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
         src_mac_addr: MacAddress,
         dst_mac_addr: MacAddress,
     ) -> (Option<&RxState<'a>>, ReturnCode) {
+        if packet_len < lowpan_frag::FRAGN_HDR_SIZE {
+            return (None, ReturnCode::ESIZE);
+        }
         if is_fragment(packet) {
             let (is_frag1, dgram_size, dgram_tag, dgram_offset) = get_frag_hdr(&packet[0..5]);
             let offset_to_payload = if is_frag1 {
@@ -909,7 +940,7 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
         let rx_state = self
             .rx_states
             .iter()
-            .find(|state| !state.is_busy(self.clock.now(), A::Frequency::frequency()));
+            .find(|state| !state.is_busy(A::Frequency::frequency(), self.clock.now()));
         rx_state.map_or((None, ReturnCode::ENOMEM), |state| {
             state.start_receive(
                 src_mac_addr,
@@ -925,8 +956,8 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
                 "Error: `packet` in RxState struct is `None` \
                  in call to `receive_single_packet`.",
             );
-            if is_lowpan(payload) {
-                let decompressed = sixlowpan_compression::decompress(
+            let result = if is_lowpan(payload) {
+                sixlowpan_compression::decompress(
                     &self.ctx_store,
                     &payload[0..payload_len as usize],
                     src_mac_addr,
@@ -934,24 +965,30 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
                     &mut packet,
                     0,
                     false,
-                );
-                match decompressed {
-                    Ok((consumed, written)) => {
-                        let remaining = payload_len - consumed;
-                        packet[written..written + remaining]
-                            .copy_from_slice(&payload[consumed..consumed + remaining]);
-                        // Want dgram_size to contain decompressed size of packet
-                        state.dgram_size.set((written + remaining) as u16);
-                    }
-                    Err(_) => {
-                        return (None, ReturnCode::FAIL);
-                    }
-                }
+                )
+                .and_then(|(consumed, written)| {
+                    let remaining = payload_len - consumed;
+                    packet
+                        .get_mut(written..written + remaining)
+                        .ok_or(ReturnCode::ESIZE)?
+                        .copy_from_slice(&payload[consumed..payload_len]);
+                    // Want dgram_size to contain decompressed size of packet
+                    state.dgram_size.set((written + remaining) as u16);
+                    Ok(())
+                })
             } else {
-                packet[0..payload_len].copy_from_slice(&payload[0..payload_len]);
-            }
+                packet
+                    .get_mut(0..payload_len)
+                    .ok_or(ReturnCode::ESIZE)
+                    .map(|dst| dst.copy_from_slice(&payload[0..payload_len]))
+            };
+            // The packet is returned to the client even if it could not be
+            // decompressed, along with the error.
             state.packet.replace(packet);
-            (Some(state), ReturnCode::SUCCESS)
+            match result {
+                Ok(()) => (Some(state), ReturnCode::SUCCESS),
+                Err(code) => (Some(state), code),
+            }
         })
     }
 
@@ -979,7 +1016,7 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
             rx_state = self
                 .rx_states
                 .iter()
-                .find(|state| !state.is_busy(self.clock.now(), A::Frequency::frequency()));
+                .find(|state| !state.is_busy(A::Frequency::frequency(), self.clock.now()));
             // Initialize new state
             rx_state.map(|state| {
                 state.start_receive(
@@ -1005,7 +1042,7 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
             );
             match res {
                 // Some error occurred
-                Err(_) => (Some(state), ReturnCode::FAIL),
+                Err(code) => (Some(state), code),
                 Ok(complete) => {
                     if complete {
                         // Packet fully reassembled
@@ -1020,14 +1057,294 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
     }
 
     #[allow(dead_code)]
-    // TODO: This code is currently unimplemented
     // This function is called when a disassociation event occurs, as we need
     // to expire all pending state.
+    // TODO: Need to get buffer back from Mac layer on disassociation
     fn discard_all_state(&self) {
         for rx_state in self.rx_states.iter() {
             rx_state.end_receive(None, ReturnCode::FAIL);
         }
-        unimplemented!();
-        // TODO: Need to get buffer back from Mac layer on disassociation
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{RxState, Sixlowpan, SixlowpanRxClient, SixlowpanState};
+    use crate::ieee802154::device::RxClient;
+    use crate::net::ieee802154::{FrameType, FrameVersion, Header, MacAddress};
+    use crate::net::ipv6::ip_utils::{compute_udp_checksum, ip6_nh};
+    use crate::net::ipv6::ipv6::IP6Header;
+    use crate::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvClient, IP6RecvStruct};
+    use crate::net::loopback::link_local_addr;
+    use crate::net::sixlowpan::sixlowpan_compression::{self, Context};
+    use crate::net::udp::udp::UDPHeader;
+    use core::cell::{Cell, RefCell};
+    use host::harness::leak;
+    use host::mock::alarm::MockAlarm;
+    use kernel::ReturnCode;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct ReceivedPackets {
+        packets: Cell<usize>,
+        errors: RefCell<Vec<ReturnCode>>,
+    }
+
+    impl IP6RecvClient for ReceivedPackets {
+        fn receive(&self, _header: IP6Header, _payload: &[u8]) {
+            self.packets.set(self.packets.get() + 1);
+        }
+
+        fn receive_error(&self, error: ReturnCode) {
+            self.errors.borrow_mut().push(error);
+        }
+    }
+
+    /// What became of a received frame.
+    #[derive(Debug, PartialEq)]
+    enum Outcome {
+        /// A packet was passed to the IPv6 clients.
+        Received,
+        /// The frame was reported as malformed, with this error.
+        Error(ReturnCode),
+        /// Nothing was reported, because the frame was dropped or is a
+        /// fragment of a datagram still being reassembled.
+        Nothing,
+    }
+
+    impl ReceivedPackets {
+        /// The outcome of `receive`, which must report at most one packet or
+        /// error.
+        fn outcome<F: FnOnce()>(&self, receive: F) -> Outcome {
+            let (packets, errors) = (self.packets.get(), self.errors.borrow().len());
+            receive();
+            let errors = &self.errors.borrow()[errors..];
+            match (self.packets.get() - packets, errors) {
+                (0, []) => Outcome::Nothing,
+                (1, []) => Outcome::Received,
+                (0, [error]) => Outcome::Error(*error),
+                reported => panic!("one frame reported {:?}", reported),
+            }
+        }
+    }
+
+    /// A xorshift generator, so that a failing input can be reproduced.
+    struct XorShift(u32);
+
+    impl XorShift {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            self.next() as usize % bound
+        }
+    }
+
+    /// Frames that exercise the decoders: a header with every field inline,
+    /// compressed headers with inline, UDP and extension next headers, and
+    /// the two fragments of a compressed datagram.
+    fn seed_frames() -> Vec<Vec<u8>> {
+        // An IPv6 packet whose first bytes double as an IPHC header that
+        // carries every field inline.
+        let mut uncompressed = vec![0x60, 0, 0, 0, 0, 8, ip6_nh::UDP, 64];
+        uncompressed.extend_from_slice(&link_local_addr(1).0);
+        uncompressed.extend_from_slice(&link_local_addr(2).0);
+        uncompressed.extend_from_slice(&[0x12, 0x34, 0x56, 0x78, 0, 8, 0, 0]);
+        let (_, ip6_header) = IP6Header::decode(&uncompressed).done().unwrap();
+        let (_, udp_header) = UDPHeader::decode(&uncompressed[40..]).done().unwrap();
+        let checksum = compute_udp_checksum(&ip6_header, &udp_header, 8, &[]);
+        uncompressed[46..].copy_from_slice(&checksum.to_be_bytes());
+        let iphc_udp = [0x7e, 0x33, 0xf0, 0x12, 0x34, 0x56, 0x78, 0, 0];
+        let mut frag1 = vec![0xc0, 64, 0, 7];
+        frag1.extend_from_slice(&iphc_udp);
+        frag1.extend_from_slice(&[0xaa; 8]);
+        let mut fragn = vec![0xe0, 64, 0, 7, 7];
+        fragn.extend_from_slice(&[0xbb; 8]);
+        vec![
+            uncompressed,
+            vec![0x7a, 0x33, ip6_nh::ICMP, 128, 0, 0, 0, 0, 1, 0, 1],
+            [&iphc_udp[..], b"hello"].concat(),
+            vec![
+                0x7e,
+                0x33,
+                0xe0,
+                ip6_nh::ICMP,
+                2,
+                1,
+                0,
+                128,
+                0,
+                0,
+                0,
+                0,
+                1,
+                0,
+                1,
+            ],
+            frag1,
+            fragn,
+        ]
+    }
+
+    #[test]
+    pub fn malformed_frames_are_reported_not_fatal() {
+        type TestSixlowpan = Sixlowpan<'static, MockAlarm<'static>, Context>;
+        let alarm: &MockAlarm = leak(MockAlarm::new());
+        let sixlowpan: &TestSixlowpan = leak(Sixlowpan::new(
+            Context {
+                prefix: [0; 16],
+                prefix_len: 0,
+                id: 0,
+                compress: false,
+            },
+            alarm,
+        ));
+        for _ in 0..2 {
+            sixlowpan.add_rx_state(leak(RxState::new(Box::leak(Box::new([0; 1280])))));
+        }
+        let receiver = leak(IP6RecvStruct::new());
+        let received = leak(ReceivedPackets::default());
+        assert_eq!(receiver.add_client(received), ReturnCode::SUCCESS);
+        sixlowpan.set_rx_client(receiver);
+
+        let header = Header {
+            frame_type: FrameType::Data,
+            frame_pending: false,
+            ack_requested: false,
+            version: FrameVersion::V2006,
+            seq: Some(0),
+            dst_pan: Some(0xabcd),
+            dst_addr: Some(MacAddress::Short(2)),
+            src_pan: Some(0xabcd),
+            src_addr: Some(MacAddress::Short(1)),
+            security: None,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+
+        let receive = |frame: &[u8]| {
+            received.outcome(|| RxClient::receive(sixlowpan, frame, header, 0, frame.len()))
+        };
+
+        // The uncompressed seed is a valid packet, and the others have a
+        // wrong checksum. The first fragment waits for the second.
+        let seeds = seed_frames();
+        let outcomes: Vec<Outcome> = seeds.iter().map(|seed| receive(seed)).collect();
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Received,
+                Outcome::Error(ReturnCode::FAIL),
+                Outcome::Error(ReturnCode::FAIL),
+                Outcome::Error(ReturnCode::FAIL),
+                Outcome::Nothing,
+                Outcome::Error(ReturnCode::FAIL),
+            ]
+        );
+
+        // Frames shorter than a fragment header are dropped.
+        assert_eq!(receive(&[]), Outcome::Nothing);
+        assert_eq!(receive(&[0x7a, 0x33, ip6_nh::ICMP, 128]), Outcome::Nothing);
+        // Inline IPHC fields that are cut short, and a dispatch that is not
+        // 6LoWPAN, which is taken for an IPv6 header that does not decode.
+        assert_eq!(receive(&seeds[0][..20]), Outcome::Error(ReturnCode::ESIZE));
+        assert_eq!(
+            receive(&[0x00, 1, 2, 3, 4, 5]),
+            Outcome::Error(ReturnCode::EINVAL)
+        );
+        // Compressed UDP ports that are cut short.
+        assert_eq!(
+            receive(&[0x7e, 0x33, 0xf0, 0x12, 0x34]),
+            Outcome::Error(ReturnCode::ESIZE)
+        );
+        // A first fragment longer than its datagram.
+        let mut oversized = seeds[4].clone();
+        oversized[1] = 8;
+        assert_eq!(receive(&oversized), Outcome::Error(ReturnCode::ESIZE));
+        // A fragment that overlaps one already received.
+        assert_eq!(receive(&seeds[4]), Outcome::Nothing);
+        assert_eq!(receive(&seeds[4]), Outcome::Error(ReturnCode::FAIL));
+        // A later fragment whose first one never arrives is dropped once
+        // reassembly times out, without a report.
+        assert_eq!(receive(&seeds[5]), Outcome::Nothing);
+        alarm.advance(61 * 32768);
+        assert_eq!(receive(&seeds[2]), Outcome::Error(ReturnCode::FAIL));
+        assert_eq!(receive(&seeds[0]), Outcome::Received);
+        // The IPv6 layer is told a longer length than the buffer has, or
+        // gets a packet whose payload length goes beyond its end.
+        let receive_ip = |packet: &[u8], len: usize| {
+            received
+                .outcome(|| SixlowpanRxClient::receive(receiver, packet, len, ReturnCode::SUCCESS))
+        };
+        let packet = &seeds[0][..];
+        assert_eq!(receive_ip(packet, packet.len()), Outcome::Received);
+        assert_eq!(
+            receive_ip(packet, packet.len() + 1),
+            Outcome::Error(ReturnCode::ESIZE)
+        );
+        let mut long = packet.to_vec();
+        long[4] += 1;
+        assert_eq!(
+            receive_ip(&long, long.len()),
+            Outcome::Error(ReturnCode::ESIZE)
+        );
+
+        let mut rng = XorShift(0x2545_f491);
+        let mut out_buf = [0; 1280];
+        for iteration in 0..20000 {
+            // Mutate a seed, or start from random bytes.
+            let mut frame = match rng.below(4) {
+                0 => (0..rng.below(128)).map(|_| rng.next() as u8).collect(),
+                _ => seeds[rng.below(seeds.len())].clone(),
+            };
+            for _ in 0..rng.below(4) {
+                if !frame.is_empty() {
+                    let index = rng.below(frame.len());
+                    frame[index] = rng.next() as u8;
+                }
+            }
+            if rng.below(4) == 0 {
+                frame.truncate(rng.below(frame.len() + 1));
+            }
+
+            receive(&frame);
+            let out_len = rng.below(out_buf.len() + 1);
+            let _ = sixlowpan_compression::decompress(
+                &sixlowpan.ctx_store,
+                &frame,
+                MacAddress::Short(1),
+                MacAddress::Short(2),
+                &mut out_buf[..out_len],
+                rng.below(1281) as u16,
+                rng.below(2) == 0,
+            );
+            // The IPv6 layer is handed lengths that need not match the buffer.
+            // Every packet handed to it is either received or reported.
+            let claimed_len = rng.below(frame.len() + 8);
+            assert_ne!(
+                receive_ip(&frame[1.min(frame.len())..], claimed_len),
+                Outcome::Nothing
+            );
+
+            // Let incomplete reassemblies time out now and then.
+            if iteration % 64 == 0 {
+                alarm.advance(61 * 32768);
+            }
+        }
+
+        let errors = received.errors.borrow();
+        for code in [ReturnCode::ESIZE, ReturnCode::EINVAL, ReturnCode::FAIL].iter() {
+            assert!(errors.contains(code));
+        }
     }
 }
@@ -0,0 +1,608 @@
+//! TCP userspace interface.
+//!
+//! Lets processes open TCP connections, either by connecting to a peer or by
+//! listening for one, and send and receive data over them. The driver hands
+//! out a fixed set of `TcpConnection`s, given by the board, so each one
+//! belongs to at most one process at a time and each process has at most one
+//! connection. A connection goes back to the set once it is closed, or when
+//! another process needs it after its owner has exited.
+//!
+//! Data to send is copied from the process into a kernel buffer of the
+//! connection, which is held until the peer acknowledges all of it. Received
+//! data is copied into the receive buffer of the process as soon as it
+//! arrives, and the process is called back with its length. Since the
+//! connection always advertises the same window, data that does not fit in
+//! the receive buffer is dropped, so the buffer should hold at least
+//! `RECEIVE_WINDOW` bytes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let connections = static_init!(
+//!     [DriverConnection<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>; 1],
+//!     [DriverConnection::new(tcp_connection, &mut TCP_DRIVER_BUF)]
+//! );
+//! let tcp_driver = static_init!(
+//!     TcpDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     TcpDriver::new(
+//!         connections,
+//!         tcp_port_table,
+//!         board_kernel.create_grant(&grant_cap),
+//!         net_cap
+//!     )
+//! );
+//! tcp_driver.initialize();
+//! ```
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::tcp::tcp_connection::{TcpClient, TcpConnection, TcpState};
+use crate::net::tcp::tcp_port_table::TcpPortManager;
+use crate::net::util::host_slice_to_u16;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Tcp as usize;
+
+/// The length of a remote endpoint in the endpoint buffer: a 16 byte IPv6
+/// address followed by a port, like a `sock_addr_t`.
+pub const ENDPOINT_LEN: usize = mem::size_of::<IPAddr>() + 2;
+
+/// Connection events reported to the connection callback.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum TcpEvent {
+    Connected = 0,
+    PeerClosed = 1,
+    Closed = 2,
+}
+
+#[derive(Default)]
+pub struct App {
+    connection_callback: Option<Callback>,
+    receive_callback: Option<Callback>,
+    send_callback: Option<Callback>,
+    receive_buf: Option<AppSlice<Shared, u8>>,
+    send_buf: Option<AppSlice<Shared, u8>>,
+    endpoint: Option<AppSlice<Shared, u8>>,
+}
+
+/// A connection the driver can give to a process, with the kernel buffer
+/// its data is sent from.
+pub struct DriverConnection<'a, A: time::Alarm<'a>> {
+    connection: &'a TcpConnection<'a, A>,
+    driver: OptionalCell<&'a TcpDriver<'a, A>>,
+    owner: OptionalCell<AppId>,
+    /// Held by the connection while a send is in progress.
+    tx_buf: MapCell<LeasableBuffer<'static, u8>>,
+}
+
+impl<'a, A: time::Alarm<'a>> DriverConnection<'a, A> {
+    pub fn new(
+        connection: &'a TcpConnection<'a, A>,
+        tx_buf: &'static mut [u8],
+    ) -> DriverConnection<'a, A> {
+        DriverConnection {
+            connection: connection,
+            driver: OptionalCell::empty(),
+            owner: OptionalCell::empty(),
+            tx_buf: MapCell::new(LeasableBuffer::new(tx_buf)),
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TcpClient for DriverConnection<'a, A> {
+    fn connected(&self) {
+        self.driver
+            .map(|driver| driver.notify(self, TcpEvent::Connected, ReturnCode::SUCCESS));
+    }
+
+    fn received(&self, data: &[u8]) {
+        self.driver.map(|driver| driver.received(self, data));
+    }
+
+    fn send_done(&self, result: ReturnCode, mut buf: LeasableBuffer<'static, u8>) {
+        let len = buf.len();
+        buf.reset();
+        self.tx_buf.replace(buf);
+        self.driver.map(|driver| {
+            self.owner.map(|owner| {
+                let _ = driver.apps.enter(*owner, |app, _| {
+                    app.send_callback
+                        .map(|mut cb| cb.schedule(result.into(), len, 0));
+                });
+            })
+        });
+    }
+
+    fn peer_closed(&self) {
+        self.driver
+            .map(|driver| driver.notify(self, TcpEvent::PeerClosed, ReturnCode::SUCCESS));
+    }
+
+    fn closed(&self, result: ReturnCode) {
+        self.driver.map(|driver| {
+            driver.notify(self, TcpEvent::Closed, result);
+            driver.release(self);
+        });
+    }
+}
+
+pub struct TcpDriver<'a, A: time::Alarm<'a>> {
+    connections: &'a [DriverConnection<'a, A>],
+    port_table: &'static TcpPortManager,
+    apps: Grant<App>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> TcpDriver<'a, A> {
+    pub fn new(
+        connections: &'a [DriverConnection<'a, A>],
+        port_table: &'static TcpPortManager,
+        grant: Grant<App>,
+        net_cap: &'static NetworkCapability,
+    ) -> TcpDriver<'a, A> {
+        TcpDriver {
+            connections: connections,
+            port_table: port_table,
+            apps: grant,
+            net_cap: net_cap,
+        }
+    }
+
+    /// Makes the driver the client of its connections. Must be called once
+    /// the driver has its final address.
+    pub fn initialize(&'a self) {
+        for connection in self.connections {
+            connection.driver.set(self);
+            connection.connection.set_client(connection);
+        }
+    }
+
+    fn connection_of(&self, appid: AppId) -> Option<&'a DriverConnection<'a, A>> {
+        self.connections
+            .iter()
+            .find(|connection| connection.owner.contains(&appid))
+    }
+
+    /// Gives a free connection to `appid`, bound to `port`. Connections whose
+    /// owner no longer exists are reset and reused.
+    fn allocate(&self, appid: AppId, port: u16) -> Result<&'a DriverConnection<'a, A>, ReturnCode> {
+        if self.connection_of(appid).is_some() {
+            return Err(ReturnCode::EBUSY);
+        }
+        if port == 0 {
+            return Err(ReturnCode::EINVAL);
+        }
+        let free = self.connections.iter().find(|connection| {
+            match connection
+                .owner
+                .map(|owner| self.apps.enter(*owner, |_, _| ()))
+            {
+                None => true,
+                Some(Ok(())) => false,
+                Some(Err(_)) => {
+                    connection.connection.abort();
+                    self.release(connection);
+                    true
+                }
+            }
+        });
+        let connection = match free {
+            Some(connection) => connection,
+            None => return Err(ReturnCode::ENOMEM),
+        };
+        let socket = self.port_table.create_socket()?;
+        match self.port_table.bind(socket, port, self.net_cap) {
+            Ok(binding) => {
+                connection.connection.set_binding(binding);
+                connection.owner.set(appid);
+                Ok(connection)
+            }
+            Err(_socket) => Err(ReturnCode::EBUSY),
+        }
+    }
+
+    /// Takes `connection` back once it is closed, and frees its port.
+    fn release(&self, connection: &DriverConnection<'a, A>) {
+        connection.owner.clear();
+        connection
+            .connection
+            .get_binding()
+            .map(|binding| self.port_table.unbind(binding));
+    }
+
+    /// Releases `connection` if it closed without a `closed` callback.
+    fn release_if_closed(&self, connection: &DriverConnection<'a, A>) {
+        if connection.connection.get_state() == TcpState::Closed {
+            self.release(connection);
+        }
+    }
+
+    fn notify(&self, connection: &DriverConnection<'a, A>, event: TcpEvent, result: ReturnCode) {
+        connection.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |app, _| {
+                app.connection_callback
+                    .map(|mut cb| cb.schedule(event as usize, result.into(), 0));
+            });
+        });
+    }
+
+    fn received(&self, connection: &DriverConnection<'a, A>, data: &[u8]) {
+        connection.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |app, _| {
+                let copied = app.receive_buf.as_mut().map_or(0, |buf| {
+                    let len = cmp::min(buf.len(), data.len());
+                    buf.as_mut()[..len].copy_from_slice(&data[..len]);
+                    len
+                });
+                app.receive_callback
+                    .map(|mut cb| cb.schedule(copied, data.len(), 0));
+            });
+        });
+    }
+
+    /// Reads the remote endpoint from the endpoint buffer of the process.
+    fn endpoint(&self, appid: AppId) -> Result<(IPAddr, u16), ReturnCode> {
+        self.apps
+            .enter(appid, |app, _| match app.endpoint.as_ref() {
+                Some(buf) if buf.len() >= ENDPOINT_LEN => {
+                    let buf = buf.as_ref();
+                    let mut addr = IPAddr::new();
+                    addr.0.copy_from_slice(&buf[..mem::size_of::<IPAddr>()]);
+                    let port = host_slice_to_u16(&buf[mem::size_of::<IPAddr>()..ENDPOINT_LEN]);
+                    Ok((addr, port))
+                }
+                _ => Err(ReturnCode::EINVAL),
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn connect(&self, appid: AppId, local_port: u16) -> ReturnCode {
+        let (addr, port) = match self.endpoint(appid) {
+            Ok(endpoint) => endpoint,
+            Err(err) => return err,
+        };
+        let connection = match self.allocate(appid, local_port) {
+            Ok(connection) => connection,
+            Err(err) => return err,
+        };
+        let result = connection.connection.connect(addr, port, self.net_cap);
+        if result != ReturnCode::SUCCESS {
+            self.release(connection);
+        }
+        result
+    }
+
+    fn listen(&self, appid: AppId, local_port: u16) -> ReturnCode {
+        let connection = match self.allocate(appid, local_port) {
+            Ok(connection) => connection,
+            Err(err) => return err,
+        };
+        let result = connection.connection.listen(self.net_cap);
+        if result != ReturnCode::SUCCESS {
+            self.release(connection);
+        }
+        result
+    }
+
+    fn send(&self, appid: AppId, len: usize) -> ReturnCode {
+        let connection = match self.connection_of(appid) {
+            Some(connection) => connection,
+            None => return ReturnCode::ERESERVE,
+        };
+        let mut buf = match connection.tx_buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        let result = self
+            .apps
+            .enter(appid, |app, _| match app.send_buf.as_ref() {
+                Some(data) if len > 0 && len <= data.len() => {
+                    if len > buf.len() {
+                        return ReturnCode::ESIZE;
+                    }
+                    buf[..len].copy_from_slice(&data.as_ref()[..len]);
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::EINVAL,
+            })
+            .unwrap_or_else(|err| err.into());
+        if result != ReturnCode::SUCCESS {
+            connection.tx_buf.replace(buf);
+            return result;
+        }
+        buf.slice(0..len);
+        match connection.connection.send(buf) {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err(mut buf) => {
+                buf.reset();
+                connection.tx_buf.replace(buf);
+                ReturnCode::FAIL
+            }
+        }
+    }
+
+    fn close(&self, appid: AppId, abort: bool) -> ReturnCode {
+        let connection = match self.connection_of(appid) {
+            Some(connection) => connection,
+            None => return ReturnCode::ERESERVE,
+        };
+        let result = if abort {
+            connection.connection.abort()
+        } else {
+            connection.connection.close()
+        };
+        self.release_if_closed(connection);
+        result
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Driver for TcpDriver<'a, A> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Receive buffer. Received data is written at its start.
+    /// - `1`: Send buffer. Holds the data to send.
+    /// - `2`: Endpoint buffer. Holds the IPv6 address and port of the peer to
+    ///        connect to, laid out as a `sock_addr_t`.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.receive_buf = slice,
+                    1 => app.send_buf = slice,
+                    2 => app.endpoint = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Connection callback. The arguments are the event and a result:
+    ///        `0` once the connection is established, `1` once the peer has
+    ///        closed its side, and `2` once the connection is closed, with
+    ///        SUCCESS if both sides closed it, ECANCEL if it was refused or
+    ///        reset and ENOACK if the peer stopped answering.
+    /// - `1`: Receive callback. The arguments are the number of bytes written
+    ///        to the receive buffer and the number of bytes received, which
+    ///        is larger if the buffer was too short.
+    /// - `2`: Send callback. The arguments are the result, SUCCESS once the
+    ///        peer acknowledged the data or ECANCEL if the connection closed
+    ///        first, and the number of bytes sent.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match subscribe_num {
+                    0 => app.connection_callback = callback,
+                    1 => app.receive_callback = callback,
+                    2 => app.send_callback = callback,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// TCP control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Connect from local port `arg1` to the peer in the endpoint
+    ///        buffer.
+    /// - `2`: Listen for a peer on local port `arg1`.
+    /// - `3`: Send the first `arg1` bytes of the send buffer.
+    /// - `4`: Close the connection once the data being sent is acknowledged.
+    /// - `5`: Reset the connection and close it right away.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 | 2 if arg1 > u16::max_value() as usize => ReturnCode::EINVAL,
+            1 => self.connect(appid, arg1 as u16),
+            2 => self.listen(appid, arg1 as u16),
+            3 => self.send(appid, arg1),
+            4 => self.close(appid, false),
+            5 => self.close(appid, true),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::DriverConnection;
+    use crate::net::loopback::{
+        any_network_capability, link_local_addr, tcp_send, TcpHost, TcpStack,
+    };
+    use crate::net::tcp::tcp_connection::{TcpConnection, TcpState};
+    use host::chip::HostChip;
+    use host::harness::{
+        command, create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp,
+        TestPlatform,
+    };
+    use host::syscall::{HostApp, Resumption};
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::ReturnCode;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    pub fn tcp_driver_connects_sends_and_releases_connections() {
+        let (kernel, processes) = create_kernel();
+        let b = TcpHost::new(2, 80);
+        assert_eq!(b.connection.listen(b.net_cap), ReturnCode::SUCCESS);
+
+        let net_cap = any_network_capability();
+        let a = TcpStack::new(link_local_addr(1), net_cap);
+        let connection = leak(TcpConnection::new(a.mux));
+        a.mux.add_connection(connection);
+        let connections = leak([DriverConnection::new(
+            connection,
+            Box::leak(Box::new([0; 8])),
+        )]);
+        let tcp = leak(crate::net::tcp::TcpDriver::new(
+            connections,
+            a.port_table,
+            create_grant(kernel),
+            net_cap,
+        ));
+        tcp.initialize();
+        let settle = || loop {
+            let a_sent = a.sender.transfer(b.receiver, false);
+            let b_sent = b.sender.transfer(a.receiver, false);
+            if !a_sent && !b_sent {
+                break;
+            }
+        };
+
+        let tcp_command = |command_num, arg| command(crate::net::tcp::DRIVER_NUM, command_num, arg);
+        let allow = |allow_num, offset, len| Syscall::ALLOW {
+            driver_number: crate::net::tcp::DRIVER_NUM,
+            subdriver_number: allow_num,
+            allow_address: offset as *mut u8,
+            allow_size: len,
+        };
+        let subscribe = |subscribe_num| Syscall::SUBSCRIBE {
+            driver_number: crate::net::tcp::DRIVER_NUM,
+            subdriver_number: subscribe_num,
+            callback_ptr: 0x1000 as *mut (),
+            appdata: subscribe_num,
+        };
+        // The endpoint of the peer, then a receive buffer and the data to send.
+        let mut memory = vec![0; 80];
+        memory[..16].copy_from_slice(&b.addr.0);
+        memory[16..18].copy_from_slice(&80u16.to_ne_bytes());
+        memory[64..73].copy_from_slice(b"much data");
+        let app = leak(ScriptedApp::new_with_memory(
+            vec![
+                allow(2, 0, 18),
+                allow(0, 32, 16),
+                allow(1, 64, 16),
+                subscribe(0),
+                subscribe(1),
+                subscribe(2),
+                tcp_command(3, 4),
+                tcp_command(1, 49152),
+                tcp_command(2, 49153),
+                Syscall::YIELD,
+                // Longer than the kernel buffer.
+                tcp_command(3, 9),
+                tcp_command(3, 4),
+                tcp_command(3, 4),
+                Syscall::YIELD,
+                Syscall::YIELD,
+                tcp_command(4, 0),
+                Syscall::YIELD,
+                // The connection and its port are free again.
+                tcp_command(2, 49152),
+                tcp_command(5, 0),
+                tcp_command(3, 4),
+            ],
+            Box::leak(memory.into_boxed_slice()),
+        ));
+        let apps: &'static [&'static dyn HostApp] = leak([app as &dyn HostApp]);
+        let chip = leak(HostChip::new(apps, None));
+        load_apps(kernel, chip, processes, &[("tcp", 0)], FaultResponse::Stop);
+        let platform = TestPlatform::new().driver(crate::net::tcp::DRIVER_NUM, tcp);
+        let callback_args = || -> Vec<(usize, usize, usize)> {
+            app.callbacks
+                .borrow()
+                .iter()
+                .map(|callback| match *callback {
+                    Resumption::Callback {
+                        argument0,
+                        argument1,
+                        argument2,
+                        ..
+                    } => (argument0, argument1, argument2),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        let success = usize::from(ReturnCode::SUCCESS);
+
+        // Sending needs a connection, and each process can only have one.
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            app.returns.borrow()[6..],
+            [
+                isize::from(ReturnCode::ERESERVE),
+                0,
+                isize::from(ReturnCode::EBUSY)
+            ]
+        );
+        settle();
+        assert_eq!(connection.get_state(), TcpState::Established);
+        assert_eq!(b.events.connected.get(), 1);
+
+        // Only one send can be in progress at a time.
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            app.returns.borrow()[9..],
+            [
+                isize::from(ReturnCode::ESIZE),
+                0,
+                isize::from(ReturnCode::EBUSY)
+            ]
+        );
+        settle();
+        assert_eq!(&b.events.received.borrow()[..], b"much");
+
+        // Data that does not fit in the receive buffer is dropped.
+        let data: Vec<u8> = (0..20).collect();
+        tcp_send(&b, &data);
+        settle();
+        run_until_idle(kernel, &platform, chip);
+        let received = app.memory(32, 16);
+        assert_eq!(received, &data[..16]);
+
+        settle();
+        assert!(b.events.peer_closed.get());
+        assert_eq!(b.connection.close(), ReturnCode::SUCCESS);
+        settle();
+        assert_eq!(connection.get_state(), TcpState::TimeWait);
+        assert!(a.alarm.advance_to_alarm());
+        run_until_idle(kernel, &platform, chip);
+        assert_eq!(
+            callback_args(),
+            [
+                (0, success, 0),
+                (success, 4, 0),
+                (16, 20, 0),
+                (2, success, 0)
+            ]
+        );
+        assert_eq!(
+            app.returns.borrow()[12..],
+            [0, 0, 0, isize::from(ReturnCode::ERESERVE)]
+        );
+        assert_eq!(connection.get_state(), TcpState::Closed);
+        assert!(!connection.is_bound());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod driver;
+pub mod tcp;
+pub mod tcp_connection;
+pub mod tcp_port_table;
+
+pub use self::driver::TcpDriver;
+pub use self::driver::DRIVER_NUM;
@@ -0,0 +1,1237 @@
+//! This file contains a minimal implementation of TCP (RFC 793) for kernel
+//! capsules, on top of the `IP6Sender` and `IP6RecvClient` interfaces.
+//!
+//! A [TcpConnection](struct.TcpConnection.html) is a single connection
+//! endpoint. A capsule binds it to a port from the
+//! [TcpPortManager](../tcp_port_table/struct.TcpPortManager.html), and then
+//! either listens for a peer to connect to it or connects to a peer itself.
+//! Once established, the capsule sends data from a buffer with `send()` and
+//! receives data through the [TcpClient](trait.TcpClient.html) callbacks.
+//!
+//! All connections share a [MuxTcp](struct.MuxTcp.html), which demultiplexes
+//! received segments to the connections, sends segments for the connections
+//! one at a time through its `IP6Sender`, and runs the retransmission timers
+//! of every connection with a single alarm. Segments that match no connection
+//! are answered with a reset.
+//!
+//! To keep memory use small, the implementation is deliberately simple:
+//!
+//! - No options are sent or parsed. Segments carry at most as many data bytes
+//!   as the mux's transmit buffer holds.
+//! - At most one segment is in flight per connection: the next segment is only
+//!   sent once the previous one is acknowledged. Each connection sends from a
+//!   single buffer at a time, which is returned once all of it is
+//!   acknowledged.
+//! - Received data is passed to the client as soon as it arrives in order, so
+//!   the advertised window is always `RECEIVE_WINDOW`. Out of order segments
+//!   are dropped and the expected sequence number is acknowledged again.
+//! - Segments are retransmitted after `INITIAL_RTO_MS`, doubling on every
+//!   retransmission. The connection is dropped after `MAX_RETRANSMISSIONS`.
+//! - Connections stay in TIME-WAIT for `TIME_WAIT_MS`, much less than the two
+//!   minutes the RFC recommends.
+//!
+//! Initial sequence numbers are chosen as RFC 6528 describes: the alarm's
+//! clock plus a keyed hash of the connection's addresses and ports, so that
+//! an off-path attacker cannot predict them. The key comes from the RNG the
+//! mux is given, and connections cannot be opened until it has arrived.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tcp_mux = static_init!(
+//!     MuxTcp<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     MuxTcp::new(tcp_ip_send, tcp_alarm, tcp_rng, &mut TCP_SEGMENT_BUF, net_cap, tcp_vis)
+//! );
+//! tcp_ip_send.set_client(tcp_mux);
+//! tcp_alarm.set_client(tcp_mux);
+//! tcp_rng.set_client(tcp_mux);
+//! ip_receive.add_client(tcp_mux);
+//! tcp_mux.set_interface_addresses(interface_addrs);
+//! tcp_mux.initialize();
+//!
+//! let connection = static_init!(
+//!     TcpConnection<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     TcpConnection::new(tcp_mux)
+//! );
+//! tcp_mux.add_connection(connection);
+//! let socket = tcp_port_table.create_socket().unwrap();
+//! connection.set_binding(tcp_port_table.bind(socket, 8080, net_cap).unwrap());
+//! connection.set_client(server);
+//! connection.listen(net_cap);
+//! ```
+
+use crate::net::ipv6::interface_addrs::InterfaceAddresses;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::network_capabilities::{NetworkCapability, TcpVisibilityCapability};
+use crate::net::tcp::tcp::{tcp_flags, TCPHeader};
+use crate::net::tcp::tcp_port_table::TcpPortBinding;
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// The window advertised to peers.
+pub const RECEIVE_WINDOW: u16 = 256;
+/// Retransmission timeout of a segment sent for the first time.
+pub const INITIAL_RTO_MS: u32 = 1000;
+/// Upper bound of the retransmission timeout.
+pub const MAX_RTO_MS: u32 = 16000;
+/// Number of retransmissions after which a connection is dropped.
+pub const MAX_RETRANSMISSIONS: u8 = 5;
+/// Time spent in TIME-WAIT before a connection closes.
+pub const TIME_WAIT_MS: u32 = 4000;
+
+/// The connection states of RFC 793.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Callbacks from a `TcpConnection` to the capsule using it.
+pub trait TcpClient {
+    /// The connection was established, either by `connect()` or by a peer
+    /// connecting to a listening connection.
+    fn connected(&self);
+
+    /// In-order data was received from the peer.
+    fn received(&self, data: &[u8]);
+
+    /// The buffer passed to `send()` was acknowledged by the peer (SUCCESS),
+    /// or the connection closed before it was (ECANCEL).
+    fn send_done(&self, result: ReturnCode, buf: LeasableBuffer<'static, u8>);
+
+    /// The peer closed its side of the connection. No more data will be
+    /// received, and the client should `close()` the connection once it is
+    /// done sending.
+    fn peer_closed(&self);
+
+    /// The connection closed. `result` is SUCCESS if it was closed by both
+    /// sides, ECANCEL if it was refused or reset by the peer, and ENOACK if the
+    /// peer stopped acknowledging segments.
+    fn closed(&self, result: ReturnCode);
+}
+
+/// Returns whether sequence number `a` comes before `b`.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+/// SipHash-2-4 of `data` with `key`, the pseudorandom function RFC 6528
+/// suggests for initial sequence numbers.
+fn siphash_2_4(key: [u64; 2], data: &[u8]) -> u64 {
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    let mut v = [
+        key[0] ^ 0x736f_6d65_7073_6575,
+        key[1] ^ 0x646f_7261_6e64_6f6d,
+        key[0] ^ 0x6c79_6765_6e65_7261,
+        key[1] ^ 0x7465_6462_7974_6573,
+    ];
+    let mut compress = |m: u64| {
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    };
+    let chunks = data.chunks_exact(8);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        compress(u64::from_le_bytes(word));
+    }
+    let mut last = [0; 8];
+    last[..tail.len()].copy_from_slice(tail);
+    last[7] = data.len() as u8;
+    compress(u64::from_le_bytes(last));
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// Sends segments for, and dispatches received segments to, a set of
+/// `TcpConnection`s.
+pub struct MuxTcp<'a, A: time::Alarm<'a>> {
+    connections: List<'a, TcpConnection<'a, A>>,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    rng: &'a dyn Rng<'a>,
+    /// Chooses the local address hashed into initial sequence numbers.
+    interface_addrs: OptionalCell<&'a InterfaceAddresses>,
+    /// The secret key of initial sequence numbers, valid once all of its
+    /// words arrived from the RNG.
+    isn_key: Cell<[u32; 4]>,
+    isn_key_words: Cell<usize>,
+    /// Holds the data of the segment being sent. Its length is the maximum
+    /// number of data bytes in a segment.
+    tx_buf: TakeCell<'static, [u8]>,
+    mss: usize,
+    sending: Cell<bool>,
+    /// A reset waiting to be sent, with its destination.
+    reset: OptionalCell<(IPAddr, TCPHeader)>,
+    /// Capability used to send resets in reply to unexpected segments.
+    net_cap: &'static NetworkCapability,
+    tcp_vis: &'static TcpVisibilityCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> MuxTcp<'a, A> {
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        rng: &'a dyn Rng<'a>,
+        tx_buf: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+        tcp_vis: &'static TcpVisibilityCapability,
+    ) -> MuxTcp<'a, A> {
+        MuxTcp {
+            connections: List::new(),
+            ip_sender: ip_sender,
+            alarm: alarm,
+            rng: rng,
+            interface_addrs: OptionalCell::empty(),
+            isn_key: Cell::new([0; 4]),
+            isn_key_words: Cell::new(0),
+            mss: tx_buf.len(),
+            tx_buf: TakeCell::new(tx_buf),
+            sending: Cell::new(false),
+            reset: OptionalCell::empty(),
+            net_cap: net_cap,
+            tcp_vis: tcp_vis,
+        }
+    }
+
+    pub fn add_connection(&self, connection: &'a TcpConnection<'a, A>) {
+        self.connections.push_tail(connection);
+    }
+
+    /// Asks the RNG for the key of initial sequence numbers.
+    pub fn initialize(&self) -> ReturnCode {
+        self.rng.get()
+    }
+
+    pub fn set_interface_addresses(&self, interface_addrs: &'a InterfaceAddresses) {
+        self.interface_addrs.set(interface_addrs);
+    }
+
+    fn has_isn_key(&self) -> bool {
+        self.isn_key_words.get() == self.isn_key.get().len()
+    }
+
+    /// The initial sequence number of a connection from `local_port` to
+    /// `remote_port` at `remote_addr`, following RFC 6528: the alarm's clock
+    /// plus a keyed hash of the connection's addresses and ports.
+    fn initial_sequence(&self, local_port: u16, remote_addr: IPAddr, remote_port: u16) -> u32 {
+        let local_addr = self
+            .interface_addrs
+            .and_then(|interface_addrs| interface_addrs.select_source(remote_addr))
+            .unwrap_or(IPAddr::new());
+        let mut data = [0; 36];
+        data[..16].copy_from_slice(&local_addr.0);
+        data[16..32].copy_from_slice(&remote_addr.0);
+        data[32..34].copy_from_slice(&local_port.to_be_bytes());
+        data[34..].copy_from_slice(&remote_port.to_be_bytes());
+        let key = self.isn_key.get();
+        let key = [
+            key[0] as u64 | (key[1] as u64) << 32,
+            key[2] as u64 | (key[3] as u64) << 32,
+        ];
+        self.alarm
+            .now()
+            .wrapping_add(siphash_2_4(key, &data) as u32)
+    }
+
+    fn deadline(&self, ms: u32) -> u32 {
+        let tics = (ms as u64 * <A::Frequency>::frequency() as u64 / 1000) as u32;
+        self.alarm.now().wrapping_add(tics)
+    }
+
+    /// Queues a reset for `dst`. If a reset is already waiting, this one is
+    /// dropped; the peer will retransmit and get another one.
+    fn queue_reset(&self, dst: IPAddr, src_port: u16, dst_port: u16, seq: u32, ack: Option<u32>) {
+        if self.reset.is_some() {
+            return;
+        }
+        let mut header = TCPHeader::new();
+        header.set_src_port(src_port);
+        header.set_dst_port(dst_port);
+        header.set_seq_num(seq);
+        match ack {
+            Some(ack) => {
+                header.set_ack_num(ack);
+                header.set_flags(tcp_flags::RST | tcp_flags::ACK);
+            }
+            None => header.set_flags(tcp_flags::RST),
+        }
+        self.reset.set((dst, header));
+    }
+
+    /// Queues the reset RFC 793 sends in reply to a segment that does not
+    /// belong to a connection.
+    fn reset_segment(&self, src: IPAddr, header: &TCPHeader, data_len: usize) {
+        if header.has_flags(tcp_flags::RST) {
+            return;
+        }
+        if header.has_flags(tcp_flags::ACK) {
+            self.queue_reset(
+                src,
+                header.get_dst_port(),
+                header.get_src_port(),
+                header.get_ack_num(),
+                None,
+            );
+        } else {
+            let mut seg_len = data_len as u32;
+            if header.has_flags(tcp_flags::SYN) {
+                seg_len += 1;
+            }
+            if header.has_flags(tcp_flags::FIN) {
+                seg_len += 1;
+            }
+            self.queue_reset(
+                src,
+                header.get_dst_port(),
+                header.get_src_port(),
+                0,
+                Some(header.get_seq_num().wrapping_add(seg_len)),
+            );
+        }
+    }
+
+    /// Sends segments until the `IP6Sender` is busy or no connection has
+    /// anything to send.
+    fn output(&self) {
+        while !self.sending.get() {
+            // The buffer is missing while a send is in progress further up the
+            // stack, which will continue this loop.
+            let buf = match self.tx_buf.take() {
+                Some(buf) => buf,
+                None => break,
+            };
+            let segment = match self.reset.take() {
+                Some((dst, header)) => Some((dst, header, 0, self.net_cap)),
+                None => self
+                    .connections
+                    .iter()
+                    .find_map(|connection| connection.next_segment(buf, self.mss)),
+            };
+            match segment {
+                Some((dst, header, len, net_cap)) => {
+                    let mut payload = LeasableBuffer::new(buf);
+                    payload.slice(..len);
+                    self.sending.set(true);
+                    let result = self.ip_sender.send_to(
+                        dst,
+                        TransportHeader::TCP(header),
+                        &payload,
+                        net_cap,
+                    );
+                    self.tx_buf.replace(payload.take());
+                    if result != ReturnCode::SUCCESS {
+                        // The segment is lost. Anything that needs to arrive
+                        // is retransmitted when its timer expires.
+                        self.sending.set(false);
+                    }
+                }
+                None => {
+                    self.tx_buf.replace(buf);
+                    break;
+                }
+            }
+        }
+        self.reschedule();
+    }
+
+    /// Sets the alarm for the earliest timer of any connection.
+    fn reschedule(&self) {
+        let now = self.alarm.now();
+        let remaining = self
+            .connections
+            .iter()
+            .filter_map(|connection| connection.timer.get())
+            .map(|deadline| {
+                let remaining = deadline.wrapping_sub(now);
+                if remaining as i32 > 0 {
+                    remaining
+                } else {
+                    1
+                }
+            })
+            .min();
+        match remaining {
+            Some(remaining) => self.alarm.set_alarm(now.wrapping_add(remaining)),
+            None => self.alarm.disable(),
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for MuxTcp<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::TCP {
+            return;
+        }
+        let (offset, header) = match TCPHeader::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let src = ip_header.get_src_addr();
+        let data = &payload[offset..];
+        let connection = self
+            .connections
+            .iter()
+            .find(|connection| connection.matches(src, &header))
+            .or_else(|| {
+                self.connections.iter().find(|connection| {
+                    connection.state.get() == TcpState::Listen
+                        && connection.local_port.get() == header.get_dst_port()
+                })
+            });
+        match connection {
+            Some(connection) => connection.segment_arrives(src, &header, data),
+            None => self.reset_segment(src, &header, data.len()),
+        }
+        self.output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> rng::Client for MuxTcp<'a, A> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        _error: ReturnCode,
+    ) -> rng::Continue {
+        let mut key = self.isn_key.get();
+        let mut words = self.isn_key_words.get();
+        while words < key.len() {
+            match randomness.next() {
+                Some(word) => key[words] = word,
+                None => break,
+            }
+            words += 1;
+        }
+        self.isn_key.set(key);
+        self.isn_key_words.set(words);
+        if self.has_isn_key() {
+            rng::Continue::Done
+        } else {
+            rng::Continue::More
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for MuxTcp<'a, A> {
+    fn send_done(&self, _result: ReturnCode) {
+        // Lost segments are retransmitted by the connections' timers, so the
+        // result does not matter.
+        self.sending.set(false);
+        if self.tx_buf.is_some() {
+            self.output();
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for MuxTcp<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        for connection in self.connections.iter() {
+            let expired = connection
+                .timer
+                .get()
+                .map_or(false, |deadline| now.wrapping_sub(deadline) as i32 >= 0);
+            if expired {
+                connection.timer_fired();
+            }
+        }
+        self.output();
+    }
+}
+
+/// One end of a TCP connection.
+pub struct TcpConnection<'a, A: time::Alarm<'a>> {
+    mux: &'a MuxTcp<'a, A>,
+    client: OptionalCell<&'a dyn TcpClient>,
+    next: ListLink<'a, TcpConnection<'a, A>>,
+    binding: MapCell<TcpPortBinding>,
+    local_port: Cell<u16>,
+    net_cap: OptionalCell<&'static NetworkCapability>,
+    state: Cell<TcpState>,
+    /// Whether the connection was opened with `listen()`, so it returns to
+    /// LISTEN if the handshake is reset.
+    passive: Cell<bool>,
+    remote_addr: Cell<IPAddr>,
+    remote_port: Cell<u16>,
+    iss: Cell<u32>,
+    snd_una: Cell<u32>,
+    snd_nxt: Cell<u32>,
+    snd_wnd: Cell<u16>,
+    rcv_nxt: Cell<u32>,
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    /// Number of bytes of `tx_buffer` acknowledged by the peer.
+    tx_acked: Cell<usize>,
+    fin_sent: Cell<bool>,
+    ack_pending: Cell<bool>,
+    /// Deadline of the retransmission or TIME-WAIT timer, in alarm tics.
+    timer: Cell<Option<u32>>,
+    rto_ms: Cell<u32>,
+    retransmissions: Cell<u8>,
+}
+
+impl<'a, A: time::Alarm<'a>> ListNode<'a, TcpConnection<'a, A>> for TcpConnection<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, TcpConnection<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TcpConnection<'a, A> {
+    pub fn new(mux: &'a MuxTcp<'a, A>) -> TcpConnection<'a, A> {
+        TcpConnection {
+            mux: mux,
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+            binding: MapCell::empty(),
+            local_port: Cell::new(0),
+            net_cap: OptionalCell::empty(),
+            state: Cell::new(TcpState::Closed),
+            passive: Cell::new(false),
+            remote_addr: Cell::new(IPAddr::new()),
+            remote_port: Cell::new(0),
+            iss: Cell::new(0),
+            snd_una: Cell::new(0),
+            snd_nxt: Cell::new(0),
+            snd_wnd: Cell::new(0),
+            rcv_nxt: Cell::new(0),
+            tx_buffer: MapCell::empty(),
+            tx_acked: Cell::new(0),
+            fin_sent: Cell::new(false),
+            ack_pending: Cell::new(false),
+            timer: Cell::new(None),
+            rto_ms: Cell::new(INITIAL_RTO_MS),
+            retransmissions: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn TcpClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the port binding used by the next `listen()` or `connect()`, and
+    /// returns the previous one.
+    pub fn set_binding(&self, binding: TcpPortBinding) -> Option<TcpPortBinding> {
+        self.binding.replace(binding)
+    }
+
+    /// Takes the port binding. Fails unless the connection is closed.
+    pub fn get_binding(&self) -> Option<TcpPortBinding> {
+        match self.state.get() {
+            TcpState::Closed => self.binding.take(),
+            _ => None,
+        }
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.binding.is_some()
+    }
+
+    pub fn get_state(&self) -> TcpState {
+        self.state.get()
+    }
+
+    /// Waits for a peer to connect to the bound port. Only peers `net_cap`
+    /// allows are accepted. The client's `connected` callback is called once
+    /// the handshake completes.
+    pub fn listen(&self, net_cap: &'static NetworkCapability) -> ReturnCode {
+        let result = self.open(net_cap);
+        if result == ReturnCode::SUCCESS {
+            self.passive.set(true);
+            self.state.set(TcpState::Listen);
+        }
+        result
+    }
+
+    /// Connects to port `dst_port` at `dest` from the bound port. The client's
+    /// `connected` callback is called once the handshake completes, or the
+    /// `closed` callback if it fails.
+    pub fn connect(
+        &self,
+        dest: IPAddr,
+        dst_port: u16,
+        net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        if dst_port == 0 || !net_cap.remote_tcp_port_valid(dst_port, self.mux.tcp_vis) {
+            return ReturnCode::EINVAL;
+        }
+        let result = self.open(net_cap);
+        if result == ReturnCode::SUCCESS {
+            self.passive.set(false);
+            self.remote_addr.set(dest);
+            self.remote_port.set(dst_port);
+            self.start_handshake();
+            self.state.set(TcpState::SynSent);
+            self.mux.output();
+        }
+        result
+    }
+
+    /// Sends the data in `buf`. Only one buffer can be sent at a time; it is
+    /// returned through `send_done` once the peer has acknowledged all of it.
+    /// Data can be sent as soon as the connection is opened, and until the
+    /// connection is closed.
+    pub fn send(
+        &self,
+        buf: LeasableBuffer<'static, u8>,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        match self.state.get() {
+            TcpState::SynSent
+            | TcpState::SynReceived
+            | TcpState::Established
+            | TcpState::CloseWait => {}
+            _ => return Err(buf),
+        }
+        if buf.len() == 0 || self.tx_buffer.is_some() {
+            return Err(buf);
+        }
+        self.tx_buffer.replace(buf);
+        self.tx_acked.set(0);
+        self.mux.output();
+        Ok(())
+    }
+
+    /// Closes the connection once the data being sent has been acknowledged.
+    /// The client's `closed` callback is called once both sides have closed
+    /// the connection. A connection that is listening or still connecting
+    /// closes immediately, without a callback.
+    pub fn close(&self) -> ReturnCode {
+        match self.state.get() {
+            TcpState::Listen | TcpState::SynSent => {
+                self.finish(None);
+                ReturnCode::SUCCESS
+            }
+            TcpState::SynReceived | TcpState::Established => {
+                self.state.set(TcpState::FinWait1);
+                self.mux.output();
+                ReturnCode::SUCCESS
+            }
+            TcpState::CloseWait => {
+                self.state.set(TcpState::LastAck);
+                self.mux.output();
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EALREADY,
+        }
+    }
+
+    /// Resets the connection and closes it immediately, without a `closed`
+    /// callback.
+    pub fn abort(&self) -> ReturnCode {
+        match self.state.get() {
+            TcpState::Closed => return ReturnCode::EALREADY,
+            TcpState::Listen | TcpState::SynSent => {}
+            _ => self.mux.queue_reset(
+                self.remote_addr.get(),
+                self.local_port.get(),
+                self.remote_port.get(),
+                self.snd_nxt.get(),
+                None,
+            ),
+        }
+        self.finish(None);
+        self.mux.output();
+        ReturnCode::SUCCESS
+    }
+
+    fn open(&self, net_cap: &'static NetworkCapability) -> ReturnCode {
+        if self.state.get() != TcpState::Closed {
+            return ReturnCode::EBUSY;
+        }
+        if !self.mux.has_isn_key() {
+            return ReturnCode::EOFF;
+        }
+        match self.binding.map(|binding| binding.get_port()) {
+            Some(port) => {
+                self.local_port.set(port);
+                self.net_cap.set(net_cap);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn start_handshake(&self) {
+        let iss = self.mux.initial_sequence(
+            self.local_port.get(),
+            self.remote_addr.get(),
+            self.remote_port.get(),
+        );
+        self.iss.set(iss);
+        self.snd_una.set(iss);
+        self.snd_nxt.set(iss);
+        self.snd_wnd.set(0);
+        self.tx_acked.set(0);
+        self.fin_sent.set(false);
+        self.ack_pending.set(false);
+        self.timer.set(None);
+        self.rto_ms.set(INITIAL_RTO_MS);
+        self.retransmissions.set(0);
+    }
+
+    /// Moves to CLOSED, returning the buffer being sent and calling the
+    /// `closed` callback with `result`, if any.
+    fn finish(&self, result: Option<ReturnCode>) {
+        self.state.set(TcpState::Closed);
+        self.timer.set(None);
+        self.ack_pending.set(false);
+        self.tx_buffer.take().map(|buf| {
+            self.client
+                .map(move |client| client.send_done(ReturnCode::ECANCEL, buf));
+        });
+        result.map(|result| self.client.map(|client| client.closed(result)));
+    }
+
+    fn matches(&self, src: IPAddr, header: &TCPHeader) -> bool {
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen => false,
+            _ => {
+                self.local_port.get() == header.get_dst_port()
+                    && self.remote_port.get() == header.get_src_port()
+                    && self.remote_addr.get() == src
+            }
+        }
+    }
+
+    /// Builds the next segment this connection has to send, copying its data
+    /// into `buf`. Returns the destination, header, data length and the
+    /// capability to send with.
+    fn next_segment(
+        &self,
+        buf: &mut [u8],
+        mss: usize,
+    ) -> Option<(IPAddr, TCPHeader, usize, &'static NetworkCapability)> {
+        let state = self.state.get();
+        if state == TcpState::Closed || state == TcpState::Listen {
+            return None;
+        }
+        let (iss, snd_una, snd_nxt) = (self.iss.get(), self.snd_una.get(), self.snd_nxt.get());
+        let mut flags = 0;
+        let mut len = 0;
+        if snd_nxt == iss {
+            // The SYN has not been sent, or is being retransmitted.
+            flags |= tcp_flags::SYN;
+        } else if snd_una == snd_nxt && snd_una != iss {
+            let acked = self.tx_acked.get();
+            let remaining = self.tx_buffer.map_or(0, |tx| tx.len() - acked);
+            let can_send = match state {
+                TcpState::Established
+                | TcpState::CloseWait
+                | TcpState::FinWait1
+                | TcpState::LastAck => true,
+                _ => false,
+            };
+            if remaining > 0 && can_send {
+                // A zero window is probed with a single byte.
+                let window = cmp::max(self.snd_wnd.get() as usize, 1);
+                len = cmp::min(remaining, cmp::min(mss, window));
+                self.tx_buffer
+                    .map(|tx| buf[..len].copy_from_slice(&tx[acked..acked + len]));
+                flags |= tcp_flags::PSH;
+            } else if remaining == 0 && !self.fin_sent.get() {
+                match state {
+                    TcpState::FinWait1 | TcpState::Closing | TcpState::LastAck => {
+                        flags |= tcp_flags::FIN;
+                        self.fin_sent.set(true);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if state != TcpState::SynSent {
+            flags |= tcp_flags::ACK;
+        }
+
+        let occupied = len as u32
+            + (flags & tcp_flags::SYN != 0) as u32
+            + (flags & tcp_flags::FIN != 0) as u32;
+        if occupied == 0 && !self.ack_pending.get() {
+            return None;
+        }
+        if occupied > 0 {
+            self.snd_nxt.set(snd_nxt.wrapping_add(occupied));
+            if self.timer.get().is_none() {
+                self.timer.set(Some(self.mux.deadline(self.rto_ms.get())));
+            }
+        }
+        self.ack_pending.set(false);
+
+        let mut header = TCPHeader::new();
+        header.set_src_port(self.local_port.get());
+        header.set_dst_port(self.remote_port.get());
+        header.set_seq_num(snd_nxt);
+        if flags & tcp_flags::ACK != 0 {
+            header.set_ack_num(self.rcv_nxt.get());
+        }
+        header.set_flags(flags);
+        header.set_window(RECEIVE_WINDOW);
+        self.net_cap
+            .map(|net_cap| (self.remote_addr.get(), header, len, *net_cap))
+    }
+
+    /// Handles an acknowledgment of new data. Returns whether the FIN was
+    /// acknowledged, and the send buffer if it has been acknowledged in full.
+    fn process_ack(&self, ack: u32) -> (bool, Option<LeasableBuffer<'static, u8>>) {
+        let (snd_una, snd_nxt) = (self.snd_una.get(), self.snd_nxt.get());
+        let mut acked = ack.wrapping_sub(snd_una) as usize;
+        if snd_una == self.iss.get() {
+            acked -= 1;
+        }
+        let fin_acked = self.fin_sent.get() && ack == snd_nxt;
+        if fin_acked {
+            acked -= 1;
+        }
+        self.snd_una.set(ack);
+        self.retransmissions.set(0);
+        self.rto_ms.set(INITIAL_RTO_MS);
+        self.timer.set(if ack == snd_nxt {
+            None
+        } else {
+            Some(self.mux.deadline(INITIAL_RTO_MS))
+        });
+
+        let acked = self.tx_acked.get() + acked;
+        self.tx_acked.set(acked);
+        let done = self
+            .tx_buffer
+            .map_or(false, |tx| acked > 0 && acked >= tx.len());
+        let buf = if done { self.tx_buffer.take() } else { None };
+        (fin_acked, buf)
+    }
+
+    /// Processes a segment received for this connection, following the
+    /// "SEGMENT ARRIVES" event of RFC 793.
+    fn segment_arrives(&self, src: IPAddr, header: &TCPHeader, data: &[u8]) {
+        let flags = header.get_flags();
+        let has = |flag| flags & flag != 0;
+        let seq = header.get_seq_num();
+        let ack = header.get_ack_num();
+
+        match self.state.get() {
+            TcpState::Listen => {
+                if has(tcp_flags::RST) {
+                    return;
+                }
+                if has(tcp_flags::ACK) {
+                    self.mux.reset_segment(src, header, data.len());
+                    return;
+                }
+                let allowed = self.net_cap.map_or(false, |net_cap| {
+                    net_cap.remote_tcp_port_valid(header.get_src_port(), self.mux.tcp_vis)
+                });
+                if !has(tcp_flags::SYN) || !allowed {
+                    return;
+                }
+                self.remote_addr.set(src);
+                self.remote_port.set(header.get_src_port());
+                self.rcv_nxt.set(seq.wrapping_add(1));
+                self.start_handshake();
+                self.snd_wnd.set(header.get_window());
+                self.state.set(TcpState::SynReceived);
+                return;
+            }
+            TcpState::SynSent => {
+                let ack_acceptable = seq_lt(self.iss.get(), ack) && seq_le(ack, self.snd_nxt.get());
+                if has(tcp_flags::ACK) && !ack_acceptable {
+                    self.mux.reset_segment(src, header, data.len());
+                    return;
+                }
+                if has(tcp_flags::RST) {
+                    if has(tcp_flags::ACK) {
+                        // Connection refused.
+                        self.finish(Some(ReturnCode::ECANCEL));
+                    }
+                    return;
+                }
+                if !has(tcp_flags::SYN) {
+                    return;
+                }
+                self.rcv_nxt.set(seq.wrapping_add(1));
+                self.snd_wnd.set(header.get_window());
+                self.ack_pending.set(true);
+                if has(tcp_flags::ACK) {
+                    let (_, buf) = self.process_ack(ack);
+                    self.state.set(TcpState::Established);
+                    self.client.map(|client| client.connected());
+                    buf.map(|buf| {
+                        self.client
+                            .map(move |client| client.send_done(ReturnCode::SUCCESS, buf))
+                    });
+                } else {
+                    // Simultaneous open: send the SYN again, with an ACK.
+                    self.state.set(TcpState::SynReceived);
+                    self.snd_nxt.set(self.iss.get());
+                    self.timer.set(None);
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        // Only accept segments that start at the next expected sequence number,
+        // trimming what has already been received.
+        let rcv_nxt = self.rcv_nxt.get();
+        let mut syn = has(tcp_flags::SYN);
+        let fin = has(tcp_flags::FIN);
+        let seg_len = data.len() + syn as usize + fin as usize;
+        let old = rcv_nxt.wrapping_sub(seq) as i32;
+        let mut data = data;
+        if old < 0 || (old > 0 && old as usize >= seg_len) {
+            // Out of order or a duplicate: acknowledge what we expect.
+            if !has(tcp_flags::RST) {
+                self.ack_pending.set(true);
+                if self.state.get() == TcpState::TimeWait && fin {
+                    self.timer.set(Some(self.mux.deadline(TIME_WAIT_MS)));
+                }
+            }
+            return;
+        }
+        if old > 0 {
+            let mut old = old as usize;
+            if syn {
+                syn = false;
+                old -= 1;
+            }
+            data = &data[old..];
+        }
+
+        if has(tcp_flags::RST) {
+            if self.state.get() == TcpState::SynReceived && self.passive.get() {
+                self.state.set(TcpState::Listen);
+                self.timer.set(None);
+            } else {
+                self.finish(Some(ReturnCode::ECANCEL));
+            }
+            return;
+        }
+        if syn {
+            self.mux.reset_segment(src, header, data.len());
+            self.finish(Some(ReturnCode::ECANCEL));
+            return;
+        }
+        if !has(tcp_flags::ACK) {
+            return;
+        }
+
+        let mut connected = false;
+        let mut sent = None;
+        let (snd_una, snd_nxt) = (self.snd_una.get(), self.snd_nxt.get());
+        if seq_lt(snd_una, ack) && seq_le(ack, snd_nxt) {
+            let (fin_acked, buf) = self.process_ack(ack);
+            sent = buf;
+            match self.state.get() {
+                TcpState::SynReceived => {
+                    self.state.set(TcpState::Established);
+                    connected = true;
+                }
+                TcpState::FinWait1 if fin_acked => self.state.set(TcpState::FinWait2),
+                TcpState::Closing if fin_acked => {
+                    self.state.set(TcpState::TimeWait);
+                    self.timer.set(Some(self.mux.deadline(TIME_WAIT_MS)));
+                }
+                TcpState::LastAck if fin_acked => {
+                    sent.map(|buf| {
+                        self.client
+                            .map(move |client| client.send_done(ReturnCode::SUCCESS, buf))
+                    });
+                    self.finish(Some(ReturnCode::SUCCESS));
+                    return;
+                }
+                _ => {}
+            }
+        } else if self.state.get() == TcpState::SynReceived {
+            self.mux.reset_segment(src, header, data.len());
+            return;
+        } else if seq_lt(snd_nxt, ack) {
+            // Acknowledges something not yet sent.
+            self.ack_pending.set(true);
+            return;
+        }
+        self.snd_wnd.set(header.get_window());
+
+        let receiving = match self.state.get() {
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => true,
+            _ => false,
+        };
+        let data = if receiving { data } else { &[] };
+        if !data.is_empty() {
+            self.rcv_nxt
+                .set(self.rcv_nxt.get().wrapping_add(data.len() as u32));
+            self.ack_pending.set(true);
+        }
+
+        let mut peer_closed = false;
+        if fin {
+            self.rcv_nxt.set(self.rcv_nxt.get().wrapping_add(1));
+            self.ack_pending.set(true);
+            match self.state.get() {
+                TcpState::Established => {
+                    self.state.set(TcpState::CloseWait);
+                    peer_closed = true;
+                }
+                TcpState::FinWait1 => self.state.set(TcpState::Closing),
+                TcpState::FinWait2 | TcpState::TimeWait => {
+                    self.state.set(TcpState::TimeWait);
+                    self.timer.set(Some(self.mux.deadline(TIME_WAIT_MS)));
+                }
+                _ => {}
+            }
+        }
+
+        self.client.map(|client| {
+            if connected {
+                client.connected();
+            }
+            if !data.is_empty() {
+                client.received(data);
+            }
+            if peer_closed {
+                client.peer_closed();
+            }
+        });
+        sent.map(|buf| {
+            self.client
+                .map(move |client| client.send_done(ReturnCode::SUCCESS, buf))
+        });
+    }
+
+    /// Handles the expiry of the retransmission or TIME-WAIT timer.
+    fn timer_fired(&self) {
+        self.timer.set(None);
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen => {}
+            TcpState::TimeWait => self.finish(Some(ReturnCode::SUCCESS)),
+            _ => {
+                let retransmissions = self.retransmissions.get() + 1;
+                if retransmissions > MAX_RETRANSMISSIONS {
+                    self.finish(Some(ReturnCode::ENOACK));
+                    return;
+                }
+                self.retransmissions.set(retransmissions);
+                self.rto_ms.set(cmp::min(self.rto_ms.get() * 2, MAX_RTO_MS));
+                // Go back to the first unacknowledged byte; `next_segment`
+                // sends it again.
+                self.snd_nxt.set(self.snd_una.get());
+                self.fin_sent.set(false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{siphash_2_4, TcpConnection, TcpState, MAX_RETRANSMISSIONS};
+    use crate::net::loopback::{
+        any_network_capability, link_local_addr, tcp_send, tcp_settle, TcpHost, TcpStack,
+    };
+    use host::harness::leak;
+    use kernel::common::leasable_buffer::LeasableBuffer;
+    use kernel::ReturnCode;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[test]
+    pub fn siphash_matches_reference() {
+        // The test vector from the SipHash paper.
+        let key = [0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908];
+        let data: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash_2_4(key, &data), 0xa129_ca61_49be_45e5);
+    }
+
+    #[test]
+    pub fn initial_sequence_is_keyed_per_connection() {
+        let net_cap = any_network_capability();
+        let a = TcpStack::new_without_key(link_local_addr(1), net_cap);
+        let connection = leak(TcpConnection::new(a.mux));
+        a.mux.add_connection(connection);
+        let socket = a.port_table.create_socket().unwrap();
+        connection.set_binding(a.port_table.bind(socket, 49152, net_cap).unwrap());
+        let b = link_local_addr(2);
+
+        // No connection is opened before the key arrives.
+        assert_eq!(a.mux.initialize(), ReturnCode::SUCCESS);
+        assert_eq!(connection.connect(b, 80, net_cap), ReturnCode::EOFF);
+        assert_eq!(connection.listen(net_cap), ReturnCode::EOFF);
+        assert!(a.rng.complete(&[0x1234_5678, 0x9abc_def0]));
+        assert!(a.rng.is_requested());
+        assert_eq!(connection.connect(b, 80, net_cap), ReturnCode::EOFF);
+        assert!(a.rng.complete(&[0x0fed_cba9, 0x8765_4321, 0xffff_ffff]));
+        assert!(!a.rng.is_requested());
+
+        a.alarm.set_now(1000);
+        let iss = a.mux.initial_sequence(49152, b, 80);
+        assert_eq!(connection.connect(b, 80, net_cap), ReturnCode::SUCCESS);
+        assert_eq!(connection.iss.get(), iss);
+
+        // The hash is not the clock, and differs between connections.
+        assert_ne!(iss, 1000);
+        assert_ne!(a.mux.initial_sequence(49152, b, 81), iss);
+        assert_ne!(a.mux.initial_sequence(49153, b, 80), iss);
+        assert_ne!(a.mux.initial_sequence(49152, link_local_addr(3), 80), iss);
+        // For one connection, the clock keeps later numbers ahead.
+        a.alarm.set_now(1500);
+        assert_eq!(a.mux.initial_sequence(49152, b, 80), iss.wrapping_add(500));
+    }
+
+    #[test]
+    pub fn tcp_connects_transfers_and_closes() {
+        let a = TcpHost::new(1, 49152);
+        let b = TcpHost::new(2, 80);
+
+        assert_eq!(b.connection.listen(b.net_cap), ReturnCode::SUCCESS);
+        assert_eq!(
+            a.connection.connect(b.addr, 80, a.net_cap),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(a.connection.get_state(), TcpState::SynSent);
+        tcp_settle(&a, &b);
+        assert_eq!(a.connection.get_state(), TcpState::Established);
+        assert_eq!(b.connection.get_state(), TcpState::Established);
+        assert_eq!(a.events.connected.get(), 1);
+        assert_eq!(b.events.connected.get(), 1);
+
+        // Only one buffer can be sent at a time.
+        tcp_send(&a, b"hello");
+        assert!(a
+            .connection
+            .send(LeasableBuffer::new(Box::leak(Box::new([0; 4]))))
+            .is_err());
+        tcp_settle(&a, &b);
+        assert_eq!(&b.events.received.borrow()[..], b"hello");
+        assert_eq!(&a.events.sent.borrow()[..], &[ReturnCode::SUCCESS]);
+
+        // Longer than the transmit buffer, so sent in several segments.
+        let data: Vec<u8> = (0..100).collect();
+        tcp_send(&b, &data);
+        tcp_settle(&a, &b);
+        assert_eq!(&a.events.received.borrow()[..], &data[..]);
+        assert_eq!(&b.events.sent.borrow()[..], &[ReturnCode::SUCCESS]);
+
+        assert_eq!(a.connection.close(), ReturnCode::SUCCESS);
+        tcp_settle(&a, &b);
+        assert_eq!(a.connection.get_state(), TcpState::FinWait2);
+        assert_eq!(b.connection.get_state(), TcpState::CloseWait);
+        assert!(b.events.peer_closed.get());
+
+        // The side that has not closed yet can still send.
+        tcp_send(&b, b"bye");
+        tcp_settle(&a, &b);
+        assert_eq!(&a.events.received.borrow()[100..], b"bye");
+
+        assert_eq!(b.connection.close(), ReturnCode::SUCCESS);
+        tcp_settle(&a, &b);
+        assert_eq!(b.connection.get_state(), TcpState::Closed);
+        assert_eq!(&b.events.closed.borrow()[..], &[ReturnCode::SUCCESS]);
+        assert_eq!(a.connection.get_state(), TcpState::TimeWait);
+        assert!(a.events.closed.borrow().is_empty());
+
+        // The side that closed first waits before the connection is closed.
+        assert!(a.alarm.advance_to_alarm());
+        assert_eq!(a.connection.get_state(), TcpState::Closed);
+        assert_eq!(&a.events.closed.borrow()[..], &[ReturnCode::SUCCESS]);
+        assert!(!a.events.peer_closed.get());
+    }
+
+    #[test]
+    pub fn tcp_retransmits_and_resets() {
+        let a = TcpHost::new(1, 49152);
+        let b = TcpHost::new(2, 80);
+        assert_eq!(b.connection.listen(b.net_cap), ReturnCode::SUCCESS);
+
+        // Nothing listens on port 81, so the peer refuses the connection.
+        assert_eq!(
+            a.connection.connect(b.addr, 81, a.net_cap),
+            ReturnCode::SUCCESS
+        );
+        tcp_settle(&a, &b);
+        assert_eq!(a.connection.get_state(), TcpState::Closed);
+        assert_eq!(&a.events.closed.borrow()[..], &[ReturnCode::ECANCEL]);
+        assert_eq!(b.connection.get_state(), TcpState::Listen);
+
+        assert_eq!(
+            a.connection.connect(b.addr, 80, a.net_cap),
+            ReturnCode::SUCCESS
+        );
+        tcp_settle(&a, &b);
+        assert_eq!(a.connection.get_state(), TcpState::Established);
+
+        // A lost segment is sent again once the retransmission timer fires.
+        tcp_send(&a, b"data");
+        assert!(a.sender.transfer(b.receiver, true));
+        tcp_settle(&a, &b);
+        assert!(b.events.received.borrow().is_empty());
+        assert!(a.alarm.advance_to_alarm());
+        tcp_settle(&a, &b);
+        assert_eq!(&b.events.received.borrow()[..], b"data");
+        assert_eq!(&a.events.sent.borrow()[..], &[ReturnCode::SUCCESS]);
+
+        // When the acknowledgement is lost instead, the retransmitted data is
+        // only delivered once.
+        tcp_send(&a, b"more");
+        assert!(a.sender.transfer(b.receiver, false));
+        assert!(b.sender.transfer(a.receiver, true));
+        assert!(a.alarm.advance_to_alarm());
+        tcp_settle(&a, &b);
+        assert_eq!(&b.events.received.borrow()[..], b"datamore");
+        assert_eq!(
+            &a.events.sent.borrow()[..],
+            &[ReturnCode::SUCCESS, ReturnCode::SUCCESS]
+        );
+
+        // The connection is dropped when the peer stops answering.
+        tcp_send(&a, b"lost");
+        for _ in 0..=MAX_RETRANSMISSIONS {
+            assert!(a.sender.transfer(b.receiver, true));
+            assert!(a.alarm.advance_to_alarm());
+        }
+        while a.sender.transfer(b.receiver, true) {}
+        assert_eq!(a.connection.get_state(), TcpState::Closed);
+        assert_eq!(
+            &a.events.closed.borrow()[..],
+            &[ReturnCode::ECANCEL, ReturnCode::ENOACK]
+        );
+        assert_eq!(&a.events.sent.borrow()[2..], &[ReturnCode::ECANCEL]);
+    }
+}
@@ -0,0 +1,173 @@
+//! In-kernel structure for tracking TCP ports bound by capsules.
+//!
+//! This is the TCP counterpart of `UdpPortManager` (see
+//! `capsules/src/net/udp/udp_port_table.rs`) and works the same way: a
+//! capsule first reserves a slot in a fixed size table by creating a socket,
+//! then consumes the socket to bind to a port. Binding enforces that only one
+//! capsule is bound to a given port at any time, and returns a
+//! `TcpPortBinding` that acts as proof that the holder is bound to that port.
+//! Bindings can only be created within this file, and unbinding consumes the
+//! binding and returns the socket.
+//!
+//! A `TcpConnection` needs a binding to listen for or open connections. Since
+//! a connection both sends and receives on its port, a single binding is used
+//! for both directions, unlike UDP.
+
+use crate::net::network_capabilities::{NetworkCapability, TcpVisibilityCapability};
+use core::fmt;
+use kernel::capabilities::CreatePortTableCapability;
+use kernel::common::cells::TakeCell;
+use kernel::ReturnCode;
+
+/// Sets the maximum number of TCP ports that can be bound by capsules.
+pub const MAX_NUM_BOUND_PORTS: usize = 8;
+
+/// The SocketBindingEntry struct is stored in the port table and conveys what
+/// port is bound at the given index if one is bound. If no port is bound, the
+/// value stored at that location in the table is Unbound.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SocketBindingEntry {
+    Port(u16),
+    Unbound,
+}
+
+/// A TcpSocket provides a handle into the bound port table. When binding to
+/// a port, the socket is consumed and a `TcpPortBinding` is returned. When
+/// unbinding, the socket is returned and can be used to bind to other ports.
+#[derive(Debug)]
+pub struct TcpSocket {
+    idx: usize,
+    port_table: &'static TcpPortManager,
+}
+
+/// The TcpPortManager maintains a reference to the port_array, which manages
+/// what ports are bound at any given moment.
+pub struct TcpPortManager {
+    port_array: TakeCell<'static, [Option<SocketBindingEntry>]>,
+    tcp_vis: &'static TcpVisibilityCapability,
+}
+
+impl fmt::Debug for TcpPortManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[TCP Port Table]")
+    }
+}
+
+impl TcpSocket {
+    // important that this function is not public. If it were, capsules could
+    // obtain access to ports bound by other capsules
+    fn new(idx: usize, pt: &'static TcpPortManager) -> TcpSocket {
+        TcpSocket {
+            idx: idx,
+            port_table: pt,
+        }
+    }
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        self.port_table.destroy_socket(self);
+    }
+}
+
+/// An opaque descriptor that allows the holder to listen for and open TCP
+/// connections on a port.
+#[derive(Debug)]
+pub struct TcpPortBinding {
+    idx: usize,
+    port: u16,
+}
+
+impl TcpPortBinding {
+    fn new(idx: usize, port: u16) -> TcpPortBinding {
+        TcpPortBinding {
+            idx: idx,
+            port: port,
+        }
+    }
+
+    pub fn get_port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl TcpPortManager {
+    // Require capability so that the port table is only created by kernel
+    pub fn new(
+        _cap: &dyn CreatePortTableCapability,
+        used_kernel_ports: &'static mut [Option<SocketBindingEntry>],
+        tcp_vis: &'static TcpVisibilityCapability,
+    ) -> TcpPortManager {
+        TcpPortManager {
+            port_array: TakeCell::new(used_kernel_ports),
+            tcp_vis: tcp_vis,
+        }
+    }
+
+    /// Called by capsules that would like to eventually be able to bind to a
+    /// TCP port. This call will succeed unless every slot in the table has
+    /// already been given out.
+    pub fn create_socket(&'static self) -> Result<TcpSocket, ReturnCode> {
+        self.port_array
+            .map_or(Err(ReturnCode::ENOSUPPORT), |table| {
+                match table.iter().position(|entry| entry.is_none()) {
+                    Some(i) => {
+                        table[i] = Some(SocketBindingEntry::Unbound);
+                        Ok(TcpSocket::new(i, &self))
+                    }
+                    None => Err(ReturnCode::ENOMEM),
+                }
+            })
+    }
+
+    /// Called when sockets are dropped to free their slots in the table.
+    /// The slot in the table is only freed if the socket that is dropped is
+    /// unbound. If the slot is bound, the socket is being dropped after a call
+    /// to bind(), and the slot in the table should remain reserved.
+    fn destroy_socket(&self, socket: &mut TcpSocket) {
+        self.port_array.map(|table| {
+            if table[socket.idx] == Some(SocketBindingEntry::Unbound) {
+                table[socket.idx] = None;
+            }
+        });
+    }
+
+    /// Check if a given port is already bound by a capsule.
+    pub fn is_bound(&self, port: u16) -> bool {
+        self.port_array.map_or(false, |table| {
+            table
+                .iter()
+                .any(|entry| *entry == Some(SocketBindingEntry::Port(port)))
+        })
+    }
+
+    /// Called by capsules that have already reserved a socket to attempt to
+    /// bind to a TCP port. The socket is passed by value. On success, the
+    /// binding is returned. On failure, the same TcpSocket is returned.
+    pub fn bind(
+        &self,
+        socket: TcpSocket,
+        port: u16,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<TcpPortBinding, TcpSocket> {
+        if port == 0 || !net_cap.local_tcp_port_valid(port, self.tcp_vis) || self.is_bound(port) {
+            return Err(socket);
+        }
+        self.port_array
+            .map(|table| {
+                table[socket.idx] = Some(SocketBindingEntry::Port(port));
+                TcpPortBinding::new(socket.idx, port)
+            })
+            .ok_or(socket)
+    }
+
+    /// Disassociate the port from the given binding and return the socket
+    /// associated with it.
+    pub fn unbind(&'static self, binding: TcpPortBinding) -> TcpSocket {
+        let idx = binding.idx;
+        self.port_array.map(|table| {
+            table[idx] = Some(SocketBindingEntry::Unbound);
+        });
+        TcpSocket::new(idx, &self)
+    }
+}
@@ -3,9 +3,10 @@
 //! Implements a userspace interface for sending and receiving UDP messages.
 //! Processes use this driver to send UDP packets from a common interface
 //! and bind to UDP ports for receiving packets.
-//! Also exposes a list of interface addresses to the application (currently
-//! hard-coded).
+//! Also exposes the addresses of the network interface to the application,
+//! including addresses configured automatically.
 
+use crate::net::ipv6::interface_addrs::InterfaceAddresses;
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::network_capabilities::NetworkCapability;
 use crate::net::stream::encode_u16;
@@ -16,7 +17,7 @@ use crate::net::udp::udp_recv::UDPRecvClient;
 use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
 use crate::net::util::host_slice_to_u16;
 use core::cell::Cell;
-use core::{cmp, mem};
+use core::mem;
 use kernel::capabilities::UdpDriverCapability;
 use kernel::common::cells::MapCell;
 use kernel::common::leasable_buffer::LeasableBuffer;
@@ -82,8 +83,8 @@ pub struct UDPDriver<'a> {
     /// ID of app whose transmission request is being processed.
     current_app: Cell<Option<AppId>>,
 
-    /// List of IP Addresses of the interfaces on the device
-    interface_list: &'static [IPAddr],
+    /// IP Addresses of the interface of the device
+    interface_addrs: &'a InterfaceAddresses,
 
     /// Maximum length payload that an app can transmit via this driver
     max_tx_pyld_len: usize,
@@ -102,7 +103,7 @@ impl<'a> UDPDriver<'a> {
     pub fn new(
         sender: &'a dyn UDPSender<'a>,
         grant: Grant<App>,
-        interface_list: &'static [IPAddr],
+        interface_addrs: &'a InterfaceAddresses,
         max_tx_pyld_len: usize,
         port_table: &'static UdpPortManager,
         kernel_buffer: LeasableBuffer<'static, u8>,
@@ -113,7 +114,7 @@ impl<'a> UDPDriver<'a> {
             sender: sender,
             apps: grant,
             current_app: Cell::new(None),
-            interface_list: interface_list,
+            interface_addrs: interface_addrs,
             max_tx_pyld_len: max_tx_pyld_len,
             port_table: port_table,
             kernel_buffer: MapCell::new(kernel_buffer),
@@ -484,15 +485,13 @@ impl<'a> Driver for UDPDriver<'a> {
             //  Writes the requested number of network interface addresses
             // `arg1`: number of interfaces requested that will fit into the buffer
             1 => self.do_with_cfg_mut(appid, arg1 * mem::size_of::<IPAddr>(), |cfg| {
-                let n_ifaces_to_copy = cmp::min(arg1, self.interface_list.len());
                 let iface_size = mem::size_of::<IPAddr>();
-                for i in 0..n_ifaces_to_copy {
-                    cfg[i * iface_size..(i + 1) * iface_size]
-                        .copy_from_slice(&self.interface_list[i].0);
+                for (i, iface) in self.interface_addrs.iter().take(arg1).enumerate() {
+                    cfg[i * iface_size..(i + 1) * iface_size].copy_from_slice(&iface.0);
                 }
                 // Returns total number of interfaces
                 ReturnCode::SuccessWithValue {
-                    value: self.interface_list.len(),
+                    value: self.interface_addrs.iter().count(),
                 }
             }),
 
@@ -535,7 +534,7 @@ impl<'a> Driver for UDPDriver<'a> {
             3 => {
                 self.do_with_app(appid, |app| {
                     // Move UDPEndpoint into udp.rs?
-                    let mut requested_addr_opt = app.app_rx_cfg.as_ref().and_then(|cfg| {
+                    let requested_addr_opt = app.app_rx_cfg.as_ref().and_then(|cfg| {
                         if cfg.len() != 2 * mem::size_of::<UDPEndpoint>() {
                             None
                         } else if let Some(local_iface) =
@@ -546,11 +545,7 @@ impl<'a> Driver for UDPDriver<'a> {
                             None
                         }
                     });
-                    if requested_addr_opt.is_none() {
-                        return ReturnCode::EINVAL;
-                    }
-                    if requested_addr_opt.is_some() {
-                        let requested_addr = requested_addr_opt.expect("missing address.");
+                    if let Some(requested_addr) = requested_addr_opt {
                         // If zero address, close any already bound socket
                         if requested_addr.is_zero() {
                             app.rx_callback = None;
@@ -558,13 +553,7 @@ impl<'a> Driver for UDPDriver<'a> {
                             return ReturnCode::SUCCESS;
                         }
                         // Check that requested addr is a local interface
-                        let mut requested_is_local = false;
-                        for i in 0..self.interface_list.len() {
-                            if requested_addr.addr == self.interface_list[i] {
-                                requested_is_local = true;
-                            }
-                        }
-                        if !requested_is_local {
+                        if !self.interface_addrs.contains(requested_addr.addr) {
                             return ReturnCode::EINVAL;
                         }
                         let mut addr_already_bound = false;
@@ -573,10 +562,7 @@ impl<'a> Driver for UDPDriver<'a> {
                         // table when checking the userspace apps.
                         for app in self.apps.iter() {
                             app.enter(|other_app, _| {
-                                if other_app.bound_port.is_some() {
-                                    let other_addr_opt = other_app.bound_port.clone();
-                                    let other_addr =
-                                        other_addr_opt.expect("Missing other address.");
+                                if let Some(other_addr) = other_app.bound_port.clone() {
                                     if other_addr.port == requested_addr.port {
                                         if other_addr.addr == requested_addr.addr {
                                             addr_already_bound = true;
@@ -598,9 +584,8 @@ impl<'a> Driver for UDPDriver<'a> {
                         if addr_already_bound {
                             ReturnCode::EBUSY
                         } else {
-                            requested_addr_opt = Some(requested_addr);
                             // If this point is reached, the requested addr is free and valid
-                            app.bound_port = requested_addr_opt;
+                            app.bound_port = Some(requested_addr);
                             ReturnCode::SUCCESS
                         }
                     } else {
@@ -688,9 +673,7 @@ impl<'a> PortQuery for UDPDriver<'a> {
         let mut port_bound = false;
         for app in self.apps.iter() {
             app.enter(|other_app, _| {
-                if other_app.bound_port.is_some() {
-                    let other_addr_opt = other_app.bound_port.clone();
-                    let other_addr = other_addr_opt.expect("Missing other_addr");
+                if let Some(other_addr) = other_app.bound_port.clone() {
                     if other_addr.port == port {
                         port_bound = true;
                     }
@@ -234,7 +234,7 @@ impl UdpPortManager {
                 }
                 port_exists
             })
-            .unwrap();
+            .ok_or(())?;
         Ok(ret)
     }
 
@@ -264,7 +264,7 @@ impl UdpPortManager {
                                 // Add socket to the linked list.
                                 Ok(binding_pair)
                             })
-                            .unwrap()
+                            .unwrap_or(Err(socket))
                     }
                 }
                 Err(_) => Err(socket),
@@ -5,7 +5,7 @@
 //! by the UDP userspace driver, which must correctly check bindings of kernel apps to ensure
 //! correctness when dispatching received packets to the appropriate client.
 
-use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use crate::net::ipv6::ipv6::IP6Header;
 use crate::net::ipv6::ipv6_recv::IP6RecvClient;
 use crate::net::udp::driver::UDPDriver;
@@ -13,7 +13,6 @@ use crate::net::udp::udp::UDPHeader;
 use crate::net::udp::udp_port_table::{PortQuery, UdpPortBindingRx};
 use kernel::common::cells::{MapCell, OptionalCell};
 use kernel::common::{List, ListLink, ListNode};
-use kernel::debug;
 
 pub struct MuxUdpReceiver<'a> {
     rcvr_list: List<'a, UDPReceiver<'a>>,
@@ -39,12 +38,15 @@ impl<'a> MuxUdpReceiver<'a> {
 
 impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
     fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::UDP {
+            return;
+        }
         match UDPHeader::decode(payload).done() {
             Some((offset, udp_header)) => {
                 let len = udp_header.get_len() as usize;
                 let dst_port = udp_header.get_dst_port();
-                if len > payload.len() {
-                    debug!("[UDP_RECV] Error: Received UDP length too long");
+                if len < offset || len > payload.len() {
+                    // The length field does not match the datagram.
                     return;
                 }
                 for rcvr in self.rcvr_list.iter() {
@@ -57,7 +59,7 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                                         ip_header.get_dst_addr(),
                                         udp_header.get_src_port(),
                                         udp_header.get_dst_port(),
-                                        &payload[offset..],
+                                        &payload[offset..len],
                                     );
                                 });
                                 rcvr.binding.replace(binding);
@@ -74,7 +76,7 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                                         ip_header.get_dst_addr(),
                                         udp_header.get_src_port(),
                                         udp_header.get_dst_port(),
-                                        &payload[offset..],
+                                        &payload[offset..len],
                                     );
                                     self.driver.replace(driver);
                                     break;
@@ -324,7 +324,8 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
             .send_to(dest, transport_header, &self, net_cap)
         {
             ReturnCode::SUCCESS => Ok(()),
-            _ => Err(self.tx_buffer.take().unwrap()),
+            // The buffer is missing only if `send_done` already returned it.
+            _ => self.tx_buffer.take().map_or(Ok(()), Err),
         }
     }
 
@@ -296,3 +296,111 @@ impl<F: hil::flash::Flash> hil::flash::Client<F> for NonvolatileToPages<'_, F> {
 
     fn erase_complete(&self, _error: hil::flash::Error) {}
 }
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use host::harness::leak;
+    use host::mock::flash::{MockFlash, MockPage, Operation, PAGE_SIZE};
+    use kernel::hil::flash::HasClient;
+    use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Keeps the buffer and length of the last completed read or write.
+    struct Completions {
+        read: OptionalCell<usize>,
+        written: OptionalCell<usize>,
+        buffer: TakeCell<'static, [u8]>,
+    }
+
+    impl Completions {
+        fn new() -> Completions {
+            Completions {
+                read: OptionalCell::empty(),
+                written: OptionalCell::empty(),
+                buffer: TakeCell::empty(),
+            }
+        }
+    }
+
+    impl NonvolatileStorageClient<'static> for Completions {
+        fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+            self.read.set(length);
+            self.buffer.replace(buffer);
+        }
+
+        fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+            self.written.set(length);
+            self.buffer.replace(buffer);
+        }
+    }
+
+    /// Complete flash operations until there are none left, and return them.
+    fn complete_all(flash: &MockFlash) -> Vec<Operation> {
+        let mut operations = Vec::new();
+        while let Some(operation) = flash.operation() {
+            operations.push(operation);
+            assert!(flash.complete());
+        }
+        operations
+    }
+
+    #[test]
+    pub fn unaligned_accesses_span_pages() {
+        let flash: &MockFlash = leak(MockFlash::new(Box::leak(
+            vec![0xff; 4 * PAGE_SIZE].into_boxed_slice(),
+        )));
+        let storage = leak(NonvolatileToPages::new(
+            flash,
+            Box::leak(Box::new(MockPage::default())),
+        ));
+        flash.set_client(storage);
+        let client = leak(Completions::new());
+        storage.set_client(client);
+
+        // Write the end of page 0, all of page 1 and the start of page 2. The
+        // partial pages are read first so their other bytes are kept.
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let buffer = Box::leak(data.clone().into_boxed_slice());
+        assert_eq!(
+            storage.write(buffer, PAGE_SIZE - 12, 600),
+            ReturnCode::SUCCESS
+        );
+        let buffer = Box::leak(vec![0; 600].into_boxed_slice());
+        assert_eq!(storage.read(buffer, 0, 600), ReturnCode::EBUSY);
+        assert_eq!(
+            complete_all(flash),
+            [
+                Operation::Read(0),
+                Operation::Write(0),
+                Operation::Write(1),
+                Operation::Read(2),
+                Operation::Write(2),
+            ]
+        );
+        assert_eq!(client.written.take(), Some(600));
+        flash.with_storage(|storage| {
+            assert!(storage[..PAGE_SIZE - 12].iter().all(|&byte| byte == 0xff));
+            assert_eq!(&storage[PAGE_SIZE - 12..PAGE_SIZE + 588], &data[..]);
+            assert!(storage[PAGE_SIZE + 588..].iter().all(|&byte| byte == 0xff));
+        });
+
+        // Reading it back goes through the same pages.
+        let buffer = client.buffer.take().unwrap();
+        buffer.iter_mut().for_each(|byte| *byte = 0);
+        assert_eq!(
+            storage.read(buffer, PAGE_SIZE - 12, 600),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(
+            complete_all(flash),
+            [Operation::Read(0), Operation::Read(1), Operation::Read(2)]
+        );
+        assert_eq!(client.read.take(), Some(600));
+        assert_eq!(client.buffer.take().as_deref(), Some(&data[..]));
+    }
+}
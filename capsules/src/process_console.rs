@@ -5,13 +5,21 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has eleven commands:
 //!  - 'help' prints the available commands and arguments
 //!  - 'status' prints the current system status
 //!  - 'list' lists the current processes with their IDs and running state
 //!  - 'stop n' stops the process with name n
 //!  - 'start n' starts the stopped process with name n
 //!  - 'fault n' forces the process with name n into a fault state
+//!  - 'fg n' sends all further input to the process with name n, until
+//!    Ctrl-G is pressed
+//!  - 'process n' prints the memory layout and statistics of the process with
+//!    name n
+//!  - 'grants' lists how many grants each process uses and how much memory
+//!    they take
+//!  - 'kernel' prints kernel statistics
+//!  - 'reboot' resets the board, if the board registered a reset function
 //!
 //! ### `list` Command Fields:
 //!
@@ -29,6 +37,46 @@
 //! - `Grants`: The number of grants that have been initialized for the process
 //!   out of the total number of grants defined by the kernel.
 //!
+//! Board commands
+//! --------------
+//!
+//! Boards and capsules can add their own commands, for example to read a
+//! sensor or print radio statistics, by implementing `ConsoleCommand` and
+//! registering it. Registered commands are listed by `help` and can be tab
+//! completed:
+//!
+//! ```rust
+//! struct RadioStats;
+//!
+//! impl ConsoleCommand for RadioStats {
+//!     fn name(&self) -> &'static str {
+//!         "radio"
+//!     }
+//!
+//!     fn help(&self) -> &'static str {
+//!         "radio [reset]: print or reset the radio statistics"
+//!     }
+//!
+//!     fn execute(&self, arguments: &[&str], writer: &mut dyn fmt::Write) -> ReturnCode {
+//!         let _ = writeln!(writer, "Frames sent: {}", ...);
+//!         ReturnCode::SUCCESS
+//!     }
+//! }
+//!
+//! pconsole.register_command(static_init!(RadioStats, RadioStats));
+//! ```
+//!
+//! Line editing
+//! ------------
+//!
+//! The console understands the usual terminal editing keys: the left and
+//! right arrows, Home and End (or Ctrl-A and Ctrl-E) move the cursor,
+//! Backspace and Delete remove characters, and typed characters are inserted
+//! at the cursor. The up and down arrows walk through previously executed
+//! commands. Tab completes command names and, after `start`, `stop`, `fault`
+//! and `fg`, process names; if several completions are possible, it completes
+//! their common prefix, and pressing it again lists them.
+//!
 //! Setup
 //! -----
 //!
@@ -46,6 +94,7 @@
 //!                  &mut console::WRITE_BUF,
 //!                  &mut console::READ_BUF,
 //!                  &mut console::COMMAND_BUF,
+//!                  &mut console::HISTORY_BUF,
 //!                  kernel,
 //!                  Capability);
 //! hil::uart::UART::set_client(&usart::USART0, pconsole);
@@ -54,6 +103,14 @@
 //! pconsole.start();
 //! ```
 //!
+//! To let the `fg` command route input to processes, give the process console
+//! the process-facing console. This enables input routing on that console, so
+//! processes only receive input while they are in the foreground:
+//!
+//! ```rust
+//! pconsole.set_console(console);
+//! ```
+//!
 //! Buffer use and output
 //! ---------------------
 //! `ProcessConsole` does not use its own write buffer for output:
@@ -99,6 +156,16 @@
 //! Timeslice expirations: 0
 //! ```
 //!
+//! To see where a process lives in flash and memory, use `process`:
+//!
+//! ```text
+//! process blink
+//! Process blink (PID 0): Yielded
+//!   Flash  0x00030000-0x00032000, code at 0x00030048
+//!   Memory 0x20004000-0x20008000, break 0x20005000, grants from 0x20007c00
+//!   Syscalls 113, dropped callbacks 0, restarts 0, timeslice expirations 0
+//! ```
+//!
 //! and you can control processes with the `start` and `stop` commands:
 //!
 //! ```text
@@ -108,24 +175,141 @@
 
 use core::cell::Cell;
 use core::cmp;
+use core::fmt;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
-use kernel::common::cells::TakeCell;
+use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::debug;
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
+use kernel::procs::ProcessType;
 use kernel::Kernel;
 use kernel::ReturnCode;
 
-// Since writes are character echoes, we do not need more than 4 bytes:
-// the longest write is 3 bytes for a backspace (backspace, space, backspace).
-pub static mut WRITE_BUF: [u8; 4] = [0; 4];
+use crate::console::{Console, FOREGROUND_ESCAPE};
+
+// Writes are character echoes or redraws of the line being edited, so the
+// write buffer must hold a whole command plus a few bytes of terminal control
+// sequences.
+pub static mut WRITE_BUF: [u8; 80] = [0; 80];
 // Since reads are byte-by-byte, to properly echo what's typed,
 // we can use a very small read buffer.
 pub static mut READ_BUF: [u8; 4] = [0; 4];
-// Commands can be up to 32 bytes long: since commands themselves are 4-5
-// characters, limiting arguments to 25 bytes or so seems fine for now.
-pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
+// Commands can be up to 64 bytes long, including their arguments. Any length
+// up to `COMMAND_BUF_LEN` works, as long as the write buffer is a little
+// longer.
+pub const COMMAND_BUF_LEN: usize = 64;
+pub static mut COMMAND_BUF: [u8; COMMAND_BUF_LEN] = [0; COMMAND_BUF_LEN];
+// Previously executed commands, each stored in a slot as long as the command
+// buffer. This holds the last 8 commands.
+pub static mut HISTORY_BUF: [u8; 512] = [0; 512];
+
+/// Commands understood by the console, in the order `help` lists them.
+const COMMANDS: [&str; 11] = [
+    "help", "status", "list", "stop", "start", "fault", "fg", "process", "grants", "kernel",
+    "reboot",
+];
+
+/// Commands whose argument is a process name.
+const PROCESS_COMMANDS: [&str; 5] = ["stop", "start", "fault", "fg", "process"];
+
+/// Maximum number of commands that can be added with `register_command`.
+pub const MAX_REGISTERED_COMMANDS: usize = 8;
+
+/// Maximum number of arguments passed to a registered command.
+pub const MAX_ARGUMENTS: usize = 8;
+
+/// A command added to the process console by a board or another capsule.
+pub trait ConsoleCommand {
+    /// The word that runs the command. It must not contain whitespace.
+    fn name(&self) -> &'static str;
+
+    /// One line describing the command and its arguments, printed by `help`.
+    fn help(&self) -> &'static str;
+
+    /// Runs the command with the words typed after its name. Text written to
+    /// `writer` is printed on the console. A return code other than SUCCESS
+    /// is reported to the user.
+    fn execute(&self, arguments: &[&str], writer: &mut dyn fmt::Write) -> ReturnCode;
+}
+
+/// Prints the output of registered commands through the debug buffer, like
+/// the output of the built-in commands.
+struct DebugOutput;
+
+impl fmt::Write for DebugOutput {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        lines
+            .next()
+            .map(|line| debug::debug_write_fmt(format_args!("{}", line)));
+        for line in lines {
+            debug::debug_write_fmt(format_args!("\r\n{}", line));
+        }
+        Ok(())
+    }
+}
+
+/// Progress through a terminal escape sequence such as `ESC [ A`.
+#[derive(Copy, Clone, PartialEq)]
+enum EscapeState {
+    None,
+    Escape,
+    /// Inside a control sequence, with the numeric parameter read so far.
+    Csi(u8),
+}
+
+/// Candidates for tab completion that share a prefix with the word being
+/// completed. Uses cells because process names are collected from within a
+/// `Fn` closure.
+struct Completion<'b> {
+    word: &'b [u8],
+    first: Cell<Option<&'static str>>,
+    common_len: Cell<usize>,
+    count: Cell<usize>,
+}
+
+impl<'b> Completion<'b> {
+    fn new(word: &'b [u8]) -> Completion<'b> {
+        Completion {
+            word: word,
+            first: Cell::new(None),
+            common_len: Cell::new(0),
+            count: Cell::new(0),
+        }
+    }
+
+    fn add(&self, candidate: &'static str) {
+        let bytes = candidate.as_bytes();
+        if !bytes.starts_with(self.word) {
+            return;
+        }
+        self.count.set(self.count.get() + 1);
+        match self.first.get() {
+            None => {
+                self.first.set(Some(candidate));
+                self.common_len.set(bytes.len());
+            }
+            Some(first) => {
+                let common_len = first
+                    .as_bytes()
+                    .iter()
+                    .zip(bytes)
+                    .take(self.common_len.get())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                self.common_len.set(common_len);
+            }
+        }
+    }
+
+    /// The bytes that all candidates add to the word.
+    fn extension(&self) -> &'static [u8] {
+        self.first.get().map_or(&[], |first| {
+            &first.as_bytes()[self.word.len()..self.common_len.get()]
+        })
+    }
+}
 
 pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     uart: &'a dyn uart::UartData<'a>,
@@ -135,6 +319,22 @@ pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     rx_buffer: TakeCell<'static, [u8]>,
     command_buffer: TakeCell<'static, [u8]>,
     command_index: Cell<usize>,
+    /// Position of the cursor in the command being edited.
+    cursor: Cell<usize>,
+    escape: Cell<EscapeState>,
+    /// Set when the line on the terminal no longer matches the command
+    /// buffer and must be redrawn once the current write finishes.
+    redraw: Cell<bool>,
+    /// Set when the last key was a tab that did not complete anything.
+    tab_pending: Cell<bool>,
+    history_buffer: TakeCell<'static, [u8]>,
+    /// Slot the next command is stored in.
+    history_next: Cell<usize>,
+    /// Number of commands stored.
+    history_len: Cell<usize>,
+    /// How far back in the history the command being edited was recalled
+    /// from, or 0 for a new command.
+    history_position: Cell<usize>,
 
     /// Flag to mark that the process console is active and has called receive
     /// from the underlying UART.
@@ -145,6 +345,12 @@ pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     execute: Cell<bool>,
     kernel: &'static Kernel,
     capability: C,
+
+    /// The console, if any, that the `fg` command routes input to.
+    console: OptionalCell<&'a Console<'a>>,
+
+    /// Commands added with `register_command`, in registration order.
+    commands: [Cell<Option<&'a dyn ConsoleCommand>>; MAX_REGISTERED_COMMANDS],
 }
 
 impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
@@ -153,6 +359,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
         tx_buffer: &'static mut [u8],
         rx_buffer: &'static mut [u8],
         cmd_buffer: &'static mut [u8],
+        history_buffer: &'static mut [u8],
         kernel: &'static Kernel,
         capability: C,
     ) -> ProcessConsole<'a, C> {
@@ -164,11 +371,83 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             rx_buffer: TakeCell::new(rx_buffer),
             command_buffer: TakeCell::new(cmd_buffer),
             command_index: Cell::new(0),
+            cursor: Cell::new(0),
+            escape: Cell::new(EscapeState::None),
+            redraw: Cell::new(false),
+            tab_pending: Cell::new(false),
+            history_buffer: TakeCell::new(history_buffer),
+            history_next: Cell::new(0),
+            history_len: Cell::new(0),
+            history_position: Cell::new(0),
             running: Cell::new(false),
             execute: Cell::new(false),
             kernel: kernel,
             capability: capability,
+            console: OptionalCell::empty(),
+            commands: Default::default(),
+        }
+    }
+
+    /// Adds a command to the console. Returns EINVAL if the name is empty or
+    /// contains whitespace, EALREADY if a command with the same name exists,
+    /// and ENOMEM if `MAX_REGISTERED_COMMANDS` are already registered.
+    pub fn register_command(&self, command: &'a dyn ConsoleCommand) -> ReturnCode {
+        let name = command.name();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return ReturnCode::EINVAL;
+        }
+        if COMMANDS.contains(&name) || self.registered_command(name).is_some() {
+            return ReturnCode::EALREADY;
+        }
+        match self.commands.iter().find(|slot| slot.get().is_none()) {
+            Some(slot) => {
+                slot.set(Some(command));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    fn registered_commands(&self) -> impl Iterator<Item = &'a dyn ConsoleCommand> + '_ {
+        self.commands.iter().filter_map(|slot| slot.get())
+    }
+
+    fn registered_command(&self, name: &str) -> Option<&'a dyn ConsoleCommand> {
+        self.registered_commands()
+            .find(|command| command.name() == name)
+    }
+
+    /// Runs a registered command with the arguments that follow its name in
+    /// `line`.
+    fn run_registered_command(&self, command: &dyn ConsoleCommand, line: &str) {
+        let mut arguments = [""; MAX_ARGUMENTS];
+        let mut count = 0;
+        for word in line.split_whitespace().skip(1) {
+            if count == MAX_ARGUMENTS {
+                debug!("Too many arguments, at most {} allowed", MAX_ARGUMENTS);
+                return;
+            }
+            arguments[count] = word;
+            count += 1;
         }
+        let result = command.execute(&arguments[..count], &mut DebugOutput);
+        if result != ReturnCode::SUCCESS {
+            debug!("{} failed: {:?}", command.name(), result);
+        }
+    }
+
+    /// Lets the `fg` command route input on `console` to a process. This
+    /// enables input routing on `console`.
+    pub fn set_console(&self, console: &'a Console<'a>) {
+        console.enable_input_routing();
+        self.console.set(console);
+    }
+
+    /// Whether input currently goes to a foreground process instead of the
+    /// process console.
+    fn in_background(&self) -> bool {
+        self.console
+            .map_or(false, |console| console.foreground().is_some())
     }
 
     pub fn start(&self) -> ReturnCode {
@@ -203,10 +482,20 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                 match cmd_str {
                     Ok(s) => {
                         let clean_str = s.trim();
-                        if clean_str.starts_with("help") {
+                        // Commands are matched by their whole first word, so
+                        // that for example `stopall` does not run `stop`.
+                        let command_name = clean_str.split_whitespace().next();
+                        let registered =
+                            command_name.and_then(|name| self.registered_command(name));
+                        if let Some(registered) = registered {
+                            self.run_registered_command(registered, clean_str);
+                        } else if command_name == Some("help") {
                             debug!("Welcome to the process console.");
-                            debug!("Valid commands are: help status list stop start fault");
-                        } else if clean_str.starts_with("start") {
+                            debug!("Valid commands are: help status list stop start fault fg process grants kernel reboot");
+                            for registered in self.registered_commands() {
+                                debug!("  {}", registered.help());
+                            }
+                        } else if command_name == Some("start") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
                                 self.kernel.process_each_capability(
@@ -220,7 +509,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                     },
                                 );
                             });
-                        } else if clean_str.starts_with("stop") {
+                        } else if command_name == Some("stop") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
                                 self.kernel.process_each_capability(
@@ -234,7 +523,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                     },
                                 );
                             });
-                        } else if clean_str.starts_with("fault") {
+                        } else if command_name == Some("fault") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
                                 self.kernel.process_each_capability(
@@ -248,7 +537,27 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                     },
                                 );
                             });
-                        } else if clean_str.starts_with("list") {
+                        } else if command_name == Some("fg") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            match (argument, self.console.map(|console| *console)) {
+                                (Some(name), Some(console)) => {
+                                    self.kernel.process_each_capability(
+                                        &self.capability,
+                                        |proc| {
+                                            if proc.get_process_name() == name {
+                                                console.set_foreground(Some(proc.appid()));
+                                                debug!(
+                                                    "Process {} is in the foreground. Press Ctrl-G to return.",
+                                                    name
+                                                );
+                                            }
+                                        },
+                                    );
+                                }
+                                (_, None) => debug!("No console to route input to"),
+                                (None, _) => debug!("Usage: fg <process name>"),
+                            }
+                        } else if command_name == Some("list") {
                             debug!(" PID    Name                Quanta  Syscalls  Dropped Callbacks  Restarts    State  Grants");
                             self.kernel
                                 .process_each_capability(&self.capability, |proc| {
@@ -271,7 +580,58 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                         grants_total
                                     );
                                 });
-                        } else if clean_str.starts_with("status") {
+                        } else if command_name == Some("process") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            argument.map(|name| {
+                                self.kernel.process_each_capability(
+                                    &self.capability,
+                                    |proc| {
+                                        if proc.get_process_name() == name {
+                                            self.print_process(proc);
+                                        }
+                                    },
+                                );
+                            });
+                        } else if command_name == Some("grants") {
+                            debug!(" PID    Name                Grants  Grant bytes");
+                            self.kernel
+                                .process_each_capability(&self.capability, |proc| {
+                                    let info: KernelInfo = KernelInfo::new(self.kernel);
+                                    let appid = proc.appid();
+                                    let (grants_used, grants_total) = info.number_app_grant_uses(appid, &self.capability);
+                                    let grant_bytes = info
+                                        .process_memory_layout(appid, &self.capability)
+                                        .map_or(0, |layout| layout.memory_end - layout.grant_start);
+                                    debug!(
+                                        "  {:?}\t{:<20}{:3}/{:<3}{:12}",
+                                        appid,
+                                        proc.get_process_name(),
+                                        grants_used,
+                                        grants_total,
+                                        grant_bytes
+                                    );
+                                });
+                        } else if command_name == Some("kernel") {
+                            let info: KernelInfo = KernelInfo::new(self.kernel);
+                            let (clients_used, clients_total) = info.deferred_call_clients(&self.capability);
+                            debug!(
+                                "Interrupt services: {}",
+                                info.number_interrupt_services(&self.capability)
+                            );
+                            debug!(
+                                "Deferred calls: {} ({}/{} clients)",
+                                info.number_deferred_calls(&self.capability),
+                                clients_used,
+                                clients_total
+                            );
+                            debug!(
+                                "Timeslice expirations: {}",
+                                info.timeslice_expirations(&self.capability)
+                            );
+                        } else if command_name == Some("reboot") {
+                            let result = self.kernel.reset(&self.capability);
+                            debug!("Cannot reboot: {:?}", result);
+                        } else if command_name == Some("status") {
                             let info: KernelInfo = KernelInfo::new(self.kernel);
                             debug!(
                                 "Total processes: {}",
@@ -286,7 +646,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                 info.timeslice_expirations(&self.capability)
                             );
                         } else {
-                            debug!("Valid commands are: help status list stop start fault");
+                            debug!("Valid commands are: help status list stop start fault fg process grants kernel reboot");
                         }
                     }
                     Err(_e) => debug!("Invalid command: {:?}", command),
@@ -297,19 +657,39 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             command[0] = 0;
         });
         self.command_index.set(0);
+        self.cursor.set(0);
+        self.history_position.set(0);
     }
 
-    fn write_byte(&self, byte: u8) -> ReturnCode {
-        if self.tx_in_progress.get() {
-            ReturnCode::EBUSY
-        } else {
-            self.tx_in_progress.set(true);
-            self.tx_buffer.take().map(|buffer| {
-                buffer[0] = byte;
-                self.uart.transmit_buffer(buffer, 1);
-            });
-            ReturnCode::SUCCESS
-        }
+    /// Prints the memory layout and statistics of a process.
+    fn print_process(&self, proc: &dyn ProcessType) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        let appid = proc.appid();
+        let layout = match info.process_memory_layout(appid, &self.capability) {
+            Some(layout) => layout,
+            None => return,
+        };
+        debug!(
+            "Process {} ({:?}): {:?}",
+            proc.get_process_name(),
+            appid,
+            proc.get_state()
+        );
+        debug!(
+            "  Flash  {:#010x}-{:#010x}, code at {:#010x}",
+            layout.flash_start, layout.flash_end, layout.flash_app_start
+        );
+        debug!(
+            "  Memory {:#010x}-{:#010x}, break {:#010x}, grants from {:#010x}",
+            layout.memory_start, layout.memory_end, layout.app_break, layout.grant_start
+        );
+        debug!(
+            "  Syscalls {}, dropped callbacks {}, restarts {}, timeslice expirations {}",
+            info.number_app_syscalls(appid, &self.capability),
+            info.number_app_dropped_callbacks(appid, &self.capability),
+            info.number_app_restarts(appid, &self.capability),
+            info.number_app_timeslice_expirations(appid, &self.capability)
+        );
     }
 
     fn write_bytes(&self, bytes: &[u8]) -> ReturnCode {
@@ -326,6 +706,354 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             ReturnCode::SUCCESS
         }
     }
+
+    /// Echoes `bytes`, or redraws the whole line later if a write is in
+    /// progress.
+    fn echo(&self, bytes: &[u8]) {
+        if self.write_bytes(bytes) != ReturnCode::SUCCESS {
+            self.redraw.set(true);
+        }
+    }
+
+    /// Redraws the line being edited and places the cursor, as soon as no
+    /// write is in progress.
+    fn redraw_line(&self) {
+        if self.tx_in_progress.get() {
+            self.redraw.set(true);
+            return;
+        }
+        self.redraw.set(false);
+        let len = self.command_index.get();
+        let back = len - self.cursor.get();
+        self.tx_in_progress.set(true);
+        self.tx_buffer.take().map(|buffer| {
+            let mut written = 0;
+            {
+                let mut push = |bytes: &[u8]| {
+                    let n = cmp::min(bytes.len(), buffer.len() - written);
+                    buffer[written..written + n].copy_from_slice(&bytes[..n]);
+                    written += n;
+                };
+                push(b"\r\x1b[K");
+                self.command_buffer.map(|command| push(&command[..len]));
+                if back > 0 {
+                    let mut sequence = [0; 8];
+                    let n = cursor_left_sequence(back, &mut sequence);
+                    push(&sequence[..n]);
+                }
+            }
+            self.uart.transmit_buffer(buffer, written);
+        });
+    }
+
+    /// Replaces the command being edited with `bytes`.
+    fn set_line(&self, bytes: &[u8]) {
+        self.command_buffer.map(|command| {
+            let len = cmp::min(bytes.len(), command.len() - 1);
+            command[..len].copy_from_slice(&bytes[..len]);
+            command[len] = 0;
+            self.command_index.set(len);
+            self.cursor.set(len);
+        });
+        self.redraw_line();
+    }
+
+    /// Inserts `bytes` at the cursor. Returns false if they do not fit.
+    fn insert(&self, bytes: &[u8]) -> bool {
+        self.command_buffer.map_or(false, |command| {
+            let len = self.command_index.get();
+            let cursor = self.cursor.get();
+            if len + bytes.len() >= command.len() {
+                return false;
+            }
+            command.copy_within(cursor..len, cursor + bytes.len());
+            command[cursor..cursor + bytes.len()].copy_from_slice(bytes);
+            command[len + bytes.len()] = 0;
+            self.command_index.set(len + bytes.len());
+            self.cursor.set(cursor + bytes.len());
+            true
+        })
+    }
+
+    /// Removes the character at `index` from the command being edited.
+    fn remove(&self, index: usize) {
+        self.command_buffer.map(|command| {
+            let len = self.command_index.get();
+            command.copy_within(index + 1..len, index);
+            command[len - 1] = 0;
+            self.command_index.set(len - 1);
+        });
+    }
+
+    /// Stores the command being edited in the history, unless it is empty or
+    /// repeats the last command.
+    fn save_history(&self) {
+        let len = self.command_index.get();
+        if len == 0 {
+            return;
+        }
+        self.command_buffer.map(|command| {
+            self.history_buffer.map(|history| {
+                let slot_len = command.len();
+                let slots = history.len() / slot_len;
+                if slots == 0 {
+                    return;
+                }
+                if self.history_len.get() > 0 {
+                    let last = (self.history_next.get() + slots - 1) % slots;
+                    let entry = &history[last * slot_len..(last + 1) * slot_len];
+                    if entry[..len] == command[..len] && entry[len] == 0 {
+                        return;
+                    }
+                }
+                let next = self.history_next.get();
+                history[next * slot_len..(next + 1) * slot_len].copy_from_slice(command);
+                self.history_next.set((next + 1) % slots);
+                self.history_len
+                    .set(cmp::min(self.history_len.get() + 1, slots));
+            });
+        });
+    }
+
+    /// Replaces the command being edited with the command `position` entries
+    /// back in the history, or an empty command if `position` is 0.
+    fn recall_history(&self, position: usize) {
+        self.history_position.set(position);
+        if position == 0 {
+            self.set_line(&[]);
+            return;
+        }
+        let slot_len = self.command_buffer.map_or(0, |command| command.len());
+        self.history_buffer.map(|history| {
+            let slots = history.len() / slot_len;
+            let slot = (self.history_next.get() + slots - position) % slots;
+            let entry = &history[slot * slot_len..(slot + 1) * slot_len];
+            let len = entry.iter().position(|&b| b == 0).unwrap_or(0);
+            self.set_line(&entry[..len]);
+        });
+    }
+
+    /// Completes the word before the cursor from the command names or, in
+    /// the argument of a command that takes one, from the process names.
+    fn complete(&self) {
+        let len = self.command_index.get();
+        if self.cursor.get() != len {
+            self.echo(b"\x07");
+            return;
+        }
+        // Completion works on a copy of the line, since the command buffer
+        // changes while the word being completed is still in use.
+        let mut line = [0; COMMAND_BUF_LEN];
+        if len > line.len() {
+            self.echo(b"\x07");
+            return;
+        }
+        self.command_buffer
+            .map(|command| line[..len].copy_from_slice(&command[..len]));
+        let line = &line[..len];
+
+        let (completion, suffix) = match line.iter().position(|&b| b == b' ') {
+            None => {
+                let completion = Completion::new(line);
+                COMMANDS.iter().for_each(|name| completion.add(name));
+                self.registered_commands()
+                    .for_each(|command| completion.add(command.name()));
+                (completion, &b" "[..])
+            }
+            Some(space) => {
+                let name = &line[..space];
+                let argument = &line[space + 1..];
+                if !PROCESS_COMMANDS.iter().any(|c| c.as_bytes() == name)
+                    || argument.contains(&b' ')
+                {
+                    self.echo(b"\x07");
+                    return;
+                }
+                let completion = Completion::new(argument);
+                self.kernel
+                    .process_each_capability(&self.capability, |proc| {
+                        completion.add(proc.get_process_name())
+                    });
+                (completion, &b""[..])
+            }
+        };
+
+        let extension = completion.extension();
+        if completion.count.get() == 0 {
+            self.echo(b"\x07");
+        } else if completion.count.get() == 1 || !extension.is_empty() {
+            // Ring instead if the completed line does not fit the command
+            // buffer.
+            if !self.insert(extension) {
+                self.echo(b"\x07");
+                return;
+            }
+            if completion.count.get() == 1 {
+                self.insert(suffix);
+            }
+            self.redraw_line();
+        } else if self.tab_pending.get() {
+            // Second tab without progress: list the candidates.
+            let word = completion.word;
+            if suffix.is_empty() {
+                self.kernel
+                    .process_each_capability(&self.capability, |proc| {
+                        let name = proc.get_process_name();
+                        if name.as_bytes().starts_with(word) {
+                            debug!("  {}", name);
+                        }
+                    });
+            } else {
+                let registered = self.registered_commands().map(|command| command.name());
+                for name in COMMANDS.iter().cloned().chain(registered) {
+                    if name.as_bytes().starts_with(word) {
+                        debug!("  {}", name);
+                    }
+                }
+            }
+            self.redraw_line();
+        } else {
+            self.tab_pending.set(true);
+            self.echo(b"\x07");
+        }
+    }
+
+    /// Handles the final byte of an escape sequence.
+    fn handle_escape(&self, byte: u8, parameter: u8) {
+        let len = self.command_index.get();
+        let cursor = self.cursor.get();
+        match (byte, parameter) {
+            (b'A', _) => {
+                if self.history_position.get() < self.history_len.get() {
+                    self.recall_history(self.history_position.get() + 1);
+                }
+            }
+            (b'B', _) => {
+                if self.history_position.get() > 0 {
+                    self.recall_history(self.history_position.get() - 1);
+                }
+            }
+            (b'C', _) => {
+                if cursor < len {
+                    self.cursor.set(cursor + 1);
+                    self.echo(b"\x1b[C");
+                }
+            }
+            (b'D', _) => {
+                if cursor > 0 {
+                    self.cursor.set(cursor - 1);
+                    self.echo(b"\x08");
+                }
+            }
+            (b'H', _) | (b'~', 1) | (b'~', 7) => {
+                self.cursor.set(0);
+                self.redraw_line();
+            }
+            (b'F', _) | (b'~', 4) | (b'~', 8) => {
+                self.cursor.set(len);
+                self.redraw_line();
+            }
+            (b'~', 3) => {
+                if cursor < len {
+                    self.remove(cursor);
+                    self.redraw_line();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles one byte typed into the console.
+    fn handle_byte(&self, byte: u8) {
+        match self.escape.get() {
+            EscapeState::Escape => {
+                self.escape.set(if byte == b'[' || byte == b'O' {
+                    EscapeState::Csi(0)
+                } else {
+                    EscapeState::None
+                });
+                return;
+            }
+            EscapeState::Csi(parameter) => {
+                if byte.is_ascii_digit() {
+                    let parameter = parameter.saturating_mul(10).saturating_add(byte - b'0');
+                    self.escape.set(EscapeState::Csi(parameter));
+                } else {
+                    self.escape.set(EscapeState::None);
+                    self.handle_escape(byte, parameter);
+                }
+                return;
+            }
+            EscapeState::None => {}
+        }
+
+        if byte != b'\t' {
+            self.tab_pending.set(false);
+        }
+        let len = self.command_index.get();
+        let cursor = self.cursor.get();
+        match byte {
+            b'\n' | b'\r' => {
+                self.save_history();
+                self.execute.set(true);
+                self.write_bytes(&[b'\r', b'\n']);
+            }
+            0x1b => self.escape.set(EscapeState::Escape),
+            b'\t' => self.complete(),
+            // Ctrl-A and Ctrl-E
+            0x01 => self.handle_escape(b'H', 0),
+            0x05 => self.handle_escape(b'F', 0),
+            // Backspace, sent as either BS or DEL
+            0x08 | 0x7f => {
+                if cursor > 0 {
+                    self.remove(cursor - 1);
+                    self.cursor.set(cursor - 1);
+                    if cursor == len {
+                        // Note echo is '\b \b' to erase
+                        self.echo(&[b'\x08', b' ', b'\x08']);
+                    } else {
+                        self.redraw_line();
+                    }
+                }
+            }
+            // For some reason, sometimes reads return > 127 but no error,
+            // which causes utf-8 decoding failure, so only accept printable
+            // ASCII. -pal
+            0x20..=0x7e => {
+                if self.insert(&[byte]) {
+                    if cursor == len {
+                        self.echo(&[byte]);
+                    } else {
+                        self.redraw_line();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes the escape sequence that moves the cursor `count` columns left
+/// into `buffer`, returning its length.
+fn cursor_left_sequence(count: usize, buffer: &mut [u8; 8]) -> usize {
+    buffer[0] = 0x1b;
+    buffer[1] = b'[';
+    let mut digits = [0; 5];
+    let mut n = 0;
+    let mut count = cmp::min(count, 99999);
+    loop {
+        digits[n] = b'0' + (count % 10) as u8;
+        n += 1;
+        count /= 10;
+        if count == 0 {
+            break;
+        }
+    }
+    for i in 0..n {
+        buffer[2 + i] = digits[n - 1 - i];
+    }
+    buffer[2 + n] = b'D';
+    n + 3
 }
 
 impl<'a, C: ProcessManagementCapability> uart::TransmitClient for ProcessConsole<'a, C> {
@@ -339,6 +1067,11 @@ impl<'a, C: ProcessManagementCapability> uart::TransmitClient for ProcessConsole
             self.execute.set(false);
             self.read_command();
         }
+
+        // Catch up with edits whose echo could not be written.
+        if self.redraw.get() {
+            self.redraw_line();
+        }
     }
 }
 impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<'a, C> {
@@ -349,33 +1082,17 @@ impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<
         _rcode: ReturnCode,
         error: uart::Error,
     ) {
-        if error == uart::Error::None {
+        if error == uart::Error::None && rx_len == 1 && self.in_background() {
+            // Input belongs to the foreground process, except for the escape
+            // byte that hands it back to us.
+            if read_buf[0] == FOREGROUND_ESCAPE {
+                self.console.map(|console| console.set_foreground(None));
+                debug!("Returned to the process console");
+            }
+        } else if error == uart::Error::None {
             match rx_len {
                 0 => debug!("ProcessConsole had read of 0 bytes"),
-                1 => {
-                    self.command_buffer.map(|command| {
-                        let index = self.command_index.get() as usize;
-                        if read_buf[0] == ('\n' as u8) || read_buf[0] == ('\r' as u8) {
-                            self.execute.set(true);
-                            self.write_bytes(&['\r' as u8, '\n' as u8]);
-                        } else if read_buf[0] == ('\x08' as u8) && index > 0 {
-                            // Backspace, echo and remove last byte
-                            // Note echo is '\b \b' to erase
-                            self.write_bytes(&['\x08' as u8, ' ' as u8, '\x08' as u8]);
-                            command[index - 1] = '\0' as u8;
-                            self.command_index.set(index - 1);
-                        } else if index < (command.len() - 1) && read_buf[0] < 128 {
-                            // For some reason, sometimes reads return > 127 but no error,
-                            // which causes utf-8 decoding failure, so check byte is < 128. -pal
-
-                            // Echo the byte and store it
-                            self.write_byte(read_buf[0]);
-                            command[index] = read_buf[0];
-                            self.command_index.set(index + 1);
-                            command[index + 1] = 0;
-                        }
-                    });
-                }
+                1 => self.handle_byte(read_buf[0]),
                 _ => debug!(
                     "ProcessConsole issues reads of 1 byte, but receive_complete was length {}",
                     rx_len
@@ -386,3 +1103,262 @@ impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<
         self.uart.receive_buffer(read_buf, 1);
     }
 }
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{ConsoleCommand, ProcessConsole, COMMAND_BUF_LEN};
+    use core::cell::RefCell;
+    use core::fmt;
+    use host::harness::{create_kernel, leak, TestCapabilities};
+    use host::mock::uart::MockUart;
+    use kernel::ReturnCode;
+    use std::boxed::Box;
+    use std::string::{String, ToString};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Just enough of a terminal to follow what the process console draws.
+    #[derive(Default)]
+    struct Terminal {
+        lines: Vec<Vec<u8>>,
+        line: Vec<u8>,
+        cursor: usize,
+        bells: usize,
+        escape: Option<Vec<u8>>,
+    }
+
+    impl Terminal {
+        fn feed(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                if let Some(mut sequence) = self.escape.take() {
+                    if sequence.is_empty() || b.is_ascii_digit() {
+                        sequence.push(b);
+                        self.escape = Some(sequence);
+                        continue;
+                    }
+                    let count = core::str::from_utf8(&sequence[1..])
+                        .unwrap()
+                        .parse()
+                        .unwrap_or(1);
+                    match b {
+                        b'K' => self.line.truncate(self.cursor),
+                        b'C' => self.cursor += count,
+                        b'D' => self.cursor -= count,
+                        _ => panic!("unexpected escape sequence"),
+                    }
+                    continue;
+                }
+                match b {
+                    0x1b => self.escape = Some(Vec::new()),
+                    0x07 => self.bells += 1,
+                    0x08 => self.cursor -= 1,
+                    b'\r' => self.cursor = 0,
+                    b'\n' => {
+                        self.lines
+                            .push(core::mem::replace(&mut self.line, Vec::new()));
+                        self.cursor = 0;
+                    }
+                    _ => {
+                        if self.cursor < self.line.len() {
+                            self.line[self.cursor] = b;
+                        } else {
+                            self.line.push(b);
+                        }
+                        self.cursor += 1;
+                    }
+                }
+            }
+        }
+
+        fn shows(&self, text: &str, cursor: usize) {
+            let line = core::str::from_utf8(&self.line).unwrap();
+            assert_eq!((line.trim_end(), self.cursor), (text, cursor));
+        }
+    }
+
+    #[test]
+    pub fn process_console_edits_lines() {
+        let (kernel, _) = create_kernel();
+        let mock_uart: &'static MockUart = leak(MockUart::new());
+        let pconsole = leak(ProcessConsole::new(
+            mock_uart,
+            Box::leak(Box::new([0; 80])),
+            Box::leak(Box::new([0; 4])),
+            Box::leak(Box::new([0; COMMAND_BUF_LEN])),
+            Box::leak(Box::new([0; 192])),
+            kernel,
+            TestCapabilities,
+        ));
+        kernel::hil::uart::Transmit::set_transmit_client(mock_uart, pconsole);
+        kernel::hil::uart::Receive::set_receive_client(mock_uart, pconsole);
+        pconsole.start();
+
+        let terminal = RefCell::new(Terminal::default());
+        let type_bytes = |bytes: &[u8]| {
+            for &b in bytes {
+                assert!(mock_uart.complete_receive(
+                    &[b],
+                    ReturnCode::SUCCESS,
+                    kernel::hil::uart::Error::None
+                ));
+                while mock_uart.is_transmitting() {
+                    mock_uart.with_transmitted_data(|data| terminal.borrow_mut().feed(data));
+                    mock_uart.complete_transmit(ReturnCode::SUCCESS);
+                }
+            }
+        };
+        let left = b"\x1b[D";
+        let right = b"\x1b[C";
+        let up = b"\x1b[A";
+        let down = b"\x1b[B";
+
+        // Insert in the middle of the line, and move with Home and End.
+        type_bytes(b"sop x");
+        type_bytes(b"\x1b[H");
+        type_bytes(right);
+        type_bytes(b"t");
+        terminal.borrow().shows("stop x", 2);
+        type_bytes(b"\x1b[F\r");
+        assert_eq!(terminal.borrow().lines, [b"stop x".to_vec()]);
+
+        // Tab completes unique command names, and rings when ambiguous.
+        type_bytes(b"st\t");
+        terminal.borrow().shows("st", 2);
+        assert_eq!(terminal.borrow().bells, 1);
+        type_bytes(b"o\t");
+        terminal.borrow().shows("stop", 5);
+        type_bytes(b"\x7f\x7f\x7f\x7f\x7ffau\t");
+        terminal.borrow().shows("fault", 6);
+        type_bytes(b"\x7f\x7f\x7f\x7f\x7f\x7fsta\ttu\t");
+        terminal.borrow().shows("status", 7);
+        assert_eq!(terminal.borrow().bells, 2);
+        type_bytes(b"\x7f\x7f\x7f\x7f\x7f\x7f\x7ffau\ty\r");
+        assert_eq!(terminal.borrow().lines[1], b"fault y");
+
+        // Backspace and Delete in the middle of the line.
+        type_bytes(b"abcd");
+        type_bytes(left);
+        type_bytes(left);
+        type_bytes(b"\x08");
+        terminal.borrow().shows("acd", 1);
+        type_bytes(b"\x1b[3~");
+        terminal.borrow().shows("ad", 1);
+
+        // The arrows walk through the history, newest first.
+        type_bytes(up);
+        terminal.borrow().shows("fault y", 7);
+        type_bytes(up);
+        type_bytes(up);
+        terminal.borrow().shows("stop x", 6);
+        type_bytes(down);
+        terminal.borrow().shows("fault y", 7);
+        type_bytes(down);
+        terminal.borrow().shows("", 0);
+
+        // Completions that do not fit the command buffer ring instead.
+        let long = leak(RecordingCommand {
+            name: "sensor_with_a_name_longer_than_the_command_buffer_of_the_process_console",
+            runs: RefCell::new(Vec::new()),
+        });
+        assert_eq!(pconsole.register_command(long), ReturnCode::SUCCESS);
+        type_bytes(b"sensor\t");
+        terminal.borrow().shows("sensor", 6);
+        assert_eq!(terminal.borrow().bells, 3);
+    }
+
+    /// A console command that records the arguments it is run with.
+    struct RecordingCommand {
+        name: &'static str,
+        runs: RefCell<Vec<Vec<String>>>,
+    }
+
+    impl ConsoleCommand for RecordingCommand {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn help(&self) -> &'static str {
+            "records its arguments"
+        }
+
+        fn execute(&self, arguments: &[&str], _writer: &mut dyn fmt::Write) -> ReturnCode {
+            self.runs
+                .borrow_mut()
+                .push(arguments.iter().map(|a| a.to_string()).collect());
+            ReturnCode::SUCCESS
+        }
+    }
+
+    #[test]
+    pub fn process_console_runs_registered_commands() {
+        let (kernel, _) = create_kernel();
+        let mock_uart: &'static MockUart = leak(MockUart::new());
+        let pconsole = leak(ProcessConsole::new(
+            mock_uart,
+            Box::leak(Box::new([0; 80])),
+            Box::leak(Box::new([0; 4])),
+            Box::leak(Box::new([0; COMMAND_BUF_LEN])),
+            Box::leak(Box::new([0; 192])),
+            kernel,
+            TestCapabilities,
+        ));
+        kernel::hil::uart::Transmit::set_transmit_client(mock_uart, pconsole);
+        kernel::hil::uart::Receive::set_receive_client(mock_uart, pconsole);
+        pconsole.start();
+
+        let recording = |name| {
+            leak(RecordingCommand {
+                name: name,
+                runs: RefCell::new(Vec::new()),
+            })
+        };
+        let sensor = recording("sensor");
+        assert_eq!(pconsole.register_command(sensor), ReturnCode::SUCCESS);
+        assert_eq!(pconsole.register_command(sensor), ReturnCode::EALREADY);
+        assert_eq!(
+            pconsole.register_command(recording("list")),
+            ReturnCode::EALREADY
+        );
+        assert_eq!(
+            pconsole.register_command(recording("two words")),
+            ReturnCode::EINVAL
+        );
+        assert_eq!(pconsole.register_command(recording("")), ReturnCode::EINVAL);
+        let names = ["c1", "c2", "c3", "c4", "c5", "c6", "c7"];
+        for &name in names.iter() {
+            assert_eq!(
+                pconsole.register_command(recording(name)),
+                ReturnCode::SUCCESS
+            );
+        }
+        assert_eq!(
+            pconsole.register_command(recording("c8")),
+            ReturnCode::ENOMEM
+        );
+
+        let type_bytes = |bytes: &[u8]| {
+            for &b in bytes {
+                assert!(mock_uart.complete_receive(
+                    &[b],
+                    ReturnCode::SUCCESS,
+                    kernel::hil::uart::Error::None
+                ));
+                while mock_uart.is_transmitting() {
+                    mock_uart.complete_transmit(ReturnCode::SUCCESS);
+                }
+            }
+        };
+
+        // Registered commands are found by their exact name and get the words
+        // that follow it.
+        type_bytes(b"sensor  read 3\r");
+        assert_eq!(*sensor.runs.borrow(), [vec!["read", "3"]]);
+
+        // Their names are tab completed like the built-in commands.
+        type_bytes(b"sen\t\r");
+        assert_eq!(sensor.runs.borrow().len(), 2);
+        assert!(sensor.runs.borrow()[1].is_empty());
+    }
+}
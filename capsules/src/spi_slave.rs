@@ -0,0 +1,493 @@
+//! Lets a process act as an SPI slave (peripheral) to an external master.
+//!
+//! The bus is driven by the master, so only one process can sensibly answer
+//! it. A process opens the driver for exclusive use, shares a buffer to send
+//! and a buffer to receive into, and arms a transfer of a given length. The
+//! transfer runs when the master selects the board and clocks the bytes; the
+//! process is then notified with the number of bytes exchanged. A separate
+//! callback reports every time the master asserts chip select, so the process
+//! can prepare its reply.
+//!
+//! Ownership is stored in the owning process's grant, so the driver is
+//! released automatically when its owner exits, faults or is restarted. A
+//! transfer that is still armed when its process closes the driver or loses
+//! it this way is cancelled.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let spi_slave_device = static_init!(
+//!     VirtualSpiSlaveDevice<'static, sam4l::spi::SpiHw>,
+//!     VirtualSpiSlaveDevice::new(&sam4l::spi::SPI)
+//! );
+//! let spi_slave = static_init!(
+//!     capsules::spi_slave::SpiSlave<'static, VirtualSpiSlaveDevice<'static, sam4l::spi::SpiHw>>,
+//!     capsules::spi_slave::SpiSlave::new(spi_slave_device, board_kernel.create_grant(&grant_cap))
+//! );
+//! spi_slave.config_buffers(&mut capsules::spi_slave::READ_BUF, &mut capsules::spi_slave::WRITE_BUF);
+//! spi_slave_device.set_client(spi_slave);
+//! hil::spi::SpiSlave::set_client(&sam4l::spi::SPI, Some(spi_slave_device));
+//! hil::spi::SpiSlave::init(&sam4l::spi::SPI);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiSlaveClient, SpiSlaveDevice};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::SpiSlave as usize;
+
+/// Kernel buffers that transfers are copied through. A single transfer cannot
+/// be longer than the shorter of the two.
+pub static mut READ_BUF: [u8; 256] = [0; 256];
+pub static mut WRITE_BUF: [u8; 256] = [0; 256];
+
+#[derive(Default)]
+pub struct App {
+    /// Whether this process has the driver open.
+    opened: bool,
+    callback: Option<Callback>,
+    selected_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct SpiSlave<'a, S: SpiSlaveDevice> {
+    spi_slave: &'a S,
+    /// The process whose transfer is armed, if any.
+    current: OptionalCell<AppId>,
+    kernel_read: TakeCell<'static, [u8]>,
+    kernel_write: TakeCell<'static, [u8]>,
+    kernel_len: Cell<usize>,
+    apps: Grant<App>,
+}
+
+impl<'a, S: SpiSlaveDevice> SpiSlave<'a, S> {
+    pub fn new(spi_slave: &'a S, grant: Grant<App>) -> SpiSlave<'a, S> {
+        SpiSlave {
+            spi_slave: spi_slave,
+            current: OptionalCell::empty(),
+            kernel_read: TakeCell::empty(),
+            kernel_write: TakeCell::empty(),
+            kernel_len: Cell::new(0),
+            apps: grant,
+        }
+    }
+
+    pub fn config_buffers(&mut self, read: &'static mut [u8], write: &'static mut [u8]) {
+        let len = cmp::min(read.len(), write.len());
+        self.kernel_len.set(len);
+        self.kernel_read.replace(read);
+        self.kernel_write.replace(write);
+    }
+
+    /// Returns the process that has the driver open, if any. Processes that
+    /// have faulted no longer have an accessible grant, so they lose the
+    /// driver.
+    fn owner(&self) -> Option<AppId> {
+        self.apps
+            .iter()
+            .find_map(|app| app.enter(|app, _| if app.opened { Some(app.appid()) } else { None }))
+    }
+
+    /// Cancels the armed transfer, if any, and takes back the kernel buffers.
+    fn abort(&self) {
+        if self.current.is_none() {
+            return;
+        }
+        match self.spi_slave.abort_transfer() {
+            // The chip cannot cancel transfers, so this one stays armed until
+            // the master clocks it.
+            (None, None) => {}
+            (write, read) => {
+                self.current.clear();
+                self.kernel_write.put(write);
+                self.kernel_read.put(read);
+            }
+        }
+    }
+
+    /// Arms a transfer of `len` bytes with the owner's buffers.
+    fn read_write(&self, len: usize, appid: AppId) -> ReturnCode {
+        if self.current.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        if len > self.kernel_len.get() {
+            return ReturnCode::ESIZE;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                if app.app_write.is_none() && app.app_read.is_none() {
+                    return ReturnCode::EINVAL;
+                }
+                let too_short = |buffer: &Option<AppSlice<Shared, u8>>| {
+                    buffer.as_ref().map_or(false, |buffer| buffer.len() < len)
+                };
+                if too_short(&app.app_write) || too_short(&app.app_read) {
+                    return ReturnCode::EINVAL;
+                }
+                let write = app.app_write.as_ref().and_then(|src| {
+                    self.kernel_write.take().map(|kwbuf| {
+                        kwbuf[..len].copy_from_slice(&src.as_ref()[..len]);
+                        kwbuf
+                    })
+                });
+                let read = match app.app_read {
+                    Some(_) => self.kernel_read.take(),
+                    None => None,
+                };
+                self.current.set(appid);
+                let result = self.spi_slave.read_write_bytes(write, read, len);
+                if result != ReturnCode::SUCCESS {
+                    self.current.clear();
+                }
+                result
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl<S: SpiSlaveDevice> Driver for SpiSlave<'_, S> {
+    /// Provide read/write buffers to SpiSlave
+    ///
+    /// - allow_num 0: Provides an app_read buffer to receive transfers into.
+    ///
+    /// - allow_num 1: Provides an app_write buffer to send transfers from.
+    ///
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.app_read = slice;
+                    } else {
+                        app.app_write = slice;
+                    }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks for SpiSlave
+    ///
+    /// - subscribe_num 0: Sets up a callback for when read_write completes.
+    ///                  This is called with the number of bytes exchanged
+    ///                  once the master has clocked the armed transfer.
+    ///
+    /// - subscribe_num 1: Sets up a callback for when the chip select line is
+    ///                  driven low, meaning that the slave was selected by
+    ///                  the Spi master. This occurs immediately before
+    ///                  a data transfer.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if subscribe_num == 0 {
+                        app.callback = callback;
+                    } else {
+                        app.selected_callback = callback;
+                    }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// - 0: check if present
+    /// - 1: arm a transfer of arg1 bytes
+    ///   - read and write buffers optional, but at least one is needed
+    ///   - fails with EINVAL if a shared buffer is shorter than arg1, and
+    ///     with ESIZE if arg1 is longer than the kernel buffers
+    /// - 2: get chip select
+    ///   - in slave mode, always returns 0
+    /// - 3: set clock phase
+    ///   - 0 is sample leading
+    ///   - non-zero is sample trailing
+    /// - 4: get clock phase
+    /// - 5: set clock polarity
+    ///   - 0 is idle low
+    ///   - non-zero is idle high
+    /// - 6: get clock polarity
+    /// - 7: open the driver for exclusive use and reset the clock to idle low,
+    ///      sample leading
+    /// - 8: close the driver, cancelling the armed transfer
+    ///
+    /// Commands other than 0 and 7 return ERESERVE unless the process has the
+    /// driver open.
+    fn command(&self, cmd_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        let owner = self.owner();
+        // A process that armed a transfer and then faulted or exited no longer
+        // owns the driver, and its transfer must not block the next owner.
+        if self
+            .current
+            .map_or(false, |current| Some(*current) != owner)
+        {
+            self.abort();
+        }
+        if cmd_num != 0 && cmd_num != 7 && owner != Some(appid) {
+            return ReturnCode::ERESERVE;
+        }
+        match cmd_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 /* read_write_bytes */ => self.read_write(arg1, appid),
+            2 /* get chip select */ => {
+                // When in slave mode, the only possible chip select is 0
+                ReturnCode::SuccessWithValue { value: 0 }
+            }
+            3 /* set phase */ => {
+                match arg1 {
+                    0 => self.spi_slave.set_phase(ClockPhase::SampleLeading),
+                    _ => self.spi_slave.set_phase(ClockPhase::SampleTrailing),
+                };
+                ReturnCode::SUCCESS
+            }
+            4 /* get phase */ => {
+                ReturnCode::SuccessWithValue { value: self.spi_slave.get_phase() as usize }
+            }
+            5 /* set polarity */ => {
+                match arg1 {
+                    0 => self.spi_slave.set_polarity(ClockPolarity::IdleLow),
+                    _ => self.spi_slave.set_polarity(ClockPolarity::IdleHigh),
+                };
+                ReturnCode::SUCCESS
+            }
+            6 /* get polarity */ => {
+                ReturnCode::SuccessWithValue { value: self.spi_slave.get_polarity() as usize }
+            }
+            7 /* open */ => match owner {
+                Some(owner) if owner == appid => ReturnCode::EALREADY,
+                Some(_) => ReturnCode::EBUSY,
+                None => self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.opened = true;
+                        self.spi_slave
+                            .configure(ClockPolarity::IdleLow, ClockPhase::SampleLeading);
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into()),
+            },
+            8 /* close */ => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.opened = false;
+                    self.abort();
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+}
+
+impl<S: SpiSlaveDevice> SpiSlaveClient for SpiSlave<'_, S> {
+    fn read_write_done(
+        &self,
+        writebuf: Option<&'static mut [u8]>,
+        readbuf: Option<&'static mut [u8]>,
+        length: usize,
+    ) {
+        // The process that armed the transfer may have closed the driver or
+        // faulted since. Only deliver the data if it still owns the driver.
+        let appid = self.current.take();
+        let owner = self.owner();
+        appid.filter(|appid| owner == Some(*appid)).map(|appid| {
+            self.apps.enter(appid, |app, _| {
+                readbuf.as_ref().map(|src| {
+                    app.app_read.as_mut().map(|dest| {
+                        let len = cmp::min(length, dest.len());
+                        dest.as_mut()[..len].copy_from_slice(&src[..len]);
+                    });
+                });
+                app.callback.map(|mut cb| {
+                    cb.schedule(length, 0, 0);
+                });
+            })
+        });
+        self.kernel_read.put(readbuf);
+        self.kernel_write.put(writebuf);
+    }
+
+    fn chip_selected(&self) {
+        self.owner().map(|owner| {
+            self.apps.enter(owner, |app, _| {
+                app.selected_callback.map(|mut cb| {
+                    cb.schedule(0, 0, 0);
+                });
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{SpiSlave, DRIVER_NUM};
+    use host::chip::HostChip;
+    use host::harness::{
+        command, create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp,
+        TestPlatform,
+    };
+    use host::mock::spi::MockSpiSlave;
+    use host::syscall::{HostApp, Resumption};
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::{Driver, ReturnCode};
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    pub fn spi_slave_is_owned_by_one_process() {
+        let (kernel, processes) = create_kernel();
+        let mock_spi: &'static MockSpiSlave = leak(MockSpiSlave::new());
+        let device = leak(crate::virtual_spi::VirtualSpiSlaveDevice::new(mock_spi));
+        let mut spi_slave = SpiSlave::new(device, create_grant(kernel));
+        spi_slave.config_buffers(Box::leak(Box::new([0; 8])), Box::leak(Box::new([0; 8])));
+        let spi_slave = leak(spi_slave);
+        device.set_client(spi_slave);
+        kernel::hil::spi::SpiSlave::set_client(mock_spi, Some(device));
+
+        let spi_command = |command_num, arg| command(DRIVER_NUM, command_num, arg);
+        let subscribe = |subscribe_num, callback_ptr| Syscall::SUBSCRIBE {
+            driver_number: DRIVER_NUM,
+            subdriver_number: subscribe_num,
+            callback_ptr: callback_ptr as *mut (),
+            appdata: 0,
+        };
+        let allow = |allow_num, offset, len| Syscall::ALLOW {
+            driver_number: DRIVER_NUM,
+            subdriver_number: allow_num,
+            allow_address: offset as *mut u8,
+            allow_size: len,
+        };
+        let owner = leak(ScriptedApp::new_with_memory(
+            vec![
+                subscribe(0, 0x1000),
+                subscribe(1, 0x2000),
+                allow(0, 16, 4),
+                allow(1, 0, 4),
+                spi_command(1, 4),
+                spi_command(7, 0),
+                spi_command(5, 1),
+                spi_command(1, 16),
+                spi_command(1, 5),
+                spi_command(1, 4),
+                spi_command(1, 4),
+            ],
+            b"pong",
+        ));
+        let other = leak(ScriptedApp::new(vec![
+            spi_command(7, 0),
+            spi_command(1, 4),
+            spi_command(0, 0),
+        ]));
+        let apps: &'static [&'static dyn HostApp] = leak([owner as &dyn HostApp, other]);
+        let chip = leak(HostChip::new(apps, None));
+        let processes = load_apps(
+            kernel,
+            chip,
+            processes,
+            &[("owner", 0), ("other", 1)],
+            FaultResponse::Stop,
+        );
+
+        let platform = TestPlatform::new().driver(DRIVER_NUM, spi_slave);
+        run_until_idle(kernel, &platform, chip);
+
+        assert_eq!(
+            *owner.returns.borrow(),
+            [
+                0,
+                0,
+                0,
+                0,
+                isize::from(ReturnCode::ERESERVE),
+                0,
+                0,
+                isize::from(ReturnCode::ESIZE),
+                isize::from(ReturnCode::EINVAL),
+                0,
+                isize::from(ReturnCode::EBUSY),
+            ]
+        );
+        assert_eq!(
+            *other.returns.borrow(),
+            [
+                isize::from(ReturnCode::EBUSY),
+                isize::from(ReturnCode::ERESERVE),
+                0,
+            ]
+        );
+        assert_eq!(
+            kernel::hil::spi::SpiSlave::get_clock(mock_spi),
+            kernel::hil::spi::ClockPolarity::IdleHigh
+        );
+
+        // The master selects the board and exchanges four bytes.
+        mock_spi.select();
+        let mut sent = [0; 4];
+        assert_eq!(mock_spi.complete(b"ping", &mut sent), Some(&b"pong"[..]));
+        run_until_idle(kernel, &platform, chip);
+
+        let callbacks: Vec<(usize, usize)> = owner
+            .callbacks
+            .borrow()
+            .iter()
+            .map(|callback| match *callback {
+                Resumption::Callback { pc, argument0, .. } => (pc, argument0),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(callbacks, [(0x2000, 0), (0x1000, 4)]);
+        let received = owner.memory(16, 4);
+        assert_eq!(received, b"ping");
+
+        // Closing the driver cancels the armed transfer and returns its buffers.
+        let (owner_process, other_process) = (processes[0].unwrap(), processes[1].unwrap());
+        let owner_id = owner_process.appid();
+        assert_eq!(spi_slave.command(1, 4, 0, owner_id), ReturnCode::SUCCESS);
+        assert_eq!(spi_slave.command(8, 0, 0, owner_id), ReturnCode::SUCCESS);
+        assert_eq!(mock_spi.transfer_len(), None);
+        assert_eq!(spi_slave.command(7, 0, 0, owner_id), ReturnCode::SUCCESS);
+        assert_eq!(spi_slave.command(1, 4, 0, owner_id), ReturnCode::SUCCESS);
+        assert_eq!(mock_spi.transfer_len(), Some(4));
+
+        // The driver is released when its owner faults, and the transfer it armed
+        // is cancelled.
+        owner_process.set_fault_state();
+        assert_eq!(
+            spi_slave.command(7, 0, 0, other_process.appid()),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(mock_spi.transfer_len(), None);
+        assert_eq!(
+            spi_slave.command(1, 4, 0, other_process.appid()),
+            ReturnCode::EINVAL
+        );
+        assert_eq!(
+            kernel::hil::spi::SpiSlave::get_clock(mock_spi),
+            kernel::hil::spi::ClockPolarity::IdleLow
+        );
+    }
+}
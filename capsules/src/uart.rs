@@ -0,0 +1,840 @@
+//! Provides userspace with raw access to secondary serial ports.
+//!
+//! Unlike the console, which multiplexes every process onto one UART, this
+//! driver hands out whole ports. A process must open a port before using it,
+//! and while it holds the port no other process can configure it, write to it
+//! or read from it. This makes the driver suitable for talking to devices such
+//! as GPS receivers or modems, where interleaved traffic from several
+//! processes would be meaningless.
+//!
+//! Ownership is stored in the owning process's grant, so a port is released
+//! automatically when its owner exits, faults or is restarted. Opening a port
+//! resets it to 115200 baud, 8N1, without flow control.
+//!
+//! Reads use the `ReceiveAdvanced` interface: a read completes once the
+//! requested number of bytes has arrived or, if an inter-byte timeout is set,
+//! once the line has been idle for that many bit periods after the first
+//! byte. This lets a process stream data of unknown length without polling.
+//! A process can also bound how long a read may take overall, after which the
+//! read is aborted and completes with the bytes received so far.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let port = static_init!(
+//!     capsules::uart::UartPort<'static>,
+//!     capsules::uart::UartPort::new(
+//!         &sam4l::usart::USART3,
+//!         &mut capsules::uart::TX_BUF,
+//!         &mut capsules::uart::RX_BUF
+//!     )
+//! );
+//! let ports = static_init!([&'static capsules::uart::UartPort<'static>; 1], [port]);
+//! let uart_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let uart = static_init!(
+//!     capsules::uart::UartDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::uart::UartDriver::new(
+//!         ports,
+//!         uart_alarm,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! uart_alarm.set_client(uart);
+//! uart.initialize_ports();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::uart;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Uart as usize;
+
+/// Maximum number of ports a single driver instance exposes.
+pub const MAX_PORTS: usize = 4;
+
+pub static mut TX_BUF: [u8; 64] = [0; 64];
+pub static mut RX_BUF: [u8; 64] = [0; 64];
+
+const DEFAULT_PARAMETERS: uart::Parameters = uart::Parameters {
+    baud_rate: 115200,
+    width: uart::Width::Eight,
+    parity: uart::Parity::None,
+    stop_bits: uart::StopBits::One,
+    hw_flow_control: false,
+};
+
+/// A process's buffers and transfer state for one port.
+#[derive(Default)]
+struct PortState {
+    write_buffer: Option<AppSlice<Shared, u8>>,
+    write_len: usize,
+    write_remaining: usize,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+}
+
+#[derive(Default)]
+pub struct App {
+    /// Bitmask of the ports this process has open.
+    opened: usize,
+    write_callback: Option<Callback>,
+    read_callback: Option<Callback>,
+    ports: [PortState; MAX_PORTS],
+}
+
+/// Receives the completions of a port's transfers.
+trait PortClient {
+    fn transmitted(&self, index: usize, rcode: ReturnCode);
+    fn received(&self, index: usize, data: &[u8], rcode: ReturnCode);
+}
+
+/// One serial port and the kernel buffers used to drive it.
+pub struct UartPort<'a> {
+    uart: &'a dyn uart::UartAdvanced<'a>,
+    parameters: Cell<uart::Parameters>,
+    /// Inter-byte receive timeout in bit periods, or 0 to wait for the whole
+    /// read.
+    interbyte_timeout: Cell<u8>,
+    /// Overall receive timeout in alarm tics, or 0 for none.
+    receive_timeout: Cell<u32>,
+    /// When the read in progress started and how long it may take, in alarm
+    /// tics, if it has a timeout.
+    receive_deadline: OptionalCell<(u32, u32)>,
+    /// Whether the read in progress was aborted because it timed out.
+    receive_timed_out: Cell<bool>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    index: Cell<usize>,
+    driver: OptionalCell<&'a dyn PortClient>,
+}
+
+impl<'a> UartPort<'a> {
+    pub fn new(
+        uart: &'a dyn uart::UartAdvanced<'a>,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+    ) -> UartPort<'a> {
+        UartPort {
+            uart: uart,
+            parameters: Cell::new(DEFAULT_PARAMETERS),
+            interbyte_timeout: Cell::new(0),
+            receive_timeout: Cell::new(0),
+            receive_deadline: OptionalCell::empty(),
+            receive_timed_out: Cell::new(false),
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            index: Cell::new(0),
+            driver: OptionalCell::empty(),
+        }
+    }
+
+    /// Applies `parameters` to the hardware, keeping the previous
+    /// configuration if the hardware rejects them.
+    fn configure(&self, parameters: uart::Parameters) -> ReturnCode {
+        let rcode = self.uart.configure(parameters);
+        if rcode == ReturnCode::SUCCESS {
+            self.parameters.set(parameters);
+        }
+        rcode
+    }
+
+    /// Stops any transfers left over from a previous owner.
+    fn abort(&self) {
+        self.receive_deadline.clear();
+        if self.tx_buffer.is_none() {
+            self.uart.transmit_abort();
+        }
+        if self.rx_buffer.is_none() {
+            self.uart.receive_abort();
+        }
+    }
+}
+
+impl uart::TransmitClient for UartPort<'_> {
+    fn transmitted_buffer(&self, buffer: &'static mut [u8], _tx_len: usize, rcode: ReturnCode) {
+        self.tx_buffer.replace(buffer);
+        self.driver
+            .map(|driver| driver.transmitted(self.index.get(), rcode));
+    }
+}
+
+impl uart::ReceiveClient for UartPort<'_> {
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        rcode: ReturnCode,
+        error: uart::Error,
+    ) {
+        self.receive_deadline.clear();
+        let rcode = match error {
+            // A read that timed out completes normally with what was received.
+            uart::Error::Aborted if self.receive_timed_out.get() => ReturnCode::SUCCESS,
+            uart::Error::None | uart::Error::Aborted => rcode,
+            _ => ReturnCode::FAIL,
+        };
+        self.receive_timed_out.set(false);
+        self.driver
+            .map(|driver| driver.received(self.index.get(), &buffer[..rx_len], rcode));
+        self.rx_buffer.replace(buffer);
+    }
+}
+
+pub struct UartDriver<'a, A: Alarm<'a>> {
+    ports: &'a [&'a UartPort<'a>],
+    alarm: &'a A,
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm<'a>> UartDriver<'a, A> {
+    pub fn new(
+        ports: &'a [&'a UartPort<'a>],
+        alarm: &'a A,
+        grant: Grant<App>,
+    ) -> UartDriver<'a, A> {
+        UartDriver {
+            ports: ports,
+            alarm: alarm,
+            apps: grant,
+        }
+    }
+
+    /// Registers each port as the client of its UART and routes its
+    /// completions back to this driver. Must be called once before any
+    /// process uses the driver.
+    pub fn initialize_ports(&'a self)
+    where
+        A: 'a,
+    {
+        for (index, port) in self.ports.iter().take(MAX_PORTS).enumerate() {
+            port.index.set(index);
+            port.driver.set(self);
+            port.uart.set_transmit_client(*port);
+            port.uart.set_receive_client(*port);
+        }
+    }
+
+    fn num_ports(&self) -> usize {
+        cmp::min(self.ports.len(), MAX_PORTS)
+    }
+
+    /// Returns the process that has `port` open, if any.
+    fn owner(&self, port: usize) -> Option<AppId> {
+        self.apps.iter().find_map(|app| {
+            app.enter(|app, _| {
+                if app.opened & (1 << port) != 0 {
+                    Some(app.appid())
+                } else {
+                    None
+                }
+            })
+        })
+    }
+
+    fn open(&self, index: usize, appid: AppId) -> ReturnCode {
+        match self.owner(index) {
+            Some(owner) if owner == appid => return ReturnCode::EALREADY,
+            Some(_) => return ReturnCode::EBUSY,
+            None => {}
+        }
+        let port = self.ports[index];
+        port.abort();
+        port.interbyte_timeout.set(0);
+        port.receive_timeout.set(0);
+        self.arm_alarm();
+        let rcode = port.configure(DEFAULT_PARAMETERS);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                app.opened |= 1 << index;
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Sets the framing from the bitfield passed to command 4.
+    fn set_framing(&self, port: &UartPort, framing: usize) -> ReturnCode {
+        let parity = match framing & 0b11 {
+            0 => uart::Parity::None,
+            1 => uart::Parity::Odd,
+            2 => uart::Parity::Even,
+            _ => return ReturnCode::EINVAL,
+        };
+        let stop_bits = if framing & (1 << 2) != 0 {
+            uart::StopBits::Two
+        } else {
+            uart::StopBits::One
+        };
+        let width = match (framing >> 4) & 0xf {
+            0 | 8 => uart::Width::Eight,
+            7 => uart::Width::Seven,
+            6 => uart::Width::Six,
+            _ => return ReturnCode::EINVAL,
+        };
+        port.configure(uart::Parameters {
+            width: width,
+            parity: parity,
+            stop_bits: stop_bits,
+            hw_flow_control: framing & (1 << 3) != 0,
+            ..port.parameters.get()
+        })
+    }
+
+    /// Copies the next chunk of the process's write buffer into the port's
+    /// transmit buffer and starts sending it.
+    fn send(&self, port: &UartPort, state: &mut PortState) -> ReturnCode {
+        let offset = state.write_len - state.write_remaining;
+        let remaining = state.write_remaining;
+        let slice = match state.write_buffer {
+            Some(ref slice) => slice,
+            None => return ReturnCode::ERESERVE,
+        };
+        let (rcode, sent) = port
+            .tx_buffer
+            .take()
+            .map_or((ReturnCode::EBUSY, 0), |buffer| {
+                let len = cmp::min(
+                    cmp::min(remaining, buffer.len()),
+                    slice.len().saturating_sub(offset),
+                );
+                if len == 0 {
+                    port.tx_buffer.replace(buffer);
+                    return (ReturnCode::ESIZE, 0);
+                }
+                buffer[..len].copy_from_slice(&slice.as_ref()[offset..offset + len]);
+                let (rcode, buffer) = port.uart.transmit_buffer(buffer, len);
+                if let Some(buffer) = buffer {
+                    port.tx_buffer.replace(buffer);
+                }
+                (rcode, len)
+            });
+        if rcode == ReturnCode::SUCCESS {
+            state.write_remaining -= sent;
+        }
+        rcode
+    }
+
+    fn write(&self, port: &UartPort, state: &mut PortState, len: usize) -> ReturnCode {
+        if port.tx_buffer.is_none() {
+            return ReturnCode::EBUSY;
+        }
+        let available = state.write_buffer.as_ref().map_or(0, |slice| slice.len());
+        if available == 0 {
+            return ReturnCode::EINVAL;
+        }
+        state.write_len = cmp::min(len, available);
+        state.write_remaining = state.write_len;
+        self.send(port, state)
+    }
+
+    fn read(&self, port: &UartPort, state: &mut PortState, len: usize) -> ReturnCode {
+        let available = state.read_buffer.as_ref().map_or(0, |slice| slice.len());
+        if available == 0 {
+            return ReturnCode::EINVAL;
+        }
+        port.rx_buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let len = cmp::min(cmp::min(len, available), buffer.len());
+            let (rcode, buffer) = match port.interbyte_timeout.get() {
+                0 => port.uart.receive_buffer(buffer, len),
+                timeout => port.uart.receive_automatic(buffer, len, timeout),
+            };
+            if let Some(buffer) = buffer {
+                port.rx_buffer.replace(buffer);
+            }
+            if rcode == ReturnCode::SUCCESS && port.receive_timeout.get() != 0 {
+                port.receive_deadline
+                    .set((self.alarm.now(), port.receive_timeout.get()));
+                self.arm_alarm();
+            }
+            rcode
+        })
+    }
+
+    /// Sets the overall receive timeout of `port` to `timeout_ms`
+    /// milliseconds.
+    fn set_receive_timeout(&self, port: &UartPort, timeout_ms: usize) -> ReturnCode {
+        let tics = (timeout_ms as u64)
+            .checked_mul(<A::Frequency>::frequency() as u64)
+            .map(|tics| tics / 1000);
+        match tics {
+            // Keep deadlines within half the range of the alarm, so that they
+            // can be told apart from deadlines that already passed.
+            Some(tics) if tics <= (u32::max_value() / 2) as u64 => {
+                port.receive_timeout.set(tics as u32);
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EINVAL,
+        }
+    }
+
+    /// Sets the alarm for the earliest receive deadline, or disables it if
+    /// no read has one.
+    fn arm_alarm(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .ports
+            .iter()
+            .filter_map(|port| {
+                port.receive_deadline
+                    .map(|&mut (start, timeout)| timeout.saturating_sub(now.wrapping_sub(start)))
+            })
+            .min();
+        match next {
+            Some(remaining) => self.alarm.set_alarm(now.wrapping_add(remaining)),
+            None => {
+                self.alarm.disable();
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> PortClient for UartDriver<'a, A> {
+    /// Continues or completes a write after the port finished transmitting a
+    /// chunk.
+    fn transmitted(&self, index: usize, rcode: ReturnCode) {
+        let port = self.ports[index];
+        self.owner(index).map(|appid| {
+            self.apps.enter(appid, |app, _| {
+                let state = &mut app.ports[index];
+                let mut rcode = rcode;
+                if rcode == ReturnCode::SUCCESS && state.write_remaining > 0 {
+                    rcode = self.send(port, state);
+                    if rcode == ReturnCode::SUCCESS {
+                        return;
+                    }
+                }
+                let written = state.write_len - state.write_remaining;
+                state.write_len = 0;
+                state.write_remaining = 0;
+                app.write_callback.map(|mut cb| {
+                    cb.schedule(index, written, isize::from(rcode) as usize);
+                });
+            })
+        });
+    }
+
+    /// Delivers received bytes to the port's owner.
+    fn received(&self, index: usize, data: &[u8], rcode: ReturnCode) {
+        self.owner(index).map(|appid| {
+            self.apps.enter(appid, |app, _| {
+                let len = app.ports[index].read_buffer.as_mut().map_or(0, |slice| {
+                    let len = cmp::min(slice.len(), data.len());
+                    slice.as_mut()[..len].copy_from_slice(&data[..len]);
+                    len
+                });
+                app.read_callback.map(|mut cb| {
+                    cb.schedule(index, len, isize::from(rcode) as usize);
+                });
+            })
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for UartDriver<'a, A> {
+    /// Aborts the reads that timed out. Their read callbacks report the bytes
+    /// received so far once the aborts complete.
+    fn fired(&self) {
+        let now = self.alarm.now();
+        for port in self.ports.iter() {
+            let expired = port
+                .receive_deadline
+                .map_or(false, |&mut (start, timeout)| {
+                    now.wrapping_sub(start) >= timeout
+                });
+            if expired {
+                port.receive_deadline.clear();
+                port.receive_timed_out.set(true);
+                port.uart.receive_abort();
+            }
+        }
+        self.arm_alarm();
+    }
+}
+
+impl<'a, A: Alarm<'a>> Driver for UartDriver<'a, A> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `2 * n`: Buffer to write to port `n`.
+    /// - `2 * n + 1`: Buffer to read from port `n` into.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        let index = allow_num / 2;
+        if index >= self.num_ports() {
+            return ReturnCode::ENOSUPPORT;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                let state = &mut app.ports[index];
+                if allow_num % 2 == 0 {
+                    state.write_buffer = slice;
+                } else {
+                    state.read_buffer = slice;
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Write completed. Called with the port, the number of bytes
+    ///        written and a return code.
+    /// - `1`: Read completed. Called with the port, the number of bytes
+    ///        received and a return code.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.write_callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            1 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.read_callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Open, configure and use ports. `arg1` is always the port number. All
+    /// commands other than 0 and 1 return ERESERVE unless the calling process
+    /// has the port open.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check. Returns the number of ports.
+    /// - `1`: Open the port for exclusive use. Returns EBUSY if another
+    ///        process has it open.
+    /// - `2`: Close the port, aborting any transfers.
+    /// - `3`: Set the baud rate to `arg2`.
+    /// - `4`: Set the framing from `arg2`: bits 0-1 are the parity (none,
+    ///        odd, even), bit 2 selects two stop bits, bit 3 enables hardware
+    ///        flow control and bits 4-7 are the data width (6, 7 or 8, with 0
+    ///        meaning 8).
+    /// - `5`: Set the receive inter-byte timeout to `arg2` bit periods, or
+    ///        0 to wait for every requested byte.
+    /// - `6`: Write up to `arg2` bytes from the write buffer.
+    /// - `7`: Read up to `arg2` bytes into the read buffer.
+    /// - `8`: Abort the write in progress.
+    /// - `9`: Abort the read in progress. The read callback reports what was
+    ///        received so far.
+    /// - `10`: Set the overall receive timeout to `arg2` milliseconds, or 0
+    ///         to wait for every requested byte. A read that times out
+    ///         completes with the bytes received so far.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SuccessWithValue {
+                value: self.num_ports(),
+            };
+        }
+        let index = arg1;
+        if index >= self.num_ports() {
+            return ReturnCode::EINVAL;
+        }
+        if command_num == 1 {
+            return self.open(index, appid);
+        }
+        let port = self.ports[index];
+        let rcode = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.opened & (1 << index) == 0 {
+                    return ReturnCode::ERESERVE;
+                }
+                match command_num {
+                    2 => {
+                        app.opened &= !(1 << index);
+                        app.ports[index].write_len = 0;
+                        app.ports[index].write_remaining = 0;
+                        ReturnCode::SUCCESS
+                    }
+                    3 => port.configure(uart::Parameters {
+                        baud_rate: arg2 as u32,
+                        ..port.parameters.get()
+                    }),
+                    4 => self.set_framing(port, arg2),
+                    5 => {
+                        if arg2 > u8::max_value() as usize {
+                            return ReturnCode::EINVAL;
+                        }
+                        port.interbyte_timeout.set(arg2 as u8);
+                        ReturnCode::SUCCESS
+                    }
+                    6 => self.write(port, &mut app.ports[index], arg2),
+                    7 => self.read(port, &mut app.ports[index], arg2),
+                    8 | 9 => ReturnCode::SUCCESS,
+                    10 => self.set_receive_timeout(port, arg2),
+                    _ => ReturnCode::ENOSUPPORT,
+                }
+            })
+            .unwrap_or_else(|err| err.into());
+
+        // Some UARTs complete an abort synchronously, and the completion
+        // enters the owner's grant, so only abort once the grant was left.
+        if rcode == ReturnCode::SUCCESS {
+            match command_num {
+                2 => port.abort(),
+                8 => {
+                    port.uart.transmit_abort();
+                }
+                9 => {
+                    port.uart.receive_abort();
+                }
+                _ => {}
+            }
+        }
+        rcode
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{UartDriver, UartPort, DRIVER_NUM};
+    use host::chip::HostChip;
+    use host::harness::{
+        create_grant, create_kernel, leak, load_apps, run_until_idle, ScriptedApp, TestPlatform,
+    };
+    use host::mock::alarm::MockAlarm;
+    use host::mock::uart::MockUart;
+    use host::syscall::{HostApp, Resumption};
+    use kernel::hil::time::Alarm;
+    use kernel::procs::FaultResponse;
+    use kernel::syscall::Syscall;
+    use kernel::{Driver, ReturnCode};
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    pub fn uart_ports_are_owned_by_one_process() {
+        let (kernel, processes) = create_kernel();
+        let mock_uart: &'static MockUart = leak(MockUart::new());
+        let port = leak(UartPort::new(
+            mock_uart,
+            Box::leak(Box::new([0; 4])),
+            Box::leak(Box::new([0; 16])),
+        ));
+        let ports = leak([port]);
+        let mock_alarm: &'static MockAlarm = leak(MockAlarm::new());
+        let uart = leak(UartDriver::new(ports, mock_alarm, create_grant(kernel)));
+        mock_alarm.set_client(uart);
+        uart.initialize_ports();
+
+        let uart_command = |command_num, port, arg| Syscall::COMMAND {
+            driver_number: DRIVER_NUM,
+            subdriver_number: command_num,
+            arg0: port,
+            arg1: arg,
+        };
+        let subscribe = |subscribe_num| Syscall::SUBSCRIBE {
+            driver_number: DRIVER_NUM,
+            subdriver_number: subscribe_num,
+            callback_ptr: 0x1000 as *mut (),
+            appdata: 0,
+        };
+        let allow = |allow_num, offset, len| Syscall::ALLOW {
+            driver_number: DRIVER_NUM,
+            subdriver_number: allow_num,
+            allow_address: offset as *mut u8,
+            allow_size: len,
+        };
+        // Writes "hello, world" in chunks of the 4-byte transmit buffer, then
+        // reads with an inter-byte timeout.
+        let owner = leak(ScriptedApp::new_with_memory(
+            vec![
+                subscribe(0),
+                subscribe(1),
+                allow(0, 0, 12),
+                allow(1, 16, 8),
+                uart_command(1, 0, 0),
+                uart_command(3, 0, 9600),
+                uart_command(4, 0, 0b0111_0110),
+                uart_command(5, 0, 20),
+                uart_command(6, 0, 100),
+                uart_command(7, 0, 8),
+            ],
+            b"hello, world",
+        ));
+        let other = leak(ScriptedApp::new(vec![
+            uart_command(1, 0, 0),
+            uart_command(3, 0, 115200),
+            uart_command(1, 1, 0),
+        ]));
+        let apps: &'static [&'static dyn HostApp] = leak([owner as &dyn HostApp, other]);
+        let chip = leak(HostChip::new(apps, None));
+        let processes = load_apps(
+            kernel,
+            chip,
+            processes,
+            &[("owner", 0), ("other", 1)],
+            FaultResponse::Stop,
+        );
+
+        let platform = TestPlatform::new().driver(DRIVER_NUM, uart);
+        run_until_idle(kernel, &platform, chip);
+
+        assert_eq!(*owner.returns.borrow(), [0; 10]);
+        assert_eq!(
+            *other.returns.borrow(),
+            [
+                isize::from(ReturnCode::EBUSY),
+                isize::from(ReturnCode::ERESERVE),
+                isize::from(ReturnCode::EINVAL),
+            ]
+        );
+        let parameters = mock_uart.parameters().unwrap();
+        assert_eq!(parameters.baud_rate, 9600);
+        assert_eq!(parameters.width, kernel::hil::uart::Width::Seven);
+        assert_eq!(parameters.parity, kernel::hil::uart::Parity::Even);
+        assert_eq!(parameters.stop_bits, kernel::hil::uart::StopBits::Two);
+        assert!(!parameters.hw_flow_control);
+
+        let mut written = Vec::new();
+        while mock_uart.is_transmitting() {
+            mock_uart.with_transmitted_data(|data| written.extend_from_slice(data));
+            mock_uart.complete_transmit(ReturnCode::SUCCESS);
+        }
+        assert_eq!(written, b"hello, world");
+        assert_eq!(mock_uart.receive_len(), Some(8));
+        assert!(mock_uart.complete_receive(
+            b"ok",
+            ReturnCode::SUCCESS,
+            kernel::hil::uart::Error::None
+        ));
+        run_until_idle(kernel, &platform, chip);
+
+        let callbacks: Vec<(usize, usize, usize)> = owner
+            .callbacks
+            .borrow()
+            .iter()
+            .map(|callback| match *callback {
+                Resumption::Callback {
+                    argument0,
+                    argument1,
+                    argument2,
+                    ..
+                } => (argument0, argument1, argument2),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(callbacks, [(0, 12, 0), (0, 2, 0)]);
+        let received = owner.memory(16, 2);
+        assert_eq!(received, b"ok");
+
+        // The port is released, and reset, when its owner faults.
+        let (owner_process, other_process) = (processes[0].unwrap(), processes[1].unwrap());
+        owner_process.set_fault_state();
+        assert_eq!(
+            uart.command(1, 0, 0, other_process.appid()),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(mock_uart.parameters().unwrap().baud_rate, 115200);
+    }
+
+    #[test]
+    pub fn uart_reads_time_out() {
+        let (kernel, processes) = create_kernel();
+        let mock_uart: &'static MockUart = leak(MockUart::new());
+        let port = leak(UartPort::new(
+            mock_uart,
+            Box::leak(Box::new([0; 4])),
+            Box::leak(Box::new([0; 16])),
+        ));
+        let ports = leak([port]);
+        let mock_alarm: &'static MockAlarm = leak(MockAlarm::new());
+        let uart = leak(UartDriver::new(ports, mock_alarm, create_grant(kernel)));
+        mock_alarm.set_client(uart);
+        uart.initialize_ports();
+
+        let uart_command = |command_num, arg| Syscall::COMMAND {
+            driver_number: DRIVER_NUM,
+            subdriver_number: command_num,
+            arg0: 0,
+            arg1: arg,
+        };
+        // Reads with a timeout of 10 ms, after rejecting inter-byte and overall
+        // timeouts that are too large.
+        let app = leak(ScriptedApp::new(vec![
+            Syscall::SUBSCRIBE {
+                driver_number: DRIVER_NUM,
+                subdriver_number: 1,
+                callback_ptr: 0x1000 as *mut (),
+                appdata: 0,
+            },
+            Syscall::ALLOW {
+                driver_number: DRIVER_NUM,
+                subdriver_number: 1,
+                allow_address: 0 as *mut u8,
+                allow_size: 8,
+            },
+            uart_command(1, 0),
+            uart_command(5, 256),
+            uart_command(10, usize::max_value()),
+            uart_command(10, 10),
+            uart_command(7, 8),
+        ]));
+        let apps: &'static [&'static dyn HostApp] = leak([app as &dyn HostApp]);
+        let chip = leak(HostChip::new(apps, None));
+        load_apps(kernel, chip, processes, &[("app", 0)], FaultResponse::Panic);
+
+        let platform = TestPlatform::new().driver(DRIVER_NUM, uart);
+        run_until_idle(kernel, &platform, chip);
+
+        let einval = isize::from(ReturnCode::EINVAL);
+        assert_eq!(*app.returns.borrow(), [0, 0, 0, einval, einval, 0, 0]);
+        assert_eq!(mock_uart.receive_len(), Some(8));
+
+        // The read is aborted once 10 ms have passed, and completes with what was
+        // received until then.
+        assert!(!mock_alarm.advance(326));
+        assert!(mock_alarm.advance(1));
+        assert!(mock_uart.complete_receive(
+            b"abc",
+            ReturnCode::SUCCESS,
+            kernel::hil::uart::Error::None
+        ));
+        run_until_idle(kernel, &platform, chip);
+        match app.callbacks.borrow()[..] {
+            [Resumption::Callback {
+                argument0: 0,
+                argument1: 3,
+                argument2: 0,
+                ..
+            }] => {}
+            ref callbacks => panic!("unexpected callbacks {:?}", callbacks),
+        }
+        let received = app.memory(0, 3);
+        assert_eq!(received, b"abc");
+        assert!(!mock_alarm.is_enabled());
+    }
+}
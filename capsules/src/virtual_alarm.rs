@@ -4,27 +4,29 @@
 use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::common::{List, ListLink, ListNode};
-use kernel::hil::time::{self, Alarm, Time};
+use kernel::hil::time::{self, Alarm, Ticks, Time};
 
-pub struct VirtualMuxAlarm<'a, A: Alarm<'a>> {
-    mux: &'a MuxAlarm<'a, A>,
-    when: Cell<u32>,
+pub struct VirtualMuxAlarm<'a, A: Alarm<'a, W>, W: Ticks = u32> {
+    mux: &'a MuxAlarm<'a, A, W>,
+    when: Cell<W>,
     armed: Cell<bool>,
-    next: ListLink<'a, VirtualMuxAlarm<'a, A>>,
+    next: ListLink<'a, VirtualMuxAlarm<'a, A, W>>,
     client: OptionalCell<&'a dyn time::AlarmClient>,
 }
 
-impl<'a, A: Alarm<'a>> ListNode<'a, VirtualMuxAlarm<'a, A>> for VirtualMuxAlarm<'a, A> {
-    fn next(&self) -> &'a ListLink<VirtualMuxAlarm<'a, A>> {
+impl<'a, A: Alarm<'a, W>, W: Ticks> ListNode<'a, VirtualMuxAlarm<'a, A, W>>
+    for VirtualMuxAlarm<'a, A, W>
+{
+    fn next(&self) -> &'a ListLink<VirtualMuxAlarm<'a, A, W>> {
         &self.next
     }
 }
 
-impl<'a, A: Alarm<'a>> VirtualMuxAlarm<'a, A> {
-    pub fn new(mux_alarm: &'a MuxAlarm<'a, A>) -> VirtualMuxAlarm<'a, A> {
+impl<'a, A: Alarm<'a, W>, W: Ticks> VirtualMuxAlarm<'a, A, W> {
+    pub fn new(mux_alarm: &'a MuxAlarm<'a, A, W>) -> VirtualMuxAlarm<'a, A, W> {
         VirtualMuxAlarm {
             mux: mux_alarm,
-            when: Cell::new(0),
+            when: Cell::new(W::default()),
             armed: Cell::new(false),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
@@ -32,22 +34,22 @@ impl<'a, A: Alarm<'a>> VirtualMuxAlarm<'a, A> {
     }
 }
 
-impl<'a, A: Alarm<'a>> Time for VirtualMuxAlarm<'a, A> {
+impl<'a, A: Alarm<'a, W>, W: Ticks> Time<W> for VirtualMuxAlarm<'a, A, W> {
     type Frequency = A::Frequency;
 
-    fn max_tics(&self) -> u32 {
+    fn max_tics(&self) -> W {
         self.mux.alarm.max_tics()
     }
 
-    fn now(&self) -> u32 {
+    fn now(&self) -> W {
         self.mux.alarm.now()
     }
 }
 
-impl<'a, A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
+impl<'a, A: Alarm<'a, W>, W: Ticks> Alarm<'a, W> for VirtualMuxAlarm<'a, A, W> {
     fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
         self.mux.virtual_alarms.push_head(self);
-        self.when.set(0);
+        self.when.set(W::default());
         self.armed.set(false);
         self.client.set(client);
     }
@@ -73,7 +75,7 @@ impl<'a, A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
         self.armed.get()
     }
 
-    fn set_alarm(&self, when: u32) {
+    fn set_alarm(&self, when: W) {
         let enabled = self.mux.enabled.get();
 
         if !self.armed.get() {
@@ -85,7 +87,7 @@ impl<'a, A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
             let cur_alarm = self.mux.alarm.get_alarm();
             let now = self.now();
 
-            if cur_alarm.wrapping_sub(now) > when.wrapping_sub(now) {
+            if self.tics_between(now, cur_alarm) > self.tics_between(now, when) {
                 self.mux.prev.set(self.mux.alarm.now());
                 self.mux.alarm.set_alarm(when);
             }
@@ -97,12 +99,12 @@ impl<'a, A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
         self.when.set(when);
     }
 
-    fn get_alarm(&self) -> u32 {
+    fn get_alarm(&self) -> W {
         self.when.get()
     }
 }
 
-impl<'a, A: Alarm<'a>> time::AlarmClient for VirtualMuxAlarm<'a, A> {
+impl<'a, A: Alarm<'a, W>, W: Ticks> time::AlarmClient for VirtualMuxAlarm<'a, A, W> {
     fn fired(&self) {
         self.client.map(|client| client.fired());
     }
@@ -110,31 +112,37 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for VirtualMuxAlarm<'a, A> {
 
 // MuxAlarm
 
-pub struct MuxAlarm<'a, A: Alarm<'a>> {
-    virtual_alarms: List<'a, VirtualMuxAlarm<'a, A>>,
+/// Multiplexes a hardware alarm among several `VirtualMuxAlarm`s.
+///
+/// `W` is the type of the alarm's clock values. Clocks narrower than `W`,
+/// such as 24-bit counters, are handled by wrapping around at the alarm's
+/// `max_tics`.
+pub struct MuxAlarm<'a, A: Alarm<'a, W>, W: Ticks = u32> {
+    virtual_alarms: List<'a, VirtualMuxAlarm<'a, A, W>>,
     enabled: Cell<usize>,
-    prev: Cell<u32>,
+    prev: Cell<W>,
     alarm: &'a A,
 }
 
-impl<'a, A: Alarm<'a>> MuxAlarm<'a, A> {
-    pub const fn new(alarm: &'a A) -> MuxAlarm<'a, A> {
+impl<'a, A: Alarm<'a, W>, W: Ticks> MuxAlarm<'a, A, W> {
+    pub fn new(alarm: &'a A) -> MuxAlarm<'a, A, W> {
         MuxAlarm {
             virtual_alarms: List::new(),
             enabled: Cell::new(0),
-            prev: Cell::new(0),
+            prev: Cell::new(W::default()),
             alarm: alarm,
         }
     }
 }
 
-fn has_expired(alarm: u32, now: u32, prev: u32) -> bool {
-    now.wrapping_sub(prev) >= alarm.wrapping_sub(prev)
+fn has_expired<W: Ticks>(alarm: W, now: W, prev: W, max_tics: W) -> bool {
+    now.wrapping_sub(prev) & max_tics >= alarm.wrapping_sub(prev) & max_tics
 }
 
-impl<'a, A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
+impl<'a, A: Alarm<'a, W>, W: Ticks> time::AlarmClient for MuxAlarm<'a, A, W> {
     fn fired(&self) {
         let now = self.alarm.now();
+        let max_tics = self.alarm.max_tics();
 
         // Capture this before the loop because it can change while checking
         // each alarm. If a timer fires, it can immediately set a new timer
@@ -146,7 +154,7 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
         // so a repeating client will set it again in the fired() callback.
         self.virtual_alarms
             .iter()
-            .filter(|cur| cur.armed.get() && has_expired(cur.when.get(), now, prev))
+            .filter(|cur| cur.armed.get() && has_expired(cur.when.get(), now, prev, max_tics))
             .for_each(|cur| {
                 cur.armed.set(false);
                 self.enabled.set(self.enabled.get() - 1);
@@ -160,13 +168,13 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
             .virtual_alarms
             .iter()
             .filter(|cur| cur.armed.get())
-            .min_by_key(|cur| cur.when.get().wrapping_sub(now));
+            .min_by_key(|cur| self.alarm.tics_between(now, cur.when.get()));
 
         self.prev.set(now);
         // If there is an alarm to fire, set the underlying alarm to it
         if let Some(valrm) = next {
             self.alarm.set_alarm(valrm.when.get());
-            if has_expired(valrm.when.get(), self.alarm.now(), prev) {
+            if has_expired(valrm.when.get(), self.alarm.now(), prev, max_tics) {
                 self.fired();
             }
         } else {
@@ -174,3 +182,119 @@ impl<'a, A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{MuxAlarm, VirtualMuxAlarm};
+    use core::cell::Cell;
+    use host::mock::alarm::MockAlarm;
+    use kernel::hil::time::{Alarm, AlarmClient, Freq32KHz, Time};
+
+    struct FiredCounter(Cell<usize>);
+
+    impl AlarmClient for FiredCounter {
+        fn fired(&self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    pub fn virtual_alarms_fire_in_order() {
+        let alarm: MockAlarm = MockAlarm::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_client(&mux);
+
+        let (first, second) = (FiredCounter(Cell::new(0)), FiredCounter(Cell::new(0)));
+        let first_alarm = VirtualMuxAlarm::new(&mux);
+        let second_alarm = VirtualMuxAlarm::new(&mux);
+        first_alarm.set_client(&first);
+        second_alarm.set_client(&second);
+
+        first_alarm.set_alarm(100);
+        second_alarm.set_alarm(50);
+        assert_eq!(alarm.get_alarm(), 50);
+
+        alarm.advance(60);
+        assert_eq!((first.0.get(), second.0.get()), (0, 1));
+        assert_eq!(alarm.get_alarm(), 100);
+
+        alarm.advance(50);
+        assert_eq!((first.0.get(), second.0.get()), (1, 1));
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    pub fn virtual_alarm_across_counter_wrap() {
+        let alarm: MockAlarm = MockAlarm::new();
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_client(&mux);
+        alarm.set_now(u32::max_value() - 10);
+
+        let counter = FiredCounter(Cell::new(0));
+        let virtual_alarm = VirtualMuxAlarm::new(&mux);
+        virtual_alarm.set_client(&counter);
+        virtual_alarm.set_alarm(virtual_alarm.now().wrapping_add(20));
+
+        alarm.advance(15);
+        assert_eq!(counter.0.get(), 0);
+        alarm.advance(5);
+        assert_eq!(counter.0.get(), 1);
+    }
+
+    #[test]
+    pub fn virtual_alarms_on_24bit_counter() {
+        let max_tics = (1 << 24) - 1;
+        let alarm: MockAlarm = MockAlarm::new_with_max_tics(max_tics);
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_client(&mux);
+        alarm.set_now(max_tics - 10);
+
+        let (first, second) = (FiredCounter(Cell::new(0)), FiredCounter(Cell::new(0)));
+        let first_alarm = VirtualMuxAlarm::new(&mux);
+        let second_alarm = VirtualMuxAlarm::new(&mux);
+        first_alarm.set_client(&first);
+        second_alarm.set_client(&second);
+
+        // The second alarm is after the counter wraps, so it is sooner than
+        // the first even though its value is smaller.
+        first_alarm.set_alarm(max_tics - 2);
+        second_alarm.set_alarm(first_alarm.add_tics(first_alarm.now(), 20));
+        assert_eq!(second_alarm.get_alarm(), 9);
+        assert_eq!(alarm.get_alarm(), max_tics - 2);
+
+        alarm.advance(10);
+        assert_eq!((first.0.get(), second.0.get()), (1, 0));
+        assert_eq!(alarm.get_alarm(), 9);
+        alarm.advance(9);
+        assert_eq!((first.0.get(), second.0.get()), (1, 0));
+        alarm.advance(1);
+        assert_eq!((first.0.get(), second.0.get()), (1, 1));
+    }
+
+    #[test]
+    pub fn virtual_alarms_on_64bit_counter() {
+        let alarm: MockAlarm<Freq32KHz, u64> = MockAlarm::new_with_max_tics(u64::max_value());
+        let mux = MuxAlarm::new(&alarm);
+        alarm.set_client(&mux);
+        alarm.set_now(1 << 40);
+
+        let (first, second) = (FiredCounter(Cell::new(0)), FiredCounter(Cell::new(0)));
+        let first_alarm = VirtualMuxAlarm::new(&mux);
+        let second_alarm = VirtualMuxAlarm::new(&mux);
+        first_alarm.set_client(&first);
+        second_alarm.set_client(&second);
+
+        // Alarms more than a 32-bit period away are not truncated.
+        first_alarm.set_alarm((1 << 40) + (1 << 33));
+        second_alarm.set_alarm((1 << 40) + 100);
+        assert_eq!(alarm.get_alarm(), (1 << 40) + 100);
+
+        alarm.advance(100);
+        assert_eq!((first.0.get(), second.0.get()), (0, 1));
+        assert_eq!(alarm.get_alarm(), (1 << 40) + (1 << 33));
+        alarm.advance(u32::max_value() as u64);
+        assert_eq!(first.0.get(), 0);
+        alarm.advance(1 << 33);
+        assert_eq!((first.0.get(), second.0.get()), (1, 1));
+    }
+}
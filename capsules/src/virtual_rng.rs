@@ -0,0 +1,214 @@
+//! Virtualize a random number generator.
+//!
+//! `MuxRng` provides shared access to a single `Rng` for multiple users.
+//! `VirtualRngDevice` is one user, and implements `Rng` itself. Randomness
+//! the generator produces is offered to every user that asked for it, one
+//! after the other, until each has as much as it wants.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! let mux_rng = static_init!(
+//!     capsules::virtual_rng::MuxRng<'static>,
+//!     capsules::virtual_rng::MuxRng::new(entropy_to_random)
+//! );
+//! entropy_to_random.set_client(mux_rng);
+//! let rng_user = static_init!(
+//!     capsules::virtual_rng::VirtualRngDevice<'static>,
+//!     capsules::virtual_rng::VirtualRngDevice::new(mux_rng)
+//! );
+//! rng_user.add_to_mux();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::rng::{self, Rng};
+use kernel::ReturnCode;
+
+pub struct MuxRng<'a> {
+    rng: &'a dyn Rng<'a>,
+    devices: List<'a, VirtualRngDevice<'a>>,
+    running: Cell<bool>,
+}
+
+impl<'a> MuxRng<'a> {
+    pub const fn new(rng: &'a dyn Rng<'a>) -> MuxRng<'a> {
+        MuxRng {
+            rng: rng,
+            devices: List::new(),
+            running: Cell::new(false),
+        }
+    }
+
+    fn requested(&self) -> bool {
+        self.devices.iter().any(|device| device.requested.get())
+    }
+
+    fn start(&self) -> ReturnCode {
+        if self.running.get() {
+            return ReturnCode::SUCCESS;
+        }
+        let result = self.rng.get();
+        self.running.set(result == ReturnCode::SUCCESS);
+        result
+    }
+}
+
+impl rng::Client for MuxRng<'_> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> rng::Continue {
+        for device in self.devices.iter().filter(|device| device.requested.get()) {
+            let done = device.client.map_or(true, |client| {
+                client.randomness_available(randomness, error) == rng::Continue::Done
+            });
+            if done {
+                device.requested.set(false);
+            }
+        }
+        if self.requested() {
+            rng::Continue::More
+        } else {
+            self.running.set(false);
+            rng::Continue::Done
+        }
+    }
+}
+
+pub struct VirtualRngDevice<'a> {
+    mux: &'a MuxRng<'a>,
+    requested: Cell<bool>,
+    client: OptionalCell<&'a dyn rng::Client>,
+    next: ListLink<'a, VirtualRngDevice<'a>>,
+}
+
+impl<'a> VirtualRngDevice<'a> {
+    pub const fn new(mux: &'a MuxRng<'a>) -> VirtualRngDevice<'a> {
+        VirtualRngDevice {
+            mux: mux,
+            requested: Cell::new(false),
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn add_to_mux(&'a self) {
+        self.mux.devices.push_head(self);
+    }
+}
+
+impl<'a> ListNode<'a, VirtualRngDevice<'a>> for VirtualRngDevice<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualRngDevice<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> Rng<'a> for VirtualRngDevice<'a> {
+    fn get(&self) -> ReturnCode {
+        let result = self.mux.start();
+        if result == ReturnCode::SUCCESS {
+            self.requested.set(true);
+        }
+        result
+    }
+
+    /// Only stops the generator if no other user is waiting for randomness.
+    fn cancel(&self) -> ReturnCode {
+        self.requested.set(false);
+        if self.mux.running.get() && !self.mux.requested() {
+            let result = self.mux.rng.cancel();
+            if result == ReturnCode::SUCCESS {
+                self.mux.running.set(false);
+            }
+            result
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn set_client(&'a self, client: &'a dyn rng::Client) {
+        self.client.set(client);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use host::harness::leak;
+    use host::mock::rng::MockRng;
+    use std::cell::RefCell;
+    use std::vec::Vec;
+
+    /// Takes random numbers until it has `wanted` of them.
+    struct Taker {
+        wanted: usize,
+        taken: RefCell<Vec<u32>>,
+    }
+
+    impl Taker {
+        fn new(wanted: usize) -> Taker {
+            Taker {
+                wanted: wanted,
+                taken: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl rng::Client for Taker {
+        fn randomness_available(
+            &self,
+            randomness: &mut dyn Iterator<Item = u32>,
+            _error: ReturnCode,
+        ) -> rng::Continue {
+            let mut taken = self.taken.borrow_mut();
+            while taken.len() < self.wanted {
+                match randomness.next() {
+                    Some(value) => taken.push(value),
+                    None => return rng::Continue::More,
+                }
+            }
+            rng::Continue::Done
+        }
+    }
+
+    #[test]
+    pub fn users_share_the_randomness() {
+        let mock = leak(MockRng::new());
+        let mux = leak(MuxRng::new(mock));
+        mock.set_client(mux);
+        let first = leak(VirtualRngDevice::new(mux));
+        let second = leak(VirtualRngDevice::new(mux));
+        let idle = leak(VirtualRngDevice::new(mux));
+        // Added to the head of the list, so `first` is offered randomness first.
+        for device in [idle, second, first].iter() {
+            device.add_to_mux();
+        }
+        let takers = leak([Taker::new(2), Taker::new(3), Taker::new(1)]);
+        first.set_client(&takers[0]);
+        second.set_client(&takers[1]);
+        idle.set_client(&takers[2]);
+
+        assert_eq!(first.get(), ReturnCode::SUCCESS);
+        assert_eq!(second.get(), ReturnCode::SUCCESS);
+        assert!(mock.complete(&[1, 2, 3]));
+        assert_eq!(&takers[0].taken.borrow()[..], &[1, 2]);
+        assert_eq!(&takers[1].taken.borrow()[..], &[3]);
+        assert!(mock.is_requested());
+
+        // Cancelling one user keeps the generator running for the other.
+        assert_eq!(first.get(), ReturnCode::SUCCESS);
+        assert_eq!(first.cancel(), ReturnCode::SUCCESS);
+        assert!(mock.is_requested());
+        assert!(mock.complete(&[4, 5, 6]));
+        assert_eq!(&takers[0].taken.borrow()[..], &[1, 2]);
+        assert_eq!(&takers[1].taken.borrow()[..], &[3, 4, 5]);
+        assert!(takers[2].taken.borrow().is_empty());
+        assert!(!mock.is_requested());
+    }
+}
@@ -243,6 +243,10 @@ impl<Spi: hil::spi::SpiSlave> hil::spi::SpiSlaveDevice for VirtualSpiSlaveDevice
         self.spi.read_write_bytes(write_buffer, read_buffer, len)
     }
 
+    fn abort_transfer(&self) -> (Option<&'static mut [u8]>, Option<&'static mut [u8]>) {
+        self.spi.abort_transfer()
+    }
+
     fn set_polarity(&self, cpol: hil::spi::ClockPolarity) {
         self.spi.set_clock(cpol);
     }
@@ -0,0 +1,309 @@
+//! Provides a 64-bit monotonic clock and an optional wall clock to processes.
+//!
+//! Hardware counters are usually 32 or 24 bits wide and wrap every few hours
+//! or minutes. `MonotonicClock` extends any `Alarm` into a 64-bit counter by
+//! counting how many times the underlying counter wrapped. It keeps the alarm
+//! armed every half period so that no wrap goes unnoticed, even if nothing
+//! reads the clock for a long time. The alarm must not be shared with another
+//! client; use a `VirtualMuxAlarm` if the hardware alarm is needed elsewhere.
+//!
+//! `WallClockDriver` exposes the 64-bit counter to processes, together with a
+//! wall clock in UTC seconds and nanoseconds. The wall clock is unset at boot
+//! and can only be set by the kernel, for example by the board from an RTC,
+//! since timestamps of every process depend on it. Processes can only read it.
+//! Once set, it advances with the monotonic clock.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let clock_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let clock = static_init!(
+//!     capsules::wall_clock::MonotonicClock<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::wall_clock::MonotonicClock::new(clock_alarm)
+//! );
+//! clock_alarm.set_client(clock);
+//! clock.start();
+//! let wall_clock = static_init!(
+//!     capsules::wall_clock::WallClockDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::wall_clock::WallClockDriver::new(clock, board_kernel.create_grant(&grant_cap))
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::{AppId, AppSlice, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::WallClock as usize;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Size of a monotonic counter value in a process buffer: a little-endian
+/// `u64`.
+pub const TICKS_LEN: usize = 8;
+
+/// Size of a wall clock value in a process buffer: little-endian `u64`
+/// seconds followed by little-endian `u32` nanoseconds.
+pub const WALL_CLOCK_LEN: usize = 12;
+
+/// A 64-bit counter built on top of a wrapping hardware alarm.
+pub struct MonotonicClock<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    /// Number of times the underlying counter has wrapped.
+    wraps: Cell<u64>,
+    /// Counter value at the last sample, used to detect wraps.
+    last: Cell<u32>,
+}
+
+impl<'a, A: Alarm<'a>> MonotonicClock<'a, A> {
+    pub const fn new(alarm: &'a A) -> MonotonicClock<'a, A> {
+        MonotonicClock {
+            alarm: alarm,
+            wraps: Cell::new(0),
+            last: Cell::new(0),
+        }
+    }
+
+    /// Starts tracking wraps of the underlying counter. Must be called once
+    /// the clock has been set as the alarm's client.
+    pub fn start(&self) {
+        self.last.set(self.alarm.now());
+        self.arm();
+    }
+
+    /// Number of tics in one period of the underlying counter.
+    fn period(&self) -> u64 {
+        self.alarm.max_tics() as u64 + 1
+    }
+
+    fn arm(&self) {
+        let period = self.period();
+        let next = (self.last.get() as u64 + period / 2) % period;
+        self.alarm.set_alarm(next as u32);
+    }
+
+    /// Reads the underlying counter and accounts for a wrap since the last
+    /// sample.
+    fn sample(&self) -> u64 {
+        let now = self.alarm.now();
+        if now < self.last.get() {
+            self.wraps.set(self.wraps.get() + 1);
+        }
+        self.last.set(now);
+        self.wraps.get() * self.period() + now as u64
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::Time<u64> for MonotonicClock<'a, A> {
+    type Frequency = A::Frequency;
+
+    fn now(&self) -> u64 {
+        self.sample()
+    }
+
+    fn max_tics(&self) -> u64 {
+        u64::max_value()
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for MonotonicClock<'a, A> {
+    fn fired(&self) {
+        self.sample();
+        self.arm();
+    }
+}
+
+/// A point in UTC time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WallTime {
+    pub seconds: u64,
+    pub nanoseconds: u32,
+}
+
+impl WallTime {
+    /// Returns the wall time `tics` clock tics at `frequency` Hz after this
+    /// one.
+    fn after(&self, tics: u64, frequency: u64) -> WallTime {
+        let subsecond = (tics % frequency) * NANOS_PER_SECOND / frequency;
+        let nanoseconds = self.nanoseconds as u64 + subsecond;
+        WallTime {
+            seconds: self.seconds + tics / frequency + nanoseconds / NANOS_PER_SECOND,
+            nanoseconds: (nanoseconds % NANOS_PER_SECOND) as u32,
+        }
+    }
+}
+
+/// A wall time together with the monotonic counter value at which it was
+/// set.
+#[derive(Copy, Clone)]
+struct Epoch {
+    ticks: u64,
+    time: WallTime,
+}
+
+#[derive(Default)]
+pub struct App {
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct WallClockDriver<'a, A: Alarm<'a>> {
+    clock: &'a MonotonicClock<'a, A>,
+    epoch: Cell<Option<Epoch>>,
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm<'a>> WallClockDriver<'a, A> {
+    pub const fn new(
+        clock: &'a MonotonicClock<'a, A>,
+        grant: Grant<App>,
+    ) -> WallClockDriver<'a, A> {
+        WallClockDriver {
+            clock: clock,
+            epoch: Cell::new(None),
+            apps: grant,
+        }
+    }
+
+    /// Sets the wall clock to `time` as of now.
+    pub fn set_wall_clock(&self, time: WallTime) -> ReturnCode {
+        if time.nanoseconds as u64 >= NANOS_PER_SECOND {
+            return ReturnCode::EINVAL;
+        }
+        self.epoch.set(Some(Epoch {
+            ticks: time::Time::now(self.clock),
+            time: time,
+        }));
+        ReturnCode::SUCCESS
+    }
+
+    /// Returns the current wall clock time, or `None` if it has not been set.
+    pub fn wall_clock(&self) -> Option<WallTime> {
+        self.epoch.get().map(|epoch| {
+            let elapsed = time::Time::now(self.clock) - epoch.ticks;
+            epoch.time.after(elapsed, A::Frequency::frequency() as u64)
+        })
+    }
+}
+
+impl<'a, A: Alarm<'a>> Driver for WallClockDriver<'a, A> {
+    /// Share a buffer with the driver.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Buffer that clock values are written to.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read the clocks. Values are written to the buffer shared with allow
+    /// number 0.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Return the frequency of the monotonic clock in Hz.
+    /// - `2`: Write the monotonic clock value to the buffer.
+    /// - `3`: Write the wall clock time to the buffer, or return EOFF if it
+    ///        has not been set.
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => ReturnCode::SuccessWithValue {
+                value: A::Frequency::frequency() as usize,
+            },
+            2 | 3 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer
+                        .as_mut()
+                        .map_or(ReturnCode::EINVAL, |buffer| match command_num {
+                            2 => {
+                                if buffer.len() < TICKS_LEN {
+                                    return ReturnCode::ESIZE;
+                                }
+                                let ticks = time::Time::now(self.clock);
+                                buffer.as_mut()[..TICKS_LEN].copy_from_slice(&ticks.to_le_bytes());
+                                ReturnCode::SUCCESS
+                            }
+                            _ => {
+                                if buffer.len() < WALL_CLOCK_LEN {
+                                    return ReturnCode::ESIZE;
+                                }
+                                self.wall_clock().map_or(ReturnCode::EOFF, |time| {
+                                    let buffer = buffer.as_mut();
+                                    buffer[..8].copy_from_slice(&time.seconds.to_le_bytes());
+                                    buffer[8..12].copy_from_slice(&time.nanoseconds.to_le_bytes());
+                                    ReturnCode::SUCCESS
+                                })
+                            }
+                        })
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MonotonicClock, WallTime};
+    use host::mock::alarm::MockAlarm;
+    use kernel::hil::time::{Alarm, Time};
+
+    #[test]
+    pub fn monotonic_clock_counts_wraps() {
+        let alarm: MockAlarm = MockAlarm::new();
+        let clock = MonotonicClock::new(&alarm);
+        alarm.set_client(&clock);
+        alarm.set_now(u32::max_value() - 10);
+        clock.start();
+        assert!(alarm.is_enabled());
+
+        alarm.advance(20);
+        assert_eq!(clock.now(), (1 << 32) + 9);
+
+        // Nothing reads the clock for several wraps; the alarm alone must
+        // keep track of them.
+        for _ in 0..6 {
+            assert!(alarm.advance_to_alarm());
+        }
+        alarm.advance(5);
+        assert_eq!(clock.now(), (3 << 32) + u32::max_value() as u64 - 5);
+    }
+
+    #[test]
+    pub fn wall_time_advances_with_tics() {
+        let epoch = WallTime {
+            seconds: 1_600_000_000,
+            nanoseconds: 750_000_000,
+        };
+        let later = epoch.after(32768 * 3 + 16384, 32768);
+        assert_eq!(
+            later,
+            WallTime {
+                seconds: 1_600_000_004,
+                nanoseconds: 250_000_000,
+            }
+        );
+        assert_eq!(epoch.after(0, 32768), epoch);
+    }
+}
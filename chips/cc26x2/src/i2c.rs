@@ -249,6 +249,12 @@ impl<'a> i2c::I2CMaster for I2CMaster<'a> {
         self.registers.mcr.modify(Configuration::MFE.val(0))
     }
 
+    fn abort(&self) -> Option<&'static mut [u8]> {
+        i2c::I2CMaster::disable(self);
+        i2c::I2CMaster::enable(self);
+        self.transfer.take().map(|transfer| transfer.buf)
+    }
+
     fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
         self.registers
             .msa
@@ -0,0 +1,141 @@
+//! Simulated chip that the kernel can run on the host.
+
+use core::cell::Cell;
+use core::fmt::Write;
+
+use kernel::mpu::MPU;
+
+use crate::syscall;
+use crate::systick::HostSysTick;
+
+/// Peripherals of a simulated chip.
+///
+/// The simulated chip has 32 interrupt lines. When an interrupt is pending
+/// the chip passes its number to the `InterruptService` it was created with,
+/// which dispatches it to the simulated peripheral raising it.
+pub trait InterruptService {
+    /// Service an interrupt, if supported by these peripherals. If this
+    /// interrupt number is not supported, return false.
+    fn service_interrupt(&self, interrupt: u32) -> bool;
+}
+
+/// A simulated chip. By default it has no MPU, other MPUs (such as
+/// `mock::mpu::MockMpu`) are used with `HostChip::new_with_mpu()`.
+pub struct HostChip<M: MPU = ()> {
+    mpu: M,
+    systick: HostSysTick,
+    userspace_kernel_boundary: syscall::SysCall,
+    interrupt_service: Option<&'static dyn InterruptService>,
+    pending_interrupts: Cell<u32>,
+    sleep_count: Cell<usize>,
+}
+
+impl HostChip {
+    /// Create a chip that runs the simulated processes in `apps`.
+    ///
+    /// Processes are matched to `apps` by the index stored in their TBF
+    /// image, see `tbf::write_app_image()`.
+    pub fn new(
+        apps: &'static [&'static dyn syscall::HostApp],
+        interrupt_service: Option<&'static dyn InterruptService>,
+    ) -> HostChip {
+        HostChip::new_with_mpu(apps, interrupt_service, ())
+    }
+}
+
+impl<M: MPU> HostChip<M> {
+    /// Create a chip like `new()` that protects process memory with `mpu`.
+    pub fn new_with_mpu(
+        apps: &'static [&'static dyn syscall::HostApp],
+        interrupt_service: Option<&'static dyn InterruptService>,
+        mpu: M,
+    ) -> HostChip<M> {
+        HostChip {
+            mpu: mpu,
+            systick: HostSysTick::new(),
+            userspace_kernel_boundary: syscall::SysCall::new(apps),
+            interrupt_service: interrupt_service,
+            pending_interrupts: Cell::new(0),
+            sleep_count: Cell::new(0),
+        }
+    }
+
+    /// Mark interrupt `interrupt` as pending. It is serviced the next time
+    /// the kernel checks for interrupts.
+    pub fn trigger_interrupt(&self, interrupt: u32) {
+        assert!(interrupt < 32, "host chip only has 32 interrupts");
+        self.pending_interrupts
+            .set(self.pending_interrupts.get() | 1 << interrupt);
+    }
+
+    /// How many times the kernel put the chip to sleep because neither the
+    /// kernel nor any process had work to do.
+    pub fn sleep_count(&self) -> usize {
+        self.sleep_count.get()
+    }
+}
+
+impl<M: MPU> kernel::Chip for HostChip<M> {
+    type MPU = M;
+    type UserspaceKernelBoundary = syscall::SysCall;
+    type SysTick = HostSysTick;
+
+    fn mpu(&self) -> &Self::MPU {
+        &self.mpu
+    }
+
+    fn systick(&self) -> &Self::SysTick {
+        &self.systick
+    }
+
+    fn userspace_kernel_boundary(&self) -> &syscall::SysCall {
+        &self.userspace_kernel_boundary
+    }
+
+    fn service_pending_interrupts(&self) {
+        // Servicing an interrupt may trigger further interrupts, so keep going
+        // until none are left.
+        while self.pending_interrupts.get() != 0 {
+            let pending = self.pending_interrupts.get();
+            let interrupt = pending.trailing_zeros();
+            self.pending_interrupts.set(pending & !(1 << interrupt));
+
+            let handled = self
+                .interrupt_service
+                .map_or(false, |service| service.service_interrupt(interrupt));
+            if !handled {
+                panic!("unhandled interrupt {}", interrupt);
+            }
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        self.pending_interrupts.get() != 0
+    }
+
+    fn sleep(&self) {
+        // Nothing can wake the simulated chip except the host, so just return
+        // to the kernel loop and let the caller decide how to continue.
+        self.sleep_count.set(self.sleep_count.get() + 1);
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // Interrupts are only raised from the host thread running the kernel,
+        // so nothing can interrupt `f`.
+        f()
+    }
+
+    unsafe fn print_state(&self, writer: &mut dyn Write) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n---| Host Chip |---\
+             \r\n Pending interrupts: {:#010X}\
+             \r\n Sleep count: {}\
+             \r\n",
+            self.pending_interrupts.get(),
+            self.sleep_count.get(),
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+//! Helpers for tests that run the kernel main loop with simulated processes.
+//!
+//! The kernel tests of this crate use these, and so can the tests of other
+//! crates, such as capsules, by enabling the `harness` feature of `host` in
+//! their dev-dependencies. Everything here leaks memory, to get the `'static`
+//! lifetimes the kernel needs, so it is only meant for tests.
+
+extern crate std;
+
+use core::cell::{Cell, RefCell};
+use std::boxed::Box;
+use std::vec;
+use std::vec::Vec;
+
+use kernel::capabilities::{
+    CreatePortTableCapability, MainLoopCapability, MemoryAllocationCapability,
+    NetworkCapabilityCreationCapability, ProcessManagementCapability, UdpDriverCapability,
+};
+use kernel::ipc;
+use kernel::mpu::MPU;
+use kernel::procs::{FaultResponse, ProcessType};
+use kernel::syscall::Syscall;
+use kernel::{Driver, Grant, Kernel, Platform};
+
+use crate::chip::HostChip;
+use crate::syscall::{HostAction, HostApp, Resumption};
+use crate::tbf;
+
+/// The process slots of a kernel created by `create_kernel()`.
+pub type Processes = [Option<&'static dyn ProcessType>];
+
+/// Every capability, for tests that need to call privileged kernel and capsule
+/// functions.
+pub struct TestCapabilities;
+
+unsafe impl CreatePortTableCapability for TestCapabilities {}
+unsafe impl MainLoopCapability for TestCapabilities {}
+unsafe impl MemoryAllocationCapability for TestCapabilities {}
+unsafe impl NetworkCapabilityCreationCapability for TestCapabilities {}
+unsafe impl ProcessManagementCapability for TestCapabilities {}
+unsafe impl UdpDriverCapability for TestCapabilities {}
+
+/// Move `value` to the heap and never free it.
+pub fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+/// A process that issues a fixed list of syscalls and then yields forever.
+///
+/// The `allow_address` of `ALLOW` syscalls and the new break of `BRK` memops in
+/// the script are offsets from the start of process memory, and `memory` is
+/// copied to the start of process memory when the process starts.
+pub struct ScriptedApp {
+    script: Vec<Syscall>,
+    initial_memory: &'static [u8],
+    next: Cell<usize>,
+    starts: Cell<usize>,
+    memory_start: Cell<usize>,
+    /// The value each syscall of the script returned, in order.
+    pub returns: RefCell<Vec<isize>>,
+    /// The callbacks the process was resumed with, in order.
+    pub callbacks: RefCell<Vec<Resumption>>,
+}
+
+impl ScriptedApp {
+    pub fn new(script: Vec<Syscall>) -> ScriptedApp {
+        ScriptedApp::new_with_memory(script, &[])
+    }
+
+    pub fn new_with_memory(script: Vec<Syscall>, memory: &'static [u8]) -> ScriptedApp {
+        ScriptedApp {
+            script: script,
+            initial_memory: memory,
+            next: Cell::new(0),
+            starts: Cell::new(0),
+            memory_start: Cell::new(0),
+            returns: RefCell::new(Vec::new()),
+            callbacks: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The address process memory started at when the process last started.
+    pub fn memory_start(&self) -> usize {
+        self.memory_start.get()
+    }
+
+    /// How many times the process was started.
+    pub fn starts(&self) -> usize {
+        self.starts.get()
+    }
+
+    /// A copy of `len` bytes of process memory, `offset` bytes from its
+    /// start.
+    pub fn memory(&self, offset: usize, len: usize) -> Vec<u8> {
+        assert_ne!(self.memory_start.get(), 0, "process never started");
+        let start = (self.memory_start.get() + offset) as *const u8;
+        unsafe { core::slice::from_raw_parts(start, len) }.to_vec()
+    }
+}
+
+impl HostApp for ScriptedApp {
+    fn resume(&self, resumption: Resumption) -> HostAction {
+        match resumption {
+            Resumption::Start { memory_start, .. } => {
+                self.starts.set(self.starts.get() + 1);
+                self.next.set(0);
+                self.memory_start.set(memory_start);
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        self.initial_memory.as_ptr(),
+                        memory_start as *mut u8,
+                        self.initial_memory.len(),
+                    );
+                }
+            }
+            Resumption::Return(return_value) => self.returns.borrow_mut().push(return_value),
+            Resumption::Callback { .. } => self.callbacks.borrow_mut().push(resumption),
+        }
+
+        let step = self.next.get();
+        self.next.set(step + 1);
+        let syscall = match self.script.get(step) {
+            Some(&Syscall::ALLOW {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            }) => Syscall::ALLOW {
+                driver_number: driver_number,
+                subdriver_number: subdriver_number,
+                allow_address: (self.memory_start.get() + allow_address as usize) as *mut u8,
+                allow_size: allow_size,
+            },
+            Some(&Syscall::MEMOP { operand: 0, arg0 }) => Syscall::MEMOP {
+                operand: 0,
+                arg0: self.memory_start.get() + arg0,
+            },
+            Some(&syscall) => syscall,
+            None => Syscall::YIELD,
+        };
+        HostAction::Syscall(syscall)
+    }
+}
+
+/// A `COMMAND` syscall whose second argument is 0.
+pub fn command(driver_number: usize, subdriver_number: usize, arg0: usize) -> Syscall {
+    Syscall::COMMAND {
+        driver_number: driver_number,
+        subdriver_number: subdriver_number,
+        arg0: arg0,
+        arg1: 0,
+    }
+}
+
+/// A platform with the drivers a test registers.
+#[derive(Default)]
+pub struct TestPlatform {
+    drivers: Vec<(usize, &'static dyn Driver)>,
+    ipc: Option<&'static ipc::IPC>,
+}
+
+impl TestPlatform {
+    /// A platform without any drivers.
+    pub fn new() -> TestPlatform {
+        TestPlatform::default()
+    }
+
+    /// Add `driver` as driver number `driver_num`.
+    pub fn driver(mut self, driver_num: usize, driver: &'static dyn Driver) -> TestPlatform {
+        self.drivers.push((driver_num, driver));
+        self
+    }
+
+    /// Add IPC, both as a driver and to be run by the kernel loop.
+    pub fn ipc(mut self, ipc: &'static ipc::IPC) -> TestPlatform {
+        self.ipc = Some(ipc);
+        self.driver(ipc::DRIVER_NUM, ipc)
+    }
+}
+
+impl Platform for TestPlatform {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn Driver>) -> R,
+    {
+        f(self
+            .drivers
+            .iter()
+            .find(|(num, _)| *num == driver_num)
+            .map(|(_, driver)| *driver))
+    }
+}
+
+/// Create a kernel with room for four processes.
+pub fn create_kernel() -> (&'static Kernel, &'static mut Processes) {
+    let processes: &'static mut Processes = Box::leak(Box::new([None; 4]));
+    let processes_ptr = processes as *mut Processes;
+    let kernel = leak(Kernel::new(unsafe { &*processes_ptr }));
+    (kernel, unsafe { &mut *processes_ptr })
+}
+
+/// Create a grant of `kernel`.
+pub fn create_grant<T: Default>(kernel: &'static Kernel) -> Grant<T> {
+    kernel.create_grant(&TestCapabilities)
+}
+
+/// Load one process per entry of `apps`, named by the first element and
+/// implemented by the `HostApp` at the index given by the second element.
+/// Returns the loaded processes.
+pub fn load_apps<M: MPU>(
+    kernel: &'static Kernel,
+    chip: &'static HostChip<M>,
+    processes: &'static mut Processes,
+    apps: &[(&str, u32)],
+    fault_response: FaultResponse,
+) -> &'static Processes {
+    load_flash(kernel, chip, processes, app_flash(apps), fault_response)
+}
+
+/// Load processes like `load_apps()`, but size their memory dynamically from
+/// a shared memory pool.
+pub fn load_apps_from_pool<M: MPU>(
+    kernel: &'static Kernel,
+    chip: &'static HostChip<M>,
+    processes: &'static mut Processes,
+    apps: &[(&str, u32)],
+    fault_response: FaultResponse,
+) -> &'static Processes {
+    let processes_ptr = processes as *const Processes;
+    kernel::procs::load_processes_from_pool(
+        kernel,
+        chip,
+        app_flash(apps),
+        app_memory(),
+        processes,
+        fault_response,
+        &TestCapabilities,
+    )
+    .unwrap();
+    unsafe { &*processes_ptr }
+}
+
+/// Load the processes whose TBF images are in `flash`.
+pub fn load_flash<M: MPU>(
+    kernel: &'static Kernel,
+    chip: &'static HostChip<M>,
+    processes: &'static mut Processes,
+    flash: &'static [u8],
+    fault_response: FaultResponse,
+) -> &'static Processes {
+    let processes_ptr = processes as *const Processes;
+    kernel::procs::load_processes(
+        kernel,
+        chip,
+        flash,
+        app_memory(),
+        processes,
+        fault_response,
+        &TestCapabilities,
+    )
+    .unwrap();
+    unsafe { &*processes_ptr }
+}
+
+/// Create the flash for one process per entry of `apps`, see `load_apps()`.
+fn app_flash(apps: &[(&str, u32)]) -> &'static [u8] {
+    let flash_len = apps.iter().map(|(name, _)| tbf::app_image_len(name)).sum();
+    let flash: &'static mut [u8] = Box::leak(vec![0; flash_len].into_boxed_slice());
+    let mut offset = 0;
+    for (name, app) in apps.iter() {
+        // Ask for more memory than the kernel gives processes initially, to
+        // leave room for grants.
+        offset += tbf::write_app_image(&mut flash[offset..], name, *app, 4096).unwrap();
+    }
+    flash
+}
+
+/// Create the memory processes are loaded into.
+fn app_memory() -> &'static mut [u8] {
+    // Keep process memory word aligned, as it would be on hardware.
+    let memory: &'static mut [u64] = Box::leak(vec![0; 4096].into_boxed_slice());
+    unsafe { core::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, memory.len() * 8) }
+}
+
+/// Run the kernel loop until the chip goes to sleep.
+pub fn run_until_idle<M: MPU>(
+    kernel: &'static Kernel,
+    platform: &TestPlatform,
+    chip: &HostChip<M>,
+) {
+    let sleep_count = chip.sleep_count();
+    for _ in 0..100 {
+        kernel.kernel_loop_operation(platform, chip, platform.ipc, false, &TestCapabilities);
+        if chip.sleep_count() > sleep_count {
+            return;
+        }
+    }
+    panic!("kernel never went to sleep");
+}
+
+/// Run `iterations` iterations of the kernel loop without letting the chip
+/// sleep.
+pub fn run_iterations<M: MPU>(
+    kernel: &'static Kernel,
+    platform: &TestPlatform,
+    chip: &HostChip<M>,
+    iterations: usize,
+) {
+    for _ in 0..iterations {
+        kernel.kernel_loop_operation(platform, chip, platform.ipc, true, &TestCapabilities);
+    }
+}
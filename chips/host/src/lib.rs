@@ -0,0 +1,39 @@
+//! Host simulation of a Tock chip.
+//!
+//! This crate implements the `Chip`, `SysTick` and `UserspaceKernelBoundary`
+//! interfaces in plain Rust so that the kernel main loop, grants, IPC and
+//! capsule drivers can be run on the development machine, for example with
+//! `cargo test`.
+//!
+//! Processes are not compiled application binaries. Each process is a Rust
+//! closure (or any other type implementing [`HostApp`](syscall::HostApp))
+//! that is resumed by the kernel every time the process would execute. The
+//! closure learns why it was resumed (it was started, a syscall returned, or a
+//! callback is to be run) and returns the next syscall the process issues.
+//! Processes are still loaded from TBF images by `load_processes()`, which
+//! can be built with the helpers in the [`tbf`] module.
+//!
+//! The simulation is deterministic: the scheduler timeslice is charged a fixed
+//! amount for every context switch and interrupts only fire when a test
+//! triggers them with `HostChip::trigger_interrupt()`.
+//!
+//! The [`mock`] module provides scriptable peripherals implementing the
+//! `kernel::hil` traits, for testing capsules with or without the rest of the
+//! simulation. With the `harness` feature, the [`harness`] module has helpers
+//! for running the kernel loop with scripted processes in tests.
+
+#![crate_name = "host"]
+#![crate_type = "rlib"]
+#![feature(const_fn)]
+#![no_std]
+
+pub mod chip;
+#[cfg(any(test, feature = "harness"))]
+pub mod harness;
+pub mod mock;
+pub mod syscall;
+pub mod systick;
+pub mod tbf;
+
+#[cfg(test)]
+mod tests;
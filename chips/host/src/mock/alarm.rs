@@ -0,0 +1,148 @@
+//! Mock alarm.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Frequency, Ticks};
+
+/// An alarm whose counter only moves when the test advances it.
+///
+/// The alarm fires when the counter reaches the alarm value while moving
+/// forward from where it was when the alarm was set, wrapping around like a
+/// hardware counter. An alarm set to a value the counter just passed
+/// therefore only fires after the counter wraps.
+///
+/// By default the counter is 32 bits wide. Other widths are simulated with
+/// `new_with_max_tics`, e.g. a 24-bit counter with `W = u32` and a
+/// `max_tics` of `(1 << 24) - 1`.
+pub struct MockAlarm<'a, F: Frequency = time::Freq32KHz, W: Ticks = u32> {
+    now: Cell<W>,
+    alarm: Cell<W>,
+    reference: Cell<W>,
+    max_tics: W,
+    enabled: Cell<bool>,
+    set_count: Cell<usize>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+    _frequency: PhantomData<F>,
+}
+
+impl<'a, F: Frequency> MockAlarm<'a, F> {
+    pub const fn new() -> MockAlarm<'a, F> {
+        MockAlarm {
+            now: Cell::new(0),
+            alarm: Cell::new(0),
+            reference: Cell::new(0),
+            max_tics: u32::max_value(),
+            enabled: Cell::new(false),
+            set_count: Cell::new(0),
+            client: OptionalCell::empty(),
+            _frequency: PhantomData,
+        }
+    }
+}
+
+impl<'a, F: Frequency, W: Ticks> MockAlarm<'a, F, W> {
+    /// A counter that wraps around after `max_tics`, which must be one less
+    /// than a power of two.
+    pub fn new_with_max_tics(max_tics: W) -> MockAlarm<'a, F, W> {
+        MockAlarm {
+            now: Cell::new(W::default()),
+            alarm: Cell::new(W::default()),
+            reference: Cell::new(W::default()),
+            max_tics: max_tics,
+            enabled: Cell::new(false),
+            set_count: Cell::new(0),
+            client: OptionalCell::empty(),
+            _frequency: PhantomData,
+        }
+    }
+
+    /// How many times the alarm was set.
+    pub fn set_count(&self) -> usize {
+        self.set_count.get()
+    }
+
+    /// Move the counter to `now` without firing the alarm, for example to
+    /// start a test just before the counter wraps.
+    pub fn set_now(&self, now: W) {
+        self.now.set(now);
+        self.reference.set(now);
+    }
+
+    /// Move the counter forward by `tics`, firing the alarm if the counter
+    /// reaches it. Returns whether the alarm fired.
+    pub fn advance(&self, tics: W) -> bool {
+        let now = self.now.get();
+        self.now.set(now.wrapping_add(tics) & self.max_tics);
+        if !self.enabled.get() {
+            return false;
+        }
+
+        // Both distances are measured from where the counter was when the
+        // alarm was set, so that wrapping around is handled.
+        let reference = self.reference.get();
+        let remaining = self.alarm.get().wrapping_sub(reference) & self.max_tics;
+        let elapsed = now.wrapping_sub(reference) & self.max_tics;
+        if tics >= remaining.wrapping_sub(elapsed) {
+            self.fire();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Move the counter to the alarm and fire it. Returns false if the alarm
+    /// was not enabled.
+    pub fn advance_to_alarm(&self) -> bool {
+        if self.enabled.get() {
+            self.now.set(self.alarm.get());
+            self.fire();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn fire(&self) {
+        self.enabled.set(false);
+        self.client.map(|client| client.fired());
+    }
+}
+
+impl<F: Frequency, W: Ticks> time::Time<W> for MockAlarm<'_, F, W> {
+    type Frequency = F;
+
+    fn now(&self) -> W {
+        self.now.get()
+    }
+
+    fn max_tics(&self) -> W {
+        self.max_tics
+    }
+}
+
+impl<'a, F: Frequency, W: Ticks> time::Alarm<'a, W> for MockAlarm<'a, F, W> {
+    fn set_alarm(&self, tics: W) {
+        self.alarm.set(tics);
+        self.reference.set(self.now.get());
+        self.enabled.set(true);
+        self.set_count.set(self.set_count.get() + 1);
+    }
+
+    fn get_alarm(&self) -> W {
+        self.alarm.get()
+    }
+
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+    }
+}
@@ -0,0 +1,183 @@
+//! Mock flash.
+
+use core::cell::Cell;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::flash;
+use kernel::ReturnCode;
+
+/// Size of a page of the mock flash.
+pub const PAGE_SIZE: usize = 512;
+
+/// A page of the mock flash.
+pub struct MockPage(pub [u8; PAGE_SIZE]);
+
+impl Default for MockPage {
+    fn default() -> Self {
+        MockPage([0; PAGE_SIZE])
+    }
+}
+
+impl AsMut<[u8]> for MockPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// A flash operation requested from the mock.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operation {
+    Read(usize),
+    Write(usize),
+    Erase(usize),
+}
+
+/// A flash backed by a memory buffer. Operations are only carried out when the
+/// test completes them, so tests can also make them fail.
+pub struct MockFlash {
+    client: OptionalCell<&'static dyn flash::Client<MockFlash>>,
+    storage: TakeCell<'static, [u8]>,
+    operation: Cell<Option<Operation>>,
+    buffer: TakeCell<'static, MockPage>,
+}
+
+impl MockFlash {
+    /// Create a flash with `storage.len() / PAGE_SIZE` pages stored in
+    /// `storage`.
+    pub fn new(storage: &'static mut [u8]) -> MockFlash {
+        MockFlash {
+            client: OptionalCell::empty(),
+            storage: TakeCell::new(storage),
+            operation: Cell::new(None),
+            buffer: TakeCell::empty(),
+        }
+    }
+
+    /// The outstanding operation, if any.
+    pub fn operation(&self) -> Option<Operation> {
+        self.operation.get()
+    }
+
+    /// Run `f` on the contents of the flash.
+    pub fn with_storage<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.storage
+            .map(f)
+            .expect("flash storage is always present")
+    }
+
+    /// Carry out the outstanding operation and tell the client it completed.
+    /// Returns false if there was no outstanding operation.
+    pub fn complete(&self) -> bool {
+        let operation = match self.operation.get() {
+            Some(operation) => operation,
+            None => return false,
+        };
+        self.storage.map(|storage| match operation {
+            Operation::Read(page) => self.buffer.map(|buffer| {
+                buffer
+                    .0
+                    .copy_from_slice(&storage[page * PAGE_SIZE..(page + 1) * PAGE_SIZE]);
+            }),
+            Operation::Write(page) => self.buffer.map(|buffer| {
+                storage[page * PAGE_SIZE..(page + 1) * PAGE_SIZE].copy_from_slice(&buffer.0);
+            }),
+            Operation::Erase(page) => {
+                for byte in storage[page * PAGE_SIZE..(page + 1) * PAGE_SIZE].iter_mut() {
+                    *byte = 0xFF;
+                }
+                Some(())
+            }
+        });
+        self.finish(flash::Error::CommandComplete)
+    }
+
+    /// Fail the outstanding operation without changing the flash. Returns
+    /// false if there was no outstanding operation.
+    pub fn fail(&self) -> bool {
+        self.finish(flash::Error::FlashError)
+    }
+
+    fn finish(&self, error: flash::Error) -> bool {
+        match self.operation.take() {
+            Some(Operation::Read(_)) => {
+                if let Some(buffer) = self.buffer.take() {
+                    self.client
+                        .map(move |client| client.read_complete(buffer, error));
+                }
+                true
+            }
+            Some(Operation::Write(_)) => {
+                if let Some(buffer) = self.buffer.take() {
+                    self.client
+                        .map(move |client| client.write_complete(buffer, error));
+                }
+                true
+            }
+            Some(Operation::Erase(_)) => {
+                self.client.map(|client| client.erase_complete(error));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn page_count(&self) -> usize {
+        self.storage.map_or(0, |storage| storage.len() / PAGE_SIZE)
+    }
+
+    fn start(&self, operation: Operation, page_number: usize) -> ReturnCode {
+        if self.operation.get().is_some() {
+            ReturnCode::EBUSY
+        } else if page_number >= self.page_count() {
+            ReturnCode::EINVAL
+        } else {
+            self.operation.set(Some(operation));
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<C: flash::Client<Self>> flash::HasClient<'static, C> for MockFlash {
+    fn set_client(&self, client: &'static C) {
+        self.client.set(client);
+    }
+}
+
+impl flash::Flash for MockFlash {
+    type Page = MockPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        match self.start(Operation::Read(page_number), page_number) {
+            ReturnCode::SUCCESS => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            error => Err((error, buf)),
+        }
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        match self.start(Operation::Write(page_number), page_number) {
+            ReturnCode::SUCCESS => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            error => Err((error, buf)),
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        self.start(Operation::Erase(page_number), page_number)
+    }
+}
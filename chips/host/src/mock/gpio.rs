@@ -0,0 +1,182 @@
+//! Mock GPIO pin.
+
+use core::cell::Cell;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::gpio;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Edge {
+    Rising,
+    Falling,
+    Either,
+}
+
+/// A GPIO pin whose input level is set by the test.
+///
+/// When the pin is an output, reading it returns the level it drives.
+/// Otherwise it returns the level set with `set_input()`, which also fires the
+/// interrupt if it is enabled for that edge.
+pub struct MockPin {
+    input: Cell<bool>,
+    output: Cell<bool>,
+    low_power: Cell<bool>,
+    floating_state: Cell<gpio::FloatingState>,
+    output_level: Cell<bool>,
+    input_level: Cell<bool>,
+    interrupt_edge: Cell<Option<Edge>>,
+    pending: Cell<bool>,
+    client: OptionalCell<&'static dyn gpio::Client>,
+}
+
+impl MockPin {
+    pub const fn new() -> MockPin {
+        MockPin {
+            input: Cell::new(false),
+            output: Cell::new(false),
+            low_power: Cell::new(true),
+            floating_state: Cell::new(gpio::FloatingState::PullNone),
+            output_level: Cell::new(false),
+            input_level: Cell::new(false),
+            interrupt_edge: Cell::new(None),
+            pending: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// The level the pin drives when it is an output.
+    pub fn output_level(&self) -> bool {
+        self.output_level.get()
+    }
+
+    /// Whether interrupts are enabled.
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupt_edge.get().is_some()
+    }
+
+    /// Whether the pin was put in its low power state and not reconfigured
+    /// since.
+    pub fn is_low_power(&self) -> bool {
+        self.low_power.get()
+    }
+
+    /// Drive the pin externally to `level`. If interrupts are enabled for the
+    /// resulting edge the client is called. Returns whether it was.
+    pub fn set_input(&self, level: bool) -> bool {
+        let previous = self.input_level.replace(level);
+        let fires = match (self.interrupt_edge.get(), previous, level) {
+            (Some(Edge::Rising), false, true)
+            | (Some(Edge::Falling), true, false)
+            | (Some(Edge::Either), false, true)
+            | (Some(Edge::Either), true, false) => true,
+            _ => false,
+        };
+        if fires {
+            self.pending.set(true);
+            self.client.map(|client| client.fired());
+            self.pending.set(false);
+        }
+        fires
+    }
+}
+
+impl gpio::Configure for MockPin {
+    fn configuration(&self) -> gpio::Configuration {
+        match (self.input.get(), self.output.get(), self.low_power.get()) {
+            (true, true, _) => gpio::Configuration::InputOutput,
+            (true, false, _) => gpio::Configuration::Input,
+            (false, true, _) => gpio::Configuration::Output,
+            (false, false, true) => gpio::Configuration::LowPower,
+            (false, false, false) => gpio::Configuration::Other,
+        }
+    }
+
+    fn make_output(&self) -> gpio::Configuration {
+        self.output.set(true);
+        self.low_power.set(false);
+        self.configuration()
+    }
+
+    fn disable_output(&self) -> gpio::Configuration {
+        self.output.set(false);
+        self.configuration()
+    }
+
+    fn make_input(&self) -> gpio::Configuration {
+        self.input.set(true);
+        self.low_power.set(false);
+        self.configuration()
+    }
+
+    fn disable_input(&self) -> gpio::Configuration {
+        self.input.set(false);
+        self.configuration()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.input.set(false);
+        self.output.set(false);
+        self.low_power.set(true);
+        self.interrupt_edge.set(None);
+    }
+
+    fn set_floating_state(&self, state: gpio::FloatingState) {
+        self.floating_state.set(state);
+    }
+
+    fn floating_state(&self) -> gpio::FloatingState {
+        self.floating_state.get()
+    }
+}
+
+impl gpio::Output for MockPin {
+    fn set(&self) {
+        self.output_level.set(true);
+    }
+
+    fn clear(&self) {
+        self.output_level.set(false);
+    }
+
+    fn toggle(&self) -> bool {
+        let level = !self.output_level.get();
+        self.output_level.set(level);
+        level
+    }
+}
+
+impl gpio::Input for MockPin {
+    fn read(&self) -> bool {
+        if self.output.get() {
+            self.output_level.get()
+        } else {
+            self.input_level.get()
+        }
+    }
+}
+
+impl gpio::Interrupt for MockPin {
+    fn set_client(&self, client: &'static dyn gpio::Client) {
+        self.client.set(client);
+    }
+
+    fn enable_interrupts(&self, mode: gpio::InterruptEdge) {
+        let edge = match mode {
+            gpio::InterruptEdge::RisingEdge => Edge::Rising,
+            gpio::InterruptEdge::FallingEdge => Edge::Falling,
+            gpio::InterruptEdge::EitherEdge => Edge::Either,
+        };
+        self.interrupt_edge.set(Some(edge));
+    }
+
+    fn disable_interrupts(&self) {
+        self.interrupt_edge.set(None);
+    }
+
+    fn is_pending(&self) -> bool {
+        self.pending.get()
+    }
+}
+
+impl gpio::Pin for MockPin {}
+impl gpio::InterruptPin for MockPin {}
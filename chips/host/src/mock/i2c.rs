@@ -0,0 +1,188 @@
+//! Mock I2C master.
+
+use core::cell::Cell;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::i2c;
+
+/// An I2C transfer requested from the mock.
+///
+/// Writes have a `read_len` of zero and reads a `write_len` of zero.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transfer {
+    pub addr: u8,
+    pub write_len: u8,
+    pub read_len: u8,
+    /// Whether the transfer was requested through `SMBusMaster`.
+    pub smbus: bool,
+}
+
+/// An I2C master that keeps the buffer of a transfer until the test completes
+/// it.
+///
+/// Starting a transfer while another one is outstanding is a bug in the
+/// caller, since the buffer could not be returned, and panics.
+pub struct MockI2CMaster {
+    client: OptionalCell<&'static dyn i2c::I2CHwMasterClient>,
+    enabled: Cell<bool>,
+    transfer: Cell<Option<Transfer>>,
+    buffer: TakeCell<'static, [u8]>,
+    transfer_count: Cell<usize>,
+    smbus_supported: Cell<bool>,
+}
+
+impl MockI2CMaster {
+    pub const fn new() -> MockI2CMaster {
+        MockI2CMaster {
+            client: OptionalCell::empty(),
+            enabled: Cell::new(false),
+            transfer: Cell::new(None),
+            buffer: TakeCell::empty(),
+            transfer_count: Cell::new(0),
+            smbus_supported: Cell::new(true),
+        }
+    }
+
+    /// Whether the bus is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// The outstanding transfer, if any.
+    pub fn transfer(&self) -> Option<Transfer> {
+        self.transfer.get()
+    }
+
+    /// How many transfers were started.
+    pub fn transfer_count(&self) -> usize {
+        self.transfer_count.get()
+    }
+
+    /// Choose whether `SMBusMaster` transfers are accepted, to test callers
+    /// with controllers that do not support SMBus.
+    pub fn set_smbus_supported(&self, supported: bool) {
+        self.smbus_supported.set(supported);
+    }
+
+    /// Run `f` on the data written by the outstanding transfer.
+    pub fn with_written_data<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let write_len = self.transfer.get().map_or(0, |t| t.write_len as usize);
+        self.buffer.map(|buffer| f(&buffer[..write_len]))
+    }
+
+    /// Complete the outstanding transfer. The bytes of `read_data`, up to the
+    /// number of bytes the transfer reads, are placed at the start of the
+    /// buffer before it is returned to the client with `error`. Returns false
+    /// if there was no outstanding transfer.
+    pub fn complete(&self, read_data: &[u8], error: i2c::Error) -> bool {
+        let transfer = match self.transfer.take() {
+            Some(transfer) => transfer,
+            None => return false,
+        };
+        self.buffer.take().map_or(false, |buffer| {
+            let len = core::cmp::min(read_data.len(), transfer.read_len as usize);
+            buffer[..len].copy_from_slice(&read_data[..len]);
+            self.client
+                .map(move |client| client.command_complete(buffer, error));
+            true
+        })
+    }
+
+    fn start(&self, transfer: Transfer, buffer: &'static mut [u8]) {
+        if self.transfer.get().is_some() {
+            panic!("I2C transfer {:?} started while busy", transfer);
+        }
+        self.transfer.set(Some(transfer));
+        self.buffer.replace(buffer);
+        self.transfer_count.set(self.transfer_count.get() + 1);
+    }
+
+    fn start_smbus(
+        &self,
+        transfer: Transfer,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        if self.smbus_supported.get() {
+            self.start(transfer, buffer);
+            Ok(())
+        } else {
+            Err((i2c::Error::NotSupported, buffer))
+        }
+    }
+}
+
+impl i2c::I2CMaster for MockI2CMaster {
+    fn set_master_client(&self, master_client: &'static dyn i2c::I2CHwMasterClient) {
+        self.client.set(master_client);
+    }
+
+    fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+    }
+
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        let transfer = Transfer {
+            addr: addr,
+            write_len: write_len,
+            read_len: read_len,
+            smbus: false,
+        };
+        self.start(transfer, data);
+    }
+
+    fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
+        self.write_read(addr, data, len, 0);
+    }
+
+    fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8) {
+        self.write_read(addr, buffer, 0, len);
+    }
+
+    fn abort(&self) -> Option<&'static mut [u8]> {
+        self.transfer.set(None);
+        self.buffer.take()
+    }
+}
+
+impl i2c::SMBusMaster for MockI2CMaster {
+    fn smbus_write_read(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: u8,
+        read_len: u8,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        let transfer = Transfer {
+            addr: addr,
+            write_len: write_len,
+            read_len: read_len,
+            smbus: true,
+        };
+        self.start_smbus(transfer, data)
+    }
+
+    fn smbus_write(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        len: u8,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.smbus_write_read(addr, data, len, 0)
+    }
+
+    fn smbus_read(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        len: u8,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.smbus_write_read(addr, buffer, 0, len)
+    }
+}
@@ -0,0 +1,21 @@
+//! Scriptable mock peripherals for testing capsules.
+//!
+//! Each mock implements one or more `kernel::hil` traits. Instead of talking
+//! to hardware, a mock records the calls a capsule makes, keeps any buffers it
+//! is passed, and only completes an operation when the test tells it to. This
+//! lets tests check exactly what a capsule asked the peripheral to do and
+//! control when and how its callbacks happen.
+//!
+//! Mocks call their clients synchronously from the `complete_*()` style
+//! methods the test calls, never from within a HIL call, just like hardware
+//! that signals completion from an interrupt.
+
+pub mod alarm;
+pub mod flash;
+pub mod gpio;
+pub mod i2c;
+pub mod mpu;
+pub mod radio;
+pub mod rng;
+pub mod spi;
+pub mod uart;
@@ -0,0 +1,132 @@
+//! Mock memory protection unit.
+
+use core::cell::Cell;
+use core::fmt;
+
+use kernel::mpu::{self, MPU};
+use kernel::AppId;
+
+/// The app memory region of one process, as set up by `MockMpu`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MockRegion {
+    /// Start of the process memory block.
+    pub start: usize,
+    /// Size of the process memory block.
+    pub size: usize,
+    /// End of the memory the process can access.
+    pub app_break: usize,
+}
+
+#[derive(Default)]
+pub struct MockMpuConfig {
+    region: Option<MockRegion>,
+}
+
+impl fmt::Display for MockMpuConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.region)
+    }
+}
+
+/// An MPU that only protects process memory blocks whose size is a multiple
+/// of a fixed granularity, like the alignment real MPUs need.
+///
+/// It does not protect anything, but checks the requests it gets like a real
+/// MPU would and remembers the region it was last configured with.
+pub struct MockMpu {
+    granularity: usize,
+    configured: Cell<Option<MockRegion>>,
+}
+
+impl MockMpu {
+    /// An MPU that rounds process memory blocks up to a multiple of
+    /// `granularity` bytes.
+    pub const fn new(granularity: usize) -> MockMpu {
+        MockMpu {
+            granularity: granularity,
+            configured: Cell::new(None),
+        }
+    }
+
+    /// The app memory region the MPU was last configured with.
+    pub fn configured_region(&self) -> Option<MockRegion> {
+        self.configured.get()
+    }
+
+    fn round_up(&self, size: usize) -> usize {
+        (size + self.granularity - 1) / self.granularity * self.granularity
+    }
+}
+
+impl MPU for MockMpu {
+    type MpuConfig = MockMpuConfig;
+
+    fn allocate_app_memory_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_memory_size: usize,
+        initial_app_memory_size: usize,
+        initial_kernel_memory_size: usize,
+        _permissions: mpu::Permissions,
+        config: &mut MockMpuConfig,
+    ) -> Option<(*const u8, usize)> {
+        let size = self.round_up(core::cmp::max(
+            min_memory_size,
+            initial_app_memory_size + initial_kernel_memory_size,
+        ));
+        if config.region.is_some() || size > unallocated_memory_size {
+            return None;
+        }
+        let start = unallocated_memory_start as usize;
+        config.region = Some(MockRegion {
+            start: start,
+            size: size,
+            app_break: start + initial_app_memory_size,
+        });
+        Some((unallocated_memory_start, size))
+    }
+
+    fn update_app_memory_region(
+        &self,
+        app_memory_break: *const u8,
+        kernel_memory_break: *const u8,
+        _permissions: mpu::Permissions,
+        config: &mut MockMpuConfig,
+    ) -> Result<(), ()> {
+        let region = config.region.as_mut().ok_or(())?;
+        let (app_break, kernel_break) = (app_memory_break as usize, kernel_memory_break as usize);
+        if app_break > kernel_break || kernel_break > region.start + region.size {
+            return Err(());
+        }
+        region.app_break = app_break;
+        Ok(())
+    }
+
+    fn resize_app_memory_region(
+        &self,
+        memory_start: *const u8,
+        available_memory_size: usize,
+        min_memory_size: usize,
+        app_memory_break: *const u8,
+        kernel_memory_size: usize,
+        _permissions: mpu::Permissions,
+        config: &mut MockMpuConfig,
+    ) -> Option<usize> {
+        let region = config.region.as_mut()?;
+        let size = self.round_up(min_memory_size);
+        if region.start != memory_start as usize
+            || size > available_memory_size
+            || app_memory_break as usize > region.start + size - kernel_memory_size
+        {
+            return None;
+        }
+        region.size = size;
+        region.app_break = app_memory_break as usize;
+        Some(size)
+    }
+
+    fn configure_mpu(&self, config: &MockMpuConfig, _app_id: &AppId) {
+        self.configured.set(config.region);
+    }
+}
@@ -0,0 +1,249 @@
+//! Mock 802.15.4 radio.
+
+use core::cell::Cell;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::radio;
+use kernel::ReturnCode;
+
+/// A radio that keeps transmitted frames until the test completes the
+/// transmission, and receives frames the test injects.
+pub struct MockRadio {
+    on: Cell<bool>,
+    address: Cell<u16>,
+    address_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    tx_power: Cell<i8>,
+    channel: Cell<u8>,
+    config_pending: Cell<bool>,
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    config_client: OptionalCell<&'static dyn radio::ConfigClient>,
+    power_client: OptionalCell<&'static dyn radio::PowerClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_count: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+}
+
+impl MockRadio {
+    pub const fn new() -> MockRadio {
+        MockRadio {
+            on: Cell::new(false),
+            address: Cell::new(0),
+            address_long: Cell::new([0; 8]),
+            pan: Cell::new(0),
+            tx_power: Cell::new(0),
+            channel: Cell::new(26),
+            config_pending: Cell::new(false),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            config_client: OptionalCell::empty(),
+            power_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_count: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+        }
+    }
+
+    /// How many frames were transmitted.
+    pub fn transmit_count(&self) -> usize {
+        self.tx_count.get()
+    }
+
+    /// Run `f` on the frame being transmitted, without the PSDU offset.
+    pub fn with_transmitted_frame<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let len = self.tx_len.get();
+        self.tx_buffer
+            .map(|buffer| f(&buffer[radio::PSDU_OFFSET..radio::PSDU_OFFSET + len]))
+    }
+
+    /// Complete the outstanding transmission. Returns false if there was none.
+    pub fn complete_transmit(&self, acked: bool, result: ReturnCode) -> bool {
+        self.tx_buffer.take().map_or(false, |buffer| {
+            self.tx_client
+                .map(move |client| client.send_done(buffer, acked, result));
+            true
+        })
+    }
+
+    /// Receive `frame`, which is copied into the receive buffer after the PSDU
+    /// offset. Returns false if the frame was dropped because the radio is off,
+    /// the client has not returned the receive buffer, or the frame does not
+    /// fit.
+    pub fn receive_frame(&self, frame: &[u8], crc_valid: bool) -> bool {
+        if !self.on.get() {
+            return false;
+        }
+        let buffer = match self.rx_buffer.take() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        if radio::PSDU_OFFSET + frame.len() > buffer.len() {
+            self.rx_buffer.replace(buffer);
+            return false;
+        }
+        buffer[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame.len()].copy_from_slice(frame);
+        self.rx_client
+            .map(move |client| client.receive(buffer, frame.len(), crc_valid, ReturnCode::SUCCESS));
+        true
+    }
+
+    /// Whether a configuration change was committed but not yet completed.
+    pub fn is_config_pending(&self) -> bool {
+        self.config_pending.get()
+    }
+
+    /// Complete a committed configuration change. Returns false if there was
+    /// none.
+    pub fn complete_config(&self) -> bool {
+        if self.config_pending.replace(false) {
+            self.config_client
+                .map(|client| client.config_done(ReturnCode::SUCCESS));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Tell the power client the radio turned on or off.
+    pub fn complete_power_change(&self) {
+        let on = self.on.get();
+        self.power_client.map(|client| client.changed(on));
+    }
+}
+
+impl radio::RadioConfig for MockRadio {
+    fn initialize(
+        &self,
+        _spi_buf: &'static mut [u8],
+        _reg_write: &'static mut [u8],
+        _reg_read: &'static mut [u8],
+    ) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn reset(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn start(&self) -> ReturnCode {
+        self.on.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.on.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_on(&self) -> bool {
+        self.on.get()
+    }
+
+    fn busy(&self) -> bool {
+        self.tx_buffer.is_some()
+    }
+
+    fn set_power_client(&self, client: &'static dyn radio::PowerClient) {
+        self.power_client.set(client);
+    }
+
+    fn config_commit(&self) {
+        self.config_pending.set(true);
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.config_client.set(client);
+    }
+
+    fn get_address(&self) -> u16 {
+        self.address.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.address_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.tx_power.get()
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.channel.get()
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.address.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.address_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.tx_power.set(power);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        if chan >= 11 && chan <= 26 {
+            self.channel.set(chan);
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::EINVAL
+        }
+    }
+}
+
+impl radio::RadioData for MockRadio {
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(
+        &self,
+        client: &'static dyn radio::RxClient,
+        receive_buffer: &'static mut [u8],
+    ) {
+        self.rx_client.set(client);
+        self.rx_buffer.replace(receive_buffer);
+    }
+
+    fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]) {
+        self.rx_buffer.replace(receive_buffer);
+    }
+
+    fn transmit(
+        &self,
+        spi_buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if !self.on.get() {
+            (ReturnCode::EOFF, Some(spi_buf))
+        } else if self.tx_buffer.is_some() {
+            (ReturnCode::EBUSY, Some(spi_buf))
+        } else if radio::PSDU_OFFSET + frame_len > spi_buf.len() {
+            (ReturnCode::ESIZE, Some(spi_buf))
+        } else {
+            self.tx_len.set(frame_len);
+            self.tx_buffer.replace(spi_buf);
+            self.tx_count.set(self.tx_count.get() + 1);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+}
+
+impl radio::Radio for MockRadio {}
@@ -0,0 +1,59 @@
+//! Mock random number generator.
+
+use core::cell::Cell;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::rng;
+use kernel::ReturnCode;
+
+/// A random number generator whose numbers are chosen by the test.
+pub struct MockRng {
+    requested: Cell<bool>,
+    client: OptionalCell<&'static dyn rng::Client>,
+}
+
+impl MockRng {
+    pub const fn new() -> MockRng {
+        MockRng {
+            requested: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Whether the client asked for randomness and has not got all it wants.
+    pub fn is_requested(&self) -> bool {
+        self.requested.get()
+    }
+
+    /// Pass `randomness` to the client if it asked for randomness. Returns
+    /// whether it did. The request stays open if the client wants more.
+    pub fn complete(&self, randomness: &[u32]) -> bool {
+        if !self.requested.get() {
+            return false;
+        }
+        let done = self.client.map_or(true, |client| {
+            let mut randomness = randomness.iter().cloned();
+            client.randomness_available(&mut randomness, ReturnCode::SUCCESS) == rng::Continue::Done
+        });
+        if done {
+            self.requested.set(false);
+        }
+        true
+    }
+}
+
+impl rng::Rng<'static> for MockRng {
+    fn get(&self) -> ReturnCode {
+        self.requested.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        self.requested.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_client(&'static self, client: &'static dyn rng::Client) {
+        self.client.set(client);
+    }
+}
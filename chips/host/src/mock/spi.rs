@@ -0,0 +1,336 @@
+//! Mock SPI master and slave.
+
+use core::cell::Cell;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::spi;
+use kernel::ReturnCode;
+
+/// An SPI master that keeps the buffers of a transfer until the test
+/// completes it.
+///
+/// Chip selects are plain numbers. The byte-at-a-time operations complete
+/// immediately: written bytes are recorded and reads return the byte set with
+/// `set_read_byte()`.
+pub struct MockSpiMaster {
+    client: OptionalCell<&'static dyn spi::SpiMasterClient>,
+    initialized: Cell<bool>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    transfer_count: Cell<usize>,
+    chip_select: Cell<Option<u8>>,
+    rate: Cell<u32>,
+    polarity: Cell<spi::ClockPolarity>,
+    phase: Cell<spi::ClockPhase>,
+    held_low: Cell<bool>,
+    written_byte: Cell<Option<u8>>,
+    read_byte: Cell<u8>,
+}
+
+impl MockSpiMaster {
+    pub const fn new() -> MockSpiMaster {
+        MockSpiMaster {
+            client: OptionalCell::empty(),
+            initialized: Cell::new(false),
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            transfer_count: Cell::new(0),
+            chip_select: Cell::new(None),
+            rate: Cell::new(0),
+            polarity: Cell::new(spi::ClockPolarity::IdleLow),
+            phase: Cell::new(spi::ClockPhase::SampleLeading),
+            held_low: Cell::new(false),
+            written_byte: Cell::new(None),
+            read_byte: Cell::new(0),
+        }
+    }
+
+    /// Whether `init()` was called.
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.get()
+    }
+
+    /// The chip select chosen by the last `specify_chip_select()` call.
+    pub fn chip_select(&self) -> Option<u8> {
+        self.chip_select.get()
+    }
+
+    /// Whether chip select is held low between transfers.
+    pub fn is_held_low(&self) -> bool {
+        self.held_low.get()
+    }
+
+    /// How many transfers were started.
+    pub fn transfer_count(&self) -> usize {
+        self.transfer_count.get()
+    }
+
+    /// The length of the outstanding transfer, if any.
+    pub fn transfer_len(&self) -> Option<usize> {
+        self.write_buffer.map(|_| self.len.get())
+    }
+
+    /// Whether the outstanding transfer reads data back.
+    pub fn transfer_reads(&self) -> bool {
+        self.read_buffer.is_some()
+    }
+
+    /// Run `f` on the data written by the outstanding transfer.
+    pub fn with_written_data<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let len = self.len.get();
+        self.write_buffer.map(|buffer| f(&buffer[..len]))
+    }
+
+    /// The last byte written with `write_byte()` or `read_write_byte()`.
+    pub fn written_byte(&self) -> Option<u8> {
+        self.written_byte.get()
+    }
+
+    /// Set the byte `read_byte()` and `read_write_byte()` return.
+    pub fn set_read_byte(&self, byte: u8) {
+        self.read_byte.set(byte);
+    }
+
+    /// Complete the outstanding transfer. The bytes the slave sent,
+    /// `read_data`, are copied into the read buffer if there is one. Returns
+    /// false if there was no outstanding transfer.
+    pub fn complete(&self, read_data: &[u8]) -> bool {
+        let len = self.len.get();
+        self.write_buffer.take().map_or(false, |write_buffer| {
+            let read_buffer = self.read_buffer.take().map(|read_buffer| {
+                let copy_len = core::cmp::min(read_data.len(), len);
+                read_buffer[..copy_len].copy_from_slice(&read_data[..copy_len]);
+                read_buffer
+            });
+            self.client
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+            true
+        })
+    }
+}
+
+impl spi::SpiMaster for MockSpiMaster {
+    type ChipSelect = u8;
+
+    fn set_client(&self, client: &'static dyn spi::SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn init(&self) {
+        self.initialized.set(true);
+    }
+
+    fn is_busy(&self) -> bool {
+        self.write_buffer.is_some()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        let read_len = read_buffer.as_ref().map_or(len, |buffer| buffer.len());
+        if len > write_buffer.len() || len > read_len {
+            return ReturnCode::ESIZE;
+        }
+        self.len.set(len);
+        self.write_buffer.replace(write_buffer);
+        self.read_buffer.put(read_buffer);
+        self.transfer_count.set(self.transfer_count.get() + 1);
+        ReturnCode::SUCCESS
+    }
+
+    fn write_byte(&self, val: u8) {
+        self.written_byte.set(Some(val));
+    }
+
+    fn read_byte(&self) -> u8 {
+        self.read_byte.get()
+    }
+
+    fn read_write_byte(&self, val: u8) -> u8 {
+        self.written_byte.set(Some(val));
+        self.read_byte.get()
+    }
+
+    fn specify_chip_select(&self, cs: Self::ChipSelect) {
+        self.chip_select.set(Some(cs));
+    }
+
+    fn set_rate(&self, rate: u32) -> u32 {
+        self.rate.set(rate);
+        rate
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+
+    fn set_clock(&self, polarity: spi::ClockPolarity) {
+        self.polarity.set(polarity);
+    }
+
+    fn get_clock(&self) -> spi::ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: spi::ClockPhase) {
+        self.phase.set(phase);
+    }
+
+    fn get_phase(&self) -> spi::ClockPhase {
+        self.phase.get()
+    }
+
+    fn hold_low(&self) {
+        self.held_low.set(true);
+    }
+
+    fn release_low(&self) {
+        self.held_low.set(false);
+    }
+}
+
+/// An SPI slave that keeps the buffers of a transfer until the test, acting
+/// as the master, completes it.
+pub struct MockSpiSlave {
+    client: OptionalCell<&'static dyn spi::SpiSlaveClient>,
+    initialized: Cell<bool>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<Option<usize>>,
+    write_byte: Cell<u8>,
+    polarity: Cell<spi::ClockPolarity>,
+    phase: Cell<spi::ClockPhase>,
+}
+
+impl MockSpiSlave {
+    pub const fn new() -> MockSpiSlave {
+        MockSpiSlave {
+            client: OptionalCell::empty(),
+            initialized: Cell::new(false),
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(None),
+            write_byte: Cell::new(0),
+            polarity: Cell::new(spi::ClockPolarity::IdleLow),
+            phase: Cell::new(spi::ClockPhase::SampleLeading),
+        }
+    }
+
+    /// Whether `init()` was called.
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.get()
+    }
+
+    /// The length of the outstanding transfer, if any.
+    pub fn transfer_len(&self) -> Option<usize> {
+        self.len.get()
+    }
+
+    /// The byte sent to the master when there is no write buffer.
+    pub fn write_byte(&self) -> u8 {
+        self.write_byte.get()
+    }
+
+    /// Have the master select this slave.
+    pub fn select(&self) {
+        self.client.map(|client| client.chip_selected());
+    }
+
+    /// Complete the outstanding transfer with the master sending
+    /// `master_data`, which is copied into the read buffer if there is one.
+    /// Returns what the slave sent to the master, or None if there was no
+    /// outstanding transfer.
+    pub fn complete<'b>(&self, master_data: &[u8], sent: &'b mut [u8]) -> Option<&'b [u8]> {
+        let len = self.len.take()?;
+        let sent_len = core::cmp::min(len, sent.len());
+        let write_byte = self.write_byte.get();
+        let write_buffer = self.write_buffer.take().map(|write_buffer| {
+            sent[..sent_len].copy_from_slice(&write_buffer[..sent_len]);
+            write_buffer
+        });
+        if write_buffer.is_none() {
+            for byte in sent[..sent_len].iter_mut() {
+                *byte = write_byte;
+            }
+        }
+        let read_buffer = self.read_buffer.take().map(|read_buffer| {
+            let copy_len = core::cmp::min(master_data.len(), len);
+            read_buffer[..copy_len].copy_from_slice(&master_data[..copy_len]);
+            read_buffer
+        });
+        self.client
+            .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+        Some(&sent[..sent_len])
+    }
+}
+
+impl spi::SpiSlave for MockSpiSlave {
+    fn init(&self) {
+        self.initialized.set(true);
+    }
+
+    fn has_client(&self) -> bool {
+        self.client.is_some()
+    }
+
+    fn set_client(&self, client: Option<&'static dyn spi::SpiSlaveClient>) {
+        self.client.insert(client);
+    }
+
+    fn set_write_byte(&self, write_byte: u8) {
+        self.write_byte.set(write_byte);
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: Option<&'static mut [u8]>,
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        if self.len.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let too_short = |buffer: &Option<&'static mut [u8]>| {
+            buffer.as_ref().map_or(false, |buffer| buffer.len() < len)
+        };
+        if too_short(&write_buffer) || too_short(&read_buffer) {
+            return ReturnCode::ESIZE;
+        }
+        self.len.set(Some(len));
+        self.write_buffer.put(write_buffer);
+        self.read_buffer.put(read_buffer);
+        ReturnCode::SUCCESS
+    }
+
+    fn abort_transfer(&self) -> (Option<&'static mut [u8]>, Option<&'static mut [u8]>) {
+        self.len.set(None);
+        (self.write_buffer.take(), self.read_buffer.take())
+    }
+
+    fn set_clock(&self, polarity: spi::ClockPolarity) {
+        self.polarity.set(polarity);
+    }
+
+    fn get_clock(&self) -> spi::ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: spi::ClockPhase) {
+        self.phase.set(phase);
+    }
+
+    fn get_phase(&self) -> spi::ClockPhase {
+        self.phase.get()
+    }
+}
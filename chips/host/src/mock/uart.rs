@@ -0,0 +1,241 @@
+//! Mock UART.
+
+use core::cell::Cell;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::ReturnCode;
+
+/// A UART that keeps transmit and receive buffers until the test completes
+/// the operation.
+pub struct MockUart<'a> {
+    parameters: Cell<Option<uart::Parameters>>,
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_word: Cell<Option<u32>>,
+    tx_aborted: Cell<bool>,
+    tx_count: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_word: Cell<bool>,
+    rx_aborted: Cell<bool>,
+}
+
+impl<'a> MockUart<'a> {
+    pub const fn new() -> MockUart<'a> {
+        MockUart {
+            parameters: Cell::new(None),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_word: Cell::new(None),
+            tx_aborted: Cell::new(false),
+            tx_count: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_word: Cell::new(false),
+            rx_aborted: Cell::new(false),
+        }
+    }
+
+    /// The parameters the UART was last configured with.
+    pub fn parameters(&self) -> Option<uart::Parameters> {
+        self.parameters.get()
+    }
+
+    /// Whether a transmission is outstanding.
+    pub fn is_transmitting(&self) -> bool {
+        self.tx_buffer.is_some() || self.tx_word.get().is_some()
+    }
+
+    /// How many transmissions were started.
+    pub fn transmit_count(&self) -> usize {
+        self.tx_count.get()
+    }
+
+    /// Run `f` on the data of the outstanding `transmit_buffer` call.
+    pub fn with_transmitted_data<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let len = self.tx_len.get();
+        self.tx_buffer.map(|buffer| f(&buffer[..len]))
+    }
+
+    /// The word of the outstanding `transmit_word` call.
+    pub fn transmitted_word(&self) -> Option<u32> {
+        self.tx_word.get()
+    }
+
+    /// Complete the outstanding transmission with `rval`. If the transmission
+    /// was aborted, the client is told it was cancelled instead. Returns false
+    /// if there was no outstanding transmission.
+    pub fn complete_transmit(&self, rval: ReturnCode) -> bool {
+        let (rval, aborted) = if self.tx_aborted.replace(false) {
+            (ReturnCode::ECANCEL, true)
+        } else {
+            (rval, false)
+        };
+
+        if let Some(buffer) = self.tx_buffer.take() {
+            let len = if aborted { 0 } else { self.tx_len.get() };
+            self.tx_client
+                .map(move |client| client.transmitted_buffer(buffer, len, rval));
+            true
+        } else if self.tx_word.take().is_some() {
+            self.tx_client.map(|client| client.transmitted_word(rval));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether a reception is outstanding.
+    pub fn is_receiving(&self) -> bool {
+        self.rx_buffer.is_some() || self.rx_word.get()
+    }
+
+    /// How many bytes the outstanding `receive_buffer` call asked for.
+    pub fn receive_len(&self) -> Option<usize> {
+        self.rx_buffer.map(|_| self.rx_len.get())
+    }
+
+    /// Complete the outstanding `receive_buffer` call by receiving `data`,
+    /// which is truncated to the requested length. If the reception was
+    /// aborted, the client is told it was cancelled instead. Returns false if
+    /// there was no outstanding `receive_buffer` call.
+    pub fn complete_receive(&self, data: &[u8], rval: ReturnCode, error: uart::Error) -> bool {
+        let (rval, error) = if self.rx_aborted.replace(false) {
+            (ReturnCode::ECANCEL, uart::Error::Aborted)
+        } else {
+            (rval, error)
+        };
+
+        self.rx_buffer.take().map_or(false, |buffer| {
+            let len = core::cmp::min(data.len(), self.rx_len.get());
+            buffer[..len].copy_from_slice(&data[..len]);
+            self.rx_client
+                .map(move |client| client.received_buffer(buffer, len, rval, error));
+            true
+        })
+    }
+
+    /// Complete the outstanding `receive_word` call by receiving `word`.
+    /// Returns false if there was no outstanding `receive_word` call.
+    pub fn complete_receive_word(&self, word: u32, rval: ReturnCode, error: uart::Error) -> bool {
+        if self.rx_word.replace(false) {
+            self.rx_client
+                .map(|client| client.received_word(word, rval, error));
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl uart::Configure for MockUart<'_> {
+    fn configure(&self, params: uart::Parameters) -> ReturnCode {
+        self.parameters.set(Some(params));
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> uart::Transmit<'a> for MockUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.is_transmitting() {
+            (ReturnCode::EBUSY, Some(tx_buffer))
+        } else if tx_len > tx_buffer.len() {
+            (ReturnCode::ESIZE, Some(tx_buffer))
+        } else {
+            self.tx_len.set(tx_len);
+            self.tx_buffer.replace(tx_buffer);
+            self.tx_count.set(self.tx_count.get() + 1);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    fn transmit_word(&self, word: u32) -> ReturnCode {
+        if self.is_transmitting() {
+            ReturnCode::EBUSY
+        } else {
+            self.tx_word.set(Some(word));
+            self.tx_count.set(self.tx_count.get() + 1);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        if self.is_transmitting() {
+            self.tx_aborted.set(true);
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::Receive<'a> for MockUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.is_receiving() {
+            (ReturnCode::EBUSY, Some(rx_buffer))
+        } else if rx_len > rx_buffer.len() {
+            (ReturnCode::ESIZE, Some(rx_buffer))
+        } else {
+            self.rx_len.set(rx_len);
+            self.rx_buffer.replace(rx_buffer);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        if self.is_receiving() {
+            ReturnCode::EBUSY
+        } else {
+            self.rx_word.set(true);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        if self.is_receiving() {
+            self.rx_aborted.set(true);
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::ReceiveAdvanced<'a> for MockUart<'a> {
+    fn receive_automatic(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        _interbyte_timeout: u8,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        uart::Receive::receive_buffer(self, rx_buffer, rx_len)
+    }
+}
+
+impl<'a> uart::Uart<'a> for MockUart<'a> {}
+impl<'a> uart::UartData<'a> for MockUart<'a> {}
+impl<'a> uart::UartAdvanced<'a> for MockUart<'a> {}
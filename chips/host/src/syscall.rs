@@ -0,0 +1,183 @@
+//! Kernel-userland system call interface for simulated processes.
+
+use core::fmt::Write;
+use core::ptr;
+
+use kernel::procs::FunctionCall;
+use kernel::syscall::{ContextSwitchReason, Syscall, UserspaceKernelBoundary};
+
+/// Why a simulated process is resumed.
+#[derive(Copy, Clone, Debug)]
+pub enum Resumption {
+    /// The process is started (or restarted). The fields are the arguments
+    /// the kernel passes to the `_start` function of a real process.
+    Start {
+        app_start: usize,
+        memory_start: usize,
+        memory_len: usize,
+        app_break: usize,
+    },
+
+    /// The last syscall the process issued returned `return_value`.
+    Return(isize),
+
+    /// The process should run a callback function. `pc` and `appdata` are
+    /// the callback pointer and data the process passed to `subscribe`.
+    Callback {
+        pc: usize,
+        argument0: usize,
+        argument1: usize,
+        argument2: usize,
+        appdata: usize,
+    },
+}
+
+/// What a simulated process does when it stops executing.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HostAction {
+    /// The process calls a syscall.
+    Syscall(Syscall),
+
+    /// The process crashes, as if it had caused a hardfault.
+    Fault,
+}
+
+/// A simulated process.
+///
+/// Every time the kernel switches to the process, `resume()` is called with
+/// the reason the process is resumed and returns what the process does next.
+/// Any state the process needs across resumptions must be kept in the
+/// implementing type, for example in `Cell`s captured by a closure.
+///
+/// When the process is restarted it is resumed with `Resumption::Start`
+/// again and should reset any state it keeps.
+pub trait HostApp {
+    fn resume(&self, resumption: Resumption) -> HostAction;
+}
+
+impl<F: Fn(Resumption) -> HostAction> HostApp for F {
+    fn resume(&self, resumption: Resumption) -> HostAction {
+        self(resumption)
+    }
+}
+
+/// Per-process state stored by the kernel between context switches.
+#[derive(Default)]
+pub struct HostStoredState {
+    /// Index of the `HostApp` that implements this process. This is only
+    /// known once the process has been started.
+    app: Option<usize>,
+
+    /// How the process should be resumed the next time it is switched to.
+    resumption: Option<Resumption>,
+}
+
+/// Switches to and from simulated processes.
+///
+/// Simulated processes are loaded from TBF images built by
+/// `tbf::write_app_image()`, whose code is a single little-endian `u32`: the
+/// index of the process in `apps`. When the kernel starts the process, that
+/// index is read from the entry point and the process is bound to the
+/// corresponding `HostApp`.
+pub struct SysCall {
+    apps: &'static [&'static dyn HostApp],
+}
+
+impl SysCall {
+    pub fn new(apps: &'static [&'static dyn HostApp]) -> SysCall {
+        SysCall { apps: apps }
+    }
+}
+
+impl UserspaceKernelBoundary for SysCall {
+    type StoredState = HostStoredState;
+
+    unsafe fn initialize_process(
+        &self,
+        stack_pointer: *const usize,
+        _stack_size: usize,
+        state: &mut Self::StoredState,
+    ) -> Result<*const usize, ()> {
+        // The process is bound to its app when it is started.
+        state.app = None;
+        state.resumption = None;
+        Ok(stack_pointer)
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _stack_pointer: *const usize,
+        state: &mut Self::StoredState,
+        return_value: isize,
+    ) {
+        state.resumption = Some(Resumption::Return(return_value));
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        stack_pointer: *const usize,
+        _remaining_stack_memory: usize,
+        state: &mut Self::StoredState,
+        callback: FunctionCall,
+    ) -> Result<*mut usize, *mut usize> {
+        if state.app.is_none() {
+            // The first function a process executes after being initialized
+            // is its entry point, which holds the index of its app.
+            let app = ptr::read_unaligned(callback.pc as *const u32) as usize;
+            if app >= self.apps.len() {
+                return Err(stack_pointer as *mut usize);
+            }
+            state.app = Some(app);
+            state.resumption = Some(Resumption::Start {
+                app_start: callback.argument0,
+                memory_start: callback.argument1,
+                memory_len: callback.argument2,
+                app_break: callback.argument3,
+            });
+        } else {
+            state.resumption = Some(Resumption::Callback {
+                pc: callback.pc,
+                argument0: callback.argument0,
+                argument1: callback.argument1,
+                argument2: callback.argument2,
+                appdata: callback.argument3,
+            });
+        }
+        Ok(stack_pointer as *mut usize)
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        stack_pointer: *const usize,
+        state: &mut Self::StoredState,
+    ) -> (*mut usize, ContextSwitchReason) {
+        let app = state.app.and_then(|app| self.apps.get(app));
+        let action = match (app, state.resumption.take()) {
+            (Some(app), Some(resumption)) => app.resume(resumption),
+            // A process that is not bound to an app or has nothing to resume
+            // with cannot make progress, which would be a fault on hardware.
+            _ => HostAction::Fault,
+        };
+
+        let switch_reason = match action {
+            HostAction::Syscall(syscall) => ContextSwitchReason::SyscallFired { syscall: syscall },
+            HostAction::Fault => ContextSwitchReason::Fault,
+        };
+        (stack_pointer as *mut usize, switch_reason)
+    }
+
+    unsafe fn print_context(
+        &self,
+        _stack_pointer: *const usize,
+        state: &Self::StoredState,
+        writer: &mut dyn Write,
+    ) {
+        let _ = writer.write_fmt(format_args!(
+            "\
+             \r\n Simulated app: {:?}\
+             \r\n Pending resumption: {:?}\
+             \r\n",
+            state.app, state.resumption,
+        ));
+    }
+}
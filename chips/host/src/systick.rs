@@ -0,0 +1,59 @@
+//! Simulated system tick timer.
+
+use core::cell::Cell;
+
+/// How much of a timeslice a process uses each time it is switched to.
+pub const DEFAULT_SWITCH_COST_US: u32 = 1000;
+
+/// A `SysTick` that counts simulated time instead of real time.
+///
+/// Simulated processes do not execute instructions, so instead of measuring
+/// how long a process runs, every context switch to a process is charged a
+/// fixed `switch_cost_us` microseconds of its timeslice. A process that never
+/// yields is therefore preempted after a deterministic number of syscalls.
+pub struct HostSysTick {
+    remaining_us: Cell<u32>,
+    switch_cost_us: u32,
+}
+
+impl HostSysTick {
+    pub const fn new() -> HostSysTick {
+        HostSysTick::new_with_switch_cost(DEFAULT_SWITCH_COST_US)
+    }
+
+    /// Create a `HostSysTick` that charges `switch_cost_us` microseconds of
+    /// the timeslice for every context switch.
+    pub const fn new_with_switch_cost(switch_cost_us: u32) -> HostSysTick {
+        HostSysTick {
+            remaining_us: Cell::new(0),
+            switch_cost_us: switch_cost_us,
+        }
+    }
+}
+
+impl kernel::SysTick for HostSysTick {
+    fn set_timer(&self, us: u32) {
+        self.remaining_us.set(us);
+    }
+
+    fn greater_than(&self, us: u32) -> bool {
+        self.remaining_us.get() > us
+    }
+
+    fn overflowed(&self) -> bool {
+        self.remaining_us.get() == 0
+    }
+
+    fn reset(&self) {
+        self.remaining_us.set(0);
+    }
+
+    fn enable(&self, with_interrupt: bool) {
+        // The kernel only enables the interrupt right before switching to a
+        // process, so this is where the process is charged for running.
+        if with_interrupt {
+            self.remaining_us
+                .set(self.remaining_us.get().saturating_sub(self.switch_cost_us));
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Tock Binary Format images for simulated processes.
+//!
+//! Simulated processes are loaded by the kernel like any other process, so
+//! they need a TBF header. The application code of these images is a single
+//! little-endian `u32` holding the index of the `HostApp` that implements the
+//! process.
+
+/// Length of a TBF image for an app named `name`.
+pub const fn app_image_len(name: &str) -> usize {
+    header_len(name) + 4
+}
+
+/// Length of a TBF image for an app named `name` that requests a memory
+/// quota.
+pub const fn app_image_with_quota_len(name: &str) -> usize {
+    app_image_len(name) + QUOTA_TLV_LEN
+}
+
+/// Length of the memory quota TLV.
+const QUOTA_TLV_LEN: usize = 8;
+
+const fn header_len(name: &str) -> usize {
+    // Base header, main TLV and package name TLV padded to four bytes.
+    16 + 16 + 4 + ((name.len() + 3) & !3)
+}
+
+/// Write the TBF image of a simulated process to the start of `buf`.
+///
+/// The process is enabled, is named `name`, asks for `minimum_ram_size` bytes
+/// of RAM, and is implemented by the `HostApp` at index `app` of the apps the
+/// `HostChip` was created with. Returns the length of the image, or `None` if
+/// `buf` is too small or the image would not fit the header fields.
+///
+/// Images can be written one after another to create the flash of multiple
+/// processes.
+pub fn write_app_image(
+    buf: &mut [u8],
+    name: &str,
+    app: u32,
+    minimum_ram_size: u32,
+) -> Option<usize> {
+    write_image(buf, name, app, minimum_ram_size, None)
+}
+
+/// Write the TBF image of a simulated process that limits its own memory to
+/// `memory_quota` bytes to the start of `buf`.
+///
+/// This is the same as `write_app_image()`, except that the header also
+/// contains a memory quota TLV.
+pub fn write_app_image_with_quota(
+    buf: &mut [u8],
+    name: &str,
+    app: u32,
+    minimum_ram_size: u32,
+    memory_quota: u32,
+) -> Option<usize> {
+    write_image(buf, name, app, minimum_ram_size, Some(memory_quota))
+}
+
+fn write_image(
+    buf: &mut [u8],
+    name: &str,
+    app: u32,
+    minimum_ram_size: u32,
+    memory_quota: Option<u32>,
+) -> Option<usize> {
+    let quota_len = memory_quota.map_or(0, |_| QUOTA_TLV_LEN);
+    let header_len = header_len(name) + quota_len;
+    let total_len = app_image_len(name) + quota_len;
+    if buf.len() < total_len || header_len > u16::max_value() as usize {
+        return None;
+    }
+    let image = &mut buf[0..total_len];
+    for byte in image.iter_mut() {
+        *byte = 0;
+    }
+
+    // Base header: version, header size, total size, flags (enabled) and
+    // the checksum, which is filled in last.
+    image[0..2].copy_from_slice(&2u16.to_le_bytes());
+    image[2..4].copy_from_slice(&(header_len as u16).to_le_bytes());
+    image[4..8].copy_from_slice(&(total_len as u32).to_le_bytes());
+    image[8..12].copy_from_slice(&1u32.to_le_bytes());
+
+    // Main TLV: the init function is at the start of the app code right after
+    // the header, and nothing is protected beyond the header.
+    image[16..18].copy_from_slice(&1u16.to_le_bytes());
+    image[18..20].copy_from_slice(&12u16.to_le_bytes());
+    image[20..24].copy_from_slice(&0u32.to_le_bytes());
+    image[24..28].copy_from_slice(&0u32.to_le_bytes());
+    image[28..32].copy_from_slice(&minimum_ram_size.to_le_bytes());
+
+    // Package name TLV.
+    image[32..34].copy_from_slice(&3u16.to_le_bytes());
+    image[34..36].copy_from_slice(&(name.len() as u16).to_le_bytes());
+    image[36..36 + name.len()].copy_from_slice(name.as_bytes());
+
+    // Memory quota TLV.
+    memory_quota.map(|memory_quota| {
+        let tlv = &mut image[header_len - QUOTA_TLV_LEN..header_len];
+        tlv[0..2].copy_from_slice(&6u16.to_le_bytes());
+        tlv[2..4].copy_from_slice(&4u16.to_le_bytes());
+        tlv[4..8].copy_from_slice(&memory_quota.to_le_bytes());
+    });
+
+    // The app code.
+    image[header_len..total_len].copy_from_slice(&app.to_le_bytes());
+
+    // The checksum is the XOR of all words of the header except itself.
+    let checksum = image[0..header_len]
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |checksum, (_, word)| {
+            checksum ^ u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+        });
+    image[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+    Some(total_len)
+}
@@ -0,0 +1,613 @@
+//! Tests that run the kernel main loop with simulated processes.
+//!
+//! Tests of capsules live next to the capsule they exercise, and use the
+//! `harness` module as well. The tests here exercise the kernel itself, and
+//! stay in this crate because the kernel cannot depend on it.
+
+extern crate std;
+
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::boxed::Box;
+use std::vec;
+use std::vec::Vec;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::rng;
+use kernel::introspection::KernelInfo;
+use kernel::ipc;
+use kernel::procs::{AlwaysRestart, FaultResponse, State};
+use kernel::syscall::Syscall;
+use kernel::{AppId, Chip, Driver, Grant, ReturnCode};
+
+use crate::chip::{HostChip, InterruptService};
+use crate::harness::{
+    command, create_grant, create_kernel, leak, load_apps, load_apps_from_pool, load_flash,
+    run_iterations, run_until_idle, ScriptedApp, TestCapabilities, TestPlatform,
+};
+use crate::mock::mpu::{MockMpu, MockRegion};
+use crate::syscall::{HostAction, HostApp, Resumption};
+use crate::tbf;
+
+/// Driver that counts the commands each process issued in a grant.
+const COUNTER_DRIVER_NUM: usize = 0x9000;
+
+struct Counter {
+    apps: Grant<usize>,
+}
+
+impl Driver for Counter {
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => self
+                .apps
+                .enter(appid, |count, _| {
+                    **count += 1;
+                    ReturnCode::SuccessWithValue { value: **count }
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+/// Random number generator that produces numbers when its interrupt fires.
+const RNG_INTERRUPT: u32 = 3;
+
+struct MockRng {
+    client: OptionalCell<&'static dyn rng::Client>,
+    requested: Cell<bool>,
+}
+
+impl rng::Rng<'static> for MockRng {
+    fn get(&self) -> ReturnCode {
+        self.requested.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        self.requested.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_client(&'static self, client: &'static dyn rng::Client) {
+        self.client.set(client);
+    }
+}
+
+impl InterruptService for MockRng {
+    fn service_interrupt(&self, interrupt: u32) -> bool {
+        if interrupt != RNG_INTERRUPT {
+            return false;
+        }
+        if self.requested.take() {
+            self.client.map(|client| {
+                let mut randomness = (1..).map(|i: u32| i * 0x0101_0101);
+                client.randomness_available(&mut randomness, ReturnCode::SUCCESS);
+            });
+        }
+        true
+    }
+}
+
+#[test]
+fn commands_use_per_process_grants() {
+    let (kernel, processes) = create_kernel();
+    let counter = leak(Counter {
+        apps: create_grant(kernel),
+    });
+
+    let first = leak(ScriptedApp::new(vec![
+        command(COUNTER_DRIVER_NUM, 0, 0),
+        command(COUNTER_DRIVER_NUM, 0, 0),
+        command(0x1234, 0, 0),
+    ]));
+    let second = leak(ScriptedApp::new(vec![command(COUNTER_DRIVER_NUM, 0, 0)]));
+    let apps: &'static [&'static dyn HostApp] = leak([first as &dyn HostApp, second]);
+    let chip = leak(HostChip::new(apps, None));
+    load_apps(
+        kernel,
+        chip,
+        processes,
+        &[("first", 0), ("second", 1)],
+        FaultResponse::Panic,
+    );
+
+    let platform = TestPlatform::new().driver(COUNTER_DRIVER_NUM, counter);
+    run_until_idle(kernel, &platform, chip);
+
+    assert_eq!(
+        *first.returns.borrow(),
+        [1, 2, isize::from(ReturnCode::ENODEVICE)]
+    );
+    assert_eq!(*second.returns.borrow(), [1]);
+}
+
+#[test]
+fn capsule_callback_after_interrupt() {
+    let (kernel, processes) = create_kernel();
+    let mock_rng = leak(MockRng {
+        client: OptionalCell::empty(),
+        requested: Cell::new(false),
+    });
+    let rng = leak(capsules::rng::RngDriver::new(
+        mock_rng,
+        create_grant(kernel),
+    ));
+    rng::Rng::set_client(mock_rng, rng);
+
+    let app = leak(ScriptedApp::new(vec![
+        Syscall::SUBSCRIBE {
+            driver_number: capsules::rng::DRIVER_NUM,
+            subdriver_number: 0,
+            callback_ptr: 0x1000 as *mut (),
+            appdata: 7,
+        },
+        Syscall::ALLOW {
+            driver_number: capsules::rng::DRIVER_NUM,
+            subdriver_number: 0,
+            allow_address: 0 as *mut u8,
+            allow_size: 8,
+        },
+        command(capsules::rng::DRIVER_NUM, 1, 6),
+    ]));
+    let apps: &'static [&'static dyn HostApp] = leak([app as &dyn HostApp]);
+    let chip = leak(HostChip::new(apps, Some(mock_rng)));
+    load_apps(kernel, chip, processes, &[("rng", 0)], FaultResponse::Panic);
+
+    let platform = TestPlatform::new().driver(capsules::rng::DRIVER_NUM, rng);
+    run_until_idle(kernel, &platform, chip);
+    assert_eq!(*app.returns.borrow(), [0, 0, 0]);
+    assert!(app.callbacks.borrow().is_empty());
+
+    chip.trigger_interrupt(RNG_INTERRUPT);
+    run_until_idle(kernel, &platform, chip);
+
+    match app.callbacks.borrow()[..] {
+        [Resumption::Callback {
+            pc,
+            argument1,
+            appdata,
+            ..
+        }] => {
+            assert_eq!(pc, 0x1000);
+            assert_eq!(argument1, 6);
+            assert_eq!(appdata, 7);
+        }
+        ref callbacks => panic!("unexpected callbacks {:?}", callbacks),
+    }
+    let buffer = app.memory(0, 8);
+    assert_eq!(buffer, [1, 1, 1, 1, 2, 2, 0, 0]);
+}
+
+#[test]
+fn ipc_notifies_service() {
+    let (kernel, processes) = create_kernel();
+    let ipc = leak(ipc::IPC::new(kernel, &TestCapabilities));
+
+    let service = leak(ScriptedApp::new(vec![Syscall::SUBSCRIBE {
+        driver_number: ipc::DRIVER_NUM,
+        subdriver_number: 0,
+        callback_ptr: 0x2000 as *mut (),
+        appdata: 0,
+    }]));
+    // The service is loaded first, so it gets process identifier 0 and IPC
+    // identifier 1.
+    let client = leak(ScriptedApp::new_with_memory(
+        vec![
+            Syscall::ALLOW {
+                driver_number: ipc::DRIVER_NUM,
+                subdriver_number: 0,
+                allow_address: 0 as *mut u8,
+                allow_size: 7,
+            },
+            command(ipc::DRIVER_NUM, 1, 0),
+        ],
+        b"service",
+    ));
+    let apps: &'static [&'static dyn HostApp] = leak([service as &dyn HostApp, client]);
+    let chip = leak(HostChip::new(apps, None));
+    load_apps(
+        kernel,
+        chip,
+        processes,
+        &[("service", 0), ("client", 1)],
+        FaultResponse::Panic,
+    );
+
+    let platform = TestPlatform::new().ipc(ipc);
+    run_until_idle(kernel, &platform, chip);
+
+    assert_eq!(*client.returns.borrow(), [1, 0]);
+    match service.callbacks.borrow()[..] {
+        [Resumption::Callback { pc, argument0, .. }] => {
+            assert_eq!(pc, 0x2000);
+            // The client is identified by its IPC identifier.
+            assert_eq!(argument0, 2);
+        }
+        ref callbacks => panic!("unexpected callbacks {:?}", callbacks),
+    }
+}
+
+#[test]
+fn busy_process_is_preempted() {
+    let (kernel, processes) = create_kernel();
+    let counter = leak(Counter {
+        apps: create_grant(kernel),
+    });
+
+    let busy = |_| HostAction::Syscall(command(COUNTER_DRIVER_NUM, 0, 0));
+    let other = leak(ScriptedApp::new(vec![command(COUNTER_DRIVER_NUM, 0, 0)]));
+    let apps: &'static [&'static dyn HostApp] = leak([leak(busy) as &dyn HostApp, other]);
+    let chip = leak(HostChip::new(apps, None));
+    let processes = load_apps(
+        kernel,
+        chip,
+        processes,
+        &[("busy", 0), ("other", 1)],
+        FaultResponse::Panic,
+    );
+
+    let platform = TestPlatform::new().driver(COUNTER_DRIVER_NUM, counter);
+    run_iterations(kernel, &platform, chip, 3);
+
+    let busy_process = processes[0].unwrap();
+    assert_eq!(busy_process.get_state(), State::Running);
+    assert!(busy_process.debug_timeslice_expiration_count() >= 2);
+    assert_eq!(*other.returns.borrow(), [1]);
+    assert_eq!(chip.sleep_count(), 0);
+}
+
+#[test]
+fn faulting_process_is_restarted() {
+    static RESTART: AlwaysRestart = AlwaysRestart::new();
+
+    let (kernel, processes) = create_kernel();
+    let starts = leak(Cell::new(0));
+    let faulting = move |resumption| match resumption {
+        Resumption::Start { .. } if starts.get() == 0 => {
+            starts.set(1);
+            HostAction::Fault
+        }
+        Resumption::Start { .. } => {
+            starts.set(starts.get() + 1);
+            HostAction::Syscall(Syscall::YIELD)
+        }
+        _ => HostAction::Fault,
+    };
+    let apps: &'static [&'static dyn HostApp] = leak([leak(faulting) as &dyn HostApp]);
+    let chip = leak(HostChip::new(apps, None));
+    let processes = load_apps(
+        kernel,
+        chip,
+        processes,
+        &[("faulting", 0)],
+        FaultResponse::Restart(&RESTART),
+    );
+
+    let platform = TestPlatform::new();
+    run_iterations(kernel, &platform, chip, 3);
+
+    let process = processes[0].unwrap();
+    assert_eq!(starts.get(), 2);
+    assert_eq!(process.get_restart_count(), 1);
+    assert_eq!(process.get_state(), State::Yielded);
+}
+
+static RESET_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn record_reset() {
+    RESET_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+#[test]
+fn kernel_info_reports_memory_and_statistics() {
+    let process_management_cap = TestCapabilities;
+    let (kernel, processes) = create_kernel();
+    let mock_rng = leak(MockRng {
+        client: OptionalCell::empty(),
+        requested: Cell::new(false),
+    });
+    let rng = leak(capsules::rng::RngDriver::new(
+        mock_rng,
+        create_grant(kernel),
+    ));
+    rng::Rng::set_client(mock_rng, rng);
+
+    let app = leak(ScriptedApp::new(vec![command(
+        capsules::rng::DRIVER_NUM,
+        1,
+        4,
+    )]));
+    let apps: &'static [&'static dyn HostApp] = leak([app as &dyn HostApp]);
+    let chip = leak(HostChip::new(apps, Some(mock_rng)));
+    let processes = load_apps(
+        kernel,
+        chip,
+        processes,
+        &[("info", 0)],
+        FaultResponse::Panic,
+    );
+
+    let platform = TestPlatform::new().driver(capsules::rng::DRIVER_NUM, rng);
+    run_until_idle(kernel, &platform, chip);
+
+    let info = KernelInfo::new(kernel);
+    let appid = processes[0].unwrap().appid();
+    let layout = info
+        .process_memory_layout(appid, &process_management_cap)
+        .unwrap();
+    assert!(layout.flash_start < layout.flash_app_start);
+    assert!(layout.flash_app_start < layout.flash_end);
+    assert_eq!(layout.memory_start, app.memory_start());
+    assert!(layout.memory_start < layout.app_break);
+    assert!(layout.app_break <= layout.grant_start);
+    assert!(layout.grant_start < layout.memory_end);
+
+    let services = info.number_interrupt_services(&process_management_cap);
+    chip.trigger_interrupt(RNG_INTERRUPT);
+    run_until_idle(kernel, &platform, chip);
+    assert_eq!(
+        info.number_interrupt_services(&process_management_cap),
+        services + 1
+    );
+
+    assert_eq!(
+        kernel.reset(&process_management_cap),
+        ReturnCode::ENOSUPPORT
+    );
+    kernel.set_reset_function(record_reset, &process_management_cap);
+    assert_eq!(kernel.reset(&process_management_cap), ReturnCode::FAIL);
+    assert!(RESET_REQUESTED.load(Ordering::SeqCst));
+}
+
+#[test]
+fn memory_quotas_limit_brk_and_sbrk() {
+    let process_management_cap = TestCapabilities;
+    let (kernel, processes) = create_kernel();
+
+    // Processes start with 3 kB of app memory, so both can grow by 256 bytes
+    // but not by another 512 bytes, and can always shrink again.
+    let sbrk = |increment: isize| Syscall::MEMOP {
+        operand: 1,
+        arg0: increment as usize,
+    };
+    let brk = |offset: usize| Syscall::MEMOP {
+        operand: 0,
+        arg0: offset,
+    };
+    let script = vec![sbrk(256), sbrk(512), sbrk(-256), sbrk(512), brk(3592)];
+    let header = leak(ScriptedApp::new(script.clone()));
+    let board = leak(ScriptedApp::new(script));
+    let apps: &'static [&'static dyn HostApp] = leak([header as &dyn HostApp, board]);
+    let chip = leak(HostChip::new(apps, None));
+
+    let header_len = tbf::app_image_with_quota_len("header");
+    let flash_len = header_len + tbf::app_image_len("board");
+    let flash: &'static mut [u8] = Box::leak(vec![0; flash_len].into_boxed_slice());
+    tbf::write_app_image_with_quota(flash, "header", 0, 4096, 3584).unwrap();
+    tbf::write_app_image(&mut flash[header_len..], "board", 1, 4096).unwrap();
+    let processes = load_flash(kernel, chip, processes, flash, FaultResponse::Panic);
+
+    let info = KernelInfo::new(kernel);
+    let header_process = processes[0].unwrap();
+    let board_process = processes[1].unwrap();
+    assert_eq!(header_process.get_memory_quota(), Some(3584));
+    assert_eq!(board_process.get_memory_quota(), None);
+    board_process.set_memory_quota(Some(3584));
+    header_process.set_memory_quota(Some(4096));
+    assert_eq!(
+        info.app_memory_quota(header_process.appid(), &process_management_cap),
+        Some(3584)
+    );
+
+    let platform = TestPlatform::new();
+    run_until_idle(kernel, &platform, chip);
+
+    let esize = isize::from(ReturnCode::ESIZE);
+    for (app, process) in [(header, header_process), (board, board_process)].iter() {
+        let initial_break = (app.memory_start() + 3072) as isize;
+        assert_eq!(
+            *app.returns.borrow(),
+            [
+                initial_break,
+                esize,
+                initial_break + 256,
+                initial_break,
+                esize
+            ]
+        );
+        assert_eq!(
+            info.number_app_memory_quota_violations(process.appid(), &process_management_cap),
+            2
+        );
+        let layout = info
+            .process_memory_layout(process.appid(), &process_management_cap)
+            .unwrap();
+        assert_eq!(layout.app_break, app.memory_start() + 3584);
+    }
+}
+
+#[test]
+fn pooled_processes_grow_and_shrink() {
+    static RESTART: AlwaysRestart = AlwaysRestart::new();
+
+    let process_management_cap = TestCapabilities;
+    let (kernel, processes) = create_kernel();
+    let counter = leak(Counter {
+        apps: create_grant(kernel),
+    });
+
+    // Memops 3 and 6 return the end of process memory and the start of the
+    // grant region.
+    let memop = |operand: usize, arg0: isize| Syscall::MEMOP {
+        operand: operand,
+        arg0: arg0 as usize,
+    };
+    let count = command(COUNTER_DRIVER_NUM, 0, 0);
+    let script: &'static [Syscall] = leak([
+        count,
+        memop(3, 0),
+        memop(6, 0),
+        memop(1, 8192),
+        memop(3, 0),
+        memop(6, 0),
+        count,
+        memop(1, -8192),
+        memop(3, 0),
+        memop(6, 0),
+        count,
+        memop(1, 1 << 20),
+        memop(1, 8192),
+    ]);
+
+    // The last process in the pool runs the script, faults, and yields after
+    // it is restarted.
+    let returns = leak(RefCell::new(Vec::new()));
+    let starts = leak(Cell::new(0));
+    let next = leak(Cell::new(0));
+    let grower = move |resumption| {
+        match resumption {
+            Resumption::Start { .. } => {
+                starts.set(starts.get() + 1);
+                next.set(0);
+            }
+            Resumption::Return(return_value) => returns.borrow_mut().push(return_value),
+            Resumption::Callback { .. } => {}
+        }
+        let step = next.get();
+        next.set(step + 1);
+        match script.get(step) {
+            _ if starts.get() > 1 => HostAction::Syscall(Syscall::YIELD),
+            Some(&syscall) => HostAction::Syscall(syscall),
+            None => HostAction::Fault,
+        }
+    };
+    // Only the last process in the pool can grow: the process before it
+    // cannot grow into the memory of the next process.
+    let blocked = leak(ScriptedApp::new(vec![memop(1, 8192)]));
+    let apps: &'static [&'static dyn HostApp] = leak([blocked as &dyn HostApp, leak(grower)]);
+    let chip = leak(HostChip::new(apps, None));
+    let processes = load_apps_from_pool(
+        kernel,
+        chip,
+        processes,
+        &[("blocked", 0), ("grower", 1)],
+        FaultResponse::Restart(&RESTART),
+    );
+
+    let platform = TestPlatform::new().driver(COUNTER_DRIVER_NUM, counter);
+    run_iterations(kernel, &platform, chip, 3);
+
+    let enomem = isize::from(ReturnCode::ENOMEM);
+    assert_eq!(*blocked.returns.borrow(), [enomem]);
+
+    let returns = returns.borrow();
+    let (memory_end, grant_start, app_break) = (returns[1], returns[2], returns[3]);
+    assert_eq!(returns[0], 1);
+
+    // Growing moves the grant region to the new end of memory, and the
+    // counter in it along with it.
+    let (grown_end, grown_grant_start) = (returns[4], returns[5]);
+    assert!(grown_grant_start >= app_break + 8192);
+    assert_eq!(grown_grant_start - grant_start, grown_end - memory_end);
+    assert_eq!((grown_grant_start - grant_start) % 8, 0);
+    assert_eq!(returns[6], 2);
+
+    // Shrinking moves it back.
+    assert_eq!(returns[7], app_break + 8192);
+    assert_eq!((returns[8], returns[9]), (memory_end, grant_start));
+    assert_eq!(returns[10], 3);
+
+    // The pool is exhausted, but the process can still grow into the rest of
+    // it.
+    assert_eq!(returns[11], enomem);
+    assert_eq!(returns[12], app_break);
+
+    // The memory is returned to the pool when the process is restarted, and
+    // its grant is freed.
+    let info = KernelInfo::new(kernel);
+    let process = processes[1].unwrap();
+    assert_eq!(starts.get(), 2);
+    assert_eq!(process.get_restart_count(), 1);
+    let layout = info
+        .process_memory_layout(process.appid(), &process_management_cap)
+        .unwrap();
+    assert_eq!(layout.memory_end as isize, memory_end);
+    assert_eq!(layout.grant_start as isize, memory_end);
+    assert_eq!(layout.app_break as isize, app_break);
+}
+
+#[test]
+fn pooled_processes_are_resized_in_mpu_steps() {
+    static RESTART: AlwaysRestart = AlwaysRestart::new();
+
+    let process_management_cap = TestCapabilities;
+    let (kernel, processes) = create_kernel();
+
+    // Memops 2 and 3 return the start and the end of process memory.
+    let memop = |operand: usize, arg0: isize| Syscall::MEMOP {
+        operand: operand,
+        arg0: arg0 as usize,
+    };
+    let script: &'static [Syscall] = leak([memop(2, 0), memop(3, 0), memop(1, 3000), memop(3, 0)]);
+    let returns = leak(RefCell::new(Vec::new()));
+    let starts = leak(Cell::new(0));
+    let next = leak(Cell::new(0));
+    let grower = move |resumption| {
+        match resumption {
+            Resumption::Start { .. } => {
+                starts.set(starts.get() + 1);
+                next.set(0);
+            }
+            Resumption::Return(return_value) => returns.borrow_mut().push(return_value),
+            Resumption::Callback { .. } => {}
+        }
+        let step = next.get();
+        next.set(step + 1);
+        match script.get(step) {
+            _ if starts.get() > 1 => HostAction::Syscall(Syscall::YIELD),
+            Some(&syscall) => HostAction::Syscall(syscall),
+            None => HostAction::Fault,
+        }
+    };
+    let apps: &'static [&'static dyn HostApp] = leak([leak(grower) as &dyn HostApp]);
+    let chip = leak(HostChip::new_with_mpu(apps, None, MockMpu::new(1024)));
+    let processes = load_apps_from_pool(
+        kernel,
+        chip,
+        processes,
+        &[("grower", 0)],
+        FaultResponse::Restart(&RESTART),
+    );
+
+    let platform = TestPlatform::new();
+    run_iterations(kernel, &platform, chip, 3);
+
+    // The block only grows in steps the MPU can protect.
+    let returns = returns.borrow();
+    let (memory_start, memory_end, app_break) = (returns[0], returns[1], returns[2]);
+    assert_eq!((memory_end - memory_start) % 1024, 0);
+    assert!(returns[3] >= app_break + 3000);
+    assert_eq!((returns[3] - memory_start) % 1024, 0);
+
+    // Once the process is restarted, its block, its grant region and the MPU
+    // region are back to how they were when it was loaded.
+    let info = KernelInfo::new(kernel);
+    let process = processes[0].unwrap();
+    assert_eq!(starts.get(), 2);
+    let layout = info
+        .process_memory_layout(process.appid(), &process_management_cap)
+        .unwrap();
+    assert_eq!(layout.memory_end as isize, memory_end);
+    assert_eq!(layout.grant_start as isize, memory_end);
+    assert_eq!(layout.app_break as isize, app_break);
+    assert_eq!(
+        chip.mpu().configured_region(),
+        Some(MockRegion {
+            start: memory_start as usize,
+            size: (memory_end - memory_start) as usize,
+            app_break: app_break as usize,
+        })
+    );
+}
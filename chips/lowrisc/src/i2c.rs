@@ -394,6 +394,14 @@ impl<'a> hil::i2c::I2CMaster for I2c<'a> {
         regs.ctrl.modify(CTRL::ENABLEHOST::CLEAR);
     }
 
+    fn abort(&self) -> Option<&'static mut [u8]> {
+        // Re-enabling the host also resets the FIFOs.
+        self.disable();
+        self.enable();
+        self.slave_read_address.set(0);
+        self.buffer.take()
+    }
+
     fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
         let regs = self.registers;
 
@@ -494,3 +502,39 @@ impl<'a> hil::i2c::I2CMaster for I2c<'a> {
         self.read_data();
     }
 }
+
+/// The controller has no SMBus specific hardware, but SMBus transfers look
+/// like I2C transfers on the bus. The client computes and checks any PEC
+/// bytes.
+impl<'a> hil::i2c::SMBusMaster for I2c<'a> {
+    fn smbus_write_read(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: u8,
+        read_len: u8,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        hil::i2c::I2CMaster::write_read(self, addr, data, write_len, read_len);
+        Ok(())
+    }
+
+    fn smbus_write(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        len: u8,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        hil::i2c::I2CMaster::write(self, addr, data, len);
+        Ok(())
+    }
+
+    fn smbus_read(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        len: u8,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        hil::i2c::I2CMaster::read(self, addr, buffer, len);
+        Ok(())
+    }
+}
@@ -133,6 +133,16 @@ impl hil::i2c::I2CMaster for TWIM {
         self.disable();
     }
 
+    fn abort(&self) -> Option<&'static mut [u8]> {
+        self.registers
+            .intenclr
+            .write(INTE::STOPPED::SET + INTE::ERROR::SET);
+        self.registers.tasks_stop.write(TASK::TASK::SET);
+        self.disable();
+        self.enable();
+        self.buf.take()
+    }
+
     fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
         self.registers
             .address
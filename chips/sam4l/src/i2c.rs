@@ -1330,6 +1330,18 @@ impl hil::i2c::I2CMaster for I2CHw {
         self.disable_interrupts(twim);
     }
 
+    /// Stop the DMA transfer and reset the peripheral.
+    fn abort(&self) -> Option<&'static mut [u8]> {
+        hil::i2c::I2CMaster::disable(self);
+        let buffer = self.dma.and_then(|dma| {
+            let buffer = dma.abort_transfer();
+            dma.disable();
+            buffer
+        });
+        hil::i2c::I2CMaster::enable(self);
+        buffer
+    }
+
     fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
         I2CHw::write(
             self,
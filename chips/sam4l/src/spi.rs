@@ -662,6 +662,28 @@ impl spi::SpiSlave for SpiHw {
         self.read_write_bytes(write_buffer, read_buffer, len)
     }
 
+    /// Stop both DMA channels and return their buffers. Nothing is returned
+    /// if the transfer already completed.
+    fn abort_transfer(&self) -> (Option<&'static mut [u8]>, Option<&'static mut [u8]>) {
+        if self.transfers_in_progress.get() == 0 {
+            return (None, None);
+        }
+        self.transfers_in_progress.set(0);
+        self.dma_length.set(0);
+        let txbuf = self.dma_write.map_or(None, |dma| {
+            let buf = dma.abort_transfer();
+            dma.disable();
+            buf
+        });
+        let rxbuf = self.dma_read.map_or(None, |dma| {
+            let buf = dma.abort_transfer();
+            dma.disable();
+            buf
+        });
+        self.disable();
+        (txbuf, rxbuf)
+    }
+
     fn set_clock(&self, polarity: ClockPolarity) {
         self.set_clock(polarity);
     }
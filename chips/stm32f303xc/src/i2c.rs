@@ -475,6 +475,13 @@ impl i2c::I2CMaster for I2C<'_> {
     fn disable(&self) {
         self.registers.cr1.modify(CR1::PE::CLEAR);
     }
+    fn abort(&self) -> Option<&'static mut [u8]> {
+        // Clearing PE resets the communication state of the peripheral.
+        self.registers.cr1.modify(CR1::PE::CLEAR);
+        self.status.set(I2CStatus::Idle);
+        self.registers.cr1.modify(CR1::PE::SET);
+        self.buffer.take()
+    }
     fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
         if self.status.get() == I2CStatus::Idle {
             self.reset();
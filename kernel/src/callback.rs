@@ -119,6 +119,13 @@ impl AppId {
         self.identifier
     }
 
+    /// Returns the name of the app this `AppId` refers to, or an empty string
+    /// if the app no longer exists.
+    pub fn get_process_name(&self) -> &'static str {
+        self.kernel
+            .process_map_or("", *self, |process| process.get_process_name())
+    }
+
     /// Returns the full address of the start and end of the flash region that
     /// the app owns and can write to. This includes the app's code and data and
     /// any padding at the end of the app. It does not include the TBF header,
@@ -99,6 +99,7 @@ pub struct DynamicDeferredCall {
     client_states: &'static [DynamicDeferredCallClientState],
     handle_counter: Cell<usize>,
     call_pending: Cell<bool>,
+    call_count: Cell<usize>,
 }
 
 impl DynamicDeferredCall {
@@ -115,6 +116,7 @@ impl DynamicDeferredCall {
             client_states,
             handle_counter: Cell::new(0),
             call_pending: Cell::new(false),
+            call_count: Cell::new(0),
         }
     }
 
@@ -156,6 +158,19 @@ impl DynamicDeferredCall {
         DYNAMIC_DEFERRED_CALL.map(|ddc| ddc.has_pending())
     }
 
+    /// Returns the number of deferred calls the global instance has made, its
+    /// number of registered clients and its capacity for clients, or `None` if
+    /// no global instance was set.
+    pub unsafe fn global_instance_statistics() -> Option<(usize, usize, usize)> {
+        DYNAMIC_DEFERRED_CALL.map(|ddc| {
+            (
+                ddc.call_count.get(),
+                ddc.handle_counter.get(),
+                ddc.client_states.len(),
+            )
+        })
+    }
+
     /// Schedule a deferred call to be called
     ///
     /// The handle addresses the client that will be called.
@@ -233,6 +248,7 @@ impl DynamicDeferredCall {
                 if client_state.scheduled.get() {
                     client_state.client.map(|client| {
                         client_state.scheduled.set(false);
+                        self.call_count.set(self.call_count.get() + 1);
                         client.call(DeferredCallHandle(i));
                     });
                 }
@@ -509,6 +509,15 @@ pub fn begin_debug_fmt(args: Arguments) {
     writer.publish_bytes();
 }
 
+/// Writes to the debug output without ending the line, so that output can be
+/// assembled from several pieces.
+pub fn debug_write_fmt(args: Arguments) {
+    let writer = unsafe { get_debug_writer() };
+
+    let _ = write(writer, args);
+    writer.publish_bytes();
+}
+
 pub fn begin_debug_verbose_fmt(args: Arguments, file_line: &(&'static str, u32)) {
     let writer = unsafe { get_debug_writer() };
 
@@ -58,6 +58,11 @@ pub trait I2CMaster {
     fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8);
     fn write(&self, addr: u8, data: &'static mut [u8], len: u8);
     fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8);
+
+    /// Abort the transfer in progress, if any, and return its buffer. The
+    /// client's `command_complete` is not called for an aborted transfer, and
+    /// the controller is left enabled and ready for the next transfer.
+    fn abort(&self) -> Option<&'static mut [u8]>;
 }
 
 /// Interface for an SMBus Master hardware driver.
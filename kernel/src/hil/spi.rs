@@ -179,6 +179,16 @@ pub trait SpiSlave {
         len: usize,
     ) -> ReturnCode;
 
+    /// Cancel the transfer set up by `read_write_bytes`, if the master has
+    /// not completed it yet, and return its write and read buffers. The
+    /// client's `read_write_done` is not called for a cancelled transfer.
+    ///
+    /// The default implementation cannot cancel transfers and returns no
+    /// buffers, in which case the transfer completes as usual.
+    fn abort_transfer(&self) -> (Option<&'static mut [u8]>, Option<&'static mut [u8]>) {
+        (None, None)
+    }
+
     fn set_clock(&self, polarity: ClockPolarity);
     fn get_clock(&self) -> ClockPolarity;
     fn set_phase(&self, phase: ClockPhase);
@@ -204,6 +214,16 @@ pub trait SpiSlaveDevice {
         len: usize,
     ) -> ReturnCode;
 
+    /// Cancel the outstanding read/write operation, if any, and return its
+    /// write and read buffers. SpiSlaveClient.read_write_done is not invoked
+    /// for a cancelled operation.
+    ///
+    /// The default implementation cannot cancel operations and returns no
+    /// buffers, in which case the operation completes as usual.
+    fn abort_transfer(&self) -> (Option<&'static mut [u8]>, Option<&'static mut [u8]>) {
+        (None, None)
+    }
+
     fn set_polarity(&self, cpol: ClockPolarity);
     fn get_polarity(&self) -> ClockPolarity;
     fn set_phase(&self, cpal: ClockPhase);
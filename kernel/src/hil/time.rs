@@ -1,8 +1,53 @@
 //! Hardware agnostic interfaces for counter-like resources.
 
+use core::fmt;
+use core::ops::BitAnd;
+
 use crate::ReturnCode;
 
-pub trait Time<W = u32> {
+/// An unsigned integer type that holds clock values.
+///
+/// Clocks narrower than the type, such as 24-bit counters stored in a `u32`,
+/// are supported through [`Time`](trait.Time.html), which knows where the
+/// clock wraps around.
+pub trait Ticks: Copy + Ord + Default + BitAnd<Output = Self> + fmt::Debug {
+    fn wrapping_add(self, other: Self) -> Self;
+
+    fn wrapping_sub(self, other: Self) -> Self;
+
+    /// Converts to `usize`, keeping only the low bits if `usize` is narrower.
+    fn into_usize(self) -> usize;
+
+    /// Converts from `usize`, keeping only the low bits if `Self` is
+    /// narrower.
+    fn from_usize(value: usize) -> Self;
+}
+
+macro_rules! impl_ticks {
+    ($($t:ty),*) => {$(
+        impl Ticks for $t {
+            fn wrapping_add(self, other: Self) -> Self {
+                <$t>::wrapping_add(self, other)
+            }
+
+            fn wrapping_sub(self, other: Self) -> Self {
+                <$t>::wrapping_sub(self, other)
+            }
+
+            fn into_usize(self) -> usize {
+                self as usize
+            }
+
+            fn from_usize(value: usize) -> Self {
+                value as $t
+            }
+        }
+    )*};
+}
+
+impl_ticks!(u32, u64);
+
+pub trait Time<W: Ticks = u32> {
     type Frequency: Frequency;
 
     /// Returns the current time in hardware clock units.
@@ -11,11 +56,24 @@ pub trait Time<W = u32> {
     /// Returns the wrap-around value of the clock.
     ///
     /// The maximum value of the clock, at which `now` will wrap around. I.e., this should return
-    /// `core::u32::MAX` on a 32-bit-clock, or `(1 << 24) - 1` for a 24-bit clock.
+    /// `core::u32::MAX` on a 32-bit-clock, or `(1 << 24) - 1` for a 24-bit clock. The value must
+    /// be one less than a power of two.
     fn max_tics(&self) -> W;
+
+    /// Returns the number of tics the clock moves forward from `from` to reach `to`, taking
+    /// wrap-around at [`max_tics`](#tymethod.max_tics) into account.
+    fn tics_between(&self, from: W, to: W) -> W {
+        to.wrapping_sub(from) & self.max_tics()
+    }
+
+    /// Returns the clock value `tics` after `time`, taking wrap-around at
+    /// [`max_tics`](#tymethod.max_tics) into account.
+    fn add_tics(&self, time: W, tics: W) -> W {
+        time.wrapping_add(tics) & self.max_tics()
+    }
 }
 
-pub trait Counter<W = u32>: Time<W> {
+pub trait Counter<W: Ticks = u32>: Time<W> {
     fn start(&self) -> ReturnCode;
     fn stop(&self) -> ReturnCode;
     fn is_running(&self) -> bool;
@@ -73,7 +131,7 @@ impl Frequency for Freq1KHz {
 /// (usually clock tics). Implementers should use the
 /// [`Client`](trait.Client.html) trait to signal when the counter has
 /// reached a pre-specified value set in [`set_alarm`](#tymethod.set_alarm).
-pub trait Alarm<'a, W = u32>: Time<W> {
+pub trait Alarm<'a, W: Ticks = u32>: Time<W> {
     /// Sets a one-shot alarm to fire when the clock reaches `tics`.
     ///
     /// [`Client#fired`](trait.Client.html#tymethod.fired) is signaled
@@ -123,7 +181,7 @@ pub trait AlarmClient {
 
 /// The `Timer` trait models a timer that can notify when a particular interval
 /// has elapsed.
-pub trait Timer<'a, W = u32>: Time<W> {
+pub trait Timer<'a, W: Ticks = u32>: Time<W> {
     /// Set the client for interrupt events.
     fn set_client(&'a self, client: &'a dyn TimerClient);
 
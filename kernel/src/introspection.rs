@@ -15,9 +15,26 @@ use core::cell::Cell;
 use crate::callback::AppId;
 use crate::capabilities::ProcessManagementCapability;
 use crate::common::cells::NumericCellExt;
+use crate::common::dynamic_deferred_call::DynamicDeferredCall;
 use crate::process;
 use crate::sched::Kernel;
 
+/// Where a process lives in flash and memory.
+///
+/// In flash, the process occupies `flash_start..flash_end`, with its TBF header
+/// before `flash_app_start`. In memory, it can access `memory_start..app_break`,
+/// and the kernel keeps the process's grants in `grant_start..memory_end`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProcessMemoryLayout {
+    pub flash_start: usize,
+    pub flash_app_start: usize,
+    pub flash_end: usize,
+    pub memory_start: usize,
+    pub app_break: usize,
+    pub grant_start: usize,
+    pub memory_end: usize,
+}
+
 /// This struct provides the inspection functions.
 pub struct KernelInfo {
     kernel: &'static Kernel,
@@ -123,6 +140,29 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_timeslice_expiration_count())
     }
 
+    /// Returns the number of times this app was denied a `brk` or `sbrk`
+    /// because it would have exceeded the app's memory quota.
+    pub fn number_app_memory_quota_violations(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel.process_map_or(0, app, |process| {
+            process.debug_memory_quota_exceeded_count()
+        })
+    }
+
+    /// Returns the memory quota enforced for this app in bytes, or `None` if
+    /// the app has no quota.
+    pub fn app_memory_quota(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<usize> {
+        self.kernel
+            .process_map_or(None, app, |process| process.get_memory_quota())
+    }
+
     /// Returns a tuple of the (the number of grants in the grant region this
     /// app has allocated, total number of grants that exist in the system).
     pub fn number_app_grant_uses(
@@ -159,4 +199,50 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns the flash and memory layout of the process, or `None` if the
+    /// process no longer exists.
+    pub fn process_memory_layout(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<ProcessMemoryLayout> {
+        self.kernel.process_map_or(None, app, |process| {
+            Some(ProcessMemoryLayout {
+                flash_start: process.flash_start() as usize,
+                flash_app_start: process.flash_non_protected_start() as usize,
+                flash_end: process.flash_end() as usize,
+                memory_start: process.mem_start() as usize,
+                app_break: process.app_memory_break() as usize,
+                grant_start: process.kernel_memory_break() as usize,
+                memory_end: process.mem_end() as usize,
+            })
+        })
+    }
+
+    /// Returns how many times the kernel loop has found pending interrupts and
+    /// serviced them.
+    pub fn number_interrupt_services(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel.interrupt_service_count()
+    }
+
+    /// Returns how many dynamic deferred calls have been delivered to their
+    /// clients, or 0 if the board does not use dynamic deferred calls.
+    pub fn number_deferred_calls(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        unsafe { DynamicDeferredCall::global_instance_statistics() }
+            .map_or(0, |(calls, _, _)| calls)
+    }
+
+    /// Returns a tuple of (the number of registered dynamic deferred call
+    /// clients, the number of clients that can be registered).
+    pub fn deferred_call_clients(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> (usize, usize) {
+        unsafe { DynamicDeferredCall::global_instance_statistics() }
+            .map_or((0, 0), |(_, used, total)| (used, total))
+    }
 }
@@ -54,8 +54,8 @@ pub use crate::sched::Kernel;
 /// Publicly available process-related objects.
 pub mod procs {
     pub use crate::process::{
-        load_processes, AlwaysRestart, Error, FaultResponse, FunctionCall, Process,
-        ProcessLoadError, ProcessRestartPolicy, ProcessType, ThresholdRestart,
-        ThresholdRestartThenPanic,
+        load_processes, load_processes_from_pool, AlwaysRestart, Error, FaultResponse,
+        FunctionCall, Process, ProcessLoadError, ProcessRestartPolicy, ProcessType, State,
+        ThresholdRestart, ThresholdRestartThenPanic,
     };
 }
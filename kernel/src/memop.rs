@@ -1,6 +1,6 @@
 //! Implementation of the MEMOP family of syscalls.
 
-use crate::process::ProcessType;
+use crate::process::{Error, ProcessType};
 use crate::returncode::ReturnCode;
 
 /// Handle the `memop` syscall.
@@ -8,12 +8,15 @@ use crate::returncode::ReturnCode;
 /// ### `memop_num`
 ///
 /// - `0`: BRK. Change the location of the program break and return a
-///   ReturnCode.
+///   ReturnCode. Returns `ESIZE` if the new break would exceed the process's
+///   memory quota.
 /// - `1`: SBRK. Change the location of the program break and return the
-///   previous break address.
+///   previous break address. Returns `ESIZE` if the new break would exceed
+///   the process's memory quota.
 /// - `2`: Get the address of the start of the application's RAM allocation.
 /// - `3`: Get the address pointing to the first address after the end of the
-///   application's RAM allocation.
+///   application's RAM allocation. For processes loaded from a memory pool
+///   this moves as BRK and SBRK grow or shrink the allocation.
 /// - `4`: Get the address of the start of the application's flash region. This
 ///   is where the TBF header is located.
 /// - `5`: Get the address pointing to the first address after the end of the
@@ -41,15 +44,13 @@ pub(crate) fn memop(process: &dyn ProcessType, op_type: usize, r1: usize) -> Ret
         // Op Type 0: BRK
         0 /* BRK */ => {
             process.brk(r1 as *const u8)
-                .map(|_| ReturnCode::SUCCESS)
-                .unwrap_or(ReturnCode::ENOMEM)
+                .map_or_else(memory_error, |_| ReturnCode::SUCCESS)
         },
 
         // Op Type 1: SBRK
         1 /* SBRK */ => {
             process.sbrk(r1 as isize)
-                .map(|addr| ReturnCode::SuccessWithValue { value: addr as usize })
-                .unwrap_or(ReturnCode::ENOMEM)
+                .map_or_else(memory_error, |addr| ReturnCode::SuccessWithValue { value: addr as usize })
         },
 
         // Op Type 2: Process memory start
@@ -111,3 +112,13 @@ pub(crate) fn memop(process: &dyn ProcessType, op_type: usize, r1: usize) -> Ret
         _ => ReturnCode::ENOSUPPORT,
     }
 }
+
+/// Convert a failed `brk` or `sbrk` into the error returned to the process.
+/// Going over the memory quota is reported distinctly so that processes can
+/// tell it apart from actually running out of memory.
+fn memory_error(err: Error) -> ReturnCode {
+    match err {
+        Error::QuotaExceeded => ReturnCode::ESIZE,
+        _ => ReturnCode::ENOMEM,
+    }
+}
@@ -241,6 +241,47 @@ pub trait MPU {
         }
     }
 
+    /// Resizes the memory block previously chosen for a process by
+    /// `allocate_app_memory_region`, without moving its start.
+    ///
+    /// An implementation must choose a new block size of at least
+    /// `min_memory_size` bytes that does not exceed `available_memory_size`
+    /// bytes, and update the MPU region for app-owned memory stored in
+    /// `config` so that it covers memory up to `app_memory_break` and does not
+    /// overlap the last `kernel_memory_size` bytes of the resized block.
+    ///
+    /// This is only used for processes whose memory is sized dynamically from
+    /// a shared pool, see `load_processes_from_pool()`.
+    ///
+    /// # Arguments
+    ///
+    /// - `memory_start`:          start of the process memory block
+    /// - `available_memory_size`: maximum size the block may grow to
+    /// - `min_memory_size`:       minimum size of the resized block
+    /// - `app_memory_break`:      address for the end of app-owned memory
+    /// - `kernel_memory_size`:    size of kernel-owned memory at the block end
+    /// - `permissions`:           permissions for the MPU region
+    /// - `config`:                MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns the new size of the memory block. If the block cannot be
+    /// resized in place, returns None and leaves `config` unchanged. MPUs that
+    /// do not implement this never resize blocks.
+    #[allow(unused_variables)]
+    fn resize_app_memory_region(
+        &self,
+        memory_start: *const u8,
+        available_memory_size: usize,
+        min_memory_size: usize,
+        app_memory_break: *const u8,
+        kernel_memory_size: usize,
+        permissions: Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Option<usize> {
+        None
+    }
+
     /// Configures the MPU with the provided region configuration.
     ///
     /// An implementation must ensure that all memory locations not covered by
@@ -256,4 +297,22 @@ pub trait MPU {
 }
 
 /// Implement default MPU trait for unit.
-impl MPU for () {}
+impl MPU for () {
+    /// Without memory protection, a memory block can take any size that fits.
+    fn resize_app_memory_region(
+        &self,
+        _memory_start: *const u8,
+        available_memory_size: usize,
+        min_memory_size: usize,
+        _app_memory_break: *const u8,
+        _kernel_memory_size: usize,
+        _permissions: Permissions,
+        _config: &mut Self::MpuConfig,
+    ) -> Option<usize> {
+        if min_memory_size > available_memory_size {
+            None
+        } else {
+            Some(min_memory_size)
+        }
+    }
+}
@@ -21,7 +21,7 @@ use crate::returncode::ReturnCode;
 use crate::sched::Kernel;
 use crate::syscall::{self, Syscall, UserspaceKernelBoundary};
 use crate::tbfheader;
-use core::cmp::max;
+use core::cmp::{self, max};
 
 /// Errors that can occur when trying to load and create processes.
 pub enum ProcessLoadError {
@@ -132,11 +132,78 @@ pub fn load_processes<C: Chip>(
     procs: &'static mut [Option<&'static dyn ProcessType>],
     fault_response: FaultResponse,
     _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_advanced(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_response,
+        false,
+    )
+}
+
+/// Helper function to load processes whose memory is sized dynamically from a
+/// shared pool.
+///
+/// Processes are found and created exactly as with `load_processes()`, but
+/// each process only gets the memory it requests in its TBF header up front.
+/// The rest of `app_memory` forms a pool that processes grow into when `brk`
+/// or `sbrk` moves their app break past the memory they already have, and
+/// that they shrink back into when they lower their app break. A process can
+/// only grow into free memory directly after its own memory block, and only in
+/// increments the MPU can protect without moving the start of the block. When
+/// a process is restarted or stopped after a fault, any memory it grew into is
+/// returned to the pool.
+///
+/// Memory blocks never move and processes are placed one after another, in
+/// the order they are found in flash, so the free part of the pool is always
+/// directly after the last process loaded. That process is the only one that
+/// can grow; the others keep the memory their TBF header asks for.
+pub fn load_processes_from_pool<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8],
+    procs: &'static mut [Option<&'static dyn ProcessType>],
+    fault_response: FaultResponse,
+    _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_advanced(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_response,
+        true,
+    )
+}
+
+/// Shared implementation of `load_processes()` and
+/// `load_processes_from_pool()`.
+fn load_processes_advanced<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8],
+    procs: &'static mut [Option<&'static dyn ProcessType>],
+    fault_response: FaultResponse,
+    use_memory_pool: bool,
 ) -> Result<(), ProcessLoadError> {
     let mut remaining_flash = app_flash;
     let mut app_memory_ptr = app_memory.as_mut_ptr();
     let mut app_memory_size = app_memory.len();
 
+    // All processes loaded from a pool can grow up to the end of the memory
+    // reserved for processes.
+    let memory_pool_end = if use_memory_pool {
+        Some(app_memory.as_ptr().wrapping_add(app_memory.len()))
+    } else {
+        None
+    };
+
     if config::CONFIG.debug_load_processes {
         debug!(
             "Loading processes from flash={:#010X} into sram=[{:#010X}:{:#010X}]",
@@ -195,6 +262,7 @@ pub fn load_processes<C: Chip>(
                 version,
                 app_memory_ptr,
                 app_memory_size,
+                memory_pool_end,
                 fault_response,
                 i,
             )?;
@@ -230,6 +298,10 @@ pub fn load_processes<C: Chip>(
     Ok(())
 }
 
+/// Grant regions of processes loaded from a memory pool are only ever moved by
+/// a multiple of this many bytes, so that they stay aligned.
+const GRANT_MOVE_ALIGNMENT: usize = 8;
+
 /// This trait is implemented by process structs.
 pub trait ProcessType {
     /// Returns the process's identifier
@@ -308,6 +380,17 @@ pub trait ProcessType {
     /// the memory pointers is not valid at this point.
     fn sbrk(&self, increment: isize) -> Result<*const u8, Error>;
 
+    /// Limit how much RAM the process may make accessible to itself through
+    /// `brk` and `sbrk`, measured in bytes from the start of its RAM region.
+    /// Passing `None` removes any limit set by the board.
+    ///
+    /// If the process also requested a quota in its TBF header, the smaller
+    /// of the two quotas is enforced.
+    fn set_memory_quota(&self, quota: Option<usize>);
+
+    /// The memory quota currently enforced for this process, if any.
+    fn get_memory_quota(&self) -> Option<usize>;
+
     /// The start address of allocated RAM for this process.
     fn mem_start(&self) -> *const u8;
 
@@ -324,6 +407,10 @@ pub trait ProcessType {
     /// The lowest address of the grant region for the process.
     fn kernel_memory_break(&self) -> *const u8;
 
+    /// The first address after the memory the process can access, i.e. its
+    /// program break.
+    fn app_memory_break(&self) -> *const u8;
+
     /// How many writeable flash regions defined in the TBF header for this
     /// process.
     fn number_writeable_flash_regions(&self) -> usize;
@@ -458,6 +545,10 @@ pub trait ProcessType {
     /// Returns how many times this process has exceeded its timeslice.
     fn debug_timeslice_expiration_count(&self) -> usize;
 
+    /// Returns how many `brk` or `sbrk` calls from this process were denied
+    /// because they would have exceeded the process's memory quota.
+    fn debug_memory_quota_exceeded_count(&self) -> usize;
+
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
@@ -540,6 +631,9 @@ pub enum Error {
     NoSuchApp,
     OutOfMemory,
     AddressOutOfBounds,
+    /// The request would grow the process's accessible memory past the memory
+    /// quota set for it.
+    QuotaExceeded,
     /// The process is inactive (likely in a fault or exit state) and the
     /// attempted operation is therefore invalid.
     InactiveApp,
@@ -553,6 +647,7 @@ impl From<Error> for ReturnCode {
         match err {
             Error::OutOfMemory => ReturnCode::ENOMEM,
             Error::AddressOutOfBounds => ReturnCode::EINVAL,
+            Error::QuotaExceeded => ReturnCode::ESIZE,
             Error::NoSuchApp => ReturnCode::EINVAL,
             Error::InactiveApp => ReturnCode::FAIL,
             Error::KernelError => ReturnCode::FAIL,
@@ -690,6 +785,10 @@ struct ProcessDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many times a `brk` or `sbrk` call was denied because of the
+    /// process's memory quota.
+    memory_quota_exceeded_count: usize,
 }
 
 /// A type for userspace processes in Tock.
@@ -736,8 +835,30 @@ pub struct Process<'a, C: 'static + Chip> {
     ///  ╚═ ╘════════ ← memory[0]               ═╝
     /// ```
     ///
-    /// The process's memory.
-    memory: &'static mut [u8],
+    /// Processes loaded from a memory pool keep the grant pointers and the
+    /// process control block directly below `memory[0]`, outside of the memory
+    /// block, so that only the grant regions have to move when the end of the
+    /// block moves.
+    ///
+    /// The start of the process's memory.
+    memory_start: *const u8,
+
+    /// The number of bytes of memory currently allocated to the process. This
+    /// only changes for processes loaded from a memory pool.
+    memory_len: Cell<usize>,
+
+    /// How many bytes of memory the process was allocated when it was created.
+    /// Processes loaded from a memory pool shrink back to this size when they
+    /// are restarted or stopped.
+    original_memory_len: usize,
+
+    /// The end of the memory pool this process can grow its memory into, if
+    /// the process was loaded with `load_processes_from_pool()`.
+    memory_pool_end: Option<*const u8>,
+
+    /// Pointer to the end of the array of grant pointers. For processes with
+    /// fixed-size memory this is the end of process memory.
+    grant_pointers_end: *mut *mut u8,
 
     /// Pointer to the end of the allocated (and MPU protected) grant region.
     kernel_memory_break: Cell<*const u8>,
@@ -751,6 +872,11 @@ pub struct Process<'a, C: 'static + Chip> {
     app_break: Cell<*const u8>,
     original_app_break: *const u8,
 
+    /// Maximum number of bytes from the start of process memory to
+    /// `app_break` as configured by the board. `None` means no quota beyond
+    /// any quota requested in the TBF header.
+    memory_quota: Cell<Option<usize>>,
+
     /// Pointer to high water mark for process buffers shared through `allow`
     allow_high_water_mark: Cell<*const u8>,
     original_allow_high_water_mark: *const u8,
@@ -922,11 +1048,11 @@ impl<C: Chip> ProcessType for Process<'_, C> {
     }
 
     fn mem_start(&self) -> *const u8 {
-        self.memory.as_ptr()
+        self.memory_start
     }
 
     fn mem_end(&self) -> *const u8 {
-        unsafe { self.memory.as_ptr().add(self.memory.len()) }
+        unsafe { self.memory_start.add(self.memory_len.get()) }
     }
 
     fn flash_start(&self) -> *const u8 {
@@ -945,6 +1071,10 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         self.kernel_memory_break.get()
     }
 
+    fn app_memory_break(&self) -> *const u8 {
+        self.app_break.get()
+    }
+
     fn number_writeable_flash_regions(&self) -> usize {
         self.header.number_writeable_flash_regions()
     }
@@ -1028,8 +1158,18 @@ impl<C: Chip> ProcessType for Process<'_, C> {
 
         self.mpu_config
             .map_or(Err(Error::KernelError), |mut config| {
-                if new_break < self.allow_high_water_mark.get() || new_break >= self.mem_end() {
+                // Processes loaded from a memory pool can grow past the end
+                // of their current memory.
+                let memory_limit = self.memory_pool_end.unwrap_or(self.mem_end());
+                if new_break < self.allow_high_water_mark.get() || new_break >= memory_limit {
                     Err(Error::AddressOutOfBounds)
+                } else if self.exceeds_memory_quota(new_break) {
+                    self.debug.map(|debug| {
+                        debug.memory_quota_exceeded_count += 1;
+                    });
+                    Err(Error::QuotaExceeded)
+                } else if let Err(_) = self.resize_memory(new_break, &mut config) {
+                    Err(Error::OutOfMemory)
                 } else if new_break > self.kernel_memory_break.get() {
                     Err(Error::OutOfMemory)
                 } else if let Err(_) = self.chip.mpu().update_app_memory_region(
@@ -1048,6 +1188,18 @@ impl<C: Chip> ProcessType for Process<'_, C> {
             })
     }
 
+    fn set_memory_quota(&self, quota: Option<usize>) {
+        self.memory_quota.set(quota);
+    }
+
+    fn get_memory_quota(&self) -> Option<usize> {
+        let header_quota = self.header.get_memory_quota().map(|q| q as usize);
+        match (self.memory_quota.get(), header_quota) {
+            (Some(board), Some(header)) => Some(cmp::min(board, header)),
+            (board, header) => board.or(header),
+        }
+    }
+
     fn allow(
         &self,
         buf_start_addr: *const u8,
@@ -1131,12 +1283,6 @@ impl<C: Chip> ProcessType for Process<'_, C> {
 
     unsafe fn free(&self, _: *mut u8) {}
 
-    // This is safe today, as MPU constraints ensure that `mem_end` will always
-    // be aligned on at least a word boundary. While this is unlikely to
-    // change, it should be more proactively enforced.
-    //
-    // TODO: https://github.com/tock/tock/issues/1739
-    #[allow(clippy::cast_ptr_alignment)]
     fn get_grant_ptr(&self, grant_num: usize) -> Option<*mut u8> {
         // Do not try to access the grant region of inactive process.
         if !self.is_active() {
@@ -1149,23 +1295,13 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         }
 
         let grant_num = grant_num as isize;
-        let grant_pointer = unsafe {
-            let grant_pointer_array = self.mem_end() as *const *mut u8;
-            *grant_pointer_array.offset(-(grant_num + 1))
-        };
+        let grant_pointer = unsafe { *self.grant_pointers_end.offset(-(grant_num + 1)) };
         Some(grant_pointer)
     }
 
-    // This is safe today, as MPU constraints ensure that `mem_end` will always
-    // be aligned on at least a word boundary. While this is unlikely to
-    // change, it should be more proactively enforced.
-    //
-    // TODO: https://github.com/tock/tock/issues/1739
-    #[allow(clippy::cast_ptr_alignment)]
     unsafe fn set_grant_ptr(&self, grant_num: usize, grant_ptr: *mut u8) {
         let grant_num = grant_num as isize;
-        let grant_pointer_array = self.mem_end() as *mut *mut u8;
-        let grant_pointer_pointer = grant_pointer_array.offset(-(grant_num + 1));
+        let grant_pointer_pointer = self.grant_pointers_end.offset(-(grant_num + 1));
         *grant_pointer_pointer = grant_ptr;
     }
 
@@ -1185,7 +1321,7 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         // First we need to get how much memory is available for this app's
         // stack. Since the stack is at the bottom of the process's memory
         // region, this is straightforward.
-        let remaining_stack_bytes = self.sp() as usize - self.memory.as_ptr() as usize;
+        let remaining_stack_bytes = self.sp() as usize - self.memory_start as usize;
 
         // Next we should see if we can actually add the frame to the process's
         // stack. Architecture-specific code handles actually doing the push
@@ -1288,6 +1424,11 @@ impl<C: Chip> ProcessType for Process<'_, C> {
             .map_or(0, |debug| debug.timeslice_expiration_count)
     }
 
+    fn debug_memory_quota_exceeded_count(&self) -> usize {
+        self.debug
+            .map_or(0, |debug| debug.memory_quota_exceeded_count)
+    }
+
     fn debug_timeslice_expired(&self) {
         self.debug
             .map(|debug| debug.timeslice_expiration_count += 1);
@@ -1309,7 +1450,7 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         let flash_app_size = flash_end - flash_app_start;
 
         // SRAM addresses
-        let sram_end = self.memory.as_ptr().add(self.memory.len()) as usize;
+        let sram_end = self.mem_end() as usize;
         let sram_grant_start = self.kernel_memory_break.get() as usize;
         let sram_heap_end = self.app_break.get() as usize;
         let sram_heap_start: Option<usize> = self.debug.map_or(None, |debug| {
@@ -1321,7 +1462,7 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         let sram_stack_bottom =
             self.debug
                 .map_or(ptr::null(), |debug| debug.min_stack_pointer) as usize;
-        let sram_start = self.memory.as_ptr() as usize;
+        let sram_start = self.memory_start as usize;
 
         // SRAM sizes
         let sram_grant_size = sram_end - sram_grant_start;
@@ -1473,7 +1614,7 @@ impl<C: Chip> ProcessType for Process<'_, C> {
             let _ = writer.write_fmt(format_args!("{}", config));
         });
 
-        let sram_start = self.memory.as_ptr() as usize;
+        let sram_start = self.memory_start as usize;
         let flash_start = self.flash.as_ptr() as usize;
         let flash_init_fn = flash_start + self.header.get_init_function_offset() as usize;
 
@@ -1503,6 +1644,7 @@ impl<C: 'static + Chip> Process<'_, C> {
         app_version: u16,
         remaining_app_memory: *mut u8,
         remaining_app_memory_size: usize,
+        memory_pool_end: Option<*const u8>,
         fault_response: FaultResponse,
         index: usize,
     ) -> Result<(Option<&'static dyn ProcessType>, usize), ProcessLoadError> {
@@ -1619,13 +1761,60 @@ impl<C: 'static + Chip> Process<'_, C> {
         // Minimum memory size for the process.
         let min_total_memory_size = min_app_ram_size + initial_kernel_memory_size;
 
+        // Processes loaded from a memory pool keep their fixed-size kernel
+        // state (the process struct, the callback ring buffer, and the grant
+        // pointers, in that order) directly before their memory block, so the
+        // block can later be resized without moving any of it. The process
+        // struct is then the first thing in memory that belongs to the process.
+        // Their memory block only holds app-owned memory and grant regions,
+        // and keeps as much room for grants as other processes get.
+        let pooled_kernel_state = if memory_pool_end.is_some() {
+            let align_up = |address: usize, align: usize| (address + align - 1) & !(align - 1);
+            let process_struct_start =
+                align_up(remaining_app_memory as usize, mem::align_of::<Process<C>>());
+            let callbacks_start = align_up(
+                process_struct_start + process_struct_offset,
+                mem::align_of::<Task>(),
+            );
+            let grant_ptrs_start = align_up(
+                callbacks_start + callbacks_offset,
+                mem::align_of::<*const usize>(),
+            );
+            Some((process_struct_start, callbacks_start, grant_ptrs_start))
+        } else {
+            None
+        };
+        let unallocated_memory_start = pooled_kernel_state
+            .map_or(remaining_app_memory as usize, |(_, _, grant_ptrs_start)| {
+                grant_ptrs_start + grant_ptrs_offset
+            });
+        let fixed_kernel_memory_size = unallocated_memory_start - remaining_app_memory as usize;
+        let initial_block_kernel_memory_size = if memory_pool_end.is_some() {
+            0
+        } else {
+            initial_kernel_memory_size
+        };
+
+        if fixed_kernel_memory_size > remaining_app_memory_size {
+            if config::CONFIG.debug_load_processes {
+                debug!(
+                    "[!] flash=[{:#010X}:{:#010X}] process={:?} - couldn't allocate kernel state of size {:#X}",
+                    app_flash.as_ptr() as usize,
+                    app_flash.as_ptr() as usize + app_flash.len(),
+                    process_name,
+                    fixed_kernel_memory_size
+                );
+            }
+            return Err(ProcessLoadError::NotEnoughMemory);
+        }
+
         // Determine where process memory will go and allocate MPU region for app-owned memory.
         let (memory_start, memory_size) = match chip.mpu().allocate_app_memory_region(
-            remaining_app_memory as *const u8,
-            remaining_app_memory_size,
+            unallocated_memory_start as *const u8,
+            remaining_app_memory_size - fixed_kernel_memory_size,
             min_total_memory_size,
             initial_app_memory_size,
-            initial_kernel_memory_size,
+            initial_block_kernel_memory_size,
             mpu::Permissions::ReadWriteOnly,
             &mut mpu_config,
         ) {
@@ -1645,19 +1834,18 @@ impl<C: 'static + Chip> Process<'_, C> {
             }
         };
 
-        // Compute how much padding before start of process memory.
+        // Compute how much padding before start of process memory. For
+        // processes loaded from a memory pool this includes their fixed-size
+        // kernel state.
         let memory_padding_size = (memory_start as usize) - (remaining_app_memory as usize);
 
-        // Set up process memory.
-        let app_memory = slice::from_raw_parts_mut(memory_start as *mut u8, memory_size);
-
         // Check if the memory region is valid for the process. If a process
         // included a fixed address for the start of RAM in its TBF header (this
         // field is optional, processes that are position independent do not
         // need a fixed address) then we check that we used the same address
         // when we allocated it RAM.
         if let Some(fixed_memory_start) = tbf_header.get_fixed_address_ram() {
-            let actual_address = app_memory.as_ptr() as u32;
+            let actual_address = memory_start as u32;
             let expected_address = fixed_memory_start;
             if actual_address != expected_address {
                 return Err(ProcessLoadError::MemoryAddressMismatch {
@@ -1671,48 +1859,53 @@ impl<C: 'static + Chip> Process<'_, C> {
         let initial_stack_pointer = memory_start.add(initial_app_memory_size);
         let initial_sbrk_pointer = memory_start.add(initial_app_memory_size);
 
-        // Set up initial grant region.
-        let mut kernel_memory_break = app_memory.as_mut_ptr().add(app_memory.len());
-
-        // Now that we know we have the space we can setup the grant
-        // pointers.
-        kernel_memory_break = kernel_memory_break.offset(-(grant_ptrs_offset as isize));
+        // Set up initial grant region. Unless the process was loaded from a
+        // memory pool, the grant pointers, the callbacks and the process
+        // struct are placed at the end of process memory, below one another.
+        let memory_end = memory_start.add(memory_size);
+        let (process_struct_start, callbacks_start, grant_ptrs_start) = pooled_kernel_state
+            .unwrap_or_else(|| {
+                let grant_ptrs_start = memory_end as usize - grant_ptrs_offset;
+                let callbacks_start = grant_ptrs_start - callbacks_offset;
+                let process_struct_start = callbacks_start - process_struct_offset;
+                (process_struct_start, callbacks_start, grant_ptrs_start)
+            });
+        let kernel_memory_break = if memory_pool_end.is_some() {
+            memory_end
+        } else {
+            process_struct_start as *const u8
+        };
 
         // This is safe today, as MPU constraints ensure that `memory_start` will always
         // be aligned on at least a word boundary, and that memory_size will be aligned on at least
         // a word boundary, and `grant_ptrs_offset` is a multiple of the word size.
-        // Thus, `kernel_memory_break` must be word aligned.
+        // Thus, `grant_ptrs_start` must be word aligned. Processes loaded from a
+        // memory pool align it explicitly.
         // While this is unlikely to change, it should be more proactively enforced.
         //
         // TODO: https://github.com/tock/tock/issues/1739
         #[allow(clippy::cast_ptr_alignment)]
         // Set all pointers to null.
-        let opts =
-            slice::from_raw_parts_mut(kernel_memory_break as *mut *const usize, grant_ptrs_num);
+        let opts = slice::from_raw_parts_mut(grant_ptrs_start as *mut *const usize, grant_ptrs_num);
         for opt in opts.iter_mut() {
             *opt = ptr::null()
         }
-
-        // Now that we know we have the space we can setup the memory
-        // for the callbacks.
-        kernel_memory_break = kernel_memory_break.offset(-(callbacks_offset as isize));
+        let grant_pointers_end = (grant_ptrs_start + grant_ptrs_offset) as *mut *mut u8;
 
         // This is safe today, as MPU constraints ensure that `memory_start` will always
         // be aligned on at least a word boundary, and that memory_size will be aligned on at least
         // a word boundary, and `grant_ptrs_offset` is a multiple of the word size.
-        // Thus, `kernel_memory_break` must be word aligned.
+        // Thus, `callbacks_start` must be word aligned.
         // While this is unlikely to change, it should be more proactively enforced.
         //
         // TODO: https://github.com/tock/tock/issues/1739
         #[allow(clippy::cast_ptr_alignment)]
         // Set up ring buffer.
-        let callback_buf =
-            slice::from_raw_parts_mut(kernel_memory_break as *mut Task, callback_len);
+        let callback_buf = slice::from_raw_parts_mut(callbacks_start as *mut Task, callback_len);
         let tasks = RingBuffer::new(callback_buf);
 
         // Last thing is the process struct.
-        kernel_memory_break = kernel_memory_break.offset(-(process_struct_offset as isize));
-        let process_struct_memory_location = kernel_memory_break;
+        let process_struct_memory_location = process_struct_start as *mut u8;
 
         // Determine the debug information to the best of our
         // understanding. If the app is doing all of the PIC fixup and
@@ -1733,12 +1926,17 @@ impl<C: 'static + Chip> Process<'_, C> {
             .set(AppId::new(kernel, unique_identifier, index));
         process.kernel = kernel;
         process.chip = chip;
-        process.memory = app_memory;
+        process.memory_start = memory_start;
+        process.memory_len = Cell::new(memory_size);
+        process.original_memory_len = memory_size;
+        process.memory_pool_end = memory_pool_end;
+        process.grant_pointers_end = grant_pointers_end;
         process.header = tbf_header;
         process.kernel_memory_break = Cell::new(kernel_memory_break);
         process.original_kernel_memory_break = kernel_memory_break;
         process.app_break = Cell::new(initial_sbrk_pointer);
         process.original_app_break = initial_sbrk_pointer;
+        process.memory_quota = Cell::new(None);
         process.allow_high_water_mark = Cell::new(remaining_app_memory);
         process.original_allow_high_water_mark = remaining_app_memory;
         process.current_stack_pointer = Cell::new(initial_stack_pointer);
@@ -1771,6 +1969,7 @@ impl<C: 'static + Chip> Process<'_, C> {
             last_syscall: None,
             dropped_callback_count: 0,
             timeslice_expiration_count: 0,
+            memory_quota_exceeded_count: 0,
         });
 
         let flash_protected_size = process.header.get_protected_size() as usize;
@@ -1781,8 +1980,8 @@ impl<C: 'static + Chip> Process<'_, C> {
                 source: FunctionCallSource::Kernel,
                 pc: init_fn,
                 argument0: flash_app_start_addr,
-                argument1: process.memory.as_ptr() as usize,
-                argument2: process.memory.len() as usize,
+                argument1: process.memory_start as usize,
+                argument2: process.memory_len.get(),
                 argument3: process.app_break.get() as usize,
             }));
         });
@@ -1791,7 +1990,7 @@ impl<C: 'static + Chip> Process<'_, C> {
         match process.stored_state.map(|stored_state| {
             chip.userspace_kernel_boundary().initialize_process(
                 process.sp(),
-                process.sp() as usize - process.memory.as_ptr() as usize,
+                process.sp() as usize - process.memory_start as usize,
                 stored_state,
             )
         }) {
@@ -1888,6 +2087,7 @@ impl<C: 'static + Chip> Process<'_, C> {
             debug.last_syscall = None;
             debug.dropped_callback_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.memory_quota_exceeded_count = 0;
         });
 
         // We are going to start this process over again, so need the init_fn
@@ -1900,9 +2100,12 @@ impl<C: 'static + Chip> Process<'_, C> {
         // Reset memory pointers back to how they were when first calculated by
         // the process create function. Since these are based on properties in
         // the TBF header, and processes can't change the TBF header, it is fine
-        // to use saved values.
-        self.kernel_memory_break
-            .set(self.original_kernel_memory_break);
+        // to use saved values. Processes loaded from a memory pool have
+        // already been reset to the end of their released memory block.
+        if self.memory_pool_end.is_none() {
+            self.kernel_memory_break
+                .set(self.original_kernel_memory_break);
+        }
         self.app_break.set(self.original_app_break);
         self.current_stack_pointer.set(self.original_stack_pointer);
         self.allow_high_water_mark
@@ -1913,7 +2116,7 @@ impl<C: 'static + Chip> Process<'_, C> {
         let new_stack_pointer_res = self.stored_state.map_or(Err(()), |stored_state| unsafe {
             self.chip.userspace_kernel_boundary().initialize_process(
                 self.sp(),
-                self.sp() as usize - self.memory.as_ptr() as usize,
+                self.sp() as usize - self.memory_start as usize,
                 stored_state,
             )
         });
@@ -1949,8 +2152,8 @@ impl<C: 'static + Chip> Process<'_, C> {
                 source: FunctionCallSource::Kernel,
                 pc: init_fn,
                 argument0: flash_app_start,
-                argument1: self.memory.as_ptr() as usize,
-                argument2: self.memory.len() as usize,
+                argument1: self.memory_start as usize,
+                argument2: self.memory_len.get(),
                 argument3: self.app_break.get() as usize,
             }));
         });
@@ -1983,6 +2186,9 @@ impl<C: 'static + Chip> Process<'_, C> {
             self.grant_ptrs_reset();
         }
 
+        // Return any memory the process grew into to the memory pool.
+        self.release_pool_memory();
+
         // Mark the app as stopped so the scheduler won't try to run it.
         self.state.set(State::StoppedFaulted);
     }
@@ -2013,18 +2219,161 @@ impl<C: 'static + Chip> Process<'_, C> {
             && buf_end_addr <= self.app_break.get()
     }
 
+    /// Checks if moving the app break to `new_break` would grow the memory
+    /// accessible to the process past its memory quota. Shrinking the break is
+    /// always allowed, even if the process is currently over its quota.
+    fn exceeds_memory_quota(&self, new_break: *const u8) -> bool {
+        self.get_memory_quota().map_or(false, |quota| {
+            new_break > self.app_break.get()
+                && new_break as usize - self.mem_start() as usize > quota
+        })
+    }
+
+    /// Grow or shrink the memory block of a process loaded from a memory pool
+    /// so that the app break can move to `new_break`.
+    ///
+    /// The block grows into free memory directly after it, up to the next
+    /// process in the pool, if `new_break` does not fit below the grant region.
+    /// If the block is larger than needed for `new_break` it shrinks, but never
+    /// below the size the process was created with. Either way the grant
+    /// regions move along with the end of the block, by a multiple of
+    /// `GRANT_MOVE_ALIGNMENT` so their alignment is preserved. The block can
+    /// only be resized in steps the MPU can protect without moving its start.
+    ///
+    /// This does nothing for processes with fixed-size memory, and returns an
+    /// error if the block needs to grow but cannot.
+    fn resize_memory(
+        &self,
+        new_break: *const u8,
+        config: &mut <<C as Chip>::MPU as MPU>::MpuConfig,
+    ) -> Result<(), Error> {
+        let pool_end = match self.memory_pool_end {
+            Some(pool_end) => pool_end,
+            None => return Ok(()),
+        };
+
+        let memory_start = self.mem_start() as usize;
+        let old_kernel_break = self.kernel_memory_break.get() as usize;
+        let kernel_memory_size = self.mem_end() as usize - old_kernel_break;
+        let must_grow = new_break as usize > old_kernel_break;
+
+        // Leave room to round down where the grant regions move to.
+        let needed_size =
+            new_break as usize - memory_start + kernel_memory_size + GRANT_MOVE_ALIGNMENT;
+        let min_size = cmp::max(needed_size, self.original_memory_len);
+        if !must_grow && min_size >= self.memory_len.get() {
+            return Ok(());
+        }
+
+        let available_size = self.memory_pool_limit(pool_end) as usize - memory_start;
+        let new_size = match self.chip.mpu().resize_app_memory_region(
+            self.mem_start(),
+            available_size,
+            min_size,
+            new_break,
+            kernel_memory_size + GRANT_MOVE_ALIGNMENT,
+            mpu::Permissions::ReadWriteOnly,
+            config,
+        ) {
+            Some(new_size) => new_size,
+            None if must_grow => return Err(Error::OutOfMemory),
+            None => return Ok(()),
+        };
+
+        // Move the grant regions to the end of the resized block.
+        let new_end = memory_start + new_size;
+        let offset = (new_end - kernel_memory_size) as isize - old_kernel_break as isize;
+        let offset = offset & !(GRANT_MOVE_ALIGNMENT as isize - 1);
+        let new_kernel_break = self.kernel_memory_break.get().wrapping_offset(offset);
+        unsafe {
+            ptr::copy(
+                self.kernel_memory_break.get(),
+                new_kernel_break as *mut u8,
+                kernel_memory_size,
+            );
+
+            let grant_ptrs_num = self.kernel.get_grant_count_and_finalize();
+            for grant_num in 0..grant_ptrs_num {
+                let grant_pointer = self.grant_pointers_end.offset(-(grant_num as isize + 1));
+                if !(*grant_pointer).is_null() {
+                    *grant_pointer = (*grant_pointer).offset(offset);
+                }
+            }
+        }
+        self.kernel_memory_break.set(new_kernel_break);
+        self.memory_len.set(new_size);
+        Ok(())
+    }
+
+    /// Find how far the memory block of a process loaded from a memory pool
+    /// can grow: up to the start of the next process in the pool, or the end of
+    /// the pool. Only the last process in the pool has free memory to grow
+    /// into.
+    fn memory_pool_limit(&self, pool_end: *const u8) -> *const u8 {
+        let mem_end = self.mem_end();
+        let limit = Cell::new(pool_end);
+        self.kernel.process_each(|process| {
+            // The process struct is the lowest address that belongs to a
+            // process loaded from a memory pool.
+            let process_start = process as *const dyn ProcessType as *const u8;
+            if process_start >= mem_end && process_start < limit.get() {
+                limit.set(process_start);
+            }
+        });
+        limit.get()
+    }
+
+    /// Shrink the memory block of a process loaded from a memory pool back to
+    /// the size it was created with, returning the rest to the pool.
+    ///
+    /// If the MPU cannot shrink the block all the way, the block keeps the
+    /// bytes it cannot give back. Either way the grant region starts out
+    /// empty at the end of the block, and the MPU region for app-owned memory
+    /// ends at the original app break.
+    ///
+    /// This must only be called after the grant regions have been reset, as
+    /// they are discarded rather than moved.
+    fn release_pool_memory(&self) {
+        if self.memory_pool_end.is_none() {
+            return;
+        }
+
+        self.app_break.set(self.original_app_break);
+        self.allow_high_water_mark
+            .set(self.original_allow_high_water_mark);
+        self.mpu_config.map(|config| {
+            if self.memory_len.get() != self.original_memory_len {
+                let memory_len = self
+                    .chip
+                    .mpu()
+                    .resize_app_memory_region(
+                        self.mem_start(),
+                        self.memory_len.get(),
+                        self.original_memory_len,
+                        self.original_app_break,
+                        0,
+                        mpu::Permissions::ReadWriteOnly,
+                        config,
+                    )
+                    .unwrap_or(self.memory_len.get());
+                self.memory_len.set(memory_len);
+            }
+            self.kernel_memory_break.set(self.mem_end());
+            let _ = self.chip.mpu().update_app_memory_region(
+                self.original_app_break,
+                self.mem_end(),
+                mpu::Permissions::ReadWriteOnly,
+                config,
+            );
+        });
+    }
+
     /// Reset all `grant_ptr`s to NULL.
-    // This is safe today, as MPU constraints ensure that `mem_end` will always
-    // be aligned on at least a word boundary. While this is unlikely to
-    // change, it should be more proactively enforced.
-    //
-    // TODO: https://github.com/tock/tock/issues/1739
-    #[allow(clippy::cast_ptr_alignment)]
     unsafe fn grant_ptrs_reset(&self) {
         let grant_ptrs_num = self.kernel.get_grant_count_and_finalize();
         for grant_num in 0..grant_ptrs_num {
             let grant_num = grant_num as isize;
-            let ctr_ptr = (self.mem_end() as *mut *mut usize).offset(-(grant_num + 1));
+            let ctr_ptr = self.grant_pointers_end.offset(-(grant_num + 1));
             write_volatile(ctr_ptr, ptr::null_mut());
         }
     }
@@ -47,6 +47,13 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+
+    /// How many times the main loop found pending interrupts and serviced
+    /// them.
+    interrupt_services: Cell<usize>,
+
+    /// Board function that resets the chip, if the board registered one.
+    reset_function: Cell<Option<fn()>>,
 }
 
 impl Kernel {
@@ -57,9 +64,39 @@ impl Kernel {
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            interrupt_services: Cell::new(0),
+            reset_function: Cell::new(None),
+        }
+    }
+
+    /// Registers the board function that resets the chip, so that holders of
+    /// the `ProcessManagementCapability` can reboot the board with `reset()`.
+    pub fn set_reset_function(
+        &self,
+        reset: fn(),
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.reset_function.set(Some(reset));
+    }
+
+    /// Resets the board with the function registered by the board. Only
+    /// returns if the board did not register a reset function (ENOSUPPORT) or
+    /// the reset did not take effect (FAIL).
+    pub fn reset(&self, _capability: &dyn capabilities::ProcessManagementCapability) -> ReturnCode {
+        match self.reset_function.get() {
+            Some(reset) => {
+                reset();
+                ReturnCode::FAIL
+            }
+            None => ReturnCode::ENOSUPPORT,
         }
     }
 
+    /// How many times the main loop serviced pending interrupts.
+    pub(crate) fn interrupt_service_count(&self) -> usize {
+        self.interrupt_services.get()
+    }
+
     /// Something was scheduled for a process, so there is more work to do.
     pub(crate) fn increment_work(&self) {
         self.work.increment();
@@ -284,30 +321,52 @@ impl Kernel {
         }
     }
 
-    /// Main loop.
-    pub fn kernel_loop<P: Platform, C: Chip>(
-        &'static self,
+    /// Perform one iteration of the core Tock kernel loop.
+    ///
+    /// This function is responsible for three main operations:
+    ///
+    /// 1. Check if the kernel itself has any work to be done and if the
+    ///    scheduler wants to complete that work now. If so, it allows the
+    ///    kernel to run.
+    /// 2. Check if any processes have any work to be done, and if so if the
+    ///    scheduler wants to allow any processes to run now, and if so which
+    ///    one.
+    /// 3. After ensuring the scheduler does not want to complete any kernel or
+    ///    process work (or there is no work to be done), are there are no
+    ///    outstanding interrupts to handle, put the chip to sleep.
+    ///
+    /// This function has one configuration option: `no_sleep`. If that
+    /// argument is set to true, the kernel will never attempt to put the chip
+    /// to sleep, and this function can be called again immediately. This is
+    /// used, for example, to drive the kernel step by step from a test harness
+    /// running on the host.
+    pub fn kernel_loop_operation<P: Platform, C: Chip>(
+        &self,
         platform: &P,
         chip: &C,
         ipc: Option<&ipc::IPC>,
+        no_sleep: bool,
         _capability: &dyn capabilities::MainLoopCapability,
     ) {
-        loop {
-            unsafe {
-                chip.service_pending_interrupts();
-                DynamicDeferredCall::call_global_instance_while(|| !chip.has_pending_interrupts());
-
-                for p in self.processes.iter() {
-                    p.map(|process| {
-                        self.do_process(platform, chip, process, ipc);
-                    });
-                    if chip.has_pending_interrupts()
-                        || DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
-                    {
-                        break;
-                    }
+        unsafe {
+            if chip.has_pending_interrupts() {
+                self.interrupt_services.increment();
+            }
+            chip.service_pending_interrupts();
+            DynamicDeferredCall::call_global_instance_while(|| !chip.has_pending_interrupts());
+
+            for p in self.processes.iter() {
+                p.map(|process| {
+                    self.do_process(platform, chip, process, ipc);
+                });
+                if chip.has_pending_interrupts()
+                    || DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
+                {
+                    break;
                 }
+            }
 
+            if !no_sleep {
                 chip.atomic(|| {
                     if !chip.has_pending_interrupts()
                         && !DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
@@ -316,7 +375,20 @@ impl Kernel {
                         chip.sleep();
                     }
                 });
-            };
+            }
+        };
+    }
+
+    /// Main loop.
+    pub fn kernel_loop<P: Platform, C: Chip>(
+        &'static self,
+        platform: &P,
+        chip: &C,
+        ipc: Option<&ipc::IPC>,
+        capability: &dyn capabilities::MainLoopCapability,
+    ) {
+        loop {
+            self.kernel_loop_operation(platform, chip, ipc, false, capability);
         }
     }
 
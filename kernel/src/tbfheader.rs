@@ -97,6 +97,7 @@ pub(crate) enum TbfHeaderTypes {
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
     TbfHeaderFixedAddresses = 5,
+    TbfHeaderMemoryQuota = 6,
 
     /// Some field in the header that we do not understand. Since the TLV format
     /// specifies the length of each section, if we get a field we do not
@@ -156,6 +157,18 @@ pub(crate) struct TbfHeaderV2FixedAddresses {
     start_process_flash: u32,
 }
 
+/// Optional limit on how much RAM the process may make accessible to itself.
+///
+/// The quota bounds how far `brk` and `sbrk` may move the app break, measured
+/// from the start of the process's RAM region. It is not a reservation; the
+/// process is still given memory according to `minimum_ram_size`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TbfHeaderV2MemoryQuota {
+    /// The maximum number of bytes between the start of process RAM and the
+    /// app break.
+    memory_quota: u32,
+}
+
 // Conversion functions from slices to the various TBF fields.
 
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
@@ -201,6 +214,7 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             2 => Ok(TbfHeaderTypes::TbfHeaderWriteableFlashRegions),
             3 => Ok(TbfHeaderTypes::TbfHeaderPackageName),
             5 => Ok(TbfHeaderTypes::TbfHeaderFixedAddresses),
+            6 => Ok(TbfHeaderTypes::TbfHeaderMemoryQuota),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
     }
@@ -288,6 +302,20 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2FixedAddresses {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2MemoryQuota {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2MemoryQuota, Self::Error> {
+        Ok(TbfHeaderV2MemoryQuota {
+            memory_quota: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
 /// Single header that can contain all parts of a v2 header.
 ///
 /// Note, this struct limits the number of writeable regions an app can have to
@@ -300,6 +328,7 @@ pub(crate) struct TbfHeaderV2 {
     package_name: Option<&'static str>,
     writeable_regions: Option<[Option<TbfHeaderV2WriteableFlashRegion>; 4]>,
     fixed_addresses: Option<TbfHeaderV2FixedAddresses>,
+    memory_quota: Option<TbfHeaderV2MemoryQuota>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -426,6 +455,15 @@ impl TbfHeader {
             start => Some(start),
         }
     }
+
+    /// Get the memory quota the process requested in its header, in bytes. If
+    /// the process did not request a quota, return `None`.
+    pub(crate) fn get_memory_quota(&self) -> Option<u32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.memory_quota.map(|mq| mq.memory_quota),
+            _ => None,
+        }
+    }
 }
 
 /// Parse the TBF header length and the entire length of the TBF binary.
@@ -541,6 +579,7 @@ pub(crate) fn parse_tbf_header(
                     Default::default();
                 let mut app_name_str = "";
                 let mut fixed_address_pointer: Option<TbfHeaderV2FixedAddresses> = None;
+                let mut memory_quota_pointer: Option<TbfHeaderV2MemoryQuota> = None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
@@ -621,6 +660,15 @@ pub(crate) fn parse_tbf_header(
                             }
                         }
 
+                        TbfHeaderTypes::TbfHeaderMemoryQuota => {
+                            let entry_len = 4;
+                            if tlv_header.length as usize == entry_len {
+                                memory_quota_pointer = Some(remaining.try_into()?);
+                            } else {
+                                return Err(TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+                            }
+                        }
+
                         _ => {}
                     }
 
@@ -638,6 +686,7 @@ pub(crate) fn parse_tbf_header(
                     package_name: Some(app_name_str),
                     writeable_regions: Some(wfr_pointer),
                     fixed_addresses: fixed_address_pointer,
+                    memory_quota: memory_quota_pointer,
                 };
 
                 Ok(TbfHeader::TbfHeaderV2(tbf_header))